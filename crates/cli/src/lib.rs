@@ -14,7 +14,7 @@ use roc_build::program::{
     BuildOrdering, BuiltFile, CodeGenBackend, CodeGenOptions, DEFAULT_ROC_FILENAME,
 };
 use roc_error_macros::{internal_error, user_error};
-use roc_gen_dev::AssemblyBackendMode;
+use roc_gen_dev::{AssemblyBackendMode, DevOptLevel};
 use roc_gen_llvm::llvm::build::LlvmBackendMode;
 use roc_load::{ExpectMetadata, Threading};
 use roc_mono::ir::OptLevel;
@@ -55,6 +55,7 @@ pub const CMD_GEN_STUB_LIB: &str = "gen-stub-lib";
 pub const FLAG_DEBUG: &str = "debug";
 pub const FLAG_BUNDLE: &str = "bundle";
 pub const FLAG_DEV: &str = "dev";
+pub const FLAG_DEV_OPT_LEVEL: &str = "dev-opt-level";
 pub const FLAG_OPTIMIZE: &str = "optimize";
 pub const FLAG_MAX_THREADS: &str = "max-threads";
 pub const FLAG_OPT_SIZE: &str = "opt-size";
@@ -62,6 +63,7 @@ pub const FLAG_LIB: &str = "lib";
 pub const FLAG_NO_LINK: &str = "no-link";
 pub const FLAG_TARGET: &str = "target";
 pub const FLAG_TIME: &str = "time";
+pub const FLAG_VERBOSE: &str = "verbose";
 pub const FLAG_LINKER: &str = "linker";
 pub const FLAG_PREBUILT: &str = "prebuilt-platform";
 pub const FLAG_CHECK: &str = "check";
@@ -100,6 +102,13 @@ pub fn build_app() -> Command {
         .action(ArgAction::SetTrue)
         .required(false);
 
+    let flag_dev_opt_level = Arg::new(FLAG_DEV_OPT_LEVEL)
+        .short('O')
+        .long(FLAG_DEV_OPT_LEVEL)
+        .help("Set how much the dev backend optimizes with --dev\n(0 compiles fastest, 1 also runs cheap optimizations like jump tables. The default is 1.)")
+        .value_parser(["0", "1"])
+        .required(false);
+
     let flag_debug = Arg::new(FLAG_DEBUG)
         .long(FLAG_DEBUG)
         .help("Store LLVM debug information in the generated program")
@@ -112,6 +121,12 @@ pub fn build_app() -> Command {
         .action(ArgAction::SetTrue)
         .required(false);
 
+    let flag_verbose = Arg::new(FLAG_VERBOSE)
+        .long(FLAG_VERBOSE)
+        .help("Print extra code gen information, like register allocation stats for every proc with --dev")
+        .action(ArgAction::SetTrue)
+        .required(false);
+
     let flag_linker = Arg::new(FLAG_LINKER)
         .long(FLAG_LINKER)
         .help("Set which linker to use\n(The surgical linker is enabled by default only when building for wasm32 or x86_64 Linux, because those are the only targets it currently supports. Otherwise the legacy linker is used by default.)")
@@ -154,8 +169,10 @@ pub fn build_app() -> Command {
             .arg(flag_max_threads.clone())
             .arg(flag_opt_size.clone())
             .arg(flag_dev.clone())
+            .arg(flag_dev_opt_level.clone())
             .arg(flag_debug.clone())
             .arg(flag_time.clone())
+            .arg(flag_verbose.clone())
             .arg(flag_linker.clone())
             .arg(flag_prebuilt.clone())
             .arg(flag_wasm_stack_size_kb)
@@ -203,8 +220,10 @@ pub fn build_app() -> Command {
             .arg(flag_max_threads.clone())
             .arg(flag_opt_size.clone())
             .arg(flag_dev.clone())
+            .arg(flag_dev_opt_level.clone())
             .arg(flag_debug.clone())
             .arg(flag_time.clone())
+            .arg(flag_verbose.clone())
             .arg(flag_linker.clone())
             .arg(flag_prebuilt.clone())
             .arg(
@@ -225,8 +244,10 @@ pub fn build_app() -> Command {
             .arg(flag_max_threads.clone())
             .arg(flag_opt_size.clone())
             .arg(flag_dev.clone())
+            .arg(flag_dev_opt_level.clone())
             .arg(flag_debug.clone())
             .arg(flag_time.clone())
+            .arg(flag_verbose.clone())
             .arg(flag_linker.clone())
             .arg(flag_prebuilt.clone())
             .arg(roc_file_to_run.clone())
@@ -238,8 +259,10 @@ pub fn build_app() -> Command {
             .arg(flag_max_threads.clone())
             .arg(flag_opt_size.clone())
             .arg(flag_dev.clone())
+            .arg(flag_dev_opt_level.clone())
             .arg(flag_debug.clone())
             .arg(flag_time.clone())
+            .arg(flag_verbose.clone())
             .arg(flag_linker.clone())
             .arg(flag_prebuilt.clone())
             .arg(roc_file_to_run.clone())
@@ -329,8 +352,10 @@ pub fn build_app() -> Command {
         .arg(flag_max_threads)
         .arg(flag_opt_size)
         .arg(flag_dev)
+        .arg(flag_dev_opt_level)
         .arg(flag_debug)
         .arg(flag_time)
+        .arg(flag_verbose)
         .arg(flag_linker)
         .arg(flag_prebuilt)
         .arg(roc_file_to_run)
@@ -673,6 +698,7 @@ pub fn build(
     };
 
     let emit_debug_info = matches.get_flag(FLAG_DEBUG);
+    let verbose = matches.get_flag(FLAG_VERBOSE);
     let emit_timings = matches.get_flag(FLAG_TIME);
 
     let threading = match matches.get_one::<usize>(FLAG_MAX_THREADS) {
@@ -717,10 +743,22 @@ pub fn build(
         _ => BuildOrdering::AlwaysBuild,
     };
 
+    let dev_opt_level = match matches
+        .try_get_one::<String>(FLAG_DEV_OPT_LEVEL)
+        .ok()
+        .flatten()
+        .map(|s| s.as_str())
+    {
+        Some("0") => DevOptLevel::Zero,
+        _ => DevOptLevel::One,
+    };
+
     let code_gen_options = CodeGenOptions {
         backend: code_gen_backend,
         opt_level,
+        dev_opt_level,
         emit_debug_info,
+        verbose,
     };
 
     let load_config = standard_load_config(&triple, build_ordering, threading);
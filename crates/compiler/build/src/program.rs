@@ -3,15 +3,18 @@ use crate::link::{
 };
 use bumpalo::Bump;
 use inkwell::memory_buffer::MemoryBuffer;
+use roc_collections::all::MutMap;
 use roc_error_macros::internal_error;
-use roc_gen_dev::AssemblyBackendMode;
+use roc_gen_dev::{AssemblyBackendMode, DebugInfo, DevOptLevel};
 use roc_gen_llvm::llvm::build::{module_from_builtins, LlvmBackendMode};
 use roc_gen_llvm::llvm::externs::add_default_roc_externs;
 use roc_load::{
     EntryPoint, ExecutionMode, ExpectMetadata, LoadConfig, LoadMonomorphizedError, LoadedModule,
     LoadingProblem, MonomorphizedModule, Threading,
 };
-use roc_mono::ir::{OptLevel, SingleEntryPoint};
+use roc_module::symbol::{Interns, Symbol};
+use roc_mono::ir::{OptLevel, Proc, ProcLayout, SingleEntryPoint};
+use roc_mono::layout::STLayoutInterner;
 use roc_packaging::cache::RocCacheDir;
 use roc_reporting::{
     cli::{report_problems, Problems},
@@ -86,7 +89,11 @@ pub enum CodeGenBackend {
 pub struct CodeGenOptions {
     pub backend: CodeGenBackend,
     pub opt_level: OptLevel,
+    /// How much the dev backend optimizes, only used with `CodeGenBackend::Assembly`.
+    pub dev_opt_level: DevOptLevel,
     pub emit_debug_info: bool,
+    /// Print extra information about code gen, like the register allocation stats of the dev backend.
+    pub verbose: bool,
 }
 
 type GenFromMono<'a> = (CodeObject, CodeGenTiming, ExpectMetadata<'a>);
@@ -104,6 +111,10 @@ pub fn gen_from_mono_module<'a>(
     let path = roc_file_path;
     let debug = code_gen_options.emit_debug_info;
     let opt = code_gen_options.opt_level;
+    let dev_opt = code_gen_options.dev_opt_level;
+    let verbose = code_gen_options.verbose;
+    // The dev backend writes the debug info of the app into a separate file next to it.
+    let dwo_path = debug.then(|| path.with_extension("dwo"));
 
     match code_gen_options.backend {
         CodeGenBackend::Wasm => gen_from_mono_module_dev(
@@ -113,6 +124,10 @@ pub fn gen_from_mono_module<'a>(
             preprocessed_host_path,
             wasm_dev_stack_bytes,
             AssemblyBackendMode::Binary, // dummy value, unused in practice
+            verbose,
+            opt,
+            dev_opt,
+            None,
         ),
         CodeGenBackend::Assembly(backend_mode) => gen_from_mono_module_dev(
             arena,
@@ -121,6 +136,10 @@ pub fn gen_from_mono_module<'a>(
             preprocessed_host_path,
             wasm_dev_stack_bytes,
             backend_mode,
+            verbose,
+            opt,
+            dev_opt,
+            dwo_path.as_deref(),
         ),
         CodeGenBackend::Llvm(backend_mode) => {
             gen_from_mono_module_llvm(arena, loaded, path, target, opt, backend_mode, debug)
@@ -216,19 +235,7 @@ fn gen_from_mono_module_llvm<'a>(
     // expects that would confuse the surgical linker
     add_default_roc_externs(&env);
 
-    let entry_point = match loaded.entry_point {
-        EntryPoint::Executable {
-            exposed_to_host,
-            platform_path: _,
-        } => {
-            // TODO support multiple of these!
-            debug_assert_eq!(exposed_to_host.len(), 1);
-            let (symbol, layout) = exposed_to_host[0];
-
-            roc_mono::ir::EntryPoint::Single(SingleEntryPoint { symbol, layout })
-        }
-        EntryPoint::Test => roc_mono::ir::EntryPoint::Expects { symbols: &[] },
-    };
+    let entry_point = llvm_entry_point(loaded.entry_point);
 
     roc_gen_llvm::llvm::build::build_procedures(
         &env,
@@ -464,6 +471,7 @@ fn gen_from_mono_module_llvm<'a>(
 }
 
 #[cfg(feature = "target-wasm32")]
+#[allow(clippy::too_many_arguments)]
 fn gen_from_mono_module_dev<'a>(
     arena: &'a bumpalo::Bump,
     loaded: MonomorphizedModule<'a>,
@@ -471,6 +479,10 @@ fn gen_from_mono_module_dev<'a>(
     preprocessed_host_path: &Path,
     wasm_dev_stack_bytes: Option<u32>,
     backend_mode: AssemblyBackendMode,
+    verbose: bool,
+    opt: OptLevel,
+    dev_opt: DevOptLevel,
+    dwo_path: Option<&Path>,
 ) -> GenFromMono<'a> {
     use target_lexicon::Architecture;
 
@@ -481,14 +493,22 @@ fn gen_from_mono_module_dev<'a>(
             preprocessed_host_path,
             wasm_dev_stack_bytes,
         ),
-        Architecture::X86_64 | Architecture::Aarch64(_) => {
-            gen_from_mono_module_dev_assembly(arena, loaded, target, backend_mode)
-        }
+        Architecture::X86_64 | Architecture::Aarch64(_) => gen_from_mono_module_dev_assembly(
+            arena,
+            loaded,
+            target,
+            backend_mode,
+            verbose,
+            opt,
+            dev_opt,
+            dwo_path,
+        ),
         _ => todo!(),
     }
 }
 
 #[cfg(not(feature = "target-wasm32"))]
+#[allow(clippy::too_many_arguments)]
 pub fn gen_from_mono_module_dev<'a>(
     arena: &'a bumpalo::Bump,
     loaded: MonomorphizedModule<'a>,
@@ -496,13 +516,24 @@ pub fn gen_from_mono_module_dev<'a>(
     _host_input_path: &Path,
     _wasm_dev_stack_bytes: Option<u32>,
     backend_mode: AssemblyBackendMode,
+    verbose: bool,
+    opt: OptLevel,
+    dev_opt: DevOptLevel,
+    dwo_path: Option<&Path>,
 ) -> GenFromMono<'a> {
     use target_lexicon::Architecture;
 
     match target.architecture {
-        Architecture::X86_64 | Architecture::Aarch64(_) => {
-            gen_from_mono_module_dev_assembly(arena, loaded, target, backend_mode)
-        }
+        Architecture::X86_64 | Architecture::Aarch64(_) => gen_from_mono_module_dev_assembly(
+            arena,
+            loaded,
+            target,
+            backend_mode,
+            verbose,
+            opt,
+            dev_opt,
+            dwo_path,
+        ),
         _ => todo!(),
     }
 }
@@ -581,11 +612,149 @@ fn gen_from_mono_module_dev_wasm32<'a>(
     )
 }
 
+fn llvm_entry_point<'a>(entry_point: EntryPoint<'a>) -> roc_mono::ir::EntryPoint<'a> {
+    match entry_point {
+        EntryPoint::Executable {
+            exposed_to_host,
+            platform_path: _,
+        } => {
+            // TODO support multiple of these!
+            debug_assert_eq!(exposed_to_host.len(), 1);
+            let (symbol, layout) = exposed_to_host[0];
+
+            roc_mono::ir::EntryPoint::Single(SingleEntryPoint { symbol, layout })
+        }
+        EntryPoint::Test => roc_mono::ir::EntryPoint::Expects { symbols: &[] },
+    }
+}
+
+/// Builds the procs that the dev backend left undefined with LLVM, see `roc_gen_dev::Env::llvm_fallback`.
+/// LLVM builds them along with everything they call, but the object only exports the fallback procs.
+#[allow(clippy::too_many_arguments)]
+fn build_llvm_fallback_object<'a>(
+    arena: &'a bumpalo::Bump,
+    target: &Triple,
+    opt_level: OptLevel,
+    interns: Interns,
+    layout_interner: &STLayoutInterner<'a>,
+    procedures: MutMap<(Symbol, ProcLayout<'a>), Proc<'a>>,
+    entry_point: roc_mono::ir::EntryPoint<'a>,
+    fallback_procs: &[(Symbol, ProcLayout<'a>, String)],
+) -> MemoryBuffer {
+    use crate::target::{self, convert_opt_level};
+    use inkwell::context::Context;
+    use inkwell::module::Linkage;
+    use inkwell::targets::{FileType, RelocMode};
+
+    let context = Context::create();
+    let module = arena.alloc(module_from_builtins(target, &context, "app"));
+
+    let builder = context.create_builder();
+    let (dibuilder, compile_unit) = roc_gen_llvm::llvm::build::Env::new_debug_info(module);
+    let (mpm, _fpm) = roc_gen_llvm::llvm::build::construct_optimization_passes(module, opt_level);
+
+    let env = roc_gen_llvm::llvm::build::Env {
+        arena,
+        builder: &builder,
+        dibuilder: &dibuilder,
+        compile_unit: &compile_unit,
+        context: &context,
+        interns,
+        module,
+        target_info: TargetInfo::from(target),
+        mode: LlvmBackendMode::Binary,
+        // The wrappers that the host calls are in the dev object.
+        exposed_to_host: Default::default(),
+    };
+
+    add_default_roc_externs(&env);
+
+    roc_gen_llvm::llvm::build::build_procedures_expose_fallbacks(
+        &env,
+        layout_interner,
+        opt_level,
+        procedures,
+        entry_point,
+        fallback_procs,
+    );
+
+    env.dibuilder.finalize();
+    module.strip_debug_info();
+
+    // The dev object and the builtins it links with define everything else,
+    // so anything else defined here would be a duplicate symbol.
+    for function in module.get_functions() {
+        let name = function.get_name().to_str().unwrap();
+        let fallback = fallback_procs.iter().any(|(_, _, fn_name)| fn_name == name);
+        if function.count_basic_blocks() > 0 && !fallback {
+            function.set_linkage(Linkage::Internal);
+        }
+    }
+    for global in module.get_globals() {
+        let name = global.get_name().to_str().unwrap();
+        if global.get_initializer().is_some() && !name.starts_with("llvm.") {
+            global.set_linkage(Linkage::Internal);
+        }
+    }
+
+    mpm.run_on(module);
+
+    if let Err(errors) = env.module.verify() {
+        internal_error!(
+            "😱 LLVM errors when building the procs that the dev backend left undefined:\n\n {}",
+            errors.to_string(),
+        );
+    }
+
+    let target_machine =
+        target::target_machine(target, convert_opt_level(opt_level), RelocMode::PIC).unwrap();
+
+    target_machine
+        .write_to_memory_buffer(env.module, FileType::Object)
+        .expect("Writing .o file failed")
+}
+
+/// Links the object of the dev backend with the one of the procs that LLVM built for it into a single relocatable object.
+fn merge_llvm_fallback_object(dev_object: &[u8], llvm_object: &[u8]) -> Vec<u8> {
+    use std::process::Command;
+
+    let dir = tempfile::tempdir().unwrap();
+    let dev_o_file = dir.path().join("dev.o");
+    let llvm_o_file = dir.path().join("llvm.o");
+    let app_o_file = dir.path().join("app.o");
+
+    std::fs::write(&dev_o_file, dev_object).unwrap();
+    std::fs::write(&llvm_o_file, llvm_object).unwrap();
+
+    let ld = Command::new("ld")
+        .args([
+            "-r",
+            dev_o_file.to_str().unwrap(),
+            llvm_o_file.to_str().unwrap(),
+            "-o",
+            app_o_file.to_str().unwrap(),
+        ])
+        .output();
+
+    match ld {
+        Ok(output) if output.status.success() => std::fs::read(&app_o_file).unwrap(),
+        Ok(output) => internal_error!(
+            "`ld -r` failed to combine the dev backend and LLVM objects:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        ),
+        Err(error) => internal_error!("Failed to run `ld -r`: {:?}", error),
+    }
+}
+
 fn gen_from_mono_module_dev_assembly<'a>(
     arena: &'a bumpalo::Bump,
     loaded: MonomorphizedModule<'a>,
     target: &target_lexicon::Triple,
     backend_mode: AssemblyBackendMode,
+    verbose: bool,
+    opt: OptLevel,
+    dev_opt: DevOptLevel,
+    dwo_path: Option<&Path>,
 ) -> GenFromMono<'a> {
     let all_code_gen_start = Instant::now();
 
@@ -597,19 +766,73 @@ fn gen_from_mono_module_dev_assembly<'a>(
         mut interns,
         exposed_to_host,
         mut layout_interner,
+        entry_point,
         ..
     } = loaded;
 
-    let env = roc_gen_dev::Env {
-        arena,
-        module_id,
-        exposed_to_host: exposed_to_host.top_level_values.keys().copied().collect(),
-        lazy_literals,
-        mode: backend_mode,
+    // Procs with constructs the dev backend does not support yet are built with LLVM instead.
+    // Combining the objects takes `ld -r`, which is not an option on Windows.
+    let llvm_fallback = target.operating_system != target_lexicon::OperatingSystem::Windows;
+    let fallback_procedures = llvm_fallback.then(|| procedures.clone());
+
+    // Maps the symbols of the object back to Roc procs, for demangling crash addresses and profiles.
+    let symbol_map_path = std::env::var_os("ROC_DEV_SYMBOL_MAP");
+
+    // The stack limit is shared by all threads, so only hosts that call into Roc from one thread opt in.
+    let stack_check_size = std::env::var("ROC_DEV_STACK_CHECK_SIZE").ok().map(|size| {
+        size.parse().unwrap_or_else(|_| {
+            internal_error!(
+                "ROC_DEV_STACK_CHECK_SIZE is not a number of bytes: {}",
+                size
+            )
+        })
+    });
+
+    // The dev backend only writes debug info into ELF objects.
+    let dwo_path = dwo_path.filter(|_| target.binary_format == target_lexicon::BinaryFormat::Elf);
+    let debug_info = match dwo_path {
+        Some(path) => DebugInfo::Split {
+            dwo_name: arena.alloc_str(&path.to_string_lossy()),
+        },
+        None => DebugInfo::None,
     };
 
-    let module_object =
-        roc_gen_dev::build_module(&env, &mut interns, &mut layout_interner, target, procedures);
+    let env = roc_gen_dev::EnvBuilder::new(arena, module_id)
+        .exposed_to_host(exposed_to_host.top_level_values.keys().copied().collect())
+        .lazy_literals(lazy_literals)
+        .mode(backend_mode)
+        .opt_level(opt)
+        .dev_opt_level(dev_opt)
+        .stack_check_size(stack_check_size)
+        .symbol_map(symbol_map_path.is_some())
+        .phase_timing(verbose)
+        .llvm_fallback(llvm_fallback)
+        .debug_info(debug_info)
+        .build();
+
+    let (module_object, dwo_object, report) = roc_gen_dev::build_module_with_split_debug(
+        &env,
+        &mut interns,
+        &mut layout_interner,
+        target,
+        procedures,
+    )
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    });
+    if verbose {
+        eprint!("Dev backend: {}", report.summary(10));
+    }
+    // Breaks the size of the code down by module, to see what makes a dev binary big.
+    if std::env::var_os("ROC_DEV_SIZE_REPORT").is_some() {
+        eprint!("Dev backend code size: {}", report.code_size_report());
+    }
+    if let Some(path) = symbol_map_path {
+        if let Err(error) = report.symbol_map.write(Path::new(&path)) {
+            eprintln!("Failed to write the symbol map to {:?}: {}", path, error);
+        }
+    }
 
     let generate_final_ir = all_code_gen_start.elapsed();
     let code_gen_object_start = Instant::now();
@@ -618,6 +841,35 @@ fn gen_from_mono_module_dev_assembly<'a>(
         .write()
         .expect("failed to build output object");
 
+    if let (Some(path), Some(dwo_object)) = (dwo_path, dwo_object) {
+        let dwo_out = dwo_object.write().expect("failed to build dwo object");
+        if let Err(error) = std::fs::write(path, dwo_out) {
+            eprintln!("Failed to write the debug info to {:?}: {}", path, error);
+        }
+    }
+
+    let module_out = match fallback_procedures {
+        Some(procedures) if !report.fallback_procs.is_empty() => {
+            if verbose {
+                for (_, _, fn_name) in report.fallback_procs.iter() {
+                    eprintln!("Dev backend: built {} with LLVM", fn_name);
+                }
+            }
+            let llvm_object = build_llvm_fallback_object(
+                arena,
+                target,
+                opt,
+                interns.clone(),
+                &layout_interner,
+                procedures,
+                llvm_entry_point(entry_point),
+                &report.fallback_procs,
+            );
+            merge_llvm_fallback_object(&module_out, llvm_object.as_slice())
+        }
+        _ => module_out,
+    };
+
     let code_gen_object = code_gen_object_start.elapsed();
     let total = all_code_gen_start.elapsed();
 
@@ -1278,7 +1530,9 @@ pub fn build_str_test<'a>(
     let code_gen_options = CodeGenOptions {
         backend: CodeGenBackend::Llvm(LlvmBackendMode::Binary),
         opt_level: OptLevel::Normal,
+        dev_opt_level: DevOptLevel::One,
         emit_debug_info: false,
+        verbose: false,
     };
 
     let emit_timings = false;
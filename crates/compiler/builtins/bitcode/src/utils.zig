@@ -1,5 +1,6 @@
 const std = @import("std");
 const builtin = @import("builtin");
+const RocStr = @import("str.zig").RocStr;
 const always_inline = std.builtin.CallOptions.Modifier.always_inline;
 const Monotonic = std.builtin.AtomicOrder.Monotonic;
 
@@ -91,18 +92,51 @@ pub fn dealloc(c_ptr: [*]u8, alignment: u32) void {
     return roc_dealloc(c_ptr, alignment);
 }
 
+extern fn setjmp([*c]c_int) c_int;
+extern fn longjmp([*c]c_int, c_int) noreturn;
+
+// The dev backend tests run main through test_catch_panic, and test_panic jumps back out of it with the message,
+// like the roc_panic of the LLVM tests does. Every test loads a library of its own, so these are not shared.
+// The buffer is bigger than the jmp_buf of every libc we link against.
+var test_panic_jmp_buf: [128]c_int align(16) = undefined;
+var test_panic_catching: bool = false;
+var test_panic_msg: RocStr = undefined;
+var test_panic_tag: u32 = 0;
+
+// Runs `run(ctx)` and returns 0, or 1 + the crash tag with the message in `msg` if it called roc_panic.
+pub fn test_catch_panic(run: fn (*anyopaque) callconv(.C) void, ctx: *anyopaque, msg: *RocStr) callconv(.C) u32 {
+    if (setjmp(&test_panic_jmp_buf) != 0) {
+        test_panic_catching = false;
+        msg.* = test_panic_msg;
+        return test_panic_tag + 1;
+    }
+
+    test_panic_catching = true;
+    run(ctx);
+    test_panic_catching = false;
+    return 0;
+}
+
+// The roc_panic of dev backend tests. Outside of test_catch_panic it reports the message and ends the process.
 // indirection because otherwise zig creates an alias to the panic function which our LLVM code
 // does not know how to deal with
 pub fn test_panic(c_ptr: *anyopaque, crash_tag: u32) callconv(.C) void {
-    _ = c_ptr;
-    _ = crash_tag;
-
-    //    const cstr = @ptrCast([*:0]u8, c_ptr);
-    //
-    //    const stderr = std.io.getStdErr().writer();
-    //    stderr.print("Roc panicked: {s}!\n", .{cstr}) catch unreachable;
-    //
-    //    std.c.exit(1);
+    if (builtin.target.cpu.arch != .wasm32) {
+        const msg = @ptrCast(*const RocStr, @alignCast(@alignOf(RocStr), c_ptr));
+        if (test_panic_catching) {
+            test_panic_msg = msg.*;
+            test_panic_tag = crash_tag;
+            longjmp(&test_panic_jmp_buf, 1);
+        }
+
+        const stderr = std.io.getStdErr().writer();
+        switch (crash_tag) {
+            0 => stderr.print("Roc failed with message: \"{s}\"\n", .{msg.asSlice()}) catch unreachable,
+            else => stderr.print("User crash with message: \"{s}\"\n", .{msg.asSlice()}) catch unreachable,
+        }
+
+        std.process.exit(1);
+    }
 }
 
 pub const Inc = fn (?[*]u8) callconv(.C) void;
@@ -157,6 +157,10 @@ interface Num
         toF32Checked,
         toF64,
         toF64Checked,
+        f32ToBits,
+        f64ToBits,
+        f32FromBits,
+        f64FromBits,
     ]
     imports [
         Bool.{ Bool },
@@ -1431,3 +1435,27 @@ toU128Checked : Int * -> Result U128 [OutOfBounds]
 toNatChecked : Int * -> Result Nat [OutOfBounds]
 toF32Checked : Num * -> Result F32 [OutOfBounds]
 toF64Checked : Num * -> Result F64 [OutOfBounds]
+
+## Returns the bits of an [F32] as a [U32], unchanged.
+##
+## This is useful for hashing or serializing floats. Every NaN keeps the bits it has.
+## ```
+## Num.f32ToBits 1.0f32
+##
+## 0x3f80_0000
+## ```
+f32ToBits : F32 -> U32
+
+## Returns the bits of an [F64] as a [U64], unchanged.
+## ```
+## Num.f64ToBits 1.0f64
+##
+## 0x3ff0_0000_0000_0000
+## ```
+f64ToBits : F64 -> U64
+
+## Interprets the bits of a [U32] as an [F32]. This is the inverse of [Num.f32ToBits].
+f32FromBits : U32 -> F32
+
+## Interprets the bits of a [U64] as an [F64]. This is the inverse of [Num.f64ToBits].
+f64FromBits : U64 -> F64
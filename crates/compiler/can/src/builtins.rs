@@ -211,6 +211,10 @@ map_symbol_to_lowlevel_and_arity! {
     NumCountLeadingZeroBits; NUM_COUNT_LEADING_ZERO_BITS; 1,
     NumCountTrailingZeroBits; NUM_COUNT_TRAILING_ZERO_BITS; 1,
     NumCountOneBits; NUM_COUNT_ONE_BITS; 1,
+    NumF32ToBits; NUM_F32_TO_BITS; 1,
+    NumF64ToBits; NUM_F64_TO_BITS; 1,
+    NumF32FromBits; NUM_F32_FROM_BITS; 1,
+    NumF64FromBits; NUM_F64_FROM_BITS; 1,
     I128OfDec; I128_OF_DEC; 1,
 
     Eq; BOOL_STRUCTURAL_EQ; 2,
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Running out of bytes is fine, every other way of not building the proc is a bug.
+    let _ = roc_gen_dev::fuzz::fuzz_proc(data);
+});
@@ -0,0 +1,3044 @@
+use bumpalo::collections::Vec;
+use roc_builtins::bitcode::{self, FloatWidth, IntWidth};
+use roc_collections::all::{MutMap, MutSet};
+use roc_error_macros::internal_error;
+use roc_module::ident::ModuleName;
+use roc_module::low_level::{LowLevel, LowLevelWrapperType};
+use roc_module::symbol::{Interns, ModuleId, Symbol};
+use roc_mono::code_gen_help::{CallerProc, CodeGenHelp};
+use roc_mono::ir::{
+    BranchInfo, CallType, CrashTag, Expr, HigherOrderLowLevel, JoinPointId, ListLiteralElement,
+    Literal, LookupType, ModifyRc, Param, Proc, ProcLayout, SelfRecursive, Stmt,
+};
+use roc_mono::layout::{
+    Builtin, InLayout, LambdaName, Layout, LayoutIds, LayoutInterner, LayoutRepr, STLayoutInterner,
+    TagIdIntType, UnionLayout,
+};
+use roc_mono::list_element_layout;
+use roc_region::all::Region;
+
+use crate::{
+    compare_switch, fold_low_level, hoist_loop_invariants, hot_reload_slot_name, is_pure,
+    literal_int_value, overflow_checked, register_operands, tail_call, taken_branch, wrap_int,
+    DevOptLevel, Env, LiveInterval, Liveness, PhaseTimes, ProcStats, Relocation,
+    STACK_OVERFLOW_HELPER,
+};
+use std::time::Instant;
+
+#[repr(u8)]
+enum UpdateMode {
+    Immutable = 0,
+}
+
+pub struct ListArgument<'a> {
+    element_layout: InLayout<'a>,
+
+    alignment: Symbol,
+    element_width: Symbol,
+}
+
+/// The only use of a symbol, when that use needs the value in a specific register.
+#[derive(Debug, Clone, Copy)]
+pub enum SoleUse<'a> {
+    /// The argument at `index` of a call to a proc.
+    CallArg {
+        arg_layouts: &'a [InLayout<'a>],
+        index: usize,
+        ret_layout: InLayout<'a>,
+    },
+    /// The value returned from the proc.
+    Return,
+}
+
+/// The alignments that get a decref helper. Allocations are aligned to at least a pointer,
+/// and allocations with other alignments are left to the bitcode.
+const DECREF_HELPER_ALIGNMENTS: [u32; 2] = [8, 16];
+
+/// The refcount operations that are built as helpers in the object instead of calling the bitcode.
+/// Their data pointers may be null and have tag id bits set, their refcount pointers may not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefcountHelper {
+    /// Adds an amount to the refcount of a data pointer.
+    IncrefDataPtr,
+    /// Adds an amount to the refcount behind a refcount pointer.
+    IncrefRcPtr,
+    /// Decrements the refcount of a data pointer to an allocation with this alignment.
+    /// The allocation is given to roc_dealloc once its last reference is gone.
+    DecrefDataPtr(u32),
+    /// Decrements the refcount behind a refcount pointer to an allocation with this alignment.
+    DecrefRcPtr(u32),
+}
+
+impl RefcountHelper {
+    pub(crate) fn all() -> impl Iterator<Item = Self> {
+        let decrefs = DECREF_HELPER_ALIGNMENTS.into_iter().flat_map(|alignment| {
+            [
+                RefcountHelper::DecrefDataPtr(alignment),
+                RefcountHelper::DecrefRcPtr(alignment),
+            ]
+        });
+        [RefcountHelper::IncrefDataPtr, RefcountHelper::IncrefRcPtr]
+            .into_iter()
+            .chain(decrefs)
+    }
+
+    pub(crate) fn name(self) -> String {
+        match self {
+            RefcountHelper::IncrefDataPtr => "roc_dev_incref_data_ptr".to_string(),
+            RefcountHelper::IncrefRcPtr => "roc_dev_incref_rc_ptr".to_string(),
+            RefcountHelper::DecrefDataPtr(alignment) => {
+                format!("roc_dev_decref_data_ptr_{alignment}")
+            }
+            RefcountHelper::DecrefRcPtr(alignment) => format!("roc_dev_decref_rc_ptr_{alignment}"),
+        }
+    }
+
+    /// The number of arguments of the low level that the helper takes.
+    /// A decref takes the pointer, the alignment is part of the helper.
+    fn arg_count(self) -> usize {
+        match self {
+            RefcountHelper::IncrefDataPtr | RefcountHelper::IncrefRcPtr => 2,
+            RefcountHelper::DecrefDataPtr(_) | RefcountHelper::DecrefRcPtr(_) => 1,
+        }
+    }
+}
+
+pub trait Backend<'a> {
+    fn env(&self) -> &Env<'a>;
+    fn interns(&self) -> &Interns;
+    fn interns_mut(&mut self) -> &mut Interns;
+    fn interner(&self) -> &STLayoutInterner<'a>;
+
+    fn interner_mut(&mut self) -> &mut STLayoutInterner<'a> {
+        self.module_interns_helpers_mut().1
+    }
+
+    fn debug_symbol(&mut self, name: &str) -> Symbol {
+        let module_id = self.env().module_id;
+
+        self.debug_symbol_in(module_id, name)
+    }
+
+    fn debug_symbol_in(&mut self, module_id: ModuleId, name: &str) -> Symbol {
+        let ident_ids = self
+            .interns_mut()
+            .all_ident_ids
+            .get_mut(&module_id)
+            .unwrap();
+
+        let ident_id = ident_ids.add_str(name);
+        Symbol::new(module_id, ident_id)
+    }
+
+    // This method is suboptimal, but it seems to be the only way to make rust understand
+    // that all of these values can be mutable at the same time. By returning them together,
+    // rust understands that they are part of a single use of mutable self.
+    fn module_interns_helpers_mut(
+        &mut self,
+    ) -> (
+        ModuleId,
+        &mut STLayoutInterner<'a>,
+        &mut Interns,
+        &mut CodeGenHelp<'a>,
+        &mut Vec<'a, CallerProc<'a>>,
+    );
+
+    fn lambda_name_to_string<'b, I>(
+        &self,
+        name: LambdaName,
+        arguments: I,
+        _lambda_set: Option<InLayout>,
+        result: InLayout,
+    ) -> String
+    where
+        I: Iterator<Item = InLayout<'b>>,
+    {
+        use std::fmt::Write;
+        use std::hash::{BuildHasher, Hash, Hasher};
+
+        let symbol = name.name();
+
+        let mut buf = String::with_capacity(1024);
+
+        let argument_layouts: std::vec::Vec<InLayout<'b>> = arguments.collect();
+        for a in argument_layouts.iter() {
+            write!(buf, "{:?}", self.interner().dbg_stable(*a)).expect("capacity");
+        }
+
+        // lambda set should not matter; it should already be added as an argument
+        // but the niche of the lambda name may be the only thing differentiating two different
+        // implementations of a function with the same symbol
+        write!(buf, "{:?}", name.niche().dbg_stable(self.interner())).expect("capacity");
+
+        write!(buf, "{:?}", self.interner().dbg_stable(result)).expect("capacity");
+
+        // NOTE: due to randomness, this will not be consistent between runs
+        let mut state = roc_collections::all::BuildHasher::default().build_hasher();
+        buf.hash(&mut state);
+
+        let interns = self.interns();
+        let ident_string = symbol.as_str(interns);
+        let module_string = interns.module_ids.get_name(symbol.module_id()).unwrap();
+
+        // the functions from the generates #help module (refcounting, equality) is always suffixed
+        // with 1. That is fine, they are always unique anyway.
+        if ident_string.contains("#help") {
+            format!("{}_{}_1", module_string, ident_string)
+        } else if self.env().layout_symbol_names {
+            // Like `[I64,List_Str->Str]`. The hash stays, procs with the same layouts can still differ in their niche.
+            let summary = |layout| -> String {
+                let dbg = self.interner().dbg(layout);
+                dbg.split_whitespace()
+                    .collect::<std::vec::Vec<_>>()
+                    .join("_")
+                    .replace(",_", ",")
+            };
+            let arguments: std::vec::Vec<String> =
+                argument_layouts.iter().map(|a| summary(*a)).collect();
+            format!(
+                "{}_{}_[{}->{}]_{}",
+                module_string,
+                ident_string,
+                arguments.join(","),
+                summary(result),
+                state.finish()
+            )
+        } else {
+            format!("{}_{}_{}", module_string, ident_string, state.finish())
+        }
+    }
+
+    fn defined_in_app_module(&self, symbol: Symbol) -> bool {
+        symbol
+            .module_string(self.interns())
+            .starts_with(ModuleName::APP)
+    }
+
+    fn list_argument(&mut self, list_layout: InLayout<'a>) -> ListArgument<'a> {
+        let element_layout = match self.interner().get_repr(list_layout) {
+            LayoutRepr::Builtin(Builtin::List(e)) => e,
+            _ => unreachable!(),
+        };
+
+        let (element_width_int, alignment_int) =
+            self.interner().stack_size_and_alignment(element_layout);
+
+        let alignment = self.debug_symbol("alignment");
+        self.load_literal_i32(&alignment, Ord::max(alignment_int, 8) as i32);
+
+        let element_width = self.debug_symbol("element_width");
+        self.load_literal_i64(&element_width, element_width_int as i64);
+
+        ListArgument {
+            element_layout,
+            alignment,
+            element_width,
+        }
+    }
+
+    fn increment_fn_pointer(&mut self, layout: InLayout<'a>) -> Symbol {
+        let box_layout = self
+            .interner_mut()
+            .insert_direct_no_semantic(LayoutRepr::Boxed(layout));
+
+        let element_increment = self.debug_symbol("element_increment");
+        let element_increment_symbol = self.build_indirect_inc(layout);
+
+        let element_increment_string = self.lambda_name_to_string(
+            LambdaName::no_niche(element_increment_symbol),
+            [box_layout].into_iter(),
+            None,
+            Layout::UNIT,
+        );
+
+        self.build_fn_pointer(&element_increment, element_increment_string);
+
+        element_increment
+    }
+
+    fn decrement_fn_pointer(&mut self, layout: InLayout<'a>) -> Symbol {
+        let box_layout = self
+            .interner_mut()
+            .insert_direct_no_semantic(LayoutRepr::Boxed(layout));
+
+        let element_decrement = self.debug_symbol("element_decrement");
+        let element_decrement_symbol = self.build_indirect_dec(layout);
+
+        let element_decrement_string = self.lambda_name_to_string(
+            LambdaName::no_niche(element_decrement_symbol),
+            [box_layout].into_iter(),
+            None,
+            Layout::UNIT,
+        );
+
+        self.build_fn_pointer(&element_decrement, element_decrement_string);
+
+        element_decrement
+    }
+
+    fn helper_proc_gen_mut(&mut self) -> &mut CodeGenHelp<'a>;
+
+    fn helper_proc_symbols_mut(&mut self) -> &mut Vec<'a, (Symbol, ProcLayout<'a>)>;
+
+    fn helper_proc_symbols(&self) -> &Vec<'a, (Symbol, ProcLayout<'a>)>;
+    fn caller_procs(&self) -> &Vec<'a, CallerProc<'a>>;
+
+    /// reset resets any registers or other values that may be occupied at the end of a procedure.
+    /// It also passes basic procedure information to the builder for setup of the next function.
+    fn reset(&mut self, name: String, is_self_recursive: SelfRecursive);
+
+    /// finalize does any setup and cleanup that should happen around the procedure.
+    /// finalize does setup because things like stack size and jump locations are not know until the function is written.
+    /// For example, this can store the frame pointer and setup stack space.
+    /// finalize is run at the end of build_proc when all internal code is finalized.
+    fn finalize(&mut self) -> (Vec<'a, u8>, Vec<'a, Relocation<'a>>);
+
+    /// The size in bytes of the stack frame setup by the most recent call to finalize.
+    fn stack_frame_size(&self) -> u32;
+
+    /// The stats of the proc finished by the most recent call to finalize.
+    fn proc_stats(&self) -> ProcStats;
+
+    /// The phase times of the proc being built, see `Env::phase_timing`. They are cleared by reset.
+    fn phase_times(&self) -> PhaseTimes;
+    fn phase_times_mut(&mut self) -> &mut PhaseTimes;
+
+    // load_args is used to let the backend know what the args are.
+    // The backend should track these args so it can use them as needed.
+    fn load_args(&mut self, args: &'a [(InLayout<'a>, Symbol)], ret_layout: &InLayout<'a>);
+
+    // load_host_args is load_args for procs that the host calls, which take their args the way the C ABI passes them.
+    fn load_host_args(&mut self, args: &'a [(InLayout<'a>, Symbol)], ret_layout: &InLayout<'a>);
+
+    /// Used for generating trampolines, which jump to a linked symbol, like the wrappers for malloc/realloc/free.
+    /// Outgoing general arg `i` is incoming general arg `arg_order[i]`, the others are passed on as they are.
+    /// Returns the bytes and the offset of the jump, which needs a relocation to the symbol.
+    fn build_trampoline(&mut self, arg_order: &[usize]) -> (&'a [u8], u64);
+
+    /// Used for generating the roc_alloc wrapper, which respects the alignment argument.
+    /// Returns the bytes and the offsets of the jumps to aligned_alloc and to malloc.
+    fn build_wrapped_aligned_alloc(&mut self) -> (&'a [u8], u64, u64);
+
+    /// Used for generating the refcount helpers, see `RefcountHelper`.
+    /// Returns the bytes and the offset of the jump to roc_dealloc, if the helper frees.
+    fn build_refcount_helper(&mut self, helper: RefcountHelper) -> (&'a [u8], Option<u64>);
+
+    /// Used for generating the helper that compares lists byte for byte, see `MEMEQ_HELPER`.
+    fn build_memeq_helper(&mut self) -> &'a [u8];
+
+    /// Used for generating the helper that crashes with a stack overflow, see `STACK_OVERFLOW_HELPER`.
+    /// Returns the bytes and relocations of the helper.
+    fn build_stack_overflow_helper(&mut self) -> (Vec<'a, u8>, Vec<'a, Relocation<'a>>) {
+        self.reset(
+            STACK_OVERFLOW_HELPER.to_string(),
+            SelfRecursive::NotSelfRecursive,
+        );
+        self.roc_panic_with_message("stack overflow", CrashTag::Roc);
+        self.finalize()
+    }
+
+    /// build_proc creates a procedure and outputs it to the wrapped object writer.
+    /// Returns the procedure bytes, its relocations, and the names of the refcounting functions it references.
+    /// With host_abi, the proc takes its args with the C ABI instead of the convention between Roc procs.
+    /// That is meant for the wrappers that are exposed to the host, which call on to the Roc proc.
+    fn build_proc(
+        &mut self,
+        proc: Proc<'a>,
+        layout_ids: &mut LayoutIds<'a>,
+        host_abi: bool,
+    ) -> (
+        Vec<'a, u8>,
+        Vec<'a, Relocation<'a>>,
+        Vec<'a, (Symbol, String)>,
+    ) {
+        let proc_name = self.lambda_name_to_string(
+            proc.name,
+            proc.args.iter().map(|t| t.0),
+            proc.closure_data_layout,
+            proc.ret_layout,
+        );
+
+        let (args, body) = match self.loopify_self_tail_calls(&proc) {
+            Some((args, body)) => (args, body),
+            None => (proc.args, proc.body),
+        };
+        let body = self.env().arena.alloc(body);
+
+        self.reset(proc_name, proc.is_self_recursive);
+        let body = match hoist_loop_invariants(self.env().arena, body) {
+            Some((body, hoisted)) => {
+                // Loading these lazily would put the loads back into the loop.
+                self.hoisted_literals().extend(hoisted);
+                body
+            }
+            None => body,
+        };
+        let timing = self.env().phase_timing;
+        let emission_start = timing.then(Instant::now);
+        if host_abi {
+            self.load_host_args(args, &proc.ret_layout);
+        } else {
+            self.load_args(args, &proc.ret_layout);
+        }
+        for (layout, sym) in args {
+            self.set_layout_map(*sym, layout);
+        }
+        let mut emission = emission_start
+            .map(|start| start.elapsed())
+            .unwrap_or_default();
+        self.scan_ast(body);
+        let emission_start = timing.then(Instant::now);
+        self.build_stmt(layout_ids, body, &proc.ret_layout);
+
+        let mut helper_proc_names = bumpalo::vec![in self.env().arena];
+        helper_proc_names.reserve(self.helper_proc_symbols().len());
+        for (rc_proc_sym, rc_proc_layout) in self.helper_proc_symbols() {
+            let name = layout_ids
+                .get_toplevel(*rc_proc_sym, rc_proc_layout)
+                .to_symbol_string(*rc_proc_sym, self.interns());
+
+            helper_proc_names.push((*rc_proc_sym, name));
+        }
+
+        for caller_proc in self.caller_procs() {
+            let proc_layout = caller_proc.proc_layout;
+            let proc_symbol = caller_proc.proc_symbol;
+            let name = layout_ids
+                .get_toplevel(proc_symbol, &proc_layout)
+                .to_symbol_string(proc_symbol, self.interns());
+
+            helper_proc_names.push((proc_symbol, name));
+        }
+
+        let (bytes, relocs) = self.finalize();
+        if let Some(start) = emission_start {
+            emission += start.elapsed();
+            self.phase_times_mut().emission += emission;
+        }
+        (bytes, relocs, helper_proc_names)
+    }
+
+    /// Rewrites self-recursive calls in tail position into jumps to a join point around the body,
+    /// so that the proc loops instead of growing the stack.
+    /// Mono already does this for most procs, this catches any tail calls left in place.
+    /// Returns the new proc arguments and body, or None if there was nothing to rewrite.
+    fn loopify_self_tail_calls(
+        &mut self,
+        proc: &Proc<'a>,
+    ) -> Option<(&'a [(InLayout<'a>, Symbol)], Stmt<'a>)> {
+        if let SelfRecursive::NotSelfRecursive = proc.is_self_recursive {
+            return None;
+        }
+        // The body may already loop through the join point mono made, so this one gets an id of its own.
+        let id = JoinPointId(self.debug_symbol("tail_call_loop"));
+
+        let arena = self.env().arena;
+        let mut args = Vec::with_capacity_in(proc.args.len(), arena);
+        let mut proc_args = Vec::with_capacity_in(proc.args.len(), arena);
+        for (layout, symbol) in proc.args {
+            let new = self.debug_symbol("tail_call_arg");
+            args.push((*layout, *symbol, new));
+            proc_args.push((*layout, new));
+        }
+
+        let body = roc_mono::tail_recursion::make_tail_recursive(
+            arena,
+            id,
+            proc.name,
+            proc.body.clone(),
+            args.into_bump_slice(),
+            proc.ret_layout,
+        )?;
+        Some((proc_args.into_bump_slice(), body))
+    }
+
+    /// build_stmt builds a statement and outputs at the end of the buffer.
+    fn build_stmt(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        stmt: &Stmt<'a>,
+        ret_layout: &InLayout<'a>,
+    ) {
+        self.enter_stmt(stmt);
+        match stmt {
+            Stmt::Let(sym, expr, layout, following) => {
+                let dead = self
+                    .free_map()
+                    .get(&(stmt as *const Stmt<'a>))
+                    .map_or(false, |syms| syms.contains(sym));
+                if let Expr::RuntimeErrorFunction(message) = expr {
+                    // Nothing after the runtime error can run.
+                    self.roc_panic_with_message(message, CrashTag::Roc);
+                    return;
+                }
+                if dead && is_pure(expr) {
+                    // Nothing reads the value, so there is no need to compute it.
+                    self.set_layout_map(*sym, layout);
+                    self.free_symbols(stmt);
+                    self.build_stmt(layout_ids, following, ret_layout);
+                    return;
+                }
+                let compare = match self.env().dev_opt_level {
+                    DevOptLevel::Zero => None,
+                    DevOptLevel::One => compare_switch(sym, expr, following),
+                };
+                if let Some(compare) = compare {
+                    let arg_layout = self.layout_map().get(&compare.args[0]).copied();
+                    let is_int = matches!(
+                        arg_layout.and_then(|layout| layout.try_int_width()),
+                        Some(width) if width.stack_size() <= 8
+                    );
+                    if is_int && self.fused_compares().remove(sym) {
+                        // Branch on the comparison directly instead of storing the bool.
+                        self.load_literal_symbols(compare.args);
+                        self.set_layout_map(*sym, layout);
+                        self.build_compare_switch(
+                            layout_ids,
+                            stmt,
+                            compare.op,
+                            &compare.args[0],
+                            &compare.args[1],
+                            &arg_layout.unwrap(),
+                            compare.branches,
+                            compare.default_branch,
+                            &compare.ret_layout,
+                        );
+                        self.free_symbols(following);
+                        return;
+                    }
+                }
+                if let Some(call) = tail_call(sym, expr, following) {
+                    if !self.env().hot_reload && self.can_tail_call(call.arg_layouts, ret_layout) {
+                        // Reuse the frame of this proc for the callee, which then returns to our caller.
+                        let fn_name = self.lambda_name_to_string(
+                            call.name,
+                            call.arg_layouts.iter().copied(),
+                            None,
+                            call.ret_layout,
+                        );
+                        self.load_literal_symbols(call.args);
+                        self.set_layout_map(*sym, layout);
+                        self.build_fn_tail_call(fn_name, call.args, call.arg_layouts, ret_layout);
+                        self.free_symbols(stmt);
+                        return;
+                    }
+                }
+                if let (Some(args), Stmt::Let(_, next_expr, _, _)) =
+                    (register_operands(expr), following)
+                {
+                    // Issue the loads of an independent operation right after ours,
+                    // so they are in flight while this operation runs.
+                    match register_operands(next_expr) {
+                        Some(next_args) if !next_args.contains(sym) => {
+                            self.preload_symbols(args);
+                            self.preload_symbols(next_args);
+                        }
+                        _ => {}
+                    }
+                }
+                self.build_expr(sym, expr, layout);
+                self.set_layout_map(*sym, layout);
+                self.free_symbols(stmt);
+                self.build_stmt(layout_ids, following, ret_layout);
+            }
+            Stmt::Ret(sym) => {
+                self.load_literal_symbols(&[*sym]);
+                self.return_symbol(sym, ret_layout);
+                self.free_symbols(stmt);
+            }
+            Stmt::Refcounting(ModifyRc::Free(symbol), following) => {
+                let dst = Symbol::DEV_TMP;
+
+                let layout = *self.layout_map().get(symbol).unwrap();
+                let alignment_bytes = self.interner().allocation_alignment_bytes(layout);
+                let alignment = self.debug_symbol("alignment");
+                self.load_literal_i32(&alignment, alignment_bytes as i32);
+
+                // NOTE: UTILS_FREE_DATA_PTR clears any tag id bits
+
+                self.build_fn_call(
+                    &dst,
+                    bitcode::UTILS_FREE_DATA_PTR.to_string(),
+                    &[*symbol, alignment],
+                    &[Layout::I64, Layout::I32],
+                    &Layout::UNIT,
+                );
+
+                self.free_symbol(&dst);
+                self.free_symbol(&alignment);
+                self.free_symbols(stmt);
+
+                self.build_stmt(layout_ids, following, ret_layout)
+            }
+            Stmt::Refcounting(modify, following) => {
+                let sym = modify.get_symbol();
+                let layout = *self.layout_map().get(&sym).unwrap();
+
+                // Expand the Refcounting statement into more detailed IR with a function call
+                // If this layout requires a new RC proc, we get enough info to create a linker symbol
+                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
+                let (rc_stmt, new_specializations) = {
+                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
+                        self.module_interns_helpers_mut();
+                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
+
+                    rc_proc_gen.expand_refcount_stmt(
+                        ident_ids,
+                        layout_interner,
+                        layout,
+                        modify,
+                        following,
+                    )
+                };
+
+                for spec in new_specializations.into_iter() {
+                    self.helper_proc_symbols_mut().push(spec);
+                }
+
+                self.build_stmt(layout_ids, rc_stmt, ret_layout)
+            }
+            Stmt::Switch {
+                cond_symbol,
+                cond_layout,
+                branches,
+                default_branch,
+                ret_layout,
+            } => {
+                // A switch on a literal only needs the code of the branch it takes.
+                if let Some(taken) =
+                    self.literal_switch_branch(cond_symbol, branches, default_branch)
+                {
+                    self.free_branch_symbols(taken);
+                    self.build_stmt(layout_ids, taken, ret_layout);
+                    self.free_symbols(stmt);
+                    return;
+                }
+                self.load_literal_symbols(&[*cond_symbol]);
+                self.build_switch(
+                    layout_ids,
+                    cond_symbol,
+                    cond_layout,
+                    branches,
+                    default_branch,
+                    ret_layout,
+                );
+                self.free_symbols(stmt);
+            }
+            Stmt::Join {
+                id,
+                parameters,
+                body,
+                remainder,
+            } => {
+                for param in parameters.iter() {
+                    self.set_layout_map(param.symbol, &param.layout);
+                }
+                self.build_join(layout_ids, id, parameters, body, remainder, ret_layout);
+                self.free_symbols(stmt);
+            }
+            Stmt::Jump(id, args) => {
+                self.load_literal_symbols(args);
+                let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
+                    bumpalo::vec![in self.env().arena];
+                arg_layouts.reserve(args.len());
+                let layout_map = self.layout_map();
+                for arg in *args {
+                    if let Some(layout) = layout_map.get(arg) {
+                        arg_layouts.push(*layout);
+                    } else {
+                        internal_error!("the argument, {:?}, has no know layout", arg);
+                    }
+                }
+                self.build_jump(id, args, arg_layouts.into_bump_slice(), ret_layout);
+                self.free_symbols(stmt);
+            }
+            Stmt::Expect {
+                condition,
+                region,
+                lookups,
+                variables,
+                remainder,
+            } => {
+                if self.env().report_expects {
+                    self.load_literal_symbols(&[*condition]);
+                    self.load_literal_symbols(lookups);
+                    self.build_expect(*condition, *region, *lookups, *variables);
+                } else if self.env().expect_checks {
+                    self.load_literal_symbols(&[*condition]);
+                    self.build_expect_check(*condition);
+                }
+                self.free_symbols(stmt);
+                self.build_stmt(layout_ids, remainder, ret_layout);
+            }
+            Stmt::Dbg {
+                symbol,
+                variable,
+                remainder,
+            } => {
+                if self.env().report_expects {
+                    self.load_literal_symbols(&[*symbol]);
+                    self.build_dbg(*symbol, *variable);
+                }
+                self.free_symbols(stmt);
+                self.build_stmt(layout_ids, remainder, ret_layout);
+            }
+            Stmt::Crash(msg, crash_tag) => self.roc_panic(*msg, *crash_tag),
+            Stmt::ExpectFx { .. } => self.build_unsupported("expect-fx".to_string()),
+        }
+    }
+
+    /// build_expect reports to the parent process when `condition` is false,
+    /// along with the `region` of the expect and the values of its lookups.
+    fn build_expect(
+        &mut self,
+        condition: Symbol,
+        region: Region,
+        lookups: &'a [Symbol],
+        variables: &'a [LookupType],
+    );
+
+    /// build_expect_check crashes with roc_panic when `condition` is false.
+    fn build_expect_check(&mut self, condition: Symbol);
+
+    /// build_dbg reports the value of `symbol` to the parent process, along with its `variable`.
+    fn build_dbg(&mut self, symbol: Symbol, variable: LookupType);
+
+    /// roc_panic calls the host's roc_panic with the message `msg`, a Str, and the crash tag.
+    fn roc_panic(&mut self, msg: Symbol, crash_tag: CrashTag) {
+        let error_message = self.debug_symbol("error_message");
+
+        self.load_literal(
+            &error_message,
+            &Layout::U32,
+            &Literal::Int((crash_tag as u128).to_ne_bytes()),
+        );
+
+        // Now that the message is needed, load it if it is a literal.
+        self.load_literal_symbols(&[msg]);
+
+        // roc_panic takes a pointer to the RocStr, so the message must be in memory.
+        let msg_ptr = self.debug_symbol("panic_message_ptr");
+        self.build_alloca(msg_ptr, msg, Layout::STR);
+
+        self.build_fn_call(
+            &Symbol::DEV_TMP2,
+            String::from("roc_panic"),
+            &[msg_ptr, error_message],
+            &[Layout::OPAQUE_PTR, Layout::U32],
+            &Layout::UNIT,
+        );
+
+        self.free_symbol(&msg_ptr);
+        self.free_symbol(&error_message);
+        self.free_symbol(&Symbol::DEV_TMP2);
+    }
+
+    /// roc_panic_with_message calls roc_panic with a message that is known at compile time.
+    /// This is how runtime errors and failed checks in the generated code crash.
+    fn roc_panic_with_message(&mut self, message: &'a str, crash_tag: CrashTag) {
+        let msg = self.debug_symbol("panic_message");
+        self.load_literal(&msg, &Layout::STR, &Literal::Str(message));
+        self.roc_panic(msg, crash_tag);
+        self.free_symbol(&msg);
+    }
+
+    /// The constructs of the current proc that could not be built, see `build_unsupported`.
+    fn unsupported_constructs_mut(&mut self) -> &mut std::vec::Vec<String>;
+
+    /// build_unsupported records that `construct` is not supported by the dev backend yet.
+    /// The generated code crashes when it gets there instead, so the rest of the module can still be built
+    /// and every unsupported construct can be reported at once, see `BackendError::Unsupported`.
+    fn build_unsupported(&mut self, construct: String) {
+        self.unsupported_constructs_mut().push(construct);
+        self.roc_panic_with_message("not supported by the dev backend", CrashTag::Roc);
+    }
+
+    /// Like build_unsupported, but for a construct that defines `sym`.
+    /// `sym` still gets storage for a value of `layout`, so the code after the construct can be built.
+    fn build_unsupported_value(&mut self, sym: &Symbol, layout: &InLayout<'a>, construct: String) {
+        self.build_unsupported(construct);
+        self.claim_unreachable_value(sym, layout);
+    }
+
+    /// claim_unreachable_value gives `sym` storage for a value of `layout` without setting it.
+    /// It is only used after code that crashes.
+    fn claim_unreachable_value(&mut self, sym: &Symbol, layout: &InLayout<'a>);
+
+    // build_switch generates a instructions for a switch statement.
+    fn build_switch(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        cond_symbol: &Symbol,
+        cond_layout: &InLayout<'a>,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+    );
+
+    // build_compare_switch generates the switch following `compare_stmt` on the bool from comparing two integers with `op`.
+    // The bool is never stored, the branch is picked straight from the comparison.
+    // The symbols that die in `compare_stmt` are freed once the comparison is emitted.
+    #[allow(clippy::too_many_arguments)]
+    fn build_compare_switch(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        compare_stmt: &Stmt<'a>,
+        op: LowLevel,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+    );
+
+    // build_join generates a instructions for a join statement.
+    fn build_join(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        id: &JoinPointId,
+        parameters: &'a [Param<'a>],
+        body: &'a Stmt<'a>,
+        remainder: &'a Stmt<'a>,
+        ret_layout: &InLayout<'a>,
+    );
+
+    // build_jump generates a instructions for a jump statement.
+    fn build_jump(
+        &mut self,
+        id: &JoinPointId,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_expr builds the expressions for the specified symbol.
+    /// The builder must keep track of the symbol because it may be referred to later.
+    fn build_expr(&mut self, sym: &Symbol, expr: &Expr<'a>, layout: &InLayout<'a>) {
+        match expr {
+            Expr::Literal(lit) => {
+                if self.env().lazy_literals && !self.hoisted_literals().contains(sym) {
+                    self.literal_map().insert(*sym, (lit, layout));
+                } else {
+                    self.load_literal(sym, layout, lit);
+                }
+            }
+            Expr::Call(roc_mono::ir::Call {
+                call_type,
+                arguments,
+            }) => {
+                match call_type {
+                    CallType::ByName {
+                        name: func_sym,
+                        arg_layouts,
+                        ret_layout,
+                        ..
+                    } => {
+                        if let LowLevelWrapperType::CanBeReplacedBy(lowlevel) =
+                            LowLevelWrapperType::from_symbol(func_sym.name())
+                        {
+                            return self.build_run_low_level(
+                                sym,
+                                &lowlevel,
+                                arguments,
+                                arg_layouts,
+                                ret_layout,
+                            );
+                        } else if func_sym.name().is_builtin() {
+                            // These builtins can be built through `build_fn_call` as well, but the
+                            // implementation in `build_builtin` inlines some of the symbols.
+                            return self.build_builtin(
+                                sym,
+                                *func_sym,
+                                arguments,
+                                arg_layouts,
+                                ret_layout,
+                            );
+                        }
+
+                        let fn_name = self.lambda_name_to_string(
+                            *func_sym,
+                            arg_layouts.iter().copied(),
+                            None,
+                            *ret_layout,
+                        );
+
+                        // Now that the arguments are needed, load them if they are literals.
+                        self.load_literal_symbols(arguments);
+                        if self.env().hot_reload {
+                            let slot_name = hot_reload_slot_name(&fn_name);
+                            self.build_fn_call_through_slot(
+                                sym,
+                                slot_name,
+                                arguments,
+                                arg_layouts,
+                                ret_layout,
+                            )
+                        } else {
+                            self.build_fn_call(sym, fn_name, arguments, arg_layouts, ret_layout)
+                        }
+                    }
+
+                    CallType::LowLevel { op: lowlevel, .. } => {
+                        let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
+                            bumpalo::vec![in self.env().arena];
+                        arg_layouts.reserve(arguments.len());
+                        let layout_map = self.layout_map();
+                        for arg in *arguments {
+                            if let Some(layout) = layout_map.get(arg) {
+                                arg_layouts.push(*layout);
+                            } else {
+                                internal_error!("the argument, {:?}, has no know layout", arg);
+                            }
+                        }
+
+                        self.build_run_low_level(
+                            sym,
+                            lowlevel,
+                            arguments,
+                            arg_layouts.into_bump_slice(),
+                            layout,
+                        )
+                    }
+                    CallType::HigherOrder(higher_order) => {
+                        self.build_higher_order_lowlevel(sym, higher_order, *layout)
+                    }
+                    CallType::Foreign {
+                        foreign_symbol,
+                        ret_layout,
+                    } => {
+                        let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
+                            bumpalo::vec![in self.env().arena];
+                        arg_layouts.reserve(arguments.len());
+                        let layout_map = self.layout_map();
+                        for arg in *arguments {
+                            if let Some(layout) = layout_map.get(arg) {
+                                arg_layouts.push(*layout);
+                            } else {
+                                internal_error!("the argument, {:?}, has no know layout", arg);
+                            }
+                        }
+
+                        self.load_literal_symbols(arguments);
+                        self.build_fn_call(
+                            sym,
+                            foreign_symbol.as_str().to_string(),
+                            arguments,
+                            arg_layouts.into_bump_slice(),
+                            ret_layout,
+                        );
+                    }
+                }
+            }
+            Expr::EmptyArray => {
+                self.create_empty_array(sym);
+            }
+            Expr::Array { elem_layout, elems } => {
+                let mut syms = bumpalo::vec![in self.env().arena];
+                for sym in elems.iter().filter_map(|x| match x {
+                    ListLiteralElement::Symbol(sym) => Some(sym),
+                    _ => None,
+                }) {
+                    syms.push(*sym);
+                }
+                self.create_array(sym, elem_layout, elems);
+            }
+            Expr::Struct(fields) => {
+                self.load_literal_symbols(fields);
+                self.create_struct(sym, layout, fields);
+            }
+            Expr::StructAtIndex {
+                index,
+                field_layouts,
+                structure,
+            } => {
+                self.load_struct_at_index(sym, structure, *index, field_layouts);
+            }
+            Expr::UnionAtIndex {
+                structure,
+                tag_id,
+                union_layout,
+                index,
+            } => {
+                self.load_union_at_index(sym, structure, *tag_id, *index, union_layout);
+            }
+            Expr::UnionFieldPtrAtIndex {
+                structure,
+                tag_id,
+                union_layout,
+                index,
+            } => {
+                self.load_union_field_ptr_at_index(sym, structure, *tag_id, *index, union_layout);
+            }
+            Expr::GetTagId {
+                structure,
+                union_layout,
+            } => {
+                self.get_tag_id(sym, structure, union_layout);
+            }
+            Expr::Tag {
+                tag_layout,
+                tag_id,
+                arguments,
+                reuse,
+            } => {
+                self.load_literal_symbols(arguments);
+                let reuse = reuse.map(|ru| ru.symbol);
+                self.tag(sym, arguments, tag_layout, *tag_id, reuse);
+            }
+            Expr::ExprBox { symbol: value } => {
+                let element_layout = match self.interner().get_repr(*layout) {
+                    LayoutRepr::Boxed(boxed) => boxed,
+                    _ => unreachable!("{:?}", self.interner().dbg(*layout)),
+                };
+
+                self.load_literal_symbols([*value].as_slice());
+                self.expr_box(*sym, *value, element_layout, None)
+            }
+            Expr::ExprUnbox { symbol: ptr } => {
+                let element_layout = *layout;
+
+                self.load_literal_symbols([*ptr].as_slice());
+                self.expr_unbox(*sym, *ptr, element_layout)
+            }
+            Expr::NullPointer => {
+                self.load_literal_i64(sym, 0);
+            }
+            Expr::Reset { symbol, .. } => {
+                let layout = *self.layout_map().get(symbol).unwrap();
+
+                // Expand the Refcounting statement into more detailed IR with a function call
+                // If this layout requires a new RC proc, we get enough info to create a linker symbol
+                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
+                let (new_expr, new_specializations) = {
+                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
+                        self.module_interns_helpers_mut();
+                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
+
+                    rc_proc_gen.call_reset_refcount(ident_ids, layout_interner, layout, *symbol)
+                };
+
+                for spec in new_specializations.into_iter() {
+                    self.helper_proc_symbols_mut().push(spec);
+                }
+
+                self.build_expr(sym, &new_expr, &Layout::BOOL)
+            }
+            Expr::ResetRef { symbol, .. } => {
+                let layout = *self.layout_map().get(symbol).unwrap();
+
+                // Expand the Refcounting statement into more detailed IR with a function call
+                // If this layout requires a new RC proc, we get enough info to create a linker symbol
+                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
+                let (new_expr, new_specializations) = {
+                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
+                        self.module_interns_helpers_mut();
+                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
+
+                    rc_proc_gen.call_resetref_refcount(ident_ids, layout_interner, layout, *symbol)
+                };
+
+                for spec in new_specializations.into_iter() {
+                    self.helper_proc_symbols_mut().push(spec);
+                }
+
+                self.build_expr(sym, &new_expr, &Layout::BOOL)
+            }
+            Expr::RuntimeErrorFunction(_) => {
+                internal_error!("runtime errors are built by build_stmt")
+            }
+        }
+    }
+
+    /// build_run_low_level builds the low level opertation and outputs to the specified symbol.
+    /// The builder must keep track of the symbol because it may be referred to later.
+    fn build_run_low_level(
+        &mut self,
+        sym: &Symbol,
+        lowlevel: &LowLevel,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) {
+        if self.fold_literal_low_level(sym, lowlevel, args, arg_layouts, ret_layout)
+            || self.build_inline_builtin(sym, lowlevel, args, arg_layouts, ret_layout)
+        {
+            return;
+        }
+        if let Some(helper) = self.refcount_helper(lowlevel, args) {
+            // The alignment literal of a decref is part of the helper, so it is never loaded.
+            let arg_count = helper.arg_count();
+            self.load_literal_symbols(&args[..arg_count]);
+            self.build_fn_call(
+                sym,
+                helper.name(),
+                &args[..arg_count],
+                &arg_layouts[..arg_count],
+                ret_layout,
+            );
+            return;
+        }
+        if self.env().overflow_checks && overflow_checked(lowlevel, *ret_layout) {
+            self.load_literal_symbols(args);
+            self.build_int_op_overflow_checked(sym, lowlevel, &args[0], &args[1], ret_layout);
+            return;
+        }
+
+        // Now that the arguments are needed, load them if they are literals.
+        // Integer ops with an immediate or shift form load their own arguments, a small literal may not need a register.
+        let has_imm_form = matches!(
+            lowlevel,
+            LowLevel::NumAdd
+                | LowLevel::NumAddWrap
+                | LowLevel::NumSub
+                | LowLevel::NumSubWrap
+                | LowLevel::NumBitwiseAnd
+                | LowLevel::NumMul
+                | LowLevel::NumMulWrap
+                | LowLevel::NumDivTruncUnchecked
+                | LowLevel::NumLt
+                | LowLevel::NumLte
+                | LowLevel::NumGt
+                | LowLevel::NumGte
+        ) && matches!(
+            arg_layouts.first().and_then(|layout| layout.try_int_width()),
+            Some(width) if width.stack_size() <= 8
+        );
+        if !has_imm_form {
+            self.load_literal_symbols(args);
+        }
+        match lowlevel {
+            LowLevel::NumAbs => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumAbs: expected to have exactly one argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumAbs: expected to have the same argument and return layout"
+                );
+                self.build_num_abs(sym, &args[0], ret_layout)
+            }
+            LowLevel::NumAdd => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumAdd: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumAdd: expected all arguments of to have the same layout"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumAdd: expected to have the same argument and return layout"
+                );
+                self.build_num_add(sym, &args[0], &args[1], ret_layout)
+            }
+            LowLevel::NumAddSaturated => {
+                self.build_num_add_saturated(*sym, args[0], args[1], *ret_layout);
+            }
+            LowLevel::NumAddWrap => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumAdd: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumAdd: expected all arguments of to have the same layout"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumAdd: expected to have the same argument and return layout"
+                );
+                self.build_num_add(sym, &args[0], &args[1], ret_layout)
+            }
+            LowLevel::NumAddChecked => {
+                self.build_num_add_checked(sym, &args[0], &args[1], &arg_layouts[0], ret_layout)
+            }
+            LowLevel::NumSubChecked => {
+                self.build_num_sub_checked(sym, &args[0], &args[1], &arg_layouts[0], ret_layout)
+            }
+            LowLevel::NumMulChecked => {
+                self.build_num_mul_checked(sym, &args[0], &args[1], &arg_layouts[0], ret_layout)
+            }
+            LowLevel::NumAcos => self.build_fn_call(
+                sym,
+                bitcode::NUM_ACOS[FloatWidth::F64].to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::NumAsin => self.build_fn_call(
+                sym,
+                bitcode::NUM_ASIN[FloatWidth::F64].to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::NumAtan => self.build_fn_call(
+                sym,
+                bitcode::NUM_ATAN[FloatWidth::F64].to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::NumMul => self.build_num_mul(sym, &args[0], &args[1], ret_layout),
+            LowLevel::NumMulWrap => self.build_num_mul_wrap(sym, &args[0], &args[1], ret_layout),
+            LowLevel::NumDivTruncUnchecked | LowLevel::NumDivFrac => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumDiv: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumDiv: expected all arguments of to have the same layout"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumDiv: expected to have the same argument and return layout"
+                );
+                self.build_num_div(sym, &args[0], &args[1], ret_layout)
+            }
+
+            LowLevel::NumRemUnchecked => self.build_num_rem(sym, &args[0], &args[1], ret_layout),
+            LowLevel::NumNeg => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumNeg: expected to have exactly one argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumNeg: expected to have the same argument and return layout"
+                );
+                self.build_num_neg(sym, &args[0], ret_layout)
+            }
+            LowLevel::NumPowInt => self.build_fn_call(
+                sym,
+                bitcode::NUM_POW_INT[IntWidth::I64].to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::NumSub => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumSub: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumSub: expected all arguments of to have the same layout"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumSub: expected to have the same argument and return layout"
+                );
+                self.build_num_sub(sym, &args[0], &args[1], ret_layout)
+            }
+            LowLevel::NumSubWrap => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumSubWrap: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumSubWrap: expected all arguments of to have the same layout"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], *ret_layout,
+                    "NumSubWrap: expected to have the same argument and return layout"
+                );
+                self.build_num_sub_wrap(sym, &args[0], &args[1], ret_layout)
+            }
+            LowLevel::NumSubSaturated => {
+                self.build_num_sub_saturated(*sym, args[0], args[1], *ret_layout);
+            }
+            LowLevel::NumBitwiseAnd => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_bitwise_and(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("bitwise and on a non-integer")
+                }
+            }
+            LowLevel::NumBitwiseOr => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_bitwise_or(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("bitwise or on a non-integer")
+                }
+            }
+            LowLevel::NumBitwiseXor => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_bitwise_xor(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("bitwise xor on a non-integer")
+                }
+            }
+            LowLevel::And => {
+                if let LayoutRepr::Builtin(Builtin::Bool) = self.interner().get_repr(*ret_layout) {
+                    self.build_int_bitwise_and(sym, &args[0], &args[1], IntWidth::U8)
+                } else {
+                    internal_error!("bitwise and on a non-integer")
+                }
+            }
+            LowLevel::Or => {
+                if let LayoutRepr::Builtin(Builtin::Bool) = self.interner().get_repr(*ret_layout) {
+                    self.build_int_bitwise_or(sym, &args[0], &args[1], IntWidth::U8)
+                } else {
+                    internal_error!("bitwise or on a non-integer")
+                }
+            }
+            LowLevel::NumShiftLeftBy => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_shift_left(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("shift left on a non-integer")
+                }
+            }
+            LowLevel::NumShiftRightBy => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_shift_right(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("shift right on a non-integer")
+                }
+            }
+            LowLevel::NumShiftRightZfBy => {
+                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
+                    self.interner().get_repr(*ret_layout)
+                {
+                    self.build_int_shift_right_zero_fill(sym, &args[0], &args[1], int_width)
+                } else {
+                    internal_error!("shift right zero-fill on a non-integer")
+                }
+            }
+            LowLevel::Eq => {
+                debug_assert_eq!(2, args.len(), "Eq: expected to have exactly two argument");
+
+                let a = Layout::runtime_representation_in(arg_layouts[0], self.interner());
+                let b = Layout::runtime_representation_in(arg_layouts[1], self.interner());
+
+                debug_assert!(
+                    self.interner().eq_repr(a, b),
+                    "Eq: expected all arguments to have the same layout, but {} != {}",
+                    self.interner().dbg(a),
+                    self.interner().dbg(b),
+                );
+
+                self.build_eq(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::NotEq => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NotEq: expected to have exactly two argument"
+                );
+
+                let a = Layout::runtime_representation_in(arg_layouts[0], self.interner());
+                let b = Layout::runtime_representation_in(arg_layouts[1], self.interner());
+
+                debug_assert!(
+                    self.interner().eq_repr(a, b),
+                    "NotEq: expected all arguments to have the same layout, but {} != {}",
+                    self.interner().dbg(a),
+                    self.interner().dbg(b),
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NotEq: expected to have return layout of type Bool"
+                );
+                self.build_neq(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::Not => {
+                debug_assert_eq!(1, args.len(), "Not: expected to have exactly one argument");
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "Not: expected to have return layout of type Bool"
+                );
+                self.build_not(sym, &args[0], &arg_layouts[0])
+            }
+            LowLevel::NumLt => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumLt: expected to have exactly two argument"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(arg_layouts[0], arg_layouts[1],),
+                    "NumLt: expected all arguments of to have the same layout"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumLt: expected to have return layout of type Bool"
+                );
+                self.build_num_lt(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::NumGt => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumGt: expected to have exactly two argument"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(arg_layouts[0], arg_layouts[1],),
+                    "NumGt: expected all arguments of to have the same layout"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumGt: expected to have return layout of type Bool"
+                );
+                self.build_num_gt(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::NumToFrac => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumToFrac: expected to have exactly one argument"
+                );
+
+                debug_assert!(
+                    matches!(*ret_layout, Layout::F32 | Layout::F64),
+                    "NumToFrac: expected to have return layout of type Float"
+                );
+                self.build_num_to_frac(sym, &args[0], &arg_layouts[0], ret_layout)
+            }
+            LowLevel::NumIsNan => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumIsNan: expected to have exactly one argument"
+                );
+
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumIsNan: expected to have return layout of type Bool"
+                );
+                self.build_num_is_nan(sym, &args[0], &arg_layouts[0])
+            }
+            LowLevel::NumIsInfinite => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumIsInfinite: expected to have exactly one argument"
+                );
+
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumIsInfinite: expected to have return layout of type Bool"
+                );
+                self.build_num_is_infinite(sym, &args[0], &arg_layouts[0])
+            }
+            LowLevel::NumIsFinite => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumIsFinite: expected to have exactly one argument"
+                );
+
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumIsFinite: expected to have return layout of type Bool"
+                );
+                self.build_num_is_finite(sym, &args[0], &arg_layouts[0])
+            }
+            LowLevel::NumF32ToBits => self.build_num_float_to_bits(sym, &args[0], FloatWidth::F32),
+            LowLevel::NumF64ToBits => self.build_num_float_to_bits(sym, &args[0], FloatWidth::F64),
+            LowLevel::NumF32FromBits => {
+                self.build_num_float_from_bits(sym, &args[0], FloatWidth::F32)
+            }
+            LowLevel::NumF64FromBits => {
+                self.build_num_float_from_bits(sym, &args[0], FloatWidth::F64)
+            }
+            LowLevel::NumLte => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumLte: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumLte: expected all arguments of to have the same layout"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumLte: expected to have return layout of type Bool"
+                );
+                self.build_num_lte(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::NumGte => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "NumGte: expected to have exactly two argument"
+                );
+                debug_assert_eq!(
+                    arg_layouts[0], arg_layouts[1],
+                    "NumGte: expected all arguments of to have the same layout"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumGte: expected to have return layout of type Bool"
+                );
+                self.build_num_gte(sym, &args[0], &args[1], &arg_layouts[0])
+            }
+            LowLevel::NumLogUnchecked => {
+                let float_width = match arg_layouts[0] {
+                    Layout::F64 => FloatWidth::F64,
+                    Layout::F32 => FloatWidth::F32,
+                    _ => unreachable!("invalid layout for sqrt"),
+                };
+
+                self.build_fn_call(
+                    sym,
+                    bitcode::NUM_LOG[float_width].to_string(),
+                    args,
+                    arg_layouts,
+                    ret_layout,
+                )
+            }
+            LowLevel::NumSqrtUnchecked => {
+                let float_width = match arg_layouts[0] {
+                    Layout::F64 => FloatWidth::F64,
+                    Layout::F32 => FloatWidth::F32,
+                    _ => unreachable!("invalid layout for sqrt"),
+                };
+
+                self.build_num_sqrt(*sym, args[0], float_width);
+            }
+            LowLevel::NumRound => self.build_fn_call(
+                sym,
+                bitcode::NUM_ROUND_F64[IntWidth::I64].to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::ListLen => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "ListLen: expected to have exactly one argument"
+                );
+                self.build_list_len(sym, &args[0])
+            }
+            LowLevel::ListWithCapacity => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "ListWithCapacity: expected to have exactly one argument"
+                );
+                let elem_layout = list_element_layout!(self.interner(), *ret_layout);
+                self.build_list_with_capacity(sym, args[0], arg_layouts[0], elem_layout, ret_layout)
+            }
+            LowLevel::ListReserve => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "ListReserve: expected to have exactly two arguments"
+                );
+                self.build_list_reserve(sym, args, arg_layouts, ret_layout)
+            }
+            LowLevel::ListAppendUnsafe => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "ListAppendUnsafe: expected to have exactly two arguments"
+                );
+                self.build_list_append_unsafe(sym, args, arg_layouts, ret_layout)
+            }
+            LowLevel::ListGetUnsafe => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "ListGetUnsafe: expected to have exactly two arguments"
+                );
+                self.build_list_get_unsafe(sym, &args[0], &args[1], ret_layout)
+            }
+            LowLevel::ListReplaceUnsafe => {
+                debug_assert_eq!(
+                    3,
+                    args.len(),
+                    "ListReplaceUnsafe: expected to have exactly three arguments"
+                );
+                self.build_list_replace_unsafe(sym, args, arg_layouts, ret_layout)
+            }
+            LowLevel::ListConcat => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "ListConcat: expected to have exactly two arguments"
+                );
+                let elem_layout = list_element_layout!(self.interner(), *ret_layout);
+                self.build_list_concat(sym, args, arg_layouts, elem_layout, ret_layout)
+            }
+            LowLevel::ListPrepend => {
+                debug_assert_eq!(
+                    2,
+                    args.len(),
+                    "ListPrepend: expected to have exactly two arguments"
+                );
+                self.build_list_prepend(sym, args, arg_layouts, ret_layout)
+            }
+            LowLevel::StrConcat => self.build_fn_call(
+                sym,
+                bitcode::STR_CONCAT.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrJoinWith => self.build_fn_call(
+                sym,
+                bitcode::STR_JOIN_WITH.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrSplit => self.build_fn_call(
+                sym,
+                bitcode::STR_SPLIT.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrStartsWith => self.build_fn_call(
+                sym,
+                bitcode::STR_STARTS_WITH.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrStartsWithScalar => self.build_fn_call(
+                sym,
+                bitcode::STR_STARTS_WITH_SCALAR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrAppendScalar => self.build_fn_call(
+                sym,
+                bitcode::STR_APPEND_SCALAR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrEndsWith => self.build_fn_call(
+                sym,
+                bitcode::STR_ENDS_WITH.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrCountGraphemes => self.build_fn_call(
+                sym,
+                bitcode::STR_COUNT_GRAPEHEME_CLUSTERS.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrSubstringUnsafe => self.build_fn_call(
+                sym,
+                bitcode::STR_SUBSTRING_UNSAFE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrToUtf8 => self.build_fn_call(
+                sym,
+                bitcode::STR_TO_UTF8.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrCountUtf8Bytes => self.build_fn_call(
+                sym,
+                bitcode::STR_COUNT_UTF8_BYTES.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrFromUtf8Range => self.build_fn_call(
+                sym,
+                bitcode::STR_FROM_UTF8_RANGE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            //            LowLevel::StrToUtf8 => self.build_fn_call(
+            //                sym,
+            //                bitcode::STR_TO_UTF8.to_string(),
+            //                args,
+            //                arg_layouts,
+            //                ret_layout,
+            //            ),
+            LowLevel::StrRepeat => self.build_fn_call(
+                sym,
+                bitcode::STR_REPEAT.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrTrim => self.build_fn_call(
+                sym,
+                bitcode::STR_TRIM.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrTrimStart => self.build_fn_call(
+                sym,
+                bitcode::STR_TRIM_START.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrTrimEnd => self.build_fn_call(
+                sym,
+                bitcode::STR_TRIM_END.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrReserve => self.build_fn_call(
+                sym,
+                bitcode::STR_RESERVE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrWithCapacity => self.build_fn_call(
+                sym,
+                bitcode::STR_WITH_CAPACITY.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrToScalars => self.build_fn_call(
+                sym,
+                bitcode::STR_TO_SCALARS.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrGetUnsafe => self.build_fn_call(
+                sym,
+                bitcode::STR_GET_UNSAFE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrGetScalarUnsafe => self.build_fn_call(
+                sym,
+                bitcode::STR_GET_SCALAR_UNSAFE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::StrToNum => {
+                let number_layout = match self.interner().get_repr(*ret_layout) {
+                    LayoutRepr::Struct(field_layouts) => field_layouts[0], // TODO: why is it sometimes a struct?
+                    _ => unreachable!(),
+                };
+
+                // match on the return layout to figure out which zig builtin we need
+                let intrinsic = match self.interner().get_repr(number_layout) {
+                    LayoutRepr::Builtin(Builtin::Int(int_width)) => &bitcode::STR_TO_INT[int_width],
+                    LayoutRepr::Builtin(Builtin::Float(float_width)) => {
+                        &bitcode::STR_TO_FLOAT[float_width]
+                    }
+                    LayoutRepr::Builtin(Builtin::Decimal) => bitcode::DEC_FROM_STR,
+                    _ => unreachable!(),
+                };
+
+                self.build_fn_call(sym, intrinsic.to_string(), args, arg_layouts, ret_layout)
+            }
+            LowLevel::PtrCast => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "RefCountGetPtr: expected to have exactly one argument"
+                );
+
+                debug_assert_eq!(
+                    self.interner().stack_size_and_alignment(arg_layouts[0]),
+                    (8, 8),
+                    "cannot pointer cast from source: {}",
+                    self.interner().dbg(arg_layouts[0])
+                );
+
+                debug_assert_eq!(
+                    self.interner().stack_size_and_alignment(*ret_layout),
+                    (8, 8),
+                    "cannot pointer cast to target: {}",
+                    self.interner().dbg(*ret_layout)
+                );
+
+                self.build_ptr_cast(sym, &args[0])
+            }
+            LowLevel::PtrStore => {
+                let element_layout = match self.interner().get_repr(arg_layouts[0]) {
+                    LayoutRepr::Ptr(inner) => inner,
+                    LayoutRepr::Boxed(inner) => inner,
+                    _ => unreachable!("cannot write to {:?}", self.interner().dbg(*ret_layout)),
+                };
+
+                self.build_ptr_store(*sym, args[0], args[1], element_layout);
+            }
+            LowLevel::PtrLoad => {
+                self.build_ptr_load(*sym, args[0], *ret_layout);
+            }
+
+            LowLevel::PtrClearTagId => {
+                self.build_ptr_clear_tag_id(*sym, args[0]);
+            }
+
+            LowLevel::Alloca => {
+                self.build_alloca(*sym, args[0], arg_layouts[0]);
+            }
+
+            LowLevel::RefCountDecRcPtr => self.build_fn_call(
+                sym,
+                bitcode::UTILS_DECREF_RC_PTR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::RefCountIncRcPtr => self.build_fn_call(
+                sym,
+                bitcode::UTILS_INCREF_RC_PTR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::RefCountDecDataPtr => self.build_fn_call(
+                sym,
+                bitcode::UTILS_DECREF_DATA_PTR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::RefCountIncDataPtr => self.build_fn_call(
+                sym,
+                bitcode::UTILS_INCREF_DATA_PTR.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::RefCountIsUnique => self.build_fn_call(
+                sym,
+                bitcode::UTILS_IS_UNIQUE.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::DictPseudoSeed => self.build_fn_call(
+                sym,
+                bitcode::UTILS_DICT_PSEUDO_SEED.to_string(),
+                args,
+                arg_layouts,
+                ret_layout,
+            ),
+            LowLevel::NumToStr => {
+                let arg_layout = arg_layouts[0];
+                let intrinsic = match self.interner().get_repr(arg_layout) {
+                    LayoutRepr::Builtin(Builtin::Int(width)) => &bitcode::STR_FROM_INT[width],
+                    LayoutRepr::Builtin(Builtin::Float(width)) => &bitcode::STR_FROM_FLOAT[width],
+                    LayoutRepr::Builtin(Builtin::Decimal) => bitcode::DEC_TO_STR,
+                    x => internal_error!("NumToStr is not defined for {:?}", x),
+                };
+
+                self.build_fn_call(sym, intrinsic.to_string(), args, arg_layouts, ret_layout)
+            }
+            LowLevel::StrIsEmpty => {
+                let intrinsic = bitcode::STR_IS_EMPTY.to_string();
+                self.build_fn_call(sym, intrinsic, args, arg_layouts, ret_layout);
+            }
+            LowLevel::NumIntCast => {
+                let source_width = match self.interner().get_repr(arg_layouts[0]) {
+                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
+                    _ => unreachable!(),
+                };
+
+                let target_width = match self.interner().get_repr(*ret_layout) {
+                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
+                    _ => unreachable!(),
+                };
+
+                self.build_num_int_cast(sym, &args[0], source_width, target_width)
+            }
+            LowLevel::NumCountLeadingZeroBits
+            | LowLevel::NumCountTrailingZeroBits
+            | LowLevel::NumCountOneBits => {
+                let int_width = match self.interner().get_repr(arg_layouts[0]) {
+                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
+                    x => internal_error!("{:?} is not defined for {:?}", lowlevel, x),
+                };
+
+                match (lowlevel, int_width) {
+                    (_, IntWidth::I128 | IntWidth::U128) => {
+                        let intrinsic = match lowlevel {
+                            LowLevel::NumCountLeadingZeroBits => {
+                                &bitcode::NUM_COUNT_LEADING_ZERO_BITS[int_width]
+                            }
+                            LowLevel::NumCountTrailingZeroBits => {
+                                &bitcode::NUM_COUNT_TRAILING_ZERO_BITS[int_width]
+                            }
+                            _ => &bitcode::NUM_COUNT_ONE_BITS[int_width],
+                        };
+                        self.build_fn_call(
+                            sym,
+                            intrinsic.to_string(),
+                            args,
+                            arg_layouts,
+                            ret_layout,
+                        )
+                    }
+                    (LowLevel::NumCountLeadingZeroBits, _) => {
+                        self.build_num_count_leading_zero_bits(sym, &args[0], int_width)
+                    }
+                    (LowLevel::NumCountTrailingZeroBits, _) => {
+                        self.build_num_count_trailing_zero_bits(sym, &args[0], int_width)
+                    }
+                    _ => self.build_num_count_one_bits(sym, &args[0], int_width),
+                }
+            }
+            LowLevel::NumToIntChecked => {
+                let source_width = match self.interner().get_repr(arg_layouts[0]) {
+                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
+                    x => internal_error!("NumToIntChecked is not defined for {:?}", x),
+                };
+
+                // The result is a `{ value, out_of_bounds }` struct.
+                let target_width = match self.interner().get_repr(*ret_layout) {
+                    LayoutRepr::Struct(&[value, _]) => match self.interner().get_repr(value) {
+                        LayoutRepr::Builtin(Builtin::Int(width)) => width,
+                        x => internal_error!("NumToIntChecked is not defined for {:?}", x),
+                    },
+                    x => internal_error!("NumToIntChecked is not defined for {:?}", x),
+                };
+
+                if matches!(source_width, IntWidth::I128 | IntWidth::U128)
+                    || matches!(target_width, IntWidth::I128 | IntWidth::U128)
+                {
+                    let intrinsic = if source_width.is_signed() {
+                        &bitcode::NUM_INT_TO_INT_CHECKING_MAX_AND_MIN[target_width][source_width]
+                    } else {
+                        &bitcode::NUM_INT_TO_INT_CHECKING_MAX[target_width][source_width]
+                    };
+                    self.build_fn_call(sym, intrinsic.to_string(), args, arg_layouts, ret_layout)
+                } else {
+                    self.build_num_to_int_checked(
+                        sym,
+                        &args[0],
+                        source_width,
+                        target_width,
+                        ret_layout,
+                    )
+                }
+            }
+            LowLevel::NumIsMultipleOf => {
+                let int_width = arg_layouts[0].try_int_width().unwrap();
+                let intrinsic = bitcode::NUM_IS_MULTIPLE_OF[int_width].to_string();
+                self.build_fn_call(sym, intrinsic, args, arg_layouts, ret_layout);
+            }
+            LowLevel::ListSublist => {
+                //    list: RocList,
+                //    alignment: u32,
+                //    element_width: usize,
+                //    start: usize,
+                //    len: usize,
+                //    dec: Dec,
+
+                let list = args[0];
+                let start = args[1];
+                let len = args[2];
+
+                let list_layout = arg_layouts[0];
+                let list_argument = self.list_argument(list_layout);
+                let element_layout = list_argument.element_layout;
+
+                let args = [
+                    list,
+                    list_argument.alignment,
+                    list_argument.element_width,
+                    start,
+                    len,
+                    self.decrement_fn_pointer(element_layout),
+                ];
+
+                let layout_usize = Layout::U64;
+
+                let arg_layouts = [
+                    arg_layouts[0],
+                    Layout::U32,
+                    layout_usize,
+                    arg_layouts[1],
+                    arg_layouts[2],
+                    layout_usize,
+                ];
+
+                let intrinsic = bitcode::LIST_SUBLIST.to_string();
+                self.build_fn_call(sym, intrinsic, &args, &arg_layouts, &list_layout);
+            }
+            LowLevel::ListSwap => {
+                let list = args[0];
+                let i = args[1];
+                let j = args[2];
+
+                let list_layout = arg_layouts[0];
+                let list_argument = self.list_argument(list_layout);
+
+                let update_mode = self.debug_symbol("update_mode");
+                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
+
+                let layout_usize = Layout::U64;
+
+                //    list: RocList,
+                //    alignment: u32,
+                //    element_width: usize,
+                //    index_1: usize,
+                //    index_2: usize,
+                //    update_mode: UpdateMode,
+
+                self.build_fn_call(
+                    sym,
+                    bitcode::LIST_SWAP.to_string(),
+                    &[
+                        list,
+                        list_argument.alignment,
+                        list_argument.element_width,
+                        i,
+                        j,
+                        update_mode,
+                    ],
+                    &[
+                        list_layout,
+                        Layout::U32,
+                        layout_usize,
+                        layout_usize,
+                        layout_usize,
+                        Layout::U8,
+                    ],
+                    ret_layout,
+                );
+            }
+            LowLevel::ListReleaseExcessCapacity => {
+                let list = args[0];
+
+                let list_layout = arg_layouts[0];
+                let list_argument = self.list_argument(list_layout);
+
+                let update_mode = self.debug_symbol("update_mode");
+                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
+
+                let layout_usize = Layout::U64;
+
+                //    list: RocList,
+                //    alignment: u32,
+                //    element_width: usize,
+                //    update_mode: UpdateMode,
+
+                self.build_fn_call(
+                    sym,
+                    bitcode::LIST_RELEASE_EXCESS_CAPACITY.to_string(),
+                    &[
+                        list,
+                        list_argument.alignment,
+                        list_argument.element_width,
+                        update_mode,
+                    ],
+                    &[list_layout, Layout::U32, layout_usize, Layout::U8],
+                    ret_layout,
+                );
+            }
+
+            LowLevel::ListDropAt => {
+                let list = args[0];
+                let drop_index = args[1];
+
+                let list_layout = arg_layouts[0];
+                let list_argument = self.list_argument(list_layout);
+                let element_layout = list_argument.element_layout;
+
+                let update_mode = self.debug_symbol("update_mode");
+                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
+
+                let layout_usize = Layout::U64;
+                let element_decrement = self.decrement_fn_pointer(element_layout);
+
+                //    list: RocList,
+                //    alignment: u32,
+                //    element_width: usize,
+                //    drop_index: usize,
+                //    dec: Dec,
+
+                self.build_fn_call(
+                    sym,
+                    bitcode::LIST_DROP_AT.to_string(),
+                    &[
+                        list,
+                        list_argument.alignment,
+                        list_argument.element_width,
+                        drop_index,
+                        element_decrement,
+                    ],
+                    &[
+                        list_layout,
+                        Layout::U32,
+                        layout_usize,
+                        layout_usize,
+                        layout_usize,
+                    ],
+                    ret_layout,
+                );
+            }
+
+            LowLevel::NumCompare => {
+                self.build_num_cmp(sym, &args[0], &args[1], &arg_layouts[0]);
+            }
+
+            x => self.build_unsupported_value(sym, ret_layout, format!("the low level {:?}", x)),
+        }
+    }
+
+    /// Builds a builtin functions that do not map directly to a low level
+    /// If the builtin is simple enough, it will be inlined.
+    fn build_builtin(
+        &mut self,
+        sym: &Symbol,
+        func_name: LambdaName,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) {
+        match func_name.name() {
+            Symbol::NUM_IS_ZERO => {
+                debug_assert_eq!(
+                    1,
+                    args.len(),
+                    "NumIsZero: expected to have exactly one argument"
+                );
+                debug_assert!(
+                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
+                    "NumIsZero: expected to have return layout of type Bool"
+                );
+
+                self.load_literal_symbols(args);
+                self.load_literal(
+                    &Symbol::DEV_TMP,
+                    &arg_layouts[0],
+                    &Literal::Int(0i128.to_ne_bytes()),
+                );
+                self.build_eq(sym, &args[0], &Symbol::DEV_TMP, &arg_layouts[0]);
+                self.free_symbol(&Symbol::DEV_TMP)
+            }
+            Symbol::LIST_GET | Symbol::LIST_SET | Symbol::LIST_REPLACE | Symbol::LIST_APPEND => {
+                // TODO: This is probably simple enough to be worth inlining.
+                let fn_name = self.lambda_name_to_string(
+                    func_name,
+                    arg_layouts.iter().copied(),
+                    None,
+                    *ret_layout,
+                );
+                // Now that the arguments are needed, load them if they are literals.
+                self.load_literal_symbols(args);
+                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
+            }
+            Symbol::BOOL_TRUE => {
+                const LITERAL: &Literal<'static> = &Literal::Bool(true);
+                const BOOL_LAYOUT: &InLayout<'static> = &Layout::BOOL;
+
+                if self.env().lazy_literals {
+                    self.literal_map().insert(*sym, (LITERAL, BOOL_LAYOUT));
+                } else {
+                    self.load_literal(sym, BOOL_LAYOUT, LITERAL);
+                }
+            }
+            Symbol::BOOL_FALSE => {
+                const LITERAL: &Literal<'static> = &Literal::Bool(false);
+                const BOOL_LAYOUT: &InLayout<'static> = &Layout::BOOL;
+
+                if self.env().lazy_literals {
+                    self.literal_map().insert(*sym, (LITERAL, BOOL_LAYOUT));
+                } else {
+                    self.load_literal(sym, BOOL_LAYOUT, LITERAL);
+                }
+            }
+            Symbol::STR_IS_VALID_SCALAR => {
+                // just call the function
+                let fn_name = self.lambda_name_to_string(
+                    func_name,
+                    arg_layouts.iter().copied(),
+                    None,
+                    *ret_layout,
+                );
+                // Now that the arguments are needed, load them if they are literals.
+                self.load_literal_symbols(args);
+                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
+            }
+            _other => {
+                // just call the function
+                let fn_name = self.lambda_name_to_string(
+                    func_name,
+                    arg_layouts.iter().copied(),
+                    None,
+                    *ret_layout,
+                );
+                // Now that the arguments are needed, load them if they are literals.
+                self.load_literal_symbols(args);
+                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
+            }
+        }
+    }
+
+    /// build_fn_call creates a call site for a function.
+    /// This includes dealing with things like saving regs and propagating the returned value.
+    fn build_fn_call(
+        &mut self,
+        dst: &Symbol,
+        fn_name: String,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_fn_call_through_slot creates a call site for a function that loads its address from
+    /// the hot reload slot named slot_name, see `Env::hot_reload`.
+    fn build_fn_call_through_slot(
+        &mut self,
+        dst: &Symbol,
+        slot_name: String,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// can_tail_call returns true if a call with these layouts can reuse the stack frame of the current proc.
+    fn can_tail_call(&self, arg_layouts: &[InLayout<'a>], ret_layout: &InLayout<'a>) -> bool;
+
+    /// build_fn_tail_call creates a call site for a function in tail position.
+    /// The stack frame of the current proc is cleaned up before jumping to the function,
+    /// so the function returns directly to our caller.
+    fn build_fn_tail_call(
+        &mut self,
+        fn_name: String,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    fn build_fn_pointer(&mut self, dst: &Symbol, fn_name: String);
+
+    /// Move a returned value into `dst`
+    fn move_return_value(&mut self, dst: &Symbol, ret_layout: &InLayout<'a>);
+
+    /// build_num_abs stores the absolute value of src into dst.
+    fn build_num_int_cast(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        source: IntWidth,
+        target: IntWidth,
+    );
+
+    /// build_num_count_leading_zero_bits stores the number of leading zero bits of src into dst.
+    /// The int is at most 64 bits.
+    fn build_num_count_leading_zero_bits(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// build_num_count_trailing_zero_bits stores the number of trailing zero bits of src into dst.
+    /// The int is at most 64 bits.
+    fn build_num_count_trailing_zero_bits(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// build_num_count_one_bits stores the number of set bits of src into dst.
+    /// The int is at most 64 bits.
+    fn build_num_count_one_bits(&mut self, dst: &Symbol, src: &Symbol, int_width: IntWidth);
+
+    /// build_num_to_int_checked stores src converted to the target width into dst, with a flag for whether it is out of bounds.
+    /// Both widths are at most 64 bits.
+    fn build_num_to_int_checked(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        source: IntWidth,
+        target: IntWidth,
+        return_layout: &InLayout<'a>,
+    );
+
+    /// build_num_abs stores the absolute value of src into dst.
+    fn build_num_abs(&mut self, dst: &Symbol, src: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_add stores the sum of src1 and src2 into dst.
+    fn build_num_add(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_add_saturated stores the sum of src1 and src2 into dst.
+    fn build_num_add_saturated(
+        &mut self,
+        dst: Symbol,
+        src1: Symbol,
+        src2: Symbol,
+        layout: InLayout<'a>,
+    );
+
+    /// build_num_sub_saturated stores the difference of src1 and src2 into dst.
+    fn build_num_sub_saturated(
+        &mut self,
+        dst: Symbol,
+        src1: Symbol,
+        src2: Symbol,
+        layout: InLayout<'a>,
+    );
+
+    /// build_num_add_checked stores the sum of src1 and src2 into dst.
+    fn build_num_add_checked(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        num_layout: &InLayout<'a>,
+        return_layout: &InLayout<'a>,
+    );
+
+    /// build_num_sub_checked stores the sum of src1 and src2 into dst.
+    fn build_num_sub_checked(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        num_layout: &InLayout<'a>,
+        return_layout: &InLayout<'a>,
+    );
+
+    /// build_num_mul_checked stores the product of src1 and src2 into dst, with whether it overflowed.
+    fn build_num_mul_checked(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        num_layout: &InLayout<'a>,
+        return_layout: &InLayout<'a>,
+    );
+
+    /// build_inline_builtin replaces the bitcode call of a low level with a short instruction sequence when that is cheaper.
+    /// These are `NUM_POW_INT` with a small literal exponent, and `NUM_ROUND_F64` from an F64 to an I64.
+    /// Returns false if the low level should be built as usual.
+    fn build_inline_builtin(
+        &mut self,
+        sym: &Symbol,
+        lowlevel: &LowLevel,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) -> bool;
+
+    /// build_int_op_overflow_checked stores the result of the `NumAdd`, `NumSub` or `NumMul` `lowlevel` of src1 and src2 into dst.
+    /// It crashes with roc_panic if the result overflows the integer `layout`.
+    fn build_int_op_overflow_checked(
+        &mut self,
+        dst: &Symbol,
+        lowlevel: &LowLevel,
+        src1: &Symbol,
+        src2: &Symbol,
+        layout: &InLayout<'a>,
+    );
+
+    /// preload_symbols loads the stack stored integer symbols into registers before they are used.
+    /// Symbols are only loaded while there are free registers, so this never spills anything.
+    fn preload_symbols(&mut self, syms: &[Symbol]);
+
+    /// build_num_mul stores `src1 * src2` into dst.
+    fn build_num_mul(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_mul_wrap stores `src1 * src2` into dst.
+    fn build_num_mul_wrap(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        layout: &InLayout<'a>,
+    );
+
+    /// build_num_mul stores `src1 / src2` into dst.
+    fn build_num_div(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_mul stores `src1 % src2` into dst.
+    fn build_num_rem(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_neg stores the negated value of src into dst.
+    fn build_num_neg(&mut self, dst: &Symbol, src: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_sub stores the `src1 - src2` difference into dst.
+    fn build_num_sub(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+
+    /// build_num_sub_wrap stores the `src1 - src2` difference into dst.
+    fn build_num_sub_wrap(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        layout: &InLayout<'a>,
+    );
+
+    /// stores the `src1 & src2` into dst.
+    fn build_int_bitwise_and(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// stores the `src1 | src2` into dst.
+    fn build_int_bitwise_or(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// stores the `src1 ^ src2` into dst.
+    fn build_int_bitwise_xor(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// stores the `Num.shiftLeftBy src1 src2` into dst.
+    fn build_int_shift_left(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// stores the `Num.shiftRightBy src1 src2` into dst.
+    fn build_int_shift_right(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// stores the `Num.shiftRightZfBy src1 src2` into dst.
+    fn build_int_shift_right_zero_fill(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    );
+
+    /// build_eq stores the result of `src1 == src2` into dst.
+    fn build_eq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>);
+
+    /// build_neq stores the result of `src1 != src2` into dst.
+    fn build_neq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>);
+
+    /// build_not stores the result of `!src` into dst.
+    fn build_not(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+
+    fn build_num_cmp(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+    );
+
+    /// build_num_lt stores the result of `src1 < src2` into dst.
+    fn build_num_lt(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+    );
+
+    /// build_num_gt stores the result of `src1 > src2` into dst.
+    fn build_num_gt(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+    );
+
+    /// build_num_to_frac convert Number to Frac
+    fn build_num_to_frac(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        arg_layout: &InLayout<'a>,
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_num_is_nan check is a Frac is NaN
+    fn build_num_is_nan(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+
+    /// build_num_is_infinite check is a Frac is infinite
+    fn build_num_is_infinite(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+
+    /// build_num_is_finite check is a Frac is finite
+    fn build_num_is_finite(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+
+    /// build_num_float_to_bits stores the bits of the float src into the int dst.
+    fn build_num_float_to_bits(&mut self, dst: &Symbol, src: &Symbol, float_width: FloatWidth);
+
+    /// build_num_float_from_bits stores the bits of the int src into the float dst.
+    fn build_num_float_from_bits(&mut self, dst: &Symbol, src: &Symbol, float_width: FloatWidth);
+
+    /// build_num_lte stores the result of `src1 <= src2` into dst.
+    fn build_num_lte(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+    );
+
+    /// build_num_gte stores the result of `src1 >= src2` into dst.
+    fn build_num_gte(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+    );
+
+    /// build_sqrt stores the result of `sqrt(src)` into dst.
+    fn build_num_sqrt(&mut self, dst: Symbol, src: Symbol, float_width: FloatWidth);
+
+    /// build_list_len returns the length of a list.
+    fn build_list_len(&mut self, dst: &Symbol, list: &Symbol);
+
+    /// generate a call to a higher-order lowlevel
+    fn build_higher_order_lowlevel(
+        &mut self,
+        dst: &Symbol,
+        holl: &HigherOrderLowLevel<'a>,
+        ret_layout: InLayout<'a>,
+    );
+
+    fn build_indirect_inc(&mut self, layout: InLayout<'a>) -> Symbol;
+    fn build_indirect_dec(&mut self, layout: InLayout<'a>) -> Symbol;
+
+    /// build_list_with_capacity creates and returns a list with the given capacity.
+    fn build_list_with_capacity(
+        &mut self,
+        dst: &Symbol,
+        capacity: Symbol,
+        capacity_layout: InLayout<'a>,
+        elem_layout: InLayout<'a>,
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_reserve enlarges a list to at least accommodate the given capacity.
+    fn build_list_reserve(
+        &mut self,
+        dst: &Symbol,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_append_unsafe returns a new list with a given element appended.
+    fn build_list_append_unsafe(
+        &mut self,
+        dst: &Symbol,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_get_unsafe loads the element from the list at the index.
+    fn build_list_get_unsafe(
+        &mut self,
+        dst: &Symbol,
+        list: &Symbol,
+        index: &Symbol,
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_replace_unsafe returns the old element and new list with the list having the new element inserted.
+    fn build_list_replace_unsafe(
+        &mut self,
+        dst: &Symbol,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_concat returns a new list containing the two argument lists concatenated.
+    fn build_list_concat(
+        &mut self,
+        dst: &Symbol,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        elem_layout: InLayout<'a>,
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_list_prepend returns a new list with a given element prepended.
+    fn build_list_prepend(
+        &mut self,
+        dst: &Symbol,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    );
+
+    /// build_refcount_getptr loads the pointer to the reference count of src into dst.
+    fn build_ptr_cast(&mut self, dst: &Symbol, src: &Symbol);
+
+    fn build_ptr_store(
+        &mut self,
+        sym: Symbol,
+        ptr: Symbol,
+        value: Symbol,
+        element_layout: InLayout<'a>,
+    );
+
+    fn build_ptr_load(&mut self, sym: Symbol, ptr: Symbol, element_layout: InLayout<'a>);
+
+    fn build_ptr_clear_tag_id(&mut self, sym: Symbol, ptr: Symbol);
+
+    fn build_alloca(&mut self, sym: Symbol, value: Symbol, element_layout: InLayout<'a>);
+
+    /// literal_map gets the map from symbol to literal and layout, used for lazy loading and literal folding.
+    fn literal_map(&mut self) -> &mut MutMap<Symbol, (*const Literal<'a>, *const InLayout<'a>)>;
+
+    fn load_literal_symbols(&mut self, syms: &[Symbol]) {
+        if self.env().lazy_literals {
+            for sym in syms {
+                if let Some((lit, layout)) = self.literal_map().remove(sym) {
+                    // This operation is always safe but complicates lifetimes.
+                    // The map is reset when building a procedure and then used for that single procedure.
+                    // Since the lifetime is shorter than the entire backend, we need to use a pointer.
+                    let (lit, layout) = unsafe { (*lit, *layout) };
+                    self.load_literal(sym, &layout, &lit);
+                }
+            }
+        }
+    }
+
+    /// literal_switch_branch finds the branch of a switch whose condition is a lazy integer or bool literal.
+    fn literal_switch_branch(
+        &mut self,
+        cond_symbol: &Symbol,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+    ) -> Option<&'a Stmt<'a>> {
+        if !self.env().lazy_literals {
+            return None;
+        }
+        let value = self.lazy_int_literal(cond_symbol)?;
+        taken_branch(value, branches, default_branch.1)
+    }
+
+    /// The helper that builds the refcount `lowlevel`, see `RefcountHelper`.
+    /// Returns None if it is left to the bitcode, which is not allowed with atomic refcounts.
+    fn refcount_helper(&mut self, lowlevel: &LowLevel, args: &[Symbol]) -> Option<RefcountHelper> {
+        let helper = match lowlevel {
+            LowLevel::RefCountIncDataPtr => RefcountHelper::IncrefDataPtr,
+            LowLevel::RefCountIncRcPtr => RefcountHelper::IncrefRcPtr,
+            LowLevel::RefCountDecDataPtr | LowLevel::RefCountDecRcPtr => {
+                let alignment = self.lazy_int_literal(&args[1]);
+                let alignment = DECREF_HELPER_ALIGNMENTS
+                    .into_iter()
+                    .find(|x| Some(*x as i128) == alignment);
+                match (alignment, lowlevel) {
+                    (Some(alignment), LowLevel::RefCountDecDataPtr) => {
+                        RefcountHelper::DecrefDataPtr(alignment)
+                    }
+                    (Some(alignment), _) => RefcountHelper::DecrefRcPtr(alignment),
+                    (None, _) if self.env().atomic_refcounts => {
+                        internal_error!(
+                            "atomic refcounts need the alignment of a decref as a lazy literal"
+                        )
+                    }
+                    (None, _) => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(helper)
+    }
+
+    /// The value of sym, if it is an integer or bool literal that has not been loaded yet.
+    fn lazy_int_literal(&mut self, sym: &Symbol) -> Option<i128> {
+        let (lit, _) = self.literal_map().get(sym)?;
+        // See `load_literal_symbols` for why the literal is behind a pointer.
+        literal_int_value(unsafe { &**lit })
+    }
+
+    /// fold_literal_low_level computes a low level call whose arguments are all lazy integer or bool literals.
+    /// The result is added to the literal map instead of emitting any instructions.
+    /// Returns false if the call can not be folded, see `fold_low_level`.
+    fn fold_literal_low_level(
+        &mut self,
+        sym: &Symbol,
+        lowlevel: &LowLevel,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        if !self.env().lazy_literals {
+            return false;
+        }
+        let arg_layout = match arg_layouts.first() {
+            Some(layout) if arg_layouts.iter().all(|other| other == layout) => *layout,
+            _ => return false,
+        };
+        let width = if arg_layout == Layout::BOOL {
+            None
+        } else {
+            match arg_layout.try_int_width() {
+                Some(width) if width.stack_size() <= 8 => Some(width),
+                _ => return false,
+            }
+        };
+
+        let mut values = Vec::with_capacity_in(args.len(), self.env().arena);
+        for arg in args {
+            let Some((lit, _)) = self.literal_map().get(arg) else {
+                return false;
+            };
+            // See `load_literal_symbols` for why the literal is behind a pointer.
+            match literal_int_value(unsafe { &**lit }) {
+                Some(value) => values.push(width.map_or(value, |width| wrap_int(value, width))),
+                None => return false,
+            }
+        }
+
+        let lit = match fold_low_level(*lowlevel, width, &values) {
+            Some(lit @ Literal::Bool(_)) if *ret_layout == Layout::BOOL => lit,
+            Some(lit @ Literal::Int(_)) if *ret_layout == arg_layout => lit,
+            _ => return false,
+        };
+        let arena = self.env().arena;
+        let lit: &'a Literal<'a> = arena.alloc(lit);
+        let layout: &'a InLayout<'a> = arena.alloc(*ret_layout);
+        self.literal_map().insert(*sym, (lit, layout));
+        true
+    }
+
+    /// load_literal sets a symbol to be equal to a literal.
+    fn load_literal(&mut self, sym: &Symbol, layout: &InLayout<'a>, lit: &Literal<'a>);
+
+    fn load_literal_i64(&mut self, sym: &Symbol, value: i64) {
+        let literal = Literal::Int((value as i128).to_ne_bytes());
+
+        self.load_literal(sym, &Layout::I64, &literal)
+    }
+
+    fn load_literal_i32(&mut self, sym: &Symbol, value: i32) {
+        let literal = Literal::Int((value as i128).to_ne_bytes());
+
+        self.load_literal(sym, &Layout::I32, &literal)
+    }
+
+    fn load_literal_i16(&mut self, sym: &Symbol, value: i16) {
+        let literal = Literal::Int((value as i128).to_ne_bytes());
+
+        self.load_literal(sym, &Layout::I16, &literal)
+    }
+
+    fn load_literal_i8(&mut self, sym: &Symbol, value: i8) {
+        let literal = Literal::Int((value as i128).to_ne_bytes());
+
+        self.load_literal(sym, &Layout::I8, &literal)
+    }
+
+    /// create_empty_array creates an empty array with nullptr, zero length, and zero capacity.
+    fn create_empty_array(&mut self, sym: &Symbol);
+
+    /// create_array creates an array filling it with the specified objects.
+    fn create_array(
+        &mut self,
+        sym: &Symbol,
+        elem_layout: &InLayout<'a>,
+        elems: &'a [ListLiteralElement<'a>],
+    );
+
+    /// create_struct creates a struct with the elements specified loaded into it as data.
+    fn create_struct(&mut self, sym: &Symbol, layout: &InLayout<'a>, fields: &'a [Symbol]);
+
+    /// load_struct_at_index loads into `sym` the value at `index` in `structure`.
+    fn load_struct_at_index(
+        &mut self,
+        sym: &Symbol,
+        structure: &Symbol,
+        index: u64,
+        field_layouts: &'a [InLayout<'a>],
+    );
+
+    /// load_union_at_index loads into `sym` the value at `index` for `tag_id`.
+    fn load_union_at_index(
+        &mut self,
+        sym: &Symbol,
+        structure: &Symbol,
+        tag_id: TagIdIntType,
+        index: u64,
+        union_layout: &UnionLayout<'a>,
+    );
+
+    /// load_union_at_index loads into `sym` the value at `index` for `tag_id`.
+    fn load_union_field_ptr_at_index(
+        &mut self,
+        sym: &Symbol,
+        structure: &Symbol,
+        tag_id: TagIdIntType,
+        index: u64,
+        union_layout: &UnionLayout<'a>,
+    );
+
+    /// get_tag_id loads the tag id from a the union.
+    fn get_tag_id(&mut self, sym: &Symbol, structure: &Symbol, union_layout: &UnionLayout<'a>);
+
+    /// tag sets the tag for a union.
+    fn tag(
+        &mut self,
+        sym: &Symbol,
+        args: &'a [Symbol],
+        tag_layout: &UnionLayout<'a>,
+        tag_id: TagIdIntType,
+        reuse: Option<Symbol>,
+    );
+
+    /// load a value from a pointer
+    fn expr_unbox(&mut self, sym: Symbol, ptr: Symbol, element_layout: InLayout<'a>);
+
+    /// store a refcounted value on the heap
+    fn expr_box(
+        &mut self,
+        sym: Symbol,
+        value: Symbol,
+        element_layout: InLayout<'a>,
+        reuse: Option<Symbol>,
+    );
+
+    /// return_symbol moves a symbol to the correct return location for the backend and adds a jump to the end of the function.
+    fn return_symbol(&mut self, sym: &Symbol, layout: &InLayout<'a>);
+
+    /// free_symbols will free all symbols for the given statement.
+    fn free_symbols(&mut self, stmt: &Stmt<'a>) {
+        if let Some(syms) = self.free_map().remove(&(stmt as *const Stmt<'a>)) {
+            for sym in syms {
+                // println!("Freeing symbol: {:?}", sym);
+                self.free_symbol(&sym);
+            }
+        }
+    }
+
+    /// free_symbol frees any registers or stack space used to hold a symbol.
+    fn free_symbol(&mut self, sym: &Symbol);
+
+    /// set_layout_map sets the layout for a specific symbol.
+    fn set_layout_map(&mut self, sym: Symbol, layout: &InLayout<'a>) {
+        if let Some(old_layout) = self.layout_map().insert(sym, *layout) {
+            // Layout map already contains the symbol. We should never need to overwrite.
+            // If the layout is not the same, that is a bug.
+            if &old_layout != layout {
+                internal_error!(
+                    "Overwriting layout for symbol, {:?}: got {:?}, want {:?}",
+                    sym,
+                    layout,
+                    old_layout
+                )
+            }
+        }
+    }
+
+    /// layout_map gets the map from symbol to layout.
+    fn layout_map(&mut self) -> &mut MutMap<Symbol, InLayout<'a>>;
+
+    /// free_map gets the map statement to the symbols that are free after they run.
+    fn free_map(&mut self) -> &mut MutMap<*const Stmt<'a>, Vec<'a, Symbol>>;
+
+    /// set_free_map sets the free map to the given map.
+    fn set_free_map(&mut self, map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>);
+
+    /// free_branch_symbols will free all symbols that are dead when entering the given switch branch.
+    fn free_branch_symbols(&mut self, branch: &Stmt<'a>) {
+        if let Some(syms) = self.branch_free_map().remove(&(branch as *const Stmt<'a>)) {
+            for sym in syms {
+                self.free_symbol(&sym);
+            }
+        }
+    }
+
+    /// branch_free_map gets the map from the first statement of a switch branch to the symbols that are free when entering it.
+    fn branch_free_map(&mut self) -> &mut MutMap<*const Stmt<'a>, Vec<'a, Symbol>>;
+
+    /// set_branch_free_map sets the branch free map to the given map.
+    fn set_branch_free_map(&mut self, map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>);
+
+    /// fused_compares gets the comparisons that are only used by the switch right after them.
+    fn fused_compares(&mut self) -> &mut MutSet<Symbol>;
+
+    /// set_fused_compares sets the fused compares to the given set.
+    fn set_fused_compares(&mut self, set: MutSet<Symbol>);
+
+    /// hoisted_literals gets the literals that were moved in front of a loop.
+    /// They are loaded where they are defined instead of lazily.
+    fn hoisted_literals(&mut self) -> &mut MutSet<Symbol>;
+
+    /// scan_ast runs through the ast and fills the free maps and live intervals.
+    fn scan_ast(&mut self, stmt: &'a Stmt<'a>) {
+        let scan_start = self.env().phase_timing.then(Instant::now);
+        let Liveness {
+            free_map,
+            branch_free_map,
+            fused_compares,
+            stmt_positions,
+            live_intervals,
+            call_positions,
+            layouts,
+            sole_uses,
+            ..
+        } = Liveness::scan_ast(stmt);
+
+        let arena = self.env().arena;
+        let to_arena = |map: MutMap<*const Stmt<'a>, std::vec::Vec<Symbol>>| -> MutMap<_, _> {
+            map.into_iter()
+                .map(|(stmt, syms)| (stmt, Vec::from_iter_in(syms, arena)))
+                .collect()
+        };
+        self.set_free_map(to_arena(free_map));
+        self.set_branch_free_map(to_arena(branch_free_map));
+        self.set_fused_compares(fused_compares);
+        let allocation_start = scan_start.map(|scan_start| {
+            self.phase_times_mut().scan_ast += scan_start.elapsed();
+            Instant::now()
+        });
+        self.set_live_intervals(
+            stmt_positions,
+            live_intervals,
+            call_positions,
+            layouts,
+            sole_uses,
+        );
+        if let Some(start) = allocation_start {
+            self.phase_times_mut().register_allocation += start.elapsed();
+        }
+    }
+
+    /// set_live_intervals passes the statement positions and symbol live intervals from the scan to the register allocator.
+    /// `call_positions` are the sorted positions of statements that call functions.
+    /// `layouts` contains the layouts of all symbols defined in the proc body.
+    /// `sole_uses` are the symbols whose only use is as a call argument or return value.
+    fn set_live_intervals(
+        &mut self,
+        stmt_positions: MutMap<*const Stmt<'a>, u32>,
+        live_intervals: MutMap<Symbol, LiveInterval>,
+        call_positions: std::vec::Vec<u32>,
+        layouts: MutMap<Symbol, InLayout<'a>>,
+        sole_uses: MutMap<Symbol, SoleUse<'a>>,
+    );
+
+    /// enter_stmt lets the register allocator know which statement is being built.
+    fn enter_stmt(&mut self, stmt: &Stmt<'a>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm_snapshot::Fixture;
+    use crate::generic64::new_backend_64bit;
+    use crate::generic64::x86_64::{
+        X86_64Assembler, X86_64FloatReg, X86_64GeneralReg, X86_64SystemV,
+    };
+    use bumpalo::Bump;
+    use roc_target::TargetInfo;
+
+    #[test]
+    fn self_tail_calls_loop_through_a_join_point_of_their_own() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let x = f.symbol("x");
+        let r = f.symbol("r");
+        let mono_id = JoinPointId(f.symbol("mono_loop"));
+
+        // A loop that mono made, with a self call in tail position left in it.
+        let mut proc = f.proc("count", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+        let call = f.call(&proc, &[x]);
+        let loop_body = Stmt::Let(r, call, Layout::U64, f.arena.alloc(Stmt::Ret(r)));
+        proc.body = Stmt::Join {
+            id: mono_id,
+            parameters: &[],
+            body: f.arena.alloc(loop_body),
+            remainder: f.arena.alloc(Stmt::Jump(mono_id, &[])),
+        };
+        proc.is_self_recursive = SelfRecursive::SelfRecursive(mono_id);
+
+        let env = f.env_builder().build();
+        let mut backend =
+            new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+                &env,
+                TargetInfo::default_x86_64(),
+                &mut f.interns,
+                &mut f.layout_interner,
+            );
+        let (args, body) = backend
+            .loopify_self_tail_calls(&proc)
+            .expect("the self call was not rewritten");
+
+        let Stmt::Join {
+            id,
+            parameters,
+            body: mono_loop,
+            remainder,
+        } = body
+        else {
+            panic!("no join point around {:?}", body);
+        };
+        assert_ne!(id, mono_id);
+        assert_eq!(parameters.len(), 1);
+        assert_eq!(parameters[0].symbol, x);
+        assert_eq!(remainder, &Stmt::Jump(id, &[args[0].1]));
+
+        // The self call jumps to the new join point, not to the one of mono.
+        let Stmt::Join {
+            id: inner_id,
+            body: inner_body,
+            ..
+        } = mono_loop
+        else {
+            panic!("the loop of mono is gone from {:?}", mono_loop);
+        };
+        assert_eq!(*inner_id, mono_id);
+        assert_eq!(*inner_body, &Stmt::Jump(id, &[x]));
+
+        // Both join points are built, each with its own target.
+        proc.args = args;
+        proc.body = body.clone();
+        backend.build_proc(proc, &mut LayoutIds::default(), false);
+    }
+}
@@ -0,0 +1,347 @@
+//! Minimal DWARF 5 debug info for the procs of a dev backend object.
+//! Only split debug info is supported: the main object gets a skeleton unit and
+//! the address table, everything else goes into a separate `.dwo` object.
+
+use object::write::{Object, Relocation, SectionId, SymbolId};
+use object::{BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SectionKind};
+use roc_error_macros::internal_error;
+
+use crate::BackendError;
+use std::hash::{Hash, Hasher};
+
+const DW_UT_SPLIT_COMPILE: u8 = 0x05;
+const DW_UT_SKELETON: u8 = 0x04;
+
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+const DW_TAG_SKELETON_UNIT: u8 = 0x4a;
+
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_AT_ADDR_BASE: u8 = 0x73;
+const DW_AT_DWO_NAME: u8 = 0x76;
+
+const DW_FORM_DATA8: u8 = 0x07;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+const DW_FORM_ADDRX: u8 = 0x1b;
+
+const DW_CHILDREN_NO: u8 = 0;
+const DW_CHILDREN_YES: u8 = 1;
+
+const PRODUCER: &str = "roc dev backend";
+
+/// Size of the .debug_addr header, which is also the value of DW_AT_addr_base.
+pub(crate) const DEBUG_ADDR_HEADER_SIZE: u64 = 8;
+
+/// A proc to describe in the debug info.
+pub struct DebugProc<'p> {
+    pub name: &'p str,
+    pub symbol: SymbolId,
+    pub code_size: u64,
+}
+
+/// Adds the skeleton unit and address table to `output`
+/// and returns the `.dwo` object containing the rest of the debug info.
+/// Only ELF objects can get split debug info.
+pub fn add_split_debug_info<'a>(
+    output: &mut Object,
+    dwo_name: &str,
+    procs: &[DebugProc],
+) -> Result<Object<'a>, BackendError> {
+    if output.format() != BinaryFormat::Elf {
+        return Err(BackendError::UnsupportedDebugInfo(format!(
+            "{:?}",
+            output.format()
+        )));
+    }
+
+    let dwo_id = dwo_id(dwo_name, procs);
+
+    // Skeleton unit in the main object.
+    let mut abbrev = std::vec::Vec::new();
+    abbrev.extend([1, DW_TAG_SKELETON_UNIT, DW_CHILDREN_NO]);
+    abbrev.extend([DW_AT_DWO_NAME, DW_FORM_STRING]);
+    abbrev.extend([DW_AT_ADDR_BASE, DW_FORM_SEC_OFFSET]);
+    abbrev.extend([0, 0, 0]);
+
+    let mut info = unit_header(DW_UT_SKELETON, dwo_id);
+    let abbrev_offset_pos = 8;
+    info.push(1);
+    write_cstr(&mut info, dwo_name);
+    let addr_base_pos = info.len();
+    info.extend(0u32.to_le_bytes());
+    finish_unit(&mut info);
+
+    let mut addr = std::vec::Vec::new();
+    addr.extend(((DEBUG_ADDR_HEADER_SIZE - 4) as u32 + 8 * procs.len() as u32).to_le_bytes());
+    addr.extend(5u16.to_le_bytes());
+    addr.push(8); // address size
+    addr.push(0); // segment selector size
+
+    let abbrev_section = add_debug_section(output, b".debug_abbrev");
+    let info_section = add_debug_section(output, b".debug_info");
+    let addr_section = add_debug_section(output, b".debug_addr");
+
+    let abbrev_base = output.append_section_data(abbrev_section, &abbrev, 1);
+    let info_base = output.append_section_data(info_section, &info, 1);
+    let abbrev_symbol = output.section_symbol(abbrev_section);
+    let addr_symbol = output.section_symbol(addr_section);
+    add_debug_relocation(
+        output,
+        info_section,
+        info_base + abbrev_offset_pos,
+        32,
+        abbrev_symbol,
+        abbrev_base as i64,
+    );
+
+    let addr_base = output.append_section_data(addr_section, &addr, 1);
+    add_debug_relocation(
+        output,
+        info_section,
+        info_base + addr_base_pos as u64,
+        32,
+        addr_symbol,
+        (addr_base + DEBUG_ADDR_HEADER_SIZE) as i64,
+    );
+    for proc in procs {
+        let offset = output.append_section_data(addr_section, &0u64.to_le_bytes(), 1);
+        add_debug_relocation(output, addr_section, offset, 64, proc.symbol, 0);
+    }
+
+    // Full unit in the dwo object.
+    let mut dwo_abbrev = std::vec::Vec::new();
+    dwo_abbrev.extend([1, DW_TAG_COMPILE_UNIT, DW_CHILDREN_YES]);
+    dwo_abbrev.extend([DW_AT_PRODUCER, DW_FORM_STRING]);
+    dwo_abbrev.extend([DW_AT_NAME, DW_FORM_STRING]);
+    dwo_abbrev.extend([0, 0]);
+    dwo_abbrev.extend([2, DW_TAG_SUBPROGRAM, DW_CHILDREN_NO]);
+    dwo_abbrev.extend([DW_AT_NAME, DW_FORM_STRING]);
+    dwo_abbrev.extend([DW_AT_LOW_PC, DW_FORM_ADDRX]);
+    dwo_abbrev.extend([DW_AT_HIGH_PC, DW_FORM_DATA8]);
+    dwo_abbrev.extend([0, 0, 0]);
+
+    let mut dwo_info = unit_header(DW_UT_SPLIT_COMPILE, dwo_id);
+    dwo_info.push(1);
+    write_cstr(&mut dwo_info, PRODUCER);
+    write_cstr(&mut dwo_info, dwo_name);
+    for (index, proc) in procs.iter().enumerate() {
+        dwo_info.push(2);
+        write_cstr(&mut dwo_info, proc.name);
+        write_uleb128(&mut dwo_info, index as u64);
+        // With a constant class form, high_pc is the size of the proc.
+        dwo_info.extend(proc.code_size.to_le_bytes());
+    }
+    dwo_info.push(0);
+    finish_unit(&mut dwo_info);
+
+    let mut dwo = Object::new(output.format(), output.architecture(), Endianness::Little);
+    let dwo_abbrev_section = add_debug_section(&mut dwo, b".debug_abbrev.dwo");
+    let dwo_info_section = add_debug_section(&mut dwo, b".debug_info.dwo");
+    dwo.append_section_data(dwo_abbrev_section, &dwo_abbrev, 1);
+    dwo.append_section_data(dwo_info_section, &dwo_info, 1);
+    Ok(dwo)
+}
+
+/// Writes a 32 bit DWARF 5 unit header with a placeholder length and a zero abbrev offset.
+fn unit_header(unit_type: u8, dwo_id: u64) -> std::vec::Vec<u8> {
+    let mut out = std::vec::Vec::new();
+    out.extend(0u32.to_le_bytes()); // unit length, set by finish_unit
+    out.extend(5u16.to_le_bytes()); // version
+    out.push(unit_type);
+    out.push(8); // address size
+    out.extend(0u32.to_le_bytes()); // debug_abbrev offset
+    out.extend(dwo_id.to_le_bytes());
+    out
+}
+
+fn finish_unit(unit: &mut [u8]) {
+    let length = unit.len() as u32 - 4;
+    unit[..4].copy_from_slice(&length.to_le_bytes());
+}
+
+fn add_debug_section(output: &mut Object, name: &[u8]) -> SectionId {
+    output.add_section(vec![], name.to_vec(), SectionKind::Debug)
+}
+
+fn add_debug_relocation(
+    output: &mut Object,
+    section: SectionId,
+    offset: u64,
+    size: u8,
+    symbol: SymbolId,
+    addend: i64,
+) {
+    let reloc = Relocation {
+        offset,
+        size,
+        kind: RelocationKind::Absolute,
+        encoding: RelocationEncoding::Generic,
+        symbol,
+        addend,
+    };
+    if let Err(e) = output.add_relocation(section, reloc) {
+        internal_error!("{:?}", e);
+    }
+}
+
+/// The dwo id links the skeleton unit to the unit in the dwo object.
+fn dwo_id(dwo_name: &str, procs: &[DebugProc]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    dwo_name.hash(&mut hasher);
+    for proc in procs {
+        proc.name.hash(&mut hasher);
+        proc.code_size.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn write_cstr(out: &mut std::vec::Vec<u8>, s: &str) {
+    out.extend(s.as_bytes());
+    out.push(0);
+}
+
+fn write_uleb128(out: &mut std::vec::Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use object::read::{Object as _, ObjectSection};
+    use object::write::{StandardSection, Symbol, SymbolSection};
+    use object::{Architecture, SymbolFlags, SymbolKind, SymbolScope};
+
+    /// An object with a 6 byte proc named `answer`.
+    fn object_with_proc<'a>(format: BinaryFormat) -> (Object<'a>, SymbolId) {
+        let mut output = Object::new(format, Architecture::X86_64, Endianness::Little);
+        let text = output.section_id(StandardSection::Text);
+        let symbol = output.add_symbol(Symbol {
+            name: b"answer".to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        });
+        output.add_symbol_data(symbol, text, &[0xc3; 6], 16);
+        (output, symbol)
+    }
+
+    fn section_data(object: &object::File, name: &str) -> std::vec::Vec<u8> {
+        let section = object.section_by_name(name).unwrap();
+        section.data().unwrap().to_vec()
+    }
+
+    #[test]
+    fn split_units() {
+        let (mut output, symbol) = object_with_proc(BinaryFormat::Elf);
+        let procs = [DebugProc {
+            name: "answer",
+            symbol,
+            code_size: 6,
+        }];
+        let dwo = add_split_debug_info(&mut output, "app.dwo", &procs).unwrap();
+        let output = output.write().unwrap();
+        let dwo = dwo.write().unwrap();
+        let output = object::File::parse(output.as_slice()).unwrap();
+        let dwo = object::File::parse(dwo.as_slice()).unwrap();
+
+        let skeleton = section_data(&output, ".debug_info");
+        let split = section_data(&dwo, ".debug_info.dwo");
+        for unit in [&skeleton, &split] {
+            let length = u32::from_le_bytes(unit[..4].try_into().unwrap());
+            assert_eq!(length as usize, unit.len() - 4);
+            assert_eq!(u16::from_le_bytes([unit[4], unit[5]]), 5);
+            assert_eq!(unit[7], 8, "address size");
+        }
+        assert_eq!(skeleton[6], DW_UT_SKELETON);
+        assert_eq!(split[6], DW_UT_SPLIT_COMPILE);
+        // The dwo id is what links the skeleton to the split unit.
+        assert_eq!(skeleton[12..20], split[12..20]);
+
+        let mut pos = 20;
+        assert_eq!(skeleton[pos], 1);
+        pos += 1;
+        assert_eq!(read_cstr(&skeleton, &mut pos), "app.dwo");
+        assert_eq!(
+            read_subprograms(&split),
+            [("answer".to_string(), 0, 6)].to_vec()
+        );
+
+        // The address table has one slot for the proc after its header.
+        let addr = section_data(&output, ".debug_addr");
+        assert_eq!(addr.len() as u64, DEBUG_ADDR_HEADER_SIZE + 8);
+        let length = u32::from_le_bytes(addr[..4].try_into().unwrap());
+        assert_eq!(length as usize, addr.len() - 4);
+    }
+
+    #[test]
+    fn split_debug_info_needs_elf() {
+        let (mut output, symbol) = object_with_proc(BinaryFormat::MachO);
+        let procs = [DebugProc {
+            name: "answer",
+            symbol,
+            code_size: 6,
+        }];
+        assert_eq!(
+            add_split_debug_info(&mut output, "app.dwo", &procs).err(),
+            Some(BackendError::UnsupportedDebugInfo("MachO".to_string()))
+        );
+    }
+
+    /// The name, address index and size of every subprogram in the `.debug_info.dwo` section of a dwo object.
+    pub(crate) fn read_subprograms(dwo_info: &[u8]) -> std::vec::Vec<(String, u64, u64)> {
+        // The 32 bit DWARF 5 header of a split unit, see `unit_header`.
+        let mut pos = 20;
+        assert_eq!(dwo_info[pos], 1, "no compile unit");
+        pos += 1;
+        let _producer = read_cstr(dwo_info, &mut pos);
+        let _name = read_cstr(dwo_info, &mut pos);
+        let mut subprograms = std::vec::Vec::new();
+        while dwo_info[pos] != 0 {
+            assert_eq!(dwo_info[pos], 2, "no subprogram at {}", pos);
+            pos += 1;
+            let name = read_cstr(dwo_info, &mut pos);
+            let index = read_uleb128(dwo_info, &mut pos);
+            let size = u64::from_le_bytes(dwo_info[pos..pos + 8].try_into().unwrap());
+            pos += 8;
+            subprograms.push((name, index, size));
+        }
+        subprograms
+    }
+
+    fn read_cstr(bytes: &[u8], pos: &mut usize) -> String {
+        let len = bytes[*pos..].iter().position(|byte| *byte == 0).unwrap();
+        let s = String::from_utf8(bytes[*pos..*pos + len].to_vec()).unwrap();
+        *pos += len + 1;
+        s
+    }
+
+    fn read_uleb128(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+            shift += 7;
+        }
+    }
+}
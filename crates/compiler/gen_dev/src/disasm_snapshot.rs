@@ -0,0 +1,663 @@
+//! Snapshot tests of the x86_64 code that the dev backend builds for small mono IR procs.
+//! The code of every fixture is disassembled and compared against `src/snapshots/<fixture>.txt`,
+//! so changes to instruction selection show up in review without running any code.
+//!
+//! A missing snapshot fails the test. Run the tests with `ROC_UPDATE_SNAPSHOTS=1` to write the snapshots
+//! of new fixtures and the ones that changed, and check them in along with the fixture.
+
+use std::path::PathBuf;
+
+use bumpalo::Bump;
+use capstone::prelude::*;
+use object::write::Object;
+use object::{Architecture, BinaryFormat, Endianness};
+use roc_collections::all::MutMap;
+use roc_module::ident::ModuleName;
+use roc_module::low_level::LowLevel;
+use roc_module::symbol::{IdentIds, Interns, ModuleId, ModuleIds, Symbol};
+use roc_mono::ir::{
+    BranchInfo, Call, CallSpecId, CallType, Expr, HostExposedLayouts, Literal, OptLevel, Proc,
+    ProcLayout, SelfRecursive, Stmt, UpdateModeId,
+};
+use roc_mono::layout::{
+    InLayout, LambdaName, Layout, LayoutIds, LayoutRepr, STLayoutInterner, UnionLayout,
+};
+use roc_region::all::Region;
+use roc_target::TargetInfo;
+
+use crate::generic64::aarch64::{AArch64Assembler, AArch64Call};
+use crate::generic64::verify::verify_code;
+use crate::generic64::x86_64::{
+    X86_64Assembler, X86_64FloatReg, X86_64GeneralReg, X86_64SystemV, X86_64WindowsFastcall,
+};
+use crate::generic64::{new_backend_64bit, Assembler, CallConv, RegTrait};
+use crate::object_builder::build_object_with_backend;
+use crate::{AssemblyBackendMode, Backend, BuildReport, DevOptLevel, EnvBuilder};
+
+/// The module of the fixtures, with the interner of their layouts.
+/// The tests of other modules use it to build their procs too.
+pub(crate) struct Fixture<'a> {
+    pub(crate) arena: &'a Bump,
+    pub(crate) home: ModuleId,
+    pub(crate) interns: Interns,
+    pub(crate) layout_interner: STLayoutInterner<'a>,
+}
+
+impl<'a> Fixture<'a> {
+    pub(crate) fn new(arena: &'a Bump) -> Self {
+        let mut module_ids = ModuleIds::default();
+        let home = module_ids.get_or_insert(&ModuleName::from("Test"));
+        let mut all_ident_ids = IdentIds::exposed_builtins(1);
+        all_ident_ids.insert(home, IdentIds::default());
+
+        Fixture {
+            arena,
+            home,
+            interns: Interns {
+                module_ids,
+                all_ident_ids,
+            },
+            layout_interner: STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64()),
+        }
+    }
+
+    pub(crate) fn symbol(&mut self, name: &str) -> Symbol {
+        let ident_id = self
+            .interns
+            .all_ident_ids
+            .get_or_insert(self.home)
+            .add_str(name);
+        Symbol::new(self.home, ident_id)
+    }
+
+    pub(crate) fn proc(
+        &mut self,
+        name: &str,
+        args: &[(InLayout<'a>, Symbol)],
+        body: Stmt<'a>,
+        ret_layout: InLayout<'a>,
+    ) -> Proc<'a> {
+        Proc {
+            name: LambdaName::no_niche(self.symbol(name)),
+            args: self.arena.alloc_slice_copy(args),
+            body,
+            closure_data_layout: None,
+            ret_layout,
+            is_self_recursive: SelfRecursive::NotSelfRecursive,
+            host_exposed_layouts: HostExposedLayouts::NotHostExposed,
+        }
+    }
+
+    pub(crate) fn low_level(&self, op: LowLevel, arguments: &[Symbol]) -> Expr<'a> {
+        Expr::Call(Call {
+            call_type: CallType::LowLevel {
+                op,
+                update_mode: UpdateModeId::BACKEND_DUMMY,
+            },
+            arguments: self.arena.alloc_slice_copy(arguments),
+        })
+    }
+
+    /// A call of `proc` with `arguments`.
+    pub(crate) fn call(&self, proc: &Proc<'a>, arguments: &[Symbol]) -> Expr<'a> {
+        Expr::Call(Call {
+            call_type: CallType::ByName {
+                name: proc.name,
+                ret_layout: proc.ret_layout,
+                arg_layouts: self
+                    .arena
+                    .alloc_slice_fill_iter(proc.args.iter().map(|(layout, _)| *layout)),
+                specialization_id: CallSpecId::BACKEND_DUMMY,
+            },
+            arguments: self.arena.alloc_slice_copy(arguments),
+        })
+    }
+
+    /// `procs` keyed by their symbol and layout, like mono hands them to the backends.
+    pub(crate) fn procedures(
+        &self,
+        procs: &[Proc<'a>],
+    ) -> MutMap<(Symbol, ProcLayout<'a>), Proc<'a>> {
+        procs
+            .iter()
+            .map(|proc| {
+                let layout = ProcLayout {
+                    arguments: self
+                        .arena
+                        .alloc_slice_fill_iter(proc.args.iter().map(|(layout, _)| *layout)),
+                    result: proc.ret_layout,
+                    niche: proc.name.niche(),
+                };
+                ((proc.name.name(), layout), proc.clone())
+            })
+            .collect()
+    }
+
+    /// Builds `proc` for x86_64 without debug checks, and returns its disassembly.
+    /// Relocations are left unresolved, so calls and loads of data point at offset 0.
+    fn disassemble(&mut self, proc: Proc<'a>) -> String {
+        self.disassemble_at(proc, DevOptLevel::One)
+    }
+
+    fn disassemble_at(&mut self, proc: Proc<'a>, dev_opt_level: DevOptLevel) -> String {
+        let env = self.env_builder().dev_opt_level(dev_opt_level);
+        self.disassemble_with(proc, env)
+    }
+
+    pub(crate) fn env_builder(&self) -> EnvBuilder<'a> {
+        EnvBuilder::new(self.arena, self.home)
+            .lazy_literals(false)
+            .mode(AssemblyBackendMode::Test)
+            .opt_level(OptLevel::Optimize)
+            .verify_machine_code(true)
+    }
+
+    fn disassemble_with(&mut self, proc: Proc<'a>, env: EnvBuilder<'a>) -> String {
+        let env = env.build();
+        let mut backend =
+            new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+                &env,
+                TargetInfo::default_x86_64(),
+                &mut self.interns,
+                &mut self.layout_interner,
+            );
+        let (code, _, _) = backend.build_proc(proc, &mut LayoutIds::default(), false);
+        disassemble_x86_64(&code)
+    }
+
+    /// Builds `procs` into an x86_64 ELF object, like build_module does when the target is enabled.
+    /// Returns the bytes of the object and of its `.dwo` object, with what was built.
+    pub(crate) fn build_object(
+        &mut self,
+        procs: &[Proc<'a>],
+        env: EnvBuilder<'a>,
+    ) -> (
+        std::vec::Vec<u8>,
+        Option<std::vec::Vec<u8>>,
+        BuildReport<'a>,
+    ) {
+        let procedures = self.procedures(procs);
+        let env = env.build();
+        let backend =
+            new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+                &env,
+                TargetInfo::default_x86_64(),
+                &mut self.interns,
+                &mut self.layout_interner,
+            );
+        let output = Object::new(BinaryFormat::Elf, Architecture::X86_64, Endianness::Little);
+        let (object, dwo, report) = build_object_with_backend(procedures, backend, output)
+            .unwrap_or_else(|error| panic!("failed to build the object: {}", error));
+        let dwo = dwo.map(|dwo| dwo.write().unwrap());
+        (object.write().unwrap(), dwo, report)
+    }
+
+    /// The name of the symbol that `proc` is defined as.
+    pub(crate) fn symbol_name(&mut self, proc: &Proc<'a>, env: EnvBuilder<'a>) -> String {
+        let env = env.build();
+        let backend =
+            new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+                &env,
+                TargetInfo::default_x86_64(),
+                &mut self.interns,
+                &mut self.layout_interner,
+            );
+        backend.lambda_name_to_string(
+            proc.name,
+            proc.args.iter().map(|(layout, _)| *layout),
+            None,
+            proc.ret_layout,
+        )
+    }
+
+    fn disassemble_trampoline(&mut self, arg_order: &[usize]) -> String {
+        let env = self.env_builder().build();
+        let mut backend =
+            new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+                &env,
+                TargetInfo::default_x86_64(),
+                &mut self.interns,
+                &mut self.layout_interner,
+            );
+        let (code, _) = backend.build_trampoline(arg_order);
+        disassemble_x86_64(code)
+    }
+
+    fn disassemble_memeq_helper(&mut self) -> String {
+        let code = self.verified_memeq_helper::<_, _, X86_64Assembler, X86_64SystemV>(
+            TargetInfo::default_x86_64(),
+        );
+        disassemble_x86_64(&code)
+    }
+
+    /// Builds the memeq helper of a target, and checks that all of its jumps land on an instruction.
+    fn verified_memeq_helper<GeneralReg, FloatReg, ASM, CC>(
+        &mut self,
+        target_info: TargetInfo,
+    ) -> std::vec::Vec<u8>
+    where
+        GeneralReg: RegTrait,
+        FloatReg: RegTrait,
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        let env = self.env_builder().build();
+        let mut backend = new_backend_64bit::<GeneralReg, FloatReg, ASM, CC>(
+            &env,
+            target_info,
+            &mut self.interns,
+            &mut self.layout_interner,
+        );
+        let code = backend.build_memeq_helper();
+        assert_eq!(verify_code::<GeneralReg, FloatReg, ASM>(code, &[]), Ok(()));
+        code.to_vec()
+    }
+}
+
+fn disassemble_x86_64(code: &[u8]) -> String {
+    let cs = Capstone::new()
+        .x86()
+        .mode(arch::x86::ArchMode::Mode64)
+        .syntax(arch::x86::ArchSyntax::Intel)
+        .build()
+        .expect("Failed to create Capstone object");
+    let instructions = cs.disasm_all(code, 0).unwrap();
+    let mut text = String::new();
+    for inst in instructions.iter() {
+        let mnemonic = inst.mnemonic().unwrap_or("");
+        match inst.op_str() {
+            Some(operands) if !operands.is_empty() => text.push_str(&format!(
+                "{:04x}: {} {}\n",
+                inst.address(),
+                mnemonic,
+                operands
+            )),
+            _ => text.push_str(&format!("{:04x}: {}\n", inst.address(), mnemonic)),
+        }
+    }
+    text
+}
+
+/// Compares `actual` to the checked in snapshot of `name`.
+/// Only with `ROC_UPDATE_SNAPSHOTS` set it writes the snapshot instead, the tests leave the tree alone otherwise.
+fn assert_snapshot(name: &str, actual: &str) {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "src", "snapshots"]
+        .iter()
+        .collect::<PathBuf>()
+        .join(format!("{}.txt", name));
+
+    if std::env::var_os("ROC_UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!(
+            "no snapshot of {} at {}: {}, run with ROC_UPDATE_SNAPSHOTS=1 to write it",
+            name,
+            path.display(),
+            error
+        )
+    });
+    assert_eq!(
+        expected,
+        actual,
+        "the code of {} changed, run with ROC_UPDATE_SNAPSHOTS=1 to update {}",
+        name,
+        path.display()
+    );
+}
+
+#[test]
+fn num_add_i64() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let a = f.symbol("a");
+    let b = f.symbol("b");
+    let sum = f.symbol("sum");
+    let body = Stmt::Let(
+        sum,
+        f.low_level(LowLevel::NumAdd, &[a, b]),
+        Layout::I64,
+        f.arena.alloc(Stmt::Ret(sum)),
+    );
+    let proc = f.proc(
+        "add",
+        &[(Layout::I64, a), (Layout::I64, b)],
+        body,
+        Layout::I64,
+    );
+    assert_snapshot("num_add_i64", &f.disassemble(proc));
+}
+
+#[test]
+fn num_mul_f64() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let a = f.symbol("a");
+    let b = f.symbol("b");
+    let product = f.symbol("product");
+    let body = Stmt::Let(
+        product,
+        f.low_level(LowLevel::NumMul, &[a, b]),
+        Layout::F64,
+        f.arena.alloc(Stmt::Ret(product)),
+    );
+    let proc = f.proc(
+        "mul",
+        &[(Layout::F64, a), (Layout::F64, b)],
+        body,
+        Layout::F64,
+    );
+    assert_snapshot("num_mul_f64", &f.disassemble(proc));
+}
+
+#[test]
+fn float_literal() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let x = f.symbol("x");
+    let body = Stmt::Let(
+        x,
+        Expr::Literal(Literal::Float(1.5)),
+        Layout::F64,
+        f.arena.alloc(Stmt::Ret(x)),
+    );
+    let proc = f.proc("one_and_a_half", &[], body, Layout::F64);
+    assert_snapshot("float_literal", &f.disassemble(proc));
+}
+
+#[test]
+fn switch_on_bool() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let cond = f.symbol("cond");
+    let x = f.symbol("x");
+    let y = f.symbol("y");
+    let body = Stmt::Switch {
+        cond_symbol: cond,
+        cond_layout: Layout::BOOL,
+        branches: f.arena.alloc([(1, BranchInfo::None, Stmt::Ret(x))]),
+        default_branch: (BranchInfo::None, f.arena.alloc(Stmt::Ret(y))),
+        ret_layout: Layout::U64,
+    };
+    let proc = f.proc(
+        "choose",
+        &[(Layout::BOOL, cond), (Layout::U64, x), (Layout::U64, y)],
+        body,
+        Layout::U64,
+    );
+    assert_snapshot("switch_on_bool", &f.disassemble(proc));
+}
+
+#[test]
+fn struct_field() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let pair = f.symbol("pair");
+    let second = f.symbol("second");
+    let field_layouts: &[_] = f.arena.alloc([Layout::U64, Layout::U64]);
+    let pair_layout = f
+        .layout_interner
+        .insert_direct_no_semantic(LayoutRepr::struct_(field_layouts));
+    let body = Stmt::Let(
+        second,
+        Expr::StructAtIndex {
+            index: 1,
+            field_layouts,
+            structure: pair,
+        },
+        Layout::U64,
+        f.arena.alloc(Stmt::Ret(second)),
+    );
+    let proc = f.proc("get_second", &[(pair_layout, pair)], body, Layout::U64);
+    assert_snapshot("struct_field", &f.disassemble(proc));
+}
+
+/// `when cond is 0 -> x0 ... 3 -> x3 _ -> x4`, which is dense enough for a jump table.
+fn dense_switch<'a>(f: &mut Fixture<'a>) -> Proc<'a> {
+    let cond = f.symbol("cond");
+    let values: std::vec::Vec<Symbol> = (0..5).map(|i| f.symbol(&format!("x{}", i))).collect();
+    let branches = f
+        .arena
+        .alloc_slice_fill_iter((0..4).map(|i| (i as u64, BranchInfo::None, Stmt::Ret(values[i]))));
+    let body = Stmt::Switch {
+        cond_symbol: cond,
+        cond_layout: Layout::U64,
+        branches,
+        default_branch: (BranchInfo::None, f.arena.alloc(Stmt::Ret(values[4]))),
+        ret_layout: Layout::U64,
+    };
+    let mut args = vec![(Layout::U64, cond)];
+    args.extend(values.iter().map(|value| (Layout::U64, *value)));
+    f.proc("pick", &args, body, Layout::U64)
+}
+
+#[test]
+fn dense_switch_jump_table() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let proc = dense_switch(f);
+    let code = f.disassemble(proc);
+    assert!(code.contains("jmp r"), "no indirect jump in\n{}", code);
+    assert_snapshot("dense_switch_jump_table", &code);
+
+    // Level zero tests the values one after the other.
+    let proc = dense_switch(f);
+    let code = f.disassemble_at(proc, DevOptLevel::Zero);
+    assert!(!code.contains("jmp r"), "indirect jump in\n{}", code);
+}
+
+#[test]
+fn single_tag_union() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let a = f.symbol("a");
+    let b = f.symbol("b");
+    let pair = f.symbol("pair");
+    let id = f.symbol("id");
+    let second = f.symbol("second");
+    let union_layout = UnionLayout::NonRecursive(
+        f.arena
+            .alloc([f.arena.alloc([Layout::U64, Layout::U64]) as &[_]]),
+    );
+    let union_in_layout = f
+        .layout_interner
+        .insert_direct_no_semantic(LayoutRepr::Union(union_layout));
+    let ret = f.arena.alloc(Stmt::Ret(second));
+    let get_second = f.arena.alloc(Stmt::Let(
+        second,
+        Expr::UnionAtIndex {
+            structure: pair,
+            tag_id: 0,
+            union_layout,
+            index: 1,
+        },
+        Layout::U64,
+        ret,
+    ));
+    let get_id = f.arena.alloc(Stmt::Let(
+        id,
+        Expr::GetTagId {
+            structure: pair,
+            union_layout,
+        },
+        union_layout.tag_id_layout(),
+        get_second,
+    ));
+    let body = Stmt::Let(
+        pair,
+        Expr::Tag {
+            tag_layout: union_layout,
+            tag_id: 0,
+            arguments: f.arena.alloc([a, b]),
+            reuse: None,
+        },
+        union_in_layout,
+        get_id,
+    );
+    let proc = f.proc(
+        "second_of_pair",
+        &[(Layout::U64, a), (Layout::U64, b)],
+        body,
+        Layout::U64,
+    );
+    let code = f.disassemble(proc);
+    // The payload is stored like a struct, there is no byte for the tag id.
+    assert!(!code.contains("byte ptr"), "tag id stored in\n{}", code);
+    assert_snapshot("single_tag_union", &code);
+}
+
+/// `expect cond` followed by `x`.
+fn expect_proc<'a>(f: &mut Fixture<'a>) -> Proc<'a> {
+    let cond = f.symbol("cond");
+    let x = f.symbol("x");
+    let body = Stmt::Expect {
+        condition: cond,
+        region: Region::zero(),
+        lookups: &[],
+        variables: &[],
+        remainder: f.arena.alloc(Stmt::Ret(x)),
+    };
+    f.proc(
+        "checked",
+        &[(Layout::BOOL, cond), (Layout::U64, x)],
+        body,
+        Layout::U64,
+    )
+}
+
+#[test]
+fn expect_check() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+
+    // A failed expect crashes through roc_panic.
+    let proc = expect_proc(f);
+    let env = f.env_builder().expect_checks(true);
+    let code = f.disassemble_with(proc, env);
+    assert!(code.contains("call"), "no call to roc_panic in\n{}", code);
+    assert_snapshot("expect_check", &code);
+
+    let proc = expect_proc(f);
+    let env = f.env_builder().expect_checks(false);
+    let code = f.disassemble_with(proc, env);
+    assert!(!code.contains("call"), "expect checked in\n{}", code);
+}
+
+#[test]
+fn stack_check() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let x = f.symbol("x");
+
+    // Stack checks are only built when the host asks for them.
+    let proc = f.proc("leaf", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+    let code = f.disassemble_with(proc, f.env_builder().opt_level(OptLevel::Development));
+    assert!(!code.contains("call"), "stack checked in\n{}", code);
+
+    // Past the limit, the proc calls the stack overflow helper.
+    let proc = f.proc("leaf", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+    let env = f.env_builder().stack_check_size(Some(1 << 20));
+    let code = f.disassemble_with(proc, env);
+    assert!(code.contains("call"), "no stack check in\n{}", code);
+    assert_snapshot("stack_check", &code);
+}
+
+#[test]
+fn tail_call() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let x = f.symbol("x");
+    let y = f.symbol("y");
+    let callee = f.proc("callee", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+    let body = Stmt::Let(
+        y,
+        f.call(&callee, &[x]),
+        Layout::U64,
+        f.arena.alloc(Stmt::Ret(y)),
+    );
+    let proc = f.proc("caller", &[(Layout::U64, x)], body, Layout::U64);
+    let code = f.disassemble(proc);
+
+    // The frame is torn down before jumping to the callee, which returns to our caller.
+    assert!(!code.contains("call"), "no tail call in\n{}", code);
+    let lines: std::vec::Vec<_> = code.lines().collect();
+    let [.., epilogue, jump] = lines.as_slice() else {
+        panic!("no tail call in\n{}", code);
+    };
+    assert!(epilogue.ends_with("pop rbp"), "no epilogue in\n{}", code);
+    assert!(jump.contains("jmp"), "no jump to the callee in\n{}", code);
+    assert_snapshot("tail_call", &code);
+}
+
+#[test]
+fn trampoline() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+
+    // Without shuffling, a trampoline is just the jump.
+    let code = f.disassemble_trampoline(&[]);
+    assert_eq!(code.lines().count(), 1, "more than a jump in\n{}", code);
+
+    // Swapping the first two args needs a scratch register.
+    let code = f.disassemble_trampoline(&[1, 0, 2]);
+    assert!(code.contains("r11"), "no scratch register in\n{}", code);
+    assert_snapshot("trampoline_swap", &code);
+}
+
+#[test]
+fn memeq_helper() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let code = f.disassemble_memeq_helper();
+    for inst in ["pcmpeqb", "pmovmskb", "movups"] {
+        assert!(code.contains(inst), "no {} in\n{}", inst, code);
+    }
+    assert_snapshot("memeq_helper", &code);
+
+    // Windows x64 has only 4 general param regs, and AArch64 has other scratch regs.
+    f.verified_memeq_helper::<_, _, X86_64Assembler, X86_64WindowsFastcall>(
+        TargetInfo::default_x86_64(),
+    );
+    f.verified_memeq_helper::<_, _, AArch64Assembler, AArch64Call>(TargetInfo::default_aarch64());
+}
+
+#[test]
+fn layout_symbol_names() {
+    let arena = Bump::new();
+    let f = &mut Fixture::new(&arena);
+    let n = f.symbol("n");
+    let s = f.symbol("s");
+    let x = f.symbol("x");
+    let body = Stmt::Let(
+        x,
+        Expr::Literal(Literal::Float(1.5)),
+        Layout::F64,
+        f.arena.alloc(Stmt::Ret(x)),
+    );
+    let proc = f.proc(
+        "scale",
+        &[(Layout::I64, n), (Layout::STR, s)],
+        body,
+        Layout::F64,
+    );
+
+    let plain = f.symbol_name(&proc, f.env_builder());
+    assert!(!plain.contains('['), "layouts in {}", plain);
+
+    let annotated = f.symbol_name(&proc, f.env_builder().layout_symbol_names(true));
+    assert!(
+        annotated.starts_with("Test_scale_[I64,Str->Float64]_"),
+        "no layouts in {}",
+        annotated
+    );
+    // The hash is kept, so the names only differ in the layouts.
+    assert_eq!(
+        annotated.replace("[I64,Str->Float64]_", ""),
+        plain,
+        "different hash in {}",
+        annotated
+    );
+}
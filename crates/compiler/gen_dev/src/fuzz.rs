@@ -0,0 +1,337 @@
+//! Builds small mono IR procs from arbitrary bytes and runs them through `Backend::build_proc`,
+//! so that a fuzzer can look for inputs that make the backend panic or fail an encoder assertion.
+//! See `fuzz/` for the cargo-fuzz target.
+//!
+//! The procs are well typed, and only use layouts and operations that the x86_64 backend supports.
+//! Their code is checked with `Env::verify_machine_code` and `Env::check_storage_invariants`, but never run.
+
+use bumpalo::Bump;
+use roc_module::ident::ModuleName;
+use roc_module::low_level::LowLevel;
+use roc_module::symbol::{IdentIds, Interns, ModuleId, ModuleIds, Symbol};
+use roc_mono::ir::{
+    BranchInfo, Call, CallType, Expr, HostExposedLayouts, Literal, OptLevel, Proc, SelfRecursive,
+    Stmt, UpdateModeId,
+};
+use roc_mono::layout::{InLayout, LambdaName, Layout, LayoutIds, STLayoutInterner};
+use roc_target::TargetInfo;
+
+use crate::generic64::new_backend_64bit;
+use crate::generic64::x86_64::{X86_64Assembler, X86_64FloatReg, X86_64GeneralReg, X86_64SystemV};
+use crate::{AssemblyBackendMode, Backend, EnvBuilder};
+
+/// Why `fuzz_proc` did not build a proc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuzzError {
+    /// The input ran out before it described a whole proc.
+    OutOfBytes,
+}
+
+const INT_LAYOUTS: [InLayout<'static>; 4] = [Layout::U8, Layout::I32, Layout::I64, Layout::U64];
+const FLOAT_LAYOUTS: [InLayout<'static>; 2] = [Layout::F32, Layout::F64];
+const LAYOUTS: [InLayout<'static>; 7] = [
+    Layout::BOOL,
+    Layout::U8,
+    Layout::I32,
+    Layout::I64,
+    Layout::U64,
+    Layout::F32,
+    Layout::F64,
+];
+
+const INT_OPS: [LowLevel; 8] = [
+    LowLevel::NumAddWrap,
+    LowLevel::NumSubWrap,
+    LowLevel::NumMulWrap,
+    LowLevel::NumBitwiseAnd,
+    LowLevel::NumBitwiseOr,
+    LowLevel::NumBitwiseXor,
+    LowLevel::NumAddSaturated,
+    LowLevel::NumSubSaturated,
+];
+const FLOAT_OPS: [LowLevel; 4] = [
+    LowLevel::NumAdd,
+    LowLevel::NumSub,
+    LowLevel::NumMul,
+    LowLevel::NumDivFrac,
+];
+const COMPARISONS: [LowLevel; 6] = [
+    LowLevel::NumLt,
+    LowLevel::NumLte,
+    LowLevel::NumGt,
+    LowLevel::NumGte,
+    LowLevel::Eq,
+    LowLevel::NotEq,
+];
+const SHIFTS: [LowLevel; 3] = [
+    LowLevel::NumShiftLeftBy,
+    LowLevel::NumShiftRightBy,
+    LowLevel::NumShiftRightZfBy,
+];
+
+/// The bytes that decide what the proc looks like, every decision takes one or more of them.
+struct Input<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Input<'b> {
+    fn byte(&mut self) -> Result<u8, FuzzError> {
+        let (first, rest) = self.bytes.split_first().ok_or(FuzzError::OutOfBytes)?;
+        self.bytes = rest;
+        Ok(*first)
+    }
+
+    fn below(&mut self, n: usize) -> Result<usize, FuzzError> {
+        Ok(self.byte()? as usize % n)
+    }
+
+    fn choose<T: Copy>(&mut self, options: &[T]) -> Result<T, FuzzError> {
+        Ok(options[self.below(options.len())?])
+    }
+
+    fn u64(&mut self) -> Result<u64, FuzzError> {
+        let mut bytes = [0; 8];
+        for byte in bytes.iter_mut() {
+            *byte = self.byte()?;
+        }
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// The proc being generated, as the lets of its body so far and the values they define.
+struct ProcBuilder<'a, 'b> {
+    arena: &'a Bump,
+    input: Input<'b>,
+    home: ModuleId,
+    ident_ids: IdentIds,
+    lets: std::vec::Vec<(Symbol, Expr<'a>, InLayout<'a>)>,
+    values: std::vec::Vec<(Symbol, InLayout<'a>)>,
+}
+
+impl<'a, 'b> ProcBuilder<'a, 'b> {
+    fn symbol(&mut self, name: &str) -> Symbol {
+        let ident_id = self.ident_ids.add_str(name);
+        Symbol::new(self.home, ident_id)
+    }
+
+    fn define(&mut self, expr: Expr<'a>, layout: InLayout<'a>) -> Symbol {
+        let symbol = self.symbol(&format!("v{}", self.lets.len()));
+        self.lets.push((symbol, expr, layout));
+        self.values.push((symbol, layout));
+        symbol
+    }
+
+    fn literal(&mut self, layout: InLayout<'a>) -> Result<Literal<'a>, FuzzError> {
+        Ok(match layout {
+            Layout::BOOL => Literal::Bool(self.input.byte()? & 1 == 1),
+            Layout::F32 | Layout::F64 => Literal::Float(f64::from_bits(self.input.u64()?)),
+            // Truncated to the layout by the backend, like the literals mono builds.
+            _ => Literal::Int((self.input.u64()? as i64 as i128).to_ne_bytes()),
+        })
+    }
+
+    /// A value of `layout`, either one that is already defined or a new literal.
+    fn operand(&mut self, layout: InLayout<'a>) -> Result<Symbol, FuzzError> {
+        let defined: std::vec::Vec<Symbol> = self
+            .values
+            .iter()
+            .filter(|(_, value_layout)| *value_layout == layout)
+            .map(|(symbol, _)| *symbol)
+            .collect();
+        let choice = self.input.below(defined.len() + 1)?;
+        match defined.get(choice) {
+            Some(symbol) => Ok(*symbol),
+            None => {
+                let literal = self.literal(layout)?;
+                Ok(self.define(Expr::Literal(literal), layout))
+            }
+        }
+    }
+
+    fn low_level(&mut self, op: LowLevel, arguments: &[Symbol], layout: InLayout<'a>) -> Symbol {
+        let expr = Expr::Call(Call {
+            call_type: CallType::LowLevel {
+                op,
+                update_mode: UpdateModeId::BACKEND_DUMMY,
+            },
+            arguments: self.arena.alloc_slice_copy(arguments),
+        });
+        self.define(expr, layout)
+    }
+
+    /// Adds a let of a random operation on values from before it.
+    fn operation(&mut self) -> Result<(), FuzzError> {
+        match self.input.below(7)? {
+            0 => {
+                let layout = self.input.choose(&LAYOUTS)?;
+                let literal = self.literal(layout)?;
+                self.define(Expr::Literal(literal), layout);
+            }
+            1 => {
+                let layout = self.input.choose(&INT_LAYOUTS)?;
+                let op = self.input.choose(&INT_OPS)?;
+                let args = [self.operand(layout)?, self.operand(layout)?];
+                self.low_level(op, &args, layout);
+            }
+            2 => {
+                let layout = self.input.choose(&FLOAT_LAYOUTS)?;
+                let op = self.input.choose(&FLOAT_OPS)?;
+                let args = [self.operand(layout)?, self.operand(layout)?];
+                self.low_level(op, &args, layout);
+            }
+            3 => {
+                let layout = self.input.choose(&LAYOUTS[1..])?;
+                let op = self.input.choose(&COMPARISONS)?;
+                let args = [self.operand(layout)?, self.operand(layout)?];
+                self.low_level(op, &args, Layout::BOOL);
+            }
+            4 => {
+                let from = self.input.choose(&INT_LAYOUTS)?;
+                let arg = self.operand(from)?;
+                if self.input.byte()? & 1 == 0 {
+                    let to = self.input.choose(&INT_LAYOUTS)?;
+                    self.low_level(LowLevel::NumIntCast, &[arg], to);
+                } else {
+                    let to = self.input.choose(&FLOAT_LAYOUTS)?;
+                    self.low_level(LowLevel::NumToFloatCast, &[arg], to);
+                }
+            }
+            5 => {
+                let layout = self.input.choose(&INT_LAYOUTS)?;
+                let op = self.input.choose(&SHIFTS)?;
+                let args = [self.operand(layout)?, self.operand(Layout::U8)?];
+                self.low_level(op, &args, layout);
+            }
+            _ => {
+                let op = self
+                    .input
+                    .choose(&[LowLevel::And, LowLevel::Or, LowLevel::Not])?;
+                let arg = self.operand(Layout::BOOL)?;
+                if op == LowLevel::Not {
+                    self.low_level(op, &[arg], Layout::BOOL);
+                } else {
+                    let args = [arg, self.operand(Layout::BOOL)?];
+                    self.low_level(op, &args, Layout::BOOL);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Builds a proc described by `bytes` for x86_64, and returns its code.
+/// Every input either builds or runs out of bytes, anything else, like a panic, is a bug in the backend.
+pub fn fuzz_proc(bytes: &[u8]) -> Result<std::vec::Vec<u8>, FuzzError> {
+    let arena = Bump::new();
+    let mut module_ids = ModuleIds::default();
+    let home = module_ids.get_or_insert(&ModuleName::from("Fuzz"));
+    let mut builder = ProcBuilder {
+        arena: &arena,
+        input: Input { bytes },
+        home,
+        ident_ids: IdentIds::default(),
+        lets: std::vec::Vec::new(),
+        values: std::vec::Vec::new(),
+    };
+
+    let opt_level =
+        builder
+            .input
+            .choose(&[OptLevel::Development, OptLevel::Normal, OptLevel::Optimize])?;
+    let mut args = bumpalo::vec![in &arena];
+    for i in 0..builder.input.below(4)? {
+        let layout = builder.input.choose(&LAYOUTS)?;
+        let symbol = builder.symbol(&format!("a{}", i));
+        args.push((layout, symbol));
+        builder.values.push((symbol, layout));
+    }
+    for _ in 0..builder.input.below(16)? + 1 {
+        builder.operation()?;
+    }
+
+    // Either return a value, or switch on a bool and return one of two.
+    let ret_layout = builder.input.choose(&LAYOUTS)?;
+    let mut body = if builder.input.byte()? & 1 == 0 {
+        Stmt::Ret(builder.operand(ret_layout)?)
+    } else {
+        let cond_symbol = builder.operand(Layout::BOOL)?;
+        let then = builder.operand(ret_layout)?;
+        let otherwise = builder.operand(ret_layout)?;
+        Stmt::Switch {
+            cond_symbol,
+            cond_layout: Layout::BOOL,
+            branches: arena.alloc([(1, BranchInfo::None, Stmt::Ret(then))]),
+            default_branch: (BranchInfo::None, arena.alloc(Stmt::Ret(otherwise))),
+            ret_layout,
+        }
+    };
+    for (symbol, expr, layout) in builder.lets.drain(..).rev() {
+        body = Stmt::Let(symbol, expr, layout, arena.alloc(body));
+    }
+
+    let name = builder.symbol("fuzz");
+    let proc = Proc {
+        name: LambdaName::no_niche(name),
+        args: args.into_bump_slice(),
+        body,
+        closure_data_layout: None,
+        ret_layout,
+        is_self_recursive: SelfRecursive::NotSelfRecursive,
+        host_exposed_layouts: HostExposedLayouts::NotHostExposed,
+    };
+
+    let mut all_ident_ids = IdentIds::exposed_builtins(1);
+    all_ident_ids.insert(home, builder.ident_ids);
+    let mut interns = Interns {
+        module_ids,
+        all_ident_ids,
+    };
+    let mut layout_interner = STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64());
+    let env = EnvBuilder::new(&arena, home)
+        .mode(AssemblyBackendMode::Test)
+        .opt_level(opt_level)
+        .verify_machine_code(true)
+        .check_storage_invariants(true)
+        .build();
+    let mut backend =
+        new_backend_64bit::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>(
+            &env,
+            TargetInfo::default_x86_64(),
+            &mut interns,
+            &mut layout_interner,
+        );
+    let (code, _, _) = backend.build_proc(proc, &mut LayoutIds::default(), false);
+    Ok(code.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_runs_out_of_bytes() {
+        assert_eq!(fuzz_proc(&[]), Err(FuzzError::OutOfBytes));
+        assert_eq!(fuzz_proc(&[0, 0]), Err(FuzzError::OutOfBytes));
+    }
+
+    #[test]
+    fn add_of_two_args() {
+        let input = [
+            2, // OptLevel::Optimize
+            2, // two args
+            3, // I64
+            3, // I64
+            0, // one operation
+            1, // on ints
+            2, // I64
+            0, // NumAddWrap
+            0, // the first I64 value, a0
+            1, // the second I64 value, a1
+            3, // returning an I64
+            0, // without a switch
+            2, // the third I64 value, the sum
+        ];
+        let code = fuzz_proc(&input).unwrap();
+        assert!(!code.is_empty());
+    }
+}
@@ -1,13 +1,16 @@
 use crate::generic64::{storage::StorageManager, Assembler, CallConv, RegTrait};
-use crate::Relocation;
+use crate::{
+    pointer_layouts, single_register_floats, single_register_int_builtins,
+    single_register_integers, Relocation,
+};
 use bumpalo::collections::Vec;
 use packed_struct::prelude::*;
 use roc_builtins::bitcode::FloatWidth;
 use roc_error_macros::internal_error;
 use roc_module::symbol::Symbol;
-use roc_mono::layout::{InLayout, STLayoutInterner};
+use roc_mono::layout::{InLayout, LayoutInterner, LayoutRepr, STLayoutInterner, UnionLayout};
 
-use super::{CompareOperation, RegisterWidth};
+use super::{CompareOperation, DecodedInstruction, ImmOperation, IntCondition, RegisterWidth};
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
 #[allow(dead_code)]
@@ -258,7 +261,16 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
         AArch64GeneralReg::IP0,
         AArch64GeneralReg::IP1,
     ];
-    const FLOAT_PARAM_REGS: &'static [AArch64FloatReg] = &[];
+    const FLOAT_PARAM_REGS: &'static [AArch64FloatReg] = &[
+        AArch64FloatReg::V0,
+        AArch64FloatReg::V1,
+        AArch64FloatReg::V2,
+        AArch64FloatReg::V3,
+        AArch64FloatReg::V4,
+        AArch64FloatReg::V5,
+        AArch64FloatReg::V6,
+        AArch64FloatReg::V7,
+    ];
     const FLOAT_RETURN_REGS: &'static [AArch64FloatReg] = Self::FLOAT_PARAM_REGS;
     const FLOAT_DEFAULT_FREE_REGS: &'static [AArch64FloatReg] = &[];
 
@@ -281,8 +293,19 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
         )
     }
     #[inline(always)]
-    fn float_callee_saved(_reg: &AArch64FloatReg) -> bool {
-        todo!("AArch64 FloatRegs");
+    fn float_callee_saved(reg: &AArch64FloatReg) -> bool {
+        // Only the bottom 64 bits of these are callee saved, which is all a float reg holds for us.
+        matches!(
+            reg,
+            AArch64FloatReg::V8
+                | AArch64FloatReg::V9
+                | AArch64FloatReg::V10
+                | AArch64FloatReg::V11
+                | AArch64FloatReg::V12
+                | AArch64FloatReg::V13
+                | AArch64FloatReg::V14
+                | AArch64FloatReg::V15
+        )
     }
 
     #[inline(always)]
@@ -386,8 +409,8 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
 
     #[inline(always)]
     fn load_args<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
             'a,
             '_,
             AArch64GeneralReg,
@@ -395,17 +418,68 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
             AArch64Assembler,
             AArch64Call,
         >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _args: &'a [(InLayout<'a>, Symbol)],
-        _ret_layout: &InLayout<'a>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        args: &'a [(InLayout<'a>, Symbol)],
+        ret_layout: &InLayout<'a>,
     ) {
-        todo!("Loading args for AArch64");
+        // The frame pointer is the stack pointer of the caller, so stack args start right at it.
+        let mut arg_offset = Self::SHADOW_SPACE_SIZE as i32;
+
+        // Integers and floats are assigned from their own register file.
+        // An `I64, F64, I64` signature uses X0, V0 and X1.
+        let mut general_registers_used = 0;
+        let mut float_registers_used = 0;
+
+        for (layout, sym) in args.iter() {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() | pointer_layouts!() => {
+                    match Self::GENERAL_PARAM_REGS.get(general_registers_used) {
+                        Some(reg) => {
+                            storage_manager.general_reg_arg(sym, *reg);
+                            general_registers_used += 1;
+                        }
+                        None => {
+                            storage_manager.primitive_stack_arg(sym, arg_offset);
+                            arg_offset += 8;
+                        }
+                    }
+                }
+                single_register_floats!() => {
+                    match Self::FLOAT_PARAM_REGS.get(float_registers_used) {
+                        Some(reg) => {
+                            storage_manager.float_reg_arg(sym, *reg);
+                            float_registers_used += 1;
+                        }
+                        None => {
+                            storage_manager.primitive_stack_arg(sym, arg_offset);
+                            arg_offset += 8;
+                        }
+                    }
+                }
+                _ if layout_interner.stack_size(*layout) == 0 => {
+                    storage_manager.no_data(sym);
+                }
+                _ => {
+                    todo!(
+                        "Loading args with layout {:?} for AArch64",
+                        layout_interner.dbg(*layout)
+                    );
+                }
+            }
+        }
+
+        if Self::returns_via_arg_pointer(layout_interner, ret_layout) {
+            // XR is a scratch reg of the backend, so the pointer moves to a reg that it allocates.
+            // The param regs are taken by now, so this does not clobber an arg.
+            let reg = storage_manager.claim_general_reg(buf, &Symbol::RET_POINTER);
+            AArch64Assembler::mov_reg64_reg64(buf, reg, AArch64GeneralReg::XR);
+        }
     }
 
     #[inline(always)]
     fn store_args<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
             'a,
             '_,
             AArch64GeneralReg,
@@ -413,18 +487,110 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
             AArch64Assembler,
             AArch64Call,
         >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _dst: &Symbol,
-        _args: &[Symbol],
-        _arg_layouts: &[InLayout<'a>],
-        _ret_layout: &InLayout<'a>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        dst: &Symbol,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
     ) {
-        todo!("Storing args for AArch64");
+        let mut tmp_stack_offset = Self::SHADOW_SPACE_SIZE as i32;
+
+        // This must assign registers exactly like load_args.
+        let mut general_registers_used = 0;
+        let mut float_registers_used = 0;
+
+        for (sym, layout) in args.iter().zip(arg_layouts.iter()) {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() | pointer_layouts!() => {
+                    match Self::GENERAL_PARAM_REGS.get(general_registers_used) {
+                        Some(reg) => {
+                            storage_manager.load_to_specified_general_reg(buf, sym, *reg);
+                            general_registers_used += 1;
+                        }
+                        None => {
+                            // Copy to stack using the indirect result reg as buffer, it is never a param reg.
+                            let tmp = AArch64GeneralReg::XR;
+
+                            storage_manager.load_to_specified_general_reg(buf, sym, tmp);
+                            AArch64Assembler::mov_stack32_reg64(buf, tmp_stack_offset, tmp);
+
+                            tmp_stack_offset += 8;
+                        }
+                    }
+                }
+                single_register_floats!() => {
+                    match Self::FLOAT_PARAM_REGS.get(float_registers_used) {
+                        Some(reg) => {
+                            storage_manager.load_to_specified_float_reg(buf, sym, *reg);
+                            float_registers_used += 1;
+                        }
+                        None => {
+                            // Copy to stack using the first float return reg as buffer.
+                            let tmp = Self::FLOAT_RETURN_REGS[0];
+
+                            storage_manager.load_to_specified_float_reg(buf, sym, tmp);
+                            AArch64Assembler::mov_stack32_freg64(buf, tmp_stack_offset, tmp);
+
+                            tmp_stack_offset += 8;
+                        }
+                    }
+                }
+                _ if layout_interner.stack_size(*layout) == 0 => {}
+                _ => {
+                    todo!(
+                        "calling with arg type {:?} for AArch64",
+                        layout_interner.dbg(*layout)
+                    );
+                }
+            }
+        }
+
+        if Self::returns_via_arg_pointer(layout_interner, ret_layout) {
+            // The callee writes the result to the stack area of dst.
+            // This comes after the stack args, which go through XR.
+            let base_offset =
+                storage_manager.claim_stack_area(dst, layout_interner.stack_size(*ret_layout));
+            // The offset may be negative, which add with an immediate does not take.
+            AArch64Assembler::mov_reg64_imm64(buf, AArch64GeneralReg::XR, base_offset as i64);
+            AArch64Assembler::add_reg64_reg64_reg64(
+                buf,
+                AArch64GeneralReg::XR,
+                AArch64GeneralReg::XR,
+                Self::BASE_PTR_REG,
+            );
+        }
+        storage_manager.update_fn_call_stack_size(tmp_stack_offset as u32);
+    }
+
+    fn returns_via_arg_pointer<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        // Results over 16 bytes are written to memory that the caller passes a pointer to in XR.
+        layout_interner.stack_size(*ret_layout) > 16
+    }
+
+    fn args_in_registers<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        arg_layouts: &[InLayout<'a>],
+        _ret_layout: &InLayout<'a>,
+    ) -> bool {
+        // Unlike on x86_64, the pointer for a result returned via arg pointer is in XR, not in a param reg.
+        let mut general_i = 0;
+        let mut float_i = 0;
+        for layout in arg_layouts {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() => general_i += 1,
+                single_register_floats!() => float_i += 1,
+                _ => return false,
+            }
+        }
+        general_i <= Self::GENERAL_PARAM_REGS.len() && float_i <= Self::FLOAT_PARAM_REGS.len()
     }
 
     fn return_complex_symbol<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
             'a,
             '_,
             AArch64GeneralReg,
@@ -432,16 +598,30 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
             AArch64Assembler,
             AArch64Call,
         >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _sym: &Symbol,
-        _layout: &InLayout<'a>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        sym: &Symbol,
+        layout: &InLayout<'a>,
     ) {
-        todo!("Returning complex symbols for AArch64");
+        match layout_interner.stack_size(*layout) {
+            0 => {}
+            size if size <= 16 => {
+                let (base_offset, _) = storage_manager.stack_offset_and_size(sym);
+                AArch64Assembler::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset);
+                if size > 8 {
+                    AArch64Assembler::mov_reg64_base32(
+                        buf,
+                        Self::GENERAL_RETURN_REGS[1],
+                        base_offset + 8,
+                    );
+                }
+            }
+            _ => storage_manager.copy_symbol_to_arg_pointer(buf, sym, layout),
+        }
     }
 
     fn load_returned_complex_symbol<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
             'a,
             '_,
             AArch64GeneralReg,
@@ -449,11 +629,27 @@ impl CallConv<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler> for AArch64C
             AArch64Assembler,
             AArch64Call,
         >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _sym: &Symbol,
-        _layout: &InLayout<'a>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        sym: &Symbol,
+        layout: &InLayout<'a>,
     ) {
-        todo!("Loading returned complex symbols for AArch64");
+        match layout_interner.stack_size(*layout) {
+            0 => storage_manager.no_data(sym),
+            size if size <= 16 => {
+                let offset = storage_manager.claim_stack_area(sym, size);
+                AArch64Assembler::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
+                if size > 8 {
+                    AArch64Assembler::mov_base32_reg64(
+                        buf,
+                        offset + 8,
+                        Self::GENERAL_RETURN_REGS[1],
+                    );
+                }
+            }
+            _ => {
+                // store_args passed a pointer to the stack area of the symbol, the callee wrote the result there.
+            }
+        }
     }
 }
 
@@ -465,9 +661,9 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
     }
 
     #[inline(always)]
-    fn abs_freg64_freg64(
+    fn abs_freg64_freg64<'a>(
         buf: &mut Vec<'_, u8>,
-        _relocs: &mut Vec<'_, Relocation>,
+        _relocs: &mut Vec<'a, Relocation<'a>>,
         dst: AArch64FloatReg,
         src: AArch64FloatReg,
     ) {
@@ -481,9 +677,13 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         src: AArch64GeneralReg,
         imm32: i32,
     ) {
+        if imm32 == 0 && dst == src {
+            // Adding 0 to a register in place does nothing.
+            return;
+        }
         if imm32 < 0 {
             todo!("immediate addition with values less than 0");
-        } else if imm32 < 0xFFF {
+        } else if imm32 <= 0xFFF {
             add_reg64_reg64_imm12(buf, dst, src, imm32 as u16);
         } else {
             todo!("immediate additions with values greater than 12bits");
@@ -518,18 +718,85 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
     }
 
     #[inline(always)]
-    fn call(_buf: &mut Vec<'_, u8>, _relocs: &mut Vec<'_, Relocation>, _fn_name: String) {
-        todo!("calling functions literal for AArch64");
+    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, fn_name: String) {
+        // The linker fills in the branch offset (R_AARCH64_CALL26 or ARM64_RELOC_BRANCH26).
+        bl_imm26(buf, 0);
+        relocs.push(Relocation::LinkedFunction {
+            offset: buf.len() as u64 - 4,
+            name: fn_name,
+            addend: 0,
+        });
+    }
+
+    #[inline(always)]
+    fn call_slot(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, slot_name: String) {
+        // IP0 is the scratch register for veneers, it never holds an argument.
+        let reg = AArch64GeneralReg::IP0;
+        Self::data_pointer(buf, relocs, slot_name, 0, reg);
+        ldr_reg64_reg64_imm12(buf, reg, reg, 0);
+        blr_reg64(buf, reg);
+    }
+
+    #[inline(always)]
+    fn lea_reg64_base_index(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        base: AArch64GeneralReg,
+        index: AArch64GeneralReg,
+        shift: u8,
+        offset: i32,
+    ) {
+        debug_assert!(shift <= 3);
+        add_reg64_reg64_reg64_lsl(buf, dst, base, index, shift);
+        if offset != 0 {
+            Self::add_reg64_reg64_imm32(buf, dst, dst, offset);
+        }
     }
 
     #[inline(always)]
     fn function_pointer(
-        _buf: &mut Vec<'_, u8>,
-        _relocs: &mut Vec<'_, Relocation>,
-        _fn_name: String,
-        _dst: AArch64GeneralReg,
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        fn_name: String,
+        dst: AArch64GeneralReg,
+    ) {
+        // The linker fills in the page of the function and then the offset within that page.
+        adrp_reg64_imm21(buf, dst, 0);
+        add_reg64_reg64_imm12(buf, dst, dst, 0);
+        relocs.push(Relocation::LinkedFunctionPage {
+            offset: buf.len() as u64 - 8,
+            name: fn_name,
+        });
+    }
+
+    #[inline(always)]
+    fn data_pointer(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        data_name: String,
+        addend: i64,
+        dst: AArch64GeneralReg,
     ) {
-        todo!("calling functions literal for AArch64");
+        // Data symbols are defined in the object, so their page can be used directly like a function's.
+        Self::function_pointer(buf, relocs, data_name, dst);
+        match addend {
+            0 => {}
+            1..=0xFF_FFFF => {
+                let (high, low) = ((addend >> 12) as u16, (addend & 0xFFF) as u16);
+                if high != 0 {
+                    add_reg64_reg64_imm12_lsl12(buf, dst, dst, high);
+                }
+                if low != 0 {
+                    add_reg64_reg64_imm12(buf, dst, dst, low);
+                }
+            }
+            _ => {
+                // XR is never allocated, so it is free to hold the addend.
+                debug_assert_ne!(dst, AArch64GeneralReg::XR);
+                materialize_reg64_imm64(buf, AArch64GeneralReg::XR, addend as u64);
+                add_reg64_reg64_reg64(buf, dst, dst, AArch64GeneralReg::XR);
+            }
+        }
     }
 
     #[inline(always)]
@@ -555,6 +822,32 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         mul_reg64_reg64_reg64(buf, dst, src1, src2);
     }
 
+    fn umulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        umulh_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
+    fn smulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        smulh_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
     fn idiv_reg64_reg64_reg64<'a, ASM, CC>(
         buf: &mut Vec<'a, u8>,
         _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
@@ -656,6 +949,14 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         buf.len()
     }
 
+    #[inline(always)]
+    fn jmp_table_reg64(buf: &mut Vec<'_, u8>, tmp: AArch64GeneralReg, index: AArch64GeneralReg) {
+        // The entries are 4 byte branches, starting right after these 3 instructions.
+        adr_reg64_imm21(buf, tmp, 12);
+        add_reg64_reg64_reg64_lsl(buf, tmp, tmp, index, 2);
+        br_reg64(buf, tmp);
+    }
+
     #[inline(always)]
     fn tail_call(buf: &mut Vec<'_, u8>) -> u64 {
         Self::jmp_imm32(buf, 0);
@@ -693,9 +994,45 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
     }
 
     #[inline(always)]
-    fn mov_freg32_imm32(
+    fn jcc_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        _register_width: RegisterWidth,
+        condition: IntCondition,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+        offset: i32,
+    ) -> usize {
+        cmp_reg64_reg64(buf, src1, src2);
+
+        if (-(1 << 20)..(1 << 20)).contains(&offset) {
+            b_cond_imm19(buf, ConditionCode::from(condition), offset);
+        } else {
+            todo!("jump offsets over 20 bits for AArch64: {:#x}", offset);
+        }
+
+        buf.len()
+    }
+
+    #[inline(always)]
+    fn jump_sizes(_code: &[u8]) -> (usize, usize) {
+        // All branches are a single 4 byte instruction.
+        (4, 4)
+    }
+
+    #[inline(always)]
+    fn encode_jump(_buf: &mut Vec<'_, u8>, _jump: &[u8], _offset: i32, _short: bool) {
+        internal_error!("AArch64 branches have no short form to relax to")
+    }
+
+    #[inline(always)]
+    fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction> {
+        decode_instruction(code)
+    }
+
+    #[inline(always)]
+    fn mov_freg32_imm32<'a>(
         buf: &mut Vec<'_, u8>,
-        _relocs: &mut Vec<'_, Relocation>,
+        _relocs: &mut Vec<'a, Relocation<'a>>,
         dst: AArch64FloatReg,
         imm: f32,
     ) {
@@ -709,14 +1046,16 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
                 fmov_freg_imm8(buf, FloatWidth::F32, dst, imm8);
             }
             None => {
-                todo!("loading f32 literal over 8 bits for AArch64");
+                // XR is never allocated, so it is free to hold the bits on the way over.
+                materialize_reg64_imm64(buf, AArch64GeneralReg::XR, imm.to_bits() as u64);
+                fmov_freg32_reg32(buf, dst, AArch64GeneralReg::XR);
             }
         }
     }
     #[inline(always)]
-    fn mov_freg64_imm64(
+    fn mov_freg64_imm64<'a>(
         buf: &mut Vec<'_, u8>,
-        _relocs: &mut Vec<'_, Relocation>,
+        _relocs: &mut Vec<'a, Relocation<'a>>,
         dst: AArch64FloatReg,
         imm: f64,
     ) {
@@ -730,39 +1069,38 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
                 fmov_freg_imm8(buf, FloatWidth::F64, dst, imm8);
             }
             None => {
-                todo!("loading f64 literal over 8 bits for AArch64");
+                // XR is never allocated, so it is free to hold the bits on the way over.
+                materialize_reg64_imm64(buf, AArch64GeneralReg::XR, imm.to_bits());
+                fmov_freg64_reg64(buf, dst, AArch64GeneralReg::XR);
             }
         }
     }
     #[inline(always)]
     fn mov_reg64_imm64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm: i64) {
-        let mut remaining = imm as u64;
-        movz_reg64_imm16(buf, dst, remaining as u16, 0);
-        remaining >>= 16;
-        if remaining > 0 {
-            movk_reg64_imm16(buf, dst, remaining as u16, 1);
-        }
-        remaining >>= 16;
-        if remaining > 0 {
-            movk_reg64_imm16(buf, dst, remaining as u16, 2);
-        }
-        remaining >>= 16;
-        if remaining > 0 {
-            movk_reg64_imm16(buf, dst, remaining as u16, 3);
-        }
+        materialize_reg64_imm64(buf, dst, imm as u64);
     }
     #[inline(always)]
     fn mov_freg64_freg64(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
-        fmov_freg_freg(buf, FloatWidth::F64, dst, src);
+        if dst != src {
+            fmov_freg_freg(buf, FloatWidth::F64, dst, src);
+        }
     }
 
     #[inline(always)]
-    fn mov_reg32_freg32(_buf: &mut Vec<'_, u8>, _dst: AArch64GeneralReg, _src: AArch64FloatReg) {
-        unimplemented!();
+    fn mov_reg32_freg32(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64FloatReg) {
+        fmov_reg32_freg32(buf, dst, src);
+    }
+    #[inline(always)]
+    fn mov_reg64_freg64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64FloatReg) {
+        fmov_reg64_freg64(buf, dst, src);
+    }
+    #[inline(always)]
+    fn mov_freg32_reg32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64GeneralReg) {
+        fmov_freg32_reg32(buf, dst, src);
     }
     #[inline(always)]
-    fn mov_reg64_freg64(_buf: &mut Vec<'_, u8>, _dst: AArch64GeneralReg, _src: AArch64FloatReg) {
-        unimplemented!();
+    fn mov_freg64_reg64(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64GeneralReg) {
+        fmov_freg64_reg64(buf, dst, src);
     }
 
     #[inline(always)]
@@ -776,28 +1114,38 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
             RegisterWidth::W8 => todo!(),
             RegisterWidth::W16 => todo!(),
             RegisterWidth::W32 => todo!(),
-            RegisterWidth::W64 => mov_reg64_reg64(buf, dst, src),
+            RegisterWidth::W64 => {
+                if dst != src {
+                    mov_reg64_reg64(buf, dst, src)
+                }
+            }
         }
     }
 
     #[inline(always)]
     fn movsx_reg_reg(
-        _buf: &mut Vec<'_, u8>,
-        _input_width: RegisterWidth,
-        _dst: AArch64GeneralReg,
-        _src: AArch64GeneralReg,
+        buf: &mut Vec<'_, u8>,
+        input_width: RegisterWidth,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
     ) {
-        todo!("move with sign extension");
+        match input_width {
+            RegisterWidth::W64 => mov_reg64_reg64(buf, dst, src),
+            width => sbfm_reg64_reg64_imm6(buf, dst, src, 0, register_width_bits(width) - 1),
+        }
     }
 
     #[inline(always)]
     fn movzx_reg_reg(
-        _buf: &mut Vec<'_, u8>,
-        _input_width: RegisterWidth,
-        _dst: AArch64GeneralReg,
-        _src: AArch64GeneralReg,
+        buf: &mut Vec<'_, u8>,
+        input_width: RegisterWidth,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
     ) {
-        todo!("move with zero extension");
+        match input_width {
+            RegisterWidth::W64 => mov_reg64_reg64(buf, dst, src),
+            width => ubfm_reg64_reg64_imm6(buf, dst, src, 0, register_width_bits(width) - 1),
+        }
     }
 
     #[inline(always)]
@@ -805,6 +1153,16 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         todo!("loading floating point reg from base offset for AArch64");
     }
     #[inline(always)]
+    fn mov_freg128_base32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, offset: i32) {
+        if offset % 16 == 0 && (0..(0xFFF << 4)).contains(&offset) {
+            ldr_freg128_reg64_imm12(buf, dst, AArch64GeneralReg::FP, (offset >> 4) as u16);
+        } else if (-256..256).contains(&offset) {
+            ldur_freg128_reg64_imm9(buf, dst, AArch64GeneralReg::FP, offset as i16);
+        } else {
+            todo!("unaligned 128 bit base offsets over 255 for AArch64");
+        }
+    }
+    #[inline(always)]
     fn mov_reg64_base32(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, offset: i32) {
         if offset < 0 {
             todo!("negative base offsets for AArch64");
@@ -832,6 +1190,16 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         todo!("saving floating point reg to base offset for AArch64");
     }
     #[inline(always)]
+    fn mov_base32_freg128(buf: &mut Vec<'_, u8>, offset: i32, src: AArch64FloatReg) {
+        if offset % 16 == 0 && (0..(0xFFF << 4)).contains(&offset) {
+            str_freg128_reg64_imm12(buf, src, AArch64GeneralReg::FP, (offset >> 4) as u16);
+        } else if (-256..256).contains(&offset) {
+            stur_freg128_reg64_imm9(buf, src, AArch64GeneralReg::FP, offset as i16);
+        } else {
+            todo!("unaligned 128 bit base offsets over 255 for AArch64");
+        }
+    }
+    #[inline(always)]
     fn movesd_mem64_offset32_freg64(
         _buf: &mut Vec<'_, u8>,
         _ptr: AArch64GeneralReg,
@@ -909,12 +1277,18 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
     }
     #[inline(always)]
     fn mov_reg8_mem8_offset32(
-        _buf: &mut Vec<'_, u8>,
-        _dst: AArch64GeneralReg,
-        _src: AArch64GeneralReg,
-        _offset: i32,
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        offset: i32,
     ) {
-        todo!()
+        if offset < 0 {
+            todo!("negative mem offsets for AArch64");
+        } else if offset <= 0xFFF {
+            ldrb_reg32_reg64_imm12(buf, dst, src, offset as u16);
+        } else {
+            todo!("mem8 offsets over 4k for AArch64");
+        }
     }
 
     #[inline(always)]
@@ -934,6 +1308,30 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         }
     }
 
+    #[inline(always)]
+    fn atomic_add_mem64_offset32_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        offset: i32,
+        src: AArch64GeneralReg,
+    ) {
+        // LDADD only takes an address in a register.
+        let addr = if offset == 0 {
+            dst
+        } else {
+            // XR is never allocated, so it is free to hold the address.
+            let addr = AArch64GeneralReg::XR;
+            if (0..=0xFFF).contains(&offset) {
+                add_reg64_reg64_imm12(buf, addr, dst, offset as u16);
+            } else {
+                materialize_reg64_imm64(buf, addr, offset as i64 as u64);
+                add_reg64_reg64_reg64(buf, addr, addr, dst);
+            }
+            addr
+        };
+        ldadd_reg64_reg64_reg64(buf, src, src, addr);
+    }
+
     #[inline(always)]
     fn mov_mem32_offset32_reg32(
         _buf: &mut Vec<'_, u8>,
@@ -1048,9 +1446,13 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         src: AArch64GeneralReg,
         imm32: i32,
     ) {
+        if imm32 == 0 && dst == src {
+            // Subtracting 0 from a register in place does nothing.
+            return;
+        }
         if imm32 < 0 {
             todo!("immediate subtractions with values less than 0");
-        } else if imm32 < 0xFFF {
+        } else if imm32 <= 0xFFF {
             sub_reg64_reg64_imm12(buf, dst, src, imm32 as u16);
         } else {
             todo!("immediate subtractions with values greater than 12bits");
@@ -1112,6 +1514,20 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         cset_reg64_cond(buf, dst, ConditionCode::NE);
     }
 
+    #[inline(always)]
+    fn eq_freg128_freg128_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src1: AArch64FloatReg,
+        src2: AArch64FloatReg,
+    ) {
+        cmeq_freg128_freg128_freg128(buf, src1, src1, src2);
+        // The least of the bytes is 0xFF when they are all equal.
+        uminv_freg8_freg128(buf, src1, src1);
+        fmov_reg32_freg32(buf, dst, src1);
+        lsr_reg64_reg64_imm6(buf, dst, dst, 7);
+    }
+
     #[inline(always)]
     fn cmp_freg_freg_reg64(
         buf: &mut Vec<'_, u8>,
@@ -1167,11 +1583,73 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         todo!("set if overflow for AArch64");
     }
 
+    #[inline(always)]
+    fn adds_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+    ) {
+        adds_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
+    #[inline(always)]
+    fn subs_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+    ) {
+        subs_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
+    #[inline(always)]
+    fn cmov_if_overflow_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+    ) {
+        csel_reg64_reg64_reg64_cond(buf, dst, src, dst, ConditionCode::VS);
+    }
+
+    #[inline(always)]
+    fn cmov_if_carry_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+    ) {
+        csel_reg64_reg64_reg64_cond(buf, dst, src, dst, ConditionCode::CSHS);
+    }
+
+    #[inline(always)]
+    fn cmov_if_borrow_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+    ) {
+        // A subs clears the carry flag when it borrows.
+        csel_reg64_reg64_reg64_cond(buf, dst, src, dst, ConditionCode::CCLO);
+    }
+
     #[inline(always)]
     fn ret(buf: &mut Vec<'_, u8>) {
         ret_reg64(buf, AArch64GeneralReg::LR)
     }
 
+    #[inline(always)]
+    fn and_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm32: i32,
+    ) {
+        // Like on x86_64, the immediate is sign extended.
+        let imm = encode_logical_imm64(imm32 as i64 as u64).unwrap_or_else(|| {
+            internal_error!("{:#x} is not a logical immediate on AArch64", imm32)
+        });
+        and_reg64_reg64_imm(buf, dst, src, imm);
+    }
+
     fn and_reg64_reg64_reg64(
         buf: &mut Vec<'_, u8>,
         dst: AArch64GeneralReg,
@@ -1238,12 +1716,98 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         asr_reg64_reg64_reg64(buf, dst, src1, src2);
     }
 
-    fn sqrt_freg64_freg64(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
-        fsqrt_freg_freg(buf, FloatWidth::F64, dst, src);
-    }
-
-    fn sqrt_freg32_freg32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
-        fsqrt_freg_freg(buf, FloatWidth::F32, dst, src);
+    fn count_leading_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        _bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        clz_reg64_reg64(buf, dst, src);
+    }
+
+    fn count_trailing_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        _bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        // The trailing zeros are the leading zeros of the reversed bits.
+        rbit_reg64_reg64(buf, dst, src);
+        clz_reg64_reg64(buf, dst, dst);
+    }
+
+    fn count_one_bits_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        _bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        // There is only a vector popcount, which counts the bits of every byte. Those are summed up after.
+        storage_manager.with_tmp_float_reg(buf, |_, buf, tmp| {
+            fmov_freg64_reg64(buf, tmp, src);
+            cnt_freg_freg(buf, tmp, tmp);
+            addv_freg_freg(buf, tmp, tmp);
+            fmov_reg64_freg64(buf, dst, tmp);
+        })
+    }
+
+    fn shl_reg64_reg64_imm8(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm8: u8,
+    ) {
+        lsl_reg64_reg64_imm6(buf, dst, src, imm8);
+    }
+
+    fn shr_reg64_reg64_imm8(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm8: u8,
+    ) {
+        lsr_reg64_reg64_imm6(buf, dst, src, imm8);
+    }
+
+    fn sar_reg64_reg64_imm8(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm8: u8,
+    ) {
+        asr_reg64_reg64_imm6(buf, dst, src, imm8);
+    }
+
+    fn sqrt_freg64_freg64(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
+        fsqrt_freg_freg(buf, FloatWidth::F64, dst, src);
+    }
+
+    fn round_reg64_freg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        _storage_manager: &mut StorageManager<'a, '_, AArch64GeneralReg, AArch64FloatReg, ASM, CC>,
+        dst: AArch64GeneralReg,
+        src: AArch64FloatReg,
+    ) where
+        ASM: Assembler<AArch64GeneralReg, AArch64FloatReg>,
+        CC: CallConv<AArch64GeneralReg, AArch64FloatReg, ASM>,
+    {
+        fcvtas_reg64_freg(buf, FloatWidth::F64, dst, src);
+    }
+
+    fn sqrt_freg32_freg32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
+        fsqrt_freg_freg(buf, FloatWidth::F32, dst, src);
     }
 
     fn signed_compare_reg64(
@@ -1282,6 +1846,41 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
         cset_reg64_cond(buf, dst, cond);
     }
 
+    fn compare_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        _register_width: RegisterWidth,
+        condition: IntCondition,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm32: i32,
+    ) {
+        cmp_reg64_imm12(buf, src, imm32 as u16);
+        cset_reg64_cond(buf, dst, ConditionCode::from(condition));
+    }
+
+    fn select_reg64_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64GeneralReg,
+        src: AArch64GeneralReg,
+        imm32: i32,
+        src1: AArch64GeneralReg,
+        src2: AArch64GeneralReg,
+    ) {
+        cmp_reg64_imm12(buf, src, imm32 as u16);
+        csel_reg64_reg64_reg64_cond(buf, dst, src1, src2, ConditionCode::EQ);
+    }
+
+    fn supports_imm32_operand(operation: ImmOperation, imm: i64) -> bool {
+        match operation {
+            ImmOperation::Add | ImmOperation::Sub | ImmOperation::Compare => {
+                (0..=0xFFF).contains(&imm)
+            }
+            ImmOperation::And => {
+                i32::try_from(imm).is_ok() && encode_logical_imm64(imm as u64).is_some()
+            }
+        }
+    }
+
     fn mov_freg64_mem64_offset32(
         _buf: &mut Vec<'_, u8>,
         _dst: AArch64FloatReg,
@@ -1299,6 +1898,22 @@ impl Assembler<AArch64GeneralReg, AArch64FloatReg> for AArch64Assembler {
     ) {
         todo!()
     }
+
+    #[inline(always)]
+    fn mov_freg128_mem128_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: AArch64FloatReg,
+        src: AArch64GeneralReg,
+        offset: i32,
+    ) {
+        if offset >= 0 && offset % 16 == 0 && offset < (0xFFF << 4) {
+            ldr_freg128_reg64_imm12(buf, dst, src, (offset >> 4) as u16);
+        } else if (-256..256).contains(&offset) {
+            ldur_freg128_reg64_imm9(buf, dst, src, offset as i16);
+        } else {
+            todo!("unaligned mem128 offsets over 256 for AArch64");
+        }
+    }
 }
 
 impl AArch64Assembler {}
@@ -1498,6 +2113,23 @@ impl ArithmeticShifted {
     }
 }
 
+impl From<IntCondition> for ConditionCode {
+    fn from(condition: IntCondition) -> Self {
+        match condition {
+            IntCondition::Equal => ConditionCode::EQ,
+            IntCondition::NotEqual => ConditionCode::NE,
+            IntCondition::Signed(CompareOperation::LessThan) => ConditionCode::LT,
+            IntCondition::Signed(CompareOperation::LessThanOrEqual) => ConditionCode::LE,
+            IntCondition::Signed(CompareOperation::GreaterThan) => ConditionCode::GT,
+            IntCondition::Signed(CompareOperation::GreaterThanOrEqual) => ConditionCode::GE,
+            IntCondition::Unsigned(CompareOperation::LessThan) => ConditionCode::CCLO,
+            IntCondition::Unsigned(CompareOperation::LessThanOrEqual) => ConditionCode::LS,
+            IntCondition::Unsigned(CompareOperation::GreaterThan) => ConditionCode::HI,
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual) => ConditionCode::CSHS,
+        }
+    }
+}
+
 // ARM manual section C1.2.4
 #[derive(Copy, Clone, PartialEq)]
 #[allow(dead_code)]
@@ -1588,6 +2220,57 @@ impl std::fmt::Display for ConditionCode {
     }
 }
 
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct Bitfield {
+    sf: bool,
+    opc: Integer<u8, packed_bits::Bits<2>>,
+    fixed: Integer<u8, packed_bits::Bits<6>>, // = 0b100110,
+    n: bool,
+    immr: Integer<u8, packed_bits::Bits<6>>,
+    imms: Integer<u8, packed_bits::Bits<6>>,
+    reg_n: Integer<u8, packed_bits::Bits<5>>,
+    reg_d: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for Bitfield {}
+
+pub struct BitfieldParams {
+    opc: u8,
+    immr: u8,
+    imms: u8,
+    rn: AArch64GeneralReg,
+    rd: AArch64GeneralReg,
+}
+
+impl Bitfield {
+    #[inline(always)]
+    fn new(
+        BitfieldParams {
+            opc,
+            immr,
+            imms,
+            rn,
+            rd,
+        }: BitfieldParams,
+    ) -> Self {
+        debug_assert!(opc <= 0b11);
+        debug_assert!(immr <= 0b111111);
+        debug_assert!(imms <= 0b111111);
+
+        Self {
+            sf: true,
+            opc: opc.into(),
+            fixed: 0b100110.into(),
+            n: true,
+            immr: immr.into(),
+            imms: imms.into(),
+            reg_n: rn.id().into(),
+            reg_d: rd.id().into(),
+        }
+    }
+}
+
 #[derive(PackedStruct)]
 #[packed_struct(endian = "msb")]
 pub struct ConditionalBranchImmediate {
@@ -1679,6 +2362,47 @@ impl ConditionalSelect {
     }
 }
 
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct DataProcessingOneSource {
+    sf: bool,
+    fixed: bool,
+    s: bool,
+    fixed2: Integer<u8, packed_bits::Bits<8>>,
+    opcode2: Integer<u8, packed_bits::Bits<5>>,
+    opcode: Integer<u8, packed_bits::Bits<6>>,
+    reg_n: Integer<u8, packed_bits::Bits<5>>,
+    reg_d: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for DataProcessingOneSource {}
+
+pub struct DataProcessingOneSourceParams {
+    opcode: u8,
+    rn: AArch64GeneralReg,
+    rd: AArch64GeneralReg,
+}
+
+impl DataProcessingOneSource {
+    #[inline(always)]
+    fn new(
+        DataProcessingOneSourceParams { opcode, rn, rd }: DataProcessingOneSourceParams,
+    ) -> Self {
+        debug_assert!(opcode <= 0b111111);
+
+        Self {
+            sf: true,
+            fixed: true,
+            s: false,
+            fixed2: 0b11010110.into(),
+            opcode2: 0b00000.into(),
+            opcode: opcode.into(),
+            reg_n: rn.id().into(),
+            reg_d: rd.id().into(),
+        }
+    }
+}
+
 #[derive(PackedStruct)]
 #[packed_struct(endian = "msb")]
 pub struct DataProcessingTwoSource {
@@ -1851,6 +2575,63 @@ impl LogicalShiftedRegister {
     }
 }
 
+/// The `N:immr:imms` fields of a logical immediate, see `encode_logical_imm64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BitmaskImmediate {
+    n: bool,
+    immr: u8,
+    imms: u8,
+}
+
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct LogicalImmediate {
+    sf: bool,
+    opc: Integer<u8, packed_bits::Bits<2>>,
+    fixed: Integer<u8, packed_bits::Bits<6>>, // = 0b100100,
+    n: bool,
+    immr: Integer<u8, packed_bits::Bits<6>>,
+    imms: Integer<u8, packed_bits::Bits<6>>,
+    reg_n: Integer<u8, packed_bits::Bits<5>>,
+    reg_d: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for LogicalImmediate {}
+
+pub struct LogicalImmediateParams {
+    op: LogicalOp,
+    imm: BitmaskImmediate,
+    rn: AArch64GeneralReg,
+    rd: AArch64GeneralReg,
+}
+
+impl LogicalImmediate {
+    #[inline(always)]
+    fn new(LogicalImmediateParams { op, imm, rn, rd }: LogicalImmediateParams) -> Self {
+        debug_assert!(imm.immr <= 0b111111);
+        debug_assert!(imm.imms <= 0b111111);
+
+        let opc = match op {
+            LogicalOp::AND => 0b00,
+            LogicalOp::ORR => 0b01,
+            LogicalOp::EOR => 0b10,
+            LogicalOp::ANDS => 0b11,
+            _ => internal_error!("{:?} has no immediate form", op),
+        };
+
+        Self {
+            reg_d: rd.id().into(),
+            reg_n: rn.id().into(),
+            imms: imm.imms.into(),
+            immr: imm.immr.into(),
+            n: imm.n,
+            fixed: 0b100100.into(),
+            opc: opc.into(),
+            sf: true,
+        }
+    }
+}
+
 #[derive(PackedStruct)]
 pub struct UnconditionalBranchRegister {
     fixed: Integer<u8, packed_bits::Bits<7>>,
@@ -1923,6 +2704,83 @@ impl UnconditionalBranchImmediate {
     }
 }
 
+// Uses a page offset when op is true (ADRP)
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct PcRelativeAddressing {
+    op: bool, // false=ADR, true=ADRP
+    immlo: Integer<u8, packed_bits::Bits<2>>,
+    fixed: Integer<u8, packed_bits::Bits<5>>,
+    immhi: Integer<u32, packed_bits::Bits<19>>,
+    rd: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for PcRelativeAddressing {}
+
+pub struct PcRelativeAddressingParams {
+    op: bool,
+    imm21: u32,
+    rd: AArch64GeneralReg,
+}
+
+impl PcRelativeAddressing {
+    #[inline(always)]
+    fn new(PcRelativeAddressingParams { op, imm21, rd }: PcRelativeAddressingParams) -> Self {
+        debug_assert!(imm21 <= 0b1_1111_1111_1111_1111_1111);
+
+        Self {
+            rd: rd.id().into(),
+            immhi: (imm21 >> 2).into(),
+            fixed: 0b10000.into(),
+            immlo: ((imm21 & 0b11) as u8).into(),
+            op,
+        }
+    }
+}
+
+// Atomic memory operations need the large system extensions of ARMv8.1.
+// opc = 0b000 is an add, a and r add acquire and release semantics.
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct AtomicMemoryOperation {
+    size: Integer<u8, packed_bits::Bits<2>>,
+    fixed: Integer<u8, packed_bits::Bits<3>>, // = 0b111,
+    v: bool,
+    fixed2: Integer<u8, packed_bits::Bits<2>>, // = 0b00,
+    a: bool,
+    r: bool,
+    fixed3: bool, // = true,
+    rs: Integer<u8, packed_bits::Bits<5>>,
+    o3: bool,
+    opc: Integer<u8, packed_bits::Bits<3>>,
+    fixed4: Integer<u8, packed_bits::Bits<2>>, // = 0b00,
+    rn: Integer<u8, packed_bits::Bits<5>>,
+    rt: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for AtomicMemoryOperation {}
+
+impl AtomicMemoryOperation {
+    #[inline(always)]
+    fn new_add(rs: AArch64GeneralReg, rt: AArch64GeneralReg, rn: AArch64GeneralReg) -> Self {
+        Self {
+            size: 0b11.into(),
+            fixed: 0b111.into(),
+            v: false,
+            fixed2: 0b00.into(),
+            a: false,
+            r: false,
+            fixed3: true,
+            rs: rs.id().into(),
+            o3: false,
+            opc: 0b000.into(),
+            fixed4: 0b00.into(),
+            rn: rn.id().into(),
+            rt: rt.id().into(),
+        }
+    }
+}
+
 // Uses unsigned Offset
 // opc = 0b01 means load
 // opc = 0b00 means store
@@ -1985,11 +2843,70 @@ impl LoadStoreRegisterImmediate {
     }
 }
 
-#[derive(PackedStruct)]
+impl LoadStoreRegisterImmediate {
+    /// Loads or stores the 128 bit vector register rt.
+    #[inline(always)]
+    fn new_simd128(opc: u8, imm12: u16, rn: AArch64GeneralReg, rt: AArch64FloatReg) -> Self {
+        debug_assert!(imm12 <= 0xFFF);
+
+        Self {
+            rt: rt.id().into(),
+            rn: rn.id().into(),
+            imm12: imm12.into(),
+            opc: opc.into(),
+            fixed3: 0b01.into(),
+            fixed2: true,
+            fixed: 0b111.into(),
+            size: 0b00.into(),
+        }
+    }
+}
+
+// Uses an unscaled, signed offset.
+// With the vector register bit v set, size = 0b00 and opc = 0b11 means a 128 bit load
+// and opc = 0b10 means a 128 bit store.
+#[derive(PackedStruct, Debug)]
 #[packed_struct(endian = "msb")]
-pub struct AdvancedSimdModifiedImmediate {
-    fixed: bool,
-    q: bool,
+pub struct LoadStoreRegisterUnscaled {
+    size: Integer<u8, packed_bits::Bits<2>>,
+    fixed: Integer<u8, packed_bits::Bits<3>>, // = 0b111,
+    v: bool,
+    fixed2: Integer<u8, packed_bits::Bits<2>>, // = 0b00,
+    opc: Integer<u8, packed_bits::Bits<2>>,
+    fixed3: bool,
+    imm9: Integer<u16, packed_bits::Bits<9>>,
+    fixed4: Integer<u8, packed_bits::Bits<2>>, // = 0b00,
+    rn: Integer<u8, packed_bits::Bits<5>>,
+    rt: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for LoadStoreRegisterUnscaled {}
+
+impl LoadStoreRegisterUnscaled {
+    #[inline(always)]
+    fn new_simd128(opc: u8, imm9: i16, rn: AArch64GeneralReg, rt: AArch64FloatReg) -> Self {
+        debug_assert!((-256..256).contains(&imm9));
+
+        Self {
+            rt: rt.id().into(),
+            rn: rn.id().into(),
+            fixed4: 0b00.into(),
+            imm9: ((imm9 as u16) & 0x1FF).into(),
+            fixed3: false,
+            opc: opc.into(),
+            fixed2: 0b00.into(),
+            v: true,
+            fixed: 0b111.into(),
+            size: 0b00.into(),
+        }
+    }
+}
+
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct AdvancedSimdModifiedImmediate {
+    fixed: bool,
+    q: bool,
     op: bool,
     fixed2: Integer<u16, packed_bits::Bits<10>>,
     a: bool,
@@ -2032,6 +2949,156 @@ impl AdvancedSimdModifiedImmediate {
     }
 }
 
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct AdvancedSimdTwoRegisterMiscellaneous {
+    fixed: bool,
+    q: bool,
+    u: bool,
+    fixed2: Integer<u8, packed_bits::Bits<5>>,
+    size: Integer<u8, packed_bits::Bits<2>>,
+    fixed3: Integer<u8, packed_bits::Bits<5>>,
+    opcode: Integer<u8, packed_bits::Bits<5>>,
+    fixed4: Integer<u8, packed_bits::Bits<2>>,
+    rn: Integer<u8, packed_bits::Bits<5>>,
+    rd: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for AdvancedSimdTwoRegisterMiscellaneous {}
+
+impl AdvancedSimdTwoRegisterMiscellaneous {
+    /// An op on the 8 bytes of the lower half of the registers.
+    #[inline(always)]
+    fn new_8b(opcode: u8, rn: AArch64FloatReg, rd: AArch64FloatReg) -> Self {
+        debug_assert!(opcode <= 0b11111);
+
+        Self {
+            fixed: false,
+            q: false,
+            u: false,
+            fixed2: 0b01110.into(),
+            size: 0b00.into(),
+            fixed3: 0b10000.into(),
+            opcode: opcode.into(),
+            fixed4: 0b10.into(),
+            rn: rn.id().into(),
+            rd: rd.id().into(),
+        }
+    }
+}
+
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct AdvancedSimdAcrossLanes {
+    fixed: bool,
+    q: bool,
+    u: bool,
+    fixed2: Integer<u8, packed_bits::Bits<5>>,
+    size: Integer<u8, packed_bits::Bits<2>>,
+    fixed3: Integer<u8, packed_bits::Bits<5>>,
+    opcode: Integer<u8, packed_bits::Bits<5>>,
+    fixed4: Integer<u8, packed_bits::Bits<2>>,
+    rn: Integer<u8, packed_bits::Bits<5>>,
+    rd: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for AdvancedSimdAcrossLanes {}
+
+impl AdvancedSimdAcrossLanes {
+    /// An op across the 8 bytes of the lower half of rn.
+    #[inline(always)]
+    fn new_8b(opcode: u8, rn: AArch64FloatReg, rd: AArch64FloatReg) -> Self {
+        debug_assert!(opcode <= 0b11111);
+
+        Self {
+            fixed: false,
+            q: false,
+            u: false,
+            fixed2: 0b01110.into(),
+            size: 0b00.into(),
+            fixed3: 0b11000.into(),
+            opcode: opcode.into(),
+            fixed4: 0b10.into(),
+            rn: rn.id().into(),
+            rd: rd.id().into(),
+        }
+    }
+
+    /// An op across the 16 bytes of rn.
+    #[inline(always)]
+    fn new_16b(u: bool, opcode: u8, rn: AArch64FloatReg, rd: AArch64FloatReg) -> Self {
+        debug_assert!(opcode <= 0b11111);
+
+        Self {
+            fixed: false,
+            q: true,
+            u,
+            fixed2: 0b01110.into(),
+            size: 0b00.into(),
+            fixed3: 0b11000.into(),
+            opcode: opcode.into(),
+            fixed4: 0b10.into(),
+            rn: rn.id().into(),
+            rd: rd.id().into(),
+        }
+    }
+}
+
+#[derive(PackedStruct)]
+#[packed_struct(endian = "msb")]
+pub struct AdvancedSimdThreeSame {
+    fixed: bool,
+    q: bool,
+    u: bool,
+    fixed2: Integer<u8, packed_bits::Bits<5>>,
+    size: Integer<u8, packed_bits::Bits<2>>,
+    fixed3: bool,
+    rm: Integer<u8, packed_bits::Bits<5>>,
+    opcode: Integer<u8, packed_bits::Bits<5>>,
+    fixed4: bool,
+    rn: Integer<u8, packed_bits::Bits<5>>,
+    rd: Integer<u8, packed_bits::Bits<5>>,
+}
+
+impl Aarch64Bytes for AdvancedSimdThreeSame {}
+
+impl AdvancedSimdThreeSame {
+    /// An op on the 16 bytes of the registers.
+    #[inline(always)]
+    fn new_16b(
+        u: bool,
+        opcode: u8,
+        rm: AArch64FloatReg,
+        rn: AArch64FloatReg,
+        rd: AArch64FloatReg,
+    ) -> Self {
+        debug_assert!(opcode <= 0b11111);
+
+        Self {
+            fixed: false,
+            q: true,
+            u,
+            fixed2: 0b01110.into(),
+            size: 0b00.into(),
+            fixed3: true,
+            rm: rm.id().into(),
+            opcode: opcode.into(),
+            fixed4: true,
+            rn: rn.id().into(),
+            rd: rd.id().into(),
+        }
+    }
+}
+
+fn register_width_bits(width: RegisterWidth) -> u8 {
+    match width {
+        RegisterWidth::W8 => 8,
+        RegisterWidth::W16 => 16,
+        RegisterWidth::W32 => 32,
+        RegisterWidth::W64 => 64,
+    }
+}
+
 fn encode_float_width(width: FloatWidth) -> u8 {
     match width {
         FloatWidth::F32 => 0b00,
@@ -2324,6 +3391,26 @@ fn add_reg64_reg64_imm12(
     buf.extend(inst.bytes());
 }
 
+/// `ADD Xd, Xn, imm12, LSL #12` -> Add Xn and imm12 shifted left by 12 and place the result into Xd.
+#[inline(always)]
+fn add_reg64_reg64_imm12_lsl12(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm12: u16,
+) {
+    let inst = ArithmeticImmediate::new(ArithmeticImmediateParams {
+        op: false,
+        s: false,
+        rd: dst,
+        rn: src,
+        imm12,
+        sh: true,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `ADD Xd, Xm, Xn` -> Add Xm and Xn and place the result into Xd.
 #[inline(always)]
 fn add_reg64_reg64_reg64(
@@ -2345,6 +3432,83 @@ fn add_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `ADDS Xd, Xn, Xm` -> Add Xn and Xm and place the result into Xd. Set condition flags.
+#[inline(always)]
+fn adds_reg64_reg64_reg64(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src1: AArch64GeneralReg,
+    src2: AArch64GeneralReg,
+) {
+    let inst = ArithmeticShifted::new(ArithmeticShiftedParams {
+        op: false,
+        s: true,
+        shift: ShiftType::LSL,
+        imm6: 0,
+        rm: src2,
+        rn: src1,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `ADD Xd, Xn, Xm, LSL #imm6` -> Add Xn and Xm shifted left by imm6 and place the result into Xd.
+#[inline(always)]
+fn add_reg64_reg64_reg64_lsl(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src1: AArch64GeneralReg,
+    src2: AArch64GeneralReg,
+    imm6: u8,
+) {
+    let inst = ArithmeticShifted::new(ArithmeticShiftedParams {
+        op: false,
+        s: false,
+        shift: ShiftType::LSL,
+        imm6,
+        rm: src2,
+        rn: src1,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `ADR Xd, imm21` -> Place the address PC + imm21 into Xd.
+#[inline(always)]
+fn adr_reg64_imm21(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm21: i32) {
+    debug_assert!(
+        (-(1 << 20)..(1 << 20)).contains(&imm21),
+        "offset must fit in 21 bits"
+    );
+    let inst = PcRelativeAddressing::new(PcRelativeAddressingParams {
+        op: false,
+        imm21: (imm21 as u32) & 0x1F_FFFF,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `ADRP Xd, imm21` -> Place the address of the 4KB page at PC + (imm21 << 12) into Xd.
+/// This is almost always paired with an `ADD` of the offset within the page.
+#[inline(always)]
+fn adrp_reg64_imm21(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm21: i32) {
+    debug_assert!(
+        (-(1 << 20)..(1 << 20)).contains(&imm21),
+        "page offset must fit in 21 bits"
+    );
+    // Only the low 21 bits are encoded, the rest is sign extension.
+    let inst = PcRelativeAddressing::new(PcRelativeAddressingParams {
+        op: true,
+        imm21: (imm21 as u32) & 0x1F_FFFF,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `AND Xd, Xn, Xm` -> Bitwise AND Xn and Xm and place the result into Xd.
 #[inline(always)]
 fn and_reg64_reg64_reg64(
@@ -2383,6 +3547,26 @@ fn asr_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `ASR Xd, Xn, #imm6` -> Arithmetic shift right Xn by imm6 and place the result into Xd.
+/// This is an alias of `SBFM Xd, Xn, #imm6, #63`.
+#[inline(always)]
+fn asr_reg64_reg64_imm6(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm6: u8,
+) {
+    let inst = Bitfield::new(BitfieldParams {
+        opc: 0b00,
+        immr: imm6,
+        imms: 63,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `B.cond imm19` -> Jump to PC + imm19 if cond is met.
 #[inline(always)]
 fn b_cond_imm19(buf: &mut Vec<'_, u8>, cond: ConditionCode, imm19: i32) {
@@ -2412,6 +3596,63 @@ fn b_cond_imm19(buf: &mut Vec<'_, u8>, cond: ConditionCode, imm19: i32) {
 /// `B imm26` -> Jump to PC + imm26.
 #[inline(always)]
 fn b_imm26(buf: &mut Vec<'_, u8>, imm26: i32) {
+    let inst = UnconditionalBranchImmediate::new(UnconditionalBranchImmediateParams {
+        op: false,
+        imm26: encode_branch_imm26(imm26),
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `BL imm26` -> Jump to PC + imm26, storing the return address in the link register.
+#[inline(always)]
+fn bl_imm26(buf: &mut Vec<'_, u8>, imm26: i32) {
+    let inst = UnconditionalBranchImmediate::new(UnconditionalBranchImmediateParams {
+        op: true,
+        imm26: encode_branch_imm26(imm26),
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// Decodes the instruction that `code` starts with. All of them are 4 bytes, and relocations patch the whole instruction.
+/// Only the top level encoding group is checked, except for the branches within a proc.
+fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction> {
+    let inst = u32::from_le_bytes(code.get(..4)?.try_into().unwrap());
+    // The reserved, unallocated and SVE groups, this assembler never emits those.
+    if (inst >> 25) & 0b1100 == 0 {
+        return None;
+    }
+
+    // Sign extends the word offset in the `bits` bits at `shift` to a byte offset.
+    let offset =
+        |shift: u32, bits: u32| ((inst << (32 - shift - bits)) as i32 >> (32 - bits)) as i64 * 4;
+    let jump_target = if inst >> 26 == 0b000101 {
+        // B, but not BL.
+        Some(offset(0, 26))
+    } else if inst >> 24 == 0b0101_0100 && inst & 0b1_0000 == 0 {
+        // B.cond
+        Some(offset(5, 19))
+    } else if (inst >> 25) & 0b11_1111 == 0b01_1010 {
+        // CBZ and CBNZ
+        Some(offset(5, 19))
+    } else if (inst >> 25) & 0b11_1111 == 0b01_1011 {
+        // TBZ and TBNZ
+        Some(offset(5, 14))
+    } else {
+        None
+    };
+
+    Some(DecodedInstruction {
+        len: 4,
+        reloc_field: Some(0),
+        jump_target,
+    })
+}
+
+/// Converts a byte offset into the 26 bit word offset used by `B` and `BL`.
+#[inline(always)]
+fn encode_branch_imm26(imm26: i32) -> u32 {
     // Since instructions are 4 bytes, the branch instructions assume the last 2 bits are 0
     debug_assert!(imm26 & 0b11 == 0, "branch location must be 4-byte aligned");
     let shifted = imm26 >> 2;
@@ -2427,36 +3668,175 @@ fn b_imm26(buf: &mut Vec<'_, u8>, imm26: i32) {
         debug_assert!(left_removed | 0b1111_1110_0000_0000_0000_0000_0000_0000 == unsigned);
     }
 
-    let inst = UnconditionalBranchImmediate::new(UnconditionalBranchImmediateParams {
-        op: false,
-        imm26: left_removed,
-    });
-
-    buf.extend(inst.bytes());
+    left_removed
 }
 
-/// `CMP Xn, imm12` -> Compare Xn and imm12, setting condition flags.
+/// Encodes `imm` as the immediate of a 64 bit `AND`, `ORR` or `EOR`, if it is one.
+/// Those are the values that repeat an element of 2, 4, 8, 16, 32 or 64 bits,
+/// where the element is a rotated run of ones that is neither empty nor the whole element.
+/// This operation is the inverse of DecodeBitMasks in the ARM manual.
 #[inline(always)]
-fn cmp_reg64_imm12(buf: &mut Vec<'_, u8>, src: AArch64GeneralReg, imm12: u16) {
-    subs_reg64_reg64_imm12(buf, AArch64GeneralReg::ZRSP, src, imm12);
-}
+fn encode_logical_imm64(imm: u64) -> Option<BitmaskImmediate> {
+    if imm == 0 || imm == u64::MAX {
+        return None;
+    }
 
-/// `CMP Xn, Xm` -> Compare Xn and Xm, setting condition flags.
-#[inline(always)]
-fn cmp_reg64_reg64(buf: &mut Vec<'_, u8>, src1: AArch64GeneralReg, src2: AArch64GeneralReg) {
-    subs_reg64_reg64_reg64(buf, AArch64GeneralReg::ZRSP, src1, src2);
+    // Find the smallest element that the value repeats.
+    let mut size = 64;
+    while size > 2 {
+        let half = size / 2;
+        let mask = (1u64 << half) - 1;
+        if imm & mask != (imm >> half) & mask {
+            break;
+        }
+        size = half;
+    }
+    let mask = if size == 64 {
+        u64::MAX
+    } else {
+        (1u64 << size) - 1
+    };
+    let element = imm & mask;
+
+    // The instruction rotates the run of ones right by immr, so undo that rotation.
+    let ones = element.count_ones();
+    let run = (1u64 << ones) - 1;
+    let rotate_right = |value: u64, by: u32| {
+        if by == 0 {
+            value
+        } else {
+            ((value >> by) | (value << (size - by))) & mask
+        }
+    };
+    let rotation = (0..size).find(|by| rotate_right(element, *by) == run)?;
+
+    Some(BitmaskImmediate {
+        n: size == 64,
+        immr: ((size - rotation) % size) as u8,
+        // The high bits of imms give the element size, as the ones before its first 0.
+        imms: (((!(size - 1) << 1) & 0b111111) | (ones - 1)) as u8,
+    })
 }
 
-/// `CNEG Xd, Xn, cond` -> If cond is true, then Xd = -Xn, else Xd = Xn.
+/// Puts `imm` into `dst` with the shortest of these sequences, which take 1 to 4 instructions:
+/// - `MOVZ` for the first 16 bit chunk that is not 0, and a `MOVK` for each of the others.
+/// - `MOVN` for the first chunk that is not 0xFFFF, and a `MOVK` for each of the others.
+/// - `ORR Xd, XZR, #imm`, when `imm` is a logical immediate.
+/// - `ORR` of a logical immediate that only differs from `imm` in one chunk, and a `MOVK` for it.
 #[inline(always)]
-fn cneg_reg64_reg64_cond(
-    buf: &mut Vec<'_, u8>,
-    dst: AArch64GeneralReg,
-    src: AArch64GeneralReg,
-    cond: ConditionCode,
-) {
-    csneg_reg64_reg64_reg64_cond(buf, dst, src, src, cond.invert());
-}
+fn materialize_reg64_imm64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm: u64) {
+    let chunk = |value: u64, hw: u8| (value >> (16 * hw)) as u16;
+    let zero_chunks = (0..4).filter(|hw| chunk(imm, *hw) == 0).count();
+    let ones_chunks = (0..4).filter(|hw| chunk(imm, *hw) == 0xFFFF).count();
+
+    // One MOVZ or MOVN can't be beaten.
+    if zero_chunks < 3 && ones_chunks < 3 {
+        if let Some(bitmask) = encode_logical_imm64(imm) {
+            orr_reg64_reg64_imm(buf, dst, AArch64GeneralReg::ZRSP, bitmask);
+            return;
+        }
+    }
+
+    // Otherwise the wide sequences take 3 or 4 instructions, so try 2.
+    // The chunk that is off is usually one that breaks a repeating pattern, so fill it with its neighbours.
+    if zero_chunks < 2 && ones_chunks < 2 {
+        for hw in 0..4 {
+            for other in (0..4).filter(|other| *other != hw) {
+                let filled =
+                    (imm & !(0xFFFF << (16 * hw))) | ((chunk(imm, other) as u64) << (16 * hw));
+                if let Some(bitmask) = encode_logical_imm64(filled) {
+                    orr_reg64_reg64_imm(buf, dst, AArch64GeneralReg::ZRSP, bitmask);
+                    movk_reg64_imm16(buf, dst, chunk(imm, hw), hw);
+                    return;
+                }
+            }
+        }
+    }
+
+    // MOVN sets the chunks it does not write to 0xFFFF, MOVZ sets them to 0.
+    let inverted = ones_chunks > zero_chunks;
+    let filler = if inverted { 0xFFFF } else { 0 };
+    let mut first = true;
+    for hw in 0..4 {
+        let value = chunk(imm, hw);
+        if value == filler {
+            continue;
+        }
+        if !first {
+            movk_reg64_imm16(buf, dst, value, hw);
+        } else if inverted {
+            movn_reg64_imm16(buf, dst, !value, hw);
+        } else {
+            movz_reg64_imm16(buf, dst, value, hw);
+        }
+        first = false;
+    }
+    if first {
+        // All chunks are the filler: 0 or -1.
+        if inverted {
+            movn_reg64_imm16(buf, dst, 0, 0);
+        } else {
+            movz_reg64_imm16(buf, dst, 0, 0);
+        }
+    }
+}
+
+/// `CLZ Xd, Xn` -> Count the leading zero bits of Xn and place the result into Xd.
+#[inline(always)]
+fn clz_reg64_reg64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64GeneralReg) {
+    let inst = DataProcessingOneSource::new(DataProcessingOneSourceParams {
+        opcode: 0b000100,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `CMP Xn, imm12` -> Compare Xn and imm12, setting condition flags.
+#[inline(always)]
+fn cmp_reg64_imm12(buf: &mut Vec<'_, u8>, src: AArch64GeneralReg, imm12: u16) {
+    subs_reg64_reg64_imm12(buf, AArch64GeneralReg::ZRSP, src, imm12);
+}
+
+/// `CMP Xn, Xm` -> Compare Xn and Xm, setting condition flags.
+#[inline(always)]
+fn cmp_reg64_reg64(buf: &mut Vec<'_, u8>, src1: AArch64GeneralReg, src2: AArch64GeneralReg) {
+    subs_reg64_reg64_reg64(buf, AArch64GeneralReg::ZRSP, src1, src2);
+}
+
+/// `CNEG Xd, Xn, cond` -> If cond is true, then Xd = -Xn, else Xd = Xn.
+#[inline(always)]
+fn cneg_reg64_reg64_cond(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    cond: ConditionCode,
+) {
+    csneg_reg64_reg64_reg64_cond(buf, dst, src, src, cond.invert());
+}
+
+/// `CSEL Xd, Xn, Xm, cond` -> If cond is true, then Xd = Xn, else Xd = Xm.
+#[inline(always)]
+fn csel_reg64_reg64_reg64_cond(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src1: AArch64GeneralReg,
+    src2: AArch64GeneralReg,
+    cond: ConditionCode,
+) {
+    let inst = ConditionalSelect::new(ConditionalSelectParams {
+        op: false,
+        s: false,
+        cond,
+        op2: 0b00,
+        rm: src2,
+        rn: src1,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
 
 /// `CSET Xd, cond` -> If cond is true, then Xd = 1, else Xd = 0.
 #[inline(always)]
@@ -2534,6 +3914,20 @@ fn eor_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `LDR Qt, [Xn, #offset]` -> Load the 128 bits at Xn + Offset into Qt. ZRSP is SP.
+/// Note: imm12 is the offest divided by 16.
+#[inline(always)]
+fn ldr_freg128_reg64_imm12(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64FloatReg,
+    base: AArch64GeneralReg,
+    imm12: u16,
+) {
+    let inst = LoadStoreRegisterImmediate::new_simd128(0b11, imm12, base, dst);
+
+    buf.extend(inst.bytes());
+}
+
 /// `LDR Xt, [Xn, #offset]` -> Load Xn + Offset Xt. ZRSP is SP.
 /// Note: imm12 is the offest divided by 8.
 #[inline(always)]
@@ -2553,6 +3947,72 @@ fn ldr_reg64_reg64_imm12(
     buf.extend(inst.bytes());
 }
 
+/// `LDRB Wt, [Xn, #offset]` -> Load the byte at Xn + Offset into Wt, zeroing the rest of Xt. ZRSP is SP.
+#[inline(always)]
+fn ldrb_reg32_reg64_imm12(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    base: AArch64GeneralReg,
+    imm12: u16,
+) {
+    let inst = LoadStoreRegisterImmediate::new_load(LoadStoreRegisterImmediateParams {
+        size: 0b00,
+        imm12,
+        rn: base,
+        rt: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `LDADD Xs, Xt, [Xn]` -> Atomically add Xs to the value at Xn, loading the value from before the addition into Xt. ZRSP is SP for Xn.
+#[inline(always)]
+fn ldadd_reg64_reg64_reg64(
+    buf: &mut Vec<'_, u8>,
+    src: AArch64GeneralReg,
+    dst: AArch64GeneralReg,
+    base: AArch64GeneralReg,
+) {
+    let inst = AtomicMemoryOperation::new_add(src, dst, base);
+
+    buf.extend(inst.bytes());
+}
+
+/// `LDUR Qt, [Xn, #offset]` -> Load the 128 bits at Xn + Offset into Qt. ZRSP is SP.
+/// Note: the offset is not scaled, it must fit in a signed 9 bit immediate.
+#[inline(always)]
+fn ldur_freg128_reg64_imm9(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64FloatReg,
+    base: AArch64GeneralReg,
+    imm9: i16,
+) {
+    let inst = LoadStoreRegisterUnscaled::new_simd128(0b11, imm9, base, dst);
+
+    buf.extend(inst.bytes());
+}
+
+/// `LSL Xd, Xn, #imm6` -> Logical shift Xn left by imm6 and place the result into Xd.
+/// This is an alias of `UBFM Xd, Xn, #(-imm6 MOD 64), #(63 - imm6)`.
+#[inline(always)]
+fn lsl_reg64_reg64_imm6(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm6: u8,
+) {
+    debug_assert!(imm6 < 64);
+    let inst = Bitfield::new(BitfieldParams {
+        opc: 0b10,
+        immr: (64 - imm6) % 64,
+        imms: 63 - imm6,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `LSL Xd, Xn, Xm` -> Logical shift Xn left by Xm and place the result into Xd.
 #[inline(always)]
 fn lsl_reg64_reg64_reg64(
@@ -2571,6 +4031,26 @@ fn lsl_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `LSR Xd, Xn, #imm6` -> Logical shift Xn right by imm6 and place the result into Xd.
+/// This is an alias of `UBFM Xd, Xn, #imm6, #63`.
+#[inline(always)]
+fn lsr_reg64_reg64_imm6(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm6: u8,
+) {
+    let inst = Bitfield::new(BitfieldParams {
+        opc: 0b10,
+        immr: imm6,
+        imms: 63,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `LSR Xd, Xn, Xm` -> Logical shift Xn right by Xm and place the result into Xd.
 #[inline(always)]
 fn lsr_reg64_reg64_reg64(
@@ -2630,6 +4110,20 @@ fn movk_reg64_imm16(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm16: u16, h
     buf.extend(inst.bytes());
 }
 
+/// `MOVN Xd, imm16` -> Moves the inverse of an optionally shifted imm16 to Xd.
+#[inline(always)]
+fn movn_reg64_imm16(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm16: u16, hw: u8) {
+    let inst = MoveWideImmediate::new(MoveWideImmediateParams {
+        opc: 0b00,
+        rd: dst,
+        imm16,
+        hw,
+        sf: true,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `MOVZ Xd, imm16` -> Zeros Xd and moves an optionally shifted imm16 to Xd.
 #[inline(always)]
 fn movz_reg64_imm16(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, imm16: u16, hw: u8) {
@@ -2681,6 +4175,63 @@ fn orr_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `AND Xd, Xn, #imm` -> Bitwise AND Xn and a logical immediate and place the result into Xd.
+#[inline(always)]
+fn and_reg64_reg64_imm(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm: BitmaskImmediate,
+) {
+    let inst = LogicalImmediate::new(LogicalImmediateParams {
+        op: LogicalOp::AND,
+        imm,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `ORR Xd, Xn, #imm` -> Bitwise OR Xn and a logical immediate and place the result into Xd.
+#[inline(always)]
+fn orr_reg64_reg64_imm(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    imm: BitmaskImmediate,
+) {
+    let inst = LogicalImmediate::new(LogicalImmediateParams {
+        op: LogicalOp::ORR,
+        imm,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `SBFM Xd, Xn, #immr, #imms` -> Sign extend the bits imms to immr of Xn, rotated right by immr, into Xd.
+/// With an immr of 0 this sign extends the lowest imms + 1 bits.
+#[inline(always)]
+fn sbfm_reg64_reg64_imm6(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    immr: u8,
+    imms: u8,
+) {
+    let inst = Bitfield::new(BitfieldParams {
+        opc: 0b00,
+        immr,
+        imms,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `SDIV Xd, Xn, Xm` -> Divide Xn by Xm and place the result into Xd.
 /// Xn, Xm, and Xd are signed integers.
 #[inline(always)]
@@ -2700,6 +4251,40 @@ fn sdiv_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `SMULH Xd, Xn, Xm` -> Multiply Xn and Xm, and place the upper 64 bits of the 128 bit product into Xd.
+/// Xn, Xm, and Xd are signed integers.
+#[inline(always)]
+fn smulh_reg64_reg64_reg64(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src1: AArch64GeneralReg,
+    src2: AArch64GeneralReg,
+) {
+    let inst = DataProcessingThreeSource::new(DataProcessingThreeSourceParams {
+        op31: 0b010,
+        rm: src2,
+        ra: AArch64GeneralReg::ZRSP,
+        rn: src1,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
+/// `STR Qt, [Xn, #offset]` -> Store the 128 bits of Qt to Xn + Offset. ZRSP is SP.
+/// Note: imm12 is the offest divided by 16.
+#[inline(always)]
+fn str_freg128_reg64_imm12(
+    buf: &mut Vec<'_, u8>,
+    src: AArch64FloatReg,
+    base: AArch64GeneralReg,
+    imm12: u16,
+) {
+    let inst = LoadStoreRegisterImmediate::new_simd128(0b10, imm12, base, src);
+
+    buf.extend(inst.bytes());
+}
+
 /// `STR Xt, [Xn, #offset]` -> Store Xt to Xn + Offset. ZRSP is SP.
 /// Note: imm12 is the offest divided by 8.
 #[inline(always)]
@@ -2719,6 +4304,20 @@ fn str_reg64_reg64_imm12(
     buf.extend(inst.bytes());
 }
 
+/// `STUR Qt, [Xn, #offset]` -> Store the 128 bits of Qt to Xn + Offset. ZRSP is SP.
+/// Note: the offset is not scaled, it must fit in a signed 9 bit immediate.
+#[inline(always)]
+fn stur_freg128_reg64_imm9(
+    buf: &mut Vec<'_, u8>,
+    src: AArch64FloatReg,
+    base: AArch64GeneralReg,
+    imm9: i16,
+) {
+    let inst = LoadStoreRegisterUnscaled::new_simd128(0b10, imm9, base, src);
+
+    buf.extend(inst.bytes());
+}
+
 /// `SUB Xd, Xn, imm12` -> Subtract Xn and imm12 and place the result into Xd.
 #[inline(always)]
 fn sub_reg64_reg64_imm12(
@@ -2801,6 +4400,36 @@ fn subs_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `BR Xn` -> Jump to the address stored in Xn.
+#[inline(always)]
+fn br_reg64(buf: &mut Vec<'_, u8>, xn: AArch64GeneralReg) {
+    let inst =
+        UnconditionalBranchRegister::new(UnconditionalBranchRegisterParams { op: 0b00, rn: xn });
+
+    buf.extend(inst.bytes());
+}
+
+/// `BLR Xn` -> Call the address stored in Xn, storing the return address in the link register.
+#[inline(always)]
+fn blr_reg64(buf: &mut Vec<'_, u8>, xn: AArch64GeneralReg) {
+    let inst =
+        UnconditionalBranchRegister::new(UnconditionalBranchRegisterParams { op: 0b01, rn: xn });
+
+    buf.extend(inst.bytes());
+}
+
+/// `RBIT Xd, Xn` -> Reverse the bits of Xn and place the result into Xd.
+#[inline(always)]
+fn rbit_reg64_reg64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64GeneralReg) {
+    let inst = DataProcessingOneSource::new(DataProcessingOneSourceParams {
+        opcode: 0b000000,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `RET Xn` -> Return to the address stored in Xn.
 #[inline(always)]
 fn ret_reg64(buf: &mut Vec<'_, u8>, xn: AArch64GeneralReg) {
@@ -2810,6 +4439,27 @@ fn ret_reg64(buf: &mut Vec<'_, u8>, xn: AArch64GeneralReg) {
     buf.extend(inst.bytes());
 }
 
+/// `UBFM Xd, Xn, #immr, #imms` -> Zero extend the bits imms to immr of Xn, rotated right by immr, into Xd.
+/// With an immr of 0 this zero extends the lowest imms + 1 bits.
+#[inline(always)]
+fn ubfm_reg64_reg64_imm6(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src: AArch64GeneralReg,
+    immr: u8,
+    imms: u8,
+) {
+    let inst = Bitfield::new(BitfieldParams {
+        opc: 0b10,
+        immr,
+        imms,
+        rn: src,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 /// `UDIV Xd, Xn, Xm` -> Divide Xn by Xm and place the result into Xd.
 /// Xn, Xm, and Xd are unsigned integers.
 #[inline(always)]
@@ -2829,9 +4479,58 @@ fn udiv_reg64_reg64_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `UMULH Xd, Xn, Xm` -> Multiply Xn and Xm, and place the upper 64 bits of the 128 bit product into Xd.
+/// Xn, Xm, and Xd are unsigned integers.
+#[inline(always)]
+fn umulh_reg64_reg64_reg64(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64GeneralReg,
+    src1: AArch64GeneralReg,
+    src2: AArch64GeneralReg,
+) {
+    let inst = DataProcessingThreeSource::new(DataProcessingThreeSourceParams {
+        op31: 0b110,
+        rm: src2,
+        ra: AArch64GeneralReg::ZRSP,
+        rn: src1,
+        rd: dst,
+    });
+
+    buf.extend(inst.bytes());
+}
+
 // Floating point (and advanced SIMD) instructions
 // ARM manual section C7
 
+/// `ADDV Bd, Vn.8B` -> Add up the 8 lower bytes of Vn and place the sum into the lowest byte of Vd, zeroing the rest.
+#[inline(always)]
+fn addv_freg_freg(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
+    let inst = AdvancedSimdAcrossLanes::new_8b(0b11011, src, dst);
+
+    buf.extend(inst.bytes());
+}
+
+/// `CMEQ Vd.16B, Vn.16B, Vm.16B` -> Set the bytes of Vd to all ones where the bytes of Vn and Vm are equal and to zero elsewhere.
+#[inline(always)]
+fn cmeq_freg128_freg128_freg128(
+    buf: &mut Vec<'_, u8>,
+    dst: AArch64FloatReg,
+    src1: AArch64FloatReg,
+    src2: AArch64FloatReg,
+) {
+    let inst = AdvancedSimdThreeSame::new_16b(true, 0b10001, src2, src1, dst);
+
+    buf.extend(inst.bytes());
+}
+
+/// `CNT Vd.8B, Vn.8B` -> Count the set bits of each of the 8 lower bytes of Vn into the bytes of Vd.
+#[inline(always)]
+fn cnt_freg_freg(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
+    let inst = AdvancedSimdTwoRegisterMiscellaneous::new_8b(0b00101, src, dst);
+
+    buf.extend(inst.bytes());
+}
+
 /// `FABS Sd/Dd, Sn/Dn` -> Take the absolute value of Sn/Dn and place the result into Sd/Dd.
 #[inline(always)]
 fn fabs_freg_freg(
@@ -2918,6 +4617,31 @@ fn fcvt_freg64_freg32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64F
     buf.extend(inst.bytes());
 }
 
+/// `FCVTAS Xd, Sn/Dn` -> Convert Sn/Dn to a signed integer, rounding to nearest with ties away from zero.
+#[inline(always)]
+fn fcvtas_reg64_freg(
+    buf: &mut Vec<'_, u8>,
+    ftype: FloatWidth,
+    dst: AArch64GeneralReg,
+    src: AArch64FloatReg,
+) {
+    let inst = ConversionBetweenFloatingPointAndInteger {
+        sf: true,
+        fixed: false,
+        s: false,
+        fixed2: 0b11110.into(),
+        ptype: encode_float_width(ftype).into(),
+        fixed3: true,
+        rmode: 0b00.into(),
+        opcode: 0b100.into(),
+        fixed4: 0b000000.into(),
+        rn: src.id().into(),
+        rd: dst.id().into(),
+    };
+
+    buf.extend(inst.bytes());
+}
+
 /// `FDIV Sd/Dd, Sn/Dn, Sm/Dm` -> Divide Sn/Dn by Sm/Dm and place the result into Sd/Dd.
 #[inline(always)]
 fn fdiv_freg_freg_freg(
@@ -3052,6 +4776,82 @@ fn encode_f64_to_imm8(imm: f64) -> Option<u8> {
     Some((ret_sign | ret_exp_first | ret_exp_last | ret_frac) as u8)
 }
 
+/// `FMOV Dd, Xn` -> Move the bits of Xn into Dd.
+#[inline(always)]
+fn fmov_freg64_reg64(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64GeneralReg) {
+    let inst = ConversionBetweenFloatingPointAndInteger::new(
+        ConversionBetweenFloatingPointAndIntegerParams {
+            opcode: 0b111,
+            rmode: 0b00,
+            ptype: FloatWidth::F64,
+            rd: dst,
+            rn: src,
+        },
+    );
+
+    buf.extend(inst.bytes());
+}
+
+/// `FMOV Sd, Wn` -> Move the bits of Wn into Sd.
+#[inline(always)]
+fn fmov_freg32_reg32(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64GeneralReg) {
+    let inst = ConversionBetweenFloatingPointAndInteger {
+        sf: false,
+        fixed: false,
+        s: false,
+        fixed2: 0b11110.into(),
+        ptype: encode_float_width(FloatWidth::F32).into(),
+        fixed3: true,
+        rmode: 0b00.into(),
+        opcode: 0b111.into(),
+        fixed4: 0b000000.into(),
+        rn: src.id().into(),
+        rd: dst.id().into(),
+    };
+
+    buf.extend(inst.bytes());
+}
+
+/// `FMOV Wd, Sn` -> Move the bits of Sn into Wd.
+#[inline(always)]
+fn fmov_reg32_freg32(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64FloatReg) {
+    let inst = ConversionBetweenFloatingPointAndInteger {
+        sf: false,
+        fixed: false,
+        s: false,
+        fixed2: 0b11110.into(),
+        ptype: encode_float_width(FloatWidth::F32).into(),
+        fixed3: true,
+        rmode: 0b00.into(),
+        opcode: 0b110.into(),
+        fixed4: 0b000000.into(),
+        rn: src.id().into(),
+        rd: dst.id().into(),
+    };
+
+    buf.extend(inst.bytes());
+}
+
+/// `FMOV Xd, Dn` -> Move the bits of Dn into Xd.
+#[inline(always)]
+fn fmov_reg64_freg64(buf: &mut Vec<'_, u8>, dst: AArch64GeneralReg, src: AArch64FloatReg) {
+    let inst = ConversionBetweenFloatingPointAndInteger {
+        sf: true,
+        fixed: false,
+        s: false,
+        fixed2: 0b11110.into(),
+        ptype: encode_float_width(FloatWidth::F64).into(),
+        fixed3: true,
+        rmode: 0b00.into(),
+        opcode: 0b110.into(),
+        fixed4: 0b000000.into(),
+        rn: src.id().into(),
+        rd: dst.id().into(),
+    };
+
+    buf.extend(inst.bytes());
+}
+
 /// `FMOV Sd/Dd, imm8` -> Move imm8 to a float register.
 /// imm8 is a float encoded using encode_f32_to_imm8 or encode_f64_to_imm8.
 #[inline(always)]
@@ -3135,6 +4935,14 @@ fn scvtf_freg_reg64(
     buf.extend(inst.bytes());
 }
 
+/// `UMINV Bd, Vn.16B` -> Place the least of the 16 bytes of Vn into the lowest byte of Vd, zeroing the rest.
+#[inline(always)]
+fn uminv_freg8_freg128(buf: &mut Vec<'_, u8>, dst: AArch64FloatReg, src: AArch64FloatReg) {
+    let inst = AdvancedSimdAcrossLanes::new_16b(true, 0b11010, src, dst);
+
+    buf.extend(inst.bytes());
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -3302,6 +5110,65 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_adds_reg64_reg64_reg64() {
+        disassembler_test!(
+            adds_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| {
+                if reg1 == AArch64GeneralReg::ZRSP {
+                    // When the first register is ZR, it gets disassembled as cmn,
+                    // which is an alias for adds.
+                    format!(
+                        "cmn {}, {}",
+                        reg2.capstone_string(UsesZR),
+                        reg3.capstone_string(UsesZR)
+                    )
+                } else {
+                    format!(
+                        "adds {}, {}, {}",
+                        reg1.capstone_string(UsesZR),
+                        reg2.capstone_string(UsesZR),
+                        reg3.capstone_string(UsesZR)
+                    )
+                }
+            },
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_cmov_if_borrow_reg64_reg64() {
+        disassembler_test!(
+            AArch64Assembler::cmov_if_borrow_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "csel {}, {}, {}, lo",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg1.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_add_reg64_reg64_reg64_lsl() {
+        disassembler_test!(
+            |buf, reg1, reg2, reg3| add_reg64_reg64_reg64_lsl(buf, reg1, reg2, reg3, 3),
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "add {}, {}, {}, lsl #3",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
     #[test]
     fn test_add_reg64_reg64_imm12() {
         disassembler_test!(
@@ -3318,6 +5185,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_add_reg64_reg64_imm12_lsl12() {
+        disassembler_test!(
+            add_reg64_reg64_imm12_lsl12,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
+                "add {}, {}, #0x{:x}, lsl #12",
+                reg1.capstone_string(UsesSP),
+                reg2.capstone_string(UsesSP),
+                imm
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            [0x123]
+        );
+    }
+
+    #[test]
+    fn test_adr_reg64_imm21() {
+        disassembler_test!(
+            adr_reg64_imm21,
+            |reg1: AArch64GeneralReg, imm: i32| format!(
+                "adr {}, #0x{:x}",
+                reg1.capstone_string(UsesZR),
+                imm
+            ),
+            ALL_GENERAL_REGS,
+            [0x1, 0xc, 0x1234, (1 << 20) - 1]
+        );
+    }
+
+    #[test]
+    fn test_adrp_reg64_imm21() {
+        disassembler_test!(
+            adrp_reg64_imm21,
+            |reg1: AArch64GeneralReg, imm: i32| format!(
+                "adrp {}, #0x{:x}",
+                reg1.capstone_string(UsesZR),
+                (imm as i64) << 12
+            ),
+            ALL_GENERAL_REGS,
+            [0x1, 0x1234, (1 << 20) - 1]
+        );
+    }
+
     #[test]
     fn test_and_reg64_reg64_reg64() {
         disassembler_test!(
@@ -3369,6 +5280,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_bl_imm26() {
+        disassembler_test!(
+            bl_imm26,
+            |imm| format!("bl #0x{:x}", imm as i64),
+            [0x120, -0x120, (1 << 27) - 4, -(1 << 27)]
+        );
+    }
+
+    #[test]
+    fn test_br_reg64() {
+        disassembler_test!(
+            br_reg64,
+            |reg1: AArch64GeneralReg| format!("br {}", reg1.capstone_string(UsesZR)),
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_blr_reg64() {
+        disassembler_test!(
+            blr_reg64,
+            |reg1: AArch64GeneralReg| format!("blr {}", reg1.capstone_string(UsesZR)),
+            ALL_GENERAL_REGS
+        );
+    }
+
     #[test]
     fn test_cmp_reg64_imm12() {
         disassembler_test!(
@@ -3425,6 +5363,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_csel_reg64_reg64_reg64_cond() {
+        disassembler_test!(
+            csel_reg64_reg64_reg64_cond,
+            |reg1: AArch64GeneralReg,
+             reg2: AArch64GeneralReg,
+             reg3: AArch64GeneralReg,
+             cond: ConditionCode| format!(
+                "csel {}, {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR),
+                cond
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            [ConditionCode::EQ, ConditionCode::LT]
+        );
+    }
+
     #[test]
     fn test_cset() {
         disassembler_test!(
@@ -3519,66 +5478,263 @@ mod tests {
     }
 
     #[test]
-    fn test_eor_reg64_reg64_reg64() {
+    fn test_eor_reg64_reg64_reg64() {
+        disassembler_test!(
+            eor_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "eor {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_ldr_freg128_reg64_imm12() {
+        disassembler_test!(
+            ldr_freg128_reg64_imm12,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg, imm| format!(
+                "ldr q{}, [{}, #0x{:x}]",
+                reg1.id(),
+                reg2.capstone_string(UsesSP),
+                imm << 4
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS,
+            [0x123]
+        );
+    }
+
+    #[test]
+    fn test_ldur_freg128_reg64_imm9() {
+        disassembler_test!(
+            ldur_freg128_reg64_imm9,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg, imm| format!(
+                "ldur q{}, [{}, #0x{:x}]",
+                reg1.id(),
+                reg2.capstone_string(UsesSP),
+                imm
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS,
+            [0x78i16]
+        );
+    }
+
+    #[test]
+    fn test_ldrb_reg32_reg64_imm12() {
+        disassembler_test!(
+            ldrb_reg32_reg64_imm12,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
+                "ldrb w{}, [{}, #0x{:x}]",
+                &reg1.capstone_string(UsesZR)[1..],
+                reg2.capstone_string(UsesSP),
+                imm
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            [0x123]
+        );
+    }
+
+    #[test]
+    fn test_atomic_add_mem64_offset32_reg64() {
+        use AArch64GeneralReg::*;
+
+        disassembler_test!(
+            |buf| AArch64Assembler::atomic_add_mem64_offset32_reg64(buf, X1, 0, X2),
+            || "ldadd x2, x2, [x1]"
+        );
+        disassembler_test!(
+            |buf| AArch64Assembler::atomic_add_mem64_offset32_reg64(buf, X1, 8, X2),
+            || "add x8, x1, #8\nldadd x2, x2, [x8]"
+        );
+        disassembler_test!(
+            |buf| AArch64Assembler::atomic_add_mem64_offset32_reg64(buf, X1, -8, X2),
+            || "mov x8, #-8\nadd x8, x8, x1\nldadd x2, x2, [x8]"
+        );
+    }
+
+    #[test]
+    fn test_ldadd_reg64_reg64_reg64() {
+        disassembler_test!(
+            ldadd_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| {
+                // An ldadd that discards the old value is an stadd.
+                if reg2 == AArch64GeneralReg::ZRSP {
+                    format!(
+                        "stadd {}, [{}]",
+                        reg1.capstone_string(UsesZR),
+                        reg3.capstone_string(UsesSP)
+                    )
+                } else {
+                    format!(
+                        "ldadd {}, {}, [{}]",
+                        reg1.capstone_string(UsesZR),
+                        reg2.capstone_string(UsesZR),
+                        reg3.capstone_string(UsesSP)
+                    )
+                }
+            },
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_ldr_reg64_reg64_imm12() {
+        disassembler_test!(
+            ldr_reg64_reg64_imm12,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
+                "ldr {}, [{}, #0x{:x}]",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesSP),
+                imm << 3
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            [0x123]
+        );
+    }
+
+    #[test]
+    fn test_lsl_reg64_reg64_reg64() {
+        disassembler_test!(
+            lsl_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "lsl {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_lsr_reg64_reg64_reg64() {
+        disassembler_test!(
+            lsr_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "lsr {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_clz_reg64_reg64() {
+        disassembler_test!(
+            clz_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "clz {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_rbit_reg64_reg64() {
+        disassembler_test!(
+            rbit_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "rbit {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_ubfm_reg64_reg64_imm6() {
+        disassembler_test!(
+            |buf: &mut Vec<'_, u8>, reg1, reg2| ubfm_reg64_reg64_imm6(buf, reg1, reg2, 0, 15),
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "ubfx {}, {}, #0, #0x10",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_sbfm_reg64_reg64_imm6() {
         disassembler_test!(
-            eor_reg64_reg64_reg64,
-            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
-                "eor {}, {}, {}",
+            |buf: &mut Vec<'_, u8>, reg1, reg2| sbfm_reg64_reg64_imm6(buf, reg1, reg2, 0, 31),
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "sxtw {}, w{}",
                 reg1.capstone_string(UsesZR),
-                reg2.capstone_string(UsesZR),
-                reg3.capstone_string(UsesZR)
+                &reg2.capstone_string(UsesZR)[1..]
             ),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS,
             ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_ldr_reg64_reg64_imm12() {
+    fn test_lsl_reg64_reg64_imm6() {
         disassembler_test!(
-            ldr_reg64_reg64_imm12,
+            lsl_reg64_reg64_imm6,
             |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
-                "ldr {}, [{}, #0x{:x}]",
+                "lsl {}, {}, #0x{:x}",
                 reg1.capstone_string(UsesZR),
-                reg2.capstone_string(UsesSP),
-                imm << 3
+                reg2.capstone_string(UsesZR),
+                imm
             ),
             ALL_GENERAL_REGS,
             ALL_GENERAL_REGS,
-            [0x123]
+            [0x0Fu8, 0x3F]
         );
     }
 
     #[test]
-    fn test_lsl_reg64_reg64_reg64() {
+    fn test_lsr_reg64_reg64_imm6() {
         disassembler_test!(
-            lsl_reg64_reg64_reg64,
-            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
-                "lsl {}, {}, {}",
+            lsr_reg64_reg64_imm6,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
+                "lsr {}, {}, #0x{:x}",
                 reg1.capstone_string(UsesZR),
                 reg2.capstone_string(UsesZR),
-                reg3.capstone_string(UsesZR)
+                imm
             ),
             ALL_GENERAL_REGS,
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [0x0Fu8, 0x3F]
         );
     }
 
     #[test]
-    fn test_lsr_reg64_reg64_reg64() {
+    fn test_asr_reg64_reg64_imm6() {
         disassembler_test!(
-            lsr_reg64_reg64_reg64,
-            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
-                "lsr {}, {}, {}",
+            asr_reg64_reg64_imm6,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, imm| format!(
+                "asr {}, {}, #0x{:x}",
                 reg1.capstone_string(UsesZR),
                 reg2.capstone_string(UsesZR),
-                reg3.capstone_string(UsesZR)
+                imm
             ),
             ALL_GENERAL_REGS,
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [0x0Fu8, 0x3F]
         );
     }
 
@@ -3648,6 +5804,21 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_movn_reg64_imm16() {
+        disassembler_test!(
+            movn_reg64_imm16,
+            |reg1: AArch64GeneralReg, imm: u16, hw: u8| format!(
+                "mov {}, #-0x{:x}",
+                reg1.capstone_string(UsesZR),
+                ((imm as u64) << (16 * hw)) + 1
+            ),
+            ALL_GENERAL_REGS,
+            [TEST_U16],
+            [0, 1, 2, 3]
+        );
+    }
+
     #[test]
     fn test_movz_reg64_imm16() {
         disassembler_test!(
@@ -3720,6 +5891,68 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_and_reg64_reg64_imm32() {
+        disassembler_test!(
+            |buf: &mut Vec<'_, u8>, dst, src| AArch64Assembler::and_reg64_reg64_imm32(
+                buf, dst, src, -8
+            ),
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| format!(
+                "and {}, {}, #0xfffffffffffffff8",
+                reg1.capstone_string(UsesSP),
+                reg2.capstone_string(UsesZR),
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn and_immediates_are_the_logical_ones() {
+        let supports = |imm| AArch64Assembler::supports_imm32_operand(ImmOperation::And, imm);
+        assert!(supports(0xFF));
+        assert!(supports(-8));
+        assert!(!supports(0));
+        assert!(!supports(-1));
+        assert!(!supports(0x1234));
+        // The imm32 form can not hold it, even though it is a logical immediate.
+        assert!(!supports(0xFF_0000_0000));
+    }
+
+    #[test]
+    fn imm12_operands_go_up_to_4095() {
+        let supports = |imm| AArch64Assembler::supports_imm32_operand(ImmOperation::Add, imm);
+        assert!(supports(0));
+        assert!(supports(0xFFF));
+        assert!(!supports(0x1000));
+        assert!(!supports(-1));
+    }
+
+    #[test]
+    fn test_orr_reg64_reg64_imm() {
+        disassembler_test!(
+            |buf: &mut Vec<'_, u8>, dst, src| orr_reg64_reg64_imm(
+                buf,
+                dst,
+                src,
+                encode_logical_imm64(0x5555_5555_5555_5555).unwrap()
+            ),
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg| {
+                if reg2 == AArch64GeneralReg::ZRSP {
+                    format!("mov {}, #0x5555555555555555", reg1.capstone_string(UsesSP))
+                } else {
+                    format!(
+                        "orr {}, {}, #0x5555555555555555",
+                        reg1.capstone_string(UsesSP),
+                        reg2.capstone_string(UsesZR),
+                    )
+                }
+            },
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
     #[test]
     fn test_sdiv_reg64_reg64_reg64() {
         disassembler_test!(
@@ -3736,6 +5969,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_smulh_reg64_reg64_reg64() {
+        disassembler_test!(
+            smulh_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "smulh {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_str_freg128_reg64_imm12() {
+        disassembler_test!(
+            str_freg128_reg64_imm12,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg, imm| format!(
+                "str q{}, [{}, #0x{:x}]",
+                reg1.id(),
+                reg2.capstone_string(UsesSP),
+                imm << 4
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS,
+            [0x123]
+        );
+    }
+
+    #[test]
+    fn test_stur_freg128_reg64_imm9() {
+        disassembler_test!(
+            stur_freg128_reg64_imm9,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg, imm| format!(
+                "stur q{}, [{}, #0x{:x}]",
+                reg1.id(),
+                reg2.capstone_string(UsesSP),
+                imm
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS,
+            [0x78i16]
+        );
+    }
+
     #[test]
     fn test_str_reg64_reg64_imm12() {
         disassembler_test!(
@@ -3886,6 +6167,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_umulh_reg64_reg64_reg64() {
+        disassembler_test!(
+            umulh_reg64_reg64_reg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64GeneralReg, reg3: AArch64GeneralReg| format!(
+                "umulh {}, {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(UsesZR),
+                reg3.capstone_string(UsesZR)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
     // Float instructions
 
     #[test]
@@ -4001,6 +6298,230 @@ mod tests {
         );
     }
 
+    /// DecodeBitMasks from the ARM manual, for the 64 bit logical immediates.
+    fn decode_logical_imm64(imm: BitmaskImmediate) -> u64 {
+        let combined = ((imm.n as u32) << 6) | (!imm.imms as u32 & 0b111111);
+        let size = 1u32 << (31 - combined.leading_zeros());
+        let levels = size - 1;
+        let ones = (imm.imms as u32 & levels) + 1;
+        let rotation = imm.immr as u32 & levels;
+        let mask = if size == 64 {
+            u64::MAX
+        } else {
+            (1u64 << size) - 1
+        };
+        let mut element = if ones == 64 {
+            u64::MAX
+        } else {
+            (1u64 << ones) - 1
+        };
+        if rotation > 0 {
+            element = ((element >> rotation) | (element << (size - rotation))) & mask;
+        }
+        (0..64 / size).fold(0, |value, i| value | element << (i * size))
+    }
+
+    #[test]
+    fn test_encode_logical_imm64() {
+        assert_eq!(
+            encode_logical_imm64(0x5555_5555_5555_5555),
+            Some(BitmaskImmediate {
+                n: false,
+                immr: 0,
+                imms: 0b111100
+            })
+        );
+        assert_eq!(
+            encode_logical_imm64(0x00FF_00FF_00FF_00FF),
+            Some(BitmaskImmediate {
+                n: false,
+                immr: 0,
+                imms: 0b100111
+            })
+        );
+        assert_eq!(
+            encode_logical_imm64(0x8000_0000_0000_0001),
+            Some(BitmaskImmediate {
+                n: true,
+                immr: 1,
+                imms: 1
+            })
+        );
+        assert_eq!(encode_logical_imm64(0), None);
+        assert_eq!(encode_logical_imm64(u64::MAX), None);
+        assert_eq!(encode_logical_imm64(0x1234), None);
+
+        // Every rotated run of ones in every element size, 5334 values in total.
+        let mut count = 0;
+        for size in [2, 4, 8, 16, 32, 64] {
+            let mask = if size == 64 {
+                u64::MAX
+            } else {
+                (1u64 << size) - 1
+            };
+            for ones in 1..size {
+                let run = (1u64 << ones) - 1;
+                for rotation in 0..size {
+                    let element = if rotation == 0 {
+                        run
+                    } else {
+                        ((run >> rotation) | (run << (size - rotation))) & mask
+                    };
+                    let imm = (0..64 / size).fold(0, |value, i| value | element << (i * size));
+
+                    let encoded = encode_logical_imm64(imm)
+                        .unwrap_or_else(|| panic!("{:#x} is a logical immediate", imm));
+                    assert_eq!(decode_logical_imm64(encoded), imm);
+                    count += 1;
+                }
+            }
+        }
+        assert_eq!(count, 5334);
+    }
+
+    /// Runs the MOVZ, MOVN, MOVK and `ORR Xd, XZR, #imm` instructions in `code`, and returns the result.
+    fn run_mov_sequence(code: &[u8]) -> u64 {
+        let mut value = 0;
+        for inst in code.chunks(4) {
+            let inst = u32::from_le_bytes(inst.try_into().unwrap());
+            let opc = (inst >> 29) & 0b11;
+            match (inst >> 23) & 0b111111 {
+                0b100101 => {
+                    let shift = 16 * ((inst >> 21) & 0b11);
+                    let imm16 = ((inst >> 5) & 0xFFFF) as u64;
+                    value = match opc {
+                        0b00 => !(imm16 << shift),
+                        0b10 => imm16 << shift,
+                        0b11 => (value & !(0xFFFF << shift)) | (imm16 << shift),
+                        _ => panic!("not a move wide: {:#x}", inst),
+                    }
+                }
+                0b100100 if opc == 0b01 && (inst >> 5) & 0b11111 == 31 => {
+                    value = decode_logical_imm64(BitmaskImmediate {
+                        n: (inst >> 22) & 1 == 1,
+                        immr: ((inst >> 16) & 0b111111) as u8,
+                        imms: ((inst >> 10) & 0b111111) as u8,
+                    });
+                }
+                _ => panic!("not a mov: {:#x}", inst),
+            }
+        }
+        value
+    }
+
+    #[test]
+    fn test_mov_reg64_imm64_picks_the_shortest_encoding() {
+        let arena = bumpalo::Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        let mut mov = |imm: i64| {
+            buf.clear();
+            AArch64Assembler::mov_reg64_imm64(&mut buf, AArch64GeneralReg::X0, imm);
+            assert_eq!(run_mov_sequence(&buf), imm as u64, "mov of {:#x}", imm);
+            buf.len() / 4
+        };
+
+        // MOVZ or MOVN alone.
+        assert_eq!(mov(0), 1);
+        assert_eq!(mov(0x1234), 1);
+        assert_eq!(mov(0x1234_0000_0000), 1);
+        assert_eq!(mov(-1), 1);
+        assert_eq!(mov(-0x1235), 1);
+        // ORR alone.
+        assert_eq!(mov(0x5555_5555_5555_5555), 1);
+        assert_eq!(mov(0xFFFF_FFFF), 1);
+        assert_eq!(mov(0x00FF_00FF_00FF_00FF), 1);
+        // MOVN and MOVK.
+        assert_eq!(mov(-0x1234_5678), 2);
+        // ORR and MOVK.
+        assert_eq!(mov(0x5555_5555_5555_1234), 2);
+        // MOVZ and MOVKs.
+        assert_eq!(mov(0x1234_0000_0000_5678), 2);
+        assert_eq!(mov(0x1234_5678_9ABC_DEF0), 4);
+
+        // Never longer than a MOVZ and a MOVK for each other chunk that is not 0.
+        let mut imm: u64 = 0x9E37_79B9_7F4A_7C15;
+        for _ in 0..1000 {
+            imm = imm
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            for value in [
+                imm,
+                imm >> 32,
+                imm | 0xFFFF_0000_0000_0000,
+                !(imm & 0xFFFF_FFFF),
+            ] {
+                let len = mov(value as i64);
+                let wide = (0..4)
+                    .filter(|hw| (value >> (16 * hw)) as u16 != 0)
+                    .count()
+                    .max(1);
+                assert!(
+                    len <= wide,
+                    "mov of {:#x} takes {} instructions",
+                    value,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_data_pointer_with_big_addends() {
+        use AArch64GeneralReg::*;
+
+        let arena = bumpalo::Bump::new();
+        let mut relocs = bumpalo::vec![in &arena];
+        let data_pointer = |addend| {
+            let mut buf = bumpalo::vec![in &arena];
+            let mut relocs = bumpalo::vec![in &arena];
+            AArch64Assembler::data_pointer(&mut buf, &mut relocs, "data".to_string(), addend, X0);
+            buf
+        };
+
+        let mut expected = bumpalo::vec![in &arena];
+        AArch64Assembler::function_pointer(&mut expected, &mut relocs, "data".to_string(), X0);
+        add_reg64_reg64_imm12_lsl12(&mut expected, X0, X0, 0x12);
+        add_reg64_reg64_imm12(&mut expected, X0, X0, 0x345);
+        assert_eq!(data_pointer(0x12345), expected);
+
+        let mut expected = bumpalo::vec![in &arena];
+        AArch64Assembler::function_pointer(&mut expected, &mut relocs, "data".to_string(), X0);
+        add_reg64_reg64_imm12_lsl12(&mut expected, X0, X0, 0x10);
+        assert_eq!(data_pointer(0x10000), expected);
+
+        let mut expected = bumpalo::vec![in &arena];
+        AArch64Assembler::function_pointer(&mut expected, &mut relocs, "data".to_string(), X0);
+        materialize_reg64_imm64(&mut expected, XR, 0x1234_5678);
+        add_reg64_reg64_reg64(&mut expected, X0, X0, XR);
+        assert_eq!(data_pointer(0x1234_5678), expected);
+    }
+
+    #[test]
+    fn test_mov_freg_imm_without_imm8_goes_through_xr() {
+        let arena = bumpalo::Bump::new();
+        let mut relocs = bumpalo::vec![in &arena];
+
+        let mut buf = bumpalo::vec![in &arena];
+        AArch64Assembler::mov_freg64_imm64(&mut buf, &mut relocs, AArch64FloatReg::V3, 1.1);
+        let mut expected = bumpalo::vec![in &arena];
+        materialize_reg64_imm64(&mut expected, AArch64GeneralReg::XR, 1.1f64.to_bits());
+        fmov_freg64_reg64(&mut expected, AArch64FloatReg::V3, AArch64GeneralReg::XR);
+        assert_eq!(buf, expected);
+
+        let mut buf = bumpalo::vec![in &arena];
+        AArch64Assembler::mov_freg32_imm32(&mut buf, &mut relocs, AArch64FloatReg::V3, 1.1);
+        let mut expected = bumpalo::vec![in &arena];
+        materialize_reg64_imm64(
+            &mut expected,
+            AArch64GeneralReg::XR,
+            1.1f32.to_bits() as u64,
+        );
+        fmov_freg32_reg32(&mut expected, AArch64FloatReg::V3, AArch64GeneralReg::XR);
+        assert_eq!(buf, expected);
+
+        assert!(relocs.is_empty());
+    }
+
     #[test]
     #[allow(clippy::unusual_byte_groupings)]
     fn test_encode_f32_to_imm8() {
@@ -4198,6 +6719,135 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cmeq_freg128_freg128_freg128() {
+        disassembler_test!(
+            cmeq_freg128_freg128_freg128,
+            |reg1: AArch64FloatReg, reg2: AArch64FloatReg, reg3: AArch64FloatReg| format!(
+                "cmeq v{}.16b, v{}.16b, v{}.16b",
+                reg1.id(),
+                reg2.id(),
+                reg3.id()
+            ),
+            ALL_FLOAT_REGS,
+            ALL_FLOAT_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_uminv_freg8_freg128() {
+        disassembler_test!(
+            uminv_freg8_freg128,
+            |reg1: AArch64FloatReg, reg2: AArch64FloatReg| format!(
+                "uminv b{}, v{}.16b",
+                reg1.id(),
+                reg2.id()
+            ),
+            ALL_FLOAT_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_cnt_freg_freg() {
+        disassembler_test!(
+            cnt_freg_freg,
+            |reg1: AArch64FloatReg, reg2: AArch64FloatReg| format!(
+                "cnt v{}.8b, v{}.8b",
+                reg1.id(),
+                reg2.id()
+            ),
+            ALL_FLOAT_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_addv_freg_freg() {
+        disassembler_test!(
+            addv_freg_freg,
+            |reg1: AArch64FloatReg, reg2: AArch64FloatReg| format!(
+                "addv b{}, v{}.8b",
+                reg1.id(),
+                reg2.id()
+            ),
+            ALL_FLOAT_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_fmov_freg32_reg32() {
+        disassembler_test!(
+            fmov_freg32_reg32,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg| format!(
+                "fmov {}, w{}",
+                reg1.capstone_string(FloatWidth::F32),
+                &reg2.capstone_string(UsesZR)[1..]
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_fmov_reg32_freg32() {
+        disassembler_test!(
+            fmov_reg32_freg32,
+            |reg1: AArch64GeneralReg, reg2: AArch64FloatReg| format!(
+                "fmov w{}, {}",
+                &reg1.capstone_string(UsesZR)[1..],
+                reg2.capstone_string(FloatWidth::F32)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_fmov_freg64_reg64() {
+        disassembler_test!(
+            fmov_freg64_reg64,
+            |reg1: AArch64FloatReg, reg2: AArch64GeneralReg| format!(
+                "fmov {}, {}",
+                reg1.capstone_string(FloatWidth::F64),
+                reg2.capstone_string(UsesZR)
+            ),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_fmov_reg64_freg64() {
+        disassembler_test!(
+            fmov_reg64_freg64,
+            |reg1: AArch64GeneralReg, reg2: AArch64FloatReg| format!(
+                "fmov {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(FloatWidth::F64)
+            ),
+            ALL_GENERAL_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
+    #[test]
+    fn test_fcvtas_reg64_freg() {
+        disassembler_test!(
+            fcvtas_reg64_freg,
+            |ftype: FloatWidth, reg1: AArch64GeneralReg, reg2: AArch64FloatReg| format!(
+                "fcvtas {}, {}",
+                reg1.capstone_string(UsesZR),
+                reg2.capstone_string(ftype)
+            ),
+            ALL_FLOAT_TYPES,
+            ALL_GENERAL_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
     #[test]
     fn test_scvtf_freg_reg64() {
         disassembler_test!(
@@ -4212,4 +6862,32 @@ mod tests {
             ALL_GENERAL_REGS
         );
     }
+
+    #[test]
+    fn result_pointer_in_xr_leaves_the_param_regs_to_args() {
+        use roc_mono::layout::Layout;
+        use roc_target::TargetInfo;
+
+        let mut interner = STLayoutInterner::with_capacity(4, TargetInfo::default_aarch64());
+        let triple = interner.insert_direct_no_semantic(LayoutRepr::Struct(&[
+            Layout::U64,
+            Layout::U64,
+            Layout::U64,
+        ]));
+        let in_registers = |arg_layouts: &[InLayout<'_>], ret_layout| {
+            <AArch64Call as CallConv<_, _, AArch64Assembler>>::args_in_registers(
+                &interner,
+                arg_layouts,
+                &ret_layout,
+            )
+        };
+
+        assert!(AArch64Call::returns_via_arg_pointer(&interner, &triple));
+        // X0 to X7 all take args, the result pointer goes in XR.
+        let args = [Layout::I64; 8];
+        assert!(in_registers(&args, triple));
+        assert!(in_registers(&args, Layout::I64));
+        let args = [Layout::I64; 9];
+        assert!(!in_registers(&args, triple));
+    }
 }
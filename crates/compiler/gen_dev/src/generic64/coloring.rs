@@ -0,0 +1,109 @@
+use crate::LiveInterval;
+use roc_collections::all::MutMap;
+use roc_module::symbol::Symbol;
+
+/// Colors the interference graph of the live intervals with `regs`, Chaitin style.
+/// Two symbols interfere if their live intervals overlap.
+/// Symbols that could not be colored are left out of the result, they are spilled as needed.
+/// The registers at the end of `regs` are preferred, matching how the free register lists are used.
+pub fn color_live_intervals<Reg: Copy + PartialEq>(
+    intervals: &[(Symbol, LiveInterval)],
+    regs: &[Reg],
+) -> MutMap<Symbol, Reg> {
+    let count = intervals.len();
+    let k = regs.len();
+    let mut colors = MutMap::default();
+    if k == 0 {
+        return colors;
+    }
+
+    // Build the interference graph by sweeping the intervals in order of their start.
+    let mut order: std::vec::Vec<usize> = (0..count).collect();
+    order.sort_by_key(|i| (intervals[*i].1.start, intervals[*i].1.end));
+    let mut neighbors = vec![std::vec::Vec::new(); count];
+    let mut active: std::vec::Vec<usize> = std::vec::Vec::new();
+    for i in order {
+        let start = intervals[i].1.start;
+        active.retain(|j| intervals[*j].1.end >= start);
+        for j in active.iter() {
+            neighbors[i].push(*j);
+            neighbors[*j].push(i);
+        }
+        active.push(i);
+    }
+
+    // Simplify: repeatedly remove a node with less than k neighbors.
+    // If there is none, optimistically remove the node with the most neighbors as a spill candidate.
+    let mut degree: std::vec::Vec<usize> = neighbors.iter().map(|n| n.len()).collect();
+    let mut removed = vec![false; count];
+    let mut stack = std::vec::Vec::with_capacity(count);
+    for _ in 0..count {
+        let remaining = (0..count).filter(|i| !removed[*i]);
+        let next = match remaining.clone().find(|i| degree[*i] < k) {
+            Some(i) => i,
+            None => remaining.max_by_key(|i| degree[*i]).unwrap(),
+        };
+        removed[next] = true;
+        for neighbor in neighbors[next].iter() {
+            degree[*neighbor] -= 1;
+        }
+        stack.push(next);
+    }
+
+    // Select: give each node a color not used by its already colored neighbors.
+    let mut assigned: std::vec::Vec<Option<usize>> = vec![None; count];
+    while let Some(i) = stack.pop() {
+        let color = (0..k)
+            .rev()
+            .find(|color| !neighbors[i].iter().any(|n| assigned[*n] == Some(*color)));
+        if let Some(color) = color {
+            assigned[i] = Some(color);
+            colors.insert(intervals[i].0, regs[color]);
+        }
+    }
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interval(start: u32, end: u32) -> LiveInterval {
+        LiveInterval { start, end }
+    }
+
+    #[test]
+    fn disjoint_intervals_share_a_register() {
+        let intervals = [
+            (Symbol::ARG_1, interval(0, 2)),
+            (Symbol::ARG_2, interval(3, 5)),
+        ];
+        let colors = color_live_intervals(&intervals, &[0u8, 1]);
+        assert_eq!(colors.get(&Symbol::ARG_1), Some(&1));
+        assert_eq!(colors.get(&Symbol::ARG_2), Some(&1));
+    }
+
+    #[test]
+    fn overlapping_intervals_get_different_registers() {
+        let intervals = [
+            (Symbol::ARG_1, interval(0, 4)),
+            (Symbol::ARG_2, interval(1, 5)),
+            (Symbol::ARG_3, interval(2, 3)),
+        ];
+        let colors = color_live_intervals(&intervals, &[0u8, 1, 2]);
+        let mut regs: std::vec::Vec<u8> = colors.values().copied().collect();
+        regs.sort_unstable();
+        assert_eq!(regs, [0, 1, 2]);
+    }
+
+    #[test]
+    fn uncolorable_symbol_is_left_out() {
+        let intervals = [
+            (Symbol::ARG_1, interval(0, 4)),
+            (Symbol::ARG_2, interval(1, 5)),
+            (Symbol::ARG_3, interval(2, 3)),
+        ];
+        let colors = color_live_intervals(&intervals, &[0u8, 1]);
+        assert_eq!(colors.len(), 2);
+    }
+}
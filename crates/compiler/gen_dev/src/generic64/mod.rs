@@ -1,29 +1,39 @@
 use crate::{
-    pointer_layouts, single_register_floats, single_register_int_builtins,
-    single_register_integers, Backend, Env, Relocation,
+    is_unlikely_branch, pointer_layouts, single_register_floats, single_register_int_builtins,
+    single_register_integers, Backend, DevOptLevel, Env, LiveInterval, PhaseTimes, ProcStats,
+    RefcountHelper, Relocation, SoleUse, MEMEQ_HELPER, STACK_LIMIT_SYMBOL, STACK_OVERFLOW_HELPER,
 };
 use bumpalo::collections::{CollectIn, Vec};
 use roc_builtins::bitcode::{self, FloatWidth, IntWidth};
-use roc_collections::all::MutMap;
+use roc_collections::all::{MutMap, MutSet};
 use roc_error_macros::internal_error;
+use roc_module::low_level::LowLevel;
 use roc_module::symbol::{Interns, ModuleId, Symbol};
 use roc_mono::code_gen_help::{CallerProc, CodeGenHelp, HelperOp};
 use roc_mono::ir::{
-    BranchInfo, HigherOrderLowLevel, JoinPointId, ListLiteralElement, Literal, Param, ProcLayout,
-    SelfRecursive, Stmt,
+    BranchInfo, CrashTag, Expr, HigherOrderLowLevel, JoinPointId, ListLiteralElement, Literal,
+    LookupType, Param, ProcLayout, SelfRecursive, Stmt,
 };
 use roc_mono::layout::{
     Builtin, InLayout, LambdaName, Layout, LayoutIds, LayoutInterner, LayoutRepr, STLayoutInterner,
     TagIdIntType, UnionLayout,
 };
 use roc_mono::low_level::HigherOrder;
+use roc_region::all::Region;
 use roc_target::TargetInfo;
 use std::marker::PhantomData;
 
 pub(crate) mod aarch64;
+mod coloring;
 #[cfg(test)]
 mod disassembler_test_macro;
+#[cfg(any(test, feature = "unstable-backend-api"))]
+pub mod recording;
+#[cfg(test)]
+mod reference_assembler;
+mod relax;
 pub(crate) mod storage;
+pub(crate) mod verify;
 pub(crate) mod x86_64;
 
 use storage::{RegStorage, StorageManager};
@@ -50,6 +60,9 @@ impl RegisterWidth {
     }
 }
 
+/// A calling convention, like System V or Windows x64 on x86_64.
+/// It decides where args and results are passed, which regs survive calls, and how stack frames are set up.
+/// The backend leaves all of that to it, so the same assembler serves every convention of its architecture.
 pub trait CallConv<GeneralReg: RegTrait, FloatReg: RegTrait, ASM: Assembler<GeneralReg, FloatReg>>:
     Sized + Copy
 {
@@ -102,6 +115,18 @@ pub trait CallConv<GeneralReg: RegTrait, FloatReg: RegTrait, ASM: Assembler<Gene
         ret_layout: &InLayout<'a>,
     );
 
+    /// load_host_args is load_args for a proc that the host calls, which gets its args the way the C ABI passes them.
+    /// It is the same as load_args unless the convention between Roc procs passes some args differently.
+    fn load_host_args<'a>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, Self>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        args: &'a [(InLayout<'a>, Symbol)],
+        ret_layout: &InLayout<'a>,
+    ) {
+        Self::load_args(buf, storage_manager, layout_interner, args, ret_layout)
+    }
+
     /// store_args stores the args in registers and on the stack for function calling.
     /// It also updates the amount of temporary stack space needed in the storage manager.
     fn store_args<'a>(
@@ -115,6 +140,45 @@ pub trait CallConv<GeneralReg: RegTrait, FloatReg: RegTrait, ASM: Assembler<Gene
         ret_layout: &InLayout<'a>,
     );
 
+    /// returns_via_arg_pointer is true if a result with `ret_layout` is written to memory the caller passes a pointer to,
+    /// instead of being returned in the return regs.
+    fn returns_via_arg_pointer<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        ret_layout: &InLayout<'a>,
+    ) -> bool;
+
+    /// args_in_registers is true if store_args passes all of `arg_layouts` in registers, with none on the stack.
+    /// By default general and float args each count up their own param regs,
+    /// and the pointer for a result returned via arg pointer takes the first general one.
+    fn args_in_registers<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        let mut general_i = usize::from(Self::returns_via_arg_pointer(layout_interner, ret_layout));
+        let mut float_i = 0;
+        for layout in arg_layouts {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() => general_i += 1,
+                single_register_floats!() => float_i += 1,
+                _ => return false,
+            }
+        }
+        general_i <= Self::GENERAL_PARAM_REGS.len() && float_i <= Self::FLOAT_PARAM_REGS.len()
+    }
+
+    /// general_param_reg_for_arg returns the general register store_args will pass the argument at `index` in.
+    /// This is used as a hint so the argument can be computed directly in that register.
+    /// It returns None if the argument is not passed in a general register or the register is not known up front.
+    fn general_param_reg_for_arg<'a>(
+        _layout_interner: &STLayoutInterner<'a>,
+        _arg_layouts: &[InLayout<'a>],
+        _index: usize,
+        _ret_layout: &InLayout<'a>,
+    ) -> Option<GeneralReg> {
+        None
+    }
+
     /// return_complex_symbol returns the specified complex/non-primative symbol.
     /// It uses the layout to determine how the data should be returned.
     fn return_complex_symbol<'a>(
@@ -136,6 +200,7 @@ pub trait CallConv<GeneralReg: RegTrait, FloatReg: RegTrait, ASM: Assembler<Gene
     );
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CompareOperation {
     LessThan,
     LessThanOrEqual,
@@ -143,6 +208,91 @@ pub enum CompareOperation {
     GreaterThanOrEqual,
 }
 
+impl CompareOperation {
+    fn negate(self) -> Self {
+        match self {
+            CompareOperation::LessThan => CompareOperation::GreaterThanOrEqual,
+            CompareOperation::LessThanOrEqual => CompareOperation::GreaterThan,
+            CompareOperation::GreaterThan => CompareOperation::LessThanOrEqual,
+            CompareOperation::GreaterThanOrEqual => CompareOperation::LessThan,
+        }
+    }
+}
+
+/// The condition of a branch on the comparison of two integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntCondition {
+    Equal,
+    NotEqual,
+    Signed(CompareOperation),
+    Unsigned(CompareOperation),
+}
+
+impl IntCondition {
+    /// The condition that holds exactly when this one does not.
+    pub fn negate(self) -> Self {
+        match self {
+            IntCondition::Equal => IntCondition::NotEqual,
+            IntCondition::NotEqual => IntCondition::Equal,
+            IntCondition::Signed(op) => IntCondition::Signed(op.negate()),
+            IntCondition::Unsigned(op) => IntCondition::Unsigned(op.negate()),
+        }
+    }
+}
+
+/// An instruction decoded by `Assembler::decode_instruction`, for `Env::verify_machine_code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    /// The size of the instruction in bytes.
+    pub len: usize,
+    /// The offset within the instruction of the field a relocation can patch, if it has one.
+    pub reloc_field: Option<usize>,
+    /// The target of a jump, relative to the start of the instruction. Calls are not included.
+    pub jump_target: Option<i64>,
+}
+
+/// The operations that have a form with an immediate operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImmOperation {
+    Add,
+    Sub,
+    And,
+    Compare,
+}
+
+impl ImmOperation {
+    fn is_commutative(self) -> bool {
+        matches!(self, ImmOperation::Add | ImmOperation::And)
+    }
+}
+
+/// A value a switch branch passes to a join point, see `select_branch`.
+struct SelectValue<'a> {
+    id: JoinPointId,
+    symbol: Symbol,
+    literal: Option<(&'a Literal<'a>, InLayout<'a>)>,
+}
+
+/// Matches a branch that only passes a single value to a join point.
+/// That is either `jump id value` or `let value = literal; jump id value`.
+fn select_branch<'a>(stmt: &'a Stmt<'a>) -> Option<SelectValue<'a>> {
+    match stmt {
+        Stmt::Jump(id, [symbol]) => Some(SelectValue {
+            id: *id,
+            symbol: *symbol,
+            literal: None,
+        }),
+        Stmt::Let(symbol, Expr::Literal(lit), layout, Stmt::Jump(id, [arg])) if arg == symbol => {
+            Some(SelectValue {
+                id: *id,
+                symbol: *symbol,
+                literal: Some((lit, *layout)),
+            })
+        }
+        _ => None,
+    }
+}
+
 /// Assembler contains calls to the backend assembly generator.
 /// These calls do not necessarily map directly to a single assembly instruction.
 /// They are higher level in cases where an instruction would not be common and shared between multiple architectures.
@@ -151,9 +301,9 @@ pub enum CompareOperation {
 /// dst should always come before sources.
 pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
     fn abs_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg);
-    fn abs_freg64_freg64(
+    fn abs_freg64_freg64<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: FloatReg,
         src: FloatReg,
     );
@@ -178,6 +328,7 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         src2: GeneralReg,
     );
 
+    fn and_reg64_reg64_imm32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src1: GeneralReg, imm32: i32);
     fn and_reg64_reg64_reg64(
         buf: &mut Vec<'_, u8>,
         dst: GeneralReg,
@@ -229,20 +380,89 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         ASM: Assembler<GeneralReg, FloatReg>,
         CC: CallConv<GeneralReg, FloatReg, ASM>;
 
-    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation>, fn_name: String);
+    /// Sets dst to the number of leading zero bits of src, which is 64 for 0.
+    /// Without `bit_count_instructions`, x86-64 takes a few more instructions to make do without lzcnt.
+    fn count_leading_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
+    /// Sets dst to the number of trailing zero bits of src, which is 64 for 0.
+    /// Without `bit_count_instructions`, x86-64 takes a few more instructions to make do without tzcnt.
+    fn count_trailing_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
+    /// Sets dst to the number of set bits of src.
+    /// Without `bit_count_instructions`, x86-64 sums them up with shifts and masks instead of popcnt.
+    fn count_one_bits_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
+    /// Shifts by a constant amount, which must be less than 64.
+    fn shl_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8);
+    fn shr_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8);
+    fn sar_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8);
+
+    /// Computes the address `base + (index << shift) + offset` into dst.
+    /// The shift must be at most 3, which covers elements of 1, 2, 4, and 8 bytes.
+    fn lea_reg64_base_index(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        base: GeneralReg,
+        index: GeneralReg,
+        shift: u8,
+        offset: i32,
+    );
+
+    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, fn_name: String);
+
+    /// Calls the function whose address is stored in the data symbol named slot_name, see `Env::hot_reload`.
+    /// All param regs must be left as they are, so this uses a scratch register that is not one of them.
+    fn call_slot(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, slot_name: String);
 
     fn function_pointer(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
         fn_name: String,
         dst: GeneralReg,
     );
 
+    /// Loads the address of the data symbol named data_name, plus addend, into dst.
+    fn data_pointer(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        data_name: String,
+        addend: i64,
+        dst: GeneralReg,
+    );
+
     /// Jumps by an offset of offset bytes unconditionally.
     /// It should always generate the same number of bytes to enable replacement if offset changes.
     /// It returns the base offset to calculate the jump from (generally the instruction after the jump).
     fn jmp_imm32(buf: &mut Vec<'_, u8>, offset: i32) -> usize;
 
+    /// Jumps to the entry at index in the table of `jmp_imm32` jumps that directly follows, overwriting tmp.
+    /// The caller makes sure the index is within the table.
+    fn jmp_table_reg64(buf: &mut Vec<'_, u8>, tmp: GeneralReg, index: GeneralReg);
+
     fn tail_call(buf: &mut Vec<'_, u8>) -> u64;
 
     /// Jumps by an offset of offset bytes if reg is not equal to imm.
@@ -259,15 +479,40 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         ASM: Assembler<GeneralReg, FloatReg>,
         CC: CallConv<GeneralReg, FloatReg, ASM>;
 
-    fn mov_freg32_imm32(
+    /// Jumps by an offset of offset bytes if comparing src1 to src2 meets the condition.
+    /// It should always generate the same number of bytes to enable replacement if offset changes.
+    /// It returns the base offset to calculate the jump from (generally the instruction after the jump).
+    fn jcc_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
+        src1: GeneralReg,
+        src2: GeneralReg,
+        offset: i32,
+    ) -> usize;
+
+    /// The size of the jump instruction that `code` ends with and the size of its short form.
+    /// The jump must be from `jmp_imm32`, `jne_reg64_imm64_imm32` or `jcc_reg64_reg64_imm32`.
+    /// Both sizes are the same if the jump has no short form.
+    fn jump_sizes(code: &[u8]) -> (usize, usize);
+
+    /// Emits the jump instruction `jump`, described by `jump_sizes`, with a new offset.
+    /// If `short` is set, its short form is emitted instead. That only fits an offset within an i8.
+    fn encode_jump(buf: &mut Vec<'_, u8>, jump: &[u8], offset: i32, short: bool);
+
+    /// Decodes the instruction that `code` starts with, see `DecodedInstruction`.
+    /// Returns None if the bytes are not an instruction, or one that is cut off.
+    fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction>;
+
+    fn mov_freg32_imm32<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: FloatReg,
         imm: f32,
     );
-    fn mov_freg64_imm64(
+    fn mov_freg64_imm64<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: FloatReg,
         imm: f64,
     );
@@ -276,6 +521,8 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
 
     fn mov_reg32_freg32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: FloatReg);
     fn mov_reg64_freg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: FloatReg);
+    fn mov_freg32_reg32(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg);
+    fn mov_freg64_reg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg);
 
     fn mov_reg_reg(
         buf: &mut Vec<'_, u8>,
@@ -314,6 +561,8 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
 
     // base32 is similar to stack based instructions but they reference the base/frame pointer.
     fn mov_freg64_base32(buf: &mut Vec<'_, u8>, dst: FloatReg, offset: i32);
+    /// Loads all 128 bits of the vector register, the offset does not need to be 16 byte aligned.
+    fn mov_freg128_base32(buf: &mut Vec<'_, u8>, dst: FloatReg, offset: i32);
 
     fn mov_reg64_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32);
     fn mov_reg32_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32);
@@ -321,6 +570,8 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
     fn mov_reg8_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32);
 
     fn mov_base32_freg64(buf: &mut Vec<'_, u8>, offset: i32, src: FloatReg);
+    /// Stores all 128 bits of the vector register, the offset does not need to be 16 byte aligned.
+    fn mov_base32_freg128(buf: &mut Vec<'_, u8>, offset: i32, src: FloatReg);
 
     fn mov_base32_reg64(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg);
     fn mov_base32_reg32(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg);
@@ -360,6 +611,13 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         src: GeneralReg,
         offset: i32,
     );
+    /// Loads all 128 bits of the vector register, src + offset does not need to be 16 byte aligned.
+    fn mov_freg128_mem128_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src: GeneralReg,
+        offset: i32,
+    );
 
     // move from register to memory
     fn mov_mem64_offset32_reg64(
@@ -368,6 +626,13 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         offset: i32,
         src: GeneralReg,
     );
+    /// Atomically adds src to the 64 bit value at dst + offset. src receives the value from before the addition.
+    fn atomic_add_mem64_offset32_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    );
     fn mov_mem32_offset32_reg32(
         buf: &mut Vec<'_, u8>,
         dst: GeneralReg,
@@ -432,6 +697,16 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
     fn sqrt_freg64_freg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg);
     fn sqrt_freg32_freg32(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg);
 
+    /// Rounds the f64 in src to the nearest i64, with halfway cases rounded away from zero like `NUM_ROUND_F64`.
+    fn round_reg64_freg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: FloatReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
     fn neg_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg);
     fn mul_freg32_freg32_freg32(
         buf: &mut Vec<'_, u8>,
@@ -473,6 +748,31 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         ASM: Assembler<GeneralReg, FloatReg>,
         CC: CallConv<GeneralReg, FloatReg, ASM>;
 
+    /// Sets dst to the upper 64 bits of the 128 bit product of src1 and src2 as unsigned integers.
+    /// Like `umul_reg64_reg64_reg64`, this can move other symbols out of the registers the multiply uses,
+    /// so registers of symbols besides dst have to be loaded again after it.
+    fn umulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
+    /// Sets dst to the upper 64 bits of the 128 bit product of src1 and src2 as signed integers.
+    /// Registers of symbols besides dst have to be loaded again after it, see `umulh_reg64_reg64_reg64`.
+    fn smulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>;
+
     fn idiv_reg64_reg64_reg64<'a, ASM, CC>(
         buf: &mut Vec<'a, u8>,
         storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
@@ -564,6 +864,31 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         src2: GeneralReg,
     );
 
+    /// Sets dst to 1 if comparing src to imm32 meets the condition, and to 0 otherwise.
+    fn compare_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
+        dst: GeneralReg,
+        src: GeneralReg,
+        imm32: i32,
+    );
+
+    /// Sets dst to src1 if src equals imm32, and to src2 otherwise, without branching.
+    /// dst must not be src1. imm32 must be accepted by `supports_imm32_operand` for compares.
+    fn select_reg64_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        imm32: i32,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    );
+
+    /// Whether imm can be the immediate operand of `operation` without loading it into a register.
+    /// The imm32 forms of the operations are only used with immediates that this accepts.
+    fn supports_imm32_operand(operation: ImmOperation, imm: i64) -> bool;
+
     fn eq_freg_freg_reg64(
         buf: &mut Vec<'_, u8>,
         dst: GeneralReg,
@@ -580,6 +905,15 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
         width: FloatWidth,
     );
 
+    /// Sets dst to 1 if all 16 bytes of src1 and src2 are equal, and to 0 otherwise.
+    /// The comparison is done in place, so src1 is overwritten.
+    fn eq_freg128_freg128_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    );
+
     fn cmp_freg_freg_reg64(
         buf: &mut Vec<'_, u8>,
         dst: GeneralReg,
@@ -601,6 +935,33 @@ pub trait Assembler<GeneralReg: RegTrait, FloatReg: RegTrait>: Sized + Copy {
 
     fn set_if_overflow(buf: &mut Vec<'_, u8>, dst: GeneralReg);
 
+    /// Like `add_reg64_reg64_reg64`, but also sets the flags that the conditional moves below read.
+    /// dst may not be one of the sources.
+    fn adds_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    );
+
+    /// Like `sub_reg64_reg64_reg64`, but also sets the flags that the conditional moves below read.
+    /// dst may not be one of the sources.
+    fn subs_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    );
+
+    /// Moves src into dst if the last adds or subs overflowed as a signed op.
+    fn cmov_if_overflow_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg);
+
+    /// Moves src into dst if the last adds carried, which is an unsigned overflow.
+    fn cmov_if_carry_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg);
+
+    /// Moves src into dst if the last subs borrowed, which is an unsigned overflow.
+    fn cmov_if_borrow_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg);
+
     fn ret(buf: &mut Vec<'_, u8>);
 }
 
@@ -629,20 +990,60 @@ pub struct Backend64Bit<
     helper_proc_symbols: Vec<'a, (Symbol, ProcLayout<'a>)>,
     caller_procs: Vec<'a, CallerProc<'a>>,
     buf: Vec<'a, u8>,
-    relocs: Vec<'a, Relocation>,
+    relocs: Vec<'a, Relocation<'a>>,
     proc_name: Option<String>,
     is_self_recursive: Option<SelfRecursive>,
 
-    last_seen_map: MutMap<Symbol, *const Stmt<'a>>,
     layout_map: MutMap<Symbol, InLayout<'a>>,
     free_map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>,
+    branch_free_map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>,
+    fused_compares: MutSet<Symbol>,
+    hoisted_literals: MutSet<Symbol>,
+    // The last symbol loaded from each field of a struct in the current straight-line region.
+    field_cache: MutMap<(Symbol, u64), Symbol>,
+    stmt_positions: MutMap<*const Stmt<'a>, u32>,
 
     literal_map: MutMap<Symbol, (*const Literal<'a>, *const InLayout<'a>)>,
     join_map: MutMap<JoinPointId, Vec<'a, (u64, u64)>>,
+    // The jump location, base offset, and callee of every tail call.
+    // The jumps go to a copy of the epilogue that ends in a jump to the callee.
+    tail_calls: Vec<'a, (u64, u64, String)>,
+    // The base offset and target of every jump within the proc, for relaxing them in finalize.
+    jumps: Vec<'a, (u64, u64)>,
+    // Jump tables need their entries at fixed offsets, so procs with one are not relaxed.
+    has_jump_table: bool,
 
     storage_manager: StorageManager<'a, 'r, GeneralReg, FloatReg, ASM, CC>,
+    stack_frame_size: u32,
+    phase_times: PhaseTimes,
+    // The constructs of the current proc that could not be built, see `Backend::build_unsupported`.
+    unsupported_constructs: std::vec::Vec<String>,
 }
 
+/// The alignment that malloc guarantees on every supported target.
+/// Allocations with a larger alignment go to aligned_alloc instead.
+const MALLOC_ALIGNMENT: i64 = 16;
+
+/// The refcount of an allocation with a single reference, `REFCOUNT_ONE_ISIZE` in the bitcode.
+const REFCOUNT_ONE: i64 = i64::MIN;
+
+/// The refcount of data that lives as long as the program and is never freed, `REFCOUNT_MAX_ISIZE` in the bitcode.
+const REFCOUNT_STATIC: i64 = 0;
+
+/// `NUM_POW_INT` calls with a literal exponent up to this are inlined as multiplications.
+const MAX_INLINE_POW_EXPONENT: i64 = 4;
+
+/// Procs with at least this many statements get their registers assigned by graph coloring.
+/// Smaller procs only use the linear scan style allocation of the storage manager.
+const GRAPH_COLORING_MIN_STMTS: usize = 64;
+
+/// The byte the stack frames are filled with, see `Env::poison_stack`.
+const STACK_POISON: u8 = 0xAA;
+
+/// Switches with at least this many branches become jump tables, if their values are dense enough.
+/// Fewer branches are tested faster one after the other.
+const JUMP_TABLE_MIN_BRANCHES: usize = 4;
+
 /// new creates a new backend that will output to the specific Object.
 pub fn new_backend_64bit<
     'a,
@@ -670,15 +1071,59 @@ pub fn new_backend_64bit<
         is_self_recursive: None,
         buf: bumpalo::vec![in env.arena],
         relocs: bumpalo::vec![in env.arena],
-        last_seen_map: MutMap::default(),
         layout_map: MutMap::default(),
         free_map: MutMap::default(),
+        branch_free_map: MutMap::default(),
+        fused_compares: MutSet::default(),
+        hoisted_literals: MutSet::default(),
+        field_cache: MutMap::default(),
+        stmt_positions: MutMap::default(),
         literal_map: MutMap::default(),
         join_map: MutMap::default(),
+        tail_calls: bumpalo::vec![in env.arena],
+        jumps: bumpalo::vec![in env.arena],
+        has_jump_table: false,
         storage_manager: storage::new_storage_manager(env, target_info),
+        stack_frame_size: 0,
+        phase_times: PhaseTimes::default(),
+        unsupported_constructs: std::vec::Vec::new(),
+    }
+}
+
+/// Whether two values of `layout` are equal exactly when their bytes are.
+/// That leaves out floats, structs with padding, and everything that points to other memory.
+fn compares_bytewise(interner: &STLayoutInterner<'_>, layout: InLayout<'_>) -> bool {
+    match interner.get_repr(layout) {
+        LayoutRepr::Builtin(Builtin::Int(_) | Builtin::Bool | Builtin::Decimal) => true,
+        LayoutRepr::Struct(field_layouts) => {
+            let field_size: u32 = field_layouts
+                .iter()
+                .map(|field| interner.stack_size(*field))
+                .sum();
+            field_size == interner.stack_size(layout)
+                && field_layouts
+                    .iter()
+                    .all(|field| compares_bytewise(interner, *field))
+        }
+        _ => false,
     }
 }
 
+/// Emits `jump` again over the one with a placeholder offset at `start` of `buf`, now that its target is known.
+/// `jump` returns where the jump ends, which its offset counts from. Its size does not depend on the offset.
+fn patch_jump<'a>(
+    buf: &mut Vec<'a, u8>,
+    start: usize,
+    target: usize,
+    jump: impl Fn(&mut Vec<'a, u8>, i32) -> usize,
+) {
+    let mut tmp = bumpalo::vec![in buf.bump()];
+    let end = start + jump(&mut tmp, 0);
+    tmp.clear();
+    jump(&mut tmp, target as i32 - end as i32);
+    buf[start..end].copy_from_slice(&tmp);
+}
+
 macro_rules! quadword_and_smaller {
     () => {
         IntWidth::I64
@@ -736,6 +1181,9 @@ impl<
     fn helper_proc_symbols_mut(&mut self) -> &mut Vec<'a, (Symbol, ProcLayout<'a>)> {
         &mut self.helper_proc_symbols
     }
+    fn unsupported_constructs_mut(&mut self) -> &mut std::vec::Vec<String> {
+        &mut self.unsupported_constructs
+    }
     fn helper_proc_symbols(&self) -> &Vec<'a, (Symbol, ProcLayout<'a>)> {
         &self.helper_proc_symbols
     }
@@ -746,22 +1194,26 @@ impl<
     fn reset(&mut self, name: String, is_self_recursive: SelfRecursive) {
         self.proc_name = Some(name);
         self.is_self_recursive = Some(is_self_recursive);
-        self.last_seen_map.clear();
         self.layout_map.clear();
         self.join_map.clear();
         self.free_map.clear();
+        self.branch_free_map.clear();
+        self.fused_compares.clear();
+        self.hoisted_literals.clear();
+        self.field_cache.clear();
+        self.stmt_positions.clear();
+        self.tail_calls.clear();
+        self.jumps.clear();
+        self.has_jump_table = false;
         self.buf.clear();
         self.storage_manager.reset();
+        self.phase_times = PhaseTimes::default();
     }
 
     fn literal_map(&mut self) -> &mut MutMap<Symbol, (*const Literal<'a>, *const InLayout<'a>)> {
         &mut self.literal_map
     }
 
-    fn last_seen_map(&mut self) -> &mut MutMap<Symbol, *const Stmt<'a>> {
-        &mut self.last_seen_map
-    }
-
     fn layout_map(&mut self) -> &mut MutMap<Symbol, InLayout<'a>> {
         &mut self.layout_map
     }
@@ -774,7 +1226,108 @@ impl<
         &mut self.free_map
     }
 
-    fn finalize(&mut self) -> (Vec<u8>, Vec<Relocation>) {
+    fn set_branch_free_map(&mut self, map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>) {
+        self.branch_free_map = map;
+    }
+
+    fn branch_free_map(&mut self) -> &mut MutMap<*const Stmt<'a>, Vec<'a, Symbol>> {
+        &mut self.branch_free_map
+    }
+
+    fn set_fused_compares(&mut self, set: MutSet<Symbol>) {
+        self.fused_compares = set;
+    }
+
+    fn fused_compares(&mut self) -> &mut MutSet<Symbol> {
+        &mut self.fused_compares
+    }
+
+    fn hoisted_literals(&mut self) -> &mut MutSet<Symbol> {
+        &mut self.hoisted_literals
+    }
+
+    fn set_live_intervals(
+        &mut self,
+        stmt_positions: MutMap<*const Stmt<'a>, u32>,
+        live_intervals: MutMap<Symbol, LiveInterval>,
+        call_positions: std::vec::Vec<u32>,
+        layouts: MutMap<Symbol, InLayout<'a>>,
+        sole_uses: MutMap<Symbol, SoleUse<'a>>,
+    ) {
+        let mut hints = MutMap::default();
+        if stmt_positions.len() >= GRAPH_COLORING_MIN_STMTS {
+            // Big procs are worth the extra time of coloring the interference graph.
+            // The coloring is used as a hint for which register each symbol should live in.
+            let candidates: std::vec::Vec<_> = live_intervals
+                .iter()
+                .filter(
+                    |(sym, _)| match layouts.get(*sym).or_else(|| self.layout_map.get(*sym)) {
+                        Some(layout) => matches!(
+                            self.layout_interner.get_repr(*layout),
+                            single_register_integers!() | pointer_layouts!()
+                        ),
+                        None => false,
+                    },
+                )
+                .map(|(sym, interval)| (*sym, *interval))
+                .collect();
+            hints = coloring::color_live_intervals(&candidates, CC::GENERAL_DEFAULT_FREE_REGS);
+        }
+
+        // Symbols only used to pass to a call or to return are best computed in the register they are needed in.
+        for (sym, sole_use) in sole_uses {
+            let reg = match sole_use {
+                SoleUse::CallArg {
+                    arg_layouts,
+                    index,
+                    ret_layout,
+                } => CC::general_param_reg_for_arg(
+                    self.layout_interner,
+                    arg_layouts,
+                    index,
+                    &ret_layout,
+                ),
+                SoleUse::Return => match layouts.get(&sym).or_else(|| self.layout_map.get(&sym)) {
+                    Some(layout)
+                        if matches!(
+                            self.layout_interner.get_repr(*layout),
+                            single_register_integers!() | pointer_layouts!()
+                        ) =>
+                    {
+                        Some(CC::GENERAL_RETURN_REGS[0])
+                    }
+                    _ => None,
+                },
+            };
+            if let Some(reg) = reg {
+                hints.insert(sym, reg);
+            }
+        }
+        self.storage_manager.set_general_reg_hints(hints);
+        self.stmt_positions = stmt_positions;
+        self.storage_manager
+            .set_live_intervals(live_intervals, call_positions);
+    }
+
+    fn enter_stmt(&mut self, stmt: &Stmt<'a>) {
+        // Statements generated while building (like expanded refcounting) have no position.
+        // They just continue at the position of the statement they came from.
+        if let Some(position) = self.stmt_positions.get(&(stmt as *const Stmt<'a>)) {
+            self.storage_manager.set_position(*position);
+        }
+        if self.env.check_storage_invariants {
+            if let Err(error) = self.storage_manager.check_invariants() {
+                internal_error!(
+                    "The storage of {:?} is inconsistent before statement {}: {}",
+                    self.proc_name,
+                    self.storage_manager.position(),
+                    error
+                );
+            }
+        }
+    }
+
+    fn finalize(&mut self) -> (Vec<'a, u8>, Vec<'a, Relocation<'a>>) {
         let mut out = bumpalo::vec![in self.env.arena];
 
         // Setup stack.
@@ -787,7 +1340,26 @@ impl<
             self.storage_manager.stack_size() as i32,
             self.storage_manager.fn_call_stack_size() as i32,
         );
+        let mut stack_check_relocs = bumpalo::vec![in self.env.arena];
+        let mut stack_check_jumps = vec![];
+        // The helper runs past the limit, so it can not check it.
+        let stack_check_size = self
+            .env
+            .stack_check_size
+            .filter(|_| self.proc_name.as_deref() != Some(STACK_OVERFLOW_HELPER));
+        if let Some(size) = stack_check_size {
+            Self::build_stack_check(
+                &mut out,
+                &mut stack_check_relocs,
+                &mut stack_check_jumps,
+                size,
+            );
+        }
+        if self.env.poison_stack {
+            Self::poison_stack_frame(&mut out, self.storage_manager.stack_size());
+        }
         let setup_offset = out.len();
+        self.stack_frame_size = aligned_stack_size as u32;
 
         // Deal with jumps to the return address.
         let old_relocs = std::mem::replace(&mut self.relocs, bumpalo::vec![in self.env.arena]);
@@ -830,19 +1402,47 @@ impl<
             }
         }
 
-        // Add function body.
-        out.extend(&self.buf[..self.buf.len() - end_jmp_size]);
-
-        // Cleanup stack.
+        // Each tail call gets its own epilogue after the regular one, ending in a jump to the callee.
+        let mut epilogue = bumpalo::vec![in self.env.arena];
         CC::cleanup_stack(
-            &mut out,
+            &mut epilogue,
             &used_general_regs,
             &used_float_regs,
             aligned_stack_size,
             self.storage_manager.fn_call_stack_size() as i32,
         );
+        let tail_calls = std::mem::replace(&mut self.tail_calls, bumpalo::vec![in self.env.arena]);
+        if !tail_calls.is_empty() {
+            tmp.clear();
+            ASM::ret(&mut tmp);
+            let mut stub_offset = ret_offset + epilogue.len() + tmp.len();
+            tmp.clear();
+            ASM::tail_call(&mut tmp);
+            let stub_size = epilogue.len() + tmp.len();
+            for (inst_loc, offset, _) in tail_calls.iter() {
+                self.update_jmp_imm32_offset(&mut tmp, *inst_loc, *offset, stub_offset as u64);
+                stub_offset += stub_size;
+            }
+        }
+
+        // Add function body.
+        out.extend(&self.buf[..self.buf.len() - end_jmp_size]);
+
+        // Cleanup stack.
+        out.extend(&epilogue);
         ASM::ret(&mut out);
 
+        let mut tail_call_relocs = bumpalo::vec![in self.env.arena];
+        for (_, _, fn_name) in tail_calls.into_iter() {
+            out.extend(&epilogue);
+            let offset = ASM::tail_call(&mut out);
+            tail_call_relocs.push(Relocation::LinkedFunction {
+                offset,
+                name: fn_name,
+                addend: 0,
+            });
+        }
+
         // Update other relocs to include stack setup offset.
         let mut out_relocs = bumpalo::vec![in self.env.arena];
         out_relocs.extend(
@@ -854,17 +1454,58 @@ impl<
                         offset: offset + setup_offset as u64,
                         data,
                     },
-                    Relocation::LinkedData { offset, name } => Relocation::LinkedData {
+                    Relocation::LinkedData {
+                        offset,
+                        name,
+                        addend,
+                    } => Relocation::LinkedData {
                         offset: offset + setup_offset as u64,
                         name,
+                        addend,
                     },
-                    Relocation::LinkedFunction { offset, name } => Relocation::LinkedFunction {
+                    Relocation::LinkedFunction {
+                        offset,
+                        name,
+                        addend,
+                    } => Relocation::LinkedFunction {
                         offset: offset + setup_offset as u64,
                         name,
+                        addend,
                     },
+                    Relocation::LinkedFunctionPage { offset, name } => {
+                        Relocation::LinkedFunctionPage {
+                            offset: offset + setup_offset as u64,
+                            name,
+                        }
+                    }
                     Relocation::JmpToReturn { .. } => unreachable!(),
                 }),
         );
+        out_relocs.extend(tail_call_relocs);
+        out_relocs.extend(stack_check_relocs);
+
+        // Now that all jumps are known, use their short forms where possible.
+        let jumps = std::mem::replace(&mut self.jumps, bumpalo::vec![in self.env.arena]);
+        let jumps: std::vec::Vec<_> = jumps
+            .into_iter()
+            .map(|(base, target)| (base + setup_offset as u64, target + setup_offset as u64))
+            .chain(stack_check_jumps)
+            .collect();
+        if !self.has_jump_table {
+            relax::relax_jumps::<GeneralReg, FloatReg, ASM>(&mut out, &jumps, &mut out_relocs);
+        }
+
+        if self.env.verify_machine_code {
+            if let Err(error) = verify::verify_code::<GeneralReg, FloatReg, ASM>(&out, &out_relocs)
+            {
+                internal_error!(
+                    "the code of {} does not decode: {}",
+                    self.proc_name.as_deref().unwrap_or("?"),
+                    error
+                );
+            }
+        }
+
         (out, out_relocs)
     }
 
@@ -878,14 +1519,329 @@ impl<
         );
     }
 
-    /// Used for generating wrappers for malloc/realloc/free
-    fn build_wrapped_jmp(&mut self) -> (&'a [u8], u64) {
-        let mut out = bumpalo::vec![in self.env.arena];
-        let offset = ASM::tail_call(&mut out);
-
-        (out.into_bump_slice(), offset)
-    }
-
+    fn load_host_args(&mut self, args: &'a [(InLayout<'a>, Symbol)], ret_layout: &InLayout<'a>) {
+        CC::load_host_args(
+            &mut self.buf,
+            &mut self.storage_manager,
+            self.layout_interner,
+            args,
+            ret_layout,
+        );
+    }
+
+    fn stack_frame_size(&self) -> u32 {
+        self.stack_frame_size
+    }
+
+    fn proc_stats(&self) -> ProcStats {
+        ProcStats {
+            spills: self.storage_manager.spills(),
+            reloads: self.storage_manager.reloads(),
+            max_live_values: self.storage_manager.max_live_values(),
+            stack_frame_size: self.stack_frame_size,
+        }
+    }
+
+    fn phase_times(&self) -> PhaseTimes {
+        self.phase_times
+    }
+
+    fn phase_times_mut(&mut self) -> &mut PhaseTimes {
+        &mut self.phase_times
+    }
+
+    fn build_trampoline(&mut self, arg_order: &[usize]) -> (&'a [u8], u64) {
+        let mut out = bumpalo::vec![in self.env.arena];
+
+        // The moves happen all at once, so a register may only be overwritten once nothing reads it anymore.
+        // A cycle, like swapping two args, is broken up with a scratch register that is no param and not callee saved.
+        let mut pending: std::vec::Vec<(GeneralReg, GeneralReg)> = arg_order
+            .iter()
+            .enumerate()
+            .filter(|(dst, src)| dst != *src)
+            .map(|(dst, src)| {
+                match (
+                    CC::GENERAL_PARAM_REGS.get(dst),
+                    CC::GENERAL_PARAM_REGS.get(*src),
+                ) {
+                    (Some(dst), Some(src)) => (*dst, *src),
+                    _ => internal_error!("trampolines only shuffle args in general param regs"),
+                }
+            })
+            .collect();
+        while !pending.is_empty() {
+            let ready = pending
+                .iter()
+                .position(|(dst, _)| !pending.iter().any(|(_, src)| src == dst));
+            match ready {
+                Some(i) => {
+                    let (dst, src) = pending.remove(i);
+                    ASM::mov_reg64_reg64(&mut out, dst, src);
+                }
+                None => {
+                    let scratch = CC::GENERAL_DEFAULT_FREE_REGS
+                        .iter()
+                        .rev()
+                        .find(|reg| {
+                            !CC::general_callee_saved(reg) && !CC::GENERAL_PARAM_REGS.contains(reg)
+                        })
+                        .copied()
+                        .unwrap_or_else(|| internal_error!("no scratch register for a trampoline"));
+                    let (blocked, _) = pending[0];
+                    ASM::mov_reg64_reg64(&mut out, scratch, blocked);
+                    for (_, src) in pending.iter_mut() {
+                        if *src == blocked {
+                            *src = scratch;
+                        }
+                    }
+                }
+            }
+        }
+
+        let offset = ASM::tail_call(&mut out);
+
+        (out.into_bump_slice(), offset)
+    }
+
+    fn build_wrapped_aligned_alloc(&mut self) -> (&'a [u8], u64, u64) {
+        // roc_alloc takes the size and then the alignment, aligned_alloc wants them the other way around.
+        // The alignment is a u32, so the upper half of its register is undefined.
+        let (size, alignment, tmp, mask) = match CC::GENERAL_PARAM_REGS {
+            [size, alignment, tmp, mask, ..] => (*size, *alignment, *tmp, *mask),
+            _ => internal_error!("roc_alloc needs 4 general param regs as scratch"),
+        };
+
+        let mut aligned = bumpalo::vec![in self.env.arena];
+        if self.env.asan_allocations {
+            ASM::mov_reg64_reg64(&mut aligned, alignment, size);
+        } else {
+            // aligned_alloc wants the size to be a multiple of the alignment, so round it up.
+            ASM::sub_reg64_reg64_imm32(&mut aligned, size, size, 1);
+            ASM::add_reg64_reg64_reg64(&mut aligned, size, size, tmp);
+            ASM::neg_reg64_reg64(&mut aligned, mask, tmp);
+            ASM::and_reg64_reg64_reg64(&mut aligned, alignment, size, mask);
+        }
+        ASM::mov_reg64_reg64(&mut aligned, size, tmp);
+        let aligned_alloc_offset = ASM::tail_call(&mut aligned);
+
+        let mut out = bumpalo::vec![in self.env.arena];
+        ASM::mov_reg32_reg32(&mut out, tmp, alignment);
+        ASM::mov_reg64_imm64(&mut out, mask, MALLOC_ALIGNMENT);
+        let aligned_start = ASM::jcc_reg64_reg64_imm32(
+            &mut out,
+            RegisterWidth::W64,
+            IntCondition::Unsigned(CompareOperation::LessThanOrEqual),
+            tmp,
+            mask,
+            aligned.len() as i32,
+        );
+        out.extend(aligned);
+        let malloc_offset = ASM::tail_call(&mut out);
+
+        (
+            out.into_bump_slice(),
+            aligned_start as u64 + aligned_alloc_offset,
+            malloc_offset,
+        )
+    }
+
+    fn build_refcount_helper(&mut self, helper: RefcountHelper) -> (&'a [u8], Option<u64>) {
+        let (ptr, amount, refcount, tmp) = match CC::GENERAL_PARAM_REGS {
+            [ptr, amount, refcount, tmp, ..] => (*ptr, *amount, *refcount, *tmp),
+            _ => internal_error!("refcount helpers need 4 general param regs as scratch"),
+        };
+        let arena = self.env.arena;
+        let atomic = self.env.atomic_refcounts;
+
+        // The sections are built back to front, so every forward jump knows how far it goes.
+        let mut done = bumpalo::vec![in arena];
+        ASM::ret(&mut done);
+
+        let mut free = bumpalo::vec![in arena];
+        let mut dealloc_offset = None;
+        let mut update = bumpalo::vec![in arena];
+        match helper {
+            RefcountHelper::IncrefDataPtr | RefcountHelper::IncrefRcPtr => {
+                if atomic {
+                    ASM::atomic_add_mem64_offset32_reg64(&mut update, ptr, 0, amount);
+                } else {
+                    ASM::add_reg64_reg64_reg64(&mut update, refcount, refcount, amount);
+                    ASM::mov_mem64_offset32_reg64(&mut update, ptr, 0, refcount);
+                }
+            }
+            RefcountHelper::DecrefDataPtr(alignment) | RefcountHelper::DecrefRcPtr(alignment) => {
+                // Freeing starts at the allocation, which is padded up to the alignment before the refcount.
+                let padding = alignment.max(8) as i32 - 8;
+                if padding > 0 {
+                    ASM::sub_reg64_reg64_imm32(&mut free, ptr, ptr, padding);
+                }
+                ASM::mov_reg64_imm64(&mut free, amount, alignment as i64);
+                dealloc_offset = Some(ASM::tail_call(&mut free));
+
+                // Either way, `refcount` keeps the refcount from before the decrement.
+                if atomic {
+                    ASM::mov_reg64_imm64(&mut update, refcount, -1);
+                    ASM::atomic_add_mem64_offset32_reg64(&mut update, ptr, 0, refcount);
+                } else {
+                    ASM::sub_reg64_reg64_imm32(&mut update, tmp, refcount, 1);
+                    ASM::mov_mem64_offset32_reg64(&mut update, ptr, 0, tmp);
+                }
+                ASM::mov_reg64_imm64(&mut update, tmp, REFCOUNT_ONE);
+                ASM::jcc_reg64_reg64_imm32(
+                    &mut update,
+                    RegisterWidth::W64,
+                    IntCondition::Equal,
+                    refcount,
+                    tmp,
+                    done.len() as i32,
+                );
+            }
+        }
+
+        // A static refcount never changes, so it can be checked without an atomic load.
+        let mut load = bumpalo::vec![in arena];
+        ASM::mov_reg64_mem64_offset32(&mut load, refcount, ptr, 0);
+        ASM::mov_reg64_imm64(&mut load, tmp, REFCOUNT_STATIC);
+        ASM::jcc_reg64_reg64_imm32(
+            &mut load,
+            RegisterWidth::W64,
+            IntCondition::Equal,
+            refcount,
+            tmp,
+            update.len() as i32,
+        );
+
+        let mut out = bumpalo::vec![in arena];
+        if matches!(
+            helper,
+            RefcountHelper::IncrefDataPtr | RefcountHelper::DecrefDataPtr(_)
+        ) {
+            // Clear the tag id bits, the refcount is the word before the data.
+            let mut to_refcount = bumpalo::vec![in arena];
+            ASM::mov_reg64_imm64(&mut to_refcount, tmp, !0b111);
+            ASM::and_reg64_reg64_reg64(&mut to_refcount, ptr, ptr, tmp);
+            ASM::sub_reg64_reg64_imm32(&mut to_refcount, ptr, ptr, 8);
+
+            ASM::mov_reg64_imm64(&mut out, tmp, 0);
+            ASM::jcc_reg64_reg64_imm32(
+                &mut out,
+                RegisterWidth::W64,
+                IntCondition::Equal,
+                ptr,
+                tmp,
+                (to_refcount.len() + load.len() + update.len()) as i32,
+            );
+            out.extend(to_refcount);
+        }
+        for section in [load, update, done] {
+            out.extend(section);
+        }
+        let free_start = out.len() as u64;
+        out.extend(free);
+
+        (
+            out.into_bump_slice(),
+            dealloc_offset.map(|offset| free_start + offset),
+        )
+    }
+
+    fn build_memeq_helper(&mut self) -> &'a [u8] {
+        let (ptr1, ptr2, size1, size2) = match CC::GENERAL_PARAM_REGS {
+            [ptr1, ptr2, size1, size2, ..] => (*ptr1, *ptr2, *size1, *size2),
+            _ => internal_error!("the memeq helper needs 4 general param regs"),
+        };
+        // Windows x64 has no more param regs, so the last scratch reg is one that calls clobber anyway.
+        let args = [ptr1, ptr2, size1, size2];
+        let tmp = CC::GENERAL_DEFAULT_FREE_REGS
+            .iter()
+            .rev()
+            .find(|reg| CC::general_caller_saved(reg) && !args.contains(reg));
+        let Some(&tmp) = tmp else {
+            internal_error!("the memeq helper needs a caller saved scratch reg")
+        };
+        let (vector1, vector2) = match CC::FLOAT_PARAM_REGS {
+            [vector1, vector2, ..] => (*vector1, *vector2),
+            _ => internal_error!("the memeq helper needs 2 float param regs as scratch"),
+        };
+        let result = CC::GENERAL_RETURN_REGS[0];
+
+        // The jumps are emitted with a placeholder offset, and patched once their target is known.
+        let sizes_differ = |buf: &mut Vec<'a, u8>, offset: i32| {
+            let condition = IntCondition::NotEqual;
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W64, condition, size1, size2, offset)
+        };
+        let vectors_done = |buf: &mut Vec<'a, u8>, offset: i32| {
+            let condition = IntCondition::Unsigned(CompareOperation::LessThan);
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W64, condition, size1, size2, offset)
+        };
+        let vectors_differ = |buf: &mut Vec<'a, u8>, offset: i32| {
+            let condition = IntCondition::Equal;
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W64, condition, size2, tmp, offset)
+        };
+        let bytes_done = |buf: &mut Vec<'a, u8>, offset: i32| {
+            let condition = IntCondition::Equal;
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W64, condition, size1, size2, offset)
+        };
+        let bytes_differ = |buf: &mut Vec<'a, u8>, offset: i32| {
+            let condition = IntCondition::NotEqual;
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W8, condition, size2, tmp, offset)
+        };
+        let jump_back = |buf: &mut Vec<'a, u8>, offset: i32| ASM::jmp_imm32(buf, offset);
+
+        let mut out = bumpalo::vec![in self.env.arena];
+        let sizes_differ_at = out.len();
+        sizes_differ(&mut out, 0);
+
+        // Once the sizes are the same, size2 is free for the comparisons.
+        let vector_loop = out.len();
+        ASM::mov_reg64_imm64(&mut out, size2, 16);
+        let vectors_done_at = out.len();
+        vectors_done(&mut out, 0);
+        ASM::mov_freg128_mem128_offset32(&mut out, vector1, ptr1, 0);
+        ASM::mov_freg128_mem128_offset32(&mut out, vector2, ptr2, 0);
+        ASM::eq_freg128_freg128_reg64(&mut out, size2, vector1, vector2);
+        ASM::mov_reg64_imm64(&mut out, tmp, 0);
+        let vectors_differ_at = out.len();
+        vectors_differ(&mut out, 0);
+        ASM::add_reg64_reg64_imm32(&mut out, ptr1, ptr1, 16);
+        ASM::add_reg64_reg64_imm32(&mut out, ptr2, ptr2, 16);
+        ASM::sub_reg64_reg64_imm32(&mut out, size1, size1, 16);
+        let vector_loop_end = out.len();
+        jump_back(&mut out, 0);
+        patch_jump(&mut out, vector_loop_end, vector_loop, jump_back);
+
+        // The last 15 bytes at most go one at a time, loads past the end could fault.
+        let byte_loop = out.len();
+        patch_jump(&mut out, vectors_done_at, byte_loop, vectors_done);
+        ASM::mov_reg64_imm64(&mut out, size2, 0);
+        let bytes_done_at = out.len();
+        bytes_done(&mut out, 0);
+        ASM::mov_reg8_mem8_offset32(&mut out, size2, ptr1, 0);
+        ASM::mov_reg8_mem8_offset32(&mut out, tmp, ptr2, 0);
+        let bytes_differ_at = out.len();
+        bytes_differ(&mut out, 0);
+        ASM::add_reg64_reg64_imm32(&mut out, ptr1, ptr1, 1);
+        ASM::add_reg64_reg64_imm32(&mut out, ptr2, ptr2, 1);
+        ASM::sub_reg64_reg64_imm32(&mut out, size1, size1, 1);
+        let byte_loop_end = out.len();
+        jump_back(&mut out, 0);
+        patch_jump(&mut out, byte_loop_end, byte_loop, jump_back);
+
+        let equal = out.len();
+        patch_jump(&mut out, bytes_done_at, equal, bytes_done);
+        ASM::mov_reg64_imm64(&mut out, result, 1);
+        ASM::ret(&mut out);
+
+        let unequal = out.len();
+        patch_jump(&mut out, sizes_differ_at, unequal, sizes_differ);
+        patch_jump(&mut out, vectors_differ_at, unequal, vectors_differ);
+        patch_jump(&mut out, bytes_differ_at, unequal, bytes_differ);
+        ASM::mov_reg64_imm64(&mut out, result, 0);
+        ASM::ret(&mut out);
+
+        out.into_bump_slice()
+    }
+
     fn build_fn_pointer(&mut self, dst: &Symbol, fn_name: String) {
         let reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
 
@@ -900,20 +1856,7 @@ impl<
         arg_layouts: &[InLayout<'a>],
         ret_layout: &InLayout<'a>,
     ) {
-        // Save used caller saved regs.
-        self.storage_manager
-            .push_used_caller_saved_regs_to_stack(&mut self.buf);
-
-        // Put values in param regs or on top of the stack.
-        CC::store_args(
-            &mut self.buf,
-            &mut self.storage_manager,
-            self.layout_interner,
-            dst,
-            args,
-            arg_layouts,
-            ret_layout,
-        );
+        self.store_fn_call_args(dst, args, arg_layouts, ret_layout);
 
         // Call function and generate reloc.
         ASM::call(&mut self.buf, &mut self.relocs, fn_name);
@@ -921,6 +1864,69 @@ impl<
         self.move_return_value(dst, ret_layout)
     }
 
+    fn build_fn_call_through_slot(
+        &mut self,
+        dst: &Symbol,
+        slot_name: String,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) {
+        self.store_fn_call_args(dst, args, arg_layouts, ret_layout);
+
+        ASM::call_slot(&mut self.buf, &mut self.relocs, slot_name);
+
+        self.move_return_value(dst, ret_layout)
+    }
+
+    fn can_tail_call(&self, arg_layouts: &[InLayout<'a>], ret_layout: &InLayout<'a>) -> bool {
+        // Only arguments passed in registers are supported.
+        // Stack arguments would have to be written to the stack area of our caller, which may be too small.
+        // Pointers are excluded because they may point into the frame that is about to be gone.
+        let in_registers = CC::args_in_registers(self.layout_interner, arg_layouts, ret_layout);
+
+        // The callee returns straight to our caller, so it must not need a pointer to our frame for the result.
+        let ret_repr = self.layout_interner.get_repr(*ret_layout);
+        let returns_in_register = matches!(
+            ret_repr,
+            single_register_integers!() | single_register_floats!()
+        ) || self.layout_interner.stack_size(*ret_layout) == 0;
+
+        in_registers && returns_in_register
+    }
+
+    fn build_fn_tail_call(
+        &mut self,
+        fn_name: String,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) {
+        self.store_fn_call_args(&Symbol::DEV_TMP, args, arg_layouts, ret_layout);
+
+        // Jump to a copy of the epilogue that jumps on to the callee, it is added by finalize.
+        let inst_loc = self.buf.len() as u64;
+        let offset = ASM::jmp_imm32(&mut self.buf, 0x1234_5678) as u64;
+        self.tail_calls.push((inst_loc, offset, fn_name));
+    }
+
+    fn claim_unreachable_value(&mut self, sym: &Symbol, layout: &InLayout<'a>) {
+        match self.interner().get_repr(*layout) {
+            single_register_integers!() | pointer_layouts!() => {
+                self.storage_manager.claim_general_reg(&mut self.buf, sym);
+            }
+            single_register_floats!() => {
+                self.storage_manager.claim_float_reg(&mut self.buf, sym);
+            }
+            _ => match self.layout_interner.stack_size(*layout) {
+                0 => self.storage_manager.no_data(sym),
+                size => {
+                    self.storage_manager.claim_stack_area(sym, size);
+                }
+            },
+        }
+    }
+
     fn move_return_value(&mut self, dst: &Symbol, ret_layout: &InLayout<'a>) {
         // move return value to dst.
         let ret_repr = self.interner().get_repr(*ret_layout);
@@ -928,13 +1934,23 @@ impl<
             single_register_integers!() => {
                 let width = RegisterWidth::try_from_layout(ret_repr).unwrap();
 
-                let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+                // The return register is free after a call, so the value can usually stay in it.
+                let dst_reg = self.storage_manager.claim_general_reg_preferring(
+                    &mut self.buf,
+                    dst,
+                    CC::GENERAL_RETURN_REGS[0],
+                );
                 ASM::movzx_reg_reg(&mut self.buf, width, dst_reg, CC::GENERAL_RETURN_REGS[0]);
             }
             single_register_floats!() => {
-                let dst_reg = self.storage_manager.claim_float_reg(&mut self.buf, dst);
+                let dst_reg = self.storage_manager.claim_float_reg_preferring(
+                    &mut self.buf,
+                    dst,
+                    CC::FLOAT_RETURN_REGS[0],
+                );
                 ASM::mov_freg64_freg64(&mut self.buf, dst_reg, CC::FLOAT_RETURN_REGS[0]);
             }
+            // Every convention returns these in the first two return regs, also Windows x64 for the Zig builtins.
             LayoutRepr::I128 | LayoutRepr::U128 => {
                 let offset = self.storage_manager.claim_stack_area(dst, 16);
 
@@ -942,7 +1958,11 @@ impl<
                 ASM::mov_base32_reg64(&mut self.buf, offset + 8, CC::GENERAL_RETURN_REGS[1]);
             }
             pointer_layouts!() => {
-                let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+                let dst_reg = self.storage_manager.claim_general_reg_preferring(
+                    &mut self.buf,
+                    dst,
+                    CC::GENERAL_RETURN_REGS[0],
+                );
                 ASM::mov_reg64_reg64(&mut self.buf, dst_reg, CC::GENERAL_RETURN_REGS[0]);
             }
             LayoutRepr::LambdaSet(lambda_set) => {
@@ -978,82 +1998,106 @@ impl<
         default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
         ret_layout: &InLayout<'a>,
     ) {
-        // Switches are a little complex due to keeping track of jumps.
-        // In general I am trying to not have to loop over things multiple times or waste memory.
-        // The basic plan is to make jumps to nowhere and then correct them once we know the correct address.
+        if self.env.dev_opt_level == DevOptLevel::One {
+            if self.build_select_switch(cond_symbol, branches, default_branch, ret_layout) {
+                return;
+            }
+            if self.build_jump_table_switch(
+                layout_ids,
+                cond_symbol,
+                branches,
+                default_branch,
+                ret_layout,
+            ) {
+                return;
+            }
+        }
+
         let cond_reg = self
             .storage_manager
             .load_to_general_reg(&mut self.buf, cond_symbol);
 
-        // this state is updated destructively in the branches. We don't want the branches to
-        // influence each other, so we must clone here.
-        let mut base_storage = self.storage_manager.clone();
-        let base_literal_map = self.literal_map.clone();
-
-        let mut max_branch_stack_size = 0;
-        let mut ret_jumps = bumpalo::vec![in self.env.arena];
-        let mut tmp = bumpalo::vec![in self.env.arena];
-        for (val, _branch_info, stmt) in branches.iter() {
-            // TODO: look into branch info and if it matters here.
-            tmp.clear();
-            // Create jump to next branch if cond_sym not equal to value.
-            // Since we don't know the offset yet, set it to 0 and overwrite later.
-            let jne_location = self.buf.len();
-            let start_offset = ASM::jne_reg64_imm64_imm32(
-                &mut self.buf,
-                &mut self.storage_manager,
-                cond_reg,
-                *val,
-                0,
-            );
-
-            // Build all statements in this branch. Using storage as from before any branch.
-            self.storage_manager = base_storage.clone();
-            self.literal_map = base_literal_map.clone();
-            self.build_stmt(layout_ids, stmt, ret_layout);
-
-            // Build unconditional jump to the end of this switch.
-            // Since we don't know the offset yet, set it to 0 and overwrite later.
-            let jmp_location = self.buf.len();
-            let jmp_offset = ASM::jmp_imm32(&mut self.buf, 0x1234_5678);
-            ret_jumps.push((jmp_location, jmp_offset));
+        self.build_branches(
+            layout_ids,
+            branches,
+            default_branch,
+            ret_layout,
+            |buf, storage_manager, val, offset| {
+                // Jump to next branch if cond_sym not equal to value.
+                ASM::jne_reg64_imm64_imm32(buf, storage_manager, cond_reg, val, offset)
+            },
+        );
+    }
 
-            // Overwrite the original jne with the correct offset.
-            let end_offset = self.buf.len();
-            let jne_offset = end_offset - start_offset;
-            ASM::jne_reg64_imm64_imm32(
-                &mut tmp,
-                &mut self.storage_manager,
-                cond_reg,
-                *val,
-                jne_offset as i32,
-            );
-            for (i, byte) in tmp.iter().enumerate() {
-                self.buf[jne_location + i] = *byte;
+    fn build_compare_switch(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        compare_stmt: &Stmt<'a>,
+        op: LowLevel,
+        src1: &Symbol,
+        src2: &Symbol,
+        arg_layout: &InLayout<'a>,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+    ) {
+        let int_width = arg_layout.try_int_width().unwrap();
+        let register_width = match int_width.stack_size() {
+            8 => RegisterWidth::W64,
+            4 => RegisterWidth::W32,
+            2 => RegisterWidth::W16,
+            1 => RegisterWidth::W8,
+            _ => internal_error!("compare switch on {:?}", int_width),
+        };
+        let compare = |op| {
+            if int_width.is_signed() {
+                IntCondition::Signed(op)
+            } else {
+                IntCondition::Unsigned(op)
             }
+        };
+        let condition = match op {
+            LowLevel::Eq => IntCondition::Equal,
+            LowLevel::NotEq => IntCondition::NotEqual,
+            LowLevel::NumLt => compare(CompareOperation::LessThan),
+            LowLevel::NumLte => compare(CompareOperation::LessThanOrEqual),
+            LowLevel::NumGt => compare(CompareOperation::GreaterThan),
+            LowLevel::NumGte => compare(CompareOperation::GreaterThanOrEqual),
+            _ => internal_error!("compare switch on {:?}", op),
+        };
 
-            // Update important storage information to avoid overwrites.
-            max_branch_stack_size =
-                std::cmp::max(max_branch_stack_size, self.storage_manager.stack_size());
-            base_storage.update_fn_call_stack_size(self.storage_manager.fn_call_stack_size());
-        }
-        self.storage_manager = base_storage;
-        self.literal_map = base_literal_map;
-        self.storage_manager
-            .update_stack_size(max_branch_stack_size);
-        let (_branch_info, stmt) = default_branch;
-        self.build_stmt(layout_ids, stmt, ret_layout);
+        let buf = &mut self.buf;
+        let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
+        let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
 
-        // Update all return jumps to jump past the default case.
-        let ret_offset = self.buf.len();
-        for (jmp_location, start_offset) in ret_jumps.into_iter() {
-            self.update_jmp_imm32_offset(
-                &mut tmp,
-                jmp_location as u64,
-                start_offset as u64,
-                ret_offset as u64,
-            );
+        // The branches do not need the arguments, only the flags from comparing them.
+        self.free_symbols(compare_stmt);
+        if let Stmt::Let(_, _, _, switch) = compare_stmt {
+            self.enter_stmt(switch);
         }
+
+        self.build_branches(
+            layout_ids,
+            branches,
+            default_branch,
+            ret_layout,
+            |buf, _storage_manager, val, offset| {
+                // The branch for true is skipped if the condition fails, the branch for false if it holds.
+                let skip_condition = if val == 0 {
+                    condition
+                } else {
+                    condition.negate()
+                };
+                ASM::jcc_reg64_reg64_imm32(
+                    buf,
+                    register_width,
+                    skip_condition,
+                    src1_reg,
+                    src2_reg,
+                    offset,
+                )
+            },
+        );
     }
 
     fn build_join(
@@ -1068,6 +2112,7 @@ impl<
         // Free everything to the stack to make sure they don't get messed up when looping back to this point.
         // TODO: look into a nicer solution.
         self.storage_manager.free_all_to_stack(&mut self.buf);
+        self.field_cache.clear();
 
         // Ensure all the joinpoint parameters have storage locations.
         // On jumps to the joinpoint, we will overwrite those locations as a way to "pass parameters" to the joinpoint.
@@ -1076,11 +2121,23 @@ impl<
 
         self.join_map.insert(*id, bumpalo::vec![in self.env.arena]);
 
+        // The body can be entered from every jump, not just the end of the remainder.
+        // So it is built with the storage from before the remainder.
+        let body_storage = self.storage_manager.clone();
+        let body_literal_map = self.literal_map.clone();
+
         // Build remainder of function first. It is what gets run and jumps to join.
         self.build_stmt(layout_ids, remainder, ret_layout);
 
         let join_location = self.buf.len() as u64;
 
+        let remainder_storage = std::mem::replace(&mut self.storage_manager, body_storage);
+        self.storage_manager
+            .keep_frame_requirements(&remainder_storage);
+        self.literal_map = body_literal_map;
+        self.field_cache.clear();
+        self.storage_manager.enter_joinpoint_body(id, parameters);
+
         // Build all statements in body.
         self.build_stmt(layout_ids, body, ret_layout);
 
@@ -1128,14 +2185,22 @@ impl<
                 let src_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src);
                 ASM::abs_freg64_freg64(&mut self.buf, &mut self.relocs, dst_reg, src_reg);
             }
-            x => todo!("NumAbs: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumAbs: layout, {:?}", x)),
         }
     }
 
     fn build_num_add(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>) {
         match self.layout_interner.get_repr(*layout) {
             LayoutRepr::Builtin(Builtin::Int(quadword_and_smaller!())) => {
+                let imm_operand = self.split_imm_operand(ImmOperation::Add, src1, src2);
                 let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+                if let Some((src, imm)) = imm_operand {
+                    let src_reg = self
+                        .storage_manager
+                        .load_to_general_reg(&mut self.buf, &src);
+                    ASM::add_reg64_reg64_imm32(&mut self.buf, dst_reg, src_reg, imm);
+                    return;
+                }
                 let src1_reg = self
                     .storage_manager
                     .load_to_general_reg(&mut self.buf, src1);
@@ -1156,7 +2221,7 @@ impl<
                 let src2_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src2);
                 ASM::add_freg32_freg32_freg32(&mut self.buf, dst_reg, src1_reg, src2_reg);
             }
-            x => todo!("NumAdd: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumAdd: layout, {:?}", x)),
         }
     }
 
@@ -1169,6 +2234,18 @@ impl<
     ) {
         match self.layout_interner.get_repr(layout) {
             LayoutRepr::Builtin(Builtin::Int(width @ quadword_and_smaller!())) => {
+                // A wrapped sum is clamped to the max, or to 0 if it is unsigned.
+                self.build_int_saturated(
+                    dst,
+                    src1,
+                    src2,
+                    width,
+                    ASM::adds_reg64_reg64_reg64,
+                    ASM::cmov_if_carry_reg64_reg64,
+                    -1,
+                )
+            }
+            LayoutRepr::Builtin(Builtin::Int(width)) => {
                 let intrinsic = bitcode::NUM_ADD_SATURATED_INT[width].to_string();
                 self.build_fn_call(&dst, intrinsic, &[src1, src2], &[layout, layout], &layout);
             }
@@ -1188,18 +2265,61 @@ impl<
                 let intrinsic = bitcode::DEC_ADD_SATURATED.to_string();
                 self.build_fn_call(&dst, intrinsic, &[src1, src2], &[layout, layout], &layout);
             }
-            x => todo!("NumAddSaturated: layout, {:?}", x),
+            x => self.build_unsupported_value(
+                &dst,
+                &layout,
+                format!("NumAddSaturated: layout, {:?}", x),
+            ),
         }
     }
 
-    fn build_num_add_checked(
+    fn build_num_sub_saturated(
         &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        num_layout: &InLayout<'a>,
-        return_layout: &InLayout<'a>,
-    ) {
+        dst: Symbol,
+        src1: Symbol,
+        src2: Symbol,
+        layout: InLayout<'a>,
+    ) {
+        match self.layout_interner.get_repr(layout) {
+            LayoutRepr::Builtin(Builtin::Int(width @ quadword_and_smaller!())) => {
+                // A wrapped difference is clamped to the min, which is 0 if it is unsigned.
+                self.build_int_saturated(
+                    dst,
+                    src1,
+                    src2,
+                    width,
+                    ASM::subs_reg64_reg64_reg64,
+                    ASM::cmov_if_borrow_reg64_reg64,
+                    0,
+                )
+            }
+            LayoutRepr::Builtin(Builtin::Int(width)) => {
+                let intrinsic = bitcode::NUM_SUB_SATURATED_INT[width].to_string();
+                self.build_fn_call(&dst, intrinsic, &[src1, src2], &[layout, layout], &layout);
+            }
+            LayoutRepr::Builtin(Builtin::Float(_)) => {
+                // Floats go to infinity instead of wrapping, so this is just a normal sub.
+                self.build_num_sub(&dst, &src1, &src2, &layout)
+            }
+            LayoutRepr::Builtin(Builtin::Decimal) => {
+                self.build_unsupported_value(&dst, &layout, "Num.subSaturated on Dec".into())
+            }
+            x => self.build_unsupported_value(
+                &dst,
+                &layout,
+                format!("NumSubSaturated: layout, {:?}", x),
+            ),
+        }
+    }
+
+    fn build_num_add_checked(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        num_layout: &InLayout<'a>,
+        return_layout: &InLayout<'a>,
+    ) {
         use Builtin::Int;
 
         let buf = &mut self.buf;
@@ -1234,16 +2354,14 @@ impl<
             }
             LayoutRepr::Builtin(Int(
                 IntWidth::U64 | IntWidth::U32 | IntWidth::U16 | IntWidth::U8,
-            )) => {
-                todo!("addChecked for unsigned integers")
-            }
+            )) => self.build_unsupported("addChecked for unsigned integers".into()),
             LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64)) => {
-                todo!("addChecked for f64")
+                self.build_unsupported("addChecked for f64".into())
             }
             LayoutRepr::Builtin(Builtin::Float(FloatWidth::F32)) => {
-                todo!("addChecked for f32")
+                self.build_unsupported("addChecked for f32".into())
             }
-            x => todo!("NumAdd: layout, {:?}", x),
+            x => self.build_unsupported(format!("NumAddChecked: layout, {:?}", x)),
         }
     }
 
@@ -1271,9 +2389,53 @@ impl<
         )
     }
 
+    fn build_num_mul_checked(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        num_layout: &InLayout<'a>,
+        return_layout: &InLayout<'a>,
+    ) {
+        let function_name = match self.interner().get_repr(*num_layout) {
+            LayoutRepr::Builtin(Builtin::Int(width @ (IntWidth::I64 | IntWidth::U64))) => {
+                let struct_size = self.layout_interner.stack_size(*return_layout);
+                let base_offset = self.storage_manager.claim_stack_area(dst, struct_size);
+                let [product, high, fits] =
+                    ["mul_product", "mul_high", "mul_fits"].map(|name| self.debug_symbol(name));
+                self.build_mul_wide64(&product, &high, &fits, src1, src2, width.is_signed());
+
+                let buf = &mut self.buf;
+                let product_reg = self.storage_manager.load_to_general_reg(buf, &product);
+                let high_reg = self.storage_manager.load_to_general_reg(buf, &high);
+                let fits_reg = self.storage_manager.load_to_general_reg(buf, &fits);
+                ASM::neq_reg_reg_reg(buf, RegisterWidth::W64, high_reg, high_reg, fits_reg);
+                ASM::mov_base32_reg64(buf, base_offset, product_reg);
+                ASM::mov_base32_reg64(buf, base_offset + 8, high_reg);
+
+                self.free_symbol(&product);
+                self.free_symbol(&high);
+                self.free_symbol(&fits);
+                return;
+            }
+            LayoutRepr::Builtin(Builtin::Int(width)) => &bitcode::NUM_MUL_CHECKED_INT[width],
+            LayoutRepr::Builtin(Builtin::Float(width)) => &bitcode::NUM_MUL_CHECKED_FLOAT[width],
+            LayoutRepr::Builtin(Builtin::Decimal) => bitcode::DEC_MUL_WITH_OVERFLOW,
+            x => internal_error!("NumMulChecked is not defined for {:?}", x),
+        };
+
+        self.build_fn_call(
+            dst,
+            function_name.to_string(),
+            &[*src1, *src2],
+            &[*num_layout, *num_layout],
+            return_layout,
+        )
+    }
+
     fn build_num_mul(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>) {
-        // for the time being, `num_mul` is implemented as wrapping multiplication. In roc, the normal
-        // `mul` should panic on overflow, but we just don't do that yet
+        // Without overflow checks, `num_mul` is wrapping multiplication.
+        // The multiplications that panic on overflow are built by `build_int_op_overflow_checked`.
         self.build_num_mul_wrap(dst, src1, src2, layout)
     }
 
@@ -1286,7 +2448,14 @@ impl<
     ) {
         use Builtin::Int;
 
-        match self.layout_interner.get_repr(*layout) {
+        let repr = self.layout_interner.get_repr(*layout);
+        if let LayoutRepr::Builtin(Int(int_width @ quadword_and_smaller!())) = repr {
+            if self.mul_by_power_of_two(dst, src1, src2, int_width) {
+                return;
+            }
+        }
+
+        match repr {
             LayoutRepr::Builtin(Int(
                 IntWidth::I64 | IntWidth::I32 | IntWidth::I16 | IntWidth::I8,
             )) => {
@@ -1319,19 +2488,46 @@ impl<
                 );
             }
             LayoutRepr::Builtin(Builtin::Int(IntWidth::I128 | IntWidth::U128)) => {
-                let int_width = match *layout {
-                    Layout::I128 => IntWidth::I128,
-                    Layout::U128 => IntWidth::U128,
-                    _ => unreachable!(),
-                };
+                // The lower 128 bits of the product are the same for signed and unsigned integers.
+                // They are the full product of the lower halves, plus the lower halves of the products
+                // of each lower half with the other upper half in the upper 64 bits.
+                self.load_literal_symbols(&[*src1, *src2]);
+                let (src1_offset, _) = self.storage_manager.stack_offset_and_size(src1);
+                let (src2_offset, _) = self.storage_manager.stack_offset_and_size(src2);
+                let base_offset = self.storage_manager.claim_stack_area(dst, 16);
+                let [lhs, rhs, high] =
+                    ["mul_lhs", "mul_rhs", "mul_high"].map(|name| self.debug_symbol(name));
 
-                self.build_fn_call(
-                    dst,
-                    bitcode::NUM_MUL_WRAP_INT[int_width].to_string(),
-                    &[*src1, *src2],
-                    &[*layout, *layout],
-                    layout,
+                let buf = &mut self.buf;
+                let lhs_reg = self.storage_manager.claim_general_reg(buf, &lhs);
+                let rhs_reg = self.storage_manager.claim_general_reg(buf, &rhs);
+                let high_reg = self.storage_manager.claim_general_reg(buf, &high);
+                ASM::mov_reg64_base32(buf, lhs_reg, src1_offset);
+                ASM::mov_reg64_base32(buf, rhs_reg, src2_offset);
+                ASM::umulh_reg64_reg64_reg64(
+                    buf,
+                    &mut self.storage_manager,
+                    high_reg,
+                    lhs_reg,
+                    rhs_reg,
                 );
+
+                let lhs_reg = self.storage_manager.load_to_general_reg(buf, &lhs);
+                let rhs_reg = self.storage_manager.load_to_general_reg(buf, &rhs);
+                ASM::imul_reg64_reg64_reg64(buf, lhs_reg, lhs_reg, rhs_reg);
+                ASM::mov_base32_reg64(buf, base_offset, lhs_reg);
+
+                for (lhs_offset, rhs_offset) in [(0, 8), (8, 0)] {
+                    ASM::mov_reg64_base32(buf, lhs_reg, src1_offset + lhs_offset);
+                    ASM::mov_reg64_base32(buf, rhs_reg, src2_offset + rhs_offset);
+                    ASM::imul_reg64_reg64_reg64(buf, lhs_reg, lhs_reg, rhs_reg);
+                    ASM::add_reg64_reg64_reg64(buf, high_reg, high_reg, lhs_reg);
+                }
+                ASM::mov_base32_reg64(buf, base_offset + 8, high_reg);
+
+                self.free_symbol(&lhs);
+                self.free_symbol(&rhs);
+                self.free_symbol(&high);
             }
             LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64)) => {
                 let dst_reg = self.storage_manager.claim_float_reg(&mut self.buf, dst);
@@ -1345,12 +2541,23 @@ impl<
                 let src2_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src2);
                 ASM::mul_freg32_freg32_freg32(&mut self.buf, dst_reg, src1_reg, src2_reg);
             }
-            x => todo!("NumMulWrap: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumMulWrap: layout, {:?}", x)),
         }
     }
 
     fn build_num_div(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>) {
-        match self.layout_interner.get_repr(*layout) {
+        let repr = self.layout_interner.get_repr(*layout);
+        if let LayoutRepr::Builtin(Builtin::Int(int_width @ quadword_and_smaller!())) = repr {
+            if self.div_by_power_of_two(dst, src1, src2, int_width) {
+                return;
+            }
+        }
+
+        if let LayoutRepr::Builtin(Builtin::Int(int_width @ quadword_and_smaller!())) = repr {
+            self.build_div_checks(src1, src2, int_width, true);
+        }
+
+        match repr {
             LayoutRepr::Builtin(Builtin::Int(
                 IntWidth::I64 | IntWidth::I32 | IntWidth::I16 | IntWidth::I8,
             )) => {
@@ -1401,23 +2608,41 @@ impl<
                 let src2_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src2);
                 ASM::div_freg32_freg32_freg32(&mut self.buf, dst_reg, src1_reg, src2_reg);
             }
-            x => todo!("NumDiv: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumDiv: layout, {:?}", x)),
         }
     }
 
     fn build_num_rem(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>) {
-        match self.layout_interner.get_repr(*layout) {
+        let repr = self.layout_interner.get_repr(*layout);
+        if let LayoutRepr::Builtin(Builtin::Int(int_width @ quadword_and_smaller!())) = repr {
+            // i64::MIN % -1 is just 0, that is handled below.
+            self.build_div_checks(src1, src2, int_width, false);
+        }
+
+        match repr {
             LayoutRepr::Builtin(Builtin::Int(
-                IntWidth::I64 | IntWidth::I32 | IntWidth::I16 | IntWidth::I8,
+                int_width @ (IntWidth::I64 | IntWidth::I32 | IntWidth::I16 | IntWidth::I8),
             )) => {
                 let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
                 let src1_reg = self
                     .storage_manager
                     .load_to_general_reg(&mut self.buf, src1);
-                let src2_reg = self
+                let mut src2_reg = self
                     .storage_manager
                     .load_to_general_reg(&mut self.buf, src2);
 
+                // The remainder does not depend on the sign of the divisor.
+                // Taking its absolute value avoids i64::MIN % -1, which the CPU traps on.
+                let divisor = self.debug_symbol("divisor");
+                let can_be_minus_one = !matches!(self.int_literal_value(src2), Some(x) if x != -1);
+                if int_width == IntWidth::I64 && can_be_minus_one {
+                    let divisor_reg = self
+                        .storage_manager
+                        .claim_general_reg(&mut self.buf, &divisor);
+                    ASM::abs_reg64_reg64(&mut self.buf, divisor_reg, src2_reg);
+                    src2_reg = divisor_reg;
+                }
+
                 ASM::irem_reg64_reg64_reg64(
                     &mut self.buf,
                     &mut self.storage_manager,
@@ -1425,6 +2650,7 @@ impl<
                     src1_reg,
                     src2_reg,
                 );
+                self.free_symbol(&divisor);
             }
             LayoutRepr::Builtin(Builtin::Int(
                 IntWidth::U64 | IntWidth::U32 | IntWidth::U16 | IntWidth::U8,
@@ -1445,7 +2671,7 @@ impl<
                     src2_reg,
                 );
             }
-            x => todo!("NumDiv: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumRem: layout, {:?}", x)),
         }
     }
 
@@ -1456,7 +2682,7 @@ impl<
                 let src_reg = self.storage_manager.load_to_general_reg(&mut self.buf, src);
                 ASM::neg_reg64_reg64(&mut self.buf, dst_reg, src_reg);
             }
-            x => todo!("NumNeg: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumNeg: layout, {:?}", x)),
         }
     }
 
@@ -1475,7 +2701,15 @@ impl<
     ) {
         match self.layout_interner.get_repr(*layout) {
             LayoutRepr::Builtin(Builtin::Int(quadword_and_smaller!())) => {
+                let imm_operand = self.split_imm_operand(ImmOperation::Sub, src1, src2);
                 let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+                if let Some((src, imm)) = imm_operand {
+                    let src_reg = self
+                        .storage_manager
+                        .load_to_general_reg(&mut self.buf, &src);
+                    ASM::sub_reg64_reg64_imm32(&mut self.buf, dst_reg, src_reg, imm);
+                    return;
+                }
                 let src1_reg = self
                     .storage_manager
                     .load_to_general_reg(&mut self.buf, src1);
@@ -1484,13 +2718,17 @@ impl<
                     .load_to_general_reg(&mut self.buf, src2);
                 ASM::sub_reg64_reg64_reg64(&mut self.buf, dst_reg, src1_reg, src2_reg);
             }
-            x => todo!("NumSubWrap: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, layout, format!("NumSubWrap: layout, {:?}", x)),
         }
     }
 
     fn build_eq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>) {
         let repr = self.interner().get_repr(*arg_layout);
         match repr {
+            LayoutRepr::LambdaSet(lambda_set) => {
+                // Lambda sets without captures are enums, they compare like their tag id.
+                self.build_eq(dst, src1, src2, &lambda_set.runtime_representation())
+            }
             single_register_int_builtins!() | LayoutRepr::BOOL => {
                 let width = match repr {
                     LayoutRepr::BOOL | LayoutRepr::I8 | LayoutRepr::U8 => RegisterWidth::W8,
@@ -1561,6 +2799,55 @@ impl<
                 ASM::eq_freg_freg_reg64(&mut self.buf, dst_reg, src_reg1, src_reg2, float_width)
             }
             LayoutRepr::DEC => todo!("NumEq: layout, {:?}", self.layout_interner.dbg(Layout::DEC)),
+            LayoutRepr::Builtin(Builtin::List(element))
+                if self.env.vector_instructions
+                    && self.layout_interner.stack_size(element) > 0
+                    && compares_bytewise(self.layout_interner, element) =>
+            {
+                // Compare the element bytes of both lists in one go, see `MEMEQ_HELPER`.
+                // Zero sized elements are left out, their lists have no bytes but can differ in length.
+                let element_size = self.layout_interner.stack_size(element);
+                let (src1_offset, _) = self.storage_manager.stack_offset_and_size(src1);
+                let (src2_offset, _) = self.storage_manager.stack_offset_and_size(src2);
+
+                let args = [
+                    (self.debug_symbol("memeq_ptr1"), src1_offset),
+                    (self.debug_symbol("memeq_ptr2"), src2_offset),
+                    (self.debug_symbol("memeq_size1"), src1_offset + 8),
+                    (self.debug_symbol("memeq_size2"), src2_offset + 8),
+                ];
+                for (i, (arg, offset)) in args.iter().enumerate() {
+                    let reg = self.storage_manager.claim_general_reg(&mut self.buf, arg);
+                    ASM::mov_reg64_base32(&mut self.buf, reg, *offset);
+                    if i < 2 || element_size == 1 {
+                        continue;
+                    }
+
+                    // From the length to the size in bytes.
+                    if element_size.is_power_of_two() {
+                        let shift = element_size.trailing_zeros() as u8;
+                        ASM::shl_reg64_reg64_imm8(&mut self.buf, reg, reg, shift);
+                    } else {
+                        self.storage_manager
+                            .with_tmp_general_reg(&mut self.buf, |_, buf, tmp| {
+                                ASM::mov_reg64_imm64(buf, tmp, element_size as i64);
+                                ASM::imul_reg64_reg64_reg64(buf, reg, reg, tmp);
+                            });
+                    }
+                }
+
+                let arg_symbols = args.map(|(arg, _)| arg);
+                self.build_fn_call(
+                    dst,
+                    MEMEQ_HELPER.to_string(),
+                    &arg_symbols,
+                    &[Layout::U64; 4],
+                    &Layout::BOOL,
+                );
+                for arg in &arg_symbols {
+                    self.free_symbol(arg);
+                }
+            }
             LayoutRepr::STR => {
                 // use a zig call
                 self.build_fn_call(
@@ -1620,13 +2907,17 @@ impl<
     }
 
     fn build_neq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>) {
-        match self.interner().get_repr(*arg_layout) {
+        let repr = self.interner().get_repr(*arg_layout);
+        match repr {
+            LayoutRepr::LambdaSet(lambda_set) => {
+                self.build_neq(dst, src1, src2, &lambda_set.runtime_representation())
+            }
             single_register_int_builtins!() | LayoutRepr::BOOL => {
-                let width = match *arg_layout {
-                    Layout::BOOL | Layout::I8 | Layout::U8 => RegisterWidth::W8,
-                    Layout::I16 | Layout::U16 => RegisterWidth::W16,
-                    Layout::U32 | Layout::I32 => RegisterWidth::W32,
-                    Layout::I64 | Layout::U64 => RegisterWidth::W64,
+                let width = match repr {
+                    LayoutRepr::BOOL | LayoutRepr::I8 | LayoutRepr::U8 => RegisterWidth::W8,
+                    LayoutRepr::I16 | LayoutRepr::U16 => RegisterWidth::W16,
+                    LayoutRepr::U32 | LayoutRepr::I32 => RegisterWidth::W32,
+                    LayoutRepr::I64 | LayoutRepr::U64 => RegisterWidth::W64,
                     _ => unreachable!(),
                 };
 
@@ -1691,7 +2982,7 @@ impl<
                 ASM::xor_reg64_reg64_reg64(&mut self.buf, src_reg, src_reg, dst_reg);
                 ASM::mov_reg64_reg64(&mut self.buf, dst_reg, src_reg);
             }
-            x => todo!("Not: layout, {:?}", x),
+            x => self.build_unsupported_value(dst, arg_layout, format!("Not: layout, {:?}", x)),
         }
     }
 
@@ -1749,7 +3040,9 @@ impl<
                 let src_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src);
                 ASM::mov_freg64_freg64(&mut self.buf, dst_reg, src_reg);
             }
-            (a, r) => todo!("NumToFrac: layout, arg {:?}, ret {:?}", a, r),
+            (a, r) => {
+                self.build_unsupported(format!("NumToFrac: layout, arg {:?}, ret {:?}", a, r))
+            }
         }
     }
 
@@ -1824,6 +3117,26 @@ impl<
         );
     }
 
+    fn build_num_float_to_bits(&mut self, dst: &Symbol, src: &Symbol, float_width: FloatWidth) {
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        let src_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src);
+
+        match float_width {
+            FloatWidth::F32 => ASM::mov_reg32_freg32(&mut self.buf, dst_reg, src_reg),
+            FloatWidth::F64 => ASM::mov_reg64_freg64(&mut self.buf, dst_reg, src_reg),
+        }
+    }
+
+    fn build_num_float_from_bits(&mut self, dst: &Symbol, src: &Symbol, float_width: FloatWidth) {
+        let dst_reg = self.storage_manager.claim_float_reg(&mut self.buf, dst);
+        let src_reg = self.storage_manager.load_to_general_reg(&mut self.buf, src);
+
+        match float_width {
+            FloatWidth::F32 => ASM::mov_freg32_reg32(&mut self.buf, dst_reg, src_reg),
+            FloatWidth::F64 => ASM::mov_freg64_reg64(&mut self.buf, dst_reg, src_reg),
+        }
+    }
+
     fn build_num_cmp(
         &mut self,
         dst: &Symbol,
@@ -2096,10 +3409,18 @@ impl<
 
                 self.free_symbol(&Symbol::DEV_TMP3);
             }
-            HigherOrder::ListMap2 { .. } => todo!(),
-            HigherOrder::ListMap3 { .. } => todo!(),
-            HigherOrder::ListMap4 { .. } => todo!(),
-            HigherOrder::ListSortWith { .. } => todo!(),
+            HigherOrder::ListMap2 { .. } => {
+                self.build_unsupported_value(dst, &ret_layout, "List.map2".into())
+            }
+            HigherOrder::ListMap3 { .. } => {
+                self.build_unsupported_value(dst, &ret_layout, "List.map3".into())
+            }
+            HigherOrder::ListMap4 { .. } => {
+                self.build_unsupported_value(dst, &ret_layout, "List.map4".into())
+            }
+            HigherOrder::ListSortWith { .. } => {
+                self.build_unsupported_value(dst, &ret_layout, "List.sortWith".into())
+            }
         }
     }
 
@@ -2305,20 +3626,44 @@ impl<
         let index_reg = self
             .storage_manager
             .load_to_general_reg(&mut self.buf, index);
+        if self.env.bounds_checks {
+            let len = self.debug_symbol("list_len");
+            let len_reg = self.storage_manager.claim_general_reg(&mut self.buf, &len);
+            ASM::mov_reg64_base32(&mut self.buf, len_reg, base_offset + 8);
+            self.build_panic_unless("List index out of bounds!", |buf, _, offset| {
+                let condition = IntCondition::Unsigned(CompareOperation::LessThan);
+                ASM::jcc_reg64_reg64_imm32(
+                    buf,
+                    RegisterWidth::W64,
+                    condition,
+                    index_reg,
+                    len_reg,
+                    offset,
+                )
+            });
+            self.free_symbol(&len);
+        }
         let ret_stack_size = self.layout_interner.stack_size(*ret_layout);
-        // TODO: This can be optimized with smarter instructions.
-        // Also can probably be moved into storage manager at least partly.
+        // TODO: This can probably be moved into storage manager at least partly.
         self.storage_manager.with_tmp_general_reg(
             &mut self.buf,
             |storage_manager, buf, list_ptr| {
                 ASM::mov_reg64_base32(buf, list_ptr, base_offset);
                 storage_manager.with_tmp_general_reg(buf, |storage_manager, buf, tmp| {
-                    // calculate `element_width * index`
-                    ASM::mov_reg64_imm64(buf, tmp, ret_stack_size as i64);
-                    ASM::imul_reg64_reg64_reg64(buf, tmp, tmp, index_reg);
-
-                    // add the offset to the list pointer, store in `tmp`
-                    ASM::add_reg64_reg64_reg64(buf, tmp, tmp, list_ptr);
+                    // calculate `list_ptr + element_width * index`, store in `tmp`
+                    if ret_stack_size.is_power_of_two() {
+                        let shift = ret_stack_size.trailing_zeros() as u8;
+                        if shift <= 3 {
+                            ASM::lea_reg64_base_index(buf, tmp, list_ptr, index_reg, shift, 0);
+                        } else {
+                            ASM::shl_reg64_reg64_imm8(buf, tmp, index_reg, shift);
+                            ASM::add_reg64_reg64_reg64(buf, tmp, tmp, list_ptr);
+                        }
+                    } else {
+                        ASM::mov_reg64_imm64(buf, tmp, ret_stack_size as i64);
+                        ASM::imul_reg64_reg64_reg64(buf, tmp, tmp, index_reg);
+                        ASM::add_reg64_reg64_reg64(buf, tmp, tmp, list_ptr);
+                    }
                     let element_ptr = tmp;
 
                     Self::ptr_read(
@@ -2696,6 +4041,22 @@ impl<
         index: u64,
         field_layouts: &'a [InLayout<'a>],
     ) {
+        // Reuse the register of an earlier load of the same field instead of loading it from memory again.
+        let cached = self.field_cache.insert((*structure, index), *sym);
+        if let Some(src_reg) = cached.and_then(|field| self.storage_manager.general_reg_of(&field))
+        {
+            let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, sym);
+            ASM::mov_reg64_reg64(&mut self.buf, dst_reg, src_reg);
+            return;
+        }
+        if let [field_layout] = field_layouts {
+            if !self.storage_manager.is_complex(structure) {
+                // A record with a single field has the layout of that field, so it can live in a register.
+                // The structure is then the field itself.
+                self.copy_primitive(sym, structure, field_layout);
+                return;
+            }
+        }
         self.storage_manager.load_field_at_index(
             self.layout_interner,
             sym,
@@ -2942,6 +4303,56 @@ impl<
         }
     }
 
+    fn build_expect(
+        &mut self,
+        condition: Symbol,
+        region: Region,
+        lookups: &'a [Symbol],
+        variables: &'a [LookupType],
+    ) {
+        // The report continues after the expect, so the calls in it must not clobber live values.
+        self.storage_manager
+            .push_used_caller_saved_regs_to_stack(&mut self.buf, &[]);
+        let cond_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &condition);
+        self.build_cold_path_unless(
+            |buf, storage_manager, offset| {
+                ASM::jne_reg64_imm64_imm32(buf, storage_manager, cond_reg, 0, offset)
+            },
+            |backend| {
+                backend.report_to_parent(
+                    region,
+                    condition.module_id(),
+                    lookups,
+                    variables,
+                    bitcode::NOTIFY_PARENT_EXPECT,
+                )
+            },
+        );
+    }
+
+    fn build_expect_check(&mut self, condition: Symbol) {
+        let cond_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &condition);
+        self.build_panic_unless("an expect failed!", |buf, storage_manager, offset| {
+            ASM::jne_reg64_imm64_imm32(buf, storage_manager, cond_reg, 0, offset)
+        });
+    }
+
+    fn build_dbg(&mut self, symbol: Symbol, variable: LookupType) {
+        // Like the LLVM backend, the symbol of the value takes the place of the region.
+        let region = unsafe { std::mem::transmute::<Symbol, Region>(symbol) };
+        self.report_to_parent(
+            region,
+            symbol.module_id(),
+            self.env.arena.alloc([symbol]),
+            self.env.arena.alloc([variable]),
+            bitcode::NOTIFY_PARENT_DBG,
+        );
+    }
+
     fn build_ptr_store(
         &mut self,
         sym: Symbol,
@@ -3068,6 +4479,11 @@ impl<
         let layout_interner: &mut STLayoutInterner<'a> = self.layout_interner;
         let _buf: &mut Vec<'a, u8> = &mut self.buf;
         match union_layout {
+            UnionLayout::NonRecursive([_]) => {
+                // A union with a single tag is just its payload, the tag id is always 0.
+                let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, sym);
+                ASM::mov_reg64_imm64(&mut self.buf, dst_reg, 0);
+            }
             UnionLayout::NonRecursive(tags) => {
                 self.storage_manager.load_union_tag_id_nonrecursive(
                     layout_interner,
@@ -3236,6 +4652,20 @@ impl<
         let (data_size, data_alignment) = union_layout.data_size_and_alignment(layout_interner);
 
         match union_layout {
+            UnionLayout::NonRecursive([field_layouts]) => {
+                // A union with a single tag stores no tag id, it is built like a struct of the payload.
+                debug_assert_eq!(tag_id, 0);
+                let layout = self
+                    .layout_interner
+                    .insert_direct_no_semantic(LayoutRepr::Struct(field_layouts));
+                self.storage_manager.create_struct(
+                    self.layout_interner,
+                    &mut self.buf,
+                    sym,
+                    &layout,
+                    fields,
+                );
+            }
             UnionLayout::NonRecursive(field_layouts) => {
                 let id_offset = data_size - data_alignment;
                 let base_offset = self.storage_manager.claim_stack_area(sym, data_size);
@@ -3493,8 +4923,9 @@ impl<
                 )),
             ) => {
                 let reg = self.storage_manager.claim_general_reg(&mut self.buf, sym);
-                let val = *x;
-                ASM::mov_reg64_imm64(&mut self.buf, reg, i128::from_ne_bytes(val) as i64);
+                let val = i128::from_ne_bytes(*x) as i64;
+                ASM::mov_reg64_imm64(&mut self.buf, reg, val);
+                self.storage_manager.record_constant(sym, val);
             }
             (
                 Literal::Int(bytes) | Literal::U128(bytes),
@@ -3520,12 +4951,15 @@ impl<
             }
             (Literal::Byte(x), LayoutRepr::Builtin(Builtin::Int(IntWidth::U8 | IntWidth::I8))) => {
                 let reg = self.storage_manager.claim_general_reg(&mut self.buf, sym);
-                let val = *x;
-                ASM::mov_reg64_imm64(&mut self.buf, reg, val as i64);
+                let val = *x as i64;
+                ASM::mov_reg64_imm64(&mut self.buf, reg, val);
+                self.storage_manager.record_constant(sym, val);
             }
             (Literal::Bool(x), LayoutRepr::Builtin(Builtin::Bool)) => {
                 let reg = self.storage_manager.claim_general_reg(&mut self.buf, sym);
-                ASM::mov_reg64_imm64(&mut self.buf, reg, *x as i64);
+                let val = *x as i64;
+                ASM::mov_reg64_imm64(&mut self.buf, reg, val);
+                self.storage_manager.record_constant(sym, val);
             }
             (Literal::Float(x), LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64))) => {
                 let reg = self.storage_manager.claim_float_reg(&mut self.buf, sym);
@@ -3658,12 +5092,17 @@ impl<
         src2: &Symbol,
         int_width: IntWidth,
     ) {
-        let buf = &mut self.buf;
-
         match int_width {
             IntWidth::U128 | IntWidth::I128 => todo!(),
             _ => {
+                let imm_operand = self.split_imm_operand(ImmOperation::And, src1, src2);
+                let buf = &mut self.buf;
                 let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
+                if let Some((src, imm)) = imm_operand {
+                    let src_reg = self.storage_manager.load_to_general_reg(buf, &src);
+                    ASM::and_reg64_reg64_imm32(buf, dst_reg, src_reg, imm);
+                    return;
+                }
                 let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
                 let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
                 ASM::and_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
@@ -3805,172 +5244,1434 @@ impl<
         }
     }
 
-    fn build_int_shift_right_zero_fill(
+    fn build_int_shift_right_zero_fill(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    ) {
+        let buf = &mut self.buf;
+
+        match int_width {
+            IntWidth::U128 | IntWidth::I128 => {
+                let layout = match int_width {
+                    IntWidth::I128 => Layout::I128,
+                    IntWidth::U128 => Layout::U128,
+                    _ => unreachable!(),
+                };
+
+                self.build_fn_call(
+                    dst,
+                    bitcode::NUM_SHIFT_RIGHT_ZERO_FILL[int_width].to_string(),
+                    &[*src1, *src2],
+                    &[layout, layout],
+                    &layout,
+                );
+            }
+            _ => {
+                let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
+                let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
+                let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
+
+                ASM::shr_reg64_reg64_reg64(
+                    buf,
+                    &mut self.storage_manager,
+                    dst_reg,
+                    src1_reg,
+                    src2_reg,
+                );
+            }
+        }
+    }
+
+    fn preload_symbols(&mut self, syms: &[Symbol]) {
+        for sym in syms {
+            let Some(layout) = self.layout_map.get(sym) else {
+                continue;
+            };
+            let is_int = matches!(
+                self.layout_interner.get_repr(*layout),
+                single_register_integers!() | pointer_layouts!()
+            );
+            if is_int && self.storage_manager.can_preload(sym) {
+                self.storage_manager.load_to_general_reg(&mut self.buf, sym);
+            }
+        }
+    }
+
+    fn build_int_op_overflow_checked(
+        &mut self,
+        dst: &Symbol,
+        lowlevel: &LowLevel,
+        src1: &Symbol,
+        src2: &Symbol,
+        layout: &InLayout<'a>,
+    ) {
+        let Some(int_width) = layout.try_int_width() else {
+            internal_error!("overflow checks are only built for integers")
+        };
+        let message = match lowlevel {
+            LowLevel::NumAdd => "integer addition overflowed!",
+            LowLevel::NumSub => "integer subtraction overflowed!",
+            LowLevel::NumMul => "integer multiplication overflowed!",
+            x => internal_error!("{:?} has no overflow check", x),
+        };
+        let bits = int_width.stack_size() as u8 * 8;
+        if *lowlevel == LowLevel::NumMul && bits == 64 {
+            let high = self.debug_symbol("overflow_check");
+            let fits = self.debug_symbol("overflow_check");
+            self.build_mul_wide64(dst, &high, &fits, src1, src2, int_width.is_signed());
+
+            let high_reg = self
+                .storage_manager
+                .load_to_general_reg(&mut self.buf, &high);
+            let fits_reg = self
+                .storage_manager
+                .load_to_general_reg(&mut self.buf, &fits);
+            self.build_panic_unless(message, |buf, _, offset| {
+                let condition = IntCondition::Equal;
+                ASM::jcc_reg64_reg64_imm32(
+                    buf,
+                    RegisterWidth::W64,
+                    condition,
+                    high_reg,
+                    fits_reg,
+                    offset,
+                )
+            });
+            self.free_symbol(&high);
+            self.free_symbol(&fits);
+            return;
+        }
+
+        let src1_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, src1);
+        let src2_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, src2);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        let check1 = self.debug_symbol("overflow_check");
+        let check1_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &check1);
+        let check2 = self.debug_symbol("overflow_check");
+        let check2_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &check2);
+
+        let buf = &mut self.buf;
+        let (condition, lhs, rhs) = if bits < 64 {
+            // Upper bits of a register are not defined for smaller integers.
+            // So extend the operands to 64 bits, where the result can not overflow.
+            // The result overflowed if truncating it to `bits` changes it.
+            let shift = 64 - bits;
+            let extend = |buf: &mut Vec<'a, u8>, dst, src| {
+                ASM::shl_reg64_reg64_imm8(buf, dst, src, shift);
+                if int_width.is_signed() {
+                    ASM::sar_reg64_reg64_imm8(buf, dst, dst, shift);
+                } else {
+                    ASM::shr_reg64_reg64_imm8(buf, dst, dst, shift);
+                }
+            };
+            extend(buf, check1_reg, src1_reg);
+            extend(buf, check2_reg, src2_reg);
+            match lowlevel {
+                LowLevel::NumAdd => {
+                    ASM::add_reg64_reg64_reg64(buf, dst_reg, check1_reg, check2_reg)
+                }
+                LowLevel::NumSub => {
+                    ASM::sub_reg64_reg64_reg64(buf, dst_reg, check1_reg, check2_reg)
+                }
+                _ => ASM::imul_reg64_reg64_reg64(buf, dst_reg, check1_reg, check2_reg),
+            }
+            extend(buf, check1_reg, dst_reg);
+            (IntCondition::Equal, dst_reg, check1_reg)
+        } else {
+            match (lowlevel, int_width.is_signed()) {
+                (LowLevel::NumAdd, false) => {
+                    // An unsigned sum that overflows wraps below both operands.
+                    ASM::add_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
+                    let condition = IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual);
+                    (condition, dst_reg, src1_reg)
+                }
+                (LowLevel::NumSub, false) => {
+                    ASM::sub_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
+                    let condition = IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual);
+                    (condition, src1_reg, src2_reg)
+                }
+                (_, _) => {
+                    // A signed result overflowed if its sign differs from the sign both operands of a sum have,
+                    // or from the sign of the left operand of a difference where the operand signs differ.
+                    // Either way, the sign bit of `check1` is set.
+                    if *lowlevel == LowLevel::NumAdd {
+                        ASM::add_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
+                        ASM::xor_reg64_reg64_reg64(buf, check1_reg, src1_reg, dst_reg);
+                        ASM::xor_reg64_reg64_reg64(buf, check2_reg, src2_reg, dst_reg);
+                    } else {
+                        ASM::sub_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
+                        ASM::xor_reg64_reg64_reg64(buf, check1_reg, src1_reg, src2_reg);
+                        ASM::xor_reg64_reg64_reg64(buf, check2_reg, src1_reg, dst_reg);
+                    }
+                    ASM::and_reg64_reg64_reg64(buf, check1_reg, check1_reg, check2_reg);
+                    ASM::mov_reg64_imm64(buf, check2_reg, 0);
+                    let condition = IntCondition::Signed(CompareOperation::GreaterThanOrEqual);
+                    (condition, check1_reg, check2_reg)
+                }
+            }
+        };
+        self.build_panic_unless(message, |buf, _, offset| {
+            ASM::jcc_reg64_reg64_imm32(buf, RegisterWidth::W64, condition, lhs, rhs, offset)
+        });
+        self.free_symbol(&check1);
+        self.free_symbol(&check2);
+    }
+
+    fn build_inline_builtin(
+        &mut self,
+        sym: &Symbol,
+        lowlevel: &LowLevel,
+        args: &'a [Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        match lowlevel {
+            LowLevel::NumPowInt => {
+                let [base, exponent] = args else {
+                    return false;
+                };
+                let layout = arg_layouts[0];
+                let small_int =
+                    matches!(layout.try_int_width(), Some(width) if width.stack_size() <= 8);
+                match self.int_literal_value(exponent) {
+                    Some(exponent)
+                        if small_int && (2..=MAX_INLINE_POW_EXPONENT).contains(&exponent) =>
+                    {
+                        self.load_literal_symbols(&[*base]);
+                        if exponent == 2 {
+                            self.build_num_mul(sym, base, base, &layout);
+                        } else {
+                            // x^3 is x^2 * x and x^4 is x^2 * x^2.
+                            let square = self.debug_symbol("square");
+                            self.build_num_mul(&square, base, base, &layout);
+                            let other = if exponent == 3 { *base } else { square };
+                            self.build_num_mul(sym, &square, &other, &layout);
+                            self.free_symbol(&square);
+                        }
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            LowLevel::NumRound if arg_layouts[0] == Layout::F64 && *ret_layout == Layout::I64 => {
+                self.load_literal_symbols(args);
+                let buf = &mut self.buf;
+                let src_reg = self.storage_manager.load_to_float_reg(buf, &args[0]);
+                let dst_reg = self.storage_manager.claim_general_reg(buf, sym);
+                ASM::round_reg64_freg64(buf, &mut self.storage_manager, dst_reg, src_reg);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn build_num_sqrt(&mut self, dst: Symbol, src: Symbol, float_width: FloatWidth) {
+        let buf = &mut self.buf;
+
+        let dst_reg = self.storage_manager.claim_float_reg(buf, &dst);
+        let src_reg = self.storage_manager.load_to_float_reg(buf, &src);
+
+        match float_width {
+            FloatWidth::F32 => ASM::sqrt_freg32_freg32(buf, dst_reg, src_reg),
+            FloatWidth::F64 => ASM::sqrt_freg64_freg64(buf, dst_reg, src_reg),
+        }
+    }
+
+    fn build_num_count_leading_zero_bits(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        int_width: IntWidth,
+    ) {
+        let value_reg = self.load_zero_extended(src, int_width);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        ASM::count_leading_zeros_reg64(
+            &mut self.buf,
+            &mut self.storage_manager,
+            dst_reg,
+            value_reg,
+            self.env.bit_count_instructions,
+        );
+
+        // The zero extension added leading zeros.
+        let bits = 8 * int_width.stack_size();
+        if bits < 64 {
+            ASM::sub_reg64_reg64_imm32(&mut self.buf, dst_reg, dst_reg, 64 - bits as i32);
+        }
+        self.free_symbol(&Symbol::DEV_TMP);
+    }
+
+    fn build_num_count_trailing_zero_bits(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        int_width: IntWidth,
+    ) {
+        let value_reg = self.load_zero_extended(src, int_width);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+
+        // Setting the bit right above the int stops the count at its width for 0.
+        let bits = 8 * int_width.stack_size();
+        if bits < 64 {
+            ASM::mov_reg64_imm64(&mut self.buf, dst_reg, 1 << bits);
+            ASM::or_reg64_reg64_reg64(&mut self.buf, value_reg, value_reg, dst_reg);
+        }
+        ASM::count_trailing_zeros_reg64(
+            &mut self.buf,
+            &mut self.storage_manager,
+            dst_reg,
+            value_reg,
+            self.env.bit_count_instructions,
+        );
+        self.free_symbol(&Symbol::DEV_TMP);
+    }
+
+    fn build_num_count_one_bits(&mut self, dst: &Symbol, src: &Symbol, int_width: IntWidth) {
+        let value_reg = self.load_zero_extended(src, int_width);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        ASM::count_one_bits_reg64(
+            &mut self.buf,
+            &mut self.storage_manager,
+            dst_reg,
+            value_reg,
+            self.env.bit_count_instructions,
+        );
+        self.free_symbol(&Symbol::DEV_TMP);
+    }
+
+    fn build_num_to_int_checked(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        source: IntWidth,
+        target: IntWidth,
+        return_layout: &InLayout<'a>,
+    ) {
+        fn bounds(width: IntWidth) -> (i128, i128) {
+            let bits = 8 * width.stack_size();
+            if width.is_signed() {
+                (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+            } else {
+                (0, (1 << bits) - 1)
+            }
+        }
+        let (source_min, source_max) = bounds(source);
+        let (target_min, target_max) = bounds(target);
+
+        let buf = &mut self.buf;
+
+        let struct_size = self.layout_interner.stack_size(*return_layout);
+        let base_offset = self.storage_manager.claim_stack_area(dst, struct_size);
+
+        let value_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP);
+        let out_of_bounds_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP2);
+        let bound_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP3);
+        let cmp_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP4);
+        let src_reg = self.storage_manager.load_to_general_reg(buf, src);
+
+        // Extend the source to 64 bits, so it can be compared against the bounds of the target.
+        let source_register_width =
+            RegisterWidth::try_from_layout(LayoutRepr::Builtin(Builtin::Int(source))).unwrap();
+        if source.is_signed() {
+            ASM::movsx_reg_reg(buf, source_register_width, value_reg, src_reg);
+        } else {
+            ASM::movzx_reg_reg(buf, source_register_width, value_reg, src_reg);
+        }
+
+        // Only the bounds of the target within the range of the source need a check,
+        // so converting to a wider int with the same sign does not compare at all.
+        ASM::mov_reg64_imm64(buf, out_of_bounds_reg, 0);
+        if target_max < source_max {
+            ASM::mov_reg64_imm64(buf, bound_reg, target_max as i64);
+            if source.is_signed() {
+                ASM::signed_compare_reg64(
+                    buf,
+                    RegisterWidth::W64,
+                    CompareOperation::GreaterThan,
+                    cmp_reg,
+                    value_reg,
+                    bound_reg,
+                );
+            } else {
+                ASM::unsigned_compare_reg64(
+                    buf,
+                    RegisterWidth::W64,
+                    CompareOperation::GreaterThan,
+                    cmp_reg,
+                    value_reg,
+                    bound_reg,
+                );
+            }
+            ASM::or_reg64_reg64_reg64(buf, out_of_bounds_reg, out_of_bounds_reg, cmp_reg);
+        }
+        if target_min > source_min {
+            // Only a signed source goes below the minimum of a target.
+            ASM::mov_reg64_imm64(buf, bound_reg, target_min as i64);
+            ASM::signed_compare_reg64(
+                buf,
+                RegisterWidth::W64,
+                CompareOperation::LessThan,
+                cmp_reg,
+                value_reg,
+                bound_reg,
+            );
+            ASM::or_reg64_reg64_reg64(buf, out_of_bounds_reg, out_of_bounds_reg, cmp_reg);
+        }
+
+        // The value goes first, the flag right after it.
+        match target.stack_size() {
+            8 => ASM::mov_base32_reg64(buf, base_offset, value_reg),
+            4 => ASM::mov_base32_reg32(buf, base_offset, value_reg),
+            2 => ASM::mov_base32_reg16(buf, base_offset, value_reg),
+            1 => ASM::mov_base32_reg8(buf, base_offset, value_reg),
+            size => internal_error!("NumToIntChecked to an int of {} bytes", size),
+        }
+        ASM::mov_base32_reg8(
+            buf,
+            base_offset + target.stack_size() as i32,
+            out_of_bounds_reg,
+        );
+
+        self.free_symbol(&Symbol::DEV_TMP);
+        self.free_symbol(&Symbol::DEV_TMP2);
+        self.free_symbol(&Symbol::DEV_TMP3);
+        self.free_symbol(&Symbol::DEV_TMP4);
+    }
+
+    fn build_num_int_cast(
+        &mut self,
+        dst: &Symbol,
+        src: &Symbol,
+        source: IntWidth,
+        target: IntWidth,
+    ) {
+        use IntWidth::*;
+
+        let buf = &mut self.buf;
+
+        match (source, target) {
+            (U128, U64) => {
+                let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
+
+                let (offset, _size) = self.storage_manager.stack_offset_and_size(src);
+
+                ASM::mov_reg64_base32(buf, dst_reg, offset + 8);
+
+                return;
+            }
+            (U64, U128) => {
+                let src_reg = self.storage_manager.load_to_general_reg(buf, src);
+
+                let base_offset = self.storage_manager.claim_stack_area(dst, 16);
+
+                let tmp = Symbol::DEV_TMP;
+                let tmp_reg = self.storage_manager.claim_general_reg(buf, &tmp);
+
+                // move a zero into the lower 8 bytes
+                ASM::mov_reg64_imm64(buf, tmp_reg, 0x0);
+                ASM::mov_base32_reg64(buf, base_offset, tmp_reg);
+
+                ASM::mov_base32_reg64(buf, base_offset + 8, src_reg);
+
+                self.free_symbol(&tmp);
+
+                return;
+            }
+
+            _ => {}
+        }
+
+        let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
+        let src_reg = self.storage_manager.load_to_general_reg(buf, src);
+
+        if source.stack_size() == target.stack_size() {
+            match source.stack_size() {
+                8 => ASM::mov_reg64_reg64(buf, dst_reg, src_reg),
+                4 => ASM::mov_reg32_reg32(buf, dst_reg, src_reg),
+                2 => ASM::mov_reg16_reg16(buf, dst_reg, src_reg),
+                1 => ASM::mov_reg8_reg8(buf, dst_reg, src_reg),
+                _ => todo!("int cast from {source:?} to {target:?}"),
+            }
+        } else {
+            match (source, target) {
+                // -- CASTING UP --
+                (I8 | U8, U16 | U32 | U64) => {
+                    // zero  out the register
+                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
+
+                    // move the 8-bit integer
+                    ASM::mov_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg);
+                }
+                (U16, U32 | U64) => {
+                    // zero  out the register
+                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
+
+                    // move the 16-bit integer
+                    ASM::mov_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg);
+                }
+                (U32, U64) => {
+                    // zero  out the register
+                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
+
+                    // move the 32-bit integer
+                    ASM::mov_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg);
+                }
+                (I8, I16 | I32 | I64) => {
+                    ASM::movsx_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg)
+                }
+                (I16, I32 | I64) => ASM::movsx_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg),
+                (I32, I64) => ASM::movsx_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg),
+                // -- CASTING DOWN --
+                (U64 | I64, I32 | U32) => {
+                    // move as a 32-bit integer (leaving any other bits behind)
+                    ASM::mov_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg);
+                }
+                (U64 | I64 | U32 | I32, I16 | U16) => {
+                    // move as a 16-bit integer (leaving any other bits behind)
+                    ASM::mov_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg);
+                }
+                (U64 | I64 | U32 | I32 | U16 | I16, I8 | U8) => {
+                    // move as an 8-bit integer (leaving any other bits behind)
+                    ASM::mov_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg);
+                }
+                _ => todo!("int cast from {source:?} to {target:?}"),
+            }
+        }
+    }
+}
+
+/// This impl block is for ir related instructions that need backend specific information.
+/// For example, loading a symbol for doing a computation.
+impl<
+        'a,
+        'r,
+        FloatReg: RegTrait,
+        GeneralReg: RegTrait,
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    > Backend64Bit<'a, 'r, GeneralReg, FloatReg, ASM, CC>
+{
+    /// Builds a switch with one branch and a default that only pass different values to the same join point.
+    /// The value is picked with a conditional move instead of branches, followed by a single jump.
+    /// Returns false without building anything if the switch does not have that shape.
+    fn build_select_switch(
+        &mut self,
+        cond_symbol: &Symbol,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        let [(val, _, branch)] = branches else {
+            return false;
+        };
+        let (Some(then_value), Some(else_value)) =
+            (select_branch(branch), select_branch(default_branch.1))
+        else {
+            return false;
+        };
+        let imm = match i32::try_from(*val) {
+            Ok(imm) if ASM::supports_imm32_operand(ImmOperation::Compare, imm as i64) => imm,
+            _ => return false,
+        };
+        if then_value.id != else_value.id {
+            return false;
+        }
+        let value_layout = |value: &SelectValue<'a>| match value.literal {
+            Some((_, layout)) => Some(layout),
+            None => self.layout_map.get(&value.symbol).copied(),
+        };
+        let layout = match (value_layout(&then_value), value_layout(&else_value)) {
+            (Some(then_layout), Some(else_layout)) if then_layout == else_layout => then_layout,
+            _ => return false,
+        };
+        if !matches!(
+            self.layout_interner.get_repr(layout),
+            single_register_integers!()
+        ) {
+            return false;
+        }
+
+        for value in [&then_value, &else_value] {
+            match value.literal {
+                Some((lit, layout)) => {
+                    self.set_layout_map(value.symbol, &layout);
+                    self.load_literal(&value.symbol, &layout, lit);
+                }
+                None => self.load_literal_symbols(&[value.symbol]),
+            }
+        }
+        let cond_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, cond_symbol);
+        let then_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &then_value.symbol);
+        let else_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &else_value.symbol);
+        let selected = self.debug_symbol("selected");
+        self.set_layout_map(selected, &layout);
+        let dst_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &selected);
+        ASM::select_reg64_reg64_reg64_imm32(
+            &mut self.buf,
+            dst_reg,
+            cond_reg,
+            imm,
+            then_reg,
+            else_reg,
+        );
+
+        // Everything that dies in either branch is dead once the value is picked.
+        for stmt in [branch, default_branch.1] {
+            self.free_branch_symbols(stmt);
+            self.free_symbols(stmt);
+            if let Stmt::Let(_, _, _, following) = stmt {
+                self.free_symbols(following);
+            }
+        }
+
+        self.build_jump(&then_value.id, &[selected], &[layout], ret_layout);
+        self.free_symbol(&selected);
+        true
+    }
+
+    /// Builds a switch as a jump to the entry for the value of cond_symbol in a table of jumps to the branches.
+    /// Values outside of the table and values without a branch go to the default branch.
+    /// Returns false without building anything if there are too few branches or their values are too sparse.
+    fn build_jump_table_switch(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        cond_symbol: &Symbol,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        if branches.len() < JUMP_TABLE_MIN_BRANCHES {
+            return false;
+        }
+        // At most half of the entries may go to the default branch.
+        let max_value = branches.iter().map(|(val, _, _)| *val).max().unwrap();
+        if max_value >= 2 * branches.len() as u64 {
+            return false;
+        }
+        let entries = max_value + 1;
+
+        let cond_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, cond_symbol);
+        let tmp_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &Symbol::DEV_TMP);
+        ASM::mov_reg64_imm64(&mut self.buf, tmp_reg, entries as i64);
+        // Compared as unsigned 64 bit values, so no value outside of the table gets past this.
+        let default_jump_location = self.buf.len();
+        let default_jump_base = ASM::jcc_reg64_reg64_imm32(
+            &mut self.buf,
+            RegisterWidth::W64,
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual),
+            cond_reg,
+            tmp_reg,
+            0,
+        );
+        ASM::jmp_table_reg64(&mut self.buf, tmp_reg, cond_reg);
+        self.free_symbol(&Symbol::DEV_TMP);
+        self.has_jump_table = true;
+
+        let mut entry_jumps = bumpalo::vec![in self.env.arena];
+        for _ in 0..entries {
+            let jmp_location = self.buf.len();
+            let base_offset = ASM::jmp_imm32(&mut self.buf, 0x1234_5678);
+            entry_jumps.push((jmp_location, base_offset));
+        }
+
+        // Like in build_branches, every branch starts with the storage from before the switch.
+        let mut base_storage = self.storage_manager.clone();
+        let base_literal_map = self.literal_map.clone();
+        let mut max_branch_stack_size = 0;
+        let mut ret_jumps = bumpalo::vec![in self.env.arena];
+        let mut targets = bumpalo::vec![in self.env.arena; None; entries as usize];
+        for (val, _branch_info, stmt) in branches {
+            targets[*val as usize] = Some(self.buf.len());
+
+            self.storage_manager = base_storage.clone();
+            self.literal_map = base_literal_map.clone();
+            self.field_cache.clear();
+            self.free_branch_symbols(stmt);
+            self.build_stmt(layout_ids, stmt, ret_layout);
+
+            let jmp_location = self.buf.len();
+            let jmp_offset = ASM::jmp_imm32(&mut self.buf, 0x1234_5678);
+            ret_jumps.push((jmp_location, jmp_offset));
+
+            max_branch_stack_size =
+                std::cmp::max(max_branch_stack_size, self.storage_manager.stack_size());
+            base_storage.update_fn_call_stack_size(self.storage_manager.fn_call_stack_size());
+        }
+        self.storage_manager = base_storage;
+        self.literal_map = base_literal_map;
+        self.field_cache.clear();
+        self.storage_manager
+            .update_stack_size(max_branch_stack_size);
+        let default_offset = self.buf.len();
+        let (_branch_info, stmt) = default_branch;
+        self.free_branch_symbols(stmt);
+        self.build_stmt(layout_ids, stmt, ret_layout);
+
+        let mut tmp = bumpalo::vec![in self.env.arena];
+        let ret_offset = self.buf.len();
+        for (jmp_location, start_offset) in ret_jumps {
+            self.update_jmp_imm32_offset(
+                &mut tmp,
+                jmp_location as u64,
+                start_offset as u64,
+                ret_offset as u64,
+            );
+        }
+        for ((jmp_location, start_offset), target) in entry_jumps.into_iter().zip(targets) {
+            self.update_jmp_imm32_offset(
+                &mut tmp,
+                jmp_location as u64,
+                start_offset as u64,
+                target.unwrap_or(default_offset) as u64,
+            );
+        }
+
+        tmp.clear();
+        ASM::jcc_reg64_reg64_imm32(
+            &mut tmp,
+            RegisterWidth::W64,
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual),
+            cond_reg,
+            tmp_reg,
+            (default_offset - default_jump_base) as i32,
+        );
+        self.buf[default_jump_location..][..tmp.len()].copy_from_slice(&tmp);
+        self.jumps
+            .push((default_jump_base as u64, default_offset as u64));
+        true
+    }
+
+    /// Builds the branches of a switch followed by its default branch.
+    /// `jump_past` emits the jump over a branch that is taken when the switch does not match the value of the branch.
+    /// It must always generate the same number of bytes, see `Assembler::jne_reg64_imm64_imm32`.
+    fn build_branches(
+        &mut self,
+        layout_ids: &mut LayoutIds<'a>,
+        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
+        ret_layout: &InLayout<'a>,
+        jump_past: impl Fn(
+            &mut Vec<'a, u8>,
+            &mut StorageManager<'a, 'r, GeneralReg, FloatReg, ASM, CC>,
+            u64,
+            i32,
+        ) -> usize,
+    ) {
+        // Switches are a little complex due to keeping track of jumps.
+        // In general I am trying to not have to loop over things multiple times or waste memory.
+        // The basic plan is to make jumps to nowhere and then correct them once we know the correct address.
+
+        // this state is updated destructively in the branches. We don't want the branches to
+        // influence each other, so we must clone here.
+        let mut base_storage = self.storage_manager.clone();
+        let base_literal_map = self.literal_map.clone();
+
+        let mut max_branch_stack_size = 0;
+        let mut ret_jumps = bumpalo::vec![in self.env.arena];
+        let mut tmp = bumpalo::vec![in self.env.arena];
+
+        // The values of a switch are distinct, so the branches can be tested in any order.
+        // Unlikely branches go last, so the common cases are tested first and fall through into their code.
+        let mut ordered: Vec<_> = branches.iter().collect_in(self.env.arena);
+        ordered.sort_by_key(|(_, branch_info, stmt)| is_unlikely_branch(branch_info, stmt));
+        for (val, _branch_info, stmt) in ordered {
+            tmp.clear();
+            // Create jump to next branch.
+            // Since we don't know the offset yet, set it to 0 and overwrite later.
+            let jne_location = self.buf.len();
+            let start_offset = jump_past(&mut self.buf, &mut self.storage_manager, *val, 0);
+
+            // Build all statements in this branch. Using storage as from before any branch.
+            self.storage_manager = base_storage.clone();
+            self.literal_map = base_literal_map.clone();
+            self.field_cache.clear();
+            self.free_branch_symbols(stmt);
+            self.build_stmt(layout_ids, stmt, ret_layout);
+
+            // Build unconditional jump to the end of this switch.
+            // Since we don't know the offset yet, set it to 0 and overwrite later.
+            let jmp_location = self.buf.len();
+            let jmp_offset = ASM::jmp_imm32(&mut self.buf, 0x1234_5678);
+            ret_jumps.push((jmp_location, jmp_offset));
+
+            // Overwrite the original jne with the correct offset.
+            let end_offset = self.buf.len();
+            let jne_offset = end_offset - start_offset;
+            jump_past(&mut tmp, &mut self.storage_manager, *val, jne_offset as i32);
+            for (i, byte) in tmp.iter().enumerate() {
+                self.buf[jne_location + i] = *byte;
+            }
+            self.jumps.push((start_offset as u64, end_offset as u64));
+
+            // Update important storage information to avoid overwrites.
+            max_branch_stack_size =
+                std::cmp::max(max_branch_stack_size, self.storage_manager.stack_size());
+            base_storage.update_fn_call_stack_size(self.storage_manager.fn_call_stack_size());
+        }
+        self.storage_manager = base_storage;
+        self.literal_map = base_literal_map;
+        self.field_cache.clear();
+        self.storage_manager
+            .update_stack_size(max_branch_stack_size);
+        let (_branch_info, stmt) = default_branch;
+        self.free_branch_symbols(stmt);
+        self.build_stmt(layout_ids, stmt, ret_layout);
+
+        // Update all return jumps to jump past the default case.
+        let ret_offset = self.buf.len();
+        for (jmp_location, start_offset) in ret_jumps.into_iter() {
+            self.update_jmp_imm32_offset(
+                &mut tmp,
+                jmp_location as u64,
+                start_offset as u64,
+                ret_offset as u64,
+            );
+        }
+    }
+
+    /// Calls the stack overflow helper once the stack pointer is below the limit in `STACK_LIMIT_SYMBOL`.
+    /// The first proc that runs sets the limit to `stack_check_size` bytes below its stack pointer.
+    /// This runs right after the stack is set up, so it only uses registers that can not hold arguments.
+    fn build_stack_check(
+        buf: &mut Vec<'a, u8>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
+        jumps: &mut std::vec::Vec<(u64, u64)>,
+        stack_check_size: u32,
+    ) {
+        let mut scratch = Self::prologue_scratch_regs();
+        let (limit_ptr, limit, stack_ptr) = match (scratch.next(), scratch.next(), scratch.next()) {
+            (Some(limit_ptr), Some(limit), Some(stack_ptr)) => (limit_ptr, limit, stack_ptr),
+            _ => internal_error!("stack checks need 3 caller saved regs that are not params"),
+        };
+
+        ASM::data_pointer(buf, relocs, STACK_LIMIT_SYMBOL.to_string(), 0, limit_ptr);
+        ASM::mov_reg64_mem64_offset32(buf, limit, limit_ptr, 0);
+
+        let mut set_limit = bumpalo::vec![in buf.bump()];
+        ASM::add_reg64_reg64_imm32(&mut set_limit, stack_ptr, CC::STACK_PTR_REG, 0);
+        ASM::mov_reg64_imm64(&mut set_limit, limit, stack_check_size as i64);
+        ASM::sub_reg64_reg64_reg64(&mut set_limit, limit, stack_ptr, limit);
+        ASM::mov_mem64_offset32_reg64(&mut set_limit, limit_ptr, 0, limit);
+
+        ASM::mov_reg64_imm64(buf, stack_ptr, 0);
+        let base = ASM::jcc_reg64_reg64_imm32(
+            buf,
+            RegisterWidth::W64,
+            IntCondition::NotEqual,
+            limit,
+            stack_ptr,
+            set_limit.len() as i32,
+        );
+        buf.extend(set_limit);
+        jumps.push((base as u64, buf.len() as u64));
+
+        // The helper never returns, so it may clobber anything.
+        let mut call = bumpalo::vec![in buf.bump()];
+        ASM::call(&mut call, &mut bumpalo::vec![in buf.bump()], String::new());
+        ASM::add_reg64_reg64_imm32(buf, stack_ptr, CC::STACK_PTR_REG, 0);
+        let base = ASM::jcc_reg64_reg64_imm32(
+            buf,
+            RegisterWidth::W64,
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual),
+            stack_ptr,
+            limit,
+            call.len() as i32,
+        );
+        ASM::call(buf, relocs, STACK_OVERFLOW_HELPER.to_string());
+        jumps.push((base as u64, buf.len() as u64));
+    }
+
+    /// Fills the `stack_size` bytes of stack storage below the base pointer with `STACK_POISON`.
+    fn poison_stack_frame(buf: &mut Vec<'a, u8>, stack_size: u32) {
+        if stack_size == 0 {
+            return;
+        }
+        let poison = match Self::prologue_scratch_regs().next() {
+            Some(reg) => reg,
+            None => internal_error!("stack poisoning needs a caller saved reg that is not a param"),
+        };
+        ASM::mov_reg64_imm64(buf, poison, i64::from_ne_bytes([STACK_POISON; 8]));
+        // Stack storage is always claimed in multiples of 8 bytes.
+        for offset in (8..=stack_size as i32).step_by(8) {
+            ASM::mov_base32_reg64(buf, -offset, poison);
+        }
+    }
+
+    /// The regs the prologue may clobber, they are caller saved and do not hold arguments.
+    fn prologue_scratch_regs() -> impl Iterator<Item = GeneralReg> {
+        CC::GENERAL_DEFAULT_FREE_REGS
+            .iter()
+            .copied()
+            .filter(|reg| CC::general_caller_saved(reg) && !CC::GENERAL_PARAM_REGS.contains(reg))
+    }
+
+    /// Builds a call to roc_panic with `message`, which `skip_panic` jumps over.
+    /// `skip_panic` emits the jump with the given offset and returns the base offset of the jump.
+    /// It must always emit the same number of bytes, so the jump can be patched once the panic is built.
+    fn build_panic_unless(
+        &mut self,
+        message: &'a str,
+        skip_panic: impl Fn(
+            &mut Vec<'a, u8>,
+            &mut StorageManager<'a, 'r, GeneralReg, FloatReg, ASM, CC>,
+            i32,
+        ) -> usize,
+    ) {
+        self.build_cold_path_unless(skip_panic, |backend| {
+            backend.roc_panic_with_message(message, CrashTag::Roc)
+        });
+    }
+
+    /// Builds `cold_path`, which `skip` jumps over. `skip` works like the one of `build_panic_unless`.
+    /// Whatever `cold_path` changes in the storage is undone after it. So if it continues after the jump target,
+    /// it must not disturb the registers of live symbols, which means freeing the caller saved ones before any calls.
+    fn build_cold_path_unless(
+        &mut self,
+        skip: impl Fn(
+            &mut Vec<'a, u8>,
+            &mut StorageManager<'a, 'r, GeneralReg, FloatReg, ASM, CC>,
+            i32,
+        ) -> usize,
+        cold_path: impl FnOnce(&mut Self),
+    ) {
+        let jump_location = self.buf.len();
+        let start_offset = skip(&mut self.buf, &mut self.storage_manager, 0);
+
+        let base_storage = self.storage_manager.clone();
+        let base_literal_map = self.literal_map.clone();
+        cold_path(self);
+        let cold_storage = std::mem::replace(&mut self.storage_manager, base_storage);
+        self.storage_manager.keep_frame_requirements(&cold_storage);
+        self.literal_map = base_literal_map;
+
+        let end_offset = self.buf.len();
+        let mut tmp = bumpalo::vec![in self.env.arena];
+        let offset = (end_offset - start_offset) as i32;
+        skip(&mut tmp, &mut self.storage_manager, offset);
+        self.buf[jump_location..jump_location + tmp.len()].copy_from_slice(&tmp);
+        self.jumps.push((start_offset as u64, end_offset as u64));
+    }
+
+    /// Writes a frame to the memory shared with the parent process, and then calls `notify` with it.
+    /// The frame has the layout the LLVM backend uses: the region and module id, then the offset and variable
+    /// of every lookup, then the values of the lookups, and then the bytes of the strings among them.
+    fn report_to_parent(
+        &mut self,
+        region: Region,
+        module_id: ModuleId,
+        lookups: &'a [Symbol],
+        variables: &'a [LookupType],
+        notify: &str,
+    ) {
+        const HEADER_SIZE: i32 = 3 * 4;
+        const LOOKUP_ENTRY_SIZE: i32 = 8 + 4;
+
+        let mut value_offsets = bumpalo::vec![in self.env.arena];
+        let mut frame_size = HEADER_SIZE + LOOKUP_ENTRY_SIZE * lookups.len() as i32;
+        for lookup in lookups {
+            let layout = match self.layout_map.get(lookup) {
+                Some(layout) => *layout,
+                None => internal_error!("the lookup, {:?}, has no known layout", lookup),
+            };
+            if layout != Layout::STR && self.layout_interner.contains_refcounted(layout) {
+                // These have to be cloned into the shared memory along with their heap allocations.
+                todo!("reporting refcounted values other than strings in the dev backend");
+            }
+            value_offsets.push(frame_size);
+            frame_size += self.layout_interner.stack_size(layout) as i32;
+        }
+
+        let shared_memory = self.debug_symbol("shared_memory");
+        self.build_fn_call(
+            &shared_memory,
+            bitcode::UTILS_EXPECT_FAILED_START_SHARED_FILE.to_string(),
+            &[],
+            &[],
+            &Layout::OPAQUE_PTR,
+        );
+
+        // The shared memory starts with the number of frames and the offset of the next one.
+        // The bytes of strings go after the frame, at `extra_offset`.
+        let frame_offset = self.debug_symbol("frame_offset");
+        let frame = self.debug_symbol("frame");
+        let mut extra_offset = self.debug_symbol("extra_offset");
+        let ptr_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &shared_memory);
+        let offset_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &frame_offset);
+        let frame_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &frame);
+        let extra_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &extra_offset);
+        ASM::mov_reg64_mem64_offset32(&mut self.buf, offset_reg, ptr_reg, 8);
+        ASM::add_reg64_reg64_reg64(&mut self.buf, frame_reg, ptr_reg, offset_reg);
+        ASM::add_reg64_reg64_imm32(&mut self.buf, extra_reg, offset_reg, frame_size);
+
+        let module_id: u32 = unsafe { std::mem::transmute(module_id) };
+        let header = [region.start().offset, region.end().offset, module_id];
+        let variables = variables.iter().map(|variable| variable.index());
+        self.storage_manager.with_tmp_general_reg(
+            &mut self.buf,
+            |storage_manager, buf, tmp_reg| {
+                for (i, value) in header.into_iter().enumerate() {
+                    ASM::mov_reg64_imm64(buf, tmp_reg, value as i64);
+                    ASM::mov_mem32_offset32_reg32(buf, frame_reg, 4 * i as i32, tmp_reg);
+                }
+
+                // The entries are not 8 byte aligned, so their address is computed up front.
+                storage_manager.with_tmp_general_reg(buf, |_, buf, entry_reg| {
+                    for (i, (variable, value_offset)) in
+                        variables.zip(value_offsets.iter()).enumerate()
+                    {
+                        let entry = HEADER_SIZE + LOOKUP_ENTRY_SIZE * i as i32;
+                        ASM::add_reg64_reg64_imm32(buf, entry_reg, frame_reg, entry);
+                        ASM::add_reg64_reg64_imm32(buf, tmp_reg, offset_reg, *value_offset);
+                        ASM::mov_mem64_offset32_reg64(buf, entry_reg, 0, tmp_reg);
+                        ASM::mov_reg64_imm64(buf, tmp_reg, variable as i64);
+                        ASM::mov_mem32_offset32_reg32(buf, entry_reg, 8, tmp_reg);
+                    }
+                });
+            },
+        );
+
+        for (lookup, value_offset) in lookups.iter().zip(value_offsets) {
+            let layout = self.layout_map[lookup];
+            if layout == Layout::STR {
+                // The string is written with the offset of its bytes in place of the pointer to them.
+                let str_offset = self.debug_symbol("str_offset");
+                let offset_reg = self
+                    .storage_manager
+                    .load_to_general_reg(&mut self.buf, &frame_offset);
+                let str_offset_reg = self
+                    .storage_manager
+                    .claim_general_reg(&mut self.buf, &str_offset);
+                ASM::add_reg64_reg64_imm32(&mut self.buf, str_offset_reg, offset_reg, value_offset);
+
+                let next_extra_offset = self.debug_symbol("extra_offset");
+                self.build_fn_call(
+                    &next_extra_offset,
+                    bitcode::STR_CLONE_TO.to_string(),
+                    &[*lookup, shared_memory, str_offset, extra_offset],
+                    &[Layout::STR, Layout::OPAQUE_PTR, Layout::U64, Layout::U64],
+                    &Layout::U64,
+                );
+                self.free_symbol(&str_offset);
+                self.free_symbol(&extra_offset);
+                extra_offset = next_extra_offset;
+                continue;
+            }
+
+            let stack_size = self.layout_interner.stack_size(layout) as u64;
+            let repr = self.layout_interner.get_repr(layout);
+            let value_ptr = self.debug_symbol("lookup_ptr");
+            let frame_reg = self
+                .storage_manager
+                .load_to_general_reg(&mut self.buf, &frame);
+            let value_reg = self
+                .storage_manager
+                .claim_general_reg(&mut self.buf, &value_ptr);
+            ASM::add_reg64_reg64_imm32(&mut self.buf, value_reg, frame_reg, value_offset);
+            Self::ptr_write(
+                &mut self.buf,
+                &mut self.storage_manager,
+                self.layout_interner,
+                value_reg,
+                0,
+                stack_size,
+                repr,
+                *lookup,
+            );
+            self.free_symbol(&value_ptr);
+        }
+        self.free_symbol(&frame_offset);
+        self.free_symbol(&frame);
+
+        let ptr_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &shared_memory);
+        let extra_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &extra_offset);
+        ASM::mov_mem64_offset32_reg64(&mut self.buf, ptr_reg, 8, extra_reg);
+        self.free_symbol(&extra_offset);
+        self.storage_manager
+            .with_tmp_general_reg(&mut self.buf, |_, buf, count_reg| {
+                ASM::mov_reg64_mem64_offset32(buf, count_reg, ptr_reg, 0);
+                ASM::add_reg64_reg64_imm32(buf, count_reg, count_reg, 1);
+                ASM::mov_mem64_offset32_reg64(buf, ptr_reg, 0, count_reg);
+            });
+
+        self.build_fn_call(
+            &Symbol::DEV_TMP,
+            notify.to_string(),
+            &[shared_memory],
+            &[Layout::OPAQUE_PTR],
+            &Layout::UNIT,
+        );
+        self.free_symbol(&Symbol::DEV_TMP);
+        self.free_symbol(&shared_memory);
+    }
+
+    /// Crashes with roc_panic before an integer division or remainder of src1 by src2 that has no result.
+    /// That is a division by zero, and with `check_overflow` an i64::MIN divided by -1, whose quotient does not fit an I64.
+    /// Otherwise the CPU would trap on these.
+    fn build_div_checks(
+        &mut self,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+        check_overflow: bool,
+    ) {
+        if matches!(self.int_literal_value(src2), Some(divisor) if divisor != 0 && divisor != -1) {
+            return;
+        }
+        let register_width = match int_width.stack_size() {
+            1 => RegisterWidth::W8,
+            2 => RegisterWidth::W16,
+            4 => RegisterWidth::W32,
+            _ => RegisterWidth::W64,
+        };
+        let src2_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, src2);
+        let check = self.debug_symbol("div_check");
+        let check_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &check);
+        ASM::mov_reg64_imm64(&mut self.buf, check_reg, 0);
+        self.build_panic_unless("integer division by zero!", |buf, _, offset| {
+            let condition = IntCondition::NotEqual;
+            ASM::jcc_reg64_reg64_imm32(buf, register_width, condition, src2_reg, check_reg, offset)
+        });
+
+        if check_overflow && int_width == IntWidth::I64 {
+            let src1_reg = self
+                .storage_manager
+                .load_to_general_reg(&mut self.buf, src1);
+            let plus_one = self.debug_symbol("div_check");
+            let plus_one_reg = self
+                .storage_manager
+                .claim_general_reg(&mut self.buf, &plus_one);
+            // `check` is `(src1 ^ i64::MIN) | (src2 + 1)`, which is only zero for i64::MIN / -1.
+            ASM::mov_reg64_imm64(&mut self.buf, check_reg, i64::MIN);
+            ASM::xor_reg64_reg64_reg64(&mut self.buf, check_reg, check_reg, src1_reg);
+            ASM::add_reg64_reg64_imm32(&mut self.buf, plus_one_reg, src2_reg, 1);
+            ASM::or_reg64_reg64_reg64(&mut self.buf, check_reg, check_reg, plus_one_reg);
+            self.build_panic_unless("integer division overflowed!", |buf, storage, offset| {
+                ASM::jne_reg64_imm64_imm32(buf, storage, check_reg, 0, offset)
+            });
+            self.free_symbol(&plus_one);
+        }
+        self.free_symbol(&check);
+    }
+
+    /// The value of sym, if it is an integer literal or a constant.
+    fn int_literal_value(&self, sym: &Symbol) -> Option<i64> {
+        match self.literal_map.get(sym) {
+            Some((lit, _)) => {
+                // See `load_literal_symbols` for why the literal is behind a pointer.
+                match unsafe { &**lit } {
+                    Literal::Int(bytes) => Some(i128::from_ne_bytes(*bytes) as i64),
+                    Literal::Byte(x) => Some(*x as i64),
+                    Literal::Bool(x) => Some(*x as i64),
+                    _ => None,
+                }
+            }
+            None => self.storage_manager.constant_value(sym),
+        }
+    }
+
+    /// Copies the primitive in src into a register of its own for dst.
+    fn copy_primitive(&mut self, dst: &Symbol, src: &Symbol, layout: &InLayout<'a>) {
+        match self.layout_interner.get_repr(*layout) {
+            LayoutRepr::Builtin(Builtin::Float(_)) => {
+                let dst_reg = self.storage_manager.claim_float_reg(&mut self.buf, dst);
+                let src_reg = self.storage_manager.load_to_float_reg(&mut self.buf, src);
+                ASM::mov_freg64_freg64(&mut self.buf, dst_reg, src_reg);
+            }
+            _ => {
+                let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+                let src_reg = self.storage_manager.load_to_general_reg(&mut self.buf, src);
+                ASM::mov_reg64_reg64(&mut self.buf, dst_reg, src_reg);
+            }
+        }
+    }
+
+    /// The value of sym, if it is an integer literal that can be the immediate operand of `operation`.
+    fn imm_operand(&self, operation: ImmOperation, sym: &Symbol) -> Option<i32> {
+        if self.env.dev_opt_level == DevOptLevel::Zero {
+            return None;
+        }
+        let value = self.int_literal_value(sym)?;
+        if ASM::supports_imm32_operand(operation, value) {
+            Some(value as i32)
+        } else {
+            None
+        }
+    }
+
+    /// Finds an operand of a binary integer op that can be an immediate.
+    /// Returns the other operand, which is needed in a register, and the immediate.
+    /// Literal operands that are not used as the immediate are loaded, the immediate is never loaded.
+    fn split_imm_operand(
         &mut self,
-        dst: &Symbol,
+        operation: ImmOperation,
         src1: &Symbol,
         src2: &Symbol,
-        int_width: IntWidth,
-    ) {
-        let buf = &mut self.buf;
-
-        match int_width {
-            IntWidth::U128 | IntWidth::I128 => {
-                let layout = match int_width {
-                    IntWidth::I128 => Layout::I128,
-                    IntWidth::U128 => Layout::U128,
-                    _ => unreachable!(),
-                };
-
-                self.build_fn_call(
-                    dst,
-                    bitcode::NUM_SHIFT_RIGHT_ZERO_FILL[int_width].to_string(),
-                    &[*src1, *src2],
-                    &[layout, layout],
-                    &layout,
-                );
-            }
-            _ => {
-                let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
-                let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
-                let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
-
-                ASM::shr_reg64_reg64_reg64(
-                    buf,
-                    &mut self.storage_manager,
-                    dst_reg,
-                    src1_reg,
-                    src2_reg,
-                );
+    ) -> Option<(Symbol, i32)> {
+        let split = match self.imm_operand(operation, src2) {
+            Some(imm) => Some((*src1, imm)),
+            None if operation.is_commutative() => {
+                self.imm_operand(operation, src1).map(|imm| (*src2, imm))
             }
+            None => None,
+        };
+        match split {
+            Some((src, _)) => self.load_literal_symbols(&[src]),
+            None => self.load_literal_symbols(&[*src1, *src2]),
         }
+        split
     }
 
-    fn build_num_sqrt(&mut self, dst: Symbol, src: Symbol, float_width: FloatWidth) {
-        let buf = &mut self.buf;
-
-        let dst_reg = self.storage_manager.claim_float_reg(buf, &dst);
-        let src_reg = self.storage_manager.load_to_float_reg(buf, &src);
-
-        match float_width {
-            FloatWidth::F32 => ASM::sqrt_freg32_freg32(buf, dst_reg, src_reg),
-            FloatWidth::F64 => ASM::sqrt_freg64_freg64(buf, dst_reg, src_reg),
+    /// If sym is an integer literal that is a power of two, the shift that multiplies or divides by it.
+    fn power_of_two_shift(&self, sym: &Symbol, signed: bool) -> Option<u8> {
+        if self.env.dev_opt_level == DevOptLevel::Zero {
+            return None;
+        }
+        let value = self.int_literal_value(sym)?;
+        if signed && value <= 0 {
+            return None;
+        }
+        let value = value as u64;
+        if value.is_power_of_two() {
+            Some(value.trailing_zeros() as u8)
+        } else {
+            None
         }
     }
 
-    fn build_num_int_cast(
+    /// Builds the lower 64 bits of `src1 * src2` into `dst`, which are the same for signed and unsigned integers,
+    /// and the upper 64 bits into `high`. Into `fits` goes what `high` is when the product fits in 64 bits:
+    /// copies of the sign bit of `dst` for signed integers, and zero for unsigned ones.
+    fn build_mul_wide64(
         &mut self,
         dst: &Symbol,
-        src: &Symbol,
-        source: IntWidth,
-        target: IntWidth,
+        high: &Symbol,
+        fits: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        signed: bool,
     ) {
-        use IntWidth::*;
-
         let buf = &mut self.buf;
+        let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
+        let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
+        let high_reg = self.storage_manager.claim_general_reg(buf, high);
+        let storage_manager = &mut self.storage_manager;
+        if signed {
+            ASM::smulh_reg64_reg64_reg64(buf, storage_manager, high_reg, src1_reg, src2_reg);
+        } else {
+            ASM::umulh_reg64_reg64_reg64(buf, storage_manager, high_reg, src1_reg, src2_reg);
+        }
 
-        match (source, target) {
-            (U128, U64) => {
-                let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
-
-                let (offset, _size) = self.storage_manager.stack_offset_and_size(src);
-
-                ASM::mov_reg64_base32(buf, dst_reg, offset + 8);
+        let src1_reg = self.storage_manager.load_to_general_reg(buf, src1);
+        let src2_reg = self.storage_manager.load_to_general_reg(buf, src2);
+        let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
+        ASM::imul_reg64_reg64_reg64(buf, dst_reg, src1_reg, src2_reg);
+        let fits_reg = self.storage_manager.claim_general_reg(buf, fits);
+        if signed {
+            ASM::sar_reg64_reg64_imm8(buf, fits_reg, dst_reg, 63);
+        } else {
+            ASM::mov_reg64_imm64(buf, fits_reg, 0);
+        }
+    }
 
-                return;
-            }
-            (U64, U128) => {
-                let src_reg = self.storage_manager.load_to_general_reg(buf, src);
+    /// Builds `dst = src1 * src2` as a left shift if one operand is a power of two.
+    /// Returns false without building anything otherwise, after loading any literal operands.
+    fn mul_by_power_of_two(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    ) -> bool {
+        let signed = int_width.is_signed();
+        let split = match self.power_of_two_shift(src2, signed) {
+            Some(shift) => Some((*src1, shift)),
+            None => self
+                .power_of_two_shift(src1, signed)
+                .map(|shift| (*src2, shift)),
+        };
+        let Some((src, shift)) = split else {
+            self.load_literal_symbols(&[*src1, *src2]);
+            return false;
+        };
 
-                let base_offset = self.storage_manager.claim_stack_area(dst, 16);
+        self.load_literal_symbols(&[src]);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        let src_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, &src);
+        ASM::shl_reg64_reg64_imm8(&mut self.buf, dst_reg, src_reg, shift);
+        true
+    }
 
-                let tmp = Symbol::DEV_TMP;
-                let tmp_reg = self.storage_manager.claim_general_reg(buf, &tmp);
+    /// Builds `dst = src1 / src2` as a right shift if src2 is a power of two.
+    /// Returns false without building anything otherwise, after loading any literal operands.
+    fn div_by_power_of_two(
+        &mut self,
+        dst: &Symbol,
+        src1: &Symbol,
+        src2: &Symbol,
+        int_width: IntWidth,
+    ) -> bool {
+        // Smaller values are divided as 64 bit values, which only works if they are extended.
+        // That is not guaranteed for the upper bits of the registers, so only full width values are shifted.
+        let shift = match int_width {
+            IntWidth::I64 => self.power_of_two_shift(src2, true),
+            IntWidth::U64 => self.power_of_two_shift(src2, false),
+            _ => None,
+        };
+        let Some(shift) = shift else {
+            self.load_literal_symbols(&[*src1, *src2]);
+            return false;
+        };
 
-                // move a zero into the lower 8 bytes
-                ASM::mov_reg64_imm64(buf, tmp_reg, 0x0);
-                ASM::mov_base32_reg64(buf, base_offset, tmp_reg);
+        self.load_literal_symbols(&[*src1]);
+        let dst_reg = self.storage_manager.claim_general_reg(&mut self.buf, dst);
+        let src_reg = self
+            .storage_manager
+            .load_to_general_reg(&mut self.buf, src1);
+        if int_width == IntWidth::U64 {
+            ASM::shr_reg64_reg64_imm8(&mut self.buf, dst_reg, src_reg, shift);
+        } else if shift == 0 {
+            ASM::mov_reg64_reg64(&mut self.buf, dst_reg, src_reg);
+        } else {
+            // An arithmetic shift rounds towards negative infinity, but division rounds towards zero.
+            // Adding `2^shift - 1` to negative values first fixes that up.
+            let tmp_reg = self
+                .storage_manager
+                .claim_general_reg(&mut self.buf, &Symbol::DEV_TMP);
+            ASM::sar_reg64_reg64_imm8(&mut self.buf, tmp_reg, src_reg, 63);
+            ASM::shr_reg64_reg64_imm8(&mut self.buf, tmp_reg, tmp_reg, 64 - shift);
+            ASM::add_reg64_reg64_reg64(&mut self.buf, tmp_reg, tmp_reg, src_reg);
+            ASM::sar_reg64_reg64_imm8(&mut self.buf, dst_reg, tmp_reg, shift);
+            self.free_symbol(&Symbol::DEV_TMP);
+        }
+        true
+    }
 
-                ASM::mov_base32_reg64(buf, base_offset + 8, src_reg);
+    /// Loads src zero extended to 64 bits into the register of `Symbol::DEV_TMP`, which the caller frees.
+    /// The upper bits of the register of an int smaller than that are not guaranteed to be zero.
+    fn load_zero_extended(&mut self, src: &Symbol, int_width: IntWidth) -> GeneralReg {
+        let value_reg = self
+            .storage_manager
+            .claim_general_reg(&mut self.buf, &Symbol::DEV_TMP);
+        let src_reg = self.storage_manager.load_to_general_reg(&mut self.buf, src);
+        let width =
+            RegisterWidth::try_from_layout(LayoutRepr::Builtin(Builtin::Int(int_width))).unwrap();
+        ASM::movzx_reg_reg(&mut self.buf, width, value_reg, src_reg);
+        value_reg
+    }
 
-                self.free_symbol(&tmp);
+    /// Builds `dst = src1 op src2` for ints of at most 64 bits, clamped to the bounds of the int where it overflows.
+    /// `unsigned_bound` is what the unsigned op is clamped to where `cmov_if_unsigned_overflow` moves,
+    /// the signed one is clamped towards the sign of src1.
+    #[allow(clippy::too_many_arguments)]
+    fn build_int_saturated(
+        &mut self,
+        dst: Symbol,
+        src1: Symbol,
+        src2: Symbol,
+        int_width: IntWidth,
+        op: fn(&mut Vec<'a, u8>, GeneralReg, GeneralReg, GeneralReg),
+        cmov_if_unsigned_overflow: fn(&mut Vec<'a, u8>, GeneralReg, GeneralReg),
+        unsigned_bound: i64,
+    ) {
+        let buf = &mut self.buf;
 
-                return;
-            }
+        // Shifting the operands into the upper bits makes the flags of the 64 bit op
+        // match those of an op at the width of the int.
+        let shift = 64 - 8 * int_width.stack_size() as u8;
 
-            _ => {}
+        let lhs_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP);
+        let rhs_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP2);
+        let bound_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP3);
+        let result_reg = self
+            .storage_manager
+            .claim_general_reg(buf, &Symbol::DEV_TMP4);
+        let src1_reg = self.storage_manager.load_to_general_reg(buf, &src1);
+        let src2_reg = self.storage_manager.load_to_general_reg(buf, &src2);
+        ASM::shl_reg64_reg64_imm8(buf, lhs_reg, src1_reg, shift);
+        ASM::shl_reg64_reg64_imm8(buf, rhs_reg, src2_reg, shift);
+
+        // The bound is ready before the op, nothing between the op and the move may change the flags.
+        if int_width.is_signed() {
+            // All ones or zeros from the sign of src1, flipped into the min or max.
+            ASM::sar_reg64_reg64_imm8(buf, bound_reg, lhs_reg, 63);
+            ASM::mov_reg64_imm64(buf, result_reg, i64::MAX);
+            ASM::xor_reg64_reg64_reg64(buf, bound_reg, bound_reg, result_reg);
+            op(buf, result_reg, lhs_reg, rhs_reg);
+            ASM::cmov_if_overflow_reg64_reg64(buf, result_reg, bound_reg);
+        } else {
+            ASM::mov_reg64_imm64(buf, bound_reg, unsigned_bound);
+            op(buf, result_reg, lhs_reg, rhs_reg);
+            cmov_if_unsigned_overflow(buf, result_reg, bound_reg);
         }
 
-        let dst_reg = self.storage_manager.claim_general_reg(buf, dst);
-        let src_reg = self.storage_manager.load_to_general_reg(buf, src);
-
-        if source.stack_size() == target.stack_size() {
-            match source.stack_size() {
-                8 => ASM::mov_reg64_reg64(buf, dst_reg, src_reg),
-                4 => ASM::mov_reg32_reg32(buf, dst_reg, src_reg),
-                2 => ASM::mov_reg16_reg16(buf, dst_reg, src_reg),
-                1 => ASM::mov_reg8_reg8(buf, dst_reg, src_reg),
-                _ => todo!("int cast from {source:?} to {target:?}"),
-            }
+        let dst_reg = self.storage_manager.claim_general_reg(buf, &dst);
+        if int_width.is_signed() {
+            ASM::sar_reg64_reg64_imm8(buf, dst_reg, result_reg, shift);
         } else {
-            match (source, target) {
-                // -- CASTING UP --
-                (I8 | U8, U16 | U32 | U64) => {
-                    // zero  out the register
-                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
-
-                    // move the 8-bit integer
-                    ASM::mov_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg);
-                }
-                (U16, U32 | U64) => {
-                    // zero  out the register
-                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
-
-                    // move the 16-bit integer
-                    ASM::mov_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg);
-                }
-                (U32, U64) => {
-                    // zero  out the register
-                    ASM::xor_reg64_reg64_reg64(buf, dst_reg, dst_reg, dst_reg);
-
-                    // move the 32-bit integer
-                    ASM::mov_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg);
-                }
-                (I8, I16 | I32 | I64) => {
-                    ASM::movsx_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg)
-                }
-                (I16, I32 | I64) => ASM::movsx_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg),
-                (I32, I64) => ASM::movsx_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg),
-                // -- CASTING DOWN --
-                (U64 | I64, I32 | U32) => {
-                    // move as a 32-bit integer (leaving any other bits behind)
-                    ASM::mov_reg_reg(buf, RegisterWidth::W32, dst_reg, src_reg);
-                }
-                (U64 | I64 | U32 | I32, I16 | U16) => {
-                    // move as a 16-bit integer (leaving any other bits behind)
-                    ASM::mov_reg_reg(buf, RegisterWidth::W16, dst_reg, src_reg);
-                }
-                (U64 | I64 | U32 | I32 | U16 | I16, I8 | U8) => {
-                    // move as an 8-bit integer (leaving any other bits behind)
-                    ASM::mov_reg_reg(buf, RegisterWidth::W8, dst_reg, src_reg);
-                }
-                _ => todo!("int cast from {source:?} to {target:?}"),
-            }
+            ASM::shr_reg64_reg64_imm8(buf, dst_reg, result_reg, shift);
         }
+
+        self.free_symbol(&Symbol::DEV_TMP);
+        self.free_symbol(&Symbol::DEV_TMP2);
+        self.free_symbol(&Symbol::DEV_TMP3);
+        self.free_symbol(&Symbol::DEV_TMP4);
     }
-}
 
-/// This impl block is for ir related instructions that need backend specific information.
-/// For example, loading a symbol for doing a computation.
-impl<
-        'a,
-        'r,
-        FloatReg: RegTrait,
-        GeneralReg: RegTrait,
-        ASM: Assembler<GeneralReg, FloatReg>,
-        CC: CallConv<GeneralReg, FloatReg, ASM>,
-    > Backend64Bit<'a, 'r, GeneralReg, FloatReg, ASM, CC>
-{
     fn clear_tag_id(&mut self, ptr_reg: GeneralReg) -> (Symbol, GeneralReg) {
         let unmasked_symbol = self.debug_symbol("unmasked");
         let unmasked_reg = self
@@ -3994,12 +6695,9 @@ impl<
     ) {
         match self.interner().get_repr(*arg_layout) {
             single_register_integers!() => {
+                let imm_operand = self.split_imm_operand(ImmOperation::Compare, src1, src2);
                 let buf = &mut self.buf;
 
-                let dst = self.storage_manager.claim_general_reg(buf, dst);
-                let src1 = self.storage_manager.load_to_general_reg(buf, src1);
-                let src2 = self.storage_manager.load_to_general_reg(buf, src2);
-
                 let int_width = arg_layout.try_int_width().unwrap();
                 let register_width = match int_width.stack_size() {
                     8 => RegisterWidth::W64,
@@ -4009,6 +6707,20 @@ impl<
                     _ => unreachable!(),
                 };
 
+                let dst = self.storage_manager.claim_general_reg(buf, dst);
+                if let Some((src, imm)) = imm_operand {
+                    let src = self.storage_manager.load_to_general_reg(buf, &src);
+                    let condition = if int_width.is_signed() {
+                        IntCondition::Signed(op)
+                    } else {
+                        IntCondition::Unsigned(op)
+                    };
+                    ASM::compare_reg64_imm32(buf, register_width, condition, dst, src, imm);
+                    return;
+                }
+                let src1 = self.storage_manager.load_to_general_reg(buf, src1);
+                let src2 = self.storage_manager.load_to_general_reg(buf, src2);
+
                 if int_width.is_signed() {
                     ASM::signed_compare_reg64(buf, register_width, op, dst, src1, src2)
                 } else {
@@ -4035,7 +6747,11 @@ impl<
                     op,
                 );
             }
-            x => todo!("NumLt: layout, {:?}", x),
+            x => self.build_unsupported_value(
+                dst,
+                &Layout::BOOL,
+                format!("{:?}: layout, {:?}", op, x),
+            ),
         }
     }
 
@@ -4099,6 +6815,8 @@ impl<
         );
 
         self.buf[jmp_start_index..][..tmp.len()].copy_from_slice(tmp.as_slice());
+        self.jumps
+            .push((jmp_end_index as u64, destination_index as u64));
 
         // figure out what register was actually used
         let dst_reg = self
@@ -4200,8 +6918,21 @@ impl<
             LayoutRepr::Builtin(builtin) => match builtin {
                 Builtin::Int(int_width) => match int_width {
                     IntWidth::I128 | IntWidth::U128 => {
-                        // can we treat this as 2 u64's?
-                        todo!()
+                        // These live on the stack, copy them like a struct of 2 u64's.
+                        storage_manager.with_tmp_general_reg(
+                            buf,
+                            |storage_manager, buf, tmp_reg| {
+                                Self::unbox_to_stack(
+                                    buf,
+                                    storage_manager,
+                                    dst,
+                                    16,
+                                    ptr_reg,
+                                    tmp_reg,
+                                    offset,
+                                );
+                            },
+                        );
                     }
                     IntWidth::I64 | IntWidth::U64 => {
                         let dst_reg = storage_manager.claim_general_reg(buf, &dst);
@@ -4239,6 +6970,17 @@ impl<
                 }
                 Builtin::Decimal => {
                     // same as 128-bit integer
+                    storage_manager.with_tmp_general_reg(buf, |storage_manager, buf, tmp_reg| {
+                        Self::unbox_to_stack(
+                            buf,
+                            storage_manager,
+                            dst,
+                            16,
+                            ptr_reg,
+                            tmp_reg,
+                            offset,
+                        );
+                    });
                 }
                 Builtin::Str | Builtin::List(_) => {
                     storage_manager.with_tmp_general_reg(buf, |storage_manager, buf, tmp_reg| {
@@ -4336,10 +7078,18 @@ impl<
                 let sym_reg = storage_manager.load_to_general_reg(buf, &value);
                 ASM::mov_mem8_offset32_reg8(buf, ptr_reg, element_offset, sym_reg);
             }
-            LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64 | FloatWidth::F32)) => {
+            LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64)) => {
                 let sym_reg = storage_manager.load_to_float_reg(buf, &value);
                 ASM::movesd_mem64_offset32_freg64(buf, ptr_reg, element_offset, sym_reg);
             }
+            LayoutRepr::Builtin(Builtin::Float(FloatWidth::F32)) => {
+                // Only 4 bytes may be written, the bytes after the value can belong to something else.
+                let sym_reg = storage_manager.load_to_float_reg(buf, &value);
+                storage_manager.with_tmp_general_reg(buf, |_storage_manager, buf, tmp_reg| {
+                    ASM::mov_reg32_freg32(buf, tmp_reg, sym_reg);
+                    ASM::mov_mem32_offset32_reg32(buf, ptr_reg, element_offset, tmp_reg);
+                });
+            }
             pointer_layouts!() => {
                 let sym_reg = storage_manager.load_to_general_reg(buf, &value);
                 ASM::mov_mem64_offset32_reg64(buf, ptr_reg, element_offset, sym_reg);
@@ -4431,6 +7181,39 @@ impl<
         }
     }
 
+    /// Puts the arguments of a function call in their param regs or on top of the stack.
+    fn store_fn_call_args(
+        &mut self,
+        dst: &Symbol,
+        args: &[Symbol],
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) {
+        // Save used caller saved regs.
+        // Arguments that are already in their param reg and die with this call can stay there.
+        let mut args_in_place = bumpalo::vec![in self.env.arena];
+        for (index, sym) in args.iter().enumerate() {
+            if let Some(reg) =
+                CC::general_param_reg_for_arg(self.layout_interner, arg_layouts, index, ret_layout)
+            {
+                args_in_place.push((*sym, reg));
+            }
+        }
+        self.storage_manager
+            .push_used_caller_saved_regs_to_stack(&mut self.buf, &args_in_place);
+
+        // Put values in param regs or on top of the stack.
+        CC::store_args(
+            &mut self.buf,
+            &mut self.storage_manager,
+            self.layout_interner,
+            dst,
+            args,
+            arg_layouts,
+            ret_layout,
+        );
+    }
+
     /// Updates a jump instruction to a new offset and returns the number of bytes written.
     fn update_jmp_imm32_offset(
         &mut self,
@@ -4442,6 +7225,7 @@ impl<
         tmp.clear();
         let jmp_offset = target_offset as i32 - base_offset as i32;
         ASM::jmp_imm32(tmp, jmp_offset);
+        self.jumps.push((base_offset, target_offset));
         for (i, byte) in tmp.iter().enumerate() {
             self.buf[jmp_location as usize + i] = *byte;
         }
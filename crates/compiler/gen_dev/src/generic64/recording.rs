@@ -0,0 +1,1360 @@
+//! An assembler that writes down the instructions it is asked to emit, along with their bytes.
+//! It wraps another assembler, so lowering code can be tested by the instructions it asks for,
+//! without matching the machine code byte for byte.
+//!
+//! Only the instructions emitted through the `Assembler` trait are recorded.
+//! The prologue and epilogue of procs are written by the calling convention,
+//! which calls the target assembler directly, so they do not show up.
+
+use crate::generic64::{
+    storage::StorageManager, Assembler, CallConv, CompareOperation, DecodedInstruction,
+    ImmOperation, IntCondition, RegTrait, RegisterWidth,
+};
+use crate::Relocation;
+use bumpalo::collections::Vec;
+use roc_builtins::bitcode::FloatWidth;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+
+thread_local! {
+    static RECORDED: RefCell<Option<std::vec::Vec<String>>> = RefCell::new(None);
+}
+
+fn record(instruction: impl FnOnce() -> String) {
+    RECORDED.with(|recorded| {
+        if let Some(instructions) = recorded.borrow_mut().as_mut() {
+            instructions.push(instruction());
+        }
+    });
+}
+
+/// Runs `f` and returns the instructions that `RecordingAssembler`s emitted on this thread meanwhile.
+/// Each one is the name of the `Assembler` method followed by its operands, like `ret` or
+/// `mov_reg64_imm64 RAX, 1`.
+pub fn record_instructions<R>(f: impl FnOnce() -> R) -> (R, std::vec::Vec<String>) {
+    let outer = RECORDED.with(|recorded| recorded.replace(Some(std::vec::Vec::new())));
+    let result = f();
+    let instructions = RECORDED.with(|recorded| recorded.replace(outer));
+    (result, instructions.unwrap_or_default())
+}
+
+/// Emits the instructions of `A`, and writes them down while inside of `record_instructions`.
+#[derive(Copy, Clone)]
+pub struct RecordingAssembler<A> {
+    phantom: PhantomData<A>,
+}
+
+impl<GeneralReg: RegTrait, FloatReg: RegTrait, A: Assembler<GeneralReg, FloatReg>>
+    Assembler<GeneralReg, FloatReg> for RecordingAssembler<A>
+{
+    fn abs_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("abs_reg64_reg64 {:?}, {:?}", dst, src));
+        A::abs_reg64_reg64(buf, dst, src)
+    }
+
+    fn abs_freg64_freg64<'a>(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
+        dst: FloatReg,
+        src: FloatReg,
+    ) {
+        record(|| format!("abs_freg64_freg64 {:?}, {:?}", dst, src));
+        A::abs_freg64_freg64(buf, relocs, dst, src)
+    }
+
+    fn add_reg64_reg64_imm32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src1: GeneralReg, imm32: i32) {
+        record(|| format!("add_reg64_reg64_imm32 {:?}, {:?}, {:?}", dst, src1, imm32));
+        A::add_reg64_reg64_imm32(buf, dst, src1, imm32)
+    }
+
+    fn add_freg32_freg32_freg32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("add_freg32_freg32_freg32 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::add_freg32_freg32_freg32(buf, dst, src1, src2)
+    }
+
+    fn add_freg64_freg64_freg64(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("add_freg64_freg64_freg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::add_freg64_freg64_freg64(buf, dst, src1, src2)
+    }
+
+    fn add_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("add_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::add_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn and_reg64_reg64_imm32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src1: GeneralReg, imm32: i32) {
+        record(|| format!("and_reg64_reg64_imm32 {:?}, {:?}, {:?}", dst, src1, imm32));
+        A::and_reg64_reg64_imm32(buf, dst, src1, imm32)
+    }
+
+    fn and_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("and_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::and_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn or_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("or_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::or_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn xor_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("xor_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::xor_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn shl_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("shl_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::shl_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn count_leading_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("count_leading_zeros_reg64 {:?}, {:?}", dst, src));
+        A::count_leading_zeros_reg64(buf, storage_manager, dst, src, bit_count_instructions)
+    }
+
+    fn count_trailing_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("count_trailing_zeros_reg64 {:?}, {:?}", dst, src));
+        A::count_trailing_zeros_reg64(buf, storage_manager, dst, src, bit_count_instructions)
+    }
+
+    fn count_one_bits_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("count_one_bits_reg64 {:?}, {:?}", dst, src));
+        A::count_one_bits_reg64(buf, storage_manager, dst, src, bit_count_instructions)
+    }
+
+    fn shr_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("shr_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::shr_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn sar_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("sar_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::sar_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn shl_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8) {
+        record(|| format!("shl_reg64_reg64_imm8 {:?}, {:?}, {:?}", dst, src, imm8));
+        A::shl_reg64_reg64_imm8(buf, dst, src, imm8)
+    }
+
+    fn shr_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8) {
+        record(|| format!("shr_reg64_reg64_imm8 {:?}, {:?}, {:?}", dst, src, imm8));
+        A::shr_reg64_reg64_imm8(buf, dst, src, imm8)
+    }
+
+    fn sar_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg, imm8: u8) {
+        record(|| format!("sar_reg64_reg64_imm8 {:?}, {:?}, {:?}", dst, src, imm8));
+        A::sar_reg64_reg64_imm8(buf, dst, src, imm8)
+    }
+
+    fn lea_reg64_base_index(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        base: GeneralReg,
+        index: GeneralReg,
+        shift: u8,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "lea_reg64_base_index {:?}, {:?}, {:?}, {:?}, {:?}",
+                dst, base, index, shift, offset
+            )
+        });
+        A::lea_reg64_base_index(buf, dst, base, index, shift, offset)
+    }
+
+    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, fn_name: String) {
+        record(|| format!("call {:?}", fn_name));
+        A::call(buf, relocs, fn_name)
+    }
+
+    fn call_slot(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, slot_name: String) {
+        record(|| format!("call_slot {:?}", slot_name));
+        A::call_slot(buf, relocs, slot_name)
+    }
+
+    fn function_pointer(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        fn_name: String,
+        dst: GeneralReg,
+    ) {
+        record(|| format!("function_pointer {:?}, {:?}", fn_name, dst));
+        A::function_pointer(buf, relocs, fn_name, dst)
+    }
+
+    fn data_pointer(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        data_name: String,
+        addend: i64,
+        dst: GeneralReg,
+    ) {
+        record(|| format!("data_pointer {:?}, {:?}, {:?}", data_name, addend, dst));
+        A::data_pointer(buf, relocs, data_name, addend, dst)
+    }
+
+    fn jmp_imm32(buf: &mut Vec<'_, u8>, offset: i32) -> usize {
+        record(|| format!("jmp_imm32 {:?}", offset));
+        A::jmp_imm32(buf, offset)
+    }
+
+    fn jmp_table_reg64(buf: &mut Vec<'_, u8>, tmp: GeneralReg, index: GeneralReg) {
+        record(|| format!("jmp_table_reg64 {:?}, {:?}", tmp, index));
+        A::jmp_table_reg64(buf, tmp, index)
+    }
+
+    fn tail_call(buf: &mut Vec<'_, u8>) -> u64 {
+        record(|| "tail_call".to_string());
+        A::tail_call(buf)
+    }
+
+    fn jne_reg64_imm64_imm32<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        reg: GeneralReg,
+        imm: u64,
+        offset: i32,
+    ) -> usize
+    where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("jne_reg64_imm64_imm32 {:?}, {:?}, {:?}", reg, imm, offset));
+        A::jne_reg64_imm64_imm32(buf, storage_manager, reg, imm, offset)
+    }
+
+    fn jcc_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
+        src1: GeneralReg,
+        src2: GeneralReg,
+        offset: i32,
+    ) -> usize {
+        record(|| {
+            format!(
+                "jcc_reg64_reg64_imm32 {:?}, {:?}, {:?}, {:?}, {:?}",
+                register_width, condition, src1, src2, offset
+            )
+        });
+        A::jcc_reg64_reg64_imm32(buf, register_width, condition, src1, src2, offset)
+    }
+
+    fn jump_sizes(code: &[u8]) -> (usize, usize) {
+        A::jump_sizes(code)
+    }
+
+    fn encode_jump(buf: &mut Vec<'_, u8>, jump: &[u8], offset: i32, short: bool) {
+        record(|| format!("encode_jump {:?}, {:?}, {:?}", jump, offset, short));
+        A::encode_jump(buf, jump, offset, short)
+    }
+
+    fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction> {
+        A::decode_instruction(code)
+    }
+
+    fn mov_freg32_imm32<'a>(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
+        dst: FloatReg,
+        imm: f32,
+    ) {
+        record(|| format!("mov_freg32_imm32 {:?}, {:?}", dst, imm));
+        A::mov_freg32_imm32(buf, relocs, dst, imm)
+    }
+
+    fn mov_freg64_imm64<'a>(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
+        dst: FloatReg,
+        imm: f64,
+    ) {
+        record(|| format!("mov_freg64_imm64 {:?}, {:?}", dst, imm));
+        A::mov_freg64_imm64(buf, relocs, dst, imm)
+    }
+
+    fn mov_reg64_imm64(buf: &mut Vec<'_, u8>, dst: GeneralReg, imm: i64) {
+        record(|| format!("mov_reg64_imm64 {:?}, {:?}", dst, imm));
+        A::mov_reg64_imm64(buf, dst, imm)
+    }
+
+    fn mov_freg64_freg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg) {
+        record(|| format!("mov_freg64_freg64 {:?}, {:?}", dst, src));
+        A::mov_freg64_freg64(buf, dst, src)
+    }
+
+    fn mov_reg32_freg32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: FloatReg) {
+        record(|| format!("mov_reg32_freg32 {:?}, {:?}", dst, src));
+        A::mov_reg32_freg32(buf, dst, src)
+    }
+
+    fn mov_reg64_freg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: FloatReg) {
+        record(|| format!("mov_reg64_freg64 {:?}, {:?}", dst, src));
+        A::mov_reg64_freg64(buf, dst, src)
+    }
+
+    fn mov_freg32_reg32(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg) {
+        record(|| format!("mov_freg32_reg32 {:?}, {:?}", dst, src));
+        A::mov_freg32_reg32(buf, dst, src)
+    }
+
+    fn mov_freg64_reg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg) {
+        record(|| format!("mov_freg64_reg64 {:?}, {:?}", dst, src));
+        A::mov_freg64_reg64(buf, dst, src)
+    }
+
+    fn mov_reg_reg(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        dst: GeneralReg,
+        src: GeneralReg,
+    ) {
+        record(|| format!("mov_reg_reg {:?}, {:?}, {:?}", register_width, dst, src));
+        A::mov_reg_reg(buf, register_width, dst, src)
+    }
+
+    fn mov_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("mov_reg64_reg64 {:?}, {:?}", dst, src));
+        A::mov_reg64_reg64(buf, dst, src)
+    }
+
+    fn mov_reg32_reg32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("mov_reg32_reg32 {:?}, {:?}", dst, src));
+        A::mov_reg32_reg32(buf, dst, src)
+    }
+
+    fn mov_reg16_reg16(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("mov_reg16_reg16 {:?}, {:?}", dst, src));
+        A::mov_reg16_reg16(buf, dst, src)
+    }
+
+    fn mov_reg8_reg8(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("mov_reg8_reg8 {:?}, {:?}", dst, src));
+        A::mov_reg8_reg8(buf, dst, src)
+    }
+
+    fn movsx_reg_reg(
+        buf: &mut Vec<'_, u8>,
+        input_width: RegisterWidth,
+        dst: GeneralReg,
+        src: GeneralReg,
+    ) {
+        record(|| format!("movsx_reg_reg {:?}, {:?}, {:?}", input_width, dst, src));
+        A::movsx_reg_reg(buf, input_width, dst, src)
+    }
+
+    fn movzx_reg_reg(
+        buf: &mut Vec<'_, u8>,
+        input_width: RegisterWidth,
+        dst: GeneralReg,
+        src: GeneralReg,
+    ) {
+        record(|| format!("movzx_reg_reg {:?}, {:?}, {:?}", input_width, dst, src));
+        A::movzx_reg_reg(buf, input_width, dst, src)
+    }
+
+    fn mov_freg64_base32(buf: &mut Vec<'_, u8>, dst: FloatReg, offset: i32) {
+        record(|| format!("mov_freg64_base32 {:?}, {:?}", dst, offset));
+        A::mov_freg64_base32(buf, dst, offset)
+    }
+
+    fn mov_freg128_base32(buf: &mut Vec<'_, u8>, dst: FloatReg, offset: i32) {
+        record(|| format!("mov_freg128_base32 {:?}, {:?}", dst, offset));
+        A::mov_freg128_base32(buf, dst, offset)
+    }
+
+    fn mov_reg64_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32) {
+        record(|| format!("mov_reg64_base32 {:?}, {:?}", dst, offset));
+        A::mov_reg64_base32(buf, dst, offset)
+    }
+
+    fn mov_reg32_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32) {
+        record(|| format!("mov_reg32_base32 {:?}, {:?}", dst, offset));
+        A::mov_reg32_base32(buf, dst, offset)
+    }
+
+    fn mov_reg16_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32) {
+        record(|| format!("mov_reg16_base32 {:?}, {:?}", dst, offset));
+        A::mov_reg16_base32(buf, dst, offset)
+    }
+
+    fn mov_reg8_base32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32) {
+        record(|| format!("mov_reg8_base32 {:?}, {:?}", dst, offset));
+        A::mov_reg8_base32(buf, dst, offset)
+    }
+
+    fn mov_base32_freg64(buf: &mut Vec<'_, u8>, offset: i32, src: FloatReg) {
+        record(|| format!("mov_base32_freg64 {:?}, {:?}", offset, src));
+        A::mov_base32_freg64(buf, offset, src)
+    }
+
+    fn mov_base32_freg128(buf: &mut Vec<'_, u8>, offset: i32, src: FloatReg) {
+        record(|| format!("mov_base32_freg128 {:?}, {:?}", offset, src));
+        A::mov_base32_freg128(buf, offset, src)
+    }
+
+    fn mov_base32_reg64(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_base32_reg64 {:?}, {:?}", offset, src));
+        A::mov_base32_reg64(buf, offset, src)
+    }
+
+    fn mov_base32_reg32(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_base32_reg32 {:?}, {:?}", offset, src));
+        A::mov_base32_reg32(buf, offset, src)
+    }
+
+    fn mov_base32_reg16(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_base32_reg16 {:?}, {:?}", offset, src));
+        A::mov_base32_reg16(buf, offset, src)
+    }
+
+    fn mov_base32_reg8(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_base32_reg8 {:?}, {:?}", offset, src));
+        A::mov_base32_reg8(buf, offset, src)
+    }
+
+    fn mov_reg64_mem64_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_reg64_mem64_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_reg64_mem64_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_reg32_mem32_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_reg32_mem32_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_reg32_mem32_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_reg16_mem16_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_reg16_mem16_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_reg16_mem16_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_reg8_mem8_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| format!("mov_reg8_mem8_offset32 {:?}, {:?}, {:?}", dst, src, offset));
+        A::mov_reg8_mem8_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_freg64_mem64_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_freg64_mem64_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_freg64_mem64_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_freg32_mem32_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_freg32_mem32_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_freg32_mem32_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_freg128_mem128_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "mov_freg128_mem128_offset32 {:?}, {:?}, {:?}",
+                dst, src, offset
+            )
+        });
+        A::mov_freg128_mem128_offset32(buf, dst, src, offset)
+    }
+
+    fn mov_mem64_offset32_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "mov_mem64_offset32_reg64 {:?}, {:?}, {:?}",
+                dst, offset, src
+            )
+        });
+        A::mov_mem64_offset32_reg64(buf, dst, offset, src)
+    }
+
+    fn atomic_add_mem64_offset32_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "atomic_add_mem64_offset32_reg64 {:?}, {:?}, {:?}",
+                dst, offset, src
+            )
+        });
+        A::atomic_add_mem64_offset32_reg64(buf, dst, offset, src)
+    }
+
+    fn mov_mem32_offset32_reg32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "mov_mem32_offset32_reg32 {:?}, {:?}, {:?}",
+                dst, offset, src
+            )
+        });
+        A::mov_mem32_offset32_reg32(buf, dst, offset, src)
+    }
+
+    fn mov_mem16_offset32_reg16(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "mov_mem16_offset32_reg16 {:?}, {:?}, {:?}",
+                dst, offset, src
+            )
+        });
+        A::mov_mem16_offset32_reg16(buf, dst, offset, src)
+    }
+
+    fn mov_mem8_offset32_reg8(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| format!("mov_mem8_offset32_reg8 {:?}, {:?}, {:?}", dst, offset, src));
+        A::mov_mem8_offset32_reg8(buf, dst, offset, src)
+    }
+
+    fn movesd_mem64_offset32_freg64(
+        buf: &mut Vec<'_, u8>,
+        ptr: GeneralReg,
+        offset: i32,
+        src: FloatReg,
+    ) {
+        record(|| {
+            format!(
+                "movesd_mem64_offset32_freg64 {:?}, {:?}, {:?}",
+                ptr, offset, src
+            )
+        });
+        A::movesd_mem64_offset32_freg64(buf, ptr, offset, src)
+    }
+
+    fn movsx_reg_base32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        dst: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "movsx_reg_base32 {:?}, {:?}, {:?}",
+                register_width, dst, offset
+            )
+        });
+        A::movsx_reg_base32(buf, register_width, dst, offset)
+    }
+
+    fn movzx_reg_base32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        dst: GeneralReg,
+        offset: i32,
+    ) {
+        record(|| {
+            format!(
+                "movzx_reg_base32 {:?}, {:?}, {:?}",
+                register_width, dst, offset
+            )
+        });
+        A::movzx_reg_base32(buf, register_width, dst, offset)
+    }
+
+    fn mov_freg64_stack32(buf: &mut Vec<'_, u8>, dst: FloatReg, offset: i32) {
+        record(|| format!("mov_freg64_stack32 {:?}, {:?}", dst, offset));
+        A::mov_freg64_stack32(buf, dst, offset)
+    }
+
+    fn mov_reg64_stack32(buf: &mut Vec<'_, u8>, dst: GeneralReg, offset: i32) {
+        record(|| format!("mov_reg64_stack32 {:?}, {:?}", dst, offset));
+        A::mov_reg64_stack32(buf, dst, offset)
+    }
+
+    fn mov_stack32_freg64(buf: &mut Vec<'_, u8>, offset: i32, src: FloatReg) {
+        record(|| format!("mov_stack32_freg64 {:?}, {:?}", offset, src));
+        A::mov_stack32_freg64(buf, offset, src)
+    }
+
+    fn mov_stack32_reg(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        offset: i32,
+        src: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "mov_stack32_reg {:?}, {:?}, {:?}",
+                register_width, offset, src
+            )
+        });
+        A::mov_stack32_reg(buf, register_width, offset, src)
+    }
+
+    fn mov_stack32_reg64(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_stack32_reg64 {:?}, {:?}", offset, src));
+        A::mov_stack32_reg64(buf, offset, src)
+    }
+
+    fn mov_stack32_reg32(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_stack32_reg32 {:?}, {:?}", offset, src));
+        A::mov_stack32_reg32(buf, offset, src)
+    }
+
+    fn mov_stack32_reg16(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_stack32_reg16 {:?}, {:?}", offset, src));
+        A::mov_stack32_reg16(buf, offset, src)
+    }
+
+    fn mov_stack32_reg8(buf: &mut Vec<'_, u8>, offset: i32, src: GeneralReg) {
+        record(|| format!("mov_stack32_reg8 {:?}, {:?}", offset, src));
+        A::mov_stack32_reg8(buf, offset, src)
+    }
+
+    fn sqrt_freg64_freg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg) {
+        record(|| format!("sqrt_freg64_freg64 {:?}, {:?}", dst, src));
+        A::sqrt_freg64_freg64(buf, dst, src)
+    }
+
+    fn sqrt_freg32_freg32(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg) {
+        record(|| format!("sqrt_freg32_freg32 {:?}, {:?}", dst, src));
+        A::sqrt_freg32_freg32(buf, dst, src)
+    }
+
+    fn round_reg64_freg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src: FloatReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("round_reg64_freg64 {:?}, {:?}", dst, src));
+        A::round_reg64_freg64(buf, storage_manager, dst, src)
+    }
+
+    fn neg_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("neg_reg64_reg64 {:?}, {:?}", dst, src));
+        A::neg_reg64_reg64(buf, dst, src)
+    }
+
+    fn mul_freg32_freg32_freg32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("mul_freg32_freg32_freg32 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::mul_freg32_freg32_freg32(buf, dst, src1, src2)
+    }
+
+    fn mul_freg64_freg64_freg64(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("mul_freg64_freg64_freg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::mul_freg64_freg64_freg64(buf, dst, src1, src2)
+    }
+
+    fn div_freg32_freg32_freg32(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("div_freg32_freg32_freg32 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::div_freg32_freg32_freg32(buf, dst, src1, src2)
+    }
+
+    fn div_freg64_freg64_freg64(
+        buf: &mut Vec<'_, u8>,
+        dst: FloatReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("div_freg64_freg64_freg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::div_freg64_freg64_freg64(buf, dst, src1, src2)
+    }
+
+    fn imul_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("imul_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::imul_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn umul_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("umul_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::umul_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn umulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("umulh_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::umulh_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn smulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("smulh_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::smulh_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn idiv_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("idiv_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::idiv_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn udiv_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("udiv_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::udiv_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn irem_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("irem_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::irem_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn urem_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, GeneralReg, FloatReg, ASM, CC>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) where
+        ASM: Assembler<GeneralReg, FloatReg>,
+        CC: CallConv<GeneralReg, FloatReg, ASM>,
+    {
+        record(|| format!("urem_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::urem_reg64_reg64_reg64(buf, storage_manager, dst, src1, src2)
+    }
+
+    fn sub_reg64_reg64_imm32(buf: &mut Vec<'_, u8>, dst: GeneralReg, src1: GeneralReg, imm32: i32) {
+        record(|| format!("sub_reg64_reg64_imm32 {:?}, {:?}, {:?}", dst, src1, imm32));
+        A::sub_reg64_reg64_imm32(buf, dst, src1, imm32)
+    }
+
+    fn sub_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("sub_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::sub_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn eq_reg_reg_reg(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "eq_reg_reg_reg {:?}, {:?}, {:?}, {:?}",
+                register_width, dst, src1, src2
+            )
+        });
+        A::eq_reg_reg_reg(buf, register_width, dst, src1, src2)
+    }
+
+    fn eq_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("eq_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::eq_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn neq_reg_reg_reg(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "neq_reg_reg_reg {:?}, {:?}, {:?}, {:?}",
+                register_width, dst, src1, src2
+            )
+        });
+        A::neq_reg_reg_reg(buf, register_width, dst, src1, src2)
+    }
+
+    fn signed_compare_reg64(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        operation: CompareOperation,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "signed_compare_reg64 {:?}, {:?}, {:?}, {:?}, {:?}",
+                register_width, operation, dst, src1, src2
+            )
+        });
+        A::signed_compare_reg64(buf, register_width, operation, dst, src1, src2)
+    }
+
+    fn unsigned_compare_reg64(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        operation: CompareOperation,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "unsigned_compare_reg64 {:?}, {:?}, {:?}, {:?}, {:?}",
+                register_width, operation, dst, src1, src2
+            )
+        });
+        A::unsigned_compare_reg64(buf, register_width, operation, dst, src1, src2)
+    }
+
+    fn compare_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
+        dst: GeneralReg,
+        src: GeneralReg,
+        imm32: i32,
+    ) {
+        record(|| {
+            format!(
+                "compare_reg64_imm32 {:?}, {:?}, {:?}, {:?}, {:?}",
+                register_width, condition, dst, src, imm32
+            )
+        });
+        A::compare_reg64_imm32(buf, register_width, condition, dst, src, imm32)
+    }
+
+    fn select_reg64_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src: GeneralReg,
+        imm32: i32,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| {
+            format!(
+                "select_reg64_reg64_reg64_imm32 {:?}, {:?}, {:?}, {:?}, {:?}",
+                dst, src, imm32, src1, src2
+            )
+        });
+        A::select_reg64_reg64_reg64_imm32(buf, dst, src, imm32, src1, src2)
+    }
+
+    fn supports_imm32_operand(operation: ImmOperation, imm: i64) -> bool {
+        A::supports_imm32_operand(operation, imm)
+    }
+
+    fn eq_freg_freg_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: FloatReg,
+        src2: FloatReg,
+        width: FloatWidth,
+    ) {
+        record(|| {
+            format!(
+                "eq_freg_freg_reg64 {:?}, {:?}, {:?}, {:?}",
+                dst, src1, src2, width
+            )
+        });
+        A::eq_freg_freg_reg64(buf, dst, src1, src2, width)
+    }
+
+    fn neq_freg_freg_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: FloatReg,
+        src2: FloatReg,
+        width: FloatWidth,
+    ) {
+        record(|| {
+            format!(
+                "neq_freg_freg_reg64 {:?}, {:?}, {:?}, {:?}",
+                dst, src1, src2, width
+            )
+        });
+        A::neq_freg_freg_reg64(buf, dst, src1, src2, width)
+    }
+
+    fn eq_freg128_freg128_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: FloatReg,
+        src2: FloatReg,
+    ) {
+        record(|| format!("eq_freg128_freg128_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::eq_freg128_freg128_reg64(buf, dst, src1, src2)
+    }
+
+    fn cmp_freg_freg_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: FloatReg,
+        src2: FloatReg,
+        width: FloatWidth,
+        operation: CompareOperation,
+    ) {
+        record(|| {
+            format!(
+                "cmp_freg_freg_reg64 {:?}, {:?}, {:?}, {:?}, {:?}",
+                dst, src1, src2, width, operation
+            )
+        });
+        A::cmp_freg_freg_reg64(buf, dst, src1, src2, width, operation)
+    }
+
+    fn is_nan_freg_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: FloatReg, width: FloatWidth) {
+        record(|| format!("is_nan_freg_reg64 {:?}, {:?}, {:?}", dst, src, width));
+        A::is_nan_freg_reg64(buf, dst, src, width)
+    }
+
+    fn to_float_freg32_reg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg) {
+        record(|| format!("to_float_freg32_reg64 {:?}, {:?}", dst, src));
+        A::to_float_freg32_reg64(buf, dst, src)
+    }
+
+    fn to_float_freg64_reg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: GeneralReg) {
+        record(|| format!("to_float_freg64_reg64 {:?}, {:?}", dst, src));
+        A::to_float_freg64_reg64(buf, dst, src)
+    }
+
+    fn to_float_freg32_freg64(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg) {
+        record(|| format!("to_float_freg32_freg64 {:?}, {:?}", dst, src));
+        A::to_float_freg32_freg64(buf, dst, src)
+    }
+
+    fn to_float_freg64_freg32(buf: &mut Vec<'_, u8>, dst: FloatReg, src: FloatReg) {
+        record(|| format!("to_float_freg64_freg32 {:?}, {:?}", dst, src));
+        A::to_float_freg64_freg32(buf, dst, src)
+    }
+
+    fn set_if_overflow(buf: &mut Vec<'_, u8>, dst: GeneralReg) {
+        record(|| format!("set_if_overflow {:?}", dst));
+        A::set_if_overflow(buf, dst)
+    }
+
+    fn adds_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("adds_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::adds_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn subs_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: GeneralReg,
+        src1: GeneralReg,
+        src2: GeneralReg,
+    ) {
+        record(|| format!("subs_reg64_reg64_reg64 {:?}, {:?}, {:?}", dst, src1, src2));
+        A::subs_reg64_reg64_reg64(buf, dst, src1, src2)
+    }
+
+    fn cmov_if_overflow_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("cmov_if_overflow_reg64_reg64 {:?}, {:?}", dst, src));
+        A::cmov_if_overflow_reg64_reg64(buf, dst, src)
+    }
+
+    fn cmov_if_carry_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("cmov_if_carry_reg64_reg64 {:?}, {:?}", dst, src));
+        A::cmov_if_carry_reg64_reg64(buf, dst, src)
+    }
+
+    fn cmov_if_borrow_reg64_reg64(buf: &mut Vec<'_, u8>, dst: GeneralReg, src: GeneralReg) {
+        record(|| format!("cmov_if_borrow_reg64_reg64 {:?}, {:?}", dst, src));
+        A::cmov_if_borrow_reg64_reg64(buf, dst, src)
+    }
+
+    fn ret(buf: &mut Vec<'_, u8>) {
+        record(|| "ret".to_string());
+        A::ret(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic64::storage::new_storage_manager;
+    use crate::generic64::x86_64::{
+        X86_64Assembler, X86_64FloatReg, X86_64GeneralReg, X86_64SystemV,
+    };
+    use crate::{AssemblyBackendMode, EnvBuilder, LiveInterval};
+    use bumpalo::Bump;
+    use roc_module::symbol::{ModuleId, Symbol};
+    use roc_mono::ir::OptLevel;
+    use roc_target::TargetInfo;
+
+    type ASM = RecordingAssembler<X86_64Assembler>;
+    type RecordingStorageManager<'a, 'r> =
+        StorageManager<'a, 'r, X86_64GeneralReg, X86_64FloatReg, ASM, X86_64SystemV>;
+
+    #[test]
+    fn records_the_instructions_and_emits_their_bytes() {
+        let arena = Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+
+        let ((), instructions) = record_instructions(|| {
+            ASM::mov_reg64_imm64(&mut buf, X86_64GeneralReg::RAX, 1);
+            ASM::ret(&mut buf);
+        });
+        assert_eq!(instructions, ["mov_reg64_imm64 RAX, 1", "ret"]);
+
+        let mut expected = bumpalo::vec![in &arena];
+        X86_64Assembler::mov_reg64_imm64(&mut expected, X86_64GeneralReg::RAX, 1);
+        X86_64Assembler::ret(&mut expected);
+        assert_eq!(buf, expected);
+
+        // Nothing is written down outside of `record_instructions`.
+        ASM::ret(&mut buf);
+        let ((), instructions) = record_instructions(|| ());
+        assert!(instructions.is_empty());
+    }
+
+    #[test]
+    fn records_the_store_of_a_spilled_symbol() {
+        let arena = Bump::new();
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .lazy_literals(false)
+            .mode(AssemblyBackendMode::Test)
+            .opt_level(OptLevel::Optimize)
+            .build();
+        let mut storage_manager: RecordingStorageManager =
+            new_storage_manager(&env, TargetInfo::default_x86_64());
+        storage_manager.reset();
+        let mut buf = bumpalo::vec![in &arena];
+
+        let sym = |index: u32| Symbol::from_index(ModuleId::ATTR, index);
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len() as u32;
+        // sym(1) lives the longest, so it is the one that is spilled.
+        let intervals = (0..=reg_count)
+            .map(|i| {
+                let end = if i == 1 { 100 } else { 10 };
+                (sym(i), LiveInterval { start: 0, end })
+            })
+            .collect();
+        storage_manager.set_live_intervals(intervals, vec![]);
+
+        let ((), instructions) = record_instructions(|| {
+            for i in 0..reg_count {
+                storage_manager.claim_general_reg(&mut buf, &sym(i));
+            }
+        });
+        assert!(instructions.is_empty());
+
+        let spilled_reg = storage_manager.general_reg_of(&sym(1)).unwrap();
+        let (reg, instructions) =
+            record_instructions(|| storage_manager.claim_general_reg(&mut buf, &sym(reg_count)));
+        assert_eq!(reg, spilled_reg);
+        assert_eq!(
+            instructions,
+            [format!("mov_base32_reg64 -8, {:?}", spilled_reg)]
+        );
+    }
+
+    #[test]
+    fn umulh_keeps_dst_in_rax_and_moves_the_symbol_out_of_rdx() {
+        let arena = Bump::new();
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .lazy_literals(false)
+            .mode(AssemblyBackendMode::Test)
+            .opt_level(OptLevel::Optimize)
+            .build();
+        let mut storage_manager: RecordingStorageManager =
+            new_storage_manager(&env, TargetInfo::default_x86_64());
+        storage_manager.reset();
+        let mut buf = bumpalo::vec![in &arena];
+
+        use X86_64GeneralReg::*;
+        let sym = |index: u32| Symbol::from_index(ModuleId::ATTR, index);
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len() as u32;
+        let intervals = (0..reg_count)
+            .map(|i| (sym(i), LiveInterval { start: 0, end: 10 }))
+            .collect();
+        storage_manager.set_live_intervals(intervals, vec![]);
+        for i in 0..reg_count {
+            storage_manager.claim_general_reg(&mut buf, &sym(i));
+        }
+        let holder = |reg| {
+            (0..reg_count)
+                .map(sym)
+                .find(|sym| storage_manager.general_reg_of(sym) == Some(reg))
+                .unwrap()
+        };
+        let dst = holder(RAX);
+        let src2 = holder(RDX);
+
+        let ((), instructions) = record_instructions(|| {
+            ASM::umulh_reg64_reg64_reg64(&mut buf, &mut storage_manager, RAX, R11, RDX)
+        });
+        assert_eq!(
+            instructions,
+            [
+                "umulh_reg64_reg64_reg64 RAX, R11, RDX",
+                "mov_base32_reg64 -8, RDX"
+            ]
+        );
+        assert_eq!(storage_manager.general_reg_of(&dst), Some(RAX));
+        assert_eq!(storage_manager.general_reg_of(&src2), None);
+    }
+
+    #[test]
+    fn copies_big_structs_through_a_vector_register() {
+        for vector_instructions in [true, false] {
+            let arena = Bump::new();
+            let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+                .lazy_literals(false)
+                .mode(AssemblyBackendMode::Test)
+                .opt_level(OptLevel::Optimize)
+                .vector_instructions(vector_instructions)
+                .build();
+            let mut storage_manager: RecordingStorageManager =
+                new_storage_manager(&env, TargetInfo::default_x86_64());
+            storage_manager.reset();
+            let mut buf = bumpalo::vec![in &arena];
+
+            let ((), instructions) = record_instructions(|| {
+                storage_manager.copy_to_stack_offset(&mut buf, 40, -48, -96)
+            });
+            let vector_moves = instructions
+                .iter()
+                .filter(|inst| inst.contains("freg128"))
+                .count();
+            if vector_instructions {
+                // Two 16 byte moves there and back, and the last 8 bytes through a general reg.
+                assert_eq!(vector_moves, 4, "{:?}", instructions);
+                assert_eq!(instructions.len(), 6, "{:?}", instructions);
+            } else {
+                assert_eq!(vector_moves, 0, "{:?}", instructions);
+                assert_eq!(instructions.len(), 10, "{:?}", instructions);
+            }
+        }
+    }
+}
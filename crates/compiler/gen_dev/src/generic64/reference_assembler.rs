@@ -0,0 +1,96 @@
+//! Cross-checks the bytes of our encoders against an external assembler.
+//! Capstone only tells us that our bytes decode to the instruction we meant,
+//! this checks that they are the bytes a real assembler emits for the same text.
+//! The tests that use it need GNU `as` and `objcopy`, so they are `#[ignore]`d, run them with
+//! `cargo test -p roc_gen_dev -- --ignored reference`. Set `ROC_REFERENCE_AS` and `ROC_REFERENCE_OBJCOPY`
+//! to use other binaries, like the ones of a cross toolchain.
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// One instruction: its text for the assembler and the bytes of our encoder.
+pub struct Case {
+    pub asm: String,
+    pub code: std::vec::Vec<u8>,
+}
+
+/// Panics with the first case whose bytes differ from the ones GNU `as` emits for its text.
+/// `{load}` and `{store}` pick between the two encodings of a reg-reg op, `{disp32}` forces a 32 bit offset.
+pub fn check_x86_64_encodings(cases: &[Case]) {
+    check_encodings(".intel_syntax noprefix\n", cases)
+}
+
+fn check_encodings(directives: &str, cases: &[Case]) {
+    // Assembling every case on its own is slow, so assemble them all at once,
+    // and only go one by one to find the case that is wrong.
+    let asm = cases.iter().map(|case| case.asm.as_str());
+    let expected = assemble(directives, asm);
+    let actual: std::vec::Vec<u8> = cases.iter().flat_map(|case| case.code.clone()).collect();
+    if expected == actual {
+        return;
+    }
+
+    for case in cases {
+        let expected = assemble(directives, std::iter::once(case.asm.as_str()));
+        assert_eq!(
+            format!("{:02x?}", case.code),
+            format!("{:02x?}", expected),
+            "the encoding of `{}` does not match the reference assembler",
+            case.asm
+        );
+    }
+    panic!("the encodings match one by one, but not all together");
+}
+
+fn assemble<'a>(directives: &str, asm: impl Iterator<Item = &'a str>) -> std::vec::Vec<u8> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let path = |extension: &str| -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "roc_reference_{}_{}.{}",
+            std::process::id(),
+            id,
+            extension
+        ))
+    };
+    let (source, object, binary) = (path("s"), path("o"), path("bin"));
+
+    let mut text = directives.to_string();
+    for line in asm {
+        text.push_str(line);
+        text.push('\n');
+    }
+    std::fs::write(&source, text).unwrap();
+
+    run(Command::new(tool("ROC_REFERENCE_AS", "as"))
+        .arg(&source)
+        .arg("-o")
+        .arg(&object));
+    run(Command::new(tool("ROC_REFERENCE_OBJCOPY", "objcopy"))
+        .args(["-O", "binary", "--only-section=.text"])
+        .arg(&object)
+        .arg(&binary));
+    let code = std::fs::read(&binary).unwrap();
+
+    for file in [source, object, binary] {
+        let _ = std::fs::remove_file(file);
+    }
+    code
+}
+
+fn tool(var: &str, default: &str) -> String {
+    std::env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+fn run(command: &mut Command) {
+    let output = command
+        .output()
+        .unwrap_or_else(|err| panic!("could not run {:?}: {}", command, err));
+    assert!(
+        output.status.success(),
+        "{:?} failed:\n{}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,182 @@
+use crate::generic64::{Assembler, RegTrait};
+use crate::Relocation;
+use bumpalo::collections::Vec;
+
+struct Jump {
+    /// The offset the jump is calculated from, the end of the jump instruction.
+    base: u64,
+    target: u64,
+    size: usize,
+    short_size: usize,
+    short: bool,
+}
+
+impl Jump {
+    fn savings(&self) -> u64 {
+        (self.size - self.short_size) as u64
+    }
+}
+
+/// Switches the jumps in `code` to their short form wherever their target is close enough.
+/// `jumps` contains the base offset and target of every jump within `code`,
+/// they are all re-encoded because shrinking a jump moves the code after it.
+/// The offsets of `relocs` are moved along with the code.
+pub fn relax_jumps<'a, GeneralReg: RegTrait, FloatReg: RegTrait, ASM>(
+    code: &mut Vec<'a, u8>,
+    jumps: &[(u64, u64)],
+    relocs: &mut [Relocation<'_>],
+) where
+    ASM: Assembler<GeneralReg, FloatReg>,
+{
+    let mut jumps: std::vec::Vec<Jump> = jumps
+        .iter()
+        .map(|(base, target)| {
+            let (size, short_size) = ASM::jump_sizes(&code[..*base as usize]);
+            Jump {
+                base: *base,
+                target: *target,
+                size,
+                short_size,
+                short: false,
+            }
+        })
+        .collect();
+    if jumps.iter().all(|jump| jump.short_size == jump.size) {
+        return;
+    }
+    jumps.sort_by_key(|jump| jump.base);
+
+    // Shrinking a jump only ever brings other jumps closer to their targets.
+    // So keep shrinking jumps until none of the remaining ones fit their short form.
+    let mut saved = saved_bytes(&jumps);
+    loop {
+        let relaxed = |offset: u64| new_offset(&jumps, &saved, offset) as i64;
+        let shrinkable: std::vec::Vec<usize> = jumps
+            .iter()
+            .enumerate()
+            .filter(|(_, jump)| !jump.short && jump.short_size < jump.size)
+            .filter(|(_, jump)| {
+                let savings = jump.savings() as i64;
+                let base = relaxed(jump.base) - savings;
+                let mut target = relaxed(jump.target);
+                if jump.target >= jump.base {
+                    target -= savings;
+                }
+                i8::try_from(target - base).is_ok()
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if shrinkable.is_empty() {
+            break;
+        }
+        for i in shrinkable {
+            jumps[i].short = true;
+        }
+        saved = saved_bytes(&jumps);
+    }
+
+    let mut out = Vec::with_capacity_in(code.len(), code.bump());
+    let mut copied = 0;
+    for jump in jumps.iter() {
+        let start = jump.base as usize - jump.size;
+        out.extend(&code[copied..start]);
+        let offset = new_offset(&jumps, &saved, jump.target) as i64
+            - new_offset(&jumps, &saved, jump.base) as i64;
+        ASM::encode_jump(
+            &mut out,
+            &code[start..jump.base as usize],
+            offset as i32,
+            jump.short,
+        );
+        copied = jump.base as usize;
+    }
+    out.extend(&code[copied..]);
+    *code = out;
+
+    for reloc in relocs.iter_mut() {
+        match reloc {
+            Relocation::LocalData { offset, .. }
+            | Relocation::LinkedFunction { offset, .. }
+            | Relocation::LinkedFunctionPage { offset, .. }
+            | Relocation::LinkedData { offset, .. } => {
+                *offset = new_offset(&jumps, &saved, *offset)
+            }
+            Relocation::JmpToReturn { .. } => {
+                unreachable!("jumps to the return are resolved before relaxing")
+            }
+        }
+    }
+}
+
+/// The bytes saved by the short jumps before each jump, followed by the total.
+fn saved_bytes(jumps: &[Jump]) -> std::vec::Vec<u64> {
+    let mut saved = std::vec::Vec::with_capacity(jumps.len() + 1);
+    saved.push(0);
+    for jump in jumps {
+        let savings = if jump.short { jump.savings() } else { 0 };
+        saved.push(saved.last().unwrap() + savings);
+    }
+    saved
+}
+
+/// Where `offset` ends up once the short jumps have been shrunk.
+fn new_offset(jumps: &[Jump], saved: &[u64], offset: u64) -> u64 {
+    offset - saved[jumps.partition_point(|jump| jump.base <= offset)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic64::x86_64::{X86_64Assembler, X86_64FloatReg, X86_64GeneralReg};
+    use bumpalo::Bump;
+
+    fn relax(code: &mut Vec<'_, u8>, jumps: &[(u64, u64)], relocs: &mut [Relocation<'_>]) {
+        relax_jumps::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler>(code, jumps, relocs);
+    }
+
+    #[test]
+    fn close_jumps_are_shortened() {
+        let arena = Bump::new();
+        let mut code = bumpalo::vec![in &arena];
+        let base = X86_64Assembler::jmp_imm32(&mut code, 3) as u64;
+        code.extend([0x90; 3]);
+        let target = code.len() as u64;
+        code.push(0xC3);
+
+        let mut relocs = [Relocation::LinkedFunction {
+            offset: target,
+            name: "f".into(),
+            addend: 0,
+        }];
+        relax(&mut code, &[(base, target)], &mut relocs);
+        assert_eq!(code.as_slice(), [0xEB, 0x03, 0x90, 0x90, 0x90, 0xC3]);
+        assert!(matches!(
+            relocs[0],
+            Relocation::LinkedFunction { offset: 5, .. }
+        ));
+    }
+
+    #[test]
+    fn far_jumps_keep_their_form() {
+        let arena = Bump::new();
+        let mut code = bumpalo::vec![in &arena];
+
+        // A backwards jump over a forward jump that gets shortened.
+        let loop_start = code.len() as u64;
+        let short_base = X86_64Assembler::jmp_imm32(&mut code, 0) as u64;
+        let short_target = code.len() as u64;
+        code.extend([0x90; 200]);
+        let far_base = X86_64Assembler::jmp_imm32(&mut code, 0) as u64;
+
+        relax(
+            &mut code,
+            &[(short_base, short_target), (far_base, loop_start)],
+            &mut [],
+        );
+        let mut expected = std::vec::Vec::from([0xEB, 0x00]);
+        expected.extend([0x90; 200]);
+        expected.push(0xE9);
+        expected.extend((-207i32).to_le_bytes());
+        assert_eq!(code.as_slice(), expected.as_slice());
+    }
+}
@@ -2,6 +2,7 @@ use crate::{
     generic64::{Assembler, CallConv, RegTrait},
     pointer_layouts, sign_extended_int_builtins, single_register_floats,
     single_register_int_builtins, single_register_integers, single_register_layouts, Env,
+    LiveInterval,
 };
 use bumpalo::collections::Vec;
 use roc_builtins::bitcode::{FloatWidth, IntWidth};
@@ -15,6 +16,7 @@ use roc_mono::{
     },
 };
 use roc_target::TargetInfo;
+use std::cell::Cell;
 use std::cmp::max;
 use std::marker::PhantomData;
 use std::rc::Rc;
@@ -80,6 +82,11 @@ enum Storage<GeneralReg: RegTrait, FloatReg: RegTrait> {
     NoData,
 }
 
+/// Stack copies of at least this many bytes are done with 16 byte vector moves.
+const VECTOR_COPY_MIN_SIZE: u32 = 32;
+
+/// Tracks where every symbol of the proc being built is stored, in registers or on the stack.
+/// It is shared by all generic64 backends, which only provide their registers, assembler, and calling convention.
 #[derive(Clone)]
 pub struct StorageManager<
     'a,
@@ -113,11 +120,28 @@ pub struct StorageManager<
     float_free_regs: Vec<'a, FloatReg>,
 
     // The last major thing we need is a way to decide what reg to free when all of them are full.
-    // Theoretically we want a basic lru cache for the currently loaded symbols.
-    // For now just a vec of used registers and the symbols they contain.
+    // This is a vec of used registers and the symbols they contain.
+    // When a register is needed, the symbol whose live interval ends last is spilled, like in linear scan.
     general_used_regs: Vec<'a, (GeneralReg, Symbol)>,
     float_used_regs: Vec<'a, (FloatReg, Symbol)>,
 
+    // The live interval of each symbol and the position of the statement currently being built.
+    live_intervals: MutMap<Symbol, LiveInterval>,
+    position: u32,
+
+    // Sorted positions of the statements that call functions.
+    // Symbols live across one of them are put in callee saved registers when possible,
+    // so they are not pushed to the stack around the call.
+    call_positions: std::vec::Vec<u32>,
+
+    // Preferred registers for symbols, used when the register is free at the time the symbol is loaded.
+    general_reg_hints: MutMap<Symbol, GeneralReg>,
+
+    // Symbols loaded from integer literals and their value.
+    // When their register is needed, they are dropped instead of spilled and the literal is loaded again on the next use.
+    // A constant that was dropped has no storage.
+    constants: MutMap<Symbol, i64>,
+
     // TODO: it probably would be faster to make these a list that linearly scans rather than hashing.
     // used callee saved regs must be tracked for pushing and popping at the beginning/end of the function.
     general_used_callee_saved_regs: MutSet<GeneralReg>,
@@ -126,8 +150,22 @@ pub struct StorageManager<
     free_stack_chunks: Vec<'a, (i32, u32)>,
     stack_size: u32,
 
+    // Stack slots that registers were spilled to, with the symbol owning each slot.
+    // Once the live interval of the owner has ended, its slot can be reused by the next spill.
+    spill_slots: Vec<'a, (i32, Symbol)>,
+
     // The amount of extra stack space needed to pass args for function calling.
     fn_call_stack_size: u32,
+
+    spill_counts: Rc<SpillCounts>,
+}
+
+/// Counts the spills and reloads of the current proc.
+/// It is shared by all clones of the storage manager, so the spills in every branch are counted.
+#[derive(Debug, Default)]
+struct SpillCounts {
+    spills: Cell<u32>,
+    reloads: Cell<u32>,
 }
 
 pub fn new_storage_manager<
@@ -155,9 +193,16 @@ pub fn new_storage_manager<
         float_free_regs: bumpalo::vec![in env.arena],
         float_used_regs: bumpalo::vec![in env.arena],
         float_used_callee_saved_regs: MutSet::default(),
+        live_intervals: MutMap::default(),
+        position: 0,
+        call_positions: std::vec::Vec::new(),
+        general_reg_hints: MutMap::default(),
+        constants: MutMap::default(),
         free_stack_chunks: bumpalo::vec![in env.arena],
         stack_size: 0,
+        spill_slots: bumpalo::vec![in env.arena],
         fn_call_stack_size: 0,
+        spill_counts: Rc::default(),
     }
 }
 
@@ -184,19 +229,122 @@ impl<
         self.float_used_regs.clear();
         self.float_free_regs
             .extend_from_slice(CC::FLOAT_DEFAULT_FREE_REGS);
+        self.live_intervals.clear();
+        self.position = 0;
+        self.call_positions.clear();
+        self.general_reg_hints.clear();
+        self.constants.clear();
         self.free_stack_chunks.clear();
         self.stack_size = 0;
+        self.spill_slots.clear();
         self.fn_call_stack_size = 0;
+        self.spill_counts = Rc::default();
     }
 
     pub fn stack_size(&self) -> u32 {
         self.stack_size
     }
 
+    /// Sets the live intervals of the symbols in the current proc and the positions of its calls.
+    pub fn set_live_intervals(
+        &mut self,
+        live_intervals: MutMap<Symbol, LiveInterval>,
+        call_positions: std::vec::Vec<u32>,
+    ) {
+        self.live_intervals = live_intervals;
+        self.call_positions = call_positions;
+    }
+
+    /// Returns true if the live interval of `sym` contains a call after the current position.
+    /// Such a symbol would have to be saved around the call if it was in a caller saved register.
+    fn lives_across_call(&self, sym: &Symbol) -> bool {
+        match self.live_intervals.get(sym) {
+            Some(interval) => {
+                let first_call = self
+                    .call_positions
+                    .partition_point(|pos| *pos <= max(interval.start, self.position));
+                matches!(self.call_positions.get(first_call), Some(pos) if *pos < interval.end)
+            }
+            None => false,
+        }
+    }
+
+    /// Sets the preferred general registers of symbols.
+    pub fn set_general_reg_hints(&mut self, hints: MutMap<Symbol, GeneralReg>) {
+        self.general_reg_hints = hints;
+    }
+
+    /// Sets the position of the statement currently being built.
+    pub fn set_position(&mut self, position: u32) {
+        self.position = position;
+    }
+
+    /// The position of the statement currently being built.
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    /// Picks the used register to spill when out of registers.
+    /// Like linear scan, this is the symbol whose live interval ends last.
+    /// Symbols without an interval are temporaries, they are assumed to end at the current position.
+    /// Ties go to the register that has been used the longest.
+    /// Constants are always picked first, they are cheaper to load again than to spill.
+    fn spill_candidate<Reg>(&self, used_regs: &[(Reg, Symbol)]) -> usize {
+        let mut candidate = 0;
+        let mut candidate_key = (false, 0);
+        for (i, (_, sym)) in used_regs.iter().enumerate() {
+            let end = self
+                .live_intervals
+                .get(sym)
+                .map(|interval| interval.end)
+                .unwrap_or(self.position);
+            let key = (self.constants.contains_key(sym), end);
+            if i == 0 || key > candidate_key {
+                candidate = i;
+                candidate_key = key;
+            }
+        }
+        candidate
+    }
+
     pub fn fn_call_stack_size(&self) -> u32 {
         self.fn_call_stack_size
     }
 
+    /// The number of times a register was stored to the stack to free it in the current proc.
+    pub fn spills(&self) -> u32 {
+        self.spill_counts.spills.get()
+    }
+
+    /// The number of times a spilled value was loaded back into a register in the current proc.
+    pub fn reloads(&self) -> u32 {
+        self.spill_counts.reloads.get()
+    }
+
+    /// The largest number of symbols that are live at the same time in the current proc.
+    pub fn max_live_values(&self) -> u32 {
+        // Intervals include their end, so a symbol is dead from the position after it.
+        let mut changes: std::vec::Vec<(u32, i32)> = self
+            .live_intervals
+            .values()
+            .flat_map(|interval| [(interval.start, 1), (interval.end + 1, -1)])
+            .collect();
+        // Deaths sort before births at the same position.
+        changes.sort_unstable();
+        let mut live = 0;
+        let mut max_live = 0;
+        for (_, change) in changes {
+            live += change;
+            max_live = max(max_live, live);
+        }
+        max_live as u32
+    }
+
+    fn count_reload(&self) {
+        let reloads = &self.spill_counts.reloads;
+        reloads.set(reloads.get() + 1);
+    }
+
     pub fn general_used_callee_saved_regs(&self) -> Vec<'a, GeneralReg> {
         let mut used_regs = bumpalo::vec![in self.env.arena];
         used_regs.extend(&self.general_used_callee_saved_regs);
@@ -211,12 +359,79 @@ impl<
 
     /// Returns true if the symbol is storing a primitive value.
     pub fn is_stored_primitive(&self, sym: &Symbol) -> bool {
+        self.constants.contains_key(sym)
+            || matches!(
+                self.get_storage_for_sym(sym),
+                Reg(_) | Stack(Primitive { .. } | ReferencedPrimitive { .. })
+            )
+    }
+
+    /// Returns true if `sym` is a primitive on the stack and loading it would not need to spill anything.
+    /// A free register is kept back for the result of the operation that uses it.
+    pub fn can_preload(&self, sym: &Symbol) -> bool {
+        self.general_free_regs.len() > 1
+            && matches!(
+                self.symbol_storage_map.get(sym),
+                Some(Stack(
+                    Primitive { reg: None, .. } | ReferencedPrimitive { .. }
+                ))
+            )
+    }
+
+    /// Whether `sym` is a struct, union or other value that is only on the stack, or has no data at all.
+    /// Fields of these can be loaded with `load_field_at_index`.
+    pub fn is_complex(&self, sym: &Symbol) -> bool {
         matches!(
             self.get_storage_for_sym(sym),
-            Reg(_) | Stack(Primitive { .. } | ReferencedPrimitive { .. })
+            Stack(Complex { .. }) | NoData
         )
     }
 
+    /// The general register that currently holds `sym`, if any.
+    pub fn general_reg_of(&self, sym: &Symbol) -> Option<GeneralReg> {
+        match self.symbol_storage_map.get(sym) {
+            Some(
+                Reg(General(reg))
+                | Stack(Primitive {
+                    reg: Some(General(reg)),
+                    ..
+                }),
+            ) => Some(*reg),
+            _ => None,
+        }
+    }
+
+    /// Records that `sym`, which is currently in a general register, is the integer constant `value`.
+    /// This lets the register be reused without spilling `sym`.
+    pub fn record_constant(&mut self, sym: &Symbol, value: i64) {
+        debug_assert!(matches!(
+            self.symbol_storage_map.get(sym),
+            Some(Reg(General(_)))
+        ));
+        self.constants.insert(*sym, value);
+    }
+
+    /// The value of `sym` if it is a recorded integer constant.
+    pub fn constant_value(&self, sym: &Symbol) -> Option<i64> {
+        self.constants.get(sym).copied()
+    }
+
+    /// Returns true if `sym` is a constant that was dropped from its register.
+    fn is_dropped_constant(&self, sym: &Symbol) -> bool {
+        self.constants.contains_key(sym) && !self.symbol_storage_map.contains_key(sym)
+    }
+
+    /// Loads a dropped constant into a general register again.
+    fn rematerialize_constant(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) {
+        if self.is_dropped_constant(sym) {
+            let value = self.constants[sym];
+            let reg = self.get_general_reg_for(buf, sym);
+            ASM::mov_reg64_imm64(buf, reg, value);
+            self.general_used_regs.push((reg, *sym));
+            self.symbol_storage_map.insert(*sym, Reg(General(reg)));
+        }
+    }
+
     /// Get a general register from the free list.
     /// Will free data to the stack if necessary to get the register.
     fn get_general_reg(&mut self, buf: &mut Vec<'a, u8>) -> GeneralReg {
@@ -226,7 +441,8 @@ impl<
             }
             reg
         } else if !self.general_used_regs.is_empty() {
-            let (reg, sym) = self.general_used_regs.remove(0);
+            let index = self.spill_candidate(&self.general_used_regs);
+            let (reg, sym) = self.general_used_regs.remove(index);
             self.free_to_stack(buf, &sym, General(reg));
             reg
         } else {
@@ -234,6 +450,84 @@ impl<
         }
     }
 
+    /// Get a general register for a specific symbol.
+    /// This is the hinted register of the symbol if it is free, otherwise any register.
+    /// Symbols that live across a call prefer a callee saved register.
+    fn get_general_reg_for(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) -> GeneralReg {
+        let hint = self.general_reg_hints.get(sym).copied();
+        if self.lives_across_call(sym) {
+            let callee_saved = match hint {
+                Some(hint) if CC::general_callee_saved(&hint) => Some(hint),
+                _ => self
+                    .general_free_regs
+                    .iter()
+                    .rev()
+                    .find(|reg| CC::general_callee_saved(reg))
+                    .copied(),
+            };
+            if let Some(reg) = callee_saved {
+                if self.take_free_general_reg(reg) {
+                    return reg;
+                }
+            }
+        }
+        if let Some(hint) = hint {
+            if self.take_free_general_reg(hint) {
+                return hint;
+            }
+        }
+        self.get_general_reg(buf)
+    }
+
+    /// Removes `reg` from the free list if it is free.
+    /// Returns whether the register was taken.
+    fn take_free_general_reg(&mut self, reg: GeneralReg) -> bool {
+        match self.general_free_regs.iter().position(|free| *free == reg) {
+            Some(pos) => {
+                self.general_free_regs.remove(pos);
+                if CC::general_callee_saved(&reg) {
+                    self.general_used_callee_saved_regs.insert(reg);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `reg` from the free list if it is free.
+    /// Returns whether the register was taken.
+    fn take_free_float_reg(&mut self, reg: FloatReg) -> bool {
+        match self.float_free_regs.iter().position(|free| *free == reg) {
+            Some(pos) => {
+                self.float_free_regs.remove(pos);
+                if CC::float_callee_saved(&reg) {
+                    self.float_used_callee_saved_regs.insert(reg);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get a float register for a specific symbol.
+    /// Symbols that live across a call prefer a callee saved register.
+    fn get_float_reg_for(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) -> FloatReg {
+        if self.lives_across_call(sym) {
+            let callee_saved = self
+                .float_free_regs
+                .iter()
+                .rev()
+                .find(|reg| CC::float_callee_saved(reg))
+                .copied();
+            if let Some(reg) = callee_saved {
+                if self.take_free_float_reg(reg) {
+                    return reg;
+                }
+            }
+        }
+        self.get_float_reg(buf)
+    }
+
     /// Get a float register from the free list.
     /// Will free data to the stack if necessary to get the register.
     fn get_float_reg(&mut self, buf: &mut Vec<'a, u8>) -> FloatReg {
@@ -243,7 +537,8 @@ impl<
             }
             reg
         } else if !self.float_used_regs.is_empty() {
-            let (reg, sym) = self.float_used_regs.remove(0);
+            let index = self.spill_candidate(&self.float_used_regs);
+            let (reg, sym) = self.float_used_regs.remove(index);
             self.free_to_stack(buf, &sym, Float(reg));
             reg
         } else {
@@ -259,7 +554,7 @@ impl<
             None,
             "Symbol {sym:?} is already in the storage map!"
         );
-        let reg = self.get_general_reg(buf);
+        let reg = self.get_general_reg_for(buf, sym);
         self.general_used_regs.push((reg, *sym));
         self.symbol_storage_map.insert(*sym, Reg(General(reg)));
         reg
@@ -269,7 +564,52 @@ impl<
     /// They symbol should not already have storage.
     pub fn claim_float_reg(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) -> FloatReg {
         debug_assert_eq!(self.symbol_storage_map.get(sym), None);
-        let reg = self.get_float_reg(buf);
+        let reg = self.get_float_reg_for(buf, sym);
+        self.float_used_regs.push((reg, *sym));
+        self.symbol_storage_map.insert(*sym, Reg(Float(reg)));
+        reg
+    }
+
+    /// Claims a general reg for a specific symbol, using `preferred` if it is free.
+    /// When the value is already in `preferred`, moving it to the claimed reg is then a no-op.
+    /// They symbol should not already have storage.
+    pub fn claim_general_reg_preferring(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        sym: &Symbol,
+        preferred: GeneralReg,
+    ) -> GeneralReg {
+        debug_assert_eq!(self.symbol_storage_map.get(sym), None);
+        // A callee saved register is worth a copy if the symbol lives across a call.
+        let wants_callee_saved =
+            self.lives_across_call(sym) && !CC::general_callee_saved(&preferred);
+        let reg = if !wants_callee_saved && self.take_free_general_reg(preferred) {
+            preferred
+        } else {
+            self.get_general_reg_for(buf, sym)
+        };
+        self.general_used_regs.push((reg, *sym));
+        self.symbol_storage_map.insert(*sym, Reg(General(reg)));
+        reg
+    }
+
+    /// Claims a float reg for a specific symbol, using `preferred` if it is free.
+    /// When the value is already in `preferred`, moving it to the claimed reg is then a no-op.
+    /// They symbol should not already have storage.
+    pub fn claim_float_reg_preferring(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        sym: &Symbol,
+        preferred: FloatReg,
+    ) -> FloatReg {
+        debug_assert_eq!(self.symbol_storage_map.get(sym), None);
+        // A callee saved register is worth a copy if the symbol lives across a call.
+        let wants_callee_saved = self.lives_across_call(sym) && !CC::float_callee_saved(&preferred);
+        let reg = if !wants_callee_saved && self.take_free_float_reg(preferred) {
+            preferred
+        } else {
+            self.get_float_reg_for(buf, sym)
+        };
         self.float_used_regs.push((reg, *sym));
         self.symbol_storage_map.insert(*sym, Reg(Float(reg)));
         reg
@@ -287,7 +627,6 @@ impl<
         self.general_free_regs.push(reg);
     }
 
-    #[allow(dead_code)]
     /// This claims a temporary float register and enables is used in the passed in function.
     /// Temporary registers are not safe across call instructions.
     pub fn with_tmp_float_reg<F: FnOnce(&mut Self, &mut Vec<'a, u8>, FloatReg)>(
@@ -305,6 +644,7 @@ impl<
     /// Will fail on values stored in float regs.
     /// Will fail for values that don't fit in a single register.
     pub fn load_to_general_reg(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) -> GeneralReg {
+        self.rematerialize_constant(buf, sym);
         let storage = self.remove_storage_for_sym(sym);
         match storage {
             Reg(General(reg))
@@ -327,8 +667,9 @@ impl<
                 base_offset,
             }) => {
                 debug_assert_eq!(base_offset % 8, 0);
-                let reg = self.get_general_reg(buf);
+                let reg = self.get_general_reg_for(buf, sym);
                 ASM::mov_reg64_base32(buf, reg, base_offset);
+                self.count_reload();
                 self.general_used_regs.push((reg, *sym));
                 self.symbol_storage_map.insert(
                     *sym,
@@ -344,7 +685,7 @@ impl<
                 size,
                 sign_extend,
             }) => {
-                let reg = self.get_general_reg(buf);
+                let reg = self.get_general_reg_for(buf, sym);
 
                 let register_width = match size {
                     8 => RegisterWidth::W64,
@@ -402,8 +743,9 @@ impl<
                 base_offset,
             }) => {
                 debug_assert_eq!(base_offset % 8, 0);
-                let reg = self.get_float_reg(buf);
+                let reg = self.get_float_reg_for(buf, sym);
                 ASM::mov_freg64_base32(buf, reg, base_offset);
+                self.count_reload();
                 self.float_used_regs.push((reg, *sym));
                 self.symbol_storage_map.insert(
                     *sym,
@@ -418,7 +760,7 @@ impl<
                 base_offset, size, ..
             }) if base_offset % 8 == 0 && size == 8 => {
                 // The primitive is aligned and the data is exactly 8 bytes, treat it like regular stack.
-                let reg = self.get_float_reg(buf);
+                let reg = self.get_float_reg_for(buf, sym);
                 ASM::mov_freg64_base32(buf, reg, base_offset);
                 self.float_used_regs.push((reg, *sym));
                 self.symbol_storage_map.insert(*sym, Reg(Float(reg)));
@@ -448,6 +790,10 @@ impl<
         sym: &Symbol,
         reg: GeneralReg,
     ) {
+        if self.is_dropped_constant(sym) {
+            ASM::mov_reg64_imm64(buf, reg, self.constants[sym]);
+            return;
+        }
         match self.get_storage_for_sym(sym) {
             Reg(General(old_reg))
             | Stack(Primitive {
@@ -572,6 +918,11 @@ impl<
             return self.no_data(sym);
         }
 
+        // Zero-sized fields need no storage, even when the rest of the structure has data.
+        if layout_interner.stack_size(field_layouts[index as usize]) == 0 {
+            return self.no_data(sym);
+        }
+
         // This must be removed and reinserted for ownership and mutability reasons.
         let owned_data = self.remove_allocation_for_sym(structure);
         self.allocation_map
@@ -676,7 +1027,7 @@ impl<
     ) {
         let struct_size = layout_interner.stack_size(*layout);
         if struct_size == 0 {
-            self.symbol_storage_map.insert(*sym, NoData);
+            self.no_data(sym);
             return;
         }
         let base_offset = self.claim_stack_area(sym, struct_size);
@@ -746,6 +1097,10 @@ impl<
         sym: &Symbol,
         layout: &InLayout<'a>,
     ) {
+        if layout_interner.stack_size(*layout) == 0 {
+            // Nothing to copy, the symbol may not even have storage.
+            return;
+        }
         match layout_interner.get_repr(*layout) {
             LayoutRepr::Builtin(builtin) => match builtin {
                 Builtin::Int(int_width) => match int_width {
@@ -842,6 +1197,26 @@ impl<
         from_offset: i32,
         to_offset: i32,
     ) {
+        if self.env.vector_instructions && size >= VECTOR_COPY_MIN_SIZE {
+            // Big aggregates are copied 16 bytes at a time through a vector register.
+            let vector_size = (size - size % 16) as i32;
+            self.with_tmp_float_reg(buf, |_storage_manager, buf, reg| {
+                for offset in (0..vector_size).step_by(16) {
+                    ASM::mov_freg128_base32(buf, reg, from_offset + offset);
+                    ASM::mov_base32_freg128(buf, to_offset + offset, reg);
+                }
+            });
+            if size % 16 != 0 {
+                self.copy_to_stack_offset(
+                    buf,
+                    size % 16,
+                    from_offset + vector_size,
+                    to_offset + vector_size,
+                );
+            }
+            return;
+        }
+
         let mut copied = 0;
         let size = size as i32;
 
@@ -942,6 +1317,7 @@ impl<
     }
 
     pub fn ensure_symbol_on_stack(&mut self, buf: &mut Vec<'a, u8>, sym: &Symbol) {
+        self.rematerialize_constant(buf, sym);
         match self.remove_storage_for_sym(sym) {
             Reg(reg_storage) => {
                 let base_offset = self.claim_stack_size(8);
@@ -1002,13 +1378,19 @@ impl<
         wanted_reg: RegStorage<GeneralReg, FloatReg>,
     ) {
         match self.remove_storage_for_sym(sym) {
+            Reg(reg_storage @ General(_)) if self.constants.contains_key(sym) => {
+                // The constant is loaded again from its literal when it is next used.
+                debug_assert_eq!(reg_storage, wanted_reg);
+            }
             Reg(reg_storage) => {
                 debug_assert_eq!(reg_storage, wanted_reg);
-                let base_offset = self.claim_stack_size(8);
+                let base_offset = self.claim_spill_slot(sym);
                 match reg_storage {
                     General(reg) => ASM::mov_base32_reg64(buf, base_offset, reg),
                     Float(reg) => ASM::mov_base32_freg64(buf, base_offset, reg),
                 }
+                let spills = &self.spill_counts.spills;
+                spills.set(spills.get() + 1);
                 self.symbol_storage_map.insert(
                     *sym,
                     Stack(Primitive {
@@ -1155,6 +1537,9 @@ impl<
     /// To do this, each of the join pionts params are given a storage location.
     /// Then those locations are stored.
     /// Later jumps to the join point can overwrite the stored locations to pass parameters.
+    /// Primitive params are given registers while at most half of the free registers are used up,
+    /// everything else goes on the stack.
+    /// The registers are only claimed by `enter_joinpoint_body`, so the code jumping to the join point can still use them.
     pub fn setup_joinpoint(
         &mut self,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -1162,6 +1547,11 @@ impl<
         id: &JoinPointId,
         params: &'a [Param<'a>],
     ) {
+        let mut general_regs = self.general_free_regs.clone();
+        let min_general_regs = general_regs.len() / 2;
+        let mut float_regs = self.float_free_regs.clone();
+        let min_float_regs = float_regs.len() / 2;
+
         let mut param_storage = bumpalo::vec![in self.env.arena];
         param_storage.reserve(params.len());
         for Param {
@@ -1170,15 +1560,68 @@ impl<
             layout,
         } in params
         {
-            // Claim a location for every join point parameter to be loaded at.
-            // Put everything on the stack for simplicity.
-            self.joinpoint_argument_stack_storage(layout_interner, *symbol, *layout);
-
-            param_storage.push(*self.get_storage_for_sym(symbol));
+            let reg = match layout_interner.get_repr(*layout) {
+                single_register_integers!() | pointer_layouts!()
+                    if general_regs.len() > min_general_regs =>
+                {
+                    general_regs.pop().map(General)
+                }
+                single_register_floats!() if float_regs.len() > min_float_regs => {
+                    float_regs.pop().map(Float)
+                }
+                _ => None,
+            };
+            match reg {
+                Some(reg) => param_storage.push(Reg(reg)),
+                None => {
+                    // Claim a location on the stack for the join point parameter to be loaded at.
+                    self.joinpoint_argument_stack_storage(layout_interner, *symbol, *layout);
+                    param_storage.push(*self.get_storage_for_sym(symbol));
+                }
+            }
         }
         self.join_param_map.insert(*id, param_storage);
     }
 
+    /// Claims the registers of the join point params for the params.
+    /// This must be called on the storage from right after `setup_joinpoint` before building the join point body.
+    pub fn enter_joinpoint_body(&mut self, id: &JoinPointId, params: &'a [Param<'a>]) {
+        let param_storage = match self.join_param_map.get(id) {
+            Some(storages) => storages.clone(),
+            None => internal_error!("Join: unknown join point: {:?}", id),
+        };
+        for (param, storage) in params.iter().zip(param_storage) {
+            match storage {
+                Reg(General(reg)) => {
+                    if !self.take_free_general_reg(reg) {
+                        internal_error!("join point param register {} is already in use", reg);
+                    }
+                    self.general_used_regs.push((reg, param.symbol));
+                    self.symbol_storage_map.insert(param.symbol, storage);
+                }
+                Reg(Float(reg)) => {
+                    if !self.take_free_float_reg(reg) {
+                        internal_error!("join point param register {} is already in use", reg);
+                    }
+                    self.float_used_regs.push((reg, param.symbol));
+                    self.symbol_storage_map.insert(param.symbol, storage);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Keeps the stack sizes and used callee saved registers of `other` when throwing it away for an earlier storage.
+    /// All of them are needed for the stack frame of the proc.
+    pub fn keep_frame_requirements(&mut self, other: &Self) {
+        self.update_stack_size(other.stack_size);
+        self.update_fn_call_stack_size(other.fn_call_stack_size);
+        self.general_used_callee_saved_regs
+            .extend(other.general_used_callee_saved_regs.iter().copied());
+        self.float_used_callee_saved_regs
+            .extend(other.float_used_callee_saved_regs.iter().copied());
+    }
+
     fn jump_argument_stack_storage(
         &mut self,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -1231,16 +1674,46 @@ impl<
             None => internal_error!("Jump: unknown point specified to jump to: {:?}", id),
         };
 
+        // Register params are moved after the stack params are written.
+        // Args of register params that live in one of the overwritten stack slots are moved to registers first.
+        let overwritten_offsets: std::vec::Vec<i32> = args
+            .iter()
+            .zip(param_storage.iter())
+            .filter_map(|(sym, wanted_storage)| match wanted_storage {
+                Stack(Primitive {
+                    base_offset,
+                    reg: None,
+                }) if self.symbol_storage_map.get(sym) != Some(wanted_storage) => {
+                    Some(*base_offset)
+                }
+                _ => None,
+            })
+            .collect();
+        for (sym, wanted_storage) in args.iter().zip(param_storage.iter()) {
+            if let (Reg(wanted_reg), Some(Stack(Primitive { base_offset, .. }))) =
+                (wanted_storage, self.symbol_storage_map.get(sym))
+            {
+                if overwritten_offsets.contains(base_offset) {
+                    let reg = match wanted_reg {
+                        General(_) => General(self.load_to_general_reg(buf, sym)),
+                        Float(_) => Float(self.load_to_float_reg(buf, sym)),
+                    };
+                    self.symbol_storage_map.insert(*sym, Reg(reg));
+                }
+            }
+        }
+
+        let mut general_params = std::vec::Vec::new();
+        let mut float_params = std::vec::Vec::new();
         let it = args.iter().zip(arg_layouts).zip(param_storage.iter());
         for ((sym, layout), wanted_storage) in it {
             // Note: it is possible that the storage we want to move to is in use by one of the args we want to pass.
-            if self.get_storage_for_sym(sym) == wanted_storage {
+            if self.symbol_storage_map.get(sym) == Some(wanted_storage) {
                 continue;
             }
             match wanted_storage {
-                Reg(_) => {
-                    internal_error!("Register storage is not allowed for jumping to joinpoint")
-                }
+                Reg(General(reg)) => general_params.push((*reg, *sym)),
+                Reg(Float(reg)) => float_params.push((*reg, *sym)),
                 Stack(Complex { base_offset, .. }) => {
                     // TODO: This might be better not to call.
                     // Maybe we want a more memcpy like method to directly get called here.
@@ -1279,9 +1752,112 @@ impl<
                 }
             }
         }
+        self.move_to_general_params(buf, general_params);
+        self.move_to_float_params(buf, float_params);
         self.join_param_map.insert(*id, param_storage);
     }
 
+    /// Moves the args of a jump into the registers of their join point params.
+    /// The moves happen in parallel, so an arg may be in the register of another param.
+    /// The storage of the args is not updated, the jump is the last thing done with it.
+    fn move_to_general_params(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        params: std::vec::Vec<(GeneralReg, Symbol)>,
+    ) {
+        // Args that are not directly in a register or a stack slot are loaded first.
+        // That may spill other args, so the sources of all moves are looked up after.
+        for (_, sym) in params.iter() {
+            if !matches!(
+                self.symbol_storage_map.get(sym),
+                Some(Reg(General(_)) | Stack(Primitive { .. }))
+            ) {
+                self.load_to_general_reg(buf, sym);
+            }
+        }
+        let moves = params
+            .iter()
+            .map(|(dst, sym)| {
+                let src = match self.get_storage_for_sym(sym) {
+                    Reg(General(reg))
+                    | Stack(Primitive {
+                        reg: Some(General(reg)),
+                        ..
+                    }) => MoveSource::Reg(*reg),
+                    Stack(Primitive { base_offset, .. }) => MoveSource::Stack(*base_offset),
+                    storage => internal_error!(
+                        "cannot move {:?} with storage {:?} to a join point param",
+                        sym,
+                        storage
+                    ),
+                };
+                (*dst, src)
+            })
+            .collect();
+
+        let mut tmp_slot = None;
+        emit_parallel_moves(
+            buf,
+            moves,
+            ASM::mov_reg64_reg64,
+            ASM::mov_reg64_base32,
+            ASM::mov_base32_reg64,
+            || *tmp_slot.get_or_insert_with(|| self.claim_stack_size(8)),
+        );
+        if let Some(offset) = tmp_slot {
+            self.free_stack_chunk(offset, 8);
+        }
+    }
+
+    /// Moves the args of a jump into the float registers of their join point params.
+    /// See `move_to_general_params`.
+    fn move_to_float_params(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        params: std::vec::Vec<(FloatReg, Symbol)>,
+    ) {
+        for (_, sym) in params.iter() {
+            if !matches!(
+                self.get_storage_for_sym(sym),
+                Reg(Float(_)) | Stack(Primitive { .. })
+            ) {
+                self.load_to_float_reg(buf, sym);
+            }
+        }
+        let moves = params
+            .iter()
+            .map(|(dst, sym)| {
+                let src = match self.get_storage_for_sym(sym) {
+                    Reg(Float(reg))
+                    | Stack(Primitive {
+                        reg: Some(Float(reg)),
+                        ..
+                    }) => MoveSource::Reg(*reg),
+                    Stack(Primitive { base_offset, .. }) => MoveSource::Stack(*base_offset),
+                    storage => internal_error!(
+                        "cannot move {:?} with storage {:?} to a join point param",
+                        sym,
+                        storage
+                    ),
+                };
+                (*dst, src)
+            })
+            .collect();
+
+        let mut tmp_slot = None;
+        emit_parallel_moves(
+            buf,
+            moves,
+            ASM::mov_freg64_freg64,
+            ASM::mov_freg64_base32,
+            ASM::mov_base32_freg64,
+            || *tmp_slot.get_or_insert_with(|| self.claim_stack_size(8)),
+        );
+        if let Some(offset) = tmp_slot {
+            self.free_stack_chunk(offset, 8);
+        }
+    }
+
     /// claim_stack_area is the public wrapper around claim_stack_size.
     /// It also deals with updating symbol storage.
     /// It returns the base offset of the stack area.
@@ -1295,6 +1871,32 @@ impl<
         base_offset
     }
 
+    /// claim_spill_slot claims an 8 byte stack slot to spill `sym` to.
+    /// Before claiming, the slots of earlier spills whose symbols are past the end of their live interval are freed.
+    /// This way big procs reuse spill slots instead of growing the stack for every spill.
+    fn claim_spill_slot(&mut self, sym: &Symbol) -> i32 {
+        let position = self.position;
+        let dead_owners: std::vec::Vec<Symbol> = self
+            .spill_slots
+            .iter()
+            .filter(|(offset, owner)| {
+                matches!(self.live_intervals.get(owner), Some(interval) if interval.end < position)
+                    && matches!(
+                        self.symbol_storage_map.get(owner),
+                        Some(Stack(Primitive { base_offset, .. })) if base_offset == offset
+                    )
+            })
+            .map(|(_, owner)| *owner)
+            .collect();
+        for owner in dead_owners {
+            self.free_symbol(&owner);
+        }
+
+        let base_offset = self.claim_stack_size(8);
+        self.spill_slots.push((base_offset, *sym));
+        base_offset
+    }
+
     pub fn claim_pointer_stack_area(&mut self, sym: Symbol) -> i32 {
         let size = 8;
 
@@ -1357,10 +1959,12 @@ impl<
             // This is a join point and will not be in the storage map.
             return;
         }
+        self.constants.remove(sym);
         match self.symbol_storage_map.remove(sym) {
             // Free stack chunck if this is the last reference to the chunk.
             Some(Stack(Primitive { base_offset, .. })) => {
                 self.free_stack_chunk(base_offset, 8);
+                self.spill_slots.retain(|(_, owner)| owner != sym);
             }
             Some(Stack(Complex { .. } | ReferencedPrimitive { .. })) => {
                 self.free_reference(sym);
@@ -1445,13 +2049,133 @@ impl<
         }
     }
 
-    pub fn push_used_caller_saved_regs_to_stack(&mut self, buf: &mut Vec<'a, u8>) {
+    /// Checks the bookkeeping of registers and the stack, see `Env::check_storage_invariants`.
+    /// Every register is either free or holds one symbol whose storage refers to it,
+    /// the free stack chunks are sorted and disjoint, and no symbol is stored in a free chunk.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        Self::check_regs(
+            "general",
+            &self.general_free_regs,
+            &self.general_used_regs,
+            |storage| match storage {
+                Reg(General(reg))
+                | Stack(Primitive {
+                    reg: Some(General(reg)),
+                    ..
+                }) => Some(*reg),
+                _ => None,
+            },
+            &self.symbol_storage_map,
+        )?;
+        Self::check_regs(
+            "float",
+            &self.float_free_regs,
+            &self.float_used_regs,
+            |storage| match storage {
+                Reg(Float(reg))
+                | Stack(Primitive {
+                    reg: Some(Float(reg)),
+                    ..
+                }) => Some(*reg),
+                _ => None,
+            },
+            &self.symbol_storage_map,
+        )?;
+
+        let mut prev_end = -(self.stack_size as i32);
+        for (offset, size) in self.free_stack_chunks.iter() {
+            if *size == 0 || *offset < prev_end || *offset + *size as i32 > 0 {
+                return Err(format!(
+                    "the free stack chunks {:?} are not disjoint chunks of the {} byte stack",
+                    self.free_stack_chunks, self.stack_size
+                ));
+            }
+            prev_end = *offset + *size as i32;
+        }
+
+        for (sym, storage) in self.symbol_storage_map.iter() {
+            let (base_offset, size) = match storage {
+                Stack(Primitive { base_offset, .. }) => (*base_offset, 8),
+                Stack(ReferencedPrimitive {
+                    base_offset, size, ..
+                })
+                | Stack(Complex { base_offset, size }) => (*base_offset, *size),
+                Reg(_) | NoData => continue,
+            };
+            let end = base_offset + size as i32;
+            let freed = self.free_stack_chunks.iter().find(|(offset, chunk_size)| {
+                *offset < end && base_offset < *offset + *chunk_size as i32
+            });
+            if let Some(chunk) = freed {
+                return Err(format!(
+                    "{:?} is stored at {:?}, which overlaps the free stack chunk {:?}",
+                    sym, storage, chunk
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_regs<R: RegTrait>(
+        kind: &str,
+        free_regs: &[R],
+        used_regs: &[(R, Symbol)],
+        storage_reg: impl Fn(&Storage<GeneralReg, FloatReg>) -> Option<R>,
+        symbol_storage_map: &MutMap<Symbol, Storage<GeneralReg, FloatReg>>,
+    ) -> Result<(), String> {
+        let mut seen = std::vec::Vec::with_capacity(free_regs.len() + used_regs.len());
+        for reg in free_regs.iter().chain(used_regs.iter().map(|(reg, _)| reg)) {
+            if seen.contains(reg) {
+                return Err(format!(
+                    "the {} register {:?} is booked twice, free: {:?}, used: {:?}",
+                    kind, reg, free_regs, used_regs
+                ));
+            }
+            seen.push(*reg);
+        }
+
+        for (reg, sym) in used_regs.iter() {
+            let storage = symbol_storage_map.get(sym);
+            if storage.and_then(&storage_reg) != Some(*reg) {
+                return Err(format!(
+                    "the {} register {:?} is used by {:?}, which is stored at {:?}",
+                    kind, reg, sym, storage
+                ));
+            }
+        }
+
+        for (sym, storage) in symbol_storage_map.iter() {
+            if let Some(reg) = storage_reg(storage) {
+                if !used_regs.contains(&(reg, *sym)) {
+                    return Err(format!(
+                        "{:?} is stored in the {} register {:?}, which is not marked as used by it",
+                        sym, kind, reg
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frees all caller saved registers to the stack before a call.
+    /// Symbols in `args_in_place` that are in the given register and not used after the call are left in place,
+    /// since they already are where the call needs them.
+    pub fn push_used_caller_saved_regs_to_stack(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        args_in_place: &[(Symbol, GeneralReg)],
+    ) {
         let old_general_used_regs = std::mem::replace(
             &mut self.general_used_regs,
             bumpalo::vec![in self.env.arena],
         );
         for (reg, saved_sym) in old_general_used_regs.into_iter() {
-            if CC::general_caller_saved(&reg) {
+            let in_place = args_in_place.contains(&(saved_sym, reg))
+                && matches!(self.symbol_storage_map.get(&saved_sym), Some(Reg(General(r))) if *r == reg)
+                && matches!(self.live_intervals.get(&saved_sym), Some(interval) if interval.end <= self.position);
+            if CC::general_caller_saved(&reg) && !in_place {
                 self.general_free_regs.push(reg);
                 self.free_to_stack(buf, &saved_sym, General(reg));
             } else {
@@ -1518,3 +2242,489 @@ fn is_primitive(layout_interner: &mut STLayoutInterner<'_>, layout: InLayout<'_>
         _ => false,
     }
 }
+
+/// Where the value of a parallel move comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum MoveSource<R: RegTrait> {
+    Reg(R),
+    Stack(i32),
+}
+
+/// Emits `moves` such that every destination register ends up with the value its source had before any of the moves.
+/// A move is emitted once no other pending move reads its destination.
+/// Cycles are broken by saving one of the registers to the stack slot returned by `tmp_slot`.
+fn emit_parallel_moves<'a, R: RegTrait>(
+    buf: &mut Vec<'a, u8>,
+    mut moves: std::vec::Vec<(R, MoveSource<R>)>,
+    mov_reg_reg: fn(&mut Vec<'a, u8>, R, R),
+    mov_reg_base32: fn(&mut Vec<'a, u8>, R, i32),
+    mov_base32_reg: fn(&mut Vec<'a, u8>, i32, R),
+    mut tmp_slot: impl FnMut() -> i32,
+) {
+    moves.retain(|(dst, src)| *src != MoveSource::Reg(*dst));
+    while !moves.is_empty() {
+        let ready = moves
+            .iter()
+            .position(|(dst, _)| moves.iter().all(|(_, src)| *src != MoveSource::Reg(*dst)));
+        match ready {
+            Some(index) => match moves.remove(index) {
+                (dst, MoveSource::Reg(src)) => mov_reg_reg(buf, dst, src),
+                (dst, MoveSource::Stack(offset)) => mov_reg_base32(buf, dst, offset),
+            },
+            None => {
+                // Every pending destination is still read by another move, so they all form cycles.
+                // A broken cycle is finished before the next one is broken, so the slot can be reused.
+                let saved = moves[0].0;
+                let offset = tmp_slot();
+                mov_base32_reg(buf, offset, saved);
+                for (_, src) in moves.iter_mut() {
+                    if *src == MoveSource::Reg(saved) {
+                        *src = MoveSource::Stack(offset);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic64::x86_64::{
+        X86_64Assembler, X86_64FloatReg, X86_64GeneralReg, X86_64SystemV,
+    };
+    use crate::{AssemblyBackendMode, EnvBuilder};
+    use bumpalo::Bump;
+    use roc_module::symbol::ModuleId;
+    use roc_mono::ir::OptLevel;
+
+    type TestStorageManager<'a, 'r> =
+        StorageManager<'a, 'r, X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, X86_64SystemV>;
+
+    fn test_env(arena: &Bump) -> Env<'_> {
+        EnvBuilder::new(arena, ModuleId::ATTR)
+            .lazy_literals(false)
+            .mode(AssemblyBackendMode::Test)
+            .opt_level(OptLevel::Optimize)
+            .build()
+    }
+
+    fn test_storage_manager<'a, 'r>(env: &'r Env<'a>) -> TestStorageManager<'a, 'r> {
+        let mut storage_manager = new_storage_manager(env, TargetInfo::default_x86_64());
+        storage_manager.reset();
+        storage_manager
+    }
+
+    fn sym(index: u32) -> Symbol {
+        Symbol::from_index(ModuleId::ATTR, index)
+    }
+
+    fn intervals(ends: &[u32]) -> MutMap<Symbol, LiveInterval> {
+        ends.iter()
+            .enumerate()
+            .map(|(i, end)| {
+                (
+                    sym(i as u32),
+                    LiveInterval {
+                        start: 0,
+                        end: *end,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn claimed_and_freed_storage_keeps_the_invariants() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+        assert_eq!(storage_manager.check_invariants(), Ok(()));
+
+        storage_manager.claim_general_reg(&mut buf, &sym(0));
+        storage_manager.claim_float_reg(&mut buf, &sym(1));
+        storage_manager.claim_stack_area(&sym(2), 24);
+        storage_manager.claim_stack_area(&sym(3), 8);
+        assert_eq!(storage_manager.check_invariants(), Ok(()));
+
+        storage_manager.free_symbol(&sym(0));
+        storage_manager.free_symbol(&sym(2));
+        assert_eq!(storage_manager.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn double_booked_reg_breaks_the_invariants() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg = storage_manager.claim_general_reg(&mut buf, &sym(0));
+        storage_manager.general_free_regs.push(reg);
+        assert!(storage_manager.check_invariants().is_err());
+    }
+
+    #[test]
+    fn reg_of_a_moved_symbol_breaks_the_invariants() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        storage_manager.claim_general_reg(&mut buf, &sym(0));
+        storage_manager.symbol_storage_map.insert(sym(0), NoData);
+        assert!(storage_manager.check_invariants().is_err());
+    }
+
+    #[test]
+    fn symbol_in_a_freed_stack_chunk_breaks_the_invariants() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+
+        let base_offset = storage_manager.claim_stack_area(&sym(0), 16);
+        storage_manager.free_stack_chunk(base_offset + 8, 8);
+        assert!(storage_manager.check_invariants().is_err());
+    }
+
+    #[test]
+    fn freeing_a_symbol_frees_its_reg() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg = storage_manager.claim_general_reg(&mut buf, &sym(0));
+        assert!(!storage_manager.general_free_regs.contains(&reg));
+
+        storage_manager.free_symbol(&sym(0));
+        assert_eq!(storage_manager.general_free_regs.last(), Some(&reg));
+        assert!(storage_manager.general_used_regs.is_empty());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn spills_the_symbol_that_lives_longest() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len();
+        let mut ends = vec![10; reg_count + 1];
+        ends[1] = 100;
+        storage_manager.set_live_intervals(intervals(&ends), vec![]);
+
+        for i in 0..reg_count {
+            storage_manager.claim_general_reg(&mut buf, &sym(i as u32));
+        }
+        assert_eq!(storage_manager.stack_size(), 0);
+
+        storage_manager.claim_general_reg(&mut buf, &sym(reg_count as u32));
+        assert_eq!(
+            storage_manager.symbol_storage_map.get(&sym(1)),
+            Some(&Stack(Primitive {
+                base_offset: -8,
+                reg: None
+            }))
+        );
+        assert_eq!(storage_manager.stack_size(), 8);
+    }
+
+    #[test]
+    fn reuses_the_spill_slot_of_a_dead_symbol() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len();
+        let mut ends = vec![40; reg_count + 2];
+        ends[0] = 20;
+        storage_manager.set_live_intervals(intervals(&ends), vec![]);
+
+        for i in 0..reg_count {
+            storage_manager.claim_general_reg(&mut buf, &sym(i as u32));
+        }
+        // All symbols live until 40 except sym(0), so the oldest one with the latest end is spilled.
+        storage_manager.claim_general_reg(&mut buf, &sym(reg_count as u32));
+        assert_eq!(storage_manager.stack_size(), 8);
+
+        // Once sym(1) is dead, its slot is taken by the next spill.
+        storage_manager.set_live_intervals(
+            {
+                let mut ends = ends.clone();
+                ends[1] = 20;
+                intervals(&ends)
+            },
+            vec![],
+        );
+        storage_manager.set_position(30);
+        storage_manager.claim_general_reg(&mut buf, &sym(reg_count as u32 + 1));
+        assert_eq!(storage_manager.stack_size(), 8);
+        assert_eq!(storage_manager.symbol_storage_map.get(&sym(1)), None);
+    }
+
+    #[test]
+    fn knows_which_symbols_are_in_general_regs() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg = storage_manager.claim_general_reg(&mut buf, &sym(0));
+        assert_eq!(storage_manager.general_reg_of(&sym(0)), Some(reg));
+
+        storage_manager.free_all_to_stack(&mut buf);
+        assert_eq!(storage_manager.general_reg_of(&sym(0)), None);
+        assert_eq!(storage_manager.general_reg_of(&sym(1)), None);
+    }
+
+    #[test]
+    fn only_preloads_stack_primitives() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        storage_manager.claim_general_reg(&mut buf, &sym(0));
+        assert!(!storage_manager.can_preload(&sym(0)));
+        assert!(!storage_manager.can_preload(&sym(1)));
+
+        storage_manager.free_all_to_stack(&mut buf);
+        assert!(storage_manager.can_preload(&sym(0)));
+
+        // Without a spare register the symbol is left on the stack.
+        storage_manager.general_free_regs.truncate(1);
+        assert!(!storage_manager.can_preload(&sym(0)));
+    }
+
+    #[test]
+    fn constants_are_loaded_again_instead_of_spilled() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len();
+        storage_manager.set_live_intervals(intervals(&vec![10; reg_count + 1]), vec![]);
+
+        storage_manager.claim_general_reg(&mut buf, &sym(0));
+        storage_manager.record_constant(&sym(0), 42);
+        for i in 1..reg_count {
+            storage_manager.claim_general_reg(&mut buf, &sym(i as u32));
+        }
+        // The constant is dropped without storing it to the stack.
+        storage_manager.claim_general_reg(&mut buf, &sym(reg_count as u32));
+        assert!(buf.is_empty());
+        assert_eq!(storage_manager.stack_size(), 0);
+        assert_eq!(storage_manager.symbol_storage_map.get(&sym(0)), None);
+
+        storage_manager.free_symbol(&sym(1));
+        let reg = storage_manager.load_to_general_reg(&mut buf, &sym(0));
+        let mut expected = bumpalo::vec![in &arena];
+        X86_64Assembler::mov_reg64_imm64(&mut expected, reg, 42);
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn counts_spills_and_reloads() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut buf = bumpalo::vec![in &arena];
+
+        let reg_count = X86_64SystemV::GENERAL_DEFAULT_FREE_REGS.len();
+        storage_manager.set_live_intervals(intervals(&vec![10; reg_count + 1]), vec![]);
+        for i in 0..=reg_count {
+            storage_manager.claim_general_reg(&mut buf, &sym(i as u32));
+        }
+        assert_eq!(storage_manager.spills(), 1);
+        assert_eq!(storage_manager.reloads(), 0);
+
+        // Reloading the spilled symbol spills another one.
+        let spilled = (0..=reg_count as u32)
+            .map(sym)
+            .find(|s| matches!(storage_manager.symbol_storage_map.get(s), Some(Stack(_))))
+            .unwrap();
+        storage_manager.load_to_general_reg(&mut buf, &spilled);
+        assert_eq!(storage_manager.spills(), 2);
+        assert_eq!(storage_manager.reloads(), 1);
+
+        // Clones share the counts, like the storage of the branches of a switch.
+        let mut branch_storage = storage_manager.clone();
+        branch_storage.claim_general_reg(&mut buf, &sym(reg_count as u32 + 1));
+        assert_eq!(storage_manager.spills(), 3);
+    }
+
+    #[test]
+    fn max_live_values_counts_overlapping_intervals() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+
+        let mut live_intervals = MutMap::default();
+        live_intervals.insert(sym(0), LiveInterval { start: 0, end: 3 });
+        live_intervals.insert(sym(1), LiveInterval { start: 1, end: 2 });
+        live_intervals.insert(sym(2), LiveInterval { start: 3, end: 5 });
+        live_intervals.insert(sym(3), LiveInterval { start: 4, end: 5 });
+        storage_manager.set_live_intervals(live_intervals, vec![]);
+        assert_eq!(storage_manager.max_live_values(), 2);
+    }
+
+    #[test]
+    fn freed_stack_chunks_are_merged() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+
+        assert_eq!(storage_manager.claim_stack_area(&sym(0), 8), -8);
+        assert_eq!(storage_manager.claim_stack_area(&sym(1), 8), -16);
+        storage_manager.free_symbol(&sym(0));
+        storage_manager.free_symbol(&sym(1));
+        assert_eq!(storage_manager.free_stack_chunks.as_slice(), &[(-16, 16)]);
+
+        assert_eq!(storage_manager.claim_stack_area(&sym(2), 16), -16);
+        assert_eq!(storage_manager.stack_size(), 16);
+    }
+
+    #[test]
+    fn zero_sized_fields_have_no_storage() {
+        let arena = Bump::new();
+        let env = test_env(&arena);
+        let mut storage_manager = test_storage_manager(&env);
+        let mut layout_interner = STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64());
+        let mut buf = bumpalo::vec![in &arena];
+
+        let field_layouts: &[_] = arena.alloc([Layout::I64, Layout::UNIT, Layout::I64]);
+        let layout = layout_interner.insert_direct_no_semantic(LayoutRepr::struct_(field_layouts));
+        storage_manager.claim_general_reg(&mut buf, &sym(0));
+        storage_manager.claim_general_reg(&mut buf, &sym(2));
+
+        // The unit field never got any storage, it is skipped instead of looked up.
+        let fields: &[_] = arena.alloc([sym(0), sym(1), sym(2)]);
+        storage_manager.create_struct(&mut layout_interner, &mut buf, &sym(3), &layout, fields);
+        assert_eq!(storage_manager.stack_offset_and_size(&sym(3)), (-16, 16));
+
+        storage_manager.load_field_at_index(
+            &mut layout_interner,
+            &sym(4),
+            &sym(3),
+            1,
+            field_layouts,
+        );
+        assert_eq!(
+            storage_manager.symbol_storage_map.get(&sym(4)),
+            Some(&NoData)
+        );
+        storage_manager.load_field_at_index(
+            &mut layout_interner,
+            &sym(5),
+            &sym(3),
+            2,
+            field_layouts,
+        );
+        assert!(storage_manager.is_stored_primitive(&sym(5)));
+    }
+
+    fn record_mov_reg_reg(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
+        buf.extend([0, dst.value(), src.value()]);
+    }
+
+    fn record_mov_reg_base32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, offset: i32) {
+        buf.extend([1, dst.value(), offset as u8]);
+    }
+
+    fn record_mov_base32_reg(buf: &mut Vec<'_, u8>, offset: i32, src: X86_64GeneralReg) {
+        buf.extend([2, offset as u8, src.value()]);
+    }
+
+    /// Runs the moves recorded in `buf` on registers that start out holding their own index.
+    fn run_recorded_moves(buf: &[u8], stack: &mut MutMap<u8, u64>) -> [u64; 16] {
+        let mut regs: [u64; 16] = std::array::from_fn(|i| i as u64);
+        for op in buf.chunks(3) {
+            match op[0] {
+                0 => regs[op[1] as usize] = regs[op[2] as usize],
+                1 => regs[op[1] as usize] = stack[&op[2]],
+                _ => {
+                    stack.insert(op[1], regs[op[2] as usize]);
+                }
+            }
+        }
+        regs
+    }
+
+    fn emit_recorded_moves<'a>(
+        buf: &mut Vec<'a, u8>,
+        moves: std::vec::Vec<(X86_64GeneralReg, MoveSource<X86_64GeneralReg>)>,
+        tmp_slots: &mut u32,
+    ) {
+        emit_parallel_moves(
+            buf,
+            moves,
+            record_mov_reg_reg,
+            record_mov_reg_base32,
+            record_mov_base32_reg,
+            || {
+                *tmp_slots += 1;
+                -16
+            },
+        );
+    }
+
+    #[test]
+    fn parallel_moves_follow_chains_without_a_tmp_slot() {
+        use X86_64GeneralReg::*;
+        let arena = Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        let mut tmp_slots = 0;
+        emit_recorded_moves(
+            &mut buf,
+            vec![
+                (RCX, MoveSource::Reg(RDX)),
+                (RAX, MoveSource::Reg(RCX)),
+                (RBX, MoveSource::Reg(RBX)),
+            ],
+            &mut tmp_slots,
+        );
+        assert_eq!(tmp_slots, 0);
+        assert_eq!(buf.len(), 6);
+
+        let regs = run_recorded_moves(&buf, &mut MutMap::default());
+        assert_eq!(regs[RAX as usize], RCX as u64);
+        assert_eq!(regs[RCX as usize], RDX as u64);
+        assert_eq!(regs[RBX as usize], RBX as u64);
+    }
+
+    #[test]
+    fn parallel_moves_break_cycles_through_the_stack() {
+        use X86_64GeneralReg::*;
+        let arena = Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        let mut tmp_slots = 0;
+        emit_recorded_moves(
+            &mut buf,
+            vec![
+                (RAX, MoveSource::Reg(RCX)),
+                (RCX, MoveSource::Reg(RAX)),
+                (RSI, MoveSource::Reg(RAX)),
+                (RDX, MoveSource::Stack(-8)),
+                (R8, MoveSource::Reg(R9)),
+                (R9, MoveSource::Reg(R8)),
+            ],
+            &mut tmp_slots,
+        );
+        assert_eq!(tmp_slots, 2);
+
+        let mut stack = MutMap::default();
+        stack.insert(-8i32 as u8, 42);
+        let regs = run_recorded_moves(&buf, &mut stack);
+        assert_eq!(regs[RAX as usize], RCX as u64);
+        assert_eq!(regs[RCX as usize], RAX as u64);
+        assert_eq!(regs[RSI as usize], RAX as u64);
+        assert_eq!(regs[RDX as usize], 42);
+        assert_eq!(regs[R8 as usize], R9 as u64);
+        assert_eq!(regs[R9 as usize], R8 as u64);
+    }
+}
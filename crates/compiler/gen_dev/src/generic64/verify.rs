@@ -0,0 +1,159 @@
+//! Decodes the code of finalized procs to catch encoder bugs, see `Env::verify_machine_code`.
+//! An encoder that gets the length of an instruction wrong throws off the decoding of everything after it,
+//! and one that gets an offset wrong sends a jump or relocation into the middle of an instruction.
+
+use crate::generic64::{Assembler, RegTrait};
+use crate::Relocation;
+
+/// Checks that `code` decodes into a sequence of instructions that covers it exactly,
+/// that every jump lands on one of them or on the end of the code,
+/// and that every relocation patches a field that relocations can patch.
+pub fn verify_code<GeneralReg: RegTrait, FloatReg: RegTrait, ASM>(
+    code: &[u8],
+    relocs: &[Relocation<'_>],
+) -> Result<(), String>
+where
+    ASM: Assembler<GeneralReg, FloatReg>,
+{
+    let mut starts = std::vec::Vec::new();
+    let mut reloc_fields = std::vec::Vec::new();
+    let mut jumps = std::vec::Vec::new();
+
+    let mut offset = 0;
+    while offset < code.len() {
+        let inst = match ASM::decode_instruction(&code[offset..]) {
+            Some(inst) => inst,
+            None => {
+                let end = std::cmp::min(offset + 16, code.len());
+                return Err(format!(
+                    "no instruction at {:#x}: {:02x?}",
+                    offset,
+                    &code[offset..end]
+                ));
+            }
+        };
+        starts.push(offset);
+        if let Some(field) = inst.reloc_field {
+            reloc_fields.push(offset + field);
+        }
+        if let Some(target) = inst.jump_target {
+            jumps.push((offset, offset as i64 + target));
+        }
+        offset += inst.len;
+    }
+
+    for (offset, target) in jumps {
+        let lands = target == code.len() as i64
+            || usize::try_from(target)
+                .map_or(false, |target| starts.binary_search(&target).is_ok());
+        if !lands {
+            return Err(format!(
+                "the jump at {:#x} goes to {:#x}, which is not the start of an instruction",
+                offset, target
+            ));
+        }
+    }
+
+    for reloc in relocs {
+        let offset = match reloc {
+            Relocation::LocalData { offset, .. }
+            | Relocation::LinkedFunction { offset, .. }
+            | Relocation::LinkedFunctionPage { offset, .. }
+            | Relocation::LinkedData { offset, .. } => *offset as usize,
+            Relocation::JmpToReturn { inst_loc, .. } => *inst_loc as usize,
+        };
+        if reloc_fields.binary_search(&offset).is_err() {
+            return Err(format!(
+                "the relocation at {:#x} does not patch the operand of an instruction: {:?}",
+                offset, reloc
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic64::aarch64::{AArch64Assembler, AArch64FloatReg, AArch64GeneralReg};
+    use crate::generic64::x86_64::{X86_64Assembler, X86_64FloatReg, X86_64GeneralReg};
+    use bumpalo::Bump;
+
+    fn verify_x86_64(code: &[u8], relocs: &[Relocation<'_>]) -> Result<(), String> {
+        verify_code::<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler>(code, relocs)
+    }
+
+    fn verify_aarch64(code: &[u8], relocs: &[Relocation<'_>]) -> Result<(), String> {
+        verify_code::<AArch64GeneralReg, AArch64FloatReg, AArch64Assembler>(code, relocs)
+    }
+
+    #[test]
+    fn x86_64_code_from_the_assembler_verifies() {
+        let arena = Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        let mut relocs = bumpalo::vec![in &arena];
+        use X86_64GeneralReg::*;
+
+        X86_64Assembler::mov_reg64_imm64(&mut buf, RAX, 1);
+        X86_64Assembler::mov_reg64_imm64(&mut buf, R11, 0x1234_5678_9abc);
+        X86_64Assembler::add_reg64_reg64_reg64(&mut buf, RAX, RAX, R11);
+        X86_64Assembler::mov_reg64_base32(&mut buf, RDX, -8);
+        X86_64Assembler::mov_reg16_mem16_offset32(&mut buf, RCX, RSP, 0x100);
+        X86_64Assembler::mov_freg64_imm64(&mut buf, &mut relocs, X86_64FloatReg::XMM3, 2.5);
+        X86_64Assembler::call(&mut buf, &mut relocs, "roc_alloc".to_string());
+        X86_64Assembler::jmp_imm32(&mut buf, 0);
+        X86_64Assembler::ret(&mut buf);
+
+        assert_eq!(verify_x86_64(&buf, &relocs), Ok(()));
+    }
+
+    #[test]
+    fn x86_64_cut_off_instruction_fails() {
+        // `mov rax, imm64` without the last byte of the immediate.
+        let code = [0x48, 0xB8, 1, 2, 3, 4, 5, 6, 7];
+        assert!(verify_x86_64(&code, &[]).is_err());
+    }
+
+    #[test]
+    fn x86_64_jump_into_an_instruction_fails() {
+        // `jmp +1` into the immediate of `mov eax, imm32`, then `ret`.
+        let code = [0xEB, 0x01, 0xB8, 1, 2, 3, 4, 0xC3];
+        assert!(verify_x86_64(&code, &[]).is_err());
+
+        // `jmp +5` to the ret.
+        let code = [0xEB, 0x05, 0xB8, 1, 2, 3, 4, 0xC3];
+        assert_eq!(verify_x86_64(&code, &[]), Ok(()));
+    }
+
+    #[test]
+    fn x86_64_relocation_outside_of_an_operand_fails() {
+        // `call rel32`, with the relocation on the opcode instead of the offset.
+        let code = [0xE8, 0, 0, 0, 0];
+        let reloc = |offset| Relocation::LinkedFunction {
+            offset,
+            name: "roc_alloc".to_string(),
+            addend: 0,
+        };
+        assert!(verify_x86_64(&code, &[reloc(0)]).is_err());
+        assert_eq!(verify_x86_64(&code, &[reloc(1)]), Ok(()));
+    }
+
+    #[test]
+    fn aarch64_code_verifies() {
+        let arena = Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        use AArch64GeneralReg::*;
+
+        AArch64Assembler::mov_reg64_imm64(&mut buf, X0, 1);
+        AArch64Assembler::add_reg64_reg64_reg64(&mut buf, X0, X0, X1);
+        AArch64Assembler::jmp_imm32(&mut buf, 4);
+        AArch64Assembler::ret(&mut buf);
+
+        assert_eq!(verify_aarch64(&buf, &[]), Ok(()));
+
+        // Cut off, and zero, which is `udf #0`.
+        assert!(verify_aarch64(&buf[..buf.len() - 2], &[]).is_err());
+        assert!(verify_aarch64(&[0; 4], &[]).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use crate::generic64::{storage::StorageManager, Assembler, CallConv, RegTrait};
 use crate::{
-    pointer_layouts, single_register_floats, single_register_int_builtins,
+    local_data, pointer_layouts, single_register_floats, single_register_int_builtins,
     single_register_integers, single_register_layouts, Relocation,
 };
 use bumpalo::collections::Vec;
@@ -8,10 +8,11 @@ use roc_builtins::bitcode::{FloatWidth, IntWidth};
 use roc_error_macros::internal_error;
 use roc_module::symbol::Symbol;
 use roc_mono::layout::{
-    Builtin, InLayout, LayoutInterner, LayoutRepr, STLayoutInterner, UnionLayout,
+    round_up_to_alignment, Builtin, InLayout, LayoutInterner, LayoutRepr, STLayoutInterner,
+    UnionLayout,
 };
 
-use super::{CompareOperation, RegisterWidth};
+use super::{CompareOperation, DecodedInstruction, ImmOperation, IntCondition, RegisterWidth};
 
 // Not sure exactly how I want to represent registers.
 // If we want max speed, we would likely make them structs that impl the same trait to avoid ifs.
@@ -126,78 +127,20 @@ pub struct X86_64SystemV {}
 
 const STACK_ALIGNMENT: u8 = 16;
 
-impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64SystemV {
-    const BASE_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RBP;
-    const STACK_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RSP;
-
-    const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] = &[
-        X86_64GeneralReg::RDI,
-        X86_64GeneralReg::RSI,
-        X86_64GeneralReg::RDX,
-        X86_64GeneralReg::RCX,
-        X86_64GeneralReg::R8,
-        X86_64GeneralReg::R9,
-    ];
-    const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] =
-        &[X86_64GeneralReg::RAX, X86_64GeneralReg::RDX];
-    const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] = &[
-        // The regs we want to use first should be at the end of this vec.
-        // We will use pop to get which reg to use next
-        // Use callee saved regs last.
-        X86_64GeneralReg::RBX,
-        // Don't use frame pointer: X86_64GeneralReg::RBP,
-        X86_64GeneralReg::R12,
-        X86_64GeneralReg::R13,
-        X86_64GeneralReg::R14,
-        X86_64GeneralReg::R15,
-        // Use caller saved regs first.
-        X86_64GeneralReg::RAX,
-        X86_64GeneralReg::RCX,
-        X86_64GeneralReg::RDX,
-        // Don't use stack pointer: X86_64GeneralReg::RSP,
-        X86_64GeneralReg::RSI,
-        X86_64GeneralReg::RDI,
-        X86_64GeneralReg::R8,
-        X86_64GeneralReg::R9,
-        X86_64GeneralReg::R10,
-        X86_64GeneralReg::R11,
-    ];
-
-    const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = &[
-        X86_64FloatReg::XMM0,
-        X86_64FloatReg::XMM1,
-        X86_64FloatReg::XMM2,
-        X86_64FloatReg::XMM3,
-        X86_64FloatReg::XMM4,
-        X86_64FloatReg::XMM5,
-        X86_64FloatReg::XMM6,
-        X86_64FloatReg::XMM7,
-    ];
-    const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] =
-        &[X86_64FloatReg::XMM0, X86_64FloatReg::XMM1];
-    const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] = &[
-        // The regs we want to use first should be at the end of this vec.
-        // We will use pop to get which reg to use next
-        // No callee saved regs.
-        // Use caller saved regs first.
-        X86_64FloatReg::XMM15,
-        X86_64FloatReg::XMM14,
-        X86_64FloatReg::XMM13,
-        X86_64FloatReg::XMM12,
-        X86_64FloatReg::XMM11,
-        X86_64FloatReg::XMM10,
-        X86_64FloatReg::XMM9,
-        X86_64FloatReg::XMM8,
-        X86_64FloatReg::XMM7,
-        X86_64FloatReg::XMM6,
-        X86_64FloatReg::XMM5,
-        X86_64FloatReg::XMM4,
-        X86_64FloatReg::XMM3,
-        X86_64FloatReg::XMM2,
-        X86_64FloatReg::XMM1,
-        X86_64FloatReg::XMM0,
-    ];
-    const SHADOW_SPACE_SIZE: u8 = 0;
+impl<ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>
+    CallConv<X86_64GeneralReg, X86_64FloatReg, ASM> for X86_64SystemV
+{
+    const BASE_PTR_REG: X86_64GeneralReg = X86_64SystemV::BASE_PTR_REG;
+    const STACK_PTR_REG: X86_64GeneralReg = X86_64SystemV::STACK_PTR_REG;
+    const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] = X86_64SystemV::GENERAL_PARAM_REGS;
+    const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] = X86_64SystemV::GENERAL_RETURN_REGS;
+    const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] =
+        X86_64SystemV::GENERAL_DEFAULT_FREE_REGS;
+    const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = X86_64SystemV::FLOAT_PARAM_REGS;
+    const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] = X86_64SystemV::FLOAT_RETURN_REGS;
+    const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] =
+        X86_64SystemV::FLOAT_DEFAULT_FREE_REGS;
+    const SHADOW_SPACE_SIZE: u8 = X86_64SystemV::SHADOW_SPACE_SIZE;
 
     #[inline(always)]
     fn general_callee_saved(reg: &X86_64GeneralReg) -> bool {
@@ -253,13 +196,13 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
 
     #[inline(always)]
     fn load_args<'a>(
-        _buf: &mut Vec<'a, u8>,
+        buf: &mut Vec<'a, u8>,
         storage_manager: &mut StorageManager<
             'a,
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64SystemV,
         >,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -281,7 +224,40 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
         }
 
         for (in_layout, sym) in args.iter() {
-            state.load_arg(storage_manager, layout_interner, *sym, *in_layout);
+            state.load_arg(buf, storage_manager, layout_interner, *sym, *in_layout);
+        }
+    }
+
+    fn load_host_args<'a>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
+            'a,
+            '_,
+            X86_64GeneralReg,
+            X86_64FloatReg,
+            ASM,
+            X86_64SystemV,
+        >,
+        layout_interner: &mut STLayoutInterner<'a>,
+        args: &'a [(InLayout<'a>, Symbol)],
+        ret_layout: &InLayout<'a>,
+    ) {
+        // Results over 16 bytes are written to memory that the caller passes a pointer to (sret) either way.
+        let returns_via_pointer =
+            X86_64SystemV::returns_via_arg_pointer(layout_interner, ret_layout);
+
+        let mut state = X64_64SystemVLoadArgs {
+            general_i: usize::from(returns_via_pointer),
+            float_i: 0,
+            argument_offset: X86_64SystemV::SHADOW_SPACE_SIZE as i32 + 16,
+        };
+
+        if returns_via_pointer {
+            storage_manager.ret_pointer_arg(X86_64SystemV::GENERAL_PARAM_REGS[0]);
+        }
+
+        for (in_layout, sym) in args.iter() {
+            state.load_host_arg(buf, storage_manager, layout_interner, *sym, *in_layout);
         }
     }
 
@@ -293,7 +269,7 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64SystemV,
         >,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -311,12 +287,7 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
             // Set the first reg to the address base + offset.
             let ret_reg = Self::GENERAL_PARAM_REGS[general_i];
             general_i += 1;
-            X86_64Assembler::add_reg64_reg64_imm32(
-                buf,
-                ret_reg,
-                X86_64GeneralReg::RBP,
-                base_offset,
-            );
+            ASM::add_reg64_reg64_imm32(buf, ret_reg, X86_64GeneralReg::RBP, base_offset);
         }
 
         let mut state = X64_64SystemVStoreArgs {
@@ -332,6 +303,37 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
         storage_manager.update_fn_call_stack_size(state.tmp_stack_offset as u32);
     }
 
+    fn returns_via_arg_pointer<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        X86_64SystemV::returns_via_arg_pointer(layout_interner, ret_layout)
+    }
+
+    fn general_param_reg_for_arg<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        arg_layouts: &[InLayout<'a>],
+        index: usize,
+        ret_layout: &InLayout<'a>,
+    ) -> Option<X86_64GeneralReg> {
+        // This mirrors the register assignment of store_args for simple arguments.
+        let mut general_i = Self::returns_via_arg_pointer(layout_interner, ret_layout) as usize;
+        for layout in arg_layouts.get(..index)? {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() | pointer_layouts!() => general_i += 1,
+                single_register_floats!() => {}
+                _ if layout_interner.stack_size(*layout) == 0 => {}
+                _ => return None,
+            }
+        }
+        match layout_interner.get_repr(*arg_layouts.get(index)?) {
+            single_register_integers!() | pointer_layouts!() => {
+                Self::GENERAL_PARAM_REGS.get(general_i).copied()
+            }
+            _ => None,
+        }
+    }
+
     fn return_complex_symbol<'a>(
         buf: &mut Vec<'a, u8>,
         storage_manager: &mut StorageManager<
@@ -339,7 +341,7 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64SystemV,
         >,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -355,22 +357,10 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
                 let (base_offset, size) = storage_manager.stack_offset_and_size(sym);
                 debug_assert_eq!(base_offset % 8, 0);
                 if size <= 8 {
-                    X86_64Assembler::mov_reg64_base32(
-                        buf,
-                        Self::GENERAL_RETURN_REGS[0],
-                        base_offset,
-                    );
+                    ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset);
                 } else if size <= 16 {
-                    X86_64Assembler::mov_reg64_base32(
-                        buf,
-                        Self::GENERAL_RETURN_REGS[0],
-                        base_offset,
-                    );
-                    X86_64Assembler::mov_reg64_base32(
-                        buf,
-                        Self::GENERAL_RETURN_REGS[1],
-                        base_offset + 8,
-                    );
+                    ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset);
+                    ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[1], base_offset + 8);
                 } else {
                     internal_error!(
                         "types that don't return via arg pointer must be less than 16 bytes"
@@ -397,7 +387,7 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64SystemV,
         >,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -415,14 +405,10 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Syste
                 let size = layout_interner.stack_size(*layout);
                 let offset = storage_manager.claim_stack_area(sym, size);
                 if size <= 8 {
-                    X86_64Assembler::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
+                    ASM::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
                 } else if size <= 16 {
-                    X86_64Assembler::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
-                    X86_64Assembler::mov_base32_reg64(
-                        buf,
-                        offset + 8,
-                        Self::GENERAL_RETURN_REGS[1],
-                    );
+                    ASM::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
+                    ASM::mov_base32_reg64(buf, offset + 8, Self::GENERAL_RETURN_REGS[1]);
                 } else {
                     internal_error!(
                         "types that don't return via arg pointer must be less than 16 bytes"
@@ -451,14 +437,26 @@ impl X64_64SystemVStoreArgs {
     const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = X86_64SystemV::FLOAT_PARAM_REGS;
     const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] = X86_64SystemV::FLOAT_RETURN_REGS;
 
-    fn store_arg<'a>(
+    fn store_arg<'a, ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
         buf: &mut Vec<'a, u8>,
-        storage_manager: &mut X86_64StorageManager<'a, '_, X86_64SystemV>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64SystemV>,
         layout_interner: &mut STLayoutInterner<'a>,
         sym: Symbol,
         in_layout: InLayout<'a>,
     ) {
+        if let Some(words) = captures_in_registers(layout_interner, in_layout) {
+            if self.general_i + words <= X86_64SystemV::GENERAL_PARAM_REGS.len() {
+                let (base_offset, _) = storage_manager.stack_offset_and_size(&sym);
+                for word in 0..words {
+                    let reg = X86_64SystemV::GENERAL_PARAM_REGS[self.general_i];
+                    ASM::mov_reg64_base32(buf, reg, base_offset + 8 * word as i32);
+                    self.general_i += 1;
+                }
+                return;
+            }
+        }
+
         match layout_interner.get_repr(in_layout) {
             single_register_integers!() => self.store_arg_general(buf, storage_manager, sym),
             pointer_layouts!() => self.store_arg_general(buf, storage_manager, sym),
@@ -470,19 +468,23 @@ impl X64_64SystemVStoreArgs {
                     let reg1 = Self::GENERAL_PARAM_REGS[self.general_i];
                     let reg2 = Self::GENERAL_PARAM_REGS[self.general_i + 1];
 
-                    X86_64Assembler::mov_reg64_base32(buf, reg1, offset);
-                    X86_64Assembler::mov_reg64_base32(buf, reg2, offset + 8);
+                    ASM::mov_reg64_base32(buf, reg1, offset);
+                    ASM::mov_reg64_base32(buf, reg2, offset + 8);
 
                     self.general_i += 2;
                 } else {
+                    // 128 bit integers on the stack are 16 byte aligned, like the C ABI wants them.
+                    self.tmp_stack_offset =
+                        round_up_to_alignment(self.tmp_stack_offset as u32, 16) as i32;
+
                     // Copy to stack using return reg as buffer.
                     let reg = Self::GENERAL_RETURN_REGS[0];
 
-                    X86_64Assembler::mov_reg64_base32(buf, reg, offset);
-                    X86_64Assembler::mov_stack32_reg64(buf, self.tmp_stack_offset, reg);
+                    ASM::mov_reg64_base32(buf, reg, offset);
+                    ASM::mov_stack32_reg64(buf, self.tmp_stack_offset, reg);
 
-                    X86_64Assembler::mov_reg64_base32(buf, reg, offset + 8);
-                    X86_64Assembler::mov_stack32_reg64(buf, self.tmp_stack_offset + 8, reg);
+                    ASM::mov_reg64_base32(buf, reg, offset + 8);
+                    ASM::mov_stack32_reg64(buf, self.tmp_stack_offset + 8, reg);
 
                     self.tmp_stack_offset += 16;
                 }
@@ -495,18 +497,14 @@ impl X64_64SystemVStoreArgs {
                 let (base_offset, size) = storage_manager.stack_offset_and_size(&sym);
                 debug_assert_eq!(base_offset % 8, 0);
                 for i in (0..size as i32).step_by(8) {
-                    X86_64Assembler::mov_reg64_base32(
-                        buf,
-                        Self::GENERAL_RETURN_REGS[0],
-                        base_offset + i,
-                    );
-                    X86_64Assembler::mov_stack32_reg64(
+                    ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset + i);
+                    ASM::mov_stack32_reg64(
                         buf,
                         self.tmp_stack_offset + i,
                         Self::GENERAL_RETURN_REGS[0],
                     );
                 }
-                self.tmp_stack_offset += size as i32;
+                self.tmp_stack_offset += round_up_to_alignment(size, 8) as i32;
             }
             LayoutRepr::LambdaSet(lambda_set) => self.store_arg(
                 buf,
@@ -520,22 +518,16 @@ impl X64_64SystemVStoreArgs {
                 let (base_offset, size) = storage_manager.stack_offset_and_size(&sym);
                 debug_assert_eq!(base_offset % 8, 0);
                 for i in (0..size as i32).step_by(8) {
-                    X86_64Assembler::mov_reg64_base32(
-                        buf,
-                        Self::GENERAL_RETURN_REGS[0],
-                        base_offset + i,
-                    );
-                    X86_64Assembler::mov_stack32_reg64(
+                    ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset + i);
+                    ASM::mov_stack32_reg64(
                         buf,
                         self.tmp_stack_offset + i,
                         Self::GENERAL_RETURN_REGS[0],
                     );
                 }
-                self.tmp_stack_offset += size as i32;
+                self.tmp_stack_offset += round_up_to_alignment(size, 8) as i32;
             }
             LayoutRepr::Union(UnionLayout::NonRecursive(_)) => {
-                type ASM = X86_64Assembler;
-
                 let tmp_reg = Self::GENERAL_RETURN_REGS[0];
                 let stack_offset = self.tmp_stack_offset;
 
@@ -578,7 +570,7 @@ impl X64_64SystemVStoreArgs {
                     }
                 }
 
-                self.tmp_stack_offset += size as i32;
+                self.tmp_stack_offset += round_up_to_alignment(size, 8) as i32;
             }
             _ => {
                 todo!(
@@ -589,10 +581,10 @@ impl X64_64SystemVStoreArgs {
         }
     }
 
-    fn store_arg_general<'a>(
+    fn store_arg_general<'a, ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
         buf: &mut Vec<'a, u8>,
-        storage_manager: &mut X86_64StorageManager<'a, '_, X86_64SystemV>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64SystemV>,
         sym: Symbol,
     ) {
         match Self::GENERAL_PARAM_REGS.get(self.general_i) {
@@ -605,17 +597,17 @@ impl X64_64SystemVStoreArgs {
                 let tmp = Self::GENERAL_RETURN_REGS[0];
 
                 storage_manager.load_to_specified_general_reg(buf, &sym, tmp);
-                X86_64Assembler::mov_stack32_reg64(buf, self.tmp_stack_offset, tmp);
+                ASM::mov_stack32_reg64(buf, self.tmp_stack_offset, tmp);
 
                 self.tmp_stack_offset += 8;
             }
         }
     }
 
-    fn store_arg_float<'a>(
+    fn store_arg_float<'a, ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
         buf: &mut Vec<'a, u8>,
-        storage_manager: &mut X86_64StorageManager<'a, '_, X86_64SystemV>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64SystemV>,
         sym: Symbol,
     ) {
         match Self::FLOAT_PARAM_REGS.get(self.float_i) {
@@ -628,7 +620,7 @@ impl X64_64SystemVStoreArgs {
                 let tmp = Self::FLOAT_RETURN_REGS[0];
 
                 storage_manager.load_to_specified_float_reg(buf, &sym, tmp);
-                X86_64Assembler::mov_stack32_freg64(buf, self.tmp_stack_offset, tmp);
+                ASM::mov_stack32_freg64(buf, self.tmp_stack_offset, tmp);
 
                 self.tmp_stack_offset += 8;
             }
@@ -642,18 +634,32 @@ struct X64_64SystemVLoadArgs {
     argument_offset: i32,
 }
 
-type X86_64StorageManager<'a, 'r, CallConv> =
-    StorageManager<'a, 'r, X86_64GeneralReg, X86_64FloatReg, X86_64Assembler, CallConv>;
+type X86_64StorageManager<'a, 'r, ASM, CallConv> =
+    StorageManager<'a, 'r, X86_64GeneralReg, X86_64FloatReg, ASM, CallConv>;
 
 impl X64_64SystemVLoadArgs {
-    fn load_arg<'a>(
+    fn load_arg<'a, ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
-        storage_manager: &mut X86_64StorageManager<'a, '_, X86_64SystemV>,
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64SystemV>,
         layout_interner: &mut STLayoutInterner<'a>,
         sym: Symbol,
         in_layout: InLayout<'a>,
     ) {
         let stack_size = layout_interner.stack_size(in_layout);
+        if let Some(words) = captures_in_registers(layout_interner, in_layout) {
+            if self.general_i + words <= X86_64SystemV::GENERAL_PARAM_REGS.len() {
+                // Spill the captures to the stack, where the closure body reads them from.
+                let base_offset = storage_manager.claim_stack_area(&sym, stack_size);
+                for word in 0..words {
+                    let reg = X86_64SystemV::GENERAL_PARAM_REGS[self.general_i];
+                    ASM::mov_base32_reg64(buf, base_offset + 8 * word as i32, reg);
+                    self.general_i += 1;
+                }
+                return;
+            }
+        }
+
         match layout_interner.get_repr(in_layout) {
             single_register_integers!() => self.load_arg_general(storage_manager, sym),
             pointer_layouts!() => self.load_arg_general(storage_manager, sym),
@@ -664,9 +670,10 @@ impl X64_64SystemVLoadArgs {
             _ if stack_size > 16 => {
                 // TODO: Double check this.
                 storage_manager.complex_stack_arg(&sym, self.argument_offset, stack_size);
-                self.argument_offset += stack_size as i32;
+                self.argument_offset += round_up_to_alignment(stack_size, 8) as i32;
             }
             LayoutRepr::LambdaSet(lambda_set) => self.load_arg(
+                buf,
                 storage_manager,
                 layout_interner,
                 sym,
@@ -675,16 +682,30 @@ impl X64_64SystemVLoadArgs {
             LayoutRepr::Struct { .. } => {
                 // for now, just also store this on the stack
                 storage_manager.complex_stack_arg(&sym, self.argument_offset, stack_size);
-                self.argument_offset += stack_size as i32;
+                self.argument_offset += round_up_to_alignment(stack_size, 8) as i32;
             }
             LayoutRepr::Builtin(Builtin::Int(IntWidth::U128 | IntWidth::I128)) => {
-                storage_manager.complex_stack_arg(&sym, self.argument_offset, stack_size);
-                self.argument_offset += stack_size as i32;
+                // Like store_arg, use two registers while there are two left, otherwise the stack.
+                if self.general_i + 1 < X86_64SystemV::GENERAL_PARAM_REGS.len() {
+                    let reg1 = X86_64SystemV::GENERAL_PARAM_REGS[self.general_i];
+                    let reg2 = X86_64SystemV::GENERAL_PARAM_REGS[self.general_i + 1];
+
+                    let base_offset = storage_manager.claim_stack_area(&sym, stack_size);
+                    ASM::mov_base32_reg64(buf, base_offset, reg1);
+                    ASM::mov_base32_reg64(buf, base_offset + 8, reg2);
+
+                    self.general_i += 2;
+                } else {
+                    self.argument_offset =
+                        round_up_to_alignment(self.argument_offset as u32, 16) as i32;
+                    storage_manager.complex_stack_arg(&sym, self.argument_offset, stack_size);
+                    self.argument_offset += stack_size as i32;
+                }
             }
             LayoutRepr::Union(UnionLayout::NonRecursive(_)) => {
                 // for now, just also store this on the stack
                 storage_manager.complex_stack_arg(&sym, self.argument_offset, stack_size);
-                self.argument_offset += stack_size as i32;
+                self.argument_offset += round_up_to_alignment(stack_size, 8) as i32;
             }
             _ => {
                 todo!(
@@ -695,9 +716,46 @@ impl X64_64SystemVLoadArgs {
         }
     }
 
-    fn load_arg_general(
+    /// Like load_arg, but structs, non-recursive tag unions and 128 bit integers of up to 16 bytes
+    /// come in general registers when all of their fields are integers, like the C ABI passes them.
+    /// Roc procs get them on the stack.
+    /// Small structs with floats are still loaded from the stack, the C ABI would pass them in float registers.
+    fn load_host_arg<'a, ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
+        &mut self,
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64SystemV>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        sym: Symbol,
+        in_layout: InLayout<'a>,
+    ) {
+        let stack_size = layout_interner.stack_size(in_layout);
+        let words = (stack_size as usize + 7) / 8;
+        let in_registers = matches!(
+            layout_interner.get_repr(in_layout),
+            LayoutRepr::Struct(_)
+                | LayoutRepr::Union(UnionLayout::NonRecursive(_))
+                | LayoutRepr::I128
+                | LayoutRepr::U128
+        ) && (1..=2).contains(&words)
+            && is_integer_class(layout_interner, in_layout)
+            && self.general_i + words <= X86_64SystemV::GENERAL_PARAM_REGS.len();
+
+        if !in_registers {
+            return self.load_arg(buf, storage_manager, layout_interner, sym, in_layout);
+        }
+
+        // Spill the registers to the stack, where Roc procs expect these values.
+        let base_offset = storage_manager.claim_stack_area(&sym, stack_size);
+        for word in 0..words {
+            let reg = X86_64SystemV::GENERAL_PARAM_REGS[self.general_i];
+            ASM::mov_base32_reg64(buf, base_offset + 8 * word as i32, reg);
+            self.general_i += 1;
+        }
+    }
+
+    fn load_arg_general<ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
-        storage_manager: &mut X86_64StorageManager<'_, '_, X86_64SystemV>,
+        storage_manager: &mut X86_64StorageManager<'_, '_, ASM, X86_64SystemV>,
         sym: Symbol,
     ) {
         if self.general_i < X86_64SystemV::GENERAL_PARAM_REGS.len() {
@@ -710,9 +768,9 @@ impl X64_64SystemVLoadArgs {
         }
     }
 
-    fn load_arg_float(
+    fn load_arg_float<ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>(
         &mut self,
-        storage_manager: &mut X86_64StorageManager<'_, '_, X86_64SystemV>,
+        storage_manager: &mut X86_64StorageManager<'_, '_, ASM, X86_64SystemV>,
         sym: Symbol,
     ) {
         if self.float_i < X86_64SystemV::FLOAT_PARAM_REGS.len() {
@@ -726,39 +784,75 @@ impl X64_64SystemVLoadArgs {
     }
 }
 
-impl X86_64SystemV {
-    fn returns_via_arg_pointer<'a>(
-        interner: &STLayoutInterner<'a>,
-        ret_layout: &InLayout<'a>,
-    ) -> bool {
-        // TODO: This will need to be more complex/extended to fully support the calling convention.
-        // details here: https://github.com/hjl-tools/x86-psABI/wiki/x86-64-psABI-1.0.pdf
-        interner.stack_size(*ret_layout) > 16
+/// The number of general registers the captures of a closure are passed in, if they are small enough.
+/// Captures that fit in two words of integers are passed by value in registers, like the C ABI passes small structs.
+/// Bigger closure environments are copied to the stack argument area, just like other big structs.
+/// This only applies to calls between Roc procs, the host never sees a lambda set.
+fn captures_in_registers<'a>(
+    interner: &STLayoutInterner<'a>,
+    layout: InLayout<'a>,
+) -> Option<usize> {
+    let LayoutRepr::LambdaSet(lambda_set) = interner.get_repr(layout) else {
+        return None;
+    };
+    let runtime_layout = lambda_set.runtime_representation();
+    if !matches!(
+        interner.get_repr(runtime_layout),
+        LayoutRepr::Struct(_) | LayoutRepr::Union(UnionLayout::NonRecursive(_))
+    ) {
+        // Enums and pointers already go in a single register.
+        return None;
+    }
+
+    let words = (interner.stack_size(runtime_layout) as usize + 7) / 8;
+    if (1..=2).contains(&words) && is_integer_class(interner, runtime_layout) {
+        Some(words)
+    } else {
+        None
     }
 }
 
-impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64WindowsFastcall {
-    const BASE_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RBP;
-    const STACK_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RSP;
+/// Whether the C ABI classifies every eightbyte of the layout as INTEGER.
+fn is_integer_class<'a>(interner: &STLayoutInterner<'a>, layout: InLayout<'a>) -> bool {
+    match interner.get_repr(layout) {
+        single_register_integers!() | pointer_layouts!() => true,
+        LayoutRepr::I128 | LayoutRepr::U128 => true,
+        LayoutRepr::Struct(field_layouts) => field_layouts
+            .iter()
+            .all(|field| is_integer_class(interner, *field)),
+        // Like a C struct of a union of the payloads, followed by the tag id.
+        // This is how a `Result` is passed to and from the host, see `RocResult` in roc_std.
+        LayoutRepr::Union(UnionLayout::NonRecursive(tags)) => tags
+            .iter()
+            .flat_map(|fields| fields.iter())
+            .all(|field| is_integer_class(interner, *field)),
+        LayoutRepr::LambdaSet(lambda_set) => {
+            is_integer_class(interner, lambda_set.runtime_representation())
+        }
+        _ => false,
+    }
+}
 
-    const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] = &[
-        X86_64GeneralReg::RCX,
+impl X86_64SystemV {
+    pub const BASE_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RBP;
+    pub const STACK_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RSP;
+
+    pub const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] = &[
+        X86_64GeneralReg::RDI,
+        X86_64GeneralReg::RSI,
         X86_64GeneralReg::RDX,
+        X86_64GeneralReg::RCX,
         X86_64GeneralReg::R8,
         X86_64GeneralReg::R9,
     ];
-    const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] = &[X86_64GeneralReg::RAX];
-    const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] = &[
+    pub const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] =
+        &[X86_64GeneralReg::RAX, X86_64GeneralReg::RDX];
+    pub const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] = &[
         // The regs we want to use first should be at the end of this vec.
         // We will use pop to get which reg to use next
-
-        // Don't use stack pointer: X86_64GeneralReg::RSP,
-        // Don't use frame pointer: X86_64GeneralReg::RBP,
-
         // Use callee saved regs last.
         X86_64GeneralReg::RBX,
-        X86_64GeneralReg::RSI,
-        X86_64GeneralReg::RDI,
+        // Don't use frame pointer: X86_64GeneralReg::RBP,
         X86_64GeneralReg::R12,
         X86_64GeneralReg::R13,
         X86_64GeneralReg::R14,
@@ -767,24 +861,34 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
         X86_64GeneralReg::RAX,
         X86_64GeneralReg::RCX,
         X86_64GeneralReg::RDX,
+        // Don't use stack pointer: X86_64GeneralReg::RSP,
+        X86_64GeneralReg::RSI,
+        X86_64GeneralReg::RDI,
         X86_64GeneralReg::R8,
         X86_64GeneralReg::R9,
         X86_64GeneralReg::R10,
         X86_64GeneralReg::R11,
     ];
-    const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = &[
+
+    pub const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = &[
         X86_64FloatReg::XMM0,
         X86_64FloatReg::XMM1,
         X86_64FloatReg::XMM2,
         X86_64FloatReg::XMM3,
+        X86_64FloatReg::XMM4,
+        X86_64FloatReg::XMM5,
+        X86_64FloatReg::XMM6,
+        X86_64FloatReg::XMM7,
     ];
-    const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] = &[X86_64FloatReg::XMM0];
-    const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] = &[
+    pub const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] =
+        &[X86_64FloatReg::XMM0, X86_64FloatReg::XMM1];
+    pub const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] = &[
         // The regs we want to use first should be at the end of this vec.
         // We will use pop to get which reg to use next
-        // Use callee saved regs last.
-        X86_64FloatReg::XMM15,
+        // No callee saved regs.
+        // Use caller saved regs first.
         X86_64FloatReg::XMM15,
+        X86_64FloatReg::XMM14,
         X86_64FloatReg::XMM13,
         X86_64FloatReg::XMM12,
         X86_64FloatReg::XMM11,
@@ -793,7 +897,6 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
         X86_64FloatReg::XMM8,
         X86_64FloatReg::XMM7,
         X86_64FloatReg::XMM6,
-        // Use caller saved regs first.
         X86_64FloatReg::XMM5,
         X86_64FloatReg::XMM4,
         X86_64FloatReg::XMM3,
@@ -801,7 +904,34 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
         X86_64FloatReg::XMM1,
         X86_64FloatReg::XMM0,
     ];
-    const SHADOW_SPACE_SIZE: u8 = 32;
+    pub const SHADOW_SPACE_SIZE: u8 = 0;
+
+    fn returns_via_arg_pointer<'a>(
+        interner: &STLayoutInterner<'a>,
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        // TODO: This will need to be more complex/extended to fully support the calling convention.
+        // details here: https://github.com/hjl-tools/x86-psABI/wiki/x86-64-psABI-1.0.pdf
+        interner.stack_size(*ret_layout) > 16
+    }
+}
+
+impl<ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>>
+    CallConv<X86_64GeneralReg, X86_64FloatReg, ASM> for X86_64WindowsFastcall
+{
+    const BASE_PTR_REG: X86_64GeneralReg = X86_64WindowsFastcall::BASE_PTR_REG;
+    const STACK_PTR_REG: X86_64GeneralReg = X86_64WindowsFastcall::STACK_PTR_REG;
+    const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] =
+        X86_64WindowsFastcall::GENERAL_PARAM_REGS;
+    const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] =
+        X86_64WindowsFastcall::GENERAL_RETURN_REGS;
+    const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] =
+        X86_64WindowsFastcall::GENERAL_DEFAULT_FREE_REGS;
+    const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = X86_64WindowsFastcall::FLOAT_PARAM_REGS;
+    const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] = X86_64WindowsFastcall::FLOAT_RETURN_REGS;
+    const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] =
+        X86_64WindowsFastcall::FLOAT_DEFAULT_FREE_REGS;
+    const SHADOW_SPACE_SIZE: u8 = X86_64WindowsFastcall::SHADOW_SPACE_SIZE;
 
     #[inline(always)]
     fn general_callee_saved(reg: &X86_64GeneralReg) -> bool {
@@ -821,7 +951,8 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
 
     #[inline(always)]
     fn float_callee_saved(reg: &X86_64FloatReg) -> bool {
-        matches!(
+        // XMM0 to XMM5 are volatile, the rest are nonvolatile.
+        !matches!(
             reg,
             X86_64FloatReg::XMM0
                 | X86_64FloatReg::XMM1
@@ -869,48 +1000,46 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
     #[inline(always)]
     fn load_args<'a>(
         _buf: &mut Vec<'a, u8>,
-        storage_manager: &mut X86_64StorageManager<'a, '_, X86_64WindowsFastcall>,
+        storage_manager: &mut X86_64StorageManager<'a, '_, ASM, X86_64WindowsFastcall>,
         layout_interner: &mut STLayoutInterner<'a>,
         args: &'a [(InLayout<'a>, Symbol)],
         ret_layout: &InLayout<'a>,
     ) {
         let mut arg_offset = Self::SHADOW_SPACE_SIZE as i32 + 16; // 16 is the size of the pushed return address and base pointer.
 
-        let mut general_registers_used = 0;
-        let mut float_registers_used = 0;
+        // Args are passed by position, the nth arg goes in the nth general or float param reg.
+        let mut slot = 0;
 
         if X86_64WindowsFastcall::returns_via_arg_pointer(layout_interner, ret_layout) {
             storage_manager.ret_pointer_arg(Self::GENERAL_PARAM_REGS[0]);
-            general_registers_used += 1;
+            slot += 1;
         }
 
         for (layout, sym) in args.iter() {
             match layout_interner.get_repr(*layout) {
-                single_register_integers!() => {
-                    match Self::GENERAL_PARAM_REGS.get(general_registers_used) {
-                        Some(reg) => {
-                            storage_manager.general_reg_arg(sym, *reg);
-                            general_registers_used += 1;
-                        }
-                        None => {
-                            storage_manager.primitive_stack_arg(sym, arg_offset);
-                            arg_offset += 8;
-                        }
+                single_register_integers!() => match Self::GENERAL_PARAM_REGS.get(slot) {
+                    Some(reg) => {
+                        storage_manager.general_reg_arg(sym, *reg);
+                        slot += 1;
                     }
-                }
-                single_register_floats!() => {
-                    match Self::FLOAT_PARAM_REGS.get(float_registers_used) {
-                        Some(reg) => {
-                            storage_manager.float_reg_arg(sym, *reg);
-                            float_registers_used += 1;
-                        }
-                        None => {
-                            storage_manager.primitive_stack_arg(sym, arg_offset);
-                            arg_offset += 8;
-                        }
+                    None => {
+                        storage_manager.primitive_stack_arg(sym, arg_offset);
+                        arg_offset += 8;
+                    }
+                },
+                single_register_floats!() => match Self::FLOAT_PARAM_REGS.get(slot) {
+                    Some(reg) => {
+                        storage_manager.float_reg_arg(sym, *reg);
+                        slot += 1;
                     }
+                    None => {
+                        storage_manager.primitive_stack_arg(sym, arg_offset);
+                        arg_offset += 8;
+                    }
+                },
+                _ if layout_interner.stack_size(*layout) == 0 => {
+                    storage_manager.no_data(sym);
                 }
-                _ if layout_interner.stack_size(*layout) == 0 => {}
                 x => {
                     todo!("Loading args with layout {:?}", x);
                 }
@@ -926,7 +1055,7 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64WindowsFastcall,
         >,
         layout_interner: &mut STLayoutInterner<'a>,
@@ -936,46 +1065,51 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
         ret_layout: &InLayout<'a>,
     ) {
         let mut tmp_stack_offset = Self::SHADOW_SPACE_SIZE as i32;
+
+        // This must assign slots exactly like load_args.
+        let mut slot = 0;
+
         if Self::returns_via_arg_pointer(layout_interner, ret_layout) {
-            // Save space on the stack for the arg we will return.
-            storage_manager.claim_stack_area(dst, layout_interner.stack_size(*ret_layout));
-            todo!("claim first parama reg for the address");
+            // Save space on the stack for the result we will be return.
+            let base_offset =
+                storage_manager.claim_stack_area(dst, layout_interner.stack_size(*ret_layout));
+            // Set the first reg to the address base + offset.
+            let ret_reg = Self::GENERAL_PARAM_REGS[slot];
+            slot += 1;
+            ASM::add_reg64_reg64_imm32(buf, ret_reg, X86_64GeneralReg::RBP, base_offset);
         }
 
-        let mut general_registers_used = 0;
-        let mut float_registers_used = 0;
-
         for (sym, layout) in args.iter().zip(arg_layouts.iter()) {
             match layout_interner.get_repr(*layout) {
                 single_register_integers!() => {
-                    match Self::GENERAL_PARAM_REGS.get(general_registers_used) {
+                    match Self::GENERAL_PARAM_REGS.get(slot) {
                         Some(reg) => {
                             storage_manager.load_to_specified_general_reg(buf, sym, *reg);
-                            general_registers_used += 1;
+                            slot += 1;
                         }
                         None => {
                             // Copy to stack using return reg as buffer.
                             let tmp = Self::GENERAL_RETURN_REGS[0];
 
                             storage_manager.load_to_specified_general_reg(buf, sym, tmp);
-                            X86_64Assembler::mov_stack32_reg64(buf, tmp_stack_offset, tmp);
+                            ASM::mov_stack32_reg64(buf, tmp_stack_offset, tmp);
 
                             tmp_stack_offset += 8;
                         }
                     }
                 }
                 single_register_floats!() => {
-                    match Self::FLOAT_PARAM_REGS.get(float_registers_used) {
+                    match Self::FLOAT_PARAM_REGS.get(slot) {
                         Some(reg) => {
                             storage_manager.load_to_specified_float_reg(buf, sym, *reg);
-                            float_registers_used += 1;
+                            slot += 1;
                         }
                         None => {
                             // Copy to stack using return reg as buffer.
                             let tmp = Self::FLOAT_RETURN_REGS[0];
 
                             storage_manager.load_to_specified_float_reg(buf, sym, tmp);
-                            X86_64Assembler::mov_stack32_freg64(buf, tmp_stack_offset, tmp);
+                            ASM::mov_stack32_freg64(buf, tmp_stack_offset, tmp);
 
                             tmp_stack_offset += 8;
                         }
@@ -990,49 +1124,208 @@ impl CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler> for X86_64Windo
         storage_manager.update_fn_call_stack_size(tmp_stack_offset as u32);
     }
 
-    fn return_complex_symbol<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
-            'a,
-            '_,
-            X86_64GeneralReg,
-            X86_64FloatReg,
-            X86_64Assembler,
-            X86_64WindowsFastcall,
-        >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _sym: &Symbol,
-        _layout: &InLayout<'a>,
-    ) {
-        todo!("Returning complex symbols for X86_64");
+    fn returns_via_arg_pointer<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        X86_64WindowsFastcall::returns_via_arg_pointer(layout_interner, ret_layout)
     }
 
-    fn load_returned_complex_symbol<'a>(
-        _buf: &mut Vec<'a, u8>,
-        _storage_manager: &mut StorageManager<
+    fn args_in_registers<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        arg_layouts: &[InLayout<'a>],
+        ret_layout: &InLayout<'a>,
+    ) -> bool {
+        // General and float args share the slots.
+        let slots = usize::from(Self::returns_via_arg_pointer(layout_interner, ret_layout))
+            + arg_layouts.len();
+        slots <= Self::GENERAL_PARAM_REGS.len()
+            && arg_layouts.iter().all(|layout| {
+                matches!(
+                    layout_interner.get_repr(*layout),
+                    single_register_integers!() | single_register_floats!()
+                )
+            })
+    }
+
+    fn general_param_reg_for_arg<'a>(
+        layout_interner: &STLayoutInterner<'a>,
+        arg_layouts: &[InLayout<'a>],
+        index: usize,
+        ret_layout: &InLayout<'a>,
+    ) -> Option<X86_64GeneralReg> {
+        // This mirrors the slot assignment of store_args, only the args before `index` need to be simple.
+        let slot = usize::from(Self::returns_via_arg_pointer(layout_interner, ret_layout)) + index;
+        for layout in arg_layouts.get(..index)? {
+            match layout_interner.get_repr(*layout) {
+                single_register_integers!() | single_register_floats!() => {}
+                _ => return None,
+            }
+        }
+        match layout_interner.get_repr(*arg_layouts.get(index)?) {
+            single_register_integers!() => Self::GENERAL_PARAM_REGS.get(slot).copied(),
+            _ => None,
+        }
+    }
+
+    fn return_complex_symbol<'a>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
+            'a,
+            '_,
+            X86_64GeneralReg,
+            X86_64FloatReg,
+            ASM,
+            X86_64WindowsFastcall,
+        >,
+        layout_interner: &mut STLayoutInterner<'a>,
+        sym: &Symbol,
+        layout: &InLayout<'a>,
+    ) {
+        match layout_interner.get_repr(*layout) {
+            single_register_layouts!() => {
+                internal_error!("single register layouts are not complex symbols");
+            }
+            _ if layout_interner.stack_size(*layout) == 0 => {}
+            LayoutRepr::I128 | LayoutRepr::U128 => {
+                let (base_offset, _) = storage_manager.stack_offset_and_size(sym);
+                ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset);
+                ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[1], base_offset + 8);
+            }
+            _ if !Self::returns_via_arg_pointer(layout_interner, layout) => {
+                let (base_offset, _) = storage_manager.stack_offset_and_size(sym);
+                debug_assert_eq!(base_offset % 8, 0);
+                ASM::mov_reg64_base32(buf, Self::GENERAL_RETURN_REGS[0], base_offset);
+            }
+            _ => {
+                storage_manager.copy_symbol_to_arg_pointer(buf, sym, layout);
+                // The caller gets the arg pointer back in the return reg.
+                storage_manager.load_to_specified_general_reg(
+                    buf,
+                    &Symbol::RET_POINTER,
+                    Self::GENERAL_RETURN_REGS[0],
+                );
+            }
+        }
+    }
+
+    fn load_returned_complex_symbol<'a>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<
             'a,
             '_,
             X86_64GeneralReg,
             X86_64FloatReg,
-            X86_64Assembler,
+            ASM,
             X86_64WindowsFastcall,
         >,
-        _layout_interner: &mut STLayoutInterner<'a>,
-        _sym: &Symbol,
-        _layout: &InLayout<'a>,
+        layout_interner: &mut STLayoutInterner<'a>,
+        sym: &Symbol,
+        layout: &InLayout<'a>,
     ) {
-        todo!("Loading returned complex symbols for X86_64");
+        match layout_interner.get_repr(*layout) {
+            single_register_layouts!() => {
+                internal_error!("single register layouts are not complex symbols");
+            }
+            _ if layout_interner.stack_size(*layout) == 0 => {
+                storage_manager.no_data(sym);
+            }
+            LayoutRepr::I128 | LayoutRepr::U128 => {
+                let offset = storage_manager.claim_stack_area(sym, 16);
+                ASM::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
+                ASM::mov_base32_reg64(buf, offset + 8, Self::GENERAL_RETURN_REGS[1]);
+            }
+            _ if !Self::returns_via_arg_pointer(layout_interner, layout) => {
+                let size = layout_interner.stack_size(*layout);
+                let offset = storage_manager.claim_stack_area(sym, size);
+                ASM::mov_base32_reg64(buf, offset, Self::GENERAL_RETURN_REGS[0]);
+            }
+            _ => {
+                // store_args passed a pointer to the stack area of the symbol, the callee wrote the result there.
+            }
+        }
     }
 }
 
 impl X86_64WindowsFastcall {
+    pub const BASE_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RBP;
+    pub const STACK_PTR_REG: X86_64GeneralReg = X86_64GeneralReg::RSP;
+
+    pub const GENERAL_PARAM_REGS: &'static [X86_64GeneralReg] = &[
+        X86_64GeneralReg::RCX,
+        X86_64GeneralReg::RDX,
+        X86_64GeneralReg::R8,
+        X86_64GeneralReg::R9,
+    ];
+    // RDX only has the upper half of a 128 bit integer, see `returns_via_arg_pointer`.
+    pub const GENERAL_RETURN_REGS: &'static [X86_64GeneralReg] =
+        &[X86_64GeneralReg::RAX, X86_64GeneralReg::RDX];
+    pub const GENERAL_DEFAULT_FREE_REGS: &'static [X86_64GeneralReg] = &[
+        // The regs we want to use first should be at the end of this vec.
+        // We will use pop to get which reg to use next
+
+        // Don't use stack pointer: X86_64GeneralReg::RSP,
+        // Don't use frame pointer: X86_64GeneralReg::RBP,
+
+        // Use callee saved regs last.
+        X86_64GeneralReg::RBX,
+        X86_64GeneralReg::RSI,
+        X86_64GeneralReg::RDI,
+        X86_64GeneralReg::R12,
+        X86_64GeneralReg::R13,
+        X86_64GeneralReg::R14,
+        X86_64GeneralReg::R15,
+        // Use caller saved regs first.
+        X86_64GeneralReg::RAX,
+        X86_64GeneralReg::RCX,
+        X86_64GeneralReg::RDX,
+        X86_64GeneralReg::R8,
+        X86_64GeneralReg::R9,
+        X86_64GeneralReg::R10,
+        X86_64GeneralReg::R11,
+    ];
+    pub const FLOAT_PARAM_REGS: &'static [X86_64FloatReg] = &[
+        X86_64FloatReg::XMM0,
+        X86_64FloatReg::XMM1,
+        X86_64FloatReg::XMM2,
+        X86_64FloatReg::XMM3,
+    ];
+    pub const FLOAT_RETURN_REGS: &'static [X86_64FloatReg] = &[X86_64FloatReg::XMM0];
+    pub const FLOAT_DEFAULT_FREE_REGS: &'static [X86_64FloatReg] = &[
+        // The regs we want to use first should be at the end of this vec.
+        // We will use pop to get which reg to use next
+        // Use callee saved regs last.
+        X86_64FloatReg::XMM15,
+        X86_64FloatReg::XMM15,
+        X86_64FloatReg::XMM13,
+        X86_64FloatReg::XMM12,
+        X86_64FloatReg::XMM11,
+        X86_64FloatReg::XMM10,
+        X86_64FloatReg::XMM9,
+        X86_64FloatReg::XMM8,
+        X86_64FloatReg::XMM7,
+        X86_64FloatReg::XMM6,
+        // Use caller saved regs first.
+        X86_64FloatReg::XMM5,
+        X86_64FloatReg::XMM4,
+        X86_64FloatReg::XMM3,
+        X86_64FloatReg::XMM2,
+        X86_64FloatReg::XMM1,
+        X86_64FloatReg::XMM0,
+    ];
+    pub const SHADOW_SPACE_SIZE: u8 = 32;
+
     fn returns_via_arg_pointer<'a>(
         interner: &STLayoutInterner<'a>,
         ret_layout: &InLayout<'a>,
     ) -> bool {
-        // TODO: This is not fully correct there are some exceptions for "vector" types.
+        // Only results of 1, 2, 4 or 8 bytes are returned in RAX, there are some exceptions for "vector" types as well.
         // details here: https://docs.microsoft.com/en-us/cpp/build/x64-calling-convention?view=msvc-160#return-values
-        interner.stack_size(*ret_layout) > 8
+        // The convention has no 128 bit integers, the Zig builtins return them in RAX and RDX like on System V.
+        match interner.get_repr(*ret_layout) {
+            LayoutRepr::I128 | LayoutRepr::U128 => false,
+            _ => !matches!(interner.stack_size(*ret_layout), 0 | 1 | 2 | 4 | 8),
+        }
     }
 }
 
@@ -1066,12 +1359,7 @@ fn x86_64_generic_setup_stack(
     };
     if let Some(aligned_stack_size) = full_stack_size.checked_add(offset as i32) {
         if aligned_stack_size > 0 {
-            X86_64Assembler::sub_reg64_reg64_imm32(
-                buf,
-                X86_64GeneralReg::RSP,
-                X86_64GeneralReg::RSP,
-                aligned_stack_size,
-            );
+            x86_64_allocate_stack(buf, aligned_stack_size);
 
             // Put values at the top of the stack to avoid conflicts with previously saved variables.
             let mut offset = aligned_stack_size - fn_call_stack_size;
@@ -1092,6 +1380,37 @@ fn x86_64_generic_setup_stack(
     }
 }
 
+/// The size of the pages the stack grows by.
+/// The guard page below the stack is at least this big.
+const STACK_PAGE_SIZE: i32 = 4096;
+
+/// Moves the stack pointer down by `size` bytes.
+/// Frames larger than a page are probed one page at a time, so a stack overflow hits the guard page instead of skipping past it.
+/// Touching the pages in order is also what Windows needs to grow the stack, so there is no need to call `__chkstk`.
+fn x86_64_allocate_stack(buf: &mut Vec<'_, u8>, size: i32) {
+    let pages = size / STACK_PAGE_SIZE;
+    if pages > 0 {
+        // R11 is a scratch register that is never used for args in any of the calling conventions.
+        let counter = X86_64GeneralReg::R11;
+        mov_reg64_imm32(buf, counter, pages);
+        let loop_start = buf.len();
+        sub_reg64_imm32(buf, X86_64GeneralReg::RSP, STACK_PAGE_SIZE);
+        X86_64Assembler::mov_stack32_reg64(buf, 0, counter);
+        sub_reg64_imm32(buf, counter, 1);
+        // The jump is 6 bytes and relative to its end.
+        jne_imm32(buf, loop_start as i32 - (buf.len() as i32 + 6));
+    }
+    let remainder = size % STACK_PAGE_SIZE;
+    if remainder > 0 {
+        X86_64Assembler::sub_reg64_reg64_imm32(
+            buf,
+            X86_64GeneralReg::RSP,
+            X86_64GeneralReg::RSP,
+            remainder,
+        );
+    }
+}
+
 #[inline(always)]
 #[allow(clippy::unnecessary_wraps)]
 fn x86_64_generic_cleanup_stack(
@@ -1149,18 +1468,19 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
     }
 
     #[inline(always)]
-    fn abs_freg64_freg64(
+    fn abs_freg64_freg64<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: X86_64FloatReg,
         src: X86_64FloatReg,
     ) {
         movsd_freg64_rip_offset32(buf, dst, 0);
 
         // TODO: make sure this constant only loads once instead of every call to abs
+        let data = local_data(relocs, &0x7fffffffffffffffu64.to_le_bytes());
         relocs.push(Relocation::LocalData {
             offset: buf.len() as u64 - 4,
-            data: 0x7fffffffffffffffu64.to_le_bytes().to_vec(),
+            data,
         });
 
         andpd_freg64_freg64(buf, dst, src);
@@ -1174,7 +1494,9 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         imm32: i32,
     ) {
         mov_reg64_reg64(buf, dst, src1);
-        add_reg64_imm32(buf, dst, imm32);
+        if imm32 != 0 {
+            add_reg64_imm32(buf, dst, imm32);
+        }
     }
 
     #[inline(always)]
@@ -1216,18 +1538,46 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
     }
 
     #[inline(always)]
-    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation>, fn_name: String) {
+    fn call(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, fn_name: String) {
         buf.extend([0xE8, 0x00, 0x00, 0x00, 0x00]);
         relocs.push(Relocation::LinkedFunction {
             offset: buf.len() as u64 - 4,
             name: fn_name,
+            addend: 0,
         });
     }
 
+    #[inline(always)]
+    fn call_slot(buf: &mut Vec<'_, u8>, relocs: &mut Vec<'_, Relocation<'_>>, slot_name: String) {
+        // R11 is caller saved and never holds an argument.
+        let reg = X86_64GeneralReg::R11;
+        Self::data_pointer(buf, relocs, slot_name, 0, reg);
+        mov_reg64_base64_offset32(buf, reg, reg, 0);
+        call_reg64(buf, reg);
+    }
+
+    #[inline(always)]
+    fn lea_reg64_base_index(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        base: X86_64GeneralReg,
+        index: X86_64GeneralReg,
+        shift: u8,
+        offset: i32,
+    ) {
+        if index == X86_64GeneralReg::RSP {
+            // RSP can not be an index, but without scaling base and index can swap.
+            debug_assert_eq!(shift, 0);
+            lea_reg64_base_index_offset(buf, dst, index, base, 0, offset);
+        } else {
+            lea_reg64_base_index_offset(buf, dst, base, index, shift, offset);
+        }
+    }
+
     #[inline(always)]
     fn function_pointer(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
         fn_name: String,
         dst: X86_64GeneralReg,
     ) {
@@ -1236,6 +1586,30 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         relocs.push(Relocation::LinkedFunction {
             offset: buf.len() as u64 - 4,
             name: fn_name,
+            addend: 0,
+        });
+    }
+
+    #[inline(always)]
+    fn data_pointer(
+        buf: &mut Vec<'_, u8>,
+        relocs: &mut Vec<'_, Relocation<'_>>,
+        data_name: String,
+        addend: i64,
+        dst: X86_64GeneralReg,
+    ) {
+        if addend == 0 {
+            // The address is loaded from the GOT.
+            mov_reg64_rip_offset32(buf, dst, 0);
+        } else {
+            // The GOT only has the start of the symbol, so the address is computed relative to this instruction.
+            lea_reg64(buf, dst);
+        }
+
+        relocs.push(Relocation::LinkedData {
+            offset: buf.len() as u64 - 4,
+            name: data_name,
+            addend,
         });
     }
 
@@ -1270,6 +1644,32 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         mov_reg64_reg64(buf, dst, X86_64GeneralReg::RAX);
     }
 
+    fn umulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        mul_high_reg64_reg64_reg64(buf, storage_manager, mul_reg64_reg64, dst, src1, src2);
+    }
+
+    fn smulh_reg64_reg64_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        mul_high_reg64_reg64_reg64(buf, storage_manager, imul_reg64, dst, src1, src2);
+    }
+
     fn mul_freg32_freg32_freg32(
         buf: &mut Vec<'_, u8>,
         dst: X86_64FloatReg,
@@ -1420,6 +1820,18 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         buf.len()
     }
 
+    #[inline(always)]
+    fn jmp_table_reg64(buf: &mut Vec<'_, u8>, tmp: X86_64GeneralReg, index: X86_64GeneralReg) {
+        // The entries are 5 byte jumps, so the entry at index is at table + index * 4 + index.
+        lea_reg64(buf, tmp);
+        let lea_end = buf.len();
+        lea_reg64_base_index_offset(buf, tmp, tmp, index, 2, 0);
+        add_reg64_reg64(buf, tmp, index);
+        jmp_reg64(buf, tmp);
+        let table_offset = (buf.len() - lea_end) as i32;
+        buf[lea_end - 4..lea_end].copy_from_slice(&table_offset.to_le_bytes());
+    }
+
     #[inline(always)]
     fn tail_call(buf: &mut Vec<'_, u8>) -> u64 {
         Self::jmp_imm32(buf, 0);
@@ -1439,7 +1851,10 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
     {
         buf.reserve(13);
-        if imm > i32::MAX as u64 {
+        if imm == 0 {
+            // Same flags for the jump, but shorter.
+            test_reg64_reg64(buf, reg, reg);
+        } else if imm > i32::MAX as u64 {
             storage_manager.with_tmp_general_reg(buf, |_, buf, tmp| {
                 mov_reg64_imm64(buf, tmp, imm as _);
                 cmp_reg64_reg64(buf, RegisterWidth::W64, reg, tmp);
@@ -1454,29 +1869,86 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
     }
 
     #[inline(always)]
-    fn mov_freg32_imm32(
+    fn jcc_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+        offset: i32,
+    ) -> usize {
+        cmp_reg64_reg64(buf, register_width, src1, src2);
+
+        let condition_code = match condition {
+            IntCondition::Equal => 0x4,
+            IntCondition::NotEqual => 0x5,
+            IntCondition::Signed(CompareOperation::LessThan) => 0xC,
+            IntCondition::Signed(CompareOperation::LessThanOrEqual) => 0xE,
+            IntCondition::Signed(CompareOperation::GreaterThan) => 0xF,
+            IntCondition::Signed(CompareOperation::GreaterThanOrEqual) => 0xD,
+            IntCondition::Unsigned(CompareOperation::LessThan) => 0x2,
+            IntCondition::Unsigned(CompareOperation::LessThanOrEqual) => 0x6,
+            IntCondition::Unsigned(CompareOperation::GreaterThan) => 0x7,
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual) => 0x3,
+        };
+        jcc_imm32(buf, condition_code, offset);
+
+        buf.len()
+    }
+
+    #[inline(always)]
+    fn jump_sizes(code: &[u8]) -> (usize, usize) {
+        // `jmp rel32` is 5 bytes and `jcc rel32` is 6, both have a 2 byte rel8 form.
+        if code[code.len() - 5] == 0xE9 {
+            (5, 2)
+        } else {
+            debug_assert_eq!(code[code.len() - 6], 0x0F);
+            (6, 2)
+        }
+    }
+
+    #[inline(always)]
+    fn encode_jump(buf: &mut Vec<'_, u8>, jump: &[u8], offset: i32, short: bool) {
+        match (jump, short) {
+            ([0xE9, ..], false) => jmp_imm32(buf, offset),
+            ([0xE9, ..], true) => jmp_imm8(buf, offset as i8),
+            ([0x0F, opcode, ..], false) => jcc_imm32(buf, opcode & 0xF, offset),
+            ([0x0F, opcode, ..], true) => jcc_imm8(buf, opcode & 0xF, offset as i8),
+            _ => internal_error!("not a jump: {:x?}", jump),
+        }
+    }
+
+    #[inline(always)]
+    fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction> {
+        decode_instruction(code)
+    }
+
+    #[inline(always)]
+    fn mov_freg32_imm32<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: X86_64FloatReg,
         imm: f32,
     ) {
         movss_freg32_rip_offset32(buf, dst, 0);
+        let data = local_data(relocs, &imm.to_le_bytes());
         relocs.push(Relocation::LocalData {
             offset: buf.len() as u64 - 4,
-            data: imm.to_le_bytes().to_vec(),
+            data,
         });
     }
     #[inline(always)]
-    fn mov_freg64_imm64(
+    fn mov_freg64_imm64<'a>(
         buf: &mut Vec<'_, u8>,
-        relocs: &mut Vec<'_, Relocation>,
+        relocs: &mut Vec<'a, Relocation<'a>>,
         dst: X86_64FloatReg,
         imm: f64,
     ) {
         movsd_freg64_rip_offset32(buf, dst, 0);
+        let data = local_data(relocs, &imm.to_le_bytes());
         relocs.push(Relocation::LocalData {
             offset: buf.len() as u64 - 4,
-            data: imm.to_le_bytes().to_vec(),
+            data,
         });
     }
     #[inline(always)]
@@ -1496,6 +1968,14 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
     fn mov_reg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64FloatReg) {
         movq_reg64_freg64(buf, dst, src);
     }
+    #[inline(always)]
+    fn mov_freg32_reg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64GeneralReg) {
+        movd_freg32_reg32(buf, dst, src);
+    }
+    #[inline(always)]
+    fn mov_freg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64GeneralReg) {
+        movq_freg64_reg64(buf, dst, src);
+    }
 
     #[inline(always)]
     fn mov_reg_reg(
@@ -1557,11 +2037,26 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         movss_freg32_base32_offset32(buf, dst, src, offset)
     }
 
+    #[inline(always)]
+    fn mov_freg128_mem128_offset32(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64FloatReg,
+        src: X86_64GeneralReg,
+        offset: i32,
+    ) {
+        movups_freg128_base64_offset32(buf, dst, src, offset)
+    }
+
     #[inline(always)]
     fn mov_freg64_base32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, offset: i32) {
         movsd_freg64_base64_offset32(buf, dst, X86_64GeneralReg::RBP, offset)
     }
 
+    #[inline(always)]
+    fn mov_freg128_base32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, offset: i32) {
+        movups_freg128_base64_offset32(buf, dst, X86_64GeneralReg::RBP, offset)
+    }
+
     #[inline(always)]
     fn mov_reg64_base32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, offset: i32) {
         mov_reg64_base64_offset32(buf, dst, X86_64GeneralReg::RBP, offset)
@@ -1584,6 +2079,11 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         movsd_base64_offset32_freg64(buf, X86_64GeneralReg::RBP, offset, src)
     }
 
+    #[inline(always)]
+    fn mov_base32_freg128(buf: &mut Vec<'_, u8>, offset: i32, src: X86_64FloatReg) {
+        movups_base64_offset32_freg128(buf, X86_64GeneralReg::RBP, offset, src)
+    }
+
     #[inline(always)]
     fn movesd_mem64_offset32_freg64(
         buf: &mut Vec<'_, u8>,
@@ -1656,6 +2156,16 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         mov_base64_offset32_reg64(buf, dst, offset, src)
     }
 
+    #[inline(always)]
+    fn atomic_add_mem64_offset32_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        offset: i32,
+        src: X86_64GeneralReg,
+    ) {
+        lock_xadd_base64_offset32_reg64(buf, dst, offset, src)
+    }
+
     #[inline(always)]
     fn mov_mem32_offset32_reg32(
         buf: &mut Vec<'_, u8>,
@@ -1759,7 +2269,9 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         imm32: i32,
     ) {
         mov_reg64_reg64(buf, dst, src1);
-        sub_reg64_imm32(buf, dst, imm32);
+        if imm32 != 0 {
+            sub_reg64_imm32(buf, dst, imm32);
+        }
     }
     #[inline(always)]
     fn sub_reg64_reg64_reg64(
@@ -1833,29 +2345,74 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         }
     }
 
-    fn eq_freg_freg_reg64(
+    fn compare_reg64_imm32(
         buf: &mut Vec<'_, u8>,
+        register_width: RegisterWidth,
+        condition: IntCondition,
         dst: X86_64GeneralReg,
-        src1: X86_64FloatReg,
-        src2: X86_64FloatReg,
-        width: FloatWidth,
+        src: X86_64GeneralReg,
+        imm32: i32,
     ) {
-        match width {
-            FloatWidth::F32 => cmp_freg32_freg32(buf, src1, src2),
-            FloatWidth::F64 => cmp_freg64_freg64(buf, src1, src2),
+        cmp_reg_imm32(buf, register_width, src, imm32);
+
+        match condition {
+            IntCondition::Equal => sete_reg64(buf, dst),
+            IntCondition::NotEqual => setne_reg64(buf, dst),
+            IntCondition::Signed(CompareOperation::LessThan) => setl_reg64(buf, dst),
+            IntCondition::Signed(CompareOperation::LessThanOrEqual) => setle_reg64(buf, dst),
+            IntCondition::Signed(CompareOperation::GreaterThan) => setg_reg64(buf, dst),
+            IntCondition::Signed(CompareOperation::GreaterThanOrEqual) => setge_reg64(buf, dst),
+            IntCondition::Unsigned(CompareOperation::LessThan) => setb_reg64(buf, dst),
+            IntCondition::Unsigned(CompareOperation::LessThanOrEqual) => setbe_reg64(buf, dst),
+            IntCondition::Unsigned(CompareOperation::GreaterThan) => seta_reg64(buf, dst),
+            IntCondition::Unsigned(CompareOperation::GreaterThanOrEqual) => setae_reg64(buf, dst),
         }
-
-        sete_reg64(buf, dst);
     }
 
-    fn neq_freg_freg_reg64(
+    fn select_reg64_reg64_reg64_imm32(
         buf: &mut Vec<'_, u8>,
         dst: X86_64GeneralReg,
-        src1: X86_64FloatReg,
-        src2: X86_64FloatReg,
-        width: FloatWidth,
-    ) {
-        match width {
+        src: X86_64GeneralReg,
+        imm32: i32,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+    ) {
+        debug_assert_ne!(dst, src1);
+        if dst != src2 {
+            mov_reg64_reg64(buf, dst, src2);
+        }
+        cmp_reg64_imm32(buf, src, imm32);
+        cmove_reg64_reg64(buf, dst, src1);
+    }
+
+    fn supports_imm32_operand(_operation: ImmOperation, imm: i64) -> bool {
+        // All of the imm32 forms sign extend the immediate.
+        i32::try_from(imm).is_ok()
+    }
+
+    fn eq_freg_freg_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64FloatReg,
+        src2: X86_64FloatReg,
+        width: FloatWidth,
+    ) {
+        match width {
+            FloatWidth::F32 => cmp_freg32_freg32(buf, src1, src2),
+            FloatWidth::F64 => cmp_freg64_freg64(buf, src1, src2),
+        }
+
+        sete_reg64(buf, dst);
+    }
+
+    fn neq_freg_freg_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64FloatReg,
+        src2: X86_64FloatReg,
+        width: FloatWidth,
+    ) {
+        match width {
             FloatWidth::F32 => cmp_freg32_freg32(buf, src1, src2),
             FloatWidth::F64 => cmp_freg64_freg64(buf, src1, src2),
         }
@@ -1863,6 +2420,20 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         setne_reg64(buf, dst);
     }
 
+    #[inline(always)]
+    fn eq_freg128_freg128_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64FloatReg,
+        src2: X86_64FloatReg,
+    ) {
+        pcmpeqb_freg128_freg128(buf, src1, src2);
+        // The mask has a bit for every byte, it is 0xFFFF when they are all equal.
+        pmovmskb_reg32_freg128(buf, dst, src1);
+        add_reg64_imm32(buf, dst, 1);
+        shr_reg64_imm8(buf, dst, 16);
+    }
+
     #[inline(always)]
     fn cmp_freg_freg_reg64(
         buf: &mut Vec<'_, u8>,
@@ -1934,6 +2505,69 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         seto_reg64(buf, dst);
     }
 
+    #[inline(always)]
+    fn adds_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+    ) {
+        // Every add sets the flags.
+        Self::add_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
+    #[inline(always)]
+    fn subs_reg64_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64GeneralReg,
+        src2: X86_64GeneralReg,
+    ) {
+        Self::sub_reg64_reg64_reg64(buf, dst, src1, src2);
+    }
+
+    #[inline(always)]
+    fn cmov_if_overflow_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+    ) {
+        cmovo_reg64_reg64(buf, dst, src);
+    }
+
+    #[inline(always)]
+    fn cmov_if_carry_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+    ) {
+        cmovb_reg64_reg64(buf, dst, src);
+    }
+
+    #[inline(always)]
+    fn cmov_if_borrow_reg64_reg64(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+    ) {
+        // A sub sets the carry flag when it borrows.
+        cmovb_reg64_reg64(buf, dst, src);
+    }
+
+    #[inline(always)]
+    fn and_reg64_reg64_imm32(
+        buf: &mut Vec<'_, u8>,
+        dst: X86_64GeneralReg,
+        src1: X86_64GeneralReg,
+        imm32: i32,
+    ) {
+        mov_reg64_reg64(buf, dst, src1);
+        match i8::try_from(imm32) {
+            Ok(imm8) => and_reg64_imm8(buf, dst, imm8),
+            Err(_) => and_reg64_imm32(buf, dst, imm32),
+        }
+    }
+
     fn and_reg64_reg64_reg64(buf: &mut Vec<'_, u8>, dst: Reg64, src1: Reg64, src2: Reg64) {
         binop_move_src_to_dst_reg64(buf, and_reg64_reg64, dst, src1, src2)
     }
@@ -1985,10 +2619,155 @@ impl Assembler<X86_64GeneralReg, X86_64FloatReg> for X86_64Assembler {
         shift_reg64_reg64_reg64(buf, storage_manager, sar_reg64_reg64, dst, src1, src2)
     }
 
+    fn count_leading_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        if bit_count_instructions {
+            lzcnt_reg64_reg64(buf, dst, src);
+            return;
+        }
+
+        // bsr finds the index of the highest set bit, which is `63 - lzcnt`, and sets ZF for 0.
+        // 127 is flipped into 64 for that case.
+        storage_manager.with_tmp_general_reg(buf, |_, buf, tmp| {
+            bsr_reg64_reg64(buf, dst, src);
+            mov_reg64_imm64(buf, tmp, 127);
+            cmove_reg64_reg64(buf, dst, tmp);
+            mov_reg64_imm64(buf, tmp, 63);
+            xor_reg64_reg64(buf, dst, tmp);
+        })
+    }
+
+    fn count_trailing_zeros_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        if bit_count_instructions {
+            tzcnt_reg64_reg64(buf, dst, src);
+            return;
+        }
+
+        // bsf finds the index of the lowest set bit, which is the count, and sets ZF for 0.
+        storage_manager.with_tmp_general_reg(buf, |_, buf, tmp| {
+            bsf_reg64_reg64(buf, dst, src);
+            mov_reg64_imm64(buf, tmp, 64);
+            cmove_reg64_reg64(buf, dst, tmp);
+        })
+    }
+
+    fn count_one_bits_reg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src: X86_64GeneralReg,
+        bit_count_instructions: bool,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        if bit_count_instructions {
+            popcnt_reg64_reg64(buf, dst, src);
+            return;
+        }
+
+        // Sums up the bits in ever wider fields: pairs, nibbles, and then bytes with a multiply.
+        storage_manager.with_tmp_general_reg(buf, |storage_manager, buf, tmp| {
+            storage_manager.with_tmp_general_reg(buf, |_, buf, mask| {
+                if dst != src {
+                    mov_reg64_reg64(buf, dst, src);
+                }
+
+                mov_reg64_reg64(buf, tmp, dst);
+                shr_reg64_imm8(buf, tmp, 1);
+                mov_reg64_imm64(buf, mask, 0x5555_5555_5555_5555);
+                and_reg64_reg64(buf, tmp, mask);
+                sub_reg64_reg64(buf, dst, tmp);
+
+                mov_reg64_reg64(buf, tmp, dst);
+                shr_reg64_imm8(buf, tmp, 2);
+                mov_reg64_imm64(buf, mask, 0x3333_3333_3333_3333);
+                and_reg64_reg64(buf, tmp, mask);
+                and_reg64_reg64(buf, dst, mask);
+                add_reg64_reg64(buf, dst, tmp);
+
+                mov_reg64_reg64(buf, tmp, dst);
+                shr_reg64_imm8(buf, tmp, 4);
+                add_reg64_reg64(buf, dst, tmp);
+                mov_reg64_imm64(buf, mask, 0x0F0F_0F0F_0F0F_0F0F);
+                and_reg64_reg64(buf, dst, mask);
+
+                mov_reg64_imm64(buf, mask, 0x0101_0101_0101_0101);
+                imul_reg64_reg64(buf, dst, mask);
+                shr_reg64_imm8(buf, dst, 56);
+            })
+        })
+    }
+
+    fn shl_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: Reg64, src: Reg64, imm8: u8) {
+        if dst != src {
+            mov_reg64_reg64(buf, dst, src);
+        }
+        shl_reg64_imm8(buf, dst, imm8);
+    }
+
+    fn shr_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: Reg64, src: Reg64, imm8: u8) {
+        if dst != src {
+            mov_reg64_reg64(buf, dst, src);
+        }
+        shr_reg64_imm8(buf, dst, imm8);
+    }
+
+    fn sar_reg64_reg64_imm8(buf: &mut Vec<'_, u8>, dst: Reg64, src: Reg64, imm8: u8) {
+        if dst != src {
+            mov_reg64_reg64(buf, dst, src);
+        }
+        sar_reg64_imm8(buf, dst, imm8);
+    }
+
     fn sqrt_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
         sqrtsd_freg64_freg64(buf, dst, src)
     }
 
+    fn round_reg64_freg64<'a, ASM, CC>(
+        buf: &mut Vec<'a, u8>,
+        storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+        dst: X86_64GeneralReg,
+        src: X86_64FloatReg,
+    ) where
+        ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+        CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+    {
+        // Adding the largest f64 below 0.5 with the sign of src and then truncating
+        // rounds to the nearest integer, with halfway cases going away from zero.
+        const ALMOST_HALF_BITS: i64 = 0x3FDF_FFFF_FFFF_FFFF;
+
+        movq_reg64_freg64(buf, dst, src);
+        shr_reg64_imm8(buf, dst, 63);
+        shl_reg64_imm8(buf, dst, 63);
+        storage_manager.with_tmp_general_reg(buf, |storage_manager, buf, tmp| {
+            mov_reg64_imm64(buf, tmp, ALMOST_HALF_BITS);
+            or_reg64_reg64(buf, dst, tmp);
+            storage_manager.with_tmp_float_reg(buf, |_, buf, float_tmp| {
+                movq_freg64_reg64(buf, float_tmp, dst);
+                addsd_freg64_freg64(buf, float_tmp, src);
+                cvttsd2si_reg64_freg64(buf, dst, float_tmp);
+            });
+        });
+    }
+
     fn sqrt_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
         sqrtss_freg32_freg32(buf, dst, src)
     }
@@ -2032,6 +2811,38 @@ fn shift_reg64_reg64_reg64<'a, ASM, CC>(
     }
 }
 
+/// Multiplies src1 and src2 into RDX:RAX with `mul_function`, and moves the upper 64 bits into dst.
+/// dst can be RAX or RDX, the symbols in the other one are moved out.
+fn mul_high_reg64_reg64_reg64<'a, ASM, CC>(
+    buf: &mut Vec<'a, u8>,
+    storage_manager: &mut StorageManager<'a, '_, X86_64GeneralReg, X86_64FloatReg, ASM, CC>,
+    mul_function: fn(buf: &mut Vec<'_, u8>, X86_64GeneralReg),
+    dst: X86_64GeneralReg,
+    src1: X86_64GeneralReg,
+    src2: X86_64GeneralReg,
+) where
+    ASM: Assembler<X86_64GeneralReg, X86_64FloatReg>,
+    CC: CallConv<X86_64GeneralReg, X86_64FloatReg, ASM>,
+{
+    use crate::generic64::RegStorage;
+
+    for reg in [X86_64GeneralReg::RAX, X86_64GeneralReg::RDX] {
+        if reg != dst {
+            storage_manager.ensure_reg_free(buf, RegStorage::General(reg));
+        }
+    }
+
+    // Moving src1 into RAX would overwrite src2 if it is there, and the product is the same either way.
+    let (src1, src2) = if src2 == X86_64GeneralReg::RAX {
+        (src2, src1)
+    } else {
+        (src1, src2)
+    };
+    mov_reg64_reg64(buf, X86_64GeneralReg::RAX, src1);
+    mul_function(buf, src2);
+    mov_reg64_reg64(buf, dst, X86_64GeneralReg::RDX);
+}
+
 impl X86_64Assembler {
     #[inline(always)]
     fn pop_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
@@ -2054,7 +2865,6 @@ const REX_PREFIX_W: u8 = 0b1000;
 /// Extension to the MODRM.reg
 /// Permits access to additional registers
 const REX_PREFIX_R: u8 = 0b0100;
-#[allow(unused)]
 /// Extension to the SIB.index field
 const REX_PREFIX_X: u8 = 0b0010;
 /// Extension to the MODRM.rm
@@ -2187,6 +2997,144 @@ fn extended_binop_reg64_reg64(
     buf.extend([rex, op_code1, op_code2, 0xC0 | dst_mod | src_mod]);
 }
 
+/// `prefix 0F op /r` on two xmm registers, the form of the scalar SSE arithmetic.
+#[inline(always)]
+fn sse_freg_freg(
+    prefix: u8,
+    op_code: u8,
+    buf: &mut Vec<'_, u8>,
+    dst: X86_64FloatReg,
+    src: X86_64FloatReg,
+) {
+    let dst_high = dst as u8 > 7;
+    let dst_mod = dst as u8 % 8;
+    let src_high = src as u8 > 7;
+    let src_mod = src as u8 % 8;
+    if dst_high || src_high {
+        buf.extend([
+            prefix,
+            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
+            0x0F,
+            op_code,
+            0xC0 | (dst_mod << 3) | (src_mod),
+        ])
+    } else {
+        buf.extend([prefix, 0x0F, op_code, 0xC0 | (dst_mod << 3) | (src_mod)])
+    }
+}
+
+// The encoders below are declared as tables of opcodes instead of being written out one by one.
+// Each table passes its entries to a callback macro: `define_encoders!` turns them into functions,
+// and the tests module passes the same tables to `encoder_tests!` to check every entry with capstone,
+// and to `reference_encoder_test!` to check them against an external assembler.
+// An entry is `name = form(opcodes), "mnemonic";` and the form picks the encoding:
+// - `mr(op)`: `op /r` with dst in r/m64 and src in r64, like `ADD r/m64,r64`.
+// - `rm(op)`: `op /r` with dst in r64 and src in r/m64, like `AND r64,r/m64`.
+// - `rm_0f(op)`: `0F op /r` with dst in r64 and src in r/m64, like `IMUL r64,r/m64`.
+// - `f3_rm_0f(op)`: `F3 0F op /r` with dst in r64 and src in r/m64, like `LZCNT r64,r/m64`.
+// - `sse(prefix, op)`: `prefix 0F op /r` on xmm registers, like `ADDSD xmm1,xmm2/m64`.
+// To add an instruction of one of these forms, add a line to its table, it gets a test for free.
+
+macro_rules! reg64_reg64_encoders {
+    ($callback:ident) => {
+        $callback! {
+            X86_64GeneralReg;
+            /// `ADD r/m64,r64` -> Add r64 to r/m64.
+            add_reg64_reg64 = mr(0x01), "add";
+            /// `AND r64,r/m64` -> Bitwise logical and r/m64 to r64.
+            and_reg64_reg64 = rm(0x23), "and";
+            /// `BSF r64,r/m64` -> Bit scan forward, the index of the lowest set bit. Sets ZF if r/m64 is 0, then r64 is undefined.
+            bsf_reg64_reg64 = rm_0f(0xBC), "bsf";
+            /// `BSR r64,r/m64` -> Bit scan reverse, the index of the highest set bit. Sets ZF if r/m64 is 0, then r64 is undefined.
+            bsr_reg64_reg64 = rm_0f(0xBD), "bsr";
+            /// `IMUL r64,r/m64` -> Signed Multiply r/m64 to r64.
+            imul_reg64_reg64 = rm_0f(0xAF), "imul";
+            /// `LZCNT r64,r/m64` -> Count the leading zero bits of r/m64 into r64. Needs the ABM extension.
+            lzcnt_reg64_reg64 = f3_rm_0f(0xBD), "lzcnt";
+            /// `OR r64,r/m64` -> Bitwise logical or r/m64 to r64.
+            or_reg64_reg64 = rm(0x0B), "or";
+            /// `POPCNT r64,r/m64` -> Count the set bits of r/m64 into r64. Needs the POPCNT extension, which comes with ABM.
+            popcnt_reg64_reg64 = f3_rm_0f(0xB8), "popcnt";
+            /// `SUB r/m64,r64` -> Sub r64 to r/m64.
+            sub_reg64_reg64 = mr(0x29), "sub";
+            /// `TEST r/m64,r64` -> AND r64 with r/m64; set SF, ZF, PF according to result.
+            #[allow(dead_code)]
+            test_reg64_reg64 = mr(0x85), "test";
+            /// `TZCNT r64,r/m64` -> Count the trailing zero bits of r/m64 into r64. Needs the BMI1 extension.
+            tzcnt_reg64_reg64 = f3_rm_0f(0xBC), "tzcnt";
+            /// `XOR r64,r/m64` -> Bitwise logical exclusive or r/m64 to r64.
+            xor_reg64_reg64 = rm(0x33), "xor";
+        }
+    };
+}
+
+macro_rules! freg_freg_encoders {
+    ($callback:ident) => {
+        $callback! {
+            X86_64FloatReg;
+            /// `ADDSD xmm1,xmm2/m64` -> Add the low double-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+            addsd_freg64_freg64 = sse(0xF2, 0x58), "addsd";
+            /// `ADDSS xmm1,xmm2/m32` -> Add the low single-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+            addss_freg32_freg32 = sse(0xF3, 0x58), "addss";
+            /// `ANDPD xmm1,xmm2/m128` -> Bitwise logical and of the packed double-precision floating-point values in xmm1 and xmm2/mem.
+            andpd_freg64_freg64 = sse(0x66, 0x54), "andpd";
+            /// `CVTSD2SS xmm1,xmm2/m64` -> Convert one double-precision floating-point value in xmm2/mem to one single-precision floating-point value in xmm1.
+            cvtsd2ss_freg32_freg64 = sse(0xF2, 0x5A), "cvtsd2ss";
+            /// `CVTSS2SD xmm1,xmm2/m32` -> Convert one single-precision floating-point value in xmm2/mem to one double-precision floating-point value in xmm1.
+            cvtss2sd_freg64_freg32 = sse(0xF3, 0x5A), "cvtss2sd";
+            /// `DIVSD xmm1,xmm2/m64` -> Divide the low double-precision floating-point value in xmm1 by the one in xmm2/mem and store the result in xmm1.
+            divsd_freg64_freg64 = sse(0xF2, 0x5E), "divsd";
+            /// `DIVSS xmm1,xmm2/m32` -> Divide the low single-precision floating-point value in xmm1 by the one in xmm2/mem and store the result in xmm1.
+            divss_freg32_freg32 = sse(0xF3, 0x5E), "divss";
+            /// `MULSD xmm1,xmm2/m64` -> Multiply the low double-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+            mulsd_freg64_freg64 = sse(0xF2, 0x59), "mulsd";
+            /// `MULSS xmm1,xmm2/m32` -> Multiply the low single-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+            mulss_freg32_freg32 = sse(0xF3, 0x59), "mulss";
+            /// `PCMPEQB xmm1,xmm2/m128` -> Compare the packed bytes of xmm2/mem and xmm1, setting the bytes of xmm1 that are equal to all ones and the rest to zero.
+            pcmpeqb_freg128_freg128 = sse(0x66, 0x74), "pcmpeqb";
+            /// `SQRTSD xmm1,xmm2/m64` -> Compute the square root of the low double-precision floating-point value in xmm2/mem and store the result in xmm1.
+            sqrtsd_freg64_freg64 = sse(0xF2, 0x51), "sqrtsd";
+            /// `SQRTSS xmm1,xmm2/m32` -> Compute the square root of the low single-precision floating-point value in xmm2/mem and store the result in xmm1.
+            sqrtss_freg32_freg32 = sse(0xF3, 0x51), "sqrtss";
+        }
+    };
+}
+
+macro_rules! define_encoders {
+    (@encode mr($op:expr), $buf:ident, $dst:ident, $src:ident) => {
+        binop_reg64_reg64($op, $buf, $dst, $src)
+    };
+    (@encode rm($op:expr), $buf:ident, $dst:ident, $src:ident) => {
+        // The reg field holds the dst, so it goes in the place of the src of `binop_reg64_reg64`.
+        binop_reg64_reg64($op, $buf, $src, $dst)
+    };
+    (@encode rm_0f($op:expr), $buf:ident, $dst:ident, $src:ident) => {
+        extended_binop_reg64_reg64(0x0F, $op, $buf, $src, $dst)
+    };
+    (@encode f3_rm_0f($op:expr), $buf:ident, $dst:ident, $src:ident) => {{
+        $buf.push(0xF3);
+        extended_binop_reg64_reg64(0x0F, $op, $buf, $src, $dst)
+    }};
+    (@encode sse($prefix:expr, $op:expr), $buf:ident, $dst:ident, $src:ident) => {
+        sse_freg_freg($prefix, $op, $buf, $dst, $src)
+    };
+    (
+        $reg:ty;
+        $($(#[$attr:meta])* $name:ident = $form:ident($($op:expr),*), $mnemonic:literal;)*
+    ) => {
+        $(
+            $(#[$attr])*
+            #[inline(always)]
+            fn $name(buf: &mut Vec<'_, u8>, dst: $reg, src: $reg) {
+                define_encoders!(@encode $form($($op),*), buf, dst, src);
+            }
+        )*
+    };
+}
+
+reg64_reg64_encoders!(define_encoders);
+freg_freg_encoders!(define_encoders);
+
 // Below here are the functions for all of the assembly instructions.
 // Their names are based on the instruction and operators combined.
 // You should call `buf.reserve()` if you push or extend more than once.
@@ -2203,31 +3151,14 @@ fn add_reg64_imm32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i32) {
     buf.extend(imm.to_le_bytes());
 }
 
-/// `ADD r/m64,r64` -> Add r64 to r/m64.
-#[inline(always)]
-fn add_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    binop_reg64_reg64(0x01, buf, dst, src);
-}
-
-/// `AND r/m64,r64` -> Bitwise logical and r64 to r/m64.
-#[inline(always)]
-fn and_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    // NOTE: src and dst are flipped by design
-    binop_reg64_reg64(0x23, buf, src, dst);
-}
-
-/// `OR r/m64,r64` -> Bitwise logical or r64 to r/m64.
-#[inline(always)]
-fn or_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    // NOTE: src and dst are flipped by design
-    binop_reg64_reg64(0x0B, buf, src, dst);
-}
-
-/// `XOR r/m64,r64` -> Bitwise logical exclusive or r64 to r/m64.
+/// `AND r/m64, imm32` -> Bitwise logical and imm32 sign-extended to 64-bits with r/m64.
 #[inline(always)]
-fn xor_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    // NOTE: src and dst are flipped by design
-    binop_reg64_reg64(0x33, buf, src, dst);
+fn and_reg64_imm32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i32) {
+    let rex = add_rm_extension(dst, REX_W);
+    let dst_mod = dst as u8 % 8;
+    buf.reserve(7);
+    buf.extend([rex, 0x81, 0xE0 | dst_mod]);
+    buf.extend(imm.to_le_bytes());
 }
 
 /// `SHL r/m64, CL` -> Multiply r/m64 by 2, CL times.
@@ -2260,152 +3191,69 @@ fn sar_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg) {
     buf.extend([rex, 0xD3, 0xC0 | (7 << 3) | dst_mod]);
 }
 
-/// `ADDSD xmm1,xmm2/m64` -> Add the low double-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+/// `SHL r/m64, imm8` -> Multiply r/m64 by 2, imm8 times.
 #[inline(always)]
-fn addsd_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
+fn shl_reg64_imm8(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: u8) {
+    debug_assert!(imm < 64);
+    let rex = add_rm_extension(dst, REX_W);
     let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF2,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x58,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF2, 0x0F, 0x58, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0xC1, 0xC0 | (4 << 3) | dst_mod, imm]);
 }
 
-/// `ADDSS xmm1,xmm2/m64` -> Add the low single-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+/// `SHR r/m64, imm8` -> Unsigned divide r/m64 by 2, imm8 times.
 #[inline(always)]
-fn addss_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
-    let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF3,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x58,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF3, 0x0F, 0x58, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
-}
-
-/// `MULSD xmm1,xmm2/m64` -> Multiply the low double-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
-#[inline(always)]
-fn mulsd_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
+fn shr_reg64_imm8(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: u8) {
+    debug_assert!(imm < 64);
+    let rex = add_rm_extension(dst, REX_W);
     let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF2,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x59,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF2, 0x0F, 0x59, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0xC1, 0xC0 | (5 << 3) | dst_mod, imm]);
 }
 
-/// `DIVSS xmm1,xmm2/m64` -> Divide the low single-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+/// `SAR r/m64, imm8` -> Signed divide r/m64 by 2, imm8 times.
 #[inline(always)]
-fn divss_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
+fn sar_reg64_imm8(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: u8) {
+    debug_assert!(imm < 64);
+    let rex = add_rm_extension(dst, REX_W);
     let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF3,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x5E,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF3, 0x0F, 0x5E, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0xC1, 0xC0 | (7 << 3) | dst_mod, imm]);
 }
 
-/// `DIVSD xmm1,xmm2/m64` -> Divide the low double-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+/// r/m64 AND imm8 (sign-extended).
 #[inline(always)]
-fn divsd_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
+fn and_reg64_imm8(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i8) {
+    let rex = add_rm_extension(dst, REX_W);
     let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF2,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x5E,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF2, 0x0F, 0x5E, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0x83, 0xE0 | dst_mod, imm as u8]);
 }
 
-/// `ADDSS xmm1,xmm2/m64` -> Add the low single-precision floating-point value from xmm2/mem to xmm1 and store the result in xmm1.
+/// `CMOVE r64,r/m64` -> Move if equal (ZF=1).
 #[inline(always)]
-fn mulss_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
-    let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
+fn cmove_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
+    let rex = add_reg_extension(dst, REX_W);
+    let rex = add_rm_extension(src, rex);
+    let dst_mod = (dst as u8 % 8) << 3;
     let src_mod = src as u8 % 8;
-    if dst_high || src_high {
-        buf.extend([
-            0xF3,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x59,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF3, 0x0F, 0x59, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0x0F, 0x44, 0xC0 | dst_mod | src_mod]);
 }
 
+/// `CMOVO r64,r/m64` -> Move if overflow (OF=1).
 #[inline(always)]
-fn andpd_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
-    let dst_mod = dst as u8 % 8;
-    let src_high = src as u8 > 7;
+fn cmovo_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
+    let rex = add_reg_extension(dst, REX_W);
+    let rex = add_rm_extension(src, rex);
+    let dst_mod = (dst as u8 % 8) << 3;
     let src_mod = src as u8 % 8;
-
-    if dst_high || src_high {
-        buf.extend([
-            0x66,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x54,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0x66, 0x0F, 0x54, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
+    buf.extend([rex, 0x0F, 0x40, 0xC0 | dst_mod | src_mod]);
 }
 
-/// r/m64 AND imm8 (sign-extended).
-#[inline(always)]
-fn and_reg64_imm8(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i8) {
-    let rex = add_rm_extension(dst, REX_W);
-    let dst_mod = dst as u8 % 8;
-    buf.extend([rex, 0x83, 0xE0 | dst_mod, imm as u8]);
+/// `CMOVB r64,r/m64` -> Move if below (CF=1).
+#[inline(always)]
+fn cmovb_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
+    let rex = add_reg_extension(dst, REX_W);
+    let rex = add_rm_extension(src, rex);
+    let dst_mod = (dst as u8 % 8) << 3;
+    let src_mod = src as u8 % 8;
+    buf.extend([rex, 0x0F, 0x42, 0xC0 | dst_mod | src_mod]);
 }
 
 /// `CMOVL r64,r/m64` -> Move if less (SF≠ OF).
@@ -2418,6 +3266,42 @@ fn cmovl_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64Ge
     buf.extend([rex, 0x0F, 0x4C, 0xC0 | dst_mod | src_mod]);
 }
 
+/// `CMP r/m, imm` -> Compare an immediate of the register width to r/m.
+/// The 64-bit compare sign extends imm to 64-bits.
+#[inline(always)]
+fn cmp_reg_imm32(
+    buf: &mut Vec<'_, u8>,
+    register_width: RegisterWidth,
+    dst: X86_64GeneralReg,
+    imm: i32,
+) {
+    let dst_mod = dst as u8 % 8;
+    buf.reserve(8);
+    match register_width {
+        RegisterWidth::W8 => {
+            // The REX prefix selects SPL, BPL, SIL, and DIL instead of AH, CH, DH, and BH.
+            let rex = add_rm_extension(dst, REX);
+            buf.extend([rex, 0x80, 0xF8 | dst_mod, imm as u8]);
+        }
+        RegisterWidth::W16 => {
+            buf.push(0x66);
+            if dst.value() > 7 {
+                buf.push(REX | REX_PREFIX_B);
+            }
+            buf.extend([0x81, 0xF8 | dst_mod]);
+            buf.extend((imm as i16).to_le_bytes());
+        }
+        RegisterWidth::W32 => {
+            if dst.value() > 7 {
+                buf.push(REX | REX_PREFIX_B);
+            }
+            buf.extend([0x81, 0xF8 | dst_mod]);
+            buf.extend(imm.to_le_bytes());
+        }
+        RegisterWidth::W64 => cmp_reg64_imm32(buf, dst, imm),
+    }
+}
+
 /// `CMP r/m64,i32` -> Compare i32 to r/m64.
 #[inline(always)]
 fn cmp_reg64_imm32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i32) {
@@ -2486,63 +3370,6 @@ fn cmp_freg32_freg32(buf: &mut Vec<'_, u8>, src1: X86_64FloatReg, src2: X86_64Fl
     }
 }
 
-#[inline(always)]
-fn sqrtsd_freg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
-    let dst_mod = dst as u8 % 8;
-
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-
-    if dst_high || src_high {
-        buf.extend([
-            0xF2,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x51,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF2, 0x0F, 0x51, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
-}
-
-#[inline(always)]
-fn sqrtss_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    let dst_high = dst as u8 > 7;
-    let dst_mod = dst as u8 % 8;
-
-    let src_high = src as u8 > 7;
-    let src_mod = src as u8 % 8;
-
-    if dst_high || src_high {
-        buf.extend([
-            0xF3,
-            0x40 | ((dst_high as u8) << 2) | (src_high as u8),
-            0x0F,
-            0x51,
-            0xC0 | (dst_mod << 3) | (src_mod),
-        ])
-    } else {
-        buf.extend([0xF3, 0x0F, 0x51, 0xC0 | (dst_mod << 3) | (src_mod)])
-    }
-}
-
-/// `TEST r/m64,r64` -> AND r64 with r/m64; set SF, ZF, PF according to result.
-#[allow(dead_code)]
-#[inline(always)]
-fn test_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    binop_reg64_reg64(0x85, buf, dst, src);
-}
-
-/// `IMUL r64,r/m64` -> Signed Multiply r/m64 to r64.
-#[inline(always)]
-fn imul_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    // IMUL is strange, the parameters are reversed from must other binary ops.
-    // The final encoding is (src, dst) instead of (dst, src).
-    extended_binop_reg64_reg64(0x0F, 0xAF, buf, src, dst);
-}
-
 /// `MUL r/m64` -> Unsigned Multiply r/m64 to r64.
 #[inline(always)]
 fn mul_reg64_reg64(buf: &mut Vec<'_, u8>, src: X86_64GeneralReg) {
@@ -2556,6 +3383,18 @@ fn mul_reg64_reg64(buf: &mut Vec<'_, u8>, src: X86_64GeneralReg) {
     buf.extend([rex, 0xF7, 0b1110_0000 | (src as u8 % 8)]);
 }
 
+/// `IMUL r/m64` -> Signed Multiply RAX by r/m64, with the 128 bit product stored in RDX:RAX.
+#[inline(always)]
+fn imul_reg64(buf: &mut Vec<'_, u8>, src: X86_64GeneralReg) {
+    let rex = if src.value() > 7 {
+        REX_W | REX_PREFIX_B
+    } else {
+        REX_W
+    };
+
+    buf.extend([rex, 0xF7, 0b1110_1000 | (src as u8 % 8)]);
+}
+
 /// `IDIV r/m64` -> Signed divide RDX:RAX by r/m64, with result stored in RAX ← Quotient, RDX ← Remainder.
 #[inline(always)]
 fn idiv_reg64_reg64(buf: &mut Vec<'_, u8>, src: X86_64GeneralReg) {
@@ -2605,6 +3444,201 @@ fn jmp_imm32(buf: &mut Vec<'_, u8>, imm: i32) {
     buf.extend(imm.to_le_bytes());
 }
 
+/// `CALL r/m64` -> Call the function whose address is in reg.
+#[inline(always)]
+fn call_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
+    if reg as u8 > 7 {
+        buf.push(add_rm_extension(reg, REX));
+    }
+    buf.extend([0xFF, 0xD0 | (reg as u8 % 8)]);
+}
+
+/// `JMP r/m64` -> Jump to the address in reg.
+#[inline(always)]
+fn jmp_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
+    if reg as u8 > 7 {
+        buf.push(add_rm_extension(reg, REX));
+    }
+    buf.extend([0xFF, 0xE0 | (reg as u8 % 8)]);
+}
+
+/// `JMP rel8` -> Jump short, RIP = RIP + 8-bit displacement sign extended to 64-bits.
+#[inline(always)]
+fn jmp_imm8(buf: &mut Vec<'_, u8>, imm: i8) {
+    buf.extend([0xEB, imm as u8]);
+}
+
+/// `Jcc rel8` -> Jump short if the condition code holds.
+#[inline(always)]
+fn jcc_imm8(buf: &mut Vec<'_, u8>, condition_code: u8, imm: i8) {
+    buf.extend([0x70 | condition_code, imm as u8]);
+}
+
+/// `Jcc rel32` -> Jump near if the condition code holds.
+#[inline(always)]
+fn jcc_imm32(buf: &mut Vec<'_, u8>, condition_code: u8, imm: i32) {
+    buf.reserve(6);
+    buf.push(0x0F);
+    buf.push(0x80 | condition_code);
+    buf.extend(imm.to_le_bytes());
+}
+
+/// The operands that follow the opcode of an instruction.
+#[derive(Clone, Copy)]
+enum Operands {
+    None,
+    ModRm,
+    ModRmImm(usize),
+    Imm(usize),
+    /// A jump or call offset relative to the end of the instruction.
+    Rel(usize),
+}
+
+/// Decodes the length of the instruction that `code` starts with.
+/// This covers the one and two byte opcode maps of general purpose and SSE instructions, not VEX encoded ones.
+fn decode_instruction(code: &[u8]) -> Option<DecodedInstruction> {
+    let mut i = 0;
+    let mut operand_size_prefix = false;
+    // lock, repne, rep and the operand size override.
+    while let Some(&prefix @ (0xF0 | 0xF2 | 0xF3 | GRP_4)) = code.get(i) {
+        operand_size_prefix |= prefix == GRP_4;
+        i += 1;
+    }
+    let mut rex_w = false;
+    if let Some(&rex @ 0x40..=0x4F) = code.get(i) {
+        rex_w = rex & REX_PREFIX_W != 0;
+        i += 1;
+    }
+    // The size of the immediates that are 16 bits with the operand size override and 32 bits otherwise.
+    let imm_z = if operand_size_prefix { 2 } else { 4 };
+
+    let opcode = *code.get(i)?;
+    i += 1;
+    let operands = match opcode {
+        0x0F => {
+            let opcode = *code.get(i)?;
+            i += 1;
+            match opcode {
+                0x05 | 0x0B | 0x31 | 0xA2 | 0xC8..=0xCF => Operands::None,
+                0x10..=0x1F
+                | 0x28..=0x2F
+                | 0x40..=0x6F
+                | 0x74..=0x76
+                | 0x7E
+                | 0x7F
+                | 0x90..=0x9F
+                | 0xA3
+                | 0xAB
+                | 0xAF
+                | 0xB0
+                | 0xB1
+                | 0xB3
+                | 0xB6..=0xB9
+                | 0xBB..=0xBF
+                | 0xC0
+                | 0xC1
+                | 0xC3
+                | 0xD0..=0xFE => Operands::ModRm,
+                0x70..=0x73 | 0xA4 | 0xAC | 0xBA | 0xC2 | 0xC4..=0xC6 => Operands::ModRmImm(1),
+                0x80..=0x8F => Operands::Rel(4),
+                // The third opcode byte is followed by the ModRM byte, which is bounds checked below.
+                0x38 => {
+                    i += 1;
+                    Operands::ModRm
+                }
+                0x3A => {
+                    i += 1;
+                    Operands::ModRmImm(1)
+                }
+                _ => return None,
+            }
+        }
+        0x00..=0x3F => match opcode & 0x7 {
+            0..=3 => Operands::ModRm,
+            4 => Operands::Imm(1),
+            5 => Operands::Imm(imm_z),
+            // Segment prefixes and instructions that are invalid in 64 bit mode.
+            _ => return None,
+        },
+        0x50..=0x5F | 0x90..=0x99 | 0x9C | 0x9D | 0xC3 | 0xC9 | 0xCC | 0xF4 => Operands::None,
+        0x63 | 0x84..=0x8F | 0xD0..=0xD3 | 0xF6 | 0xF7 | 0xFE | 0xFF => Operands::ModRm,
+        0x68 | 0xA9 => Operands::Imm(imm_z),
+        0x6A | 0xA8 | 0xB0..=0xB7 => Operands::Imm(1),
+        0x69 | 0x81 | 0xC7 => Operands::ModRmImm(imm_z),
+        0x6B | 0x80 | 0x83 | 0xC0 | 0xC1 | 0xC6 => Operands::ModRmImm(1),
+        0x70..=0x7F | 0xEB => Operands::Rel(1),
+        0xB8..=0xBF => Operands::Imm(if rex_w { 8 } else { imm_z }),
+        0xC2 => Operands::Imm(2),
+        0xE8 | 0xE9 => Operands::Rel(4),
+        _ => return None,
+    };
+
+    let mut reloc_field = None;
+    let imm = match operands {
+        Operands::None => 0,
+        Operands::Imm(size) | Operands::Rel(size) => size,
+        Operands::ModRm | Operands::ModRmImm(_) => {
+            let modrm = *code.get(i)?;
+            i += 1;
+            let mode = modrm >> 6;
+            let rm = modrm & 0x7;
+            if mode != 0b11 && rm == 0b100 {
+                let sib = *code.get(i)?;
+                i += 1;
+                if mode == 0b00 && sib & 0x7 == 0b101 {
+                    i += 4;
+                }
+            }
+            match mode {
+                0b00 if rm == 0b101 => {
+                    // RIP relative, the displacement is what relocations patch.
+                    reloc_field = Some(i);
+                    i += 4;
+                }
+                0b01 => i += 1,
+                0b10 => i += 4,
+                _ => {}
+            }
+            match operands {
+                // `TEST r/m, imm` is the only one of these groups with an immediate.
+                Operands::ModRm if matches!(opcode, 0xF6 | 0xF7) && (modrm >> 3) & 0x7 <= 1 => {
+                    if opcode == 0xF6 {
+                        1
+                    } else {
+                        imm_z
+                    }
+                }
+                Operands::ModRmImm(size) => size,
+                _ => 0,
+            }
+        }
+    };
+
+    let len = i + imm;
+    if len > code.len() {
+        return None;
+    }
+    let mut jump_target = None;
+    if let Operands::Rel(size) = operands {
+        if size == 4 {
+            reloc_field = Some(i);
+        }
+        if opcode != 0xE8 {
+            let rel = match size {
+                1 => code[i] as i8 as i64,
+                _ => i32::from_le_bytes(code[i..len].try_into().unwrap()) as i64,
+            };
+            jump_target = Some(len as i64 + rel);
+        }
+    }
+
+    Some(DecodedInstruction {
+        len,
+        reloc_field,
+        jump_target,
+    })
+}
+
 /// Jump near if not equal (ZF=0).
 #[inline(always)]
 fn jne_imm32(buf: &mut Vec<'_, u8>, imm: i32) {
@@ -2657,6 +3691,43 @@ fn lea_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg) {
     ])
 }
 
+/// `LEA r64, [base + index * 2^shift + offset]` -> Store the address of the element at index in r64.
+/// RSP can not be used as the index.
+#[inline(always)]
+#[allow(clippy::unusual_byte_groupings)]
+fn lea_reg64_base_index_offset(
+    buf: &mut Vec<'_, u8>,
+    dst: X86_64GeneralReg,
+    base: X86_64GeneralReg,
+    index: X86_64GeneralReg,
+    shift: u8,
+    offset: i32,
+) {
+    debug_assert!(shift <= 3);
+    debug_assert_ne!(index, X86_64GeneralReg::RSP);
+    let rex = add_reg_extension(dst, REX_W);
+    let rex = add_rm_extension(base, rex);
+    let rex = if index as u8 > 7 {
+        rex | REX_PREFIX_X
+    } else {
+        rex
+    };
+    let dst_mod = dst as u8 % 8;
+    let base_mod = base as u8 % 8;
+    let sib = (shift << 6) | ((index as u8 % 8) << 3) | base_mod;
+
+    // Without a displacement, RBP and R13 as base would mean no base at all.
+    // So they always get at least a zero disp8.
+    if offset == 0 && base_mod != 0b101 {
+        buf.extend([rex, 0x8D, 0b00_000_100 | (dst_mod << 3), sib]);
+    } else if let Ok(disp8) = i8::try_from(offset) {
+        buf.extend([rex, 0x8D, 0b01_000_100 | (dst_mod << 3), sib, disp8 as u8]);
+    } else {
+        buf.extend([rex, 0x8D, 0b10_000_100 | (dst_mod << 3), sib]);
+        buf.extend(offset.to_le_bytes());
+    }
+}
+
 fn raw_mov_reg_reg(
     buf: &mut Vec<'_, u8>,
     register_width: RegisterWidth,
@@ -2790,6 +3861,28 @@ fn mov_base64_offset32_reg64(
     buf.extend(offset.to_le_bytes());
 }
 
+/// `LOCK XADD r/m64,r64` -> Atomically exchange r64 and r/m64, and load their sum into r/m64.
+/// m64 references a base + offset.
+#[inline(always)]
+fn lock_xadd_base64_offset32_reg64(
+    buf: &mut Vec<'_, u8>,
+    base: X86_64GeneralReg,
+    offset: i32,
+    src: X86_64GeneralReg,
+) {
+    let rex = add_rm_extension(base, REX_W);
+    let rex = add_reg_extension(src, rex);
+    let src_mod = (src as u8 % 8) << 3;
+    let base_mod = base as u8 % 8;
+    buf.reserve(10);
+    buf.extend([0xF0, rex, 0x0F, 0xC1, 0x80 | src_mod | base_mod]);
+    // Using RSP or R12 requires a secondary index byte.
+    if base == X86_64GeneralReg::RSP || base == X86_64GeneralReg::R12 {
+        buf.push(0x24);
+    }
+    buf.extend(offset.to_le_bytes());
+}
+
 /// `MOV r/m32,r32` -> Move r32 to r/m32, where m32 references a base + offset.
 #[inline(always)]
 fn mov_base32_offset32_reg32(
@@ -3056,6 +4149,35 @@ fn movd_reg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64Fl
     }
 }
 
+/// `MOVD xmm, r32` -> Move r32 to the low doubleword of xmm, zeroing the rest.
+#[inline(always)]
+fn movd_freg32_reg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64GeneralReg) {
+    let dst_high = dst as u8 > 7;
+    let dst_mod = dst as u8 % 8;
+    let src_high = src as u8 > 7;
+    let src_mod = src as u8 % 8;
+    if dst_high || src_high {
+        let rex = add_rm_extension(src, REX);
+        let rex = add_reg_extension(dst, rex);
+
+        buf.extend([0x66, rex, 0x0F, 0x6E, 0xC0 | (dst_mod << 3) | (src_mod)])
+    } else {
+        buf.extend([0x66, 0x0F, 0x6E, 0xC0 | (dst_mod << 3) | (src_mod)])
+    }
+}
+
+/// `MOVQ xmm, r64` -> Move r64 to the low quadword of xmm.
+#[inline(always)]
+fn movq_freg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64GeneralReg) {
+    let dst_mod = dst as u8 % 8;
+    let src_mod = src as u8 % 8;
+
+    let rex = add_rm_extension(src, REX_W);
+    let rex = add_reg_extension(dst, rex);
+
+    buf.extend([0x66, rex, 0x0F, 0x6E, 0xC0 | (dst_mod << 3) | (src_mod)]);
+}
+
 #[inline(always)]
 fn movq_reg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64FloatReg) {
     let dst_mod = dst as u8 % 8;
@@ -3127,6 +4249,16 @@ fn raw_movss_freg32_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_
     }
 }
 
+/// `MOV r64,r/m64` -> Move r/m64 to r64, where m64 references the instruction pointer + offset.
+#[inline(always)]
+fn mov_reg64_rip_offset32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, offset: u32) {
+    let rex = add_reg_extension(dst, REX_W);
+    let dst_mod = dst as u8 % 8;
+    buf.reserve(7);
+    buf.extend([rex, 0x8B, 0x05 | (dst_mod << 3)]);
+    buf.extend(offset.to_le_bytes());
+}
+
 // `MOVSS xmm, m32` -> Load scalar single-precision floating-point value from m32 to xmm register.
 #[inline(always)]
 fn movss_freg32_rip_offset32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, offset: u32) {
@@ -3163,7 +4295,8 @@ fn movsd_base64_offset32_freg64(
     offset: i32,
     src: X86_64FloatReg,
 ) {
-    let rex = add_rm_extension(base, REX_W);
+    // MOVSD has no 64 bit form, so the REX prefix is only there to reach the upper registers.
+    let rex = add_rm_extension(base, REX);
     let rex = add_reg_extension(src, rex);
     let src_mod = (src as u8 % 8) << 3;
     let base_mod = base as u8 % 8;
@@ -3188,7 +4321,8 @@ fn movsd_freg64_base64_offset32(
     base: X86_64GeneralReg,
     offset: i32,
 ) {
-    let rex = add_rm_extension(base, REX_W);
+    // MOVSD has no 64 bit form, so the REX prefix is only there to reach the upper registers.
+    let rex = add_rm_extension(base, REX);
     let rex = add_reg_extension(dst, rex);
     let dst_mod = (dst as u8 % 8) << 3;
     let base_mod = base as u8 % 8;
@@ -3205,6 +4339,54 @@ fn movsd_freg64_base64_offset32(
     buf.extend(offset.to_le_bytes());
 }
 
+/// `MOVUPS xmm1,m128` -> Move unaligned packed single-precision values from m128 to xmm1.
+#[inline(always)]
+fn movups_freg128_base64_offset32(
+    buf: &mut Vec<'_, u8>,
+    dst: X86_64FloatReg,
+    base: X86_64GeneralReg,
+    offset: i32,
+) {
+    let rex = add_rm_extension(base, REX);
+    let rex = add_reg_extension(dst, rex);
+    let dst_mod = (dst as u8 % 8) << 3;
+    let base_mod = base as u8 % 8;
+    buf.reserve(9);
+    if dst as u8 > 7 || base as u8 > 7 {
+        buf.push(rex);
+    }
+    buf.extend([0x0F, 0x10, 0x80 | dst_mod | base_mod]);
+    // Using RSP or R12 requires a secondary index byte.
+    if base == X86_64GeneralReg::RSP || base == X86_64GeneralReg::R12 {
+        buf.push(0x24);
+    }
+    buf.extend(offset.to_le_bytes());
+}
+
+/// `MOVUPS m128,xmm1` -> Move unaligned packed single-precision values from xmm1 to m128.
+#[inline(always)]
+fn movups_base64_offset32_freg128(
+    buf: &mut Vec<'_, u8>,
+    base: X86_64GeneralReg,
+    offset: i32,
+    src: X86_64FloatReg,
+) {
+    let rex = add_rm_extension(base, REX);
+    let rex = add_reg_extension(src, rex);
+    let src_mod = (src as u8 % 8) << 3;
+    let base_mod = base as u8 % 8;
+    buf.reserve(9);
+    if src as u8 > 7 || base as u8 > 7 {
+        buf.push(rex);
+    }
+    buf.extend([0x0F, 0x11, 0x80 | src_mod | base_mod]);
+    // Using RSP or R12 requires a secondary index byte.
+    if base == X86_64GeneralReg::RSP || base == X86_64GeneralReg::R12 {
+        buf.push(0x24);
+    }
+    buf.extend(offset.to_le_bytes());
+}
+
 /// `MOVSS xmm1,r/m32` -> Move r/m32 to xmm1. where m64 references the base pointer.
 #[inline(always)]
 fn movss_freg32_base32_offset32(
@@ -3276,38 +4458,12 @@ fn cvtsi2_help<T: RegTrait, U: RegTrait>(
     buf.extend([op_code1, rex, 0x0F, op_code2, 0xC0 | mod1 | mod2])
 }
 
-#[inline(always)]
-fn cvtsx2_help<T: RegTrait, V: RegTrait>(
-    buf: &mut Vec<'_, u8>,
-    op_code1: u8,
-    op_code2: u8,
-    dst: T,
-    src: V,
-) {
-    let mod1 = (dst.value() % 8) << 3;
-    let mod2 = src.value() % 8;
-
-    buf.extend([op_code1, 0x0F, op_code2, 0xC0 | mod1 | mod2])
-}
-
 /// `SETE r/m64` -> Set Byte on Condition - zero/equal (ZF=1)
 #[inline(always)]
 fn sete_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
     set_reg64_help(0x94, buf, reg);
 }
 
-/// `CVTSS2SD xmm` -> Convert one single-precision floating-point value in xmm/m32 to one double-precision floating-point value in xmm.
-#[inline(always)]
-fn cvtss2sd_freg64_freg32(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    cvtsx2_help(buf, 0xF3, 0x5A, dst, src)
-}
-
-/// `CVTSD2SS xmm` -> Convert one double-precision floating-point value in xmm to one single-precision floating-point value and merge with high bits.
-#[inline(always)]
-fn cvtsd2ss_freg32_freg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64FloatReg) {
-    cvtsx2_help(buf, 0xF2, 0x5A, dst, src)
-}
-
 /// `CVTSI2SD r/m64` -> Convert one signed quadword integer from r/m64 to one double-precision floating-point value in xmm.
 #[inline(always)]
 fn cvtsi2sd_freg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64FloatReg, src: X86_64GeneralReg) {
@@ -3328,6 +4484,12 @@ fn cvttss2si_reg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86
     cvtsi2_help(buf, 0xF3, 0x2C, dst, src)
 }
 
+/// `CVTTSD2SI xmm/m64` -> Convert one double-precision floating-point value from xmm/m64 to one signed quadword integer in r64 using truncation.
+#[inline(always)]
+fn cvttsd2si_reg64_freg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64FloatReg) {
+    cvtsi2_help(buf, 0xF2, 0x2C, dst, src)
+}
+
 /// `SETNE r/m64` -> Set byte if not equal (ZF=0).
 #[inline(always)]
 fn setne_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
@@ -3411,10 +4573,21 @@ fn sub_reg64_imm32(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, imm: i32) {
     buf.extend(imm.to_le_bytes());
 }
 
-/// `SUB r/m64,r64` -> Sub r64 to r/m64.
+/// `PMOVMSKB r32, xmm` -> Move the top bits of the 16 bytes of xmm into the low 16 bits of r32, zeroing the rest.
 #[inline(always)]
-fn sub_reg64_reg64(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64GeneralReg) {
-    binop_reg64_reg64(0x29, buf, dst, src);
+fn pmovmskb_reg32_freg128(buf: &mut Vec<'_, u8>, dst: X86_64GeneralReg, src: X86_64FloatReg) {
+    let dst_high = dst as u8 > 7;
+    let dst_mod = dst as u8 % 8;
+    let src_high = src as u8 > 7;
+    let src_mod = src as u8 % 8;
+    if dst_high || src_high {
+        let rex = add_rm_extension(src, REX);
+        let rex = add_reg_extension(dst, rex);
+
+        buf.extend([0x66, rex, 0x0F, 0xD7, 0xC0 | (dst_mod << 3) | (src_mod)])
+    } else {
+        buf.extend([0x66, 0x0F, 0xD7, 0xC0 | (dst_mod << 3) | (src_mod)])
+    }
 }
 
 /// `POP r64` -> Pop top of stack into r64; increment stack pointer. Cannot encode 32-bit operand size.
@@ -3447,6 +4620,7 @@ fn push_reg64(buf: &mut Vec<'_, u8>, reg: X86_64GeneralReg) {
 mod tests {
     use super::*;
     use crate::disassembler_test;
+    use crate::generic64::reference_assembler::{check_x86_64_encodings, Case};
     use capstone::prelude::*;
 
     impl X86_64GeneralReg {
@@ -3578,6 +4752,177 @@ mod tests {
         (buf, cs)
     }
 
+    #[test]
+    fn test_setup_stack_probes_large_frames() {
+        let arena = bumpalo::Bump::new();
+        let (mut buf, cs) = setup_capstone_and_arena(&arena);
+
+        let stack_size = x86_64_generic_setup_stack(&mut buf, &[], &[], 3 * 4096 + 16, 0);
+        assert_eq!(stack_size, 3 * 4096 + 16);
+
+        let instructions = cs.disasm_all(&buf, 0).expect("Failed to disassemble");
+        let instructions: std::vec::Vec<String> = instructions
+            .iter()
+            .map(|inst| format!("{} {}", inst.mnemonic().unwrap(), inst.op_str().unwrap()))
+            .collect();
+        assert_eq!(
+            instructions,
+            [
+                "push rbp",
+                "mov rbp, rsp",
+                "mov r11, 3",
+                "sub rsp, 0x1000",
+                "mov qword ptr [rsp], r11",
+                "sub r11, 1",
+                "jne 0xb",
+                "sub rsp, 0x10",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_sub_of_zero_are_skipped() {
+        let arena = bumpalo::Bump::new();
+        let (mut buf, _) = setup_capstone_and_arena::<u8>(&arena);
+        X86_64Assembler::add_reg64_reg64_imm32(
+            &mut buf,
+            X86_64GeneralReg::RAX,
+            X86_64GeneralReg::RAX,
+            0,
+        );
+        X86_64Assembler::sub_reg64_reg64_imm32(
+            &mut buf,
+            X86_64GeneralReg::RSP,
+            X86_64GeneralReg::RSP,
+            0,
+        );
+        assert!(buf.is_empty());
+
+        let mut expected = bumpalo::vec![in &arena];
+        mov_reg64_reg64(&mut expected, X86_64GeneralReg::RAX, X86_64GeneralReg::RCX);
+        X86_64Assembler::add_reg64_reg64_imm32(
+            &mut buf,
+            X86_64GeneralReg::RAX,
+            X86_64GeneralReg::RCX,
+            0,
+        );
+        assert_eq!(buf, expected);
+    }
+
+    // Runs every entry of the encoder tables through capstone, see `define_encoders!`.
+    // The tests are in one module per table, named after the encoders, and check all pairs of `REGS`.
+    macro_rules! encoder_tests {
+        (
+            $reg:ty;
+            $($(#[$attr:meta])* $name:ident = $form:ident($($op:expr),*), $mnemonic:literal;)*
+        ) => {
+            $(
+                #[test]
+                fn $name() {
+                    disassembler_test!(
+                        super::super::$name,
+                        |reg1, reg2| format!("{} {}, {}", $mnemonic, reg1, reg2),
+                        REGS,
+                        REGS
+                    );
+                }
+            )*
+        };
+    }
+
+    // Checks every entry of an encoder table against GNU as, see `reference_assembler`.
+    macro_rules! reference_encoder_test {
+        // Assemblers pick the `mr` encoding when both exist.
+        (@pseudo_prefix rm) => { "{load} " };
+        (@pseudo_prefix $form:ident) => { "" };
+        (
+            $reg:ty;
+            $($(#[$attr:meta])* $name:ident = $form:ident($($op:expr),*), $mnemonic:literal;)*
+        ) => {
+            #[test]
+            #[ignore = "needs GNU as and objcopy"]
+            fn reference_assembler_agrees() {
+                let mut cases = std::vec::Vec::new();
+                $(
+                    for dst in REGS {
+                        for src in REGS {
+                            cases.push(reference_case(
+                                format!(
+                                    "{}{} {}, {}",
+                                    reference_encoder_test!(@pseudo_prefix $form),
+                                    $mnemonic,
+                                    dst,
+                                    src
+                                ),
+                                |buf| super::super::$name(buf, *dst, *src),
+                            ));
+                        }
+                    }
+                )*
+                check_x86_64_encodings(&cases);
+            }
+        };
+    }
+
+    mod reg64_reg64_encoders {
+        use super::*;
+        const REGS: &[X86_64GeneralReg] = ALL_GENERAL_REGS;
+        reg64_reg64_encoders!(encoder_tests);
+        reg64_reg64_encoders!(reference_encoder_test);
+    }
+
+    mod freg_freg_encoders {
+        use super::*;
+        const REGS: &[X86_64FloatReg] = ALL_FLOAT_REGS;
+        freg_freg_encoders!(encoder_tests);
+        freg_freg_encoders!(reference_encoder_test);
+    }
+
+    fn reference_case(asm: String, encode: impl FnOnce(&mut Vec<'_, u8>)) -> Case {
+        let arena = bumpalo::Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        encode(&mut buf);
+        Case {
+            asm,
+            code: buf.to_vec(),
+        }
+    }
+
+    #[test]
+    #[ignore = "needs GNU as and objcopy"]
+    fn test_memory_forms_reference_assembler_agrees() {
+        let mut cases = std::vec::Vec::new();
+        for reg in ALL_GENERAL_REGS {
+            cases.push(reference_case(
+                format!("movabs {reg}, {TEST_I64:#x}"),
+                |buf| mov_reg64_imm64(buf, *reg, TEST_I64),
+            ));
+            for base in ALL_GENERAL_REGS {
+                cases.push(reference_case(
+                    format!("{{disp32}} mov {reg}, qword ptr [{base} + {TEST_I32:#x}]"),
+                    |buf| mov_reg64_base64_offset32(buf, *reg, *base, TEST_I32),
+                ));
+                cases.push(reference_case(
+                    format!("{{disp32}} mov qword ptr [{base} + {TEST_I32:#x}], {reg}"),
+                    |buf| mov_base64_offset32_reg64(buf, *base, TEST_I32, *reg),
+                ));
+            }
+        }
+        for reg in ALL_FLOAT_REGS {
+            for base in ALL_GENERAL_REGS {
+                cases.push(reference_case(
+                    format!("{{disp32}} movsd {reg}, qword ptr [{base} + {TEST_I32:#x}]"),
+                    |buf| movsd_freg64_base64_offset32(buf, *reg, *base, TEST_I32),
+                ));
+                cases.push(reference_case(
+                    format!("{{disp32}} movsd qword ptr [{base} + {TEST_I32:#x}], {reg}"),
+                    |buf| movsd_base64_offset32_freg64(buf, *base, TEST_I32, *reg),
+                ));
+            }
+        }
+        check_x86_64_encodings(&cases);
+    }
+
     #[test]
     fn test_add_reg64_imm32() {
         disassembler_test!(
@@ -3589,108 +4934,98 @@ mod tests {
     }
 
     #[test]
-    fn test_add_reg64_reg64() {
+    fn test_and_reg64_imm32() {
         disassembler_test!(
-            add_reg64_reg64,
-            |reg1, reg2| format!("add {}, {}", reg1, reg2),
+            and_reg64_imm32,
+            |reg, imm| format!("and {}, 0x{:x}", reg, imm),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [TEST_I32]
         );
     }
 
     #[test]
-    fn test_sub_reg64_reg64() {
+    fn test_shl_reg64_reg64() {
         disassembler_test!(
-            sub_reg64_reg64,
-            |reg1, reg2| format!("sub {}, {}", reg1, reg2),
-            ALL_GENERAL_REGS,
+            shl_reg64_reg64,
+            |reg| format!("shl {reg}, cl"),
             ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_addsd_freg64_freg64() {
-        disassembler_test!(
-            addsd_freg64_freg64,
-            |reg1, reg2| format!("addsd {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
-        );
-    }
-
-    #[test]
-    fn test_addss_freg32_freg32() {
+    fn test_shr_reg64_reg64() {
         disassembler_test!(
-            addss_freg32_freg32,
-            |reg1, reg2| format!("addss {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
+            shr_reg64_reg64,
+            |reg| format!("shr {reg}, cl"),
+            ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_andpd_freg64_freg64() {
+    fn test_sar_reg64_reg64() {
         disassembler_test!(
-            andpd_freg64_freg64,
-            |reg1, reg2| format!("andpd {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
+            sar_reg64_reg64,
+            |reg| format!("sar {reg}, cl"),
+            ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_and_reg64_reg64() {
+    fn test_shl_reg64_imm8() {
         disassembler_test!(
-            and_reg64_reg64,
-            |reg1, reg2| format!("and {reg1}, {reg2}"),
+            shl_reg64_imm8,
+            |reg, imm| format!("shl {reg}, {imm:#x}"),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [0x0Fu8, 0x3F]
         );
     }
 
     #[test]
-    fn test_or_reg64_reg64() {
+    fn test_shr_reg64_imm8() {
         disassembler_test!(
-            or_reg64_reg64,
-            |reg1, reg2| format!("or {reg1}, {reg2}"),
+            shr_reg64_imm8,
+            |reg, imm| format!("shr {reg}, {imm:#x}"),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [0x0Fu8, 0x3F]
         );
     }
 
     #[test]
-    fn test_xor_reg64_reg64() {
+    fn test_sar_reg64_imm8() {
         disassembler_test!(
-            xor_reg64_reg64,
-            |reg1, reg2| format!("xor {reg1}, {reg2}"),
+            sar_reg64_imm8,
+            |reg, imm| format!("sar {reg}, {imm:#x}"),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [0x0Fu8, 0x3F]
         );
     }
 
     #[test]
-    fn test_shl_reg64_reg64() {
+    fn test_cmove_reg64_reg64() {
         disassembler_test!(
-            shl_reg64_reg64,
-            |reg| format!("shl {reg}, cl"),
+            cmove_reg64_reg64,
+            |reg1, reg2| format!("cmove {}, {}", reg1, reg2),
+            ALL_GENERAL_REGS,
             ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_shr_reg64_reg64() {
+    fn test_cmovo_reg64_reg64() {
         disassembler_test!(
-            shr_reg64_reg64,
-            |reg| format!("shr {reg}, cl"),
+            cmovo_reg64_reg64,
+            |reg1, reg2| format!("cmovo {}, {}", reg1, reg2),
+            ALL_GENERAL_REGS,
             ALL_GENERAL_REGS
         );
     }
 
     #[test]
-    fn test_sar_reg64_reg64() {
+    fn test_cmovb_reg64_reg64() {
         disassembler_test!(
-            sar_reg64_reg64,
-            |reg| format!("sar {reg}, cl"),
+            cmovb_reg64_reg64,
+            |reg1, reg2| format!("cmovb {}, {}", reg1, reg2),
+            ALL_GENERAL_REGS,
             ALL_GENERAL_REGS
         );
     }
@@ -3706,22 +5041,40 @@ mod tests {
     }
 
     #[test]
-    fn test_cmp_reg64_imm32() {
+    fn test_cmp_reg_imm32() {
         disassembler_test!(
-            cmp_reg64_imm32,
-            |reg, imm| format!("cmp {}, 0x{:x}", reg, imm),
+            cmp_reg_imm32,
+            |w, reg, imm| match w {
+                RegisterWidth::W8 => format!(
+                    "cmp {}, 0x{:x}",
+                    X86_64GeneralReg::low_8bits_string(&reg),
+                    imm
+                ),
+                RegisterWidth::W16 => format!(
+                    "cmp {}, 0x{:x}",
+                    X86_64GeneralReg::low_16bits_string(&reg),
+                    imm
+                ),
+                RegisterWidth::W32 => format!(
+                    "cmp {}, 0x{:x}",
+                    X86_64GeneralReg::low_32bits_string(&reg),
+                    imm
+                ),
+                RegisterWidth::W64 => format!("cmp {}, 0x{:x}", reg, imm),
+            },
+            ALL_REGISTER_WIDTHS,
             ALL_GENERAL_REGS,
-            [TEST_I32]
+            [0x12]
         );
     }
 
     #[test]
-    fn test_imul_reg64_reg64() {
+    fn test_cmp_reg64_imm32() {
         disassembler_test!(
-            imul_reg64_reg64,
-            |reg1, reg2| format!("imul {}, {}", reg1, reg2),
+            cmp_reg64_imm32,
+            |reg, imm| format!("cmp {}, 0x{:x}", reg, imm),
             ALL_GENERAL_REGS,
-            ALL_GENERAL_REGS
+            [TEST_I32]
         );
     }
 
@@ -3735,23 +5088,8 @@ mod tests {
     }
 
     #[test]
-    fn test_mulsd_freg64_freg64() {
-        disassembler_test!(
-            mulsd_freg64_freg64,
-            |reg1, reg2| format!("mulsd {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
-        );
-    }
-
-    #[test]
-    fn test_mulss_freg32_freg32() {
-        disassembler_test!(
-            mulss_freg32_freg32,
-            |reg1, reg2| format!("mulss {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
-        );
+    fn test_imul_reg64() {
+        disassembler_test!(imul_reg64, |reg| format!("imul {}", reg), ALL_GENERAL_REGS);
     }
 
     #[test]
@@ -3773,32 +5111,72 @@ mod tests {
     }
 
     #[test]
-    fn test_divsd_freg64_freg64() {
+    fn test_jmp_imm32() {
+        const INST_SIZE: i32 = 5;
         disassembler_test!(
-            divsd_freg64_freg64,
-            |reg1, reg2| format!("divsd {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
+            jmp_imm32,
+            |imm| format!("jmp 0x{:x}", imm + INST_SIZE),
+            [TEST_I32]
+        );
+    }
+
+    #[test]
+    fn test_call_reg64() {
+        disassembler_test!(call_reg64, |reg| format!("call {}", reg), ALL_GENERAL_REGS);
+    }
+
+    #[test]
+    fn test_jmp_reg64() {
+        disassembler_test!(jmp_reg64, |reg| format!("jmp {}", reg), ALL_GENERAL_REGS);
+    }
+
+    #[test]
+    fn test_jmp_table_reg64() {
+        let arena = bumpalo::Bump::new();
+        let (mut buf, cs) = setup_capstone_and_arena(&arena);
+        X86_64Assembler::jmp_table_reg64(&mut buf, X86_64GeneralReg::R11, X86_64GeneralReg::RAX);
+        let table = buf.len();
+        for _ in 0..3 {
+            X86_64Assembler::jmp_imm32(&mut buf, 0);
+        }
+
+        let instructions = cs.disasm_all(&buf, 0).expect("Failed to disassemble");
+        let text: std::vec::Vec<String> = instructions
+            .iter()
+            .take(4)
+            .map(|inst| format!("{} {}", inst.mnemonic().unwrap(), inst.op_str().unwrap()))
+            .collect();
+        assert_eq!(
+            text,
+            [
+                format!("lea r11, [rip + {:#x}]", table - 7),
+                "lea r11, [r11 + rax*4]".to_string(),
+                "add r11, rax".to_string(),
+                "jmp r11".to_string(),
+            ]
         );
     }
 
     #[test]
-    fn test_divss_freg32_freg32() {
+    fn test_jmp_imm8() {
+        const INST_SIZE: i32 = 2;
         disassembler_test!(
-            divss_freg32_freg32,
-            |reg1, reg2| format!("divss {}, {}", reg1, reg2),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
+            jmp_imm8,
+            |imm: i8| format!("jmp 0x{:x}", imm as i32 + INST_SIZE),
+            [0x12i8]
         );
     }
 
     #[test]
-    fn test_jmp_imm32() {
-        const INST_SIZE: i32 = 5;
+    fn test_jcc_imm8() {
+        const INST_SIZE: i32 = 2;
         disassembler_test!(
-            jmp_imm32,
-            |imm| format!("jmp 0x{:x}", imm + INST_SIZE),
-            [TEST_I32]
+            |buf: &mut Vec<'_, u8>, (condition_code, _): (u8, &str), imm: i8| {
+                jcc_imm8(buf, condition_code, imm)
+            },
+            |(_, jcc): (u8, &str), imm: i8| format!("{} 0x{:x}", jcc, imm as i32 + INST_SIZE),
+            [(0x4, "je"), (0x5, "jne"), (0xC, "jl")],
+            [0x12i8]
         );
     }
 
@@ -3812,6 +5190,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_jcc_reg64_reg64_imm32() {
+        use CompareOperation::*;
+        // The cmp is 3 bytes and the jump 6.
+        const INST_SIZE: i32 = 9;
+        let conditions = [
+            (IntCondition::Equal, "je"),
+            (IntCondition::NotEqual, "jne"),
+            (IntCondition::Signed(LessThan), "jl"),
+            (IntCondition::Signed(LessThanOrEqual), "jle"),
+            (IntCondition::Signed(GreaterThan), "jg"),
+            (IntCondition::Signed(GreaterThanOrEqual), "jge"),
+            (IntCondition::Unsigned(LessThan), "jb"),
+            (IntCondition::Unsigned(LessThanOrEqual), "jbe"),
+            (IntCondition::Unsigned(GreaterThan), "ja"),
+            (IntCondition::Unsigned(GreaterThanOrEqual), "jae"),
+        ];
+        disassembler_test!(
+            |buf: &mut Vec<'_, u8>, (condition, _), imm| {
+                X86_64Assembler::jcc_reg64_reg64_imm32(
+                    buf,
+                    RegisterWidth::W64,
+                    condition,
+                    X86_64GeneralReg::RAX,
+                    X86_64GeneralReg::RCX,
+                    imm,
+                );
+            },
+            |(_, jcc), imm| format!("cmp rax, rcx\n{} 0x{:x}", jcc, imm + INST_SIZE),
+            conditions,
+            [TEST_I32]
+        );
+    }
+
     #[test]
     fn test_mov_reg64_imm32() {
         disassembler_test!(
@@ -3847,6 +5259,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lea_reg64_base_index_offset() {
+        let index_regs: std::vec::Vec<_> = ALL_GENERAL_REGS
+            .iter()
+            .copied()
+            .filter(|reg| *reg != X86_64GeneralReg::RSP)
+            .collect();
+        disassembler_test!(
+            |buf, dst, base, index| lea_reg64_base_index_offset(buf, dst, base, index, 0, 0),
+            |dst, base, index| format!("lea {dst}, [{base} + {index}]"),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            index_regs
+        );
+        disassembler_test!(
+            |buf, dst, base, index| lea_reg64_base_index_offset(buf, dst, base, index, 2, 0x10),
+            |dst, base, index| format!("lea {dst}, [{base} + {index}*4 + 0x10]"),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            index_regs
+        );
+        disassembler_test!(
+            |buf, dst, base, index| lea_reg64_base_index_offset(buf, dst, base, index, 3, TEST_I32),
+            |dst, base, index| format!("lea {dst}, [{base} + {index}*8 + 0x{:x}]", TEST_I32),
+            ALL_GENERAL_REGS,
+            ALL_GENERAL_REGS,
+            index_regs
+        );
+    }
+
     #[test]
     fn test_mov_reg64_reg64() {
         disassembler_test!(
@@ -3944,6 +5386,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_movups_freg128_base64_offset32() {
+        disassembler_test!(
+            movups_freg128_base64_offset32,
+            |reg1, reg2, imm| format!("movups {}, xmmword ptr [{} + 0x{:x}]", reg1, reg2, imm),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS,
+            [TEST_I32]
+        );
+    }
+
+    #[test]
+    fn test_movups_base64_offset32_freg128() {
+        disassembler_test!(
+            movups_base64_offset32_freg128,
+            |reg1, imm, reg2| format!("movups xmmword ptr [{} + 0x{:x}], {}", reg1, imm, reg2),
+            ALL_GENERAL_REGS,
+            [TEST_I32],
+            ALL_FLOAT_REGS
+        );
+    }
+
     #[test]
     fn test_movss_freg32_base32_offset32() {
         disassembler_test!(
@@ -4036,6 +5500,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lock_xadd_base64_offset32_reg64() {
+        disassembler_test!(
+            lock_xadd_base64_offset32_reg64,
+            |reg1, imm, reg2| format!("lock xadd qword ptr [{} + 0x{:x}], {}", reg1, imm, reg2),
+            ALL_GENERAL_REGS,
+            [TEST_I32],
+            ALL_GENERAL_REGS
+        );
+    }
+
     #[test]
     fn test_mov_base32_offset32_reg32() {
         disassembler_test!(
@@ -4149,6 +5624,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_movd_freg32_reg32() {
+        disassembler_test!(
+            movd_freg32_reg32,
+            |dst, src: X86_64GeneralReg| format!("movd {}, {}", dst, src.low_32bits_string()),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_movq_freg64_reg64() {
+        disassembler_test!(
+            movq_freg64_reg64,
+            |dst, src| format!("movq {}, {}", dst, src),
+            ALL_FLOAT_REGS,
+            ALL_GENERAL_REGS
+        );
+    }
+
+    #[test]
+    fn test_cvttsd2si_reg64_freg64() {
+        disassembler_test!(
+            cvttsd2si_reg64_freg64,
+            |reg1, reg2| format!("cvttsd2si {}, {}", reg1, reg2),
+            ALL_GENERAL_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
     #[test]
     fn test_movq_reg64_freg64() {
         disassembler_test!(
@@ -4179,6 +5684,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_mov_reg64_rip_offset32() {
+        disassembler_test!(
+            mov_reg64_rip_offset32,
+            |reg, imm| format!("mov {}, qword ptr [rip + 0x{:x}]", reg, imm),
+            ALL_GENERAL_REGS,
+            [TEST_I32 as u32]
+        );
+    }
+
     #[test]
     fn test_movss_freg32_rip_offset32() {
         disassembler_test!(
@@ -4257,6 +5772,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pmovmskb_reg32_freg128() {
+        disassembler_test!(
+            pmovmskb_reg32_freg128,
+            |dst: X86_64GeneralReg, src| format!("pmovmskb {}, {}", dst.low_32bits_string(), src),
+            ALL_GENERAL_REGS,
+            ALL_FLOAT_REGS
+        );
+    }
+
     #[test]
     fn test_pop_reg64() {
         disassembler_test!(pop_reg64, |reg| format!("pop {}", reg), ALL_GENERAL_REGS);
@@ -4267,26 +5792,6 @@ mod tests {
         disassembler_test!(push_reg64, |reg| format!("push {}", reg), ALL_GENERAL_REGS);
     }
 
-    #[test]
-    fn test_sqrt_freg64_freg64() {
-        disassembler_test!(
-            sqrtsd_freg64_freg64,
-            |dst, src| format!("sqrtsd {dst}, {src}"),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
-        );
-    }
-
-    #[test]
-    fn test_sqrt_freg32_freg32() {
-        disassembler_test!(
-            sqrtss_freg32_freg32,
-            |dst, src| format!("sqrtss {dst}, {src}"),
-            ALL_FLOAT_REGS,
-            ALL_FLOAT_REGS
-        );
-    }
-
     #[test]
     fn test_int_cmp() {
         disassembler_test!(
@@ -4333,4 +5838,155 @@ mod tests {
             ALL_GENERAL_REGS
         );
     }
+
+    #[test]
+    fn windows_fastcall_passes_args_by_position() {
+        use roc_mono::layout::Layout;
+        use roc_target::TargetInfo;
+
+        type SystemV = X86_64SystemV;
+        type Windows = X86_64WindowsFastcall;
+        fn param_reg<'a, CC: CallConv<X86_64GeneralReg, X86_64FloatReg, X86_64Assembler>>(
+            interner: &STLayoutInterner<'a>,
+            arg_layouts: &[InLayout<'a>],
+            index: usize,
+        ) -> Option<X86_64GeneralReg> {
+            CC::general_param_reg_for_arg(interner, arg_layouts, index, &Layout::I64)
+        }
+        fn in_registers<'a>(
+            interner: &STLayoutInterner<'a>,
+            arg_layouts: &[InLayout<'a>],
+        ) -> (bool, bool) {
+            (
+                <SystemV as CallConv<_, _, X86_64Assembler>>::args_in_registers(
+                    interner,
+                    arg_layouts,
+                    &Layout::I64,
+                ),
+                <Windows as CallConv<_, _, X86_64Assembler>>::args_in_registers(
+                    interner,
+                    arg_layouts,
+                    &Layout::I64,
+                ),
+            )
+        }
+
+        let interner = STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64());
+        let args = [Layout::I64, Layout::F64, Layout::I64];
+        assert_eq!(
+            param_reg::<SystemV>(&interner, &args, 2),
+            Some(X86_64GeneralReg::RSI)
+        );
+        assert_eq!(
+            param_reg::<Windows>(&interner, &args, 2),
+            Some(X86_64GeneralReg::R8)
+        );
+
+        // Windows has 4 slots for general and float args together.
+        let args = [
+            Layout::I64,
+            Layout::F64,
+            Layout::I64,
+            Layout::F64,
+            Layout::I64,
+        ];
+        assert_eq!(in_registers(&interner, &args[..4]), (true, true));
+        assert_eq!(in_registers(&interner, &args), (true, false));
+    }
+
+    #[test]
+    fn windows_fastcall_returns_128_bit_integers_in_rax_and_rdx() {
+        use roc_mono::layout::Layout;
+        use roc_target::TargetInfo;
+
+        type Windows = X86_64WindowsFastcall;
+        let mut interner = STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64());
+        let pair =
+            interner.insert_direct_no_semantic(LayoutRepr::Struct(&[Layout::U64, Layout::U64]));
+
+        assert!(!Windows::returns_via_arg_pointer(&interner, &Layout::I128));
+        assert!(!Windows::returns_via_arg_pointer(&interner, &Layout::U128));
+        assert!(Windows::returns_via_arg_pointer(&interner, &pair));
+        assert_eq!(Windows::GENERAL_RETURN_REGS[1], X86_64GeneralReg::RDX);
+
+        // There is no hidden result pointer in RCX in front of the args.
+        let param_reg = |ret_layout| {
+            <Windows as CallConv<_, _, X86_64Assembler>>::general_param_reg_for_arg(
+                &interner,
+                &[Layout::I64],
+                0,
+                &ret_layout,
+            )
+        };
+        assert_eq!(param_reg(Layout::I128), Some(X86_64GeneralReg::RCX));
+        assert_eq!(param_reg(pair), Some(X86_64GeneralReg::RDX));
+    }
+
+    #[test]
+    fn windows_fastcall_keeps_the_upper_xmm_regs() {
+        type Windows = X86_64WindowsFastcall;
+        let callee_saved = <Windows as CallConv<_, _, X86_64Assembler>>::float_callee_saved;
+        assert!(!callee_saved(&X86_64FloatReg::XMM0));
+        assert!(!callee_saved(&X86_64FloatReg::XMM5));
+        assert!(callee_saved(&X86_64FloatReg::XMM6));
+        assert!(callee_saved(&X86_64FloatReg::XMM15));
+    }
+
+    #[test]
+    fn results_of_integers_are_integer_class() {
+        use roc_mono::layout::Layout;
+        use roc_target::TargetInfo;
+
+        let mut interner = STLayoutInterner::with_capacity(4, TargetInfo::default_x86_64());
+        let int_tags: &[&[_]] = &[&[Layout::U8], &[Layout::I64]];
+        let int_result = interner
+            .insert_direct_no_semantic(LayoutRepr::Union(UnionLayout::NonRecursive(int_tags)));
+        let float_tags: &[&[_]] = &[&[Layout::U8], &[Layout::F64]];
+        let float_result = interner
+            .insert_direct_no_semantic(LayoutRepr::Union(UnionLayout::NonRecursive(float_tags)));
+
+        assert!(is_integer_class(&interner, int_result));
+        assert!(!is_integer_class(&interner, float_result));
+    }
+
+    #[test]
+    fn data_pointer_into_the_middle_of_a_symbol() {
+        let arena = bumpalo::Bump::new();
+        let mut buf = bumpalo::vec![in &arena];
+        let mut relocs = bumpalo::vec![in &arena];
+
+        X86_64Assembler::data_pointer(
+            &mut buf,
+            &mut relocs,
+            "global".into(),
+            0,
+            X86_64GeneralReg::RAX,
+        );
+        X86_64Assembler::data_pointer(
+            &mut buf,
+            &mut relocs,
+            "global".into(),
+            8,
+            X86_64GeneralReg::RAX,
+        );
+
+        // Only the start of the symbol is in the GOT, the field is addressed relative to the lea.
+        assert_eq!(buf[1], 0x8B);
+        assert_eq!(buf[8], 0x8D);
+        assert!(matches!(
+            relocs.as_slice(),
+            [
+                Relocation::LinkedData {
+                    offset: 3,
+                    addend: 0,
+                    ..
+                },
+                Relocation::LinkedData {
+                    offset: 10,
+                    addend: 8,
+                    ..
+                },
+            ]
+        ));
+    }
 }
@@ -0,0 +1,59 @@
+//! Swapping the code of procs in a running program that was built with `Env::hot_reload`.
+//!
+//! Every user proc then gets a data symbol, its slot, that holds the address of the proc.
+//! Calls between procs load that address and call it, so writing the address of a recompiled proc
+//! into the slot makes every later call run the new code. Calls that already started finish in the old code.
+
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// The name of the slot that calls to the proc named `fn_name` go through.
+/// Its address can be looked up like any other exported symbol, e.g. with dlsym.
+pub fn hot_reload_slot_name(fn_name: &str) -> String {
+    format!("{}.hot_reload_slot", fn_name)
+}
+
+/// Points the hot reload slot at `slot` to `new_fn` and returns the address it held before.
+///
+/// # Safety
+///
+/// `slot` must be the address of a hot reload slot, and `new_fn` must be a proc with the same
+/// arguments and return layout that stays loaded for as long as the program may call it.
+pub unsafe fn patch_hot_reload_slot(slot: *mut *const u8, new_fn: *const u8) -> *const u8 {
+    let slot = &*(slot as *const AtomicPtr<u8>);
+    slot.swap(new_fn as *mut u8, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern "C" fn one() -> i64 {
+        1
+    }
+
+    extern "C" fn two() -> i64 {
+        2
+    }
+
+    #[test]
+    fn patched_slots_call_the_new_proc() {
+        let mut slot = one as *const u8;
+        let call = |slot: *const u8| {
+            let f: extern "C" fn() -> i64 = unsafe { std::mem::transmute(slot) };
+            f()
+        };
+        assert_eq!(call(slot), 1);
+
+        let old = unsafe { patch_hot_reload_slot(&mut slot, two as *const u8) };
+        assert_eq!(old, one as *const u8);
+        assert_eq!(call(slot), 2);
+    }
+
+    #[test]
+    fn slot_names_are_derived_from_the_proc_name() {
+        assert_eq!(
+            hot_reload_slot_name("UserApp_main_123"),
+            "UserApp_main_123.hot_reload_slot"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use bumpalo::Bump;
+use roc_collections::all::MutMap;
+use roc_error_macros::internal_error;
+use roc_module::symbol::Symbol;
+use roc_mono::ir::{Call, CallType, Expr, Proc, ProcLayout, SelfRecursive, Stmt};
+
+/// A proc whose body is a single expression, returned right away.
+struct Tiny<'a> {
+    params: &'a [Symbol],
+    expr: Expr<'a>,
+}
+
+/// Splices the expression of tiny procs into the procs calling them, so they do not pay for a call.
+/// A proc is tiny if its body is a single expression on its params, with no refcounting,
+/// so the expression moves to the caller with the same ownership of the params.
+/// The procs themselves stay, the host or a function pointer may still refer to them.
+pub(crate) fn inline_tiny_procs<'a>(
+    arena: &'a Bump,
+    procedures: &mut MutMap<(Symbol, ProcLayout<'a>), Proc<'a>>,
+) {
+    let tiny: MutMap<(Symbol, ProcLayout<'a>), Tiny<'a>> = procedures
+        .iter()
+        .filter_map(|(key, proc)| Some((*key, tiny_proc(arena, proc)?)))
+        .collect();
+    if tiny.is_empty() {
+        return;
+    }
+
+    for ((sym, _), proc) in procedures.iter_mut() {
+        if let Some(body) = inline_stmt(arena, &tiny, *sym, &proc.body) {
+            proc.body = body;
+        }
+    }
+}
+
+fn tiny_proc<'a>(arena: &'a Bump, proc: &Proc<'a>) -> Option<Tiny<'a>> {
+    if !matches!(proc.is_self_recursive, SelfRecursive::NotSelfRecursive) {
+        return None;
+    }
+    let Stmt::Let(sym, expr, _, Stmt::Ret(ret)) = &proc.body else {
+        return None;
+    };
+    if sym != ret {
+        return None;
+    }
+
+    let params = arena.alloc_slice_fill_iter(proc.args.iter().map(|(_, sym)| *sym));
+    let is_param = |sym: &Symbol| params.contains(sym);
+    let inlinable = match expr {
+        Expr::Literal(_) => true,
+        Expr::Struct(fields) => fields.iter().all(is_param),
+        Expr::StructAtIndex { structure, .. } => is_param(structure),
+        Expr::Call(Call {
+            call_type: CallType::LowLevel { .. },
+            arguments,
+        }) => arguments.iter().all(is_param),
+        Expr::Call(Call {
+            call_type: CallType::ByName { name, .. },
+            arguments,
+        }) => name.name() != proc.name.name() && arguments.iter().all(is_param),
+        _ => false,
+    };
+    if !inlinable {
+        return None;
+    }
+
+    Some(Tiny {
+        params,
+        expr: expr.clone(),
+    })
+}
+
+/// The expression of `tiny` with its params replaced by `args`.
+fn substitute<'a>(arena: &'a Bump, tiny: &Tiny<'a>, args: &[Symbol]) -> Expr<'a> {
+    let arg = |sym: &Symbol| {
+        let index = tiny.params.iter().position(|param| param == sym).unwrap();
+        args[index]
+    };
+    let args =
+        |syms: &[Symbol]| -> &'a [Symbol] { arena.alloc_slice_fill_iter(syms.iter().map(&arg)) };
+    match &tiny.expr {
+        Expr::Literal(lit) => Expr::Literal(*lit),
+        Expr::Struct(fields) => Expr::Struct(args(fields)),
+        Expr::StructAtIndex {
+            index,
+            field_layouts,
+            structure,
+        } => Expr::StructAtIndex {
+            index: *index,
+            field_layouts: *field_layouts,
+            structure: arg(structure),
+        },
+        Expr::Call(Call {
+            call_type,
+            arguments,
+        }) => Expr::Call(Call {
+            call_type: call_type.clone(),
+            arguments: args(arguments),
+        }),
+        other => internal_error!("{:?} is not inlined", other),
+    }
+}
+
+/// `stmt` with the calls to tiny procs replaced by their expression,
+/// or `None` if it does not call any.
+fn inline_stmt<'a>(
+    arena: &'a Bump,
+    tiny: &MutMap<(Symbol, ProcLayout<'a>), Tiny<'a>>,
+    caller: Symbol,
+    stmt: &Stmt<'a>,
+) -> Option<Stmt<'a>> {
+    let inline = |stmt: &Stmt<'a>| inline_stmt(arena, tiny, caller, stmt);
+    match stmt {
+        Stmt::Let(sym, expr, layout, cont) => {
+            let inlined = match expr {
+                Expr::Call(Call {
+                    call_type:
+                        CallType::ByName {
+                            name,
+                            ret_layout,
+                            arg_layouts,
+                            ..
+                        },
+                    arguments,
+                }) if name.name() != caller => {
+                    let key = (
+                        name.name(),
+                        ProcLayout {
+                            arguments: *arg_layouts,
+                            result: *ret_layout,
+                            niche: name.niche(),
+                        },
+                    );
+                    tiny.get(&key)
+                        .map(|tiny| substitute(arena, tiny, arguments))
+                }
+                _ => None,
+            };
+            let cont_inlined = inline(cont);
+            if inlined.is_none() && cont_inlined.is_none() {
+                return None;
+            }
+            Some(Stmt::Let(
+                *sym,
+                inlined.unwrap_or_else(|| expr.clone()),
+                *layout,
+                reuse(arena, *cont, cont_inlined),
+            ))
+        }
+        Stmt::Switch {
+            cond_symbol,
+            cond_layout,
+            branches,
+            default_branch,
+            ret_layout,
+        } => {
+            let branches_inlined: std::vec::Vec<_> =
+                branches.iter().map(|(_, _, stmt)| inline(stmt)).collect();
+            let default_inlined = inline(default_branch.1);
+            if branches_inlined.iter().all(Option::is_none) && default_inlined.is_none() {
+                return None;
+            }
+            let branches = arena.alloc_slice_fill_iter(branches.iter().zip(branches_inlined).map(
+                |((id, info, stmt), inlined)| {
+                    (*id, info.clone(), inlined.unwrap_or_else(|| stmt.clone()))
+                },
+            ));
+            Some(Stmt::Switch {
+                cond_symbol: *cond_symbol,
+                cond_layout: *cond_layout,
+                branches,
+                default_branch: (
+                    default_branch.0.clone(),
+                    reuse(arena, default_branch.1, default_inlined),
+                ),
+                ret_layout: *ret_layout,
+            })
+        }
+        Stmt::Refcounting(modify, cont) => {
+            let cont_inlined = inline(cont)?;
+            Some(Stmt::Refcounting(*modify, arena.alloc(cont_inlined)))
+        }
+        Stmt::Expect {
+            condition,
+            region,
+            lookups,
+            variables,
+            remainder,
+        } => {
+            let remainder = arena.alloc(inline(remainder)?);
+            Some(Stmt::Expect {
+                condition: *condition,
+                region: *region,
+                lookups: *lookups,
+                variables: *variables,
+                remainder,
+            })
+        }
+        Stmt::ExpectFx {
+            condition,
+            region,
+            lookups,
+            variables,
+            remainder,
+        } => {
+            let remainder = arena.alloc(inline(remainder)?);
+            Some(Stmt::ExpectFx {
+                condition: *condition,
+                region: *region,
+                lookups: *lookups,
+                variables: *variables,
+                remainder,
+            })
+        }
+        Stmt::Dbg {
+            symbol,
+            variable,
+            remainder,
+        } => {
+            let remainder = arena.alloc(inline(remainder)?);
+            Some(Stmt::Dbg {
+                symbol: *symbol,
+                variable: *variable,
+                remainder,
+            })
+        }
+        Stmt::Join {
+            id,
+            parameters,
+            body,
+            remainder,
+        } => {
+            let body_inlined = inline(body);
+            let remainder_inlined = inline(remainder);
+            if body_inlined.is_none() && remainder_inlined.is_none() {
+                return None;
+            }
+            Some(Stmt::Join {
+                id: *id,
+                parameters: *parameters,
+                body: reuse(arena, *body, body_inlined),
+                remainder: reuse(arena, *remainder, remainder_inlined),
+            })
+        }
+        Stmt::Ret(_) | Stmt::Jump(..) | Stmt::Crash(..) => None,
+    }
+}
+
+fn reuse<'a>(arena: &'a Bump, stmt: &'a Stmt<'a>, inlined: Option<Stmt<'a>>) -> &'a Stmt<'a> {
+    match inlined {
+        Some(inlined) => arena.alloc(inlined),
+        None => stmt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm_snapshot::Fixture;
+    use roc_module::low_level::LowLevel;
+    use roc_mono::layout::Layout;
+
+    fn u64_proc<'a>(f: &mut Fixture<'a>, name: &str, args: &[Symbol], body: Stmt<'a>) -> Proc<'a> {
+        let args: std::vec::Vec<_> = args.iter().map(|sym| (Layout::U64, *sym)).collect();
+        f.proc(name, &args, body, Layout::U64)
+    }
+
+    fn let_ret<'a>(f: &Fixture<'a>, ret: Symbol, expr: Expr<'a>) -> Stmt<'a> {
+        Stmt::Let(ret, expr, Layout::U64, f.arena.alloc(Stmt::Ret(ret)))
+    }
+
+    fn body_of<'a, 'p>(
+        procedures: &'p MutMap<(Symbol, ProcLayout<'a>), Proc<'a>>,
+        proc: &Proc<'a>,
+    ) -> &'p Stmt<'a> {
+        &procedures
+            .values()
+            .find(|p| p.name == proc.name)
+            .unwrap()
+            .body
+    }
+
+    #[test]
+    fn tiny_procs_are_inlined_with_the_args_of_the_call() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let [a, b, x, y, ret] = ["a", "b", "x", "y", "ret"].map(|name| f.symbol(name));
+        let add = f.low_level(LowLevel::NumAdd, &[b, a]);
+        let callee_body = let_ret(f, ret, add);
+        let callee = u64_proc(f, "callee", &[a, b], callee_body.clone());
+        let call = f.call(&callee, &[x, y]);
+        let caller_body = let_ret(f, ret, call);
+        let caller = u64_proc(f, "caller", &[x, y], caller_body);
+        let mut procedures = f.procedures(&[callee.clone(), caller.clone()]);
+
+        inline_tiny_procs(&arena, &mut procedures);
+
+        let add = f.low_level(LowLevel::NumAdd, &[y, x]);
+        assert_eq!(body_of(&procedures, &caller), &let_ret(f, ret, add));
+        // The callee stays as it is, for any other references to it.
+        assert_eq!(body_of(&procedures, &callee), &callee_body);
+    }
+
+    #[test]
+    fn procs_with_more_than_one_expression_are_not_inlined() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let [a, tmp, x, ret] = ["a", "tmp", "x", "ret"].map(|name| f.symbol(name));
+        let add = f.low_level(LowLevel::NumAdd, &[tmp, a]);
+        let rest = let_ret(f, ret, add);
+        let callee_body = Stmt::Let(
+            tmp,
+            f.low_level(LowLevel::NumAdd, &[a, a]),
+            Layout::U64,
+            f.arena.alloc(rest),
+        );
+        let callee = u64_proc(f, "callee", &[a], callee_body);
+        let call = f.call(&callee, &[x]);
+        let caller_body = let_ret(f, ret, call);
+        let caller = u64_proc(f, "caller", &[x], caller_body.clone());
+        let mut procedures = f.procedures(&[callee, caller.clone()]);
+
+        inline_tiny_procs(&arena, &mut procedures);
+
+        assert_eq!(body_of(&procedures, &caller), &caller_body);
+    }
+}
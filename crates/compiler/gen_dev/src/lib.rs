@@ -5,30 +5,58 @@
 // See github.com/roc-lang/roc/issues/800 for discussion of the large_enum_variant check.
 #![allow(clippy::large_enum_variant, clippy::upper_case_acronyms)]
 
-use std::collections::hash_map::Entry;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::time::Duration;
 
 use bumpalo::{collections::Vec, Bump};
-use roc_builtins::bitcode::{self, FloatWidth, IntWidth};
+use roc_builtins::bitcode::IntWidth;
 use roc_collections::all::{MutMap, MutSet};
-use roc_error_macros::internal_error;
-use roc_module::ident::ModuleName;
 use roc_module::low_level::{LowLevel, LowLevelWrapperType};
-use roc_module::symbol::{Interns, ModuleId, Symbol};
-use roc_mono::code_gen_help::{CallerProc, CodeGenHelp};
+use roc_module::symbol::{ModuleId, Symbol};
 use roc_mono::ir::{
-    BranchInfo, CallType, CrashTag, Expr, HigherOrderLowLevel, JoinPointId, ListLiteralElement,
-    Literal, ModifyRc, Param, Proc, ProcLayout, SelfRecursive, Stmt,
+    BranchInfo, CallType, Expr, JoinPointId, ListLiteralElement, Literal, OptLevel, Param,
+    ProcLayout, Stmt,
 };
-use roc_mono::layout::{
-    Builtin, InLayout, LambdaName, Layout, LayoutIds, LayoutInterner, LayoutRepr, STLayoutInterner,
-    TagIdIntType, UnionLayout,
-};
-use roc_mono::list_element_layout;
-
+use roc_mono::layout::{InLayout, LambdaName, Layout};
+
+mod backend;
+mod debug_info;
+#[cfg(test)]
+mod disasm_snapshot;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzz;
 mod generic64;
+mod inline;
+pub mod metadata;
 mod object_builder;
-pub use object_builder::build_module;
+pub use object_builder::{
+    build_module, build_module_with_split_debug, build_single_proc, ProcCode,
+};
+mod hot_reload;
+pub use hot_reload::{hot_reload_slot_name, patch_hot_reload_slot};
+mod proc_cache;
+pub use proc_cache::ProcCache;
 mod run_roc;
+pub mod symbol_map;
+pub use run_roc::{roc_list_to_vec, roc_str_to_string, PackedArgs, RawRocList, RocArg};
+
+#[cfg(not(feature = "unstable-backend-api"))]
+use backend::{Backend, RefcountHelper, SoleUse};
+
+// The interface between the backends and the rest of the crate, for trying out targets in other crates.
+// It changes along with the backends, so it comes without any stability guarantees.
+#[cfg(feature = "unstable-backend-api")]
+pub use backend::{Backend, ListArgument, RefcountHelper, SoleUse};
+#[cfg(feature = "unstable-backend-api")]
+pub use generic64::{
+    new_backend_64bit,
+    recording::{record_instructions, RecordingAssembler},
+    storage::StorageManager,
+    Assembler, Backend64Bit, CallConv, CompareOperation, IntCondition, RegTrait, RegisterWidth,
+};
+#[cfg(feature = "unstable-backend-api")]
+pub use object_builder::build_object_with_backend;
 
 #[derive(Debug, Clone, Copy)]
 pub enum AssemblyBackendMode {
@@ -47,2397 +75,2036 @@ impl AssemblyBackendMode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugInfo<'a> {
+    /// No debug info is generated
+    None,
+    /// Debug info is written to a separate `.dwo` object named `dwo_name`.
+    /// The main object only gets a skeleton unit referencing it.
+    /// Only ELF objects get split debug info, see `BackendError::UnsupportedDebugInfo`.
+    Split { dwo_name: &'a str },
+}
+
+/// How much work the dev backend puts into the code of a proc, separately from the `OptLevel` of the mono IR.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DevOptLevel {
+    /// Build every statement on its own, for the fastest compiles.
+    Zero,
+    /// Also run the cheap passes: inlining procs of a single expression, branching on comparisons directly,
+    /// selects for switches between two values, immediate operands, shifts for powers of two
+    /// and jump tables for dense switches.
+    #[default]
+    One,
+}
+
+/// An initialized data object the module exports, for platforms that expect constants like a version record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportedData<'a> {
+    /// The name of the symbol, exactly as the host refers to it.
+    pub name: &'a str,
+    pub bytes: &'a [u8],
+    pub alignment: u64,
+    /// Put the data in the writable data section instead of the read only one.
+    pub writable: bool,
+}
+
+/// The settings of a module build. Create it with `EnvBuilder`, so new settings do not break callers.
+#[non_exhaustive]
 pub struct Env<'a> {
     pub arena: &'a Bump,
     pub module_id: ModuleId,
     pub exposed_to_host: MutSet<Symbol>,
     pub lazy_literals: bool,
     pub mode: AssemblyBackendMode,
+    pub debug_info: DebugInfo<'a>,
+    /// Print the `ProcStats` of every proc to stderr.
+    pub print_proc_stats: bool,
+    /// Crash with roc_panic when integer addition, subtraction or multiplication overflows.
+    /// Without this, these operations wrap around like their `Wrap` versions.
+    pub overflow_checks: bool,
+    /// Crash with roc_panic when ListGetUnsafe is given an index outside of the list.
+    /// The mono IR checks the index before these, so this only catches miscompilations.
+    pub bounds_checks: bool,
+    /// Change refcounts with atomic instructions, for hosts that share Roc values between threads.
+    /// This covers the refcount helpers, see `RefcountHelper`. The bitcode changes refcounts non-atomically.
+    pub atomic_refcounts: bool,
+    /// Crash with roc_panic once the stack grows this many bytes below where the first proc ran,
+    /// instead of running into the guard page. The limit is shared by all threads, see `STACK_LIMIT_SYMBOL`,
+    /// so this is off unless set, a host that calls into Roc from other threads would crash on their stacks.
+    pub stack_check_size: Option<u32>,
+    /// Report failed expects and dbg values to the parent process through shared memory,
+    /// the way `roc dev` runs them. Otherwise both are skipped.
+    pub report_expects: bool,
+    /// Crash with roc_panic when an inline expect fails in a build that does not report expects.
+    pub expect_checks: bool,
+    /// Fill the stack frame of every proc with a poison pattern in its prologue.
+    /// This way reads of stack storage that was never written give obviously wrong values.
+    pub poison_stack: bool,
+    /// Give aligned_alloc exactly the requested size in the generated roc_alloc, for hosts built with AddressSanitizer.
+    /// Otherwise the size is rounded up to a multiple of the alignment like C11 asks for,
+    /// and accesses to the padding after the data go unnoticed.
+    pub asan_allocations: bool,
+    /// Leave procs with constructs the dev backend does not support undefined instead of failing the build,
+    /// see `BuildReport::fallback_procs`. The object then only links with another one that defines them with the C ABI,
+    /// like the one that `roc_gen_llvm::llvm::build::build_procedures_expose_fallbacks` builds.
+    pub llvm_fallback: bool,
+    /// Also expose every proc that is exposed to the host as `roc__<name>_<id>_exposed_packed`.
+    /// It takes a pointer to its arguments packed into a struct and a pointer to write its result to,
+    /// so Rust code can call it with any arguments through `run_jit_function_with_args`.
+    pub packed_exposed_procs: bool,
+    /// Call procs through a slot that holds their address instead of calling them directly, see `hot_reload_slot_name`.
+    /// A running program then picks up a recompiled proc once its slot is patched with `patch_hot_reload_slot`.
+    /// Tail calls are built as regular calls, so that they go through the slot as well.
+    pub hot_reload: bool,
+    /// Collect the Roc definition, layout and code size of every proc into `BuildReport::symbol_map`,
+    /// to write next to the object for tools that demangle its symbols.
+    pub symbol_map: bool,
+    /// Measure how long each phase of building a proc takes, see `ProcReport::phase_times`.
+    pub phase_timing: bool,
+    pub dev_opt_level: DevOptLevel,
+    /// Copy the code of procs that did not change since an earlier build from this cache,
+    /// and store the code of the others in it.
+    pub proc_cache: Option<&'a RefCell<ProcCache>>,
+    /// Data objects to define and export next to the procs.
+    pub exported_data: &'a [ExportedData<'a>],
+    /// The alignment of the code of every proc, including the generated wrappers and helpers.
+    /// 16 bytes keeps short procs within a cache line, `OptLevel::Size` packs them at 4 bytes.
+    pub proc_alignment: u64,
+    /// The least alignment of the data procs refer to, like float and string literals.
+    pub data_alignment: u64,
+    /// Count bits with lzcnt, tzcnt and popcnt on x86-64, which need the ABM and BMI1 extensions.
+    /// Without them, zeros are counted with bsr and bsf and set bits with shifts and masks.
+    /// AArch64 always has instructions for these.
+    pub bit_count_instructions: bool,
+    /// Use SSE2 on x86-64 and NEON on AArch64 where the layout allows it: big struct copies go 16 bytes at a time,
+    /// and lists of plain data, like integers and structs of them without padding, are compared with `MEMEQ_HELPER`.
+    /// Both are part of the base architectures, turning this off is for ruling the vector code out of a miscompile.
+    pub vector_instructions: bool,
+    /// Decode the code of every proc once it is finalized, and fail the build with an internal error
+    /// if it has bytes that are not an instruction, or jumps and relocations that point into the middle of one.
+    /// This is for debugging the encoders, it catches their bugs before they crash at runtime.
+    pub verify_machine_code: bool,
+    /// Check the bookkeeping of the storage manager before every statement, and fail the build with an internal error
+    /// if a register is booked twice, a symbol's register is not marked as used, or a symbol is stored in freed stack space.
+    /// This catches register and stack allocation bugs next to the statement that caused them.
+    pub check_storage_invariants: bool,
+    /// Name procs after the layouts of their arguments and result as well, like `#UserApp_map_[I64,Str->Str]_<hash>`,
+    /// instead of only a hash of them. This is for reading disassembly and backtraces when debugging specialization.
+    pub layout_symbol_names: bool,
 }
 
-// These relocations likely will need a length.
-// They may even need more definition, but this should be at least good enough for how we will use elf.
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
-pub enum Relocation {
-    LocalData {
-        offset: u64,
-        // This should probably technically be a bumpalo::Vec.
-        // The problem is that it currently is built in a place that can't access the arena.
-        data: std::vec::Vec<u8>,
-    },
-    LinkedFunction {
-        offset: u64,
-        name: String,
-    },
-    LinkedData {
-        offset: u64,
-        name: String,
-    },
-    JmpToReturn {
-        inst_loc: u64,
-        inst_size: u64,
-        offset: u64,
-    },
-}
-
-#[repr(u8)]
-enum UpdateMode {
-    Immutable = 0,
+/// Builds an `Env`. The debug checks default to what the opt level calls for,
+/// the setters for single checks override that.
+pub struct EnvBuilder<'a> {
+    arena: &'a Bump,
+    module_id: ModuleId,
+    exposed_to_host: MutSet<Symbol>,
+    lazy_literals: bool,
+    mode: AssemblyBackendMode,
+    debug_info: DebugInfo<'a>,
+    opt_level: OptLevel,
+    print_proc_stats: bool,
+    overflow_checks: Option<bool>,
+    bounds_checks: Option<bool>,
+    atomic_refcounts: bool,
+    stack_check_size: Option<u32>,
+    report_expects: Option<bool>,
+    expect_checks: Option<bool>,
+    poison_stack: Option<bool>,
+    asan_allocations: bool,
+    llvm_fallback: bool,
+    packed_exposed_procs: bool,
+    hot_reload: bool,
+    symbol_map: bool,
+    phase_timing: bool,
+    dev_opt_level: DevOptLevel,
+    proc_cache: Option<&'a RefCell<ProcCache>>,
+    exported_data: &'a [ExportedData<'a>],
+    proc_alignment: Option<u64>,
+    data_alignment: u64,
+    bit_count_instructions: bool,
+    vector_instructions: bool,
+    verify_machine_code: bool,
+    check_storage_invariants: bool,
+    layout_symbol_names: bool,
 }
 
-struct ListArgument<'a> {
-    element_layout: InLayout<'a>,
-
-    alignment: Symbol,
-    element_width: Symbol,
-}
+impl<'a> EnvBuilder<'a> {
+    /// A binary build of `module_id` for the host with lazy literals and `OptLevel::Normal`.
+    pub fn new(arena: &'a Bump, module_id: ModuleId) -> Self {
+        EnvBuilder {
+            arena,
+            module_id,
+            exposed_to_host: MutSet::default(),
+            lazy_literals: true,
+            mode: AssemblyBackendMode::Binary,
+            debug_info: DebugInfo::None,
+            opt_level: OptLevel::Normal,
+            print_proc_stats: false,
+            overflow_checks: None,
+            bounds_checks: None,
+            atomic_refcounts: false,
+            stack_check_size: None,
+            report_expects: None,
+            expect_checks: None,
+            poison_stack: None,
+            asan_allocations: false,
+            llvm_fallback: false,
+            packed_exposed_procs: false,
+            hot_reload: false,
+            symbol_map: false,
+            phase_timing: false,
+            dev_opt_level: DevOptLevel::One,
+            proc_cache: None,
+            exported_data: &[],
+            proc_alignment: None,
+            data_alignment: 4,
+            bit_count_instructions: false,
+            vector_instructions: true,
+            verify_machine_code: false,
+            check_storage_invariants: false,
+            layout_symbol_names: false,
+        }
+    }
 
-// Track when a variable is last used (and hence when it can be disregarded). This is non-trivial
-// in the presence of join points. Consider this example:
-//
-// let len = 3
-//
-// joinpoint f = \a ->
-//     joinpoint g = \b ->
-//         # len is used here
-//     in
-//         ...
-// in
-//     ...
-//
-// we have to keep `len` alive until after the joinpoint goes out of scope!
-#[derive(Debug, Default)]
-struct LastSeenMap<'a> {
-    last_seen: MutMap<Symbol, *const Stmt<'a>>,
-    join_map: MutMap<JoinPointId, &'a [Param<'a>]>,
-}
+    pub fn exposed_to_host(mut self, exposed_to_host: MutSet<Symbol>) -> Self {
+        self.exposed_to_host = exposed_to_host;
+        self
+    }
 
-impl<'a> LastSeenMap<'a> {
-    fn set_last_seen(&mut self, symbol: Symbol, stmt: &'a Stmt<'a>) {
-        self.last_seen.insert(symbol, stmt);
+    pub fn lazy_literals(mut self, lazy_literals: bool) -> Self {
+        self.lazy_literals = lazy_literals;
+        self
     }
 
-    /// scan_ast runs through the ast and fill the last seen map.
-    /// This must iterate through the ast in the same way that build_stmt does. i.e. then before else.
-    fn scan_ast(root: &'a Stmt<'a>) -> MutMap<Symbol, *const Stmt<'a>> {
-        let mut this: Self = Default::default();
+    pub fn mode(mut self, mode: AssemblyBackendMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-        this.scan_ast_help(root);
+    pub fn debug_info(mut self, debug_info: DebugInfo<'a>) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
 
-        this.last_seen
+    /// Optimized builds leave out the debug checks, development builds also report expects and dbg.
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.opt_level = opt_level;
+        self
     }
 
-    fn scan_ast_help(&mut self, stmt: &'a Stmt<'a>) {
-        match stmt {
-            Stmt::Let(sym, expr, _, following) => {
-                self.set_last_seen(*sym, stmt);
-                match expr {
-                    Expr::Literal(_) => {}
-                    Expr::NullPointer => {}
-
-                    Expr::Call(call) => self.scan_ast_call(call, stmt),
-
-                    Expr::Tag {
-                        arguments, reuse, ..
-                    } => {
-                        if let Some(ru) = reuse {
-                            self.set_last_seen(ru.symbol, stmt);
-                        }
+    pub fn print_proc_stats(mut self, print_proc_stats: bool) -> Self {
+        self.print_proc_stats = print_proc_stats;
+        self
+    }
 
-                        for sym in *arguments {
-                            self.set_last_seen(*sym, stmt);
-                        }
-                    }
-                    Expr::ExprBox { symbol } => {
-                        self.set_last_seen(*symbol, stmt);
-                    }
-                    Expr::ExprUnbox { symbol } => {
-                        self.set_last_seen(*symbol, stmt);
-                    }
-                    Expr::Struct(syms) => {
-                        for sym in *syms {
-                            self.set_last_seen(*sym, stmt);
-                        }
-                    }
-                    Expr::StructAtIndex { structure, .. } => {
-                        self.set_last_seen(*structure, stmt);
-                    }
-                    Expr::GetTagId { structure, .. } => {
-                        self.set_last_seen(*structure, stmt);
-                    }
-                    Expr::UnionAtIndex { structure, .. } => {
-                        self.set_last_seen(*structure, stmt);
-                    }
-                    Expr::UnionFieldPtrAtIndex { structure, .. } => {
-                        self.set_last_seen(*structure, stmt);
-                    }
-                    Expr::Array { elems, .. } => {
-                        for elem in *elems {
-                            if let ListLiteralElement::Symbol(sym) = elem {
-                                self.set_last_seen(*sym, stmt);
-                            }
-                        }
-                    }
-                    Expr::Reset { symbol, .. } | Expr::ResetRef { symbol, .. } => {
-                        self.set_last_seen(*symbol, stmt);
-                    }
-                    Expr::EmptyArray => {}
-                    Expr::RuntimeErrorFunction(_) => {}
-                }
-                self.scan_ast_help(following);
-            }
+    pub fn overflow_checks(mut self, overflow_checks: bool) -> Self {
+        self.overflow_checks = Some(overflow_checks);
+        self
+    }
 
-            Stmt::Switch {
-                cond_symbol,
-                branches,
-                default_branch,
-                ..
-            } => {
-                self.set_last_seen(*cond_symbol, stmt);
-                for (_, _, branch) in *branches {
-                    self.scan_ast_help(branch);
-                }
-                self.scan_ast_help(default_branch.1);
-            }
-            Stmt::Ret(sym) => {
-                self.set_last_seen(*sym, stmt);
-            }
-            Stmt::Refcounting(modify, following) => {
-                let sym = modify.get_symbol();
+    pub fn bounds_checks(mut self, bounds_checks: bool) -> Self {
+        self.bounds_checks = Some(bounds_checks);
+        self
+    }
 
-                self.set_last_seen(sym, stmt);
-                self.scan_ast_help(following);
-            }
-            Stmt::Join {
-                parameters,
-                body: continuation,
-                remainder,
-                id: JoinPointId(sym),
-                ..
-            } => {
-                self.set_last_seen(*sym, stmt);
-                self.join_map.insert(JoinPointId(*sym), parameters);
-                self.scan_ast_help(remainder);
-
-                for (symbol, symbol_stmt) in Self::scan_ast(continuation) {
-                    match self.last_seen.entry(symbol) {
-                        Entry::Occupied(mut occupied) => {
-                            // lives for the joinpoint
-                            occupied.insert(stmt);
-                        }
-                        Entry::Vacant(vacant) => {
-                            // lives for some time within the continuation
-                            vacant.insert(symbol_stmt);
-                        }
-                    }
-                }
+    pub fn atomic_refcounts(mut self, atomic_refcounts: bool) -> Self {
+        self.atomic_refcounts = atomic_refcounts;
+        self
+    }
 
-                for param in *parameters {
-                    self.set_last_seen(param.symbol, stmt);
-                }
-            }
-            Stmt::Jump(JoinPointId(sym), symbols) => {
-                if let Some(parameters) = self.join_map.get(&JoinPointId(*sym)) {
-                    // Keep the parameters around. They will be overwritten when jumping.
-                    for param in *parameters {
-                        self.set_last_seen(param.symbol, stmt);
-                    }
-                }
-                for sym in *symbols {
-                    self.set_last_seen(*sym, stmt);
-                }
-            }
+    pub fn stack_check_size(mut self, stack_check_size: Option<u32>) -> Self {
+        self.stack_check_size = stack_check_size;
+        self
+    }
 
-            Stmt::Dbg { .. } => todo!("dbg not implemented in the dev backend"),
-            Stmt::Expect { .. } => todo!("expect is not implemented in the dev backend"),
-            Stmt::ExpectFx { .. } => todo!("expect-fx is not implemented in the dev backend"),
+    pub fn report_expects(mut self, report_expects: bool) -> Self {
+        self.report_expects = Some(report_expects);
+        self
+    }
 
-            Stmt::Crash(msg, _crash_tag) => {
-                self.set_last_seen(*msg, stmt);
-            }
-        }
+    pub fn expect_checks(mut self, expect_checks: bool) -> Self {
+        self.expect_checks = Some(expect_checks);
+        self
     }
 
-    fn scan_ast_call(&mut self, call: &roc_mono::ir::Call, stmt: &'a roc_mono::ir::Stmt<'a>) {
-        let roc_mono::ir::Call {
-            call_type,
-            arguments,
-        } = call;
+    pub fn poison_stack(mut self, poison_stack: bool) -> Self {
+        self.poison_stack = Some(poison_stack);
+        self
+    }
 
-        for sym in *arguments {
-            self.set_last_seen(*sym, stmt);
-        }
+    pub fn asan_allocations(mut self, asan_allocations: bool) -> Self {
+        self.asan_allocations = asan_allocations;
+        self
+    }
 
-        match call_type {
-            CallType::ByName { .. } => {}
-            CallType::LowLevel { .. } => {}
-            CallType::HigherOrder { .. } => {}
-            CallType::Foreign { .. } => {}
-        }
+    pub fn llvm_fallback(mut self, llvm_fallback: bool) -> Self {
+        self.llvm_fallback = llvm_fallback;
+        self
     }
-}
 
-trait Backend<'a> {
-    fn env(&self) -> &Env<'a>;
-    fn interns(&self) -> &Interns;
-    fn interns_mut(&mut self) -> &mut Interns;
-    fn interner(&self) -> &STLayoutInterner<'a>;
+    pub fn packed_exposed_procs(mut self, packed_exposed_procs: bool) -> Self {
+        self.packed_exposed_procs = packed_exposed_procs;
+        self
+    }
 
-    fn interner_mut(&mut self) -> &mut STLayoutInterner<'a> {
-        self.module_interns_helpers_mut().1
+    pub fn hot_reload(mut self, hot_reload: bool) -> Self {
+        self.hot_reload = hot_reload;
+        self
     }
 
-    fn debug_symbol(&mut self, name: &str) -> Symbol {
-        let module_id = self.env().module_id;
+    pub fn symbol_map(mut self, symbol_map: bool) -> Self {
+        self.symbol_map = symbol_map;
+        self
+    }
 
-        self.debug_symbol_in(module_id, name)
+    pub fn phase_timing(mut self, phase_timing: bool) -> Self {
+        self.phase_timing = phase_timing;
+        self
     }
 
-    fn debug_symbol_in(&mut self, module_id: ModuleId, name: &str) -> Symbol {
-        let ident_ids = self
-            .interns_mut()
-            .all_ident_ids
-            .get_mut(&module_id)
-            .unwrap();
+    pub fn dev_opt_level(mut self, dev_opt_level: DevOptLevel) -> Self {
+        self.dev_opt_level = dev_opt_level;
+        self
+    }
 
-        let ident_id = ident_ids.add_str(name);
-        Symbol::new(module_id, ident_id)
+    /// Reuse the code of unchanged procs from earlier builds with the same cache.
+    pub fn proc_cache(mut self, proc_cache: &'a RefCell<ProcCache>) -> Self {
+        self.proc_cache = Some(proc_cache);
+        self
     }
 
-    // This method is suboptimal, but it seems to be the only way to make rust understand
-    // that all of these values can be mutable at the same time. By returning them together,
-    // rust understands that they are part of a single use of mutable self.
-    fn module_interns_helpers_mut(
-        &mut self,
-    ) -> (
-        ModuleId,
-        &mut STLayoutInterner<'a>,
-        &mut Interns,
-        &mut CodeGenHelp<'a>,
-        &mut Vec<'a, CallerProc<'a>>,
-    );
-
-    fn lambda_name_to_string<'b, I>(
-        &self,
-        name: LambdaName,
-        arguments: I,
-        _lambda_set: Option<InLayout>,
-        result: InLayout,
-    ) -> String
-    where
-        I: Iterator<Item = InLayout<'b>>,
-    {
-        use std::fmt::Write;
-        use std::hash::{BuildHasher, Hash, Hasher};
+    /// Define and export these data objects in the module, see `ExportedData`.
+    pub fn exported_data(mut self, exported_data: &'a [ExportedData<'a>]) -> Self {
+        self.exported_data = exported_data;
+        self
+    }
 
-        let symbol = name.name();
+    /// Align the code of every proc to this power of two.
+    pub fn proc_alignment(mut self, proc_alignment: u64) -> Self {
+        debug_assert!(proc_alignment.is_power_of_two());
+        self.proc_alignment = Some(proc_alignment);
+        self
+    }
 
-        let mut buf = String::with_capacity(1024);
+    /// Align the data procs refer to to at least this power of two.
+    pub fn data_alignment(mut self, data_alignment: u64) -> Self {
+        debug_assert!(data_alignment.is_power_of_two());
+        self.data_alignment = data_alignment;
+        self
+    }
 
-        for a in arguments {
-            write!(buf, "{:?}", self.interner().dbg_stable(a)).expect("capacity");
-        }
+    /// Count bits with the instructions of the x86-64 ABM and BMI1 extensions, for targets known to have them.
+    pub fn bit_count_instructions(mut self, bit_count_instructions: bool) -> Self {
+        self.bit_count_instructions = bit_count_instructions;
+        self
+    }
 
-        // lambda set should not matter; it should already be added as an argument
-        // but the niche of the lambda name may be the only thing differentiating two different
-        // implementations of a function with the same symbol
-        write!(buf, "{:?}", name.niche().dbg_stable(self.interner())).expect("capacity");
+    /// Use the vector instructions of SSE2 and NEON, on by default.
+    pub fn vector_instructions(mut self, vector_instructions: bool) -> Self {
+        self.vector_instructions = vector_instructions;
+        self
+    }
 
-        write!(buf, "{:?}", self.interner().dbg_stable(result)).expect("capacity");
+    pub fn verify_machine_code(mut self, verify_machine_code: bool) -> Self {
+        self.verify_machine_code = verify_machine_code;
+        self
+    }
 
-        // NOTE: due to randomness, this will not be consistent between runs
-        let mut state = roc_collections::all::BuildHasher::default().build_hasher();
-        buf.hash(&mut state);
+    pub fn check_storage_invariants(mut self, check_storage_invariants: bool) -> Self {
+        self.check_storage_invariants = check_storage_invariants;
+        self
+    }
 
-        let interns = self.interns();
-        let ident_string = symbol.as_str(interns);
-        let module_string = interns.module_ids.get_name(symbol.module_id()).unwrap();
+    pub fn layout_symbol_names(mut self, layout_symbol_names: bool) -> Self {
+        self.layout_symbol_names = layout_symbol_names;
+        self
+    }
 
-        // the functions from the generates #help module (refcounting, equality) is always suffixed
-        // with 1. That is fine, they are always unique anyway.
-        if ident_string.contains("#help") {
-            format!("{}_{}_1", module_string, ident_string)
-        } else {
-            format!("{}_{}_{}", module_string, ident_string, state.finish())
+    pub fn build(self) -> Env<'a> {
+        let debug_checks = !matches!(self.opt_level, OptLevel::Optimize | OptLevel::Size);
+        Env {
+            arena: self.arena,
+            module_id: self.module_id,
+            exposed_to_host: self.exposed_to_host,
+            lazy_literals: self.lazy_literals,
+            mode: self.mode,
+            debug_info: self.debug_info,
+            print_proc_stats: self.print_proc_stats,
+            overflow_checks: self.overflow_checks.unwrap_or(debug_checks),
+            bounds_checks: self.bounds_checks.unwrap_or(debug_checks),
+            atomic_refcounts: self.atomic_refcounts,
+            stack_check_size: self.stack_check_size,
+            report_expects: self
+                .report_expects
+                .unwrap_or(matches!(self.opt_level, OptLevel::Development)),
+            expect_checks: self.expect_checks.unwrap_or(debug_checks),
+            poison_stack: self.poison_stack.unwrap_or(debug_checks),
+            asan_allocations: self.asan_allocations,
+            llvm_fallback: self.llvm_fallback,
+            packed_exposed_procs: self.packed_exposed_procs,
+            hot_reload: self.hot_reload,
+            symbol_map: self.symbol_map,
+            phase_timing: self.phase_timing,
+            dev_opt_level: self.dev_opt_level,
+            proc_cache: self.proc_cache,
+            exported_data: self.exported_data,
+            proc_alignment: self.proc_alignment.unwrap_or(match self.opt_level {
+                OptLevel::Size => 4,
+                _ => 16,
+            }),
+            data_alignment: self.data_alignment,
+            bit_count_instructions: self.bit_count_instructions,
+            vector_instructions: self.vector_instructions,
+            verify_machine_code: self.verify_machine_code,
+            check_storage_invariants: self.check_storage_invariants,
+            layout_symbol_names: self.layout_symbol_names,
         }
     }
+}
 
-    fn defined_in_app_module(&self, symbol: Symbol) -> bool {
-        symbol
-            .module_string(self.interns())
-            .starts_with(ModuleName::APP)
-    }
+/// Register allocation and stack stats of a single proc.
+/// These are useful to compare changes to the register allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcStats {
+    /// The number of times a register was stored to the stack to free it.
+    pub spills: u32,
+    /// The number of times a spilled value was loaded back into a register.
+    pub reloads: u32,
+    /// The largest number of symbols that are live at the same time.
+    pub max_live_values: u32,
+    /// The size in bytes of the stack frame.
+    pub stack_frame_size: u32,
+}
 
-    fn list_argument(&mut self, list_layout: InLayout<'a>) -> ListArgument<'a> {
-        let element_layout = match self.interner().get_repr(list_layout) {
-            LayoutRepr::Builtin(Builtin::List(e)) => e,
-            _ => unreachable!(),
-        };
+/// The time spent in each phase of building a proc, only measured with `Env::phase_timing`.
+/// Registers are mostly allocated while the instructions are emitted, so that time counts as emission.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimes {
+    /// Finding the live intervals and the symbols to free after every statement.
+    pub scan_ast: Duration,
+    /// Picking the registers that symbols should live in before any code is emitted.
+    pub register_allocation: Duration,
+    /// Building the statements and the prologue of the proc.
+    pub emission: Duration,
+    /// Adding the code, its data and its relocations to the object.
+    pub object_writing: Duration,
+}
 
-        let (element_width_int, alignment_int) =
-            self.interner().stack_size_and_alignment(element_layout);
+impl std::ops::AddAssign for PhaseTimes {
+    fn add_assign(&mut self, other: Self) {
+        self.scan_ast += other.scan_ast;
+        self.register_allocation += other.register_allocation;
+        self.emission += other.emission;
+        self.object_writing += other.object_writing;
+    }
+}
 
-        let alignment = self.debug_symbol("alignment");
-        self.load_literal_i32(&alignment, Ord::max(alignment_int, 8) as i32);
+/// The memory that building a module took, to track regressions and size the arena up front.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// How much the arena of the `Env` grew during the build. The arena grows a chunk at a time,
+    /// so this is a multiple of its chunk sizes rather than the bytes of every allocation.
+    pub arena_bytes: usize,
+    /// The machine code of the procs and the helpers they call, without the allocator and panic wrappers.
+    pub code_bytes: usize,
+    /// The data the procs load from the data section, like float literals.
+    pub data_bytes: usize,
+    /// The length of the largest code buffer of a single proc.
+    pub largest_proc_buffer: usize,
+}
 
-        let element_width = self.debug_symbol("element_width");
-        self.load_literal_i64(&element_width, element_width_int as i64);
+/// What build_module did for every proc, to summarize a build or measure changes to the backend.
+#[derive(Debug, Clone, Default)]
+pub struct BuildReport<'a> {
+    pub procs: std::vec::Vec<ProcReport>,
+    /// The procs left undefined for another backend to build, with their layouts and the names of their symbols,
+    /// see `Env::llvm_fallback`.
+    pub fallback_procs: std::vec::Vec<(Symbol, ProcLayout<'a>, String)>,
+    /// The symbols of the procs, only filled in with `Env::symbol_map`.
+    pub symbol_map: symbol_map::SymbolMap,
+    pub memory: MemoryStats,
+}
 
-        ListArgument {
-            element_layout,
-            alignment,
-            element_width,
-        }
+/// The build of a single proc within a `BuildReport`.
+#[derive(Debug, Clone)]
+pub struct ProcReport {
+    pub name: String,
+    /// The name of the Roc module that defines the proc.
+    pub module: String,
+    /// Whether that module is a builtin one, like `Num` or `List`.
+    pub builtin: bool,
+    /// The size in bytes of the machine code of the proc.
+    pub code_size: u64,
+    /// The number of relocations in the machine code of the proc.
+    pub relocations: u32,
+    pub stats: ProcStats,
+    /// The time spent generating the machine code of the proc.
+    pub build_time: Duration,
+    /// Left at zero for procs copied from the `ProcCache`.
+    pub phase_times: PhaseTimes,
+}
+
+impl<'a> BuildReport<'a> {
+    pub fn code_size(&self) -> u64 {
+        self.procs.iter().map(|proc| proc.code_size).sum()
     }
 
-    fn increment_fn_pointer(&mut self, layout: InLayout<'a>) -> Symbol {
-        let box_layout = self
-            .interner_mut()
-            .insert_direct_no_semantic(LayoutRepr::Boxed(layout));
+    pub fn relocations(&self) -> u32 {
+        self.procs.iter().map(|proc| proc.relocations).sum()
+    }
 
-        let element_increment = self.debug_symbol("element_increment");
-        let element_increment_symbol = self.build_indirect_inc(layout);
+    pub fn spills(&self) -> u32 {
+        self.procs.iter().map(|proc| proc.stats.spills).sum()
+    }
 
-        let element_increment_string = self.lambda_name_to_string(
-            LambdaName::no_niche(element_increment_symbol),
-            [box_layout].into_iter(),
-            None,
-            Layout::UNIT,
-        );
+    pub fn build_time(&self) -> Duration {
+        self.procs.iter().map(|proc| proc.build_time).sum()
+    }
 
-        self.build_fn_pointer(&element_increment, element_increment_string);
+    pub fn phase_times(&self) -> PhaseTimes {
+        let mut total = PhaseTimes::default();
+        for proc in &self.procs {
+            total += proc.phase_times;
+        }
+        total
+    }
 
-        element_increment
+    /// The size of the code of the procs from each module, largest first.
+    pub fn code_size_by_module(&self) -> std::vec::Vec<(&str, u64)> {
+        let mut sizes: std::vec::Vec<(&str, u64)> = std::vec::Vec::new();
+        for proc in self.procs.iter() {
+            match sizes.iter_mut().find(|(module, _)| *module == proc.module) {
+                Some((_, size)) => *size += proc.code_size,
+                None => sizes.push((&proc.module, proc.code_size)),
+            }
+        }
+        sizes.sort_by(|(a_module, a_size), (b_module, b_size)| {
+            b_size.cmp(a_size).then(a_module.cmp(b_module))
+        });
+        sizes
     }
 
-    fn decrement_fn_pointer(&mut self, layout: InLayout<'a>) -> Symbol {
-        let box_layout = self
-            .interner_mut()
-            .insert_direct_no_semantic(LayoutRepr::Boxed(layout));
+    /// The size of the code of the procs from builtin modules.
+    pub fn builtin_code_size(&self) -> u64 {
+        self.procs
+            .iter()
+            .filter(|proc| proc.builtin)
+            .map(|proc| proc.code_size)
+            .sum()
+    }
 
-        let element_decrement = self.debug_symbol("element_decrement");
-        let element_decrement_symbol = self.build_indirect_dec(layout);
+    /// Lists how much of the code comes from the app and from the builtins, and then from each module.
+    pub fn code_size_report(&self) -> String {
+        use std::fmt::Write;
 
-        let element_decrement_string = self.lambda_name_to_string(
-            LambdaName::no_niche(element_decrement_symbol),
-            [box_layout].into_iter(),
-            None,
-            Layout::UNIT,
+        let total = self.code_size();
+        let builtin = self.builtin_code_size();
+        let percent = |size: u64| match total {
+            0 => 0.0,
+            _ => size as f64 * 100.0 / total as f64,
+        };
+        let mut out = format!(
+            "{} bytes of code, {} bytes ({:.1}%) app, {} bytes ({:.1}%) builtins\n",
+            total,
+            total - builtin,
+            percent(total - builtin),
+            builtin,
+            percent(builtin)
         );
+        for (module, size) in self.code_size_by_module() {
+            writeln!(
+                out,
+                "    {:8} bytes {:5.1}%   {}",
+                size,
+                percent(size),
+                module
+            )
+            .unwrap();
+        }
+        out
+    }
 
-        self.build_fn_pointer(&element_decrement, element_decrement_string);
+    /// The totals of the module, followed by the `count` procs that took the longest to build.
+    pub fn summary(&self, count: usize) -> String {
+        use std::fmt::Write;
 
-        element_decrement
+        let mut out = format!(
+            "{} procs, {} bytes of code, {} relocations, {} spills, {:.3} ms\n",
+            self.procs.len(),
+            self.code_size(),
+            self.relocations(),
+            self.spills(),
+            self.build_time().as_secs_f64() * 1000.0
+        );
+        let phase_times = self.phase_times();
+        if phase_times != PhaseTimes::default() {
+            let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+            writeln!(
+                out,
+                "    scan_ast {:.3} ms, register allocation {:.3} ms, emission {:.3} ms, object writing {:.3} ms",
+                ms(phase_times.scan_ast),
+                ms(phase_times.register_allocation),
+                ms(phase_times.emission),
+                ms(phase_times.object_writing)
+            )
+            .unwrap();
+        }
+        if self.memory != MemoryStats::default() {
+            writeln!(
+                out,
+                "    {} bytes of arena, {} bytes of code, {} bytes of data, largest proc {} bytes",
+                self.memory.arena_bytes,
+                self.memory.code_bytes,
+                self.memory.data_bytes,
+                self.memory.largest_proc_buffer
+            )
+            .unwrap();
+        }
+        let mut slowest: std::vec::Vec<&ProcReport> = self.procs.iter().collect();
+        slowest.sort_by_key(|proc| std::cmp::Reverse(proc.build_time));
+        for proc in slowest.into_iter().take(count) {
+            writeln!(
+                out,
+                "    {:9.3} ms {:8} bytes {:5} spills   {}",
+                proc.build_time.as_secs_f64() * 1000.0,
+                proc.code_size,
+                proc.stats.spills,
+                proc.name
+            )
+            .unwrap();
+        }
+        out
     }
+}
 
-    fn helper_proc_gen_mut(&mut self) -> &mut CodeGenHelp<'a>;
+/// A construct in a proc that the dev backend can not generate code for yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedConstruct {
+    pub proc_name: String,
+    pub construct: String,
+}
 
-    fn helper_proc_symbols_mut(&mut self) -> &mut Vec<'a, (Symbol, ProcLayout<'a>)>;
+/// Why the dev backend could not build a module.
+/// Programs like this still build with the LLVM backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    /// The dev backend does not generate code for this target.
+    UnsupportedTarget(String),
+    /// Every construct of the module that the dev backend does not support.
+    Unsupported(std::vec::Vec<UnsupportedConstruct>),
+    /// The dev backend does not write split debug info into objects of this format, see `DebugInfo::Split`.
+    UnsupportedDebugInfo(String),
+}
 
-    fn helper_proc_symbols(&self) -> &Vec<'a, (Symbol, ProcLayout<'a>)>;
-    fn caller_procs(&self) -> &Vec<'a, CallerProc<'a>>;
+impl std::fmt::Display for BackendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackendError::UnsupportedTarget(target) => write!(
+                f,
+                "The dev backend does not support the target {}, this program needs the LLVM backend.",
+                target
+            ),
+            BackendError::Unsupported(constructs) => {
+                writeln!(
+                    f,
+                    "This program needs the LLVM backend, the dev backend does not support:"
+                )?;
+                for UnsupportedConstruct {
+                    proc_name,
+                    construct,
+                } in constructs
+                {
+                    writeln!(f, "    {} (in {})", construct, proc_name)?;
+                }
+                Ok(())
+            }
+            BackendError::UnsupportedDebugInfo(format) => write!(
+                f,
+                "The dev backend only writes split debug info into ELF objects, not {} ones.",
+                format
+            ),
+        }
+    }
+}
 
-    /// reset resets any registers or other values that may be occupied at the end of a procedure.
-    /// It also passes basic procedure information to the builder for setup of the next function.
-    fn reset(&mut self, name: String, is_self_recursive: SelfRecursive);
+impl std::error::Error for BackendError {}
 
-    /// finalize does any setup and cleanup that should happen around the procedure.
-    /// finalize does setup because things like stack size and jump locations are not know until the function is written.
-    /// For example, this can store the frame pointer and setup stack space.
-    /// finalize is run at the end of build_proc when all internal code is finalized.
-    fn finalize(&mut self) -> (Vec<u8>, Vec<Relocation>);
+// These relocations likely will need a length.
+// They may even need more definition, but this should be at least good enough for how we will use elf.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum Relocation<'a> {
+    LocalData {
+        offset: u64,
+        // Allocated in the same arena as the relocations, see `local_data`.
+        data: &'a [u8],
+    },
+    LinkedFunction {
+        offset: u64,
+        name: String,
+        // Added to the address of the symbol.
+        addend: i64,
+    },
+    // An AArch64 `ADRP` followed directly by an `ADD` that together load the address of a function or data symbol.
+    // The offset points at the `ADRP`.
+    LinkedFunctionPage {
+        offset: u64,
+        name: String,
+    },
+    // The address of a data symbol, loaded from the GOT.
+    // With an addend, the address of a byte within the symbol, like a field of a global the host provides.
+    // That one is computed relative to the instruction, so the symbol must be linked into the same image.
+    LinkedData {
+        offset: u64,
+        name: String,
+        addend: i64,
+    },
+    JmpToReturn {
+        inst_loc: u64,
+        inst_size: u64,
+        offset: u64,
+    },
+}
 
-    // load_args is used to let the backend know what the args are.
-    // The backend should track these args so it can use them as needed.
-    fn load_args(&mut self, args: &'a [(InLayout<'a>, Symbol)], ret_layout: &InLayout<'a>);
+/// Copies the bytes of a `Relocation::LocalData` into the arena of `relocs`.
+pub(crate) fn local_data<'a>(relocs: &Vec<'a, Relocation<'a>>, data: &[u8]) -> &'a [u8] {
+    relocs.bump().alloc_slice_copy(data)
+}
 
-    /// Used for generating wrappers for malloc/realloc/free
-    fn build_wrapped_jmp(&mut self) -> (&'a [u8], u64);
+// Track where each variable dies (and hence when its storage can be disregarded).
+// This is a backwards liveness analysis, so a symbol can die at different statements in
+// different branches of a switch. Symbols that are live into a switch but unused in one of its
+// branches are freed when entering that branch.
+//
+// Join points need extra care. Consider this example:
+//
+// let len = 3
+//
+// joinpoint f = \a ->
+//     joinpoint g = \b ->
+//         # len is used here
+//     in
+//         ...
+// in
+//     ...
+//
+// we have to keep `len` alive until after the joinpoint goes out of scope!
+// The body of a join point is built after its remainder with whatever storage the remainder left,
+// so the symbols used in the body are pinned for the whole join point and freed after it.
+#[derive(Debug, Default)]
+struct Liveness<'a> {
+    join_map: MutMap<JoinPointId, &'a [Param<'a>]>,
 
-    /// build_proc creates a procedure and outputs it to the wrapped object writer.
-    /// Returns the procedure bytes, its relocations, and the names of the refcounting functions it references.
-    fn build_proc(
-        &mut self,
-        proc: Proc<'a>,
-        layout_ids: &mut LayoutIds<'a>,
-    ) -> (Vec<u8>, Vec<Relocation>, Vec<'a, (Symbol, String)>) {
-        let proc_name = self.lambda_name_to_string(
-            proc.name,
-            proc.args.iter().map(|t| t.0),
-            proc.closure_data_layout,
-            proc.ret_layout,
-        );
+    // Symbols that are free after the statement runs.
+    free_map: MutMap<*const Stmt<'a>, std::vec::Vec<Symbol>>,
+    // Symbols that are free when entering a switch branch, keyed by the first statement of the branch.
+    branch_free_map: MutMap<*const Stmt<'a>, std::vec::Vec<Symbol>>,
+
+    // Statements are numbered in the order that build_stmt visits them.
+    // These positions give every symbol a live interval for the register allocator.
+    position: u32,
+    stmt_positions: MutMap<*const Stmt<'a>, u32>,
+    live_intervals: MutMap<Symbol, LiveInterval>,
+    // Positions of the statements that call a function, in increasing order.
+    call_positions: std::vec::Vec<u32>,
+    // How often each symbol is used, and the symbols whose only use may want a specific register.
+    use_counts: MutMap<Symbol, u32>,
+    sole_uses: MutMap<Symbol, SoleUse<'a>>,
+    // Comparisons whose bool is only used to pick a branch of the switch right after them.
+    fused_compares: MutSet<Symbol>,
+    // Layouts of the symbols defined in the proc, so the allocator knows which register class they need.
+    layouts: MutMap<Symbol, InLayout<'a>>,
+}
 
-        let body = self.env().arena.alloc(proc.body);
+/// A switch on the bool from comparing two values, directly after the comparison.
+struct CompareSwitch<'r, 'a> {
+    op: LowLevel,
+    args: &'a [Symbol],
+    branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+    default_branch: &'r (BranchInfo<'a>, &'a Stmt<'a>),
+    ret_layout: InLayout<'a>,
+}
 
-        self.reset(proc_name, proc.is_self_recursive);
-        self.load_args(proc.args, &proc.ret_layout);
-        for (layout, sym) in proc.args {
-            self.set_layout_map(*sym, layout);
-        }
-        self.scan_ast(body);
-        self.create_free_map();
-        self.build_stmt(layout_ids, body, &proc.ret_layout);
-
-        let mut helper_proc_names = bumpalo::vec![in self.env().arena];
-        helper_proc_names.reserve(self.helper_proc_symbols().len());
-        for (rc_proc_sym, rc_proc_layout) in self.helper_proc_symbols() {
-            let name = layout_ids
-                .get_toplevel(*rc_proc_sym, rc_proc_layout)
-                .to_symbol_string(*rc_proc_sym, self.interns());
-
-            helper_proc_names.push((*rc_proc_sym, name));
+/// Matches `sym = compare args; switch sym`, where the switch only has branches for true and false.
+fn compare_switch<'r, 'a>(
+    sym: &Symbol,
+    expr: &'r Expr<'a>,
+    following: &'r Stmt<'a>,
+) -> Option<CompareSwitch<'r, 'a>> {
+    let Expr::Call(call) = expr else {
+        return None;
+    };
+    let op = match call.call_type {
+        CallType::LowLevel { op, .. } => op,
+        CallType::ByName { name, .. } => match LowLevelWrapperType::from_symbol(name.name()) {
+            LowLevelWrapperType::CanBeReplacedBy(op) => op,
+            LowLevelWrapperType::NotALowLevelWrapper => return None,
+        },
+        _ => return None,
+    };
+    if !matches!(
+        op,
+        LowLevel::Eq
+            | LowLevel::NotEq
+            | LowLevel::NumLt
+            | LowLevel::NumLte
+            | LowLevel::NumGt
+            | LowLevel::NumGte
+    ) || call.arguments.len() != 2
+    {
+        return None;
+    }
+    match following {
+        Stmt::Switch {
+            cond_symbol,
+            branches,
+            default_branch,
+            ret_layout,
+            ..
+        } if cond_symbol == sym && branches.iter().all(|(val, _, _)| *val <= 1) => {
+            Some(CompareSwitch {
+                op,
+                args: call.arguments,
+                branches: *branches,
+                default_branch,
+                ret_layout: *ret_layout,
+            })
         }
+        _ => None,
+    }
+}
 
-        for caller_proc in self.caller_procs() {
-            let proc_layout = caller_proc.proc_layout;
-            let proc_symbol = caller_proc.proc_symbol;
-            let name = layout_ids
-                .get_toplevel(proc_symbol, &proc_layout)
-                .to_symbol_string(proc_symbol, self.interns());
-
-            helper_proc_names.push((proc_symbol, name));
-        }
+/// A call to another proc whose result is returned right away.
+struct TailCall<'a> {
+    name: LambdaName<'a>,
+    args: &'a [Symbol],
+    arg_layouts: &'a [InLayout<'a>],
+    ret_layout: InLayout<'a>,
+}
 
-        let (bytes, relocs) = self.finalize();
-        (bytes, relocs, helper_proc_names)
+/// Matches `sym = f args; ret sym`, where f is a proc that is called through build_fn_call.
+fn tail_call<'a>(sym: &Symbol, expr: &Expr<'a>, following: &Stmt<'a>) -> Option<TailCall<'a>> {
+    let (Expr::Call(call), Stmt::Ret(ret_sym)) = (expr, following) else {
+        return None;
+    };
+    match call.call_type {
+        // Builtins are built by build_run_low_level and build_builtin instead.
+        CallType::ByName {
+            name,
+            arg_layouts,
+            ret_layout,
+            ..
+        } if ret_sym == sym && !name.name().is_builtin() => Some(TailCall {
+            name,
+            args: call.arguments,
+            arg_layouts,
+            ret_layout,
+        }),
+        _ => None,
     }
+}
 
-    /// build_stmt builds a statement and outputs at the end of the buffer.
-    fn build_stmt(
-        &mut self,
-        layout_ids: &mut LayoutIds<'a>,
-        stmt: &Stmt<'a>,
-        ret_layout: &InLayout<'a>,
-    ) {
-        match stmt {
-            Stmt::Let(sym, expr, layout, following) => {
-                self.build_expr(sym, expr, layout);
-                self.set_layout_map(*sym, layout);
-                self.free_symbols(stmt);
-                self.build_stmt(layout_ids, following, ret_layout);
-            }
-            Stmt::Ret(sym) => {
-                self.load_literal_symbols(&[*sym]);
-                self.return_symbol(sym, ret_layout);
-                self.free_symbols(stmt);
+/// Whether evaluating `expr` has no effect other than producing its value.
+/// A Let of such an expression can be skipped when its symbol is never used.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_)
+        | Expr::NullPointer
+        | Expr::EmptyArray
+        | Expr::Struct(_)
+        | Expr::StructAtIndex { .. }
+        | Expr::GetTagId { .. }
+        | Expr::UnionAtIndex { .. }
+        | Expr::UnionFieldPtrAtIndex { .. } => true,
+        Expr::Call(call) => matches!(
+            call.call_type,
+            CallType::LowLevel {
+                op: LowLevel::NumAddWrap
+                    | LowLevel::NumSubWrap
+                    | LowLevel::NumMulWrap
+                    | LowLevel::NumGt
+                    | LowLevel::NumGte
+                    | LowLevel::NumLt
+                    | LowLevel::NumLte
+                    | LowLevel::NumNeg
+                    | LowLevel::NumToFrac
+                    | LowLevel::NumIntCast
+                    | LowLevel::NumBitwiseAnd
+                    | LowLevel::NumBitwiseXor
+                    | LowLevel::NumBitwiseOr
+                    | LowLevel::Eq
+                    | LowLevel::NotEq
+                    | LowLevel::And
+                    | LowLevel::Or
+                    | LowLevel::Not
+                    | LowLevel::ListLen,
+                ..
             }
-            Stmt::Refcounting(ModifyRc::Free(symbol), following) => {
-                let dst = Symbol::DEV_TMP;
+        ),
+        _ => false,
+    }
+}
 
-                let layout = *self.layout_map().get(symbol).unwrap();
-                let alignment_bytes = self.interner().allocation_alignment_bytes(layout);
-                let alignment = self.debug_symbol("alignment");
-                self.load_literal_i32(&alignment, alignment_bytes as i32);
+/// The value of an integer or bool literal, as used by `fold_low_level`.
+fn literal_int_value(lit: &Literal) -> Option<i128> {
+    match lit {
+        Literal::Int(bytes) => Some(i128::from_ne_bytes(*bytes)),
+        Literal::Byte(x) => Some(*x as i128),
+        Literal::Bool(x) => Some(*x as i128),
+        _ => None,
+    }
+}
 
-                // NOTE: UTILS_FREE_DATA_PTR clears any tag id bits
+/// Whether every path through stmt ends in a crash.
+fn always_crashes(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Crash(..) => true,
+        Stmt::Let(_, _, _, following) | Stmt::Refcounting(_, following) => {
+            always_crashes(following)
+        }
+        Stmt::Expect { remainder, .. }
+        | Stmt::ExpectFx { remainder, .. }
+        | Stmt::Dbg { remainder, .. } => always_crashes(remainder),
+        Stmt::Switch {
+            branches,
+            default_branch,
+            ..
+        } => {
+            branches.iter().all(|(_, _, branch)| always_crashes(branch))
+                && always_crashes(default_branch.1)
+        }
+        _ => false,
+    }
+}
 
-                self.build_fn_call(
-                    &dst,
-                    bitcode::UTILS_FREE_DATA_PTR.to_string(),
-                    &[*symbol, alignment],
-                    &[Layout::I64, Layout::I32],
-                    &Layout::UNIT,
-                );
+/// Whether a switch branch is unlikely to be taken, so its code should be kept out of the way of the common case.
+/// These are the branches that crash, and the copying branches taken when a value is not unique.
+pub(crate) fn is_unlikely_branch(info: &BranchInfo, stmt: &Stmt) -> bool {
+    matches!(info, BranchInfo::Unique { unique: false, .. }) || always_crashes(stmt)
+}
 
-                self.free_symbol(&dst);
-                self.free_symbol(&alignment);
+/// The branch that a switch on `value` takes.
+/// Returns None if `value` is negative, since switch values are compared as unsigned.
+fn taken_branch<'a>(
+    value: i128,
+    branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
+    default_branch: &'a Stmt<'a>,
+) -> Option<&'a Stmt<'a>> {
+    let value = u64::try_from(value).ok()?;
+    let taken = branches
+        .iter()
+        .find(|(val, ..)| *val == value)
+        .map_or(default_branch, |(_, _, stmt)| stmt);
+    Some(taken)
+}
 
-                self.build_stmt(layout_ids, following, ret_layout)
-            }
-            Stmt::Refcounting(modify, following) => {
-                let sym = modify.get_symbol();
-                let layout = *self.layout_map().get(&sym).unwrap();
-
-                // Expand the Refcounting statement into more detailed IR with a function call
-                // If this layout requires a new RC proc, we get enough info to create a linker symbol
-                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
-                let (rc_stmt, new_specializations) = {
-                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
-                        self.module_interns_helpers_mut();
-                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
-
-                    rc_proc_gen.expand_refcount_stmt(
-                        ident_ids,
-                        layout_interner,
-                        layout,
-                        modify,
-                        following,
-                    )
-                };
-
-                for spec in new_specializations.into_iter() {
-                    self.helper_proc_symbols_mut().push(spec);
-                }
+/// Whether `lowlevel` can overflow `layout` in a way that has to crash instead of wrapping around.
+/// These are the additions, subtractions and multiplications of integers that fit in a register.
+fn overflow_checked(lowlevel: &LowLevel, layout: InLayout) -> bool {
+    matches!(
+        lowlevel,
+        LowLevel::NumAdd | LowLevel::NumSub | LowLevel::NumMul
+    ) && matches!(layout.try_int_width(), Some(width) if width.stack_size() <= 8)
+}
 
-                self.build_stmt(layout_ids, rc_stmt, ret_layout)
-            }
-            Stmt::Switch {
-                cond_symbol,
-                cond_layout,
-                branches,
+/// The data symbol that holds the lowest address the stack may grow to with `Env::stack_check_size`.
+/// It starts out as 0, the first proc that runs sets it relative to its stack pointer.
+pub(crate) const STACK_LIMIT_SYMBOL: &str = "roc_dev_stack_limit";
+
+/// The helper that crashes with a stack overflow. Procs call it when their stack frame passes the limit.
+pub(crate) const STACK_OVERFLOW_HELPER: &str = "roc_dev_stack_overflow";
+
+/// The helper that compares the bytes of two lists with `Env::vector_instructions`.
+/// It takes the element pointers and the sizes in bytes of both lists, and returns whether they are equal.
+pub(crate) const MEMEQ_HELPER: &str = "roc_dev_memeq";
+
+/// The arguments of an integer operation that is built inline, which need its arguments in registers.
+fn register_operands<'a>(expr: &Expr<'a>) -> Option<&'a [Symbol]> {
+    let Expr::Call(call) = expr else {
+        return None;
+    };
+    match call.call_type {
+        CallType::LowLevel {
+            op:
+                LowLevel::NumAdd
+                | LowLevel::NumAddWrap
+                | LowLevel::NumSub
+                | LowLevel::NumSubWrap
+                | LowLevel::NumMul
+                | LowLevel::NumMulWrap
+                | LowLevel::NumGt
+                | LowLevel::NumGte
+                | LowLevel::NumLt
+                | LowLevel::NumLte
+                | LowLevel::NumBitwiseAnd
+                | LowLevel::NumBitwiseOr
+                | LowLevel::NumBitwiseXor
+                | LowLevel::Eq
+                | LowLevel::NotEq,
+            ..
+        } => Some(call.arguments),
+        _ => None,
+    }
+}
+
+/// Whether a literal of this layout is stored on the stack, so that loading it takes several instructions.
+fn is_stack_literal(layout: InLayout) -> bool {
+    matches!(
+        layout,
+        Layout::STR | Layout::DEC | Layout::I128 | Layout::U128
+    )
+}
+
+/// Removes the Lets of stack literals from stmt and pushes them onto hoisted.
+/// Literals do not depend on anything, so they can be defined anywhere before their uses.
+fn take_stack_literals<'a>(
+    arena: &'a Bump,
+    stmt: &'a Stmt<'a>,
+    hoisted: &mut std::vec::Vec<(Symbol, Literal<'a>, InLayout<'a>)>,
+) -> &'a Stmt<'a> {
+    match stmt {
+        Stmt::Let(sym, Expr::Literal(lit), layout, following) if is_stack_literal(*layout) => {
+            hoisted.push((*sym, *lit, *layout));
+            take_stack_literals(arena, following, hoisted)
+        }
+        Stmt::Let(sym, expr, layout, following) => arena.alloc(Stmt::Let(
+            *sym,
+            expr.clone(),
+            *layout,
+            take_stack_literals(arena, following, hoisted),
+        )),
+        Stmt::Refcounting(modify, following) => arena.alloc(Stmt::Refcounting(
+            *modify,
+            take_stack_literals(arena, following, hoisted),
+        )),
+        Stmt::Switch {
+            cond_symbol,
+            cond_layout,
+            branches,
+            default_branch,
+            ret_layout,
+        } => {
+            let branches = Vec::from_iter_in(
+                branches.iter().map(|(val, info, branch)| {
+                    let branch = take_stack_literals(arena, branch, hoisted);
+                    (*val, info.clone(), branch.clone())
+                }),
+                arena,
+            );
+            let default_branch = (
+                default_branch.0.clone(),
+                take_stack_literals(arena, default_branch.1, hoisted),
+            );
+            arena.alloc(Stmt::Switch {
+                cond_symbol: *cond_symbol,
+                cond_layout: *cond_layout,
+                branches: branches.into_bump_slice(),
                 default_branch,
-                ret_layout,
-            } => {
-                self.load_literal_symbols(&[*cond_symbol]);
-                self.build_switch(
-                    layout_ids,
-                    cond_symbol,
-                    cond_layout,
-                    branches,
-                    default_branch,
-                    ret_layout,
-                );
-                self.free_symbols(stmt);
-            }
-            Stmt::Join {
-                id,
-                parameters,
+                ret_layout: *ret_layout,
+            })
+        }
+        Stmt::Join {
+            id,
+            parameters,
+            body,
+            remainder,
+        } => {
+            let body = take_stack_literals(arena, body, hoisted);
+            let remainder = take_stack_literals(arena, remainder, hoisted);
+            arena.alloc(Stmt::Join {
+                id: *id,
+                parameters: *parameters,
                 body,
                 remainder,
-            } => {
-                for param in parameters.iter() {
-                    self.set_layout_map(param.symbol, &param.layout);
-                }
-                self.build_join(layout_ids, id, parameters, body, remainder, ret_layout);
-                self.free_symbols(stmt);
-            }
-            Stmt::Jump(id, args) => {
-                self.load_literal_symbols(args);
-                let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
-                    bumpalo::vec![in self.env().arena];
-                arg_layouts.reserve(args.len());
-                let layout_map = self.layout_map();
-                for arg in *args {
-                    if let Some(layout) = layout_map.get(arg) {
-                        arg_layouts.push(*layout);
-                    } else {
-                        internal_error!("the argument, {:?}, has no know layout", arg);
-                    }
-                }
-                self.build_jump(id, args, arg_layouts.into_bump_slice(), ret_layout);
-                self.free_symbols(stmt);
+            })
+        }
+        _ => stmt,
+    }
+}
+
+/// Moves the stack literals of a loop around the whole proc body in front of the loop head.
+/// This is the shape of self-recursive procs once their tail calls are jumps.
+/// Returns the new body and the hoisted symbols, or None if there is no loop or nothing to hoist.
+fn hoist_loop_invariants<'a>(
+    arena: &'a Bump,
+    body: &'a Stmt<'a>,
+) -> Option<(&'a Stmt<'a>, std::vec::Vec<Symbol>)> {
+    let Stmt::Join {
+        id,
+        parameters,
+        body: loop_body,
+        remainder,
+    } = body
+    else {
+        return None;
+    };
+    if !matches!(remainder, Stmt::Jump(jump_id, _) if jump_id == id) {
+        return None;
+    }
+
+    let mut hoisted = std::vec::Vec::new();
+    let loop_body = take_stack_literals(arena, loop_body, &mut hoisted);
+    if hoisted.is_empty() {
+        return None;
+    }
+    let mut stmt: &'a Stmt<'a> = arena.alloc(Stmt::Join {
+        id: *id,
+        parameters: *parameters,
+        body: loop_body,
+        remainder: *remainder,
+    });
+    for (sym, lit, layout) in hoisted.iter().rev() {
+        stmt = arena.alloc(Stmt::Let(*sym, Expr::Literal(*lit), *layout, stmt));
+    }
+    Some((stmt, hoisted.into_iter().map(|(sym, ..)| sym).collect()))
+}
+
+/// Truncates value to the bits of width, then sign or zero extends it again.
+fn wrap_int(value: i128, width: IntWidth) -> i128 {
+    let shift = 128 - 8 * width.stack_size();
+    if width.is_signed() {
+        (value << shift) >> shift
+    } else {
+        ((value << shift) as u128 >> shift) as i128
+    }
+}
+
+/// Computes `op` on literal arguments at compile time.
+/// The arguments are integers of at most 64 bits of the given width, or bools if width is None.
+/// Returns None for ops that are not folded, and for results that overflow, those must fail at runtime.
+fn fold_low_level(
+    op: LowLevel,
+    width: Option<IntWidth>,
+    args: &[i128],
+) -> Option<Literal<'static>> {
+    let bool_lit = |x: bool| Some(Literal::Bool(x));
+    match (width, args) {
+        (None, [a]) => match op {
+            LowLevel::Not => bool_lit(*a == 0),
+            _ => None,
+        },
+        (None, [a, b]) => match op {
+            LowLevel::And => bool_lit(*a != 0 && *b != 0),
+            LowLevel::Or => bool_lit(*a != 0 || *b != 0),
+            LowLevel::Eq => bool_lit(a == b),
+            LowLevel::NotEq => bool_lit(a != b),
+            _ => None,
+        },
+        (Some(width), [a]) => match op {
+            LowLevel::NumNeg => {
+                let value = a.checked_neg()?;
+                (wrap_int(value, width) == value).then(|| Literal::Int(value.to_ne_bytes()))
+            }
+            _ => None,
+        },
+        (Some(width), [a, b]) => {
+            let int_lit = |x: i128| Some(Literal::Int(wrap_int(x, width).to_ne_bytes()));
+            let checked_lit = |x: Option<i128>| {
+                let value = x?;
+                (wrap_int(value, width) == value).then(|| Literal::Int(value.to_ne_bytes()))
+            };
+            match op {
+                LowLevel::NumAdd => checked_lit(a.checked_add(*b)),
+                LowLevel::NumSub => checked_lit(a.checked_sub(*b)),
+                LowLevel::NumMul => checked_lit(a.checked_mul(*b)),
+                LowLevel::NumAddWrap => int_lit(a.wrapping_add(*b)),
+                LowLevel::NumSubWrap => int_lit(a.wrapping_sub(*b)),
+                LowLevel::NumMulWrap => int_lit(a.wrapping_mul(*b)),
+                LowLevel::NumBitwiseAnd => int_lit(a & b),
+                LowLevel::NumBitwiseOr => int_lit(a | b),
+                LowLevel::NumBitwiseXor => int_lit(a ^ b),
+                LowLevel::Eq => bool_lit(a == b),
+                LowLevel::NotEq => bool_lit(a != b),
+                LowLevel::NumLt => bool_lit(a < b),
+                LowLevel::NumLte => bool_lit(a <= b),
+                LowLevel::NumGt => bool_lit(a > b),
+                LowLevel::NumGte => bool_lit(a >= b),
+                _ => None,
             }
-            Stmt::Crash(msg, crash_tag) => self.roc_panic(*msg, *crash_tag),
-            x => todo!("the statement, {:?}", x),
         }
+        _ => None,
     }
+}
 
-    fn roc_panic(&mut self, msg: Symbol, crash_tag: CrashTag) {
-        let error_message = self.debug_symbol("error_message");
+/// The range of statement positions from the definition of a symbol to its last use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveInterval {
+    pub start: u32,
+    pub end: u32,
+}
 
-        self.load_literal(
-            &error_message,
-            &Layout::U32,
-            &Literal::Int((crash_tag as u128).to_ne_bytes()),
-        );
+impl<'a> Liveness<'a> {
+    fn set_seen(&mut self, symbol: Symbol) {
+        let position = self.position;
+        self.live_intervals
+            .entry(symbol)
+            .and_modify(|interval| interval.end = max(interval.end, position))
+            .or_insert(LiveInterval {
+                start: position,
+                end: position,
+            });
+    }
 
-        // Now that the arguments are needed, load them if they are literals.
-        let arguments = &[msg, error_message];
-        self.load_literal_symbols(arguments);
-        self.build_fn_call(
-            &Symbol::DEV_TMP2,
-            String::from("roc_panic"),
-            arguments,
-            &[Layout::STR, Layout::U32],
-            &Layout::UNIT,
-        );
+    /// scan_ast runs through the ast and computes where every symbol dies and the live intervals.
+    fn scan_ast(root: &'a Stmt<'a>) -> Self {
+        let mut this: Self = Default::default();
+
+        this.scan_intervals(root);
+        this.scan_liveness(root, &MutSet::default(), true);
 
-        self.free_symbol(&error_message);
-        self.free_symbol(&Symbol::DEV_TMP2);
+        let use_counts = std::mem::take(&mut this.use_counts);
+        this.sole_uses
+            .retain(|sym, _| use_counts.get(sym).copied() == Some(1));
+        this.fused_compares
+            .retain(|sym| use_counts.get(sym).copied() == Some(1));
+
+        this
     }
 
-    // build_switch generates a instructions for a switch statement.
-    fn build_switch(
-        &mut self,
-        layout_ids: &mut LayoutIds<'a>,
-        cond_symbol: &Symbol,
-        cond_layout: &InLayout<'a>,
-        branches: &'a [(u64, BranchInfo<'a>, Stmt<'a>)],
-        default_branch: &(BranchInfo<'a>, &'a Stmt<'a>),
-        ret_layout: &InLayout<'a>,
-    );
-
-    // build_join generates a instructions for a join statement.
-    fn build_join(
-        &mut self,
-        layout_ids: &mut LayoutIds<'a>,
-        id: &JoinPointId,
-        parameters: &'a [Param<'a>],
-        body: &'a Stmt<'a>,
-        remainder: &'a Stmt<'a>,
-        ret_layout: &InLayout<'a>,
-    );
-
-    // build_jump generates a instructions for a jump statement.
-    fn build_jump(
-        &mut self,
-        id: &JoinPointId,
-        args: &[Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_expr builds the expressions for the specified symbol.
-    /// The builder must keep track of the symbol because it may be referred to later.
-    fn build_expr(&mut self, sym: &Symbol, expr: &Expr<'a>, layout: &InLayout<'a>) {
-        match expr {
-            Expr::Literal(lit) => {
-                if self.env().lazy_literals {
-                    self.literal_map().insert(*sym, (lit, layout));
-                } else {
-                    self.load_literal(sym, layout, lit);
-                }
-            }
-            Expr::Call(roc_mono::ir::Call {
-                call_type,
-                arguments,
-            }) => {
-                match call_type {
-                    CallType::ByName {
-                        name: func_sym,
-                        arg_layouts,
-                        ret_layout,
-                        ..
-                    } => {
-                        if let LowLevelWrapperType::CanBeReplacedBy(lowlevel) =
-                            LowLevelWrapperType::from_symbol(func_sym.name())
-                        {
-                            return self.build_run_low_level(
-                                sym,
-                                &lowlevel,
-                                arguments,
-                                arg_layouts,
-                                ret_layout,
-                            );
-                        } else if func_sym.name().is_builtin() {
-                            // These builtins can be built through `build_fn_call` as well, but the
-                            // implementation in `build_builtin` inlines some of the symbols.
-                            return self.build_builtin(
-                                sym,
-                                *func_sym,
-                                arguments,
-                                arg_layouts,
-                                ret_layout,
-                            );
-                        }
+    /// The symbols a statement uses, not counting the statements nested in it.
+    fn stmt_uses(stmt: &Stmt<'a>) -> std::vec::Vec<Symbol> {
+        match stmt {
+            Stmt::Let(_, expr, _, _) => match expr {
+                Expr::Literal(_) => vec![],
+                Expr::NullPointer => vec![],
+
+                Expr::Call(call) => call.arguments.to_vec(),
+
+                Expr::Tag {
+                    arguments, reuse, ..
+                } => reuse
+                    .iter()
+                    .map(|ru| ru.symbol)
+                    .chain(arguments.iter().copied())
+                    .collect(),
+                Expr::ExprBox { symbol } => vec![*symbol],
+                Expr::ExprUnbox { symbol } => vec![*symbol],
+                Expr::Struct(syms) => syms.to_vec(),
+                Expr::StructAtIndex { structure, .. } => vec![*structure],
+                Expr::GetTagId { structure, .. } => vec![*structure],
+                Expr::UnionAtIndex { structure, .. } => vec![*structure],
+                Expr::UnionFieldPtrAtIndex { structure, .. } => vec![*structure],
+                Expr::Array { elems, .. } => elems
+                    .iter()
+                    .filter_map(|elem| match elem {
+                        ListLiteralElement::Symbol(sym) => Some(*sym),
+                        ListLiteralElement::Literal(_) => None,
+                    })
+                    .collect(),
+                Expr::Reset { symbol, .. } | Expr::ResetRef { symbol, .. } => vec![*symbol],
+                Expr::EmptyArray => vec![],
+                Expr::RuntimeErrorFunction(_) => vec![],
+            },
+            Stmt::Switch { cond_symbol, .. } => vec![*cond_symbol],
+            Stmt::Ret(sym) => vec![*sym],
+            Stmt::Refcounting(modify, _) => vec![modify.get_symbol()],
+            Stmt::Join { .. } => vec![],
+            Stmt::Jump(_, symbols) => symbols.to_vec(),
+
+            Stmt::Expect {
+                condition, lookups, ..
+            } => std::iter::once(*condition)
+                .chain(lookups.iter().copied())
+                .collect(),
+
+            Stmt::Dbg { symbol, .. } => vec![*symbol],
+            // expect-fx is not supported, it is built as a crash that uses nothing.
+            Stmt::ExpectFx { .. } => vec![],
+
+            Stmt::Crash(msg, _crash_tag) => vec![*msg],
+        }
+    }
 
-                        let fn_name = self.lambda_name_to_string(
-                            *func_sym,
-                            arg_layouts.iter().copied(),
-                            None,
-                            *ret_layout,
-                        );
+    /// scan_intervals numbers the statements and fills the live intervals.
+    /// This must iterate through the ast in the same way that build_stmt does. i.e. then before else.
+    fn scan_intervals(&mut self, stmt: &'a Stmt<'a>) {
+        self.position += 1;
+        self.stmt_positions.insert(stmt, self.position);
 
-                        // Now that the arguments are needed, load them if they are literals.
-                        self.load_literal_symbols(arguments);
-                        self.build_fn_call(sym, fn_name, arguments, arg_layouts, ret_layout)
-                    }
+        for sym in Self::stmt_uses(stmt) {
+            self.set_seen(sym);
+            *self.use_counts.entry(sym).or_insert(0) += 1;
+        }
 
-                    CallType::LowLevel { op: lowlevel, .. } => {
-                        let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
-                            bumpalo::vec![in self.env().arena];
-                        arg_layouts.reserve(arguments.len());
-                        let layout_map = self.layout_map();
-                        for arg in *arguments {
-                            if let Some(layout) = layout_map.get(arg) {
-                                arg_layouts.push(*layout);
-                            } else {
-                                internal_error!("the argument, {:?}, has no know layout", arg);
-                            }
-                        }
+        match stmt {
+            Stmt::Let(sym, expr, layout, following) => {
+                if let Expr::Call(call) = expr {
+                    self.call_positions.push(self.position);
 
-                        self.build_run_low_level(
-                            sym,
-                            lowlevel,
-                            arguments,
-                            arg_layouts.into_bump_slice(),
-                            layout,
-                        )
-                    }
-                    CallType::HigherOrder(higher_order) => {
-                        self.build_higher_order_lowlevel(sym, higher_order, *layout)
-                    }
-                    CallType::Foreign {
-                        foreign_symbol,
+                    if let CallType::ByName {
+                        arg_layouts,
                         ret_layout,
-                    } => {
-                        let mut arg_layouts: bumpalo::collections::Vec<InLayout<'a>> =
-                            bumpalo::vec![in self.env().arena];
-                        arg_layouts.reserve(arguments.len());
-                        let layout_map = self.layout_map();
-                        for arg in *arguments {
-                            if let Some(layout) = layout_map.get(arg) {
-                                arg_layouts.push(*layout);
-                            } else {
-                                internal_error!("the argument, {:?}, has no know layout", arg);
-                            }
+                        ..
+                    } = call.call_type
+                    {
+                        for (index, arg) in call.arguments.iter().enumerate() {
+                            self.sole_uses.insert(
+                                *arg,
+                                SoleUse::CallArg {
+                                    arg_layouts,
+                                    index,
+                                    ret_layout,
+                                },
+                            );
                         }
-
-                        self.load_literal_symbols(arguments);
-                        self.build_fn_call(
-                            sym,
-                            foreign_symbol.as_str().to_string(),
-                            arguments,
-                            arg_layouts.into_bump_slice(),
-                            ret_layout,
-                        );
                     }
                 }
-            }
-            Expr::EmptyArray => {
-                self.create_empty_array(sym);
-            }
-            Expr::Array { elem_layout, elems } => {
-                let mut syms = bumpalo::vec![in self.env().arena];
-                for sym in elems.iter().filter_map(|x| match x {
-                    ListLiteralElement::Symbol(sym) => Some(sym),
-                    _ => None,
-                }) {
-                    syms.push(*sym);
+                if compare_switch(sym, expr, following).is_some() {
+                    self.fused_compares.insert(*sym);
                 }
-                self.create_array(sym, elem_layout, elems);
+                self.set_seen(*sym);
+                self.layouts.insert(*sym, *layout);
+                self.scan_intervals(following);
             }
-            Expr::Struct(fields) => {
-                self.load_literal_symbols(fields);
-                self.create_struct(sym, layout, fields);
-            }
-            Expr::StructAtIndex {
-                index,
-                field_layouts,
-                structure,
-            } => {
-                self.load_struct_at_index(sym, structure, *index, field_layouts);
-            }
-            Expr::UnionAtIndex {
-                structure,
-                tag_id,
-                union_layout,
-                index,
-            } => {
-                self.load_union_at_index(sym, structure, *tag_id, *index, union_layout);
-            }
-            Expr::UnionFieldPtrAtIndex {
-                structure,
-                tag_id,
-                union_layout,
-                index,
+            Stmt::Switch {
+                branches,
+                default_branch,
+                ..
             } => {
-                self.load_union_field_ptr_at_index(sym, structure, *tag_id, *index, union_layout);
+                for (_, _, branch) in *branches {
+                    self.scan_intervals(branch);
+                }
+                self.scan_intervals(default_branch.1);
             }
-            Expr::GetTagId {
-                structure,
-                union_layout,
-            } => {
-                self.get_tag_id(sym, structure, union_layout);
+            Stmt::Refcounting(_, following) => {
+                // Refcounting is expanded into calls to helper procs.
+                self.call_positions.push(self.position);
+                self.scan_intervals(following);
             }
-            Expr::Tag {
-                tag_layout,
-                tag_id,
-                arguments,
-                reuse,
+            Stmt::Join {
+                parameters,
+                body: continuation,
+                remainder,
+                id,
             } => {
-                self.load_literal_symbols(arguments);
-                let reuse = reuse.map(|ru| ru.symbol);
-                self.tag(sym, arguments, tag_layout, *tag_id, reuse);
-            }
-            Expr::ExprBox { symbol: value } => {
-                let element_layout = match self.interner().get_repr(*layout) {
-                    LayoutRepr::Boxed(boxed) => boxed,
-                    _ => unreachable!("{:?}", self.interner().dbg(*layout)),
-                };
-
-                self.load_literal_symbols([*value].as_slice());
-                self.expr_box(*sym, *value, element_layout, None)
-            }
-            Expr::ExprUnbox { symbol: ptr } => {
-                let element_layout = *layout;
-
-                self.load_literal_symbols([*ptr].as_slice());
-                self.expr_unbox(*sym, *ptr, element_layout)
-            }
-            Expr::NullPointer => {
-                self.load_literal_i64(sym, 0);
-            }
-            Expr::Reset { symbol, .. } => {
-                let layout = *self.layout_map().get(symbol).unwrap();
-
-                // Expand the Refcounting statement into more detailed IR with a function call
-                // If this layout requires a new RC proc, we get enough info to create a linker symbol
-                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
-                let (new_expr, new_specializations) = {
-                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
-                        self.module_interns_helpers_mut();
-                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
-
-                    rc_proc_gen.call_reset_refcount(ident_ids, layout_interner, layout, *symbol)
-                };
-
-                for spec in new_specializations.into_iter() {
-                    self.helper_proc_symbols_mut().push(spec);
+                let join_position = self.position;
+                self.join_map.insert(*id, parameters);
+                self.scan_intervals(remainder);
+                let continuation_start = self.position + 1;
+                self.scan_intervals(continuation);
+
+                // Symbols from before the joinpoint that are used in the continuation live for the whole joinpoint.
+                let end = self.position;
+                for interval in self.live_intervals.values_mut() {
+                    if interval.start < join_position && interval.end >= continuation_start {
+                        interval.end = end;
+                    }
+                }
+                for param in *parameters {
+                    self.set_seen(param.symbol);
+                    self.layouts.insert(param.symbol, param.layout);
                 }
-
-                self.build_expr(sym, &new_expr, &Layout::BOOL)
             }
-            Expr::ResetRef { symbol, .. } => {
-                let layout = *self.layout_map().get(symbol).unwrap();
-
-                // Expand the Refcounting statement into more detailed IR with a function call
-                // If this layout requires a new RC proc, we get enough info to create a linker symbol
-                // for it. Here we don't create linker symbols at this time, but in Wasm backend, we do.
-                let (new_expr, new_specializations) = {
-                    let (module_id, layout_interner, interns, rc_proc_gen, _) =
-                        self.module_interns_helpers_mut();
-                    let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
-
-                    rc_proc_gen.call_resetref_refcount(ident_ids, layout_interner, layout, *symbol)
-                };
-
-                for spec in new_specializations.into_iter() {
-                    self.helper_proc_symbols_mut().push(spec);
+            Stmt::Jump(id, _) => {
+                if let Some(parameters) = self.join_map.get(id) {
+                    // Keep the parameters around. They will be overwritten when jumping.
+                    for param in *parameters {
+                        self.set_seen(param.symbol);
+                    }
                 }
-
-                self.build_expr(sym, &new_expr, &Layout::BOOL)
             }
-            Expr::RuntimeErrorFunction(_) => todo!(),
+            Stmt::Ret(sym) => {
+                self.sole_uses.insert(*sym, SoleUse::Return);
+            }
+            Stmt::Expect { remainder, .. } | Stmt::Dbg { remainder, .. } => {
+                // Failed expects and dbg values are reported through calls.
+                self.call_positions.push(self.position);
+                self.scan_intervals(remainder);
+            }
+            Stmt::Crash(..) | Stmt::ExpectFx { .. } => {}
         }
     }
 
-    /// build_run_low_level builds the low level opertation and outputs to the specified symbol.
-    /// The builder must keep track of the symbol because it may be referred to later.
-    fn build_run_low_level(
+    /// scan_liveness returns the symbols that are live when entering `stmt`.
+    /// If `record` is set, it also fills the free maps with where each symbol dies.
+    /// `pinned` symbols are kept alive by an enclosing joinpoint and are never freed within it.
+    fn scan_liveness(
         &mut self,
-        sym: &Symbol,
-        lowlevel: &LowLevel,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    ) {
-        // Now that the arguments are needed, load them if they are literals.
-        self.load_literal_symbols(args);
-        match lowlevel {
-            LowLevel::NumAbs => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumAbs: expected to have exactly one argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumAbs: expected to have the same argument and return layout"
-                );
-                self.build_num_abs(sym, &args[0], ret_layout)
-            }
-            LowLevel::NumAdd => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumAdd: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumAdd: expected all arguments of to have the same layout"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumAdd: expected to have the same argument and return layout"
-                );
-                self.build_num_add(sym, &args[0], &args[1], ret_layout)
-            }
-            LowLevel::NumAddSaturated => {
-                self.build_num_add_saturated(*sym, args[0], args[1], *ret_layout);
-            }
-            LowLevel::NumAddWrap => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumAdd: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumAdd: expected all arguments of to have the same layout"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumAdd: expected to have the same argument and return layout"
-                );
-                self.build_num_add(sym, &args[0], &args[1], ret_layout)
-            }
-            LowLevel::NumAddChecked => {
-                self.build_num_add_checked(sym, &args[0], &args[1], &arg_layouts[0], ret_layout)
-            }
-            LowLevel::NumSubChecked => {
-                self.build_num_sub_checked(sym, &args[0], &args[1], &arg_layouts[0], ret_layout)
-            }
-            LowLevel::NumAcos => self.build_fn_call(
-                sym,
-                bitcode::NUM_ACOS[FloatWidth::F64].to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::NumAsin => self.build_fn_call(
-                sym,
-                bitcode::NUM_ASIN[FloatWidth::F64].to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::NumAtan => self.build_fn_call(
-                sym,
-                bitcode::NUM_ATAN[FloatWidth::F64].to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::NumMul => self.build_num_mul(sym, &args[0], &args[1], ret_layout),
-            LowLevel::NumMulWrap => self.build_num_mul_wrap(sym, &args[0], &args[1], ret_layout),
-            LowLevel::NumDivTruncUnchecked | LowLevel::NumDivFrac => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumDiv: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumDiv: expected all arguments of to have the same layout"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumDiv: expected to have the same argument and return layout"
-                );
-                self.build_num_div(sym, &args[0], &args[1], ret_layout)
-            }
-
-            LowLevel::NumRemUnchecked => self.build_num_rem(sym, &args[0], &args[1], ret_layout),
-            LowLevel::NumNeg => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumNeg: expected to have exactly one argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumNeg: expected to have the same argument and return layout"
-                );
-                self.build_num_neg(sym, &args[0], ret_layout)
-            }
-            LowLevel::NumPowInt => self.build_fn_call(
-                sym,
-                bitcode::NUM_POW_INT[IntWidth::I64].to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::NumSub => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumSub: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumSub: expected all arguments of to have the same layout"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumSub: expected to have the same argument and return layout"
-                );
-                self.build_num_sub(sym, &args[0], &args[1], ret_layout)
-            }
-            LowLevel::NumSubWrap => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumSubWrap: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumSubWrap: expected all arguments of to have the same layout"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], *ret_layout,
-                    "NumSubWrap: expected to have the same argument and return layout"
-                );
-                self.build_num_sub_wrap(sym, &args[0], &args[1], ret_layout)
-            }
-            LowLevel::NumSubSaturated => match self.interner().get_repr(*ret_layout) {
-                LayoutRepr::Builtin(Builtin::Int(int_width)) => self.build_fn_call(
-                    sym,
-                    bitcode::NUM_SUB_SATURATED_INT[int_width].to_string(),
-                    args,
-                    arg_layouts,
-                    ret_layout,
-                ),
-                LayoutRepr::Builtin(Builtin::Float(FloatWidth::F32)) => {
-                    self.build_num_sub(sym, &args[0], &args[1], ret_layout)
-                }
-                LayoutRepr::Builtin(Builtin::Float(FloatWidth::F64)) => {
-                    // saturated sub is just normal sub
-                    self.build_num_sub(sym, &args[0], &args[1], ret_layout)
-                }
-                LayoutRepr::Builtin(Builtin::Decimal) => {
-                    // self.load_args_and_call_zig(backend, bitcode::DEC_SUB_SATURATED)
-                    todo!()
-                }
-                _ => internal_error!("invalid return type"),
-            },
-            LowLevel::NumBitwiseAnd => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_bitwise_and(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("bitwise and on a non-integer")
-                }
+        stmt: &'a Stmt<'a>,
+        pinned: &MutSet<Symbol>,
+        record: bool,
+    ) -> MutSet<Symbol> {
+        let uses = Self::stmt_uses(stmt);
+        match stmt {
+            Stmt::Let(_, _, _, following)
+            | Stmt::Refcounting(_, following)
+            | Stmt::Expect {
+                remainder: following,
+                ..
             }
-            LowLevel::NumBitwiseOr => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_bitwise_or(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("bitwise or on a non-integer")
+            | Stmt::Dbg {
+                remainder: following,
+                ..
+            } => {
+                let mut live = self.scan_liveness(following, pinned, record);
+                if record {
+                    let mut dead = std::vec::Vec::new();
+                    if let Stmt::Let(sym, ..) = stmt {
+                        if !live.contains(sym) {
+                            dead.push(*sym);
+                        }
+                    }
+                    for sym in uses.iter() {
+                        if !live.contains(sym) && !pinned.contains(sym) && !dead.contains(sym) {
+                            dead.push(*sym);
+                        }
+                    }
+                    self.record_free(stmt, dead);
                 }
-            }
-            LowLevel::NumBitwiseXor => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_bitwise_xor(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("bitwise xor on a non-integer")
+                if let Stmt::Let(sym, ..) = stmt {
+                    live.remove(sym);
                 }
+                live.extend(uses);
+                live
             }
-            LowLevel::And => {
-                if let LayoutRepr::Builtin(Builtin::Bool) = self.interner().get_repr(*ret_layout) {
-                    self.build_int_bitwise_and(sym, &args[0], &args[1], IntWidth::U8)
-                } else {
-                    internal_error!("bitwise and on a non-integer")
+            Stmt::Switch {
+                branches,
+                default_branch,
+                ..
+            } => {
+                let branch_stmts: std::vec::Vec<&'a Stmt<'a>> = branches
+                    .iter()
+                    .map(|(_, _, branch)| branch)
+                    .chain(std::iter::once(default_branch.1))
+                    .collect();
+                let branch_live: std::vec::Vec<MutSet<Symbol>> = branch_stmts
+                    .iter()
+                    .map(|branch| self.scan_liveness(branch, pinned, record))
+                    .collect();
+
+                let mut live: MutSet<Symbol> = uses.iter().copied().collect();
+                for branch in branch_live.iter() {
+                    live.extend(branch.iter().copied());
                 }
-            }
-            LowLevel::Or => {
-                if let LayoutRepr::Builtin(Builtin::Bool) = self.interner().get_repr(*ret_layout) {
-                    self.build_int_bitwise_or(sym, &args[0], &args[1], IntWidth::U8)
-                } else {
-                    internal_error!("bitwise or on a non-integer")
+                if record {
+                    // Anything live into the switch that a branch does not use dies when entering it.
+                    // This includes the condition, which is only needed to pick the branch.
+                    for (branch, branch_live) in branch_stmts.iter().zip(branch_live.iter()) {
+                        let dead = live
+                            .iter()
+                            .filter(|sym| !branch_live.contains(*sym) && !pinned.contains(*sym))
+                            .copied()
+                            .collect();
+                        self.record_branch_free(branch, dead);
+                    }
                 }
+                live
             }
-            LowLevel::NumShiftLeftBy => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_shift_left(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("shift left on a non-integer")
+            Stmt::Join {
+                parameters,
+                body: continuation,
+                remainder,
+                id: JoinPointId(id_sym),
+            } => {
+                let mut inner_pinned = pinned.clone();
+                inner_pinned.insert(*id_sym);
+                inner_pinned.extend(parameters.iter().map(|param| param.symbol));
+
+                let mut continuation_live = self.scan_liveness(continuation, &inner_pinned, false);
+                for param in *parameters {
+                    continuation_live.remove(&param.symbol);
                 }
-            }
-            LowLevel::NumShiftRightBy => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_shift_right(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("shift right on a non-integer")
+                inner_pinned.extend(continuation_live.iter().copied());
+
+                let mut live = self.scan_liveness(remainder, &inner_pinned, record);
+                if record {
+                    self.scan_liveness(continuation, &inner_pinned, true);
+
+                    // Everything pinned by this joinpoint dies with it.
+                    let dead = inner_pinned
+                        .iter()
+                        .filter(|sym| !pinned.contains(*sym))
+                        .copied()
+                        .collect();
+                    self.record_free(stmt, dead);
                 }
-            }
-            LowLevel::NumShiftRightZfBy => {
-                if let LayoutRepr::Builtin(Builtin::Int(int_width)) =
-                    self.interner().get_repr(*ret_layout)
-                {
-                    self.build_int_shift_right_zero_fill(sym, &args[0], &args[1], int_width)
-                } else {
-                    internal_error!("shift right zero-fill on a non-integer")
+                live.extend(continuation_live);
+                live.remove(id_sym);
+                for param in *parameters {
+                    live.remove(&param.symbol);
                 }
-            }
-            LowLevel::Eq => {
-                debug_assert_eq!(2, args.len(), "Eq: expected to have exactly two argument");
-
-                let a = Layout::runtime_representation_in(arg_layouts[0], self.interner());
-                let b = Layout::runtime_representation_in(arg_layouts[1], self.interner());
-
-                debug_assert!(
-                    self.interner().eq_repr(a, b),
-                    "Eq: expected all arguments to have the same layout, but {} != {}",
-                    self.interner().dbg(a),
-                    self.interner().dbg(b),
-                );
-
-                self.build_eq(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::NotEq => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NotEq: expected to have exactly two argument"
-                );
-
-                let a = Layout::runtime_representation_in(arg_layouts[0], self.interner());
-                let b = Layout::runtime_representation_in(arg_layouts[1], self.interner());
-
-                debug_assert!(
-                    self.interner().eq_repr(a, b),
-                    "NotEq: expected all arguments to have the same layout, but {} != {}",
-                    self.interner().dbg(a),
-                    self.interner().dbg(b),
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NotEq: expected to have return layout of type Bool"
-                );
-                self.build_neq(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::Not => {
-                debug_assert_eq!(1, args.len(), "Not: expected to have exactly one argument");
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "Not: expected to have return layout of type Bool"
-                );
-                self.build_not(sym, &args[0], &arg_layouts[0])
-            }
-            LowLevel::NumLt => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumLt: expected to have exactly two argument"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(arg_layouts[0], arg_layouts[1],),
-                    "NumLt: expected all arguments of to have the same layout"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumLt: expected to have return layout of type Bool"
-                );
-                self.build_num_lt(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::NumGt => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumGt: expected to have exactly two argument"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(arg_layouts[0], arg_layouts[1],),
-                    "NumGt: expected all arguments of to have the same layout"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumGt: expected to have return layout of type Bool"
-                );
-                self.build_num_gt(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::NumToFrac => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumToFrac: expected to have exactly one argument"
-                );
-
-                debug_assert!(
-                    matches!(*ret_layout, Layout::F32 | Layout::F64),
-                    "NumToFrac: expected to have return layout of type Float"
-                );
-                self.build_num_to_frac(sym, &args[0], &arg_layouts[0], ret_layout)
-            }
-            LowLevel::NumIsNan => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumIsNan: expected to have exactly one argument"
-                );
-
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumIsNan: expected to have return layout of type Bool"
-                );
-                self.build_num_is_nan(sym, &args[0], &arg_layouts[0])
-            }
-            LowLevel::NumIsInfinite => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumIsInfinite: expected to have exactly one argument"
-                );
-
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumIsInfinite: expected to have return layout of type Bool"
-                );
-                self.build_num_is_infinite(sym, &args[0], &arg_layouts[0])
-            }
-            LowLevel::NumIsFinite => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumIsFinite: expected to have exactly one argument"
-                );
-
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumIsFinite: expected to have return layout of type Bool"
-                );
-                self.build_num_is_finite(sym, &args[0], &arg_layouts[0])
-            }
-            LowLevel::NumLte => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumLte: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumLte: expected all arguments of to have the same layout"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumLte: expected to have return layout of type Bool"
-                );
-                self.build_num_lte(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::NumGte => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "NumGte: expected to have exactly two argument"
-                );
-                debug_assert_eq!(
-                    arg_layouts[0], arg_layouts[1],
-                    "NumGte: expected all arguments of to have the same layout"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumGte: expected to have return layout of type Bool"
-                );
-                self.build_num_gte(sym, &args[0], &args[1], &arg_layouts[0])
-            }
-            LowLevel::NumLogUnchecked => {
-                let float_width = match arg_layouts[0] {
-                    Layout::F64 => FloatWidth::F64,
-                    Layout::F32 => FloatWidth::F32,
-                    _ => unreachable!("invalid layout for sqrt"),
-                };
-
-                self.build_fn_call(
-                    sym,
-                    bitcode::NUM_LOG[float_width].to_string(),
-                    args,
-                    arg_layouts,
-                    ret_layout,
-                )
-            }
-            LowLevel::NumSqrtUnchecked => {
-                let float_width = match arg_layouts[0] {
-                    Layout::F64 => FloatWidth::F64,
-                    Layout::F32 => FloatWidth::F32,
-                    _ => unreachable!("invalid layout for sqrt"),
-                };
-
-                self.build_num_sqrt(*sym, args[0], float_width);
-            }
-            LowLevel::NumRound => self.build_fn_call(
-                sym,
-                bitcode::NUM_ROUND_F64[IntWidth::I64].to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::ListLen => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "ListLen: expected to have exactly one argument"
-                );
-                self.build_list_len(sym, &args[0])
-            }
-            LowLevel::ListWithCapacity => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "ListWithCapacity: expected to have exactly one argument"
-                );
-                let elem_layout = list_element_layout!(self.interner(), *ret_layout);
-                self.build_list_with_capacity(sym, args[0], arg_layouts[0], elem_layout, ret_layout)
-            }
-            LowLevel::ListReserve => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "ListReserve: expected to have exactly two arguments"
-                );
-                self.build_list_reserve(sym, args, arg_layouts, ret_layout)
-            }
-            LowLevel::ListAppendUnsafe => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "ListAppendUnsafe: expected to have exactly two arguments"
-                );
-                self.build_list_append_unsafe(sym, args, arg_layouts, ret_layout)
-            }
-            LowLevel::ListGetUnsafe => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "ListGetUnsafe: expected to have exactly two arguments"
-                );
-                self.build_list_get_unsafe(sym, &args[0], &args[1], ret_layout)
-            }
-            LowLevel::ListReplaceUnsafe => {
-                debug_assert_eq!(
-                    3,
-                    args.len(),
-                    "ListReplaceUnsafe: expected to have exactly three arguments"
-                );
-                self.build_list_replace_unsafe(sym, args, arg_layouts, ret_layout)
-            }
-            LowLevel::ListConcat => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "ListConcat: expected to have exactly two arguments"
-                );
-                let elem_layout = list_element_layout!(self.interner(), *ret_layout);
-                self.build_list_concat(sym, args, arg_layouts, elem_layout, ret_layout)
-            }
-            LowLevel::ListPrepend => {
-                debug_assert_eq!(
-                    2,
-                    args.len(),
-                    "ListPrepend: expected to have exactly two arguments"
-                );
-                self.build_list_prepend(sym, args, arg_layouts, ret_layout)
-            }
-            LowLevel::StrConcat => self.build_fn_call(
-                sym,
-                bitcode::STR_CONCAT.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrJoinWith => self.build_fn_call(
-                sym,
-                bitcode::STR_JOIN_WITH.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrSplit => self.build_fn_call(
-                sym,
-                bitcode::STR_SPLIT.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrStartsWith => self.build_fn_call(
-                sym,
-                bitcode::STR_STARTS_WITH.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrStartsWithScalar => self.build_fn_call(
-                sym,
-                bitcode::STR_STARTS_WITH_SCALAR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrAppendScalar => self.build_fn_call(
-                sym,
-                bitcode::STR_APPEND_SCALAR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrEndsWith => self.build_fn_call(
-                sym,
-                bitcode::STR_ENDS_WITH.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrCountGraphemes => self.build_fn_call(
-                sym,
-                bitcode::STR_COUNT_GRAPEHEME_CLUSTERS.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrSubstringUnsafe => self.build_fn_call(
-                sym,
-                bitcode::STR_SUBSTRING_UNSAFE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrToUtf8 => self.build_fn_call(
-                sym,
-                bitcode::STR_TO_UTF8.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrCountUtf8Bytes => self.build_fn_call(
-                sym,
-                bitcode::STR_COUNT_UTF8_BYTES.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrFromUtf8Range => self.build_fn_call(
-                sym,
-                bitcode::STR_FROM_UTF8_RANGE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            //            LowLevel::StrToUtf8 => self.build_fn_call(
-            //                sym,
-            //                bitcode::STR_TO_UTF8.to_string(),
-            //                args,
-            //                arg_layouts,
-            //                ret_layout,
-            //            ),
-            LowLevel::StrRepeat => self.build_fn_call(
-                sym,
-                bitcode::STR_REPEAT.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrTrim => self.build_fn_call(
-                sym,
-                bitcode::STR_TRIM.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrTrimStart => self.build_fn_call(
-                sym,
-                bitcode::STR_TRIM_START.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrTrimEnd => self.build_fn_call(
-                sym,
-                bitcode::STR_TRIM_END.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrReserve => self.build_fn_call(
-                sym,
-                bitcode::STR_RESERVE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrWithCapacity => self.build_fn_call(
-                sym,
-                bitcode::STR_WITH_CAPACITY.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrToScalars => self.build_fn_call(
-                sym,
-                bitcode::STR_TO_SCALARS.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrGetUnsafe => self.build_fn_call(
-                sym,
-                bitcode::STR_GET_UNSAFE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrGetScalarUnsafe => self.build_fn_call(
-                sym,
-                bitcode::STR_GET_SCALAR_UNSAFE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::StrToNum => {
-                let number_layout = match self.interner().get_repr(*ret_layout) {
-                    LayoutRepr::Struct(field_layouts) => field_layouts[0], // TODO: why is it sometimes a struct?
-                    _ => unreachable!(),
-                };
-
-                // match on the return layout to figure out which zig builtin we need
-                let intrinsic = match self.interner().get_repr(number_layout) {
-                    LayoutRepr::Builtin(Builtin::Int(int_width)) => &bitcode::STR_TO_INT[int_width],
-                    LayoutRepr::Builtin(Builtin::Float(float_width)) => {
-                        &bitcode::STR_TO_FLOAT[float_width]
+                live
+            }
+            Stmt::Ret(_) | Stmt::Jump(..) | Stmt::Crash(..) | Stmt::ExpectFx { .. } => {
+                if record {
+                    let mut dead = std::vec::Vec::new();
+                    for sym in uses.iter() {
+                        if !pinned.contains(sym) && !dead.contains(sym) {
+                            dead.push(*sym);
+                        }
                     }
-                    LayoutRepr::Builtin(Builtin::Decimal) => bitcode::DEC_FROM_STR,
-                    _ => unreachable!(),
-                };
-
-                self.build_fn_call(sym, intrinsic.to_string(), args, arg_layouts, ret_layout)
-            }
-            LowLevel::PtrCast => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "RefCountGetPtr: expected to have exactly one argument"
-                );
-
-                debug_assert_eq!(
-                    self.interner().stack_size_and_alignment(arg_layouts[0]),
-                    (8, 8),
-                    "cannot pointer cast from source: {}",
-                    self.interner().dbg(arg_layouts[0])
-                );
-
-                debug_assert_eq!(
-                    self.interner().stack_size_and_alignment(*ret_layout),
-                    (8, 8),
-                    "cannot pointer cast to target: {}",
-                    self.interner().dbg(*ret_layout)
-                );
-
-                self.build_ptr_cast(sym, &args[0])
-            }
-            LowLevel::PtrStore => {
-                let element_layout = match self.interner().get_repr(arg_layouts[0]) {
-                    LayoutRepr::Ptr(inner) => inner,
-                    LayoutRepr::Boxed(inner) => inner,
-                    _ => unreachable!("cannot write to {:?}", self.interner().dbg(*ret_layout)),
-                };
-
-                self.build_ptr_store(*sym, args[0], args[1], element_layout);
-            }
-            LowLevel::PtrLoad => {
-                self.build_ptr_load(*sym, args[0], *ret_layout);
-            }
-
-            LowLevel::PtrClearTagId => {
-                self.build_ptr_clear_tag_id(*sym, args[0]);
-            }
-
-            LowLevel::Alloca => {
-                self.build_alloca(*sym, args[0], arg_layouts[0]);
-            }
-
-            LowLevel::RefCountDecRcPtr => self.build_fn_call(
-                sym,
-                bitcode::UTILS_DECREF_RC_PTR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::RefCountIncRcPtr => self.build_fn_call(
-                sym,
-                bitcode::UTILS_INCREF_RC_PTR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::RefCountDecDataPtr => self.build_fn_call(
-                sym,
-                bitcode::UTILS_DECREF_DATA_PTR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::RefCountIncDataPtr => self.build_fn_call(
-                sym,
-                bitcode::UTILS_INCREF_DATA_PTR.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::RefCountIsUnique => self.build_fn_call(
-                sym,
-                bitcode::UTILS_IS_UNIQUE.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::DictPseudoSeed => self.build_fn_call(
-                sym,
-                bitcode::UTILS_DICT_PSEUDO_SEED.to_string(),
-                args,
-                arg_layouts,
-                ret_layout,
-            ),
-            LowLevel::NumToStr => {
-                let arg_layout = arg_layouts[0];
-                let intrinsic = match self.interner().get_repr(arg_layout) {
-                    LayoutRepr::Builtin(Builtin::Int(width)) => &bitcode::STR_FROM_INT[width],
-                    LayoutRepr::Builtin(Builtin::Float(width)) => &bitcode::STR_FROM_FLOAT[width],
-                    LayoutRepr::Builtin(Builtin::Decimal) => bitcode::DEC_TO_STR,
-                    x => internal_error!("NumToStr is not defined for {:?}", x),
-                };
-
-                self.build_fn_call(sym, intrinsic.to_string(), args, arg_layouts, ret_layout)
-            }
-            LowLevel::StrIsEmpty => {
-                let intrinsic = bitcode::STR_IS_EMPTY.to_string();
-                self.build_fn_call(sym, intrinsic, args, arg_layouts, ret_layout);
-            }
-            LowLevel::NumIntCast => {
-                let source_width = match self.interner().get_repr(arg_layouts[0]) {
-                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
-                    _ => unreachable!(),
-                };
-
-                let target_width = match self.interner().get_repr(*ret_layout) {
-                    LayoutRepr::Builtin(Builtin::Int(width)) => width,
-                    _ => unreachable!(),
-                };
-
-                self.build_num_int_cast(sym, &args[0], source_width, target_width)
-            }
-            LowLevel::NumIsMultipleOf => {
-                let int_width = arg_layouts[0].try_int_width().unwrap();
-                let intrinsic = bitcode::NUM_IS_MULTIPLE_OF[int_width].to_string();
-                self.build_fn_call(sym, intrinsic, args, arg_layouts, ret_layout);
-            }
-            LowLevel::ListSublist => {
-                //    list: RocList,
-                //    alignment: u32,
-                //    element_width: usize,
-                //    start: usize,
-                //    len: usize,
-                //    dec: Dec,
-
-                let list = args[0];
-                let start = args[1];
-                let len = args[2];
-
-                let list_layout = arg_layouts[0];
-                let list_argument = self.list_argument(list_layout);
-                let element_layout = list_argument.element_layout;
-
-                let args = [
-                    list,
-                    list_argument.alignment,
-                    list_argument.element_width,
-                    start,
-                    len,
-                    self.decrement_fn_pointer(element_layout),
-                ];
-
-                let layout_usize = Layout::U64;
-
-                let arg_layouts = [
-                    arg_layouts[0],
-                    Layout::U32,
-                    layout_usize,
-                    arg_layouts[1],
-                    arg_layouts[2],
-                    layout_usize,
-                ];
-
-                let intrinsic = bitcode::LIST_SUBLIST.to_string();
-                self.build_fn_call(sym, intrinsic, &args, &arg_layouts, &list_layout);
-            }
-            LowLevel::ListSwap => {
-                let list = args[0];
-                let i = args[1];
-                let j = args[2];
-
-                let list_layout = arg_layouts[0];
-                let list_argument = self.list_argument(list_layout);
-
-                let update_mode = self.debug_symbol("update_mode");
-                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
-
-                let layout_usize = Layout::U64;
-
-                //    list: RocList,
-                //    alignment: u32,
-                //    element_width: usize,
-                //    index_1: usize,
-                //    index_2: usize,
-                //    update_mode: UpdateMode,
-
-                self.build_fn_call(
-                    sym,
-                    bitcode::LIST_SWAP.to_string(),
-                    &[
-                        list,
-                        list_argument.alignment,
-                        list_argument.element_width,
-                        i,
-                        j,
-                        update_mode,
-                    ],
-                    &[
-                        list_layout,
-                        Layout::U32,
-                        layout_usize,
-                        layout_usize,
-                        layout_usize,
-                        Layout::U8,
-                    ],
-                    ret_layout,
-                );
-            }
-            LowLevel::ListReleaseExcessCapacity => {
-                let list = args[0];
-
-                let list_layout = arg_layouts[0];
-                let list_argument = self.list_argument(list_layout);
-
-                let update_mode = self.debug_symbol("update_mode");
-                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
-
-                let layout_usize = Layout::U64;
-
-                //    list: RocList,
-                //    alignment: u32,
-                //    element_width: usize,
-                //    update_mode: UpdateMode,
-
-                self.build_fn_call(
-                    sym,
-                    bitcode::LIST_RELEASE_EXCESS_CAPACITY.to_string(),
-                    &[
-                        list,
-                        list_argument.alignment,
-                        list_argument.element_width,
-                        update_mode,
-                    ],
-                    &[list_layout, Layout::U32, layout_usize, Layout::U8],
-                    ret_layout,
-                );
-            }
-
-            LowLevel::ListDropAt => {
-                let list = args[0];
-                let drop_index = args[1];
-
-                let list_layout = arg_layouts[0];
-                let list_argument = self.list_argument(list_layout);
-                let element_layout = list_argument.element_layout;
-
-                let update_mode = self.debug_symbol("update_mode");
-                self.load_literal_i8(&update_mode, UpdateMode::Immutable as i8);
-
-                let layout_usize = Layout::U64;
-                let element_decrement = self.decrement_fn_pointer(element_layout);
-
-                //    list: RocList,
-                //    alignment: u32,
-                //    element_width: usize,
-                //    drop_index: usize,
-                //    dec: Dec,
-
-                self.build_fn_call(
-                    sym,
-                    bitcode::LIST_DROP_AT.to_string(),
-                    &[
-                        list,
-                        list_argument.alignment,
-                        list_argument.element_width,
-                        drop_index,
-                        element_decrement,
-                    ],
-                    &[
-                        list_layout,
-                        Layout::U32,
-                        layout_usize,
-                        layout_usize,
-                        layout_usize,
-                    ],
-                    ret_layout,
-                );
-            }
-
-            LowLevel::NumCompare => {
-                self.build_num_cmp(sym, &args[0], &args[1], &arg_layouts[0]);
+                    self.record_free(stmt, dead);
+                }
+                uses.into_iter().collect()
             }
-
-            x => todo!("low level, {:?}", x),
         }
     }
 
-    /// Builds a builtin functions that do not map directly to a low level
-    /// If the builtin is simple enough, it will be inlined.
-    fn build_builtin(
-        &mut self,
-        sym: &Symbol,
-        func_name: LambdaName,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    ) {
-        match func_name.name() {
-            Symbol::NUM_IS_ZERO => {
-                debug_assert_eq!(
-                    1,
-                    args.len(),
-                    "NumIsZero: expected to have exactly one argument"
-                );
-                debug_assert!(
-                    self.interner().eq_repr(Layout::BOOL, *ret_layout,),
-                    "NumIsZero: expected to have return layout of type Bool"
-                );
-
-                self.load_literal_symbols(args);
-                self.load_literal(
-                    &Symbol::DEV_TMP,
-                    &arg_layouts[0],
-                    &Literal::Int(0i128.to_ne_bytes()),
-                );
-                self.build_eq(sym, &args[0], &Symbol::DEV_TMP, &arg_layouts[0]);
-                self.free_symbol(&Symbol::DEV_TMP)
-            }
-            Symbol::LIST_GET | Symbol::LIST_SET | Symbol::LIST_REPLACE | Symbol::LIST_APPEND => {
-                // TODO: This is probably simple enough to be worth inlining.
-                let fn_name = self.lambda_name_to_string(
-                    func_name,
-                    arg_layouts.iter().copied(),
-                    None,
-                    *ret_layout,
-                );
-                // Now that the arguments are needed, load them if they are literals.
-                self.load_literal_symbols(args);
-                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
-            }
-            Symbol::BOOL_TRUE => {
-                const LITERAL: &Literal<'static> = &Literal::Bool(true);
-                const BOOL_LAYOUT: &InLayout<'static> = &Layout::BOOL;
-
-                if self.env().lazy_literals {
-                    self.literal_map().insert(*sym, (LITERAL, BOOL_LAYOUT));
-                } else {
-                    self.load_literal(sym, BOOL_LAYOUT, LITERAL);
-                }
-            }
-            Symbol::BOOL_FALSE => {
-                const LITERAL: &Literal<'static> = &Literal::Bool(false);
-                const BOOL_LAYOUT: &InLayout<'static> = &Layout::BOOL;
-
-                if self.env().lazy_literals {
-                    self.literal_map().insert(*sym, (LITERAL, BOOL_LAYOUT));
-                } else {
-                    self.load_literal(sym, BOOL_LAYOUT, LITERAL);
-                }
-            }
-            Symbol::STR_IS_VALID_SCALAR => {
-                // just call the function
-                let fn_name = self.lambda_name_to_string(
-                    func_name,
-                    arg_layouts.iter().copied(),
-                    None,
-                    *ret_layout,
-                );
-                // Now that the arguments are needed, load them if they are literals.
-                self.load_literal_symbols(args);
-                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
-            }
-            _other => {
-                // just call the function
-                let fn_name = self.lambda_name_to_string(
-                    func_name,
-                    arg_layouts.iter().copied(),
-                    None,
-                    *ret_layout,
-                );
-                // Now that the arguments are needed, load them if they are literals.
-                self.load_literal_symbols(args);
-                self.build_fn_call(sym, fn_name, args, arg_layouts, ret_layout)
-            }
+    fn record_free(&mut self, stmt: &'a Stmt<'a>, dead: std::vec::Vec<Symbol>) {
+        if dead.is_empty() {
+            self.free_map.remove(&(stmt as *const Stmt<'a>));
+        } else {
+            self.free_map.insert(stmt, dead);
         }
     }
 
-    /// build_fn_call creates a call site for a function.
-    /// This includes dealing with things like saving regs and propagating the returned value.
-    fn build_fn_call(
-        &mut self,
-        dst: &Symbol,
-        fn_name: String,
-        args: &[Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
-
-    fn build_fn_pointer(&mut self, dst: &Symbol, fn_name: String);
-
-    /// Move a returned value into `dst`
-    fn move_return_value(&mut self, dst: &Symbol, ret_layout: &InLayout<'a>);
-
-    /// build_num_abs stores the absolute value of src into dst.
-    fn build_num_int_cast(
-        &mut self,
-        dst: &Symbol,
-        src: &Symbol,
-        source: IntWidth,
-        target: IntWidth,
-    );
-
-    /// build_num_abs stores the absolute value of src into dst.
-    fn build_num_abs(&mut self, dst: &Symbol, src: &Symbol, layout: &InLayout<'a>);
-
-    /// build_num_add stores the sum of src1 and src2 into dst.
-    fn build_num_add(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
-
-    /// build_num_add_saturated stores the sum of src1 and src2 into dst.
-    fn build_num_add_saturated(
-        &mut self,
-        dst: Symbol,
-        src1: Symbol,
-        src2: Symbol,
-        layout: InLayout<'a>,
-    );
-
-    /// build_num_add_checked stores the sum of src1 and src2 into dst.
-    fn build_num_add_checked(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        num_layout: &InLayout<'a>,
-        return_layout: &InLayout<'a>,
-    );
-
-    /// build_num_sub_checked stores the sum of src1 and src2 into dst.
-    fn build_num_sub_checked(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        num_layout: &InLayout<'a>,
-        return_layout: &InLayout<'a>,
-    );
-
-    /// build_num_mul stores `src1 * src2` into dst.
-    fn build_num_mul(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
-
-    /// build_num_mul_wrap stores `src1 * src2` into dst.
-    fn build_num_mul_wrap(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        layout: &InLayout<'a>,
-    );
-
-    /// build_num_mul stores `src1 / src2` into dst.
-    fn build_num_div(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
-
-    /// build_num_mul stores `src1 % src2` into dst.
-    fn build_num_rem(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
-
-    /// build_num_neg stores the negated value of src into dst.
-    fn build_num_neg(&mut self, dst: &Symbol, src: &Symbol, layout: &InLayout<'a>);
+    fn record_branch_free(&mut self, stmt: &'a Stmt<'a>, dead: std::vec::Vec<Symbol>) {
+        if dead.is_empty() {
+            self.branch_free_map.remove(&(stmt as *const Stmt<'a>));
+        } else {
+            self.branch_free_map.insert(stmt, dead);
+        }
+    }
+}
 
-    /// build_num_sub stores the `src1 - src2` difference into dst.
-    fn build_num_sub(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, layout: &InLayout<'a>);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roc_mono::borrow::Ownership;
+    use roc_mono::ir::CrashTag;
+    use roc_region::all::Region;
 
-    /// build_num_sub_wrap stores the `src1 - src2` difference into dst.
-    fn build_num_sub_wrap(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        layout: &InLayout<'a>,
-    );
-
-    /// stores the `src1 & src2` into dst.
-    fn build_int_bitwise_and(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
-
-    /// stores the `src1 | src2` into dst.
-    fn build_int_bitwise_or(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
-
-    /// stores the `src1 ^ src2` into dst.
-    fn build_int_bitwise_xor(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
-
-    /// stores the `Num.shiftLeftBy src1 src2` into dst.
-    fn build_int_shift_left(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
-
-    /// stores the `Num.shiftRightBy src1 src2` into dst.
-    fn build_int_shift_right(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
-
-    /// stores the `Num.shiftRightZfBy src1 src2` into dst.
-    fn build_int_shift_right_zero_fill(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        int_width: IntWidth,
-    );
+    fn unit<'a>(arena: &'a Bump, sym: Symbol, following: &'a Stmt<'a>) -> &'a Stmt<'a> {
+        arena.alloc(Stmt::Let(sym, Expr::Struct(&[]), Layout::UNIT, following))
+    }
 
-    /// build_eq stores the result of `src1 == src2` into dst.
-    fn build_eq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>);
+    fn switch<'a>(
+        arena: &'a Bump,
+        cond_symbol: Symbol,
+        branch: Stmt<'a>,
+        default: Stmt<'a>,
+    ) -> &'a Stmt<'a> {
+        arena.alloc(Stmt::Switch {
+            cond_symbol,
+            cond_layout: Layout::U64,
+            branches: arena.alloc([(0, BranchInfo::None, branch)]),
+            default_branch: (BranchInfo::None, arena.alloc(default)),
+            ret_layout: Layout::UNIT,
+        })
+    }
 
-    /// build_neq stores the result of `src1 != src2` into dst.
-    fn build_neq(&mut self, dst: &Symbol, src1: &Symbol, src2: &Symbol, arg_layout: &InLayout<'a>);
+    fn branch_frees<'a>(liveness: &Liveness<'a>, stmt: &'a Stmt<'a>) -> Option<MutSet<Symbol>> {
+        liveness
+            .branch_free_map
+            .get(&(stmt as *const Stmt<'a>))
+            .map(|syms| syms.iter().copied().collect())
+    }
 
-    /// build_not stores the result of `!src` into dst.
-    fn build_not(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+    #[test]
+    fn opt_level_decides_the_debug_checks() {
+        let arena = Bump::new();
+
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .opt_level(OptLevel::Development)
+            .build();
+        assert!(env.overflow_checks && env.bounds_checks && env.poison_stack);
+        assert!(env.report_expects);
+        // The stack limit is shared by all threads, so only hosts that ask for stack checks get them.
+        assert_eq!(env.stack_check_size, None);
+
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .opt_level(OptLevel::Normal)
+            .build();
+        assert!(env.overflow_checks && !env.report_expects && env.expect_checks);
+        assert_eq!(env.stack_check_size, None);
+
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .opt_level(OptLevel::Development)
+            .stack_check_size(Some(6 << 20))
+            .build();
+        assert_eq!(env.stack_check_size, Some(6 << 20));
+
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .opt_level(OptLevel::Optimize)
+            .overflow_checks(true)
+            .build();
+        assert!(env.overflow_checks && !env.bounds_checks && !env.poison_stack);
+        assert!(!env.expect_checks);
+        assert_eq!(env.proc_alignment, 16);
+
+        let env = EnvBuilder::new(&arena, ModuleId::ATTR)
+            .opt_level(OptLevel::Size)
+            .build();
+        assert_eq!(env.proc_alignment, 4);
+    }
 
-    fn build_num_cmp(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        arg_layout: &InLayout<'a>,
-    );
-
-    /// build_num_lt stores the result of `src1 < src2` into dst.
-    fn build_num_lt(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        arg_layout: &InLayout<'a>,
-    );
-
-    /// build_num_gt stores the result of `src1 > src2` into dst.
-    fn build_num_gt(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        arg_layout: &InLayout<'a>,
-    );
-
-    /// build_num_to_frac convert Number to Frac
-    fn build_num_to_frac(
-        &mut self,
-        dst: &Symbol,
-        src: &Symbol,
-        arg_layout: &InLayout<'a>,
-        ret_layout: &InLayout<'a>,
-    );
+    #[test]
+    fn backend_error_lists_every_unsupported_construct() {
+        let error = BackendError::Unsupported(vec![
+            UnsupportedConstruct {
+                proc_name: "#UserApp_main_1".to_string(),
+                construct: "List.map2".to_string(),
+            },
+            UnsupportedConstruct {
+                proc_name: "#UserApp_helper_2".to_string(),
+                construct: "expect-fx".to_string(),
+            },
+        ]);
 
-    /// build_num_is_nan check is a Frac is NaN
-    fn build_num_is_nan(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+        assert_eq!(
+            error.to_string(),
+            "This program needs the LLVM backend, the dev backend does not support:\n    \
+             List.map2 (in #UserApp_main_1)\n    expect-fx (in #UserApp_helper_2)\n"
+        );
+    }
 
-    /// build_num_is_infinite check is a Frac is infinite
-    fn build_num_is_infinite(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+    #[test]
+    fn report_summary_lists_the_slowest_procs() {
+        let proc = |name: &str, code_size, spills, millis| ProcReport {
+            name: name.to_string(),
+            module: "#UserApp".to_string(),
+            builtin: false,
+            code_size,
+            relocations: 2,
+            stats: ProcStats {
+                spills,
+                ..Default::default()
+            },
+            build_time: Duration::from_millis(millis),
+            phase_times: PhaseTimes::default(),
+        };
+        let report = BuildReport {
+            procs: vec![proc("fast", 10, 0, 1), proc("slow", 30, 4, 5)],
+            ..Default::default()
+        };
+        assert_eq!(report.code_size(), 40);
+        assert_eq!(report.relocations(), 4);
+        assert_eq!(report.spills(), 4);
+
+        let summary = report.summary(1);
+        let lines: std::vec::Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            lines[0],
+            "2 procs, 40 bytes of code, 4 relocations, 4 spills, 6.000 ms"
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with("slow"));
+    }
 
-    /// build_num_is_finite check is a Frac is finite
-    fn build_num_is_finite(&mut self, dst: &Symbol, src: &Symbol, arg_layout: &InLayout<'a>);
+    #[test]
+    fn code_size_report_splits_the_code_by_module() {
+        let proc = |module: &str, builtin, code_size| ProcReport {
+            name: "proc".to_string(),
+            module: module.to_string(),
+            builtin,
+            code_size,
+            relocations: 0,
+            stats: ProcStats::default(),
+            build_time: Duration::ZERO,
+            phase_times: PhaseTimes::default(),
+        };
+        let report = BuildReport {
+            procs: vec![
+                proc("List", true, 30),
+                proc("#UserApp", false, 50),
+                proc("Num", true, 10),
+                proc("List", true, 10),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            report.code_size_by_module(),
+            vec![("#UserApp", 50), ("List", 40), ("Num", 10)]
+        );
+        assert_eq!(report.builtin_code_size(), 50);
 
-    /// build_num_lte stores the result of `src1 <= src2` into dst.
-    fn build_num_lte(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        arg_layout: &InLayout<'a>,
-    );
-
-    /// build_num_gte stores the result of `src1 >= src2` into dst.
-    fn build_num_gte(
-        &mut self,
-        dst: &Symbol,
-        src1: &Symbol,
-        src2: &Symbol,
-        arg_layout: &InLayout<'a>,
-    );
+        let text = report.code_size_report();
+        let lines: std::vec::Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[0],
+            "100 bytes of code, 50 bytes (50.0%) app, 50 bytes (50.0%) builtins"
+        );
+        assert_eq!(lines[2], "          40 bytes  40.0%   List");
+        assert_eq!(lines.len(), 4);
+    }
 
-    /// build_sqrt stores the result of `sqrt(src)` into dst.
-    fn build_num_sqrt(&mut self, dst: Symbol, src: Symbol, float_width: FloatWidth);
+    #[test]
+    fn report_sums_the_phase_times_of_the_procs() {
+        let proc = |emission_millis| ProcReport {
+            name: "proc".to_string(),
+            module: "#UserApp".to_string(),
+            builtin: false,
+            code_size: 10,
+            relocations: 0,
+            stats: ProcStats::default(),
+            build_time: Duration::from_millis(5),
+            phase_times: PhaseTimes {
+                scan_ast: Duration::from_millis(1),
+                emission: Duration::from_millis(emission_millis),
+                ..Default::default()
+            },
+        };
+        let report = BuildReport {
+            procs: vec![proc(2), proc(3)],
+            ..Default::default()
+        };
+        assert_eq!(
+            report.phase_times(),
+            PhaseTimes {
+                scan_ast: Duration::from_millis(2),
+                register_allocation: Duration::ZERO,
+                emission: Duration::from_millis(5),
+                object_writing: Duration::ZERO,
+            }
+        );
+        assert_eq!(
+            report.summary(0).lines().nth(1),
+            Some("    scan_ast 2.000 ms, register allocation 0.000 ms, emission 5.000 ms, object writing 0.000 ms")
+        );
+    }
 
-    /// build_list_len returns the length of a list.
-    fn build_list_len(&mut self, dst: &Symbol, list: &Symbol);
+    #[test]
+    fn report_summary_includes_the_memory_stats() {
+        let report = BuildReport {
+            memory: MemoryStats {
+                arena_bytes: 4096,
+                code_bytes: 120,
+                data_bytes: 8,
+                largest_proc_buffer: 64,
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            report.summary(0).lines().nth(1),
+            Some("    4096 bytes of arena, 120 bytes of code, 8 bytes of data, largest proc 64 bytes")
+        );
+    }
 
-    /// generate a call to a higher-order lowlevel
-    fn build_higher_order_lowlevel(
-        &mut self,
-        dst: &Symbol,
-        holl: &HigherOrderLowLevel<'a>,
-        ret_layout: InLayout<'a>,
-    );
+    #[test]
+    fn symbols_die_per_branch() {
+        let arena = Bump::new();
+        let (x, y, cond) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3);
 
-    fn build_indirect_inc(&mut self, layout: InLayout<'a>) -> Symbol;
-    fn build_indirect_dec(&mut self, layout: InLayout<'a>) -> Symbol;
+        let body = switch(&arena, cond, Stmt::Ret(x), Stmt::Ret(y));
+        let root = unit(&arena, x, unit(&arena, y, body));
+        let liveness = Liveness::scan_ast(root);
 
-    /// build_list_with_capacity creates and returns a list with the given capacity.
-    fn build_list_with_capacity(
-        &mut self,
-        dst: &Symbol,
-        capacity: Symbol,
-        capacity_layout: InLayout<'a>,
-        elem_layout: InLayout<'a>,
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_reserve enlarges a list to at least accommodate the given capacity.
-    fn build_list_reserve(
-        &mut self,
-        dst: &Symbol,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_append_unsafe returns a new list with a given element appended.
-    fn build_list_append_unsafe(
-        &mut self,
-        dst: &Symbol,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_get_unsafe loads the element from the list at the index.
-    fn build_list_get_unsafe(
-        &mut self,
-        dst: &Symbol,
-        list: &Symbol,
-        index: &Symbol,
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_replace_unsafe returns the old element and new list with the list having the new element inserted.
-    fn build_list_replace_unsafe(
-        &mut self,
-        dst: &Symbol,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_concat returns a new list containing the two argument lists concatenated.
-    fn build_list_concat(
-        &mut self,
-        dst: &Symbol,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        elem_layout: InLayout<'a>,
-        ret_layout: &InLayout<'a>,
-    );
-
-    /// build_list_prepend returns a new list with a given element prepended.
-    fn build_list_prepend(
-        &mut self,
-        dst: &Symbol,
-        args: &'a [Symbol],
-        arg_layouts: &[InLayout<'a>],
-        ret_layout: &InLayout<'a>,
-    );
+        let Stmt::Switch { branches, default_branch, .. } = body else {
+            unreachable!()
+        };
+        assert_eq!(
+            branch_frees(&liveness, &branches[0].2),
+            Some([y, cond].into_iter().collect())
+        );
+        assert_eq!(
+            branch_frees(&liveness, default_branch.1),
+            Some([x, cond].into_iter().collect())
+        );
+        assert_eq!(
+            liveness.free_map.get(&(&branches[0].2 as *const Stmt)),
+            Some(&vec![x])
+        );
+    }
 
-    /// build_refcount_getptr loads the pointer to the reference count of src into dst.
-    fn build_ptr_cast(&mut self, dst: &Symbol, src: &Symbol);
+    #[test]
+    fn expect_lookups_die_at_the_expect() {
+        let arena = Bump::new();
+        let (x, cond, lookup) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3);
+
+        let expect = arena.alloc(Stmt::Expect {
+            condition: cond,
+            region: Region::zero(),
+            lookups: arena.alloc([lookup]),
+            variables: &[],
+            remainder: arena.alloc(Stmt::Ret(x)),
+        });
+        let liveness = Liveness::scan_ast(expect);
+        assert_eq!(
+            liveness.free_map.get(&(expect as *const Stmt)),
+            Some(&vec![cond, lookup])
+        );
+    }
 
-    fn build_ptr_store(
-        &mut self,
+    fn num_lt<'a>(
+        arena: &'a Bump,
         sym: Symbol,
-        ptr: Symbol,
-        value: Symbol,
-        element_layout: InLayout<'a>,
-    );
-
-    fn build_ptr_load(&mut self, sym: Symbol, ptr: Symbol, element_layout: InLayout<'a>);
-
-    fn build_ptr_clear_tag_id(&mut self, sym: Symbol, ptr: Symbol);
-
-    fn build_alloca(&mut self, sym: Symbol, value: Symbol, element_layout: InLayout<'a>);
-
-    /// literal_map gets the map from symbol to literal and layout, used for lazy loading and literal folding.
-    fn literal_map(&mut self) -> &mut MutMap<Symbol, (*const Literal<'a>, *const InLayout<'a>)>;
-
-    fn load_literal_symbols(&mut self, syms: &[Symbol]) {
-        if self.env().lazy_literals {
-            for sym in syms {
-                if let Some((lit, layout)) = self.literal_map().remove(sym) {
-                    // This operation is always safe but complicates lifetimes.
-                    // The map is reset when building a procedure and then used for that single procedure.
-                    // Since the lifetime is shorter than the entire backend, we need to use a pointer.
-                    let (lit, layout) = unsafe { (*lit, *layout) };
-                    self.load_literal(sym, &layout, &lit);
-                }
-            }
-        }
+        args: [Symbol; 2],
+        following: &'a Stmt<'a>,
+    ) -> &'a Stmt<'a> {
+        let call = roc_mono::ir::Call {
+            call_type: CallType::LowLevel {
+                op: LowLevel::NumLt,
+                update_mode: roc_mono::ir::UpdateModeId::BACKEND_DUMMY,
+            },
+            arguments: arena.alloc(args),
+        };
+        arena.alloc(Stmt::Let(sym, Expr::Call(call), Layout::BOOL, following))
     }
 
-    /// load_literal sets a symbol to be equal to a literal.
-    fn load_literal(&mut self, sym: &Symbol, layout: &InLayout<'a>, lit: &Literal<'a>);
+    #[test]
+    fn unused_let_dies_where_it_is_defined() {
+        let arena = Bump::new();
+        let (x, y, unused) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3);
 
-    fn load_literal_i64(&mut self, sym: &Symbol, value: i64) {
-        let literal = Literal::Int((value as i128).to_ne_bytes());
+        let dead = num_lt(&arena, unused, [x, y], arena.alloc(Stmt::Ret(x)));
+        let liveness = Liveness::scan_ast(dead);
+        assert_eq!(
+            liveness.free_map.get(&(dead as *const Stmt)),
+            Some(&vec![unused, y])
+        );
 
-        self.load_literal(sym, &Layout::I64, &literal)
+        let Stmt::Let(_, expr, ..) = dead else {
+            unreachable!()
+        };
+        assert!(is_pure(expr));
+        assert!(!is_pure(&Expr::RuntimeErrorFunction("oops")));
     }
 
-    fn load_literal_i32(&mut self, sym: &Symbol, value: i32) {
-        let literal = Literal::Int((value as i128).to_ne_bytes());
+    #[test]
+    fn literal_low_levels_fold() {
+        let int = |lit: Option<Literal>| match lit {
+            Some(Literal::Int(bytes)) => Some(i128::from_ne_bytes(bytes)),
+            _ => None,
+        };
+        let fold = |op, width, args: &[i128]| int(fold_low_level(op, Some(width), args));
 
-        self.load_literal(sym, &Layout::I32, &literal)
+        assert_eq!(fold(LowLevel::NumAdd, IntWidth::I64, &[2, 3]), Some(5));
+        assert_eq!(fold(LowLevel::NumAdd, IntWidth::U8, &[200, 100]), None);
+        assert_eq!(
+            fold(LowLevel::NumAddWrap, IntWidth::U8, &[200, 100]),
+            Some(44)
+        );
+        assert_eq!(
+            fold(LowLevel::NumSubWrap, IntWidth::I8, &[-128, 1]),
+            Some(127)
+        );
+        assert_eq!(
+            fold(LowLevel::NumMulWrap, IntWidth::U64, &[u64::MAX as i128, 2]),
+            Some(u64::MAX as i128 - 1)
+        );
+        assert_eq!(
+            fold(LowLevel::NumNeg, IntWidth::I64, &[i64::MIN as i128]),
+            None
+        );
+        assert!(matches!(
+            fold_low_level(LowLevel::NumLt, Some(IntWidth::I32), &[-1, 0]),
+            Some(Literal::Bool(true))
+        ));
+        assert!(matches!(
+            fold_low_level(LowLevel::And, None, &[1, 0]),
+            Some(Literal::Bool(false))
+        ));
+        assert!(
+            fold_low_level(LowLevel::NumDivTruncUnchecked, Some(IntWidth::I64), &[1, 0]).is_none()
+        );
     }
 
-    fn load_literal_i16(&mut self, sym: &Symbol, value: i16) {
-        let literal = Literal::Int((value as i128).to_ne_bytes());
-
-        self.load_literal(sym, &Layout::I16, &literal)
+    #[test]
+    fn crashing_branches_are_unlikely() {
+        let arena = Bump::new();
+        let x = Symbol::ARG_1;
+        let crash = || Stmt::Crash(x, CrashTag::User);
+
+        let crashing = unit(&arena, Symbol::ARG_2, arena.alloc(crash()));
+        assert!(is_unlikely_branch(&BranchInfo::None, crashing));
+        assert!(is_unlikely_branch(
+            &BranchInfo::None,
+            switch(&arena, x, crash(), crash())
+        ));
+        assert!(!is_unlikely_branch(
+            &BranchInfo::None,
+            switch(&arena, x, crash(), Stmt::Ret(x))
+        ));
+
+        let shared = BranchInfo::Unique {
+            scrutinee: x,
+            unique: false,
+        };
+        assert!(is_unlikely_branch(&shared, &Stmt::Ret(x)));
+        let unique = BranchInfo::Unique {
+            scrutinee: x,
+            unique: true,
+        };
+        assert!(!is_unlikely_branch(&unique, &Stmt::Ret(x)));
     }
 
-    fn load_literal_i8(&mut self, sym: &Symbol, value: i8) {
-        let literal = Literal::Int((value as i128).to_ne_bytes());
+    #[test]
+    fn refcount_helpers_have_distinct_names() {
+        let names: MutSet<String> = RefcountHelper::all().map(RefcountHelper::name).collect();
+        assert_eq!(names.len(), RefcountHelper::all().count());
+        assert!(names.contains("roc_dev_decref_data_ptr_16"));
+    }
 
-        self.load_literal(sym, &Layout::I8, &literal)
+    #[test]
+    fn only_register_int_arithmetic_is_overflow_checked() {
+        assert!(overflow_checked(&LowLevel::NumAdd, Layout::U8));
+        assert!(overflow_checked(&LowLevel::NumSub, Layout::I64));
+        assert!(overflow_checked(&LowLevel::NumMul, Layout::U32));
+        assert!(!overflow_checked(&LowLevel::NumAddWrap, Layout::I64));
+        assert!(!overflow_checked(&LowLevel::NumAdd, Layout::I128));
+        assert!(!overflow_checked(&LowLevel::NumAdd, Layout::F64));
+        assert!(!overflow_checked(
+            &LowLevel::NumDivTruncUnchecked,
+            Layout::I64
+        ));
     }
 
-    /// create_empty_array creates an empty array with nullptr, zero length, and zero capacity.
-    fn create_empty_array(&mut self, sym: &Symbol);
+    #[test]
+    fn stack_literals_are_hoisted_out_of_loops() {
+        let arena = Bump::new();
+        let (arg, string, int) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3);
+        let id = JoinPointId(Symbol::ARG_4);
+        let jump = || Stmt::Jump(id, arena.alloc([arg]));
+
+        let str_lit = arena.alloc(Stmt::Let(
+            string,
+            Expr::Literal(Literal::Str("a string that does not fit inline")),
+            Layout::STR,
+            arena.alloc(jump()),
+        ));
+        let int_lit = arena.alloc(Stmt::Let(
+            int,
+            Expr::Literal(Literal::Int(1i128.to_ne_bytes())),
+            Layout::I64,
+            arena.alloc(Stmt::Ret(int)),
+        ));
+        let loop_body = switch(&arena, arg, str_lit.clone(), int_lit.clone());
+        let proc_body = arena.alloc(Stmt::Join {
+            id,
+            parameters: &[],
+            body: loop_body,
+            remainder: arena.alloc(jump()),
+        });
+
+        let (hoisted_body, hoisted) = hoist_loop_invariants(&arena, proc_body).unwrap();
+        assert_eq!(hoisted, vec![string]);
+        let Stmt::Let(sym, _, _, Stmt::Join { body, .. }) = hoisted_body else {
+            panic!("expected the literal in front of the loop, got {:?}", hoisted_body)
+        };
+        assert_eq!(*sym, string);
+        let Stmt::Switch {
+            branches,
+            default_branch,
+            ..
+        } = body
+        else {
+            unreachable!()
+        };
+        assert!(matches!(branches[0].2, Stmt::Jump(..)));
+        assert_eq!(default_branch.1, int_lit);
 
-    /// create_array creates an array filling it with the specified objects.
-    fn create_array(
-        &mut self,
-        sym: &Symbol,
-        elem_layout: &InLayout<'a>,
-        elems: &'a [ListLiteralElement<'a>],
-    );
+        // Procs that do not start with a loop are left alone.
+        assert!(hoist_loop_invariants(&arena, loop_body).is_none());
+    }
 
-    /// create_struct creates a struct with the elements specified loaded into it as data.
-    fn create_struct(&mut self, sym: &Symbol, layout: &InLayout<'a>, fields: &'a [Symbol]);
+    #[test]
+    fn inline_int_ops_need_register_operands() {
+        let arena = Bump::new();
+        let args: &[Symbol] = arena.alloc([Symbol::ARG_1, Symbol::ARG_2]);
+        let call = |op| {
+            Expr::Call(roc_mono::ir::Call {
+                call_type: CallType::LowLevel {
+                    op,
+                    update_mode: roc_mono::ir::UpdateModeId::BACKEND_DUMMY,
+                },
+                arguments: args,
+            })
+        };
 
-    /// load_struct_at_index loads into `sym` the value at `index` in `structure`.
-    fn load_struct_at_index(
-        &mut self,
-        sym: &Symbol,
-        structure: &Symbol,
-        index: u64,
-        field_layouts: &'a [InLayout<'a>],
-    );
-
-    /// load_union_at_index loads into `sym` the value at `index` for `tag_id`.
-    fn load_union_at_index(
-        &mut self,
-        sym: &Symbol,
-        structure: &Symbol,
-        tag_id: TagIdIntType,
-        index: u64,
-        union_layout: &UnionLayout<'a>,
-    );
-
-    /// load_union_at_index loads into `sym` the value at `index` for `tag_id`.
-    fn load_union_field_ptr_at_index(
-        &mut self,
-        sym: &Symbol,
-        structure: &Symbol,
-        tag_id: TagIdIntType,
-        index: u64,
-        union_layout: &UnionLayout<'a>,
-    );
-
-    /// get_tag_id loads the tag id from a the union.
-    fn get_tag_id(&mut self, sym: &Symbol, structure: &Symbol, union_layout: &UnionLayout<'a>);
-
-    /// tag sets the tag for a union.
-    fn tag(
-        &mut self,
-        sym: &Symbol,
-        args: &'a [Symbol],
-        tag_layout: &UnionLayout<'a>,
-        tag_id: TagIdIntType,
-        reuse: Option<Symbol>,
-    );
-
-    /// load a value from a pointer
-    fn expr_unbox(&mut self, sym: Symbol, ptr: Symbol, element_layout: InLayout<'a>);
-
-    /// store a refcounted value on the heap
-    fn expr_box(
-        &mut self,
-        sym: Symbol,
-        value: Symbol,
-        element_layout: InLayout<'a>,
-        reuse: Option<Symbol>,
-    );
-
-    /// return_symbol moves a symbol to the correct return location for the backend and adds a jump to the end of the function.
-    fn return_symbol(&mut self, sym: &Symbol, layout: &InLayout<'a>);
-
-    /// free_symbols will free all symbols for the given statement.
-    fn free_symbols(&mut self, stmt: &Stmt<'a>) {
-        if let Some(syms) = self.free_map().remove(&(stmt as *const Stmt<'a>)) {
-            for sym in syms {
-                // println!("Freeing symbol: {:?}", sym);
-                self.free_symbol(&sym);
-            }
-        }
+        assert_eq!(register_operands(&call(LowLevel::NumAddWrap)), Some(args));
+        assert_eq!(register_operands(&call(LowLevel::NumPowInt)), None);
+        assert_eq!(register_operands(&Expr::Struct(args)), None);
     }
 
-    /// free_symbol frees any registers or stack space used to hold a symbol.
-    fn free_symbol(&mut self, sym: &Symbol);
-
-    /// last_seen_map gets the map from symbol to when it is last seen in the function.
-    fn last_seen_map(&mut self) -> &mut MutMap<Symbol, *const Stmt<'a>>;
-
-    /// set_layout_map sets the layout for a specific symbol.
-    fn set_layout_map(&mut self, sym: Symbol, layout: &InLayout<'a>) {
-        if let Some(old_layout) = self.layout_map().insert(sym, *layout) {
-            // Layout map already contains the symbol. We should never need to overwrite.
-            // If the layout is not the same, that is a bug.
-            if &old_layout != layout {
-                internal_error!(
-                    "Overwriting layout for symbol, {:?}: got {:?}, want {:?}",
-                    sym,
-                    layout,
-                    old_layout
-                )
-            }
-        }
+    #[test]
+    fn literal_switch_takes_one_branch() {
+        let arena = Bump::new();
+        let cond = Symbol::ARG_1;
+        let Stmt::Switch {
+            branches,
+            default_branch,
+            ..
+        } = switch(&arena, cond, Stmt::Ret(Symbol::ARG_2), Stmt::Ret(Symbol::ARG_3))
+        else {
+            unreachable!()
+        };
+
+        let taken = |value| match taken_branch(value, branches, default_branch.1) {
+            Some(Stmt::Ret(sym)) => Some(*sym),
+            _ => None,
+        };
+        assert_eq!(taken(0), Some(Symbol::ARG_2));
+        assert_eq!(taken(1), Some(Symbol::ARG_3));
+        assert_eq!(taken(-1), None);
     }
 
-    /// layout_map gets the map from symbol to layout.
-    fn layout_map(&mut self) -> &mut MutMap<Symbol, InLayout<'a>>;
+    #[test]
+    fn compare_only_used_by_switch_is_fused() {
+        let arena = Bump::new();
+        let (x, y, cond, other) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3, Symbol::ARG_4);
 
-    fn create_free_map(&mut self) {
-        let mut free_map = MutMap::default();
-        let arena = self.env().arena;
-        for (sym, stmt) in self.last_seen_map() {
-            let vals = free_map
-                .entry(*stmt)
-                .or_insert_with(|| bumpalo::vec![in arena]);
-            vals.push(*sym);
-        }
-        self.set_free_map(free_map);
+        let fused = num_lt(
+            &arena,
+            cond,
+            [x, y],
+            switch(&arena, cond, Stmt::Ret(x), Stmt::Ret(y)),
+        );
+        let liveness = Liveness::scan_ast(fused);
+        assert_eq!(liveness.fused_compares, [cond].into_iter().collect());
+
+        // The bool is needed again in a branch, so it must be stored.
+        let used_again = num_lt(
+            &arena,
+            cond,
+            [x, y],
+            switch(&arena, cond, Stmt::Ret(cond), Stmt::Ret(y)),
+        );
+        assert!(Liveness::scan_ast(used_again).fused_compares.is_empty());
+
+        // The switch is on a different symbol.
+        let other_switch = num_lt(
+            &arena,
+            cond,
+            [x, y],
+            switch(&arena, other, Stmt::Ret(cond), Stmt::Ret(y)),
+        );
+        assert!(Liveness::scan_ast(other_switch).fused_compares.is_empty());
     }
 
-    /// free_map gets the map statement to the symbols that are free after they run.
-    fn free_map(&mut self) -> &mut MutMap<*const Stmt<'a>, Vec<'a, Symbol>>;
+    #[test]
+    fn joinpoint_keeps_body_symbols_alive() {
+        let arena = Bump::new();
+        let (x, param, cond, id) = (Symbol::ARG_1, Symbol::ARG_2, Symbol::ARG_3, Symbol::ARG_4);
 
-    /// set_free_map sets the free map to the given map.
-    fn set_free_map(&mut self, map: MutMap<*const Stmt<'a>, Vec<'a, Symbol>>);
-
-    /// scan_ast runs through the ast and fill the last seen map.
-    /// This must iterate through the ast in the same way that build_stmt does. i.e. then before else.
-    fn scan_ast(&mut self, stmt: &'a Stmt<'a>) {
-        *self.last_seen_map() = LastSeenMap::scan_ast(stmt);
+        let remainder = switch(
+            &arena,
+            cond,
+            Stmt::Ret(x),
+            Stmt::Jump(JoinPointId(id), arena.alloc([cond])),
+        );
+        let join: &Stmt = arena.alloc(Stmt::Join {
+            id: JoinPointId(id),
+            parameters: arena.alloc([Param {
+                symbol: param,
+                ownership: Ownership::Owned,
+                layout: Layout::U64,
+            }]),
+            body: arena.alloc(Stmt::Ret(x)),
+            remainder,
+        });
+        let root = unit(&arena, x, join);
+        let liveness = Liveness::scan_ast(root);
+
+        // x is used by the body, so it is neither freed when entering the jumping branch
+        // nor after its use in the other branch. It is freed with the joinpoint.
+        let Stmt::Switch {
+            branches,
+            default_branch,
+            ..
+        } = remainder
+        else {
+            unreachable!()
+        };
+        assert_eq!(branch_frees(&liveness, default_branch.1), None);
+        assert_eq!(
+            liveness.free_map.get(&(&branches[0].2 as *const Stmt)),
+            None
+        );
+        let frees: MutSet<Symbol> = liveness.free_map[&(join as *const Stmt)]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(frees, [x, param, id].into_iter().collect());
     }
 }
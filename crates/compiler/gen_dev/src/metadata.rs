@@ -0,0 +1,182 @@
+//! Compact metadata about the procs in a dev backend object.
+//! This is written into its own section so that the surgical linker can find
+//! exposed procs, their frame sizes, and the call sites that need host symbols
+//! without re-parsing the symbol and relocation tables of the object.
+
+/// Name of the metadata section in ELF objects.
+pub const ELF_SECTION_NAME: &str = ".roc_dev_metadata";
+
+/// Segment and section name of the metadata section in MachO objects.
+pub const MACHO_SEGMENT_NAME: &str = "__ROC";
+pub const MACHO_SECTION_NAME: &str = "__dev_metadata";
+
+const MAGIC: &[u8; 8] = b"ROCDEVMD";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcMetadata {
+    pub name: String,
+    /// Whether the proc is exposed to the host.
+    pub exposed: bool,
+    /// Offset of the proc within its text section.
+    pub offset: u64,
+    /// Size of the proc's machine code in bytes.
+    pub code_size: u64,
+    /// Size of the stack frame setup by the proc's prologue.
+    pub stack_size: u32,
+}
+
+/// A call site in a proc that must be resolved to a function provided by the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostRelocation {
+    /// Index of the calling proc in `ModuleMetadata::procs`.
+    pub proc_index: u32,
+    /// Offset of the relocation from the start of the calling proc.
+    pub offset: u64,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleMetadata {
+    pub procs: std::vec::Vec<ProcMetadata>,
+    pub host_relocations: std::vec::Vec<HostRelocation>,
+}
+
+impl ModuleMetadata {
+    /// Serializes the metadata into the little endian format stored in the metadata section.
+    pub fn to_bytes(&self) -> std::vec::Vec<u8> {
+        let mut out = std::vec::Vec::new();
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+
+        out.extend((self.procs.len() as u32).to_le_bytes());
+        for proc in self.procs.iter() {
+            write_str(&mut out, &proc.name);
+            out.push(proc.exposed as u8);
+            out.extend(proc.offset.to_le_bytes());
+            out.extend(proc.code_size.to_le_bytes());
+            out.extend(proc.stack_size.to_le_bytes());
+        }
+
+        out.extend((self.host_relocations.len() as u32).to_le_bytes());
+        for reloc in self.host_relocations.iter() {
+            out.extend(reloc.proc_index.to_le_bytes());
+            out.extend(reloc.offset.to_le_bytes());
+            write_str(&mut out, &reloc.name);
+        }
+        out
+    }
+
+    /// Parses the contents of a metadata section.
+    /// Returns None if the data is not valid metadata or is from a different version.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+            return None;
+        }
+
+        let proc_count = reader.u32()?;
+        let mut procs = std::vec::Vec::with_capacity(proc_count as usize);
+        for _ in 0..proc_count {
+            procs.push(ProcMetadata {
+                name: reader.str()?,
+                exposed: reader.take(1)?[0] != 0,
+                offset: reader.u64()?,
+                code_size: reader.u64()?,
+                stack_size: reader.u32()?,
+            });
+        }
+
+        let reloc_count = reader.u32()?;
+        let mut host_relocations = std::vec::Vec::with_capacity(reloc_count as usize);
+        for _ in 0..reloc_count {
+            host_relocations.push(HostRelocation {
+                proc_index: reader.u32()?,
+                offset: reader.u64()?,
+                name: reader.str()?,
+            });
+        }
+
+        if !reader.bytes.is_empty() {
+            return None;
+        }
+        Some(ModuleMetadata {
+            procs,
+            host_relocations,
+        })
+    }
+}
+
+fn write_str(out: &mut std::vec::Vec<u8>, s: &str) {
+    out.extend((s.len() as u32).to_le_bytes());
+    out.extend(s.as_bytes());
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Option<&'b [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let metadata = ModuleMetadata {
+            procs: vec![
+                ProcMetadata {
+                    name: "roc__main_1_exposed".into(),
+                    exposed: true,
+                    offset: 0,
+                    code_size: 42,
+                    stack_size: 32,
+                },
+                ProcMetadata {
+                    name: "#UserApp_helper_1".into(),
+                    exposed: false,
+                    offset: 16,
+                    code_size: 7,
+                    stack_size: 0,
+                },
+            ],
+            host_relocations: vec![HostRelocation {
+                proc_index: 0,
+                offset: 12,
+                name: "roc_alloc".into(),
+            }],
+        };
+        let bytes = metadata.to_bytes();
+        assert_eq!(ModuleMetadata::from_bytes(&bytes), Some(metadata));
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let bytes = ModuleMetadata::default().to_bytes();
+        assert_eq!(ModuleMetadata::from_bytes(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(ModuleMetadata::from_bytes(b"not metadata"), None);
+    }
+}
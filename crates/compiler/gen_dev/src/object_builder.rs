@@ -1,5 +1,13 @@
+use crate::debug_info::{add_split_debug_info, DebugProc};
 use crate::generic64::{aarch64, new_backend_64bit, x86_64};
-use crate::{Backend, Env, Relocation};
+use crate::inline::inline_tiny_procs;
+use crate::metadata::{self, HostRelocation, ModuleMetadata, ProcMetadata};
+use crate::symbol_map::MappedSymbol;
+use crate::{
+    hot_reload_slot_name, Backend, BackendError, BuildReport, DebugInfo, DevOptLevel, Env,
+    ExportedData, PhaseTimes, ProcCache, ProcReport, RefcountHelper, Relocation,
+    UnsupportedConstruct, MEMEQ_HELPER, STACK_LIMIT_SYMBOL, STACK_OVERFLOW_HELPER,
+};
 use bumpalo::collections::Vec;
 use object::write::{self, SectionId, SymbolId};
 use object::write::{Object, StandardSection, StandardSegment, Symbol, SymbolSection};
@@ -7,14 +15,15 @@ use object::{
     Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SectionKind,
     SymbolFlags, SymbolKind, SymbolScope,
 };
-use roc_collections::all::MutMap;
+use roc_collections::all::{MutMap, MutSet};
 use roc_error_macros::internal_error;
 use roc_module::symbol;
 use roc_module::symbol::Interns;
-use roc_mono::ir::{Call, CallSpecId, Expr, UpdateModeId};
+use roc_mono::ir::{Call, CallSpecId, Expr, Literal, UpdateModeId};
 use roc_mono::ir::{Proc, ProcLayout, Stmt};
 use roc_mono::layout::{LambdaName, Layout, LayoutIds, LayoutInterner, STLayoutInterner};
 use roc_target::TargetInfo;
+use std::time::Instant;
 use target_lexicon::{Architecture as TargetArch, BinaryFormat as TargetBF, Triple};
 
 // This is used by some code below which is currently commented out.
@@ -23,13 +32,29 @@ use target_lexicon::{Architecture as TargetArch, BinaryFormat as TargetBF, Tripl
 
 /// build_module is the high level builder/delegator.
 /// It takes the request to build a module and output the object file for the module.
+/// It also returns what it did for every proc, see `BuildReport`.
+/// Split debug info is dropped, use build_module_with_split_debug to get the `.dwo` object.
+/// Modules that use constructs the dev backend does not support yet fail with a `BackendError`.
 pub fn build_module<'a, 'r>(
     env: &'r Env<'a>,
     interns: &'r mut Interns,
     layout_interner: &'r mut STLayoutInterner<'a>,
     target: &Triple,
     procedures: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
-) -> Object<'a> {
+) -> Result<(Object<'a>, BuildReport<'a>), BackendError> {
+    let (object, _, report) =
+        build_module_with_split_debug(env, interns, layout_interner, target, procedures)?;
+    Ok((object, report))
+}
+
+/// Like build_module, but also returns the `.dwo` object when `env.debug_info` is `DebugInfo::Split`.
+pub fn build_module_with_split_debug<'a, 'r>(
+    env: &'r Env<'a>,
+    interns: &'r mut Interns,
+    layout_interner: &'r mut STLayoutInterner<'a>,
+    target: &Triple,
+    procedures: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
+) -> Result<(Object<'a>, Option<Object<'a>>, BuildReport<'a>), BackendError> {
     match target {
         Triple {
             architecture: TargetArch::X86_64,
@@ -114,31 +139,386 @@ pub fn build_module<'a, 'r>(
                 ),
             )
         }
-        x => unimplemented!("the target, {:?}", x),
+        x => Err(BackendError::UnsupportedTarget(x.to_string())),
+    }
+}
+
+/// The code of a single proc, see build_single_proc.
+#[derive(Debug)]
+pub struct ProcCode<'a> {
+    /// The name of the proc that calls to it refer to.
+    pub name: String,
+    pub code: Vec<'a, u8>,
+    /// The references from `code` to other procs and to functions like roc_alloc, none of them are resolved.
+    pub relocs: Vec<'a, Relocation<'a>>,
+}
+
+/// build_single_proc builds `proc` and the procs it depends on without putting them in an object,
+/// so the REPL can link and run them right away.
+/// The code of `proc` comes first, followed by its dependencies and the helpers they call.
+/// Wrappers like roc_alloc are not generated, the caller provides them. `env.stack_check_size` should be `None`,
+/// since the stack limit and the stack overflow helper only exist in objects.
+pub fn build_single_proc<'a, 'r>(
+    env: &'r Env<'a>,
+    interns: &'r mut Interns,
+    layout_interner: &'r mut STLayoutInterner<'a>,
+    target: &Triple,
+    proc: (ProcLayout<'a>, Proc<'a>),
+    dependencies: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
+) -> Result<Vec<'a, ProcCode<'a>>, BackendError> {
+    match (target.architecture, target.binary_format) {
+        (TargetArch::X86_64, TargetBF::Elf | TargetBF::Macho)
+            if cfg!(feature = "target-x86_64") =>
+        {
+            let backend = new_backend_64bit::<
+                x86_64::X86_64GeneralReg,
+                x86_64::X86_64FloatReg,
+                x86_64::X86_64Assembler,
+                x86_64::X86_64SystemV,
+            >(env, TargetInfo::default_x86_64(), interns, layout_interner);
+            build_procs_code(backend, proc, dependencies)
+        }
+        (TargetArch::Aarch64(_), TargetBF::Elf | TargetBF::Macho)
+            if cfg!(feature = "target-aarch64") =>
+        {
+            let backend =
+                new_backend_64bit::<
+                    aarch64::AArch64GeneralReg,
+                    aarch64::AArch64FloatReg,
+                    aarch64::AArch64Assembler,
+                    aarch64::AArch64Call,
+                >(env, TargetInfo::default_aarch64(), interns, layout_interner);
+            build_procs_code(backend, proc, dependencies)
+        }
+        _ => Err(BackendError::UnsupportedTarget(target.to_string())),
     }
 }
 
-fn generate_wrapper<'a, B: Backend<'a>>(
+fn build_procs_code<'a, B: Backend<'a>>(
+    mut backend: B,
+    proc: (ProcLayout<'a>, Proc<'a>),
+    dependencies: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
+) -> Result<Vec<'a, ProcCode<'a>>, BackendError> {
+    let arena = backend.env().arena;
+    let mut layout_ids = LayoutIds::default();
+    let mut unsupported = std::vec::Vec::new();
+    let mut procs = bumpalo::vec![in arena];
+
+    let user_procs = std::iter::once(proc).chain(
+        dependencies
+            .into_iter()
+            .map(|((_, layout), proc)| (layout, proc)),
+    );
+    for (layout, proc) in user_procs {
+        let name = backend.lambda_name_to_string(
+            proc.name,
+            layout.arguments.iter().copied(),
+            None,
+            layout.result,
+        );
+        procs.push(build_proc_code(
+            &mut backend,
+            &mut layout_ids,
+            &mut unsupported,
+            name,
+            proc,
+        ));
+    }
+
+    for ((sym, layout), proc) in take_helper_procs(&mut backend) {
+        let name = backend.lambda_name_to_string(
+            LambdaName::no_niche(sym),
+            layout.arguments.iter().copied(),
+            None,
+            layout.result,
+        );
+        procs.push(build_proc_code(
+            &mut backend,
+            &mut layout_ids,
+            &mut unsupported,
+            name,
+            proc,
+        ));
+    }
+
+    if unsupported.is_empty() {
+        Ok(procs)
+    } else {
+        Err(BackendError::Unsupported(unsupported))
+    }
+}
+
+fn build_proc_code<'a, B: Backend<'a>>(
+    backend: &mut B,
+    layout_ids: &mut LayoutIds<'a>,
+    unsupported: &mut std::vec::Vec<UnsupportedConstruct>,
+    name: String,
+    proc: Proc<'a>,
+) -> ProcCode<'a> {
+    let (code, relocs, _) = backend.build_proc(proc, layout_ids, false);
+    unsupported.extend(
+        backend
+            .unsupported_constructs_mut()
+            .drain(..)
+            .map(|construct| UnsupportedConstruct {
+                proc_name: name.clone(),
+                construct,
+            }),
+    );
+    ProcCode { name, code, relocs }
+}
+
+/// Generates a wrapper as a weak symbol, so that a definition from the host takes precedence.
+fn generate_weak_wrapper<'a, B: Backend<'a>>(
     backend: &mut B,
     output: &mut Object,
     wrapper_name: String,
     wraps: String,
+) {
+    generate_trampoline(backend, output, wrapper_name, wraps, &[], true)
+}
+
+/// Generates a proc called `name` that jumps to the linked symbol `target`, see `Backend::build_trampoline`.
+/// `arg_order` says which incoming general arg each outgoing one is, so a hook can take its args
+/// in another order than the function it forwards to.
+fn generate_trampoline<'a, B: Backend<'a>>(
+    backend: &mut B,
+    output: &mut Object,
+    name: String,
+    target: String,
+    arg_order: &[usize],
+    weak: bool,
 ) {
     let text_section = output.section_id(StandardSection::Text);
+    let (proc_data, offset) = backend.build_trampoline(arg_order);
     let proc_symbol = Symbol {
-        name: wrapper_name.as_bytes().to_vec(),
+        name: name.as_bytes().to_vec(),
         value: 0,
-        size: 0,
+        size: proc_data.len() as u64,
         kind: SymbolKind::Text,
         scope: SymbolScope::Dynamic,
+        weak,
+        section: SymbolSection::Section(text_section),
+        flags: SymbolFlags::None,
+    };
+    let proc_id = output.add_symbol(proc_symbol);
+    let proc_offset = output.add_symbol_data(
+        proc_id,
+        text_section,
+        proc_data,
+        backend.env().proc_alignment,
+    );
+
+    add_wrapped_call(output, offset + proc_offset, target);
+}
+
+/// Generates the functions that the expect runtime uses to share memory with the parent process.
+/// They are only required on unix systems. The wrappers are weak, so hosts may define them too.
+fn generate_shared_memory_wrappers<'a, B: Backend<'a>>(backend: &mut B, output: &mut Object) {
+    if matches!(output.format(), BinaryFormat::Elf | BinaryFormat::MachO) {
+        generate_weak_wrapper(backend, output, "roc_getppid".into(), "getppid".into());
+        generate_weak_wrapper(backend, output, "roc_mmap".into(), "mmap".into());
+        generate_weak_wrapper(backend, output, "roc_shm_open".into(), "shm_open".into());
+    }
+}
+
+/// Generates roc_alloc as a weak symbol, which calls malloc unless the alignment is larger than malloc guarantees.
+/// The size given to aligned_alloc depends on `Env::asan_allocations`.
+/// Memory from aligned_alloc can be given to free, so roc_dealloc stays a plain wrapper.
+/// realloc only keeps the alignment of malloc, which is the largest alignment of any roc layout.
+fn generate_alloc_wrapper<'a, B: Backend<'a>>(backend: &mut B, output: &mut Object) {
+    let text_section = output.section_id(StandardSection::Text);
+    let (proc_data, aligned_alloc_offset, malloc_offset) = backend.build_wrapped_aligned_alloc();
+    let proc_symbol = Symbol {
+        name: b"roc_alloc".to_vec(),
+        value: 0,
+        size: proc_data.len() as u64,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Dynamic,
+        weak: true,
+        section: SymbolSection::Section(text_section),
+        flags: SymbolFlags::None,
+    };
+    let proc_id = output.add_symbol(proc_symbol);
+    let proc_offset = output.add_symbol_data(
+        proc_id,
+        text_section,
+        proc_data,
+        backend.env().proc_alignment,
+    );
+
+    add_wrapped_call(
+        output,
+        aligned_alloc_offset + proc_offset,
+        "aligned_alloc".into(),
+    );
+    add_wrapped_call(output, malloc_offset + proc_offset, "malloc".into());
+}
+
+/// Generates the refcount helpers, see `RefcountHelper`.
+/// They are called by name from every module, so they are defined before any procs are built.
+fn generate_refcount_helpers<'a, B: Backend<'a>>(backend: &mut B, output: &mut Object) {
+    let text_section = output.section_id(StandardSection::Text);
+    for helper in RefcountHelper::all() {
+        let (proc_data, dealloc_offset) = backend.build_refcount_helper(helper);
+        let proc_symbol = Symbol {
+            name: helper.name().into_bytes(),
+            value: 0,
+            size: proc_data.len() as u64,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Section(text_section),
+            flags: SymbolFlags::None,
+        };
+        let proc_id = output.add_symbol(proc_symbol);
+        let proc_offset = output.add_symbol_data(
+            proc_id,
+            text_section,
+            proc_data,
+            backend.env().proc_alignment,
+        );
+        let Some(dealloc_offset) = dealloc_offset else {
+            continue;
+        };
+
+        // roc_dealloc is either one of the generated wrappers or comes from the host.
+        let sym_id = linked_function_symbol(output, "roc_dealloc", &[]);
+        let reloc = branch_relocation(output, dealloc_offset + proc_offset, sym_id, false);
+        if let Err(e) = output.add_relocation(text_section, reloc) {
+            internal_error!("{:?}", e);
+        }
+    }
+}
+
+/// Generates the helper that compares lists byte for byte, see `MEMEQ_HELPER`.
+/// It does not call anything, so it needs no relocations.
+fn generate_memeq_helper<'a, B: Backend<'a>>(backend: &mut B, output: &mut Object) {
+    let text_section = output.section_id(StandardSection::Text);
+    let proc_data = backend.build_memeq_helper();
+    let proc_symbol = Symbol {
+        name: MEMEQ_HELPER.as_bytes().to_vec(),
+        value: 0,
+        size: proc_data.len() as u64,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Linkage,
         weak: false,
         section: SymbolSection::Section(text_section),
         flags: SymbolFlags::None,
     };
     let proc_id = output.add_symbol(proc_symbol);
-    let (proc_data, offset) = backend.build_wrapped_jmp();
-    let proc_offset = output.add_symbol_data(proc_id, text_section, proc_data, 16);
+    output.add_symbol_data(
+        proc_id,
+        text_section,
+        proc_data,
+        backend.env().proc_alignment,
+    );
+}
 
+/// Defines the data objects of `Env::exported_data`, exported like the procs that are exposed to the host.
+fn generate_exported_data(
+    output: &mut Object,
+    data_section: SectionId,
+    exported_data: &[ExportedData],
+) {
+    if exported_data.is_empty() {
+        return;
+    }
+
+    let rodata_section = output.section_id(StandardSection::ReadOnlyData);
+    for data in exported_data {
+        let section = if data.writable {
+            data_section
+        } else {
+            rodata_section
+        };
+        let data_symbol = Symbol {
+            name: data.name.as_bytes().to_vec(),
+            value: 0,
+            size: data.bytes.len() as u64,
+            kind: SymbolKind::Data,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Section(section),
+            flags: SymbolFlags::None,
+        };
+        let data_id = output.add_symbol(data_symbol);
+        output.add_symbol_data(data_id, section, data.bytes, data.alignment.max(1));
+    }
+}
+
+/// Generates the stack limit and the helper that crashes once a proc passes it, see `Env::stack_check_size`.
+fn generate_stack_check<'a, B: Backend<'a>>(
+    backend: &mut B,
+    output: &mut Object,
+    data_section: SectionId,
+) {
+    let limit_symbol = Symbol {
+        name: STACK_LIMIT_SYMBOL.as_bytes().to_vec(),
+        value: 0,
+        size: 8,
+        kind: SymbolKind::Data,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(data_section),
+        flags: SymbolFlags::None,
+    };
+    let limit_id = output.add_symbol(limit_symbol);
+    output.add_symbol_data(limit_id, data_section, &[0; 8], 8);
+
+    let text_section = output.section_id(StandardSection::Text);
+    let (proc_data, relocs) = backend.build_stack_overflow_helper();
+    let proc_symbol = Symbol {
+        name: STACK_OVERFLOW_HELPER.as_bytes().to_vec(),
+        value: 0,
+        size: proc_data.len() as u64,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(text_section),
+        flags: SymbolFlags::None,
+    };
+    let proc_id = output.add_symbol(proc_symbol);
+    let proc_offset = output.add_symbol_data(
+        proc_id,
+        text_section,
+        &proc_data,
+        backend.env().proc_alignment,
+    );
+
+    // The helper only calls roc_panic, which is either one of the generated wrappers or comes from the host.
+    for reloc in relocs {
+        let relocs = match reloc {
+            Relocation::LinkedFunction {
+                offset,
+                name,
+                addend,
+            } => {
+                let sym_id = linked_function_symbol(output, &name, &[]);
+                let mut reloc = branch_relocation(output, offset + proc_offset, sym_id, true);
+                reloc.addend += addend;
+                vec![reloc]
+            }
+            Relocation::LinkedFunctionPage { offset, name } => {
+                let sym_id = linked_function_symbol(output, &name, &[]);
+                page_relocations(output, offset + proc_offset, sym_id).to_vec()
+            }
+            reloc => internal_error!(
+                "unexpected relocation in the stack overflow helper: {:?}",
+                reloc
+            ),
+        };
+        for reloc in relocs {
+            if let Err(e) = output.add_relocation(text_section, reloc) {
+                internal_error!("{:?}", e);
+            }
+        }
+    }
+}
+
+/// Adds the relocation of a wrapper's jump at offset in the text section to the external function wraps.
+fn add_wrapped_call(output: &mut Object, offset: u64, wraps: String) {
+    let text_section = output.section_id(StandardSection::Text);
     let name = wraps.as_str().as_bytes();
     // If the symbol is an undefined zig builtin, we need to add it here.
     let symbol = Symbol {
@@ -153,14 +533,7 @@ fn generate_wrapper<'a, B: Backend<'a>>(
     };
     output.add_symbol(symbol);
     if let Some(sym_id) = output.symbol_id(name) {
-        let reloc = write::Relocation {
-            offset: offset + proc_offset,
-            size: 32,
-            kind: RelocationKind::PltRelative,
-            encoding: RelocationEncoding::X86Branch,
-            symbol: sym_id,
-            addend: -4,
-        };
+        let reloc = branch_relocation(output, offset, sym_id, false);
 
         match output.add_relocation(text_section, reloc) {
             Ok(obj) => obj,
@@ -171,14 +544,26 @@ fn generate_wrapper<'a, B: Backend<'a>>(
     }
 }
 
-fn build_object<'a, B: Backend<'a>>(
+/// Builds the object for `procedures` with a backend from another crate, see the `unstable-backend-api` feature.
+/// `output` is the empty object for the target of the backend, like build_module sets up for the targets it knows.
+#[cfg(feature = "unstable-backend-api")]
+pub fn build_object_with_backend<'a, B: Backend<'a>>(
     procedures: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
+    backend: B,
+    output: Object<'a>,
+) -> Result<(Object<'a>, Option<Object<'a>>, BuildReport<'a>), BackendError> {
+    build_object(procedures, backend, output)
+}
+
+fn build_object<'a, B: Backend<'a>>(
+    mut procedures: MutMap<(symbol::Symbol, ProcLayout<'a>), Proc<'a>>,
     mut backend: B,
     mut output: Object<'a>,
-) -> Object<'a> {
+) -> Result<(Object<'a>, Option<Object<'a>>, BuildReport<'a>), BackendError> {
     let data_section = output.section_id(StandardSection::Data);
 
     let arena = backend.env().arena;
+    let arena_start = arena.allocated_bytes();
 
     /*
     // Commented out because we couldn't figure out how to get it to work on mac - see https://github.com/roc-lang/roc/pull/1323
@@ -191,48 +576,60 @@ fn build_object<'a, B: Backend<'a>>(
     */
 
     if backend.env().mode.generate_allocators() {
-        generate_wrapper(
-            &mut backend,
-            &mut output,
-            "roc_alloc".into(),
-            "malloc".into(),
-        );
-        generate_wrapper(
+        // All of these are weak, so a host that defines them does not get duplicate symbols.
+        generate_alloc_wrapper(&mut backend, &mut output);
+        generate_weak_wrapper(
             &mut backend,
             &mut output,
             "roc_realloc".into(),
             "realloc".into(),
         );
-        generate_wrapper(
+        generate_weak_wrapper(
             &mut backend,
             &mut output,
             "roc_dealloc".into(),
             "free".into(),
         );
-        generate_wrapper(
+        generate_weak_wrapper(
             &mut backend,
             &mut output,
             "roc_panic".into(),
             "roc_builtins.utils.test_panic".into(),
         );
-        // Extra symbols only required on unix systems.
-        if matches!(output.format(), BinaryFormat::Elf | BinaryFormat::MachO) {
-            generate_wrapper(
-                &mut backend,
-                &mut output,
-                "roc_getppid".into(),
-                "getppid".into(),
-            );
-            generate_wrapper(&mut backend, &mut output, "roc_mmap".into(), "mmap".into());
-            generate_wrapper(
-                &mut backend,
-                &mut output,
-                "roc_shm_open".into(),
-                "shm_open".into(),
-            );
+        generate_shared_memory_wrappers(&mut backend, &mut output);
+    } else {
+        // Hosts are meant to define roc_panic, but crash the program if one does not.
+        generate_weak_wrapper(
+            &mut backend,
+            &mut output,
+            "roc_panic".into(),
+            "abort".into(),
+        );
+        if backend.env().report_expects {
+            generate_shared_memory_wrappers(&mut backend, &mut output);
         }
     }
 
+    generate_refcount_helpers(&mut backend, &mut output);
+    if backend.env().vector_instructions {
+        generate_memeq_helper(&mut backend, &mut output);
+    }
+    if backend.env().stack_check_size.is_some() {
+        generate_stack_check(&mut backend, &mut output, data_section);
+    }
+    generate_exported_data(&mut output, data_section, backend.env().exported_data);
+
+    // Inlined calls skip the hot reload slot, so they would keep running the old code.
+    if backend.env().dev_opt_level == DevOptLevel::One && !backend.env().hot_reload {
+        inline_tiny_procs(arena, &mut procedures);
+    }
+
+    // Only the procs of the module can be built by another backend, not the wrappers and helpers made up here.
+    let fallback_symbols: MutSet<_> = match backend.env().llvm_fallback {
+        true => procedures.keys().map(|(sym, _)| *sym).collect(),
+        false => MutSet::default(),
+    };
+
     // Setup layout_ids for procedure calls.
     let mut layout_ids = LayoutIds::default();
     let mut procs = Vec::with_capacity_in(procedures.len(), arena);
@@ -261,6 +658,7 @@ fn build_object<'a, B: Backend<'a>>(
                 &mut layout_ids,
                 &mut procs,
                 &mut backend,
+                sym,
                 layout,
                 exposed_proc,
                 Exposed::Exposed,
@@ -271,10 +669,37 @@ fn build_object<'a, B: Backend<'a>>(
                 &mut layout_ids,
                 &mut procs,
                 &mut backend,
+                sym,
                 layout,
                 exposed_generic_proc,
                 Exposed::ExposedGeneric,
             );
+
+            let exposed_size_proc = build_exposed_size_proc(&mut backend, &proc);
+            build_proc_symbol(
+                &mut output,
+                &mut layout_ids,
+                &mut procs,
+                &mut backend,
+                sym,
+                layout,
+                exposed_size_proc,
+                Exposed::ExposedSize,
+            );
+
+            if backend.env().packed_exposed_procs {
+                let exposed_packed_proc = build_exposed_packed_proc(&mut backend, &proc);
+                build_proc_symbol(
+                    &mut output,
+                    &mut layout_ids,
+                    &mut procs,
+                    &mut backend,
+                    sym,
+                    layout,
+                    exposed_packed_proc,
+                    Exposed::ExposedPacked,
+                );
+            }
         }
 
         build_proc_symbol(
@@ -282,65 +707,61 @@ fn build_object<'a, B: Backend<'a>>(
             &mut layout_ids,
             &mut procs,
             &mut backend,
+            sym,
             layout,
             proc,
             Exposed::NotExposed,
-        )
+        );
+
+        if backend.env().hot_reload {
+            let (fn_name, _, proc_id, _) = procs.last().unwrap();
+            add_hot_reload_slot(&mut output, data_section, fn_name, *proc_id);
+        }
     }
 
     // Build procedures from user code
     let mut relocations = bumpalo::vec![in arena];
+    let mut metadata = ModuleMetadata::default();
+    let mut report = BuildReport::default();
+    let mut unsupported = std::vec::Vec::new();
+    let mut proc_ids = bumpalo::vec![in arena];
+    // The data of every `Relocation::LocalData` in the module, to point relocations to the same bytes at.
+    let mut local_data = MutMap::default();
     for (fn_name, section_id, proc_id, proc) in procs {
-        build_proc(
+        let cache_key = backend
+            .env()
+            .proc_cache
+            .map(|_| ProcCache::key(backend.env(), &output, &fn_name, &proc, backend.interner()));
+        let can_fall_back = fallback_symbols.contains(&proc.name.name());
+        let built = build_proc(
             &mut output,
             &mut backend,
             &mut relocations,
+            &mut metadata,
+            &mut report,
+            &mut unsupported,
             &mut layout_ids,
             data_section,
+            &mut local_data,
             fn_name,
             section_id,
             proc_id,
             proc,
-        )
+            cache_key,
+            can_fall_back,
+        );
+        // The split debug info below pairs these with `metadata.procs`, which only has the procs built here.
+        if built {
+            proc_ids.push(proc_id);
+        }
     }
 
     // Generate IR for specialized helper procs (refcounting & equality)
-    let empty = bumpalo::collections::Vec::new_in(arena);
-    let mut helper_symbols_and_layouts =
-        std::mem::replace(backend.helper_proc_symbols_mut(), empty);
-
-    let helper_procs = {
-        let (module_id, _interner, interns, helper_proc_gen, caller_procs) =
-            backend.module_interns_helpers_mut();
-
-        let mut owned_caller_procs = bumpalo::collections::Vec::new_in(arena);
-        std::mem::swap(caller_procs, &mut owned_caller_procs);
-
-        let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
-        let mut helper_procs = helper_proc_gen.take_procs();
-
-        for caller_proc in owned_caller_procs {
-            helper_symbols_and_layouts.push((caller_proc.proc_symbol, caller_proc.proc_layout));
-            helper_procs.push(caller_proc.proc);
-        }
-
-        if false {
-            module_id.register_debug_idents(ident_ids);
-
-            for p in &helper_procs {
-                println!("{}", p.to_pretty(_interner, 200, true));
-            }
-        }
-
-        helper_procs
-    };
-
+    let helper_procs = take_helper_procs(&mut backend);
     let mut helper_names_symbols_procs = Vec::with_capacity_in(helper_procs.len(), arena);
 
-    debug_assert_eq!(helper_symbols_and_layouts.len(), helper_procs.len());
-
     // Names and linker data for helpers
-    for ((sym, layout), proc) in helper_symbols_and_layouts.into_iter().zip(helper_procs) {
+    for ((sym, layout), proc) in helper_procs {
         debug_assert_eq!(sym, proc.name.name());
 
         let fn_name = backend.lambda_name_to_string(
@@ -382,17 +803,29 @@ fn build_object<'a, B: Backend<'a>>(
 
     // Build helpers
     for (fn_name, section_id, proc_id, proc) in helper_names_symbols_procs {
-        build_proc(
+        let built = build_proc(
             &mut output,
             &mut backend,
             &mut relocations,
+            &mut metadata,
+            &mut report,
+            &mut unsupported,
             &mut layout_ids,
             data_section,
+            &mut local_data,
             fn_name,
             section_id,
             proc_id,
             proc,
-        )
+            None,
+            false,
+        );
+        debug_assert!(built);
+        proc_ids.push(proc_id);
+    }
+
+    if !unsupported.is_empty() {
+        return Err(BackendError::Unsupported(unsupported));
     }
 
     // Relocations for all procedures (user code & helpers)
@@ -402,9 +835,95 @@ fn build_object<'a, B: Backend<'a>>(
             Err(e) => internal_error!("{:?}", e),
         }
     }
-    output
+
+    add_metadata_section(&mut output, &metadata);
+
+    let split_debug = match backend.env().debug_info {
+        DebugInfo::None => None,
+        DebugInfo::Split { dwo_name } => {
+            let debug_procs: std::vec::Vec<_> = metadata
+                .procs
+                .iter()
+                .zip(proc_ids)
+                .map(|(proc, symbol)| DebugProc {
+                    name: &proc.name,
+                    symbol,
+                    code_size: proc.code_size,
+                })
+                .collect();
+            Some(add_split_debug_info(&mut output, dwo_name, &debug_procs)?)
+        }
+    };
+    report.memory.arena_bytes = arena.allocated_bytes() - arena_start;
+    Ok((output, split_debug, report))
 }
 
+/// Takes the IR of the helper procs (refcounting, equality and the callers of higher order functions)
+/// that the procs built so far asked for, along with their symbols and layouts.
+fn take_helper_procs<'a, B: Backend<'a>>(
+    backend: &mut B,
+) -> Vec<'a, ((symbol::Symbol, ProcLayout<'a>), Proc<'a>)> {
+    let arena = backend.env().arena;
+    let empty = bumpalo::collections::Vec::new_in(arena);
+    let mut helper_symbols_and_layouts =
+        std::mem::replace(backend.helper_proc_symbols_mut(), empty);
+
+    let helper_procs = {
+        let (module_id, _interner, interns, helper_proc_gen, caller_procs) =
+            backend.module_interns_helpers_mut();
+
+        let mut owned_caller_procs = bumpalo::collections::Vec::new_in(arena);
+        std::mem::swap(caller_procs, &mut owned_caller_procs);
+
+        let ident_ids = interns.all_ident_ids.get_mut(&module_id).unwrap();
+        let mut helper_procs = helper_proc_gen.take_procs();
+
+        for caller_proc in owned_caller_procs {
+            helper_symbols_and_layouts.push((caller_proc.proc_symbol, caller_proc.proc_layout));
+            helper_procs.push(caller_proc.proc);
+        }
+
+        if false {
+            module_id.register_debug_idents(ident_ids);
+
+            for p in &helper_procs {
+                println!("{}", p.to_pretty(_interner, 200, true));
+            }
+        }
+
+        helper_procs
+    };
+
+    debug_assert_eq!(helper_symbols_and_layouts.len(), helper_procs.len());
+
+    Vec::from_iter_in(
+        helper_symbols_and_layouts.into_iter().zip(helper_procs),
+        arena,
+    )
+}
+
+/// Writes the module metadata into its own non-loaded section for the surgical linker.
+fn add_metadata_section(output: &mut Object, metadata: &ModuleMetadata) {
+    let (segment, name, kind) = match output.format() {
+        BinaryFormat::MachO => (
+            metadata::MACHO_SEGMENT_NAME.as_bytes().to_vec(),
+            metadata::MACHO_SECTION_NAME,
+            SectionKind::Other,
+        ),
+        _ => (
+            vec![],
+            metadata::ELF_SECTION_NAME,
+            SectionKind::Elf(object::elf::SHT_PROGBITS),
+        ),
+    };
+    let section_id = output.add_section(segment, name.as_bytes().to_vec(), kind);
+    output.append_section_data(section_id, &metadata.to_bytes(), 8);
+}
+
+/// Builds the wrapper that the host calls, which takes its args with the C ABI and calls on to `proc`.
+/// A `Result` is laid out like `RocResult` of roc_std: the payload of `Ok` or `Err`, followed by the tag id,
+/// which is 0 for `Err` and 1 for `Ok`. Up to 16 bytes of it are passed and returned in general registers
+/// when all payloads are integers, larger ones are returned through the pointer that the host passes first.
 fn build_exposed_proc<'a, B: Backend<'a>>(backend: &mut B, proc: &Proc<'a>) -> Proc<'a> {
     let arena = backend.env().arena;
     let interns = backend.interns();
@@ -528,27 +1047,176 @@ fn build_exposed_generic_proc<'a, B: Backend<'a>>(backend: &mut B, proc: &Proc<'
     }
 }
 
+/// Builds the wrapper that `Env::packed_exposed_procs` asks for.
+/// It takes a pointer to the arguments of `proc` packed into a struct, and a pointer to write its result to.
+fn build_exposed_packed_proc<'a, B: Backend<'a>>(backend: &mut B, proc: &Proc<'a>) -> Proc<'a> {
+    let arena = backend.env().arena;
+    let interns = backend.interns();
+
+    let sym = proc.name.name();
+    let platform = sym.module_id();
+
+    let fn_name = sym.as_str(interns).to_string();
+    let packed_proc_name = backend.debug_symbol_in(platform, &fn_name);
+    let args_ptr = backend.debug_symbol_in(platform, "args_ptr");
+    let ret_ptr = backend.debug_symbol_in(platform, "ret_ptr");
+    let packed_args = backend.debug_symbol_in(platform, "packed_args");
+    let result = backend.debug_symbol_in(platform, "result");
+    let stored = backend.debug_symbol_in(platform, "stored");
+    let unit = backend.debug_symbol_in(platform, "unit");
+
+    let field_layouts =
+        bumpalo::collections::Vec::from_iter_in(proc.args.iter().map(|t| t.0), arena)
+            .into_bump_slice();
+    let args_layout = backend
+        .interner_mut()
+        .insert_direct_no_semantic(roc_mono::layout::LayoutRepr::struct_(field_layouts));
+    let args_box_layout = backend
+        .interner_mut()
+        .insert_direct_no_semantic(roc_mono::layout::LayoutRepr::Boxed(args_layout));
+    let ret_box_layout = backend
+        .interner_mut()
+        .insert_direct_no_semantic(roc_mono::layout::LayoutRepr::Boxed(proc.ret_layout));
+
+    let call_args = bumpalo::collections::Vec::from_iter_in(
+        proc.args
+            .iter()
+            .map(|_| backend.debug_symbol_in(platform, "arg")),
+        arena,
+    )
+    .into_bump_slice();
+    let call = Call {
+        call_type: roc_mono::ir::CallType::ByName {
+            name: proc.name,
+            ret_layout: proc.ret_layout,
+            arg_layouts: field_layouts,
+            specialization_id: CallSpecId::BACKEND_DUMMY,
+        },
+        arguments: call_args,
+    };
+    let ret_write = Call {
+        call_type: roc_mono::ir::CallType::LowLevel {
+            op: roc_module::low_level::LowLevel::PtrStore,
+            update_mode: UpdateModeId::BACKEND_DUMMY,
+        },
+        arguments: arena.alloc([ret_ptr, result]),
+    };
+
+    let ret_unit = arena.alloc(Stmt::Let(
+        unit,
+        Expr::Struct(&[]),
+        Layout::UNIT,
+        arena.alloc(Stmt::Ret(unit)),
+    ));
+    let store_result = arena.alloc(Stmt::Let(
+        stored,
+        Expr::Call(ret_write),
+        ret_box_layout,
+        ret_unit,
+    ));
+    let mut body = Stmt::Let(result, Expr::Call(call), proc.ret_layout, store_result);
+
+    // Load the arguments from the packed struct, the last one first so each statement wraps the ones after it.
+    for (index, arg) in call_args.iter().enumerate().rev() {
+        body = Stmt::Let(
+            *arg,
+            Expr::StructAtIndex {
+                index: index as u64,
+                field_layouts,
+                structure: packed_args,
+            },
+            field_layouts[index],
+            arena.alloc(body),
+        );
+    }
+    if !call_args.is_empty() {
+        let args_read = Call {
+            call_type: roc_mono::ir::CallType::LowLevel {
+                op: roc_module::low_level::LowLevel::PtrLoad,
+                update_mode: UpdateModeId::BACKEND_DUMMY,
+            },
+            arguments: arena.alloc([args_ptr]),
+        };
+        body = Stmt::Let(
+            packed_args,
+            Expr::Call(args_read),
+            args_layout,
+            arena.alloc(body),
+        );
+    }
+
+    Proc {
+        name: LambdaName::no_niche(packed_proc_name),
+        args: arena.alloc([(args_box_layout, args_ptr), (ret_box_layout, ret_ptr)]),
+        body,
+        closure_data_layout: None,
+        ret_layout: Layout::UNIT,
+        is_self_recursive: roc_mono::ir::SelfRecursive::NotSelfRecursive,
+        host_exposed_layouts: roc_mono::ir::HostExposedLayouts::NotHostExposed,
+    }
+}
+
+/// Builds `roc__<name>_<id>_exposed_size`, which returns the size of the result of `proc`.
+/// Every exposed proc gets one like with the LLVM backend, so hosts with several entry points
+/// can allocate the results of each of them.
+fn build_exposed_size_proc<'a, B: Backend<'a>>(backend: &mut B, proc: &Proc<'a>) -> Proc<'a> {
+    let arena = backend.env().arena;
+    let interns = backend.interns();
+
+    let sym = proc.name.name();
+    let platform = sym.module_id();
+
+    let fn_name = sym.as_str(interns).to_string();
+    let size_proc_name = backend.debug_symbol_in(platform, &fn_name);
+    let size = backend.debug_symbol_in(platform, "size");
+
+    let result_size = backend.interner().stack_size(proc.ret_layout);
+    let body = Stmt::Let(
+        size,
+        Expr::Literal(Literal::Int((result_size as i128).to_ne_bytes())),
+        Layout::U64,
+        arena.alloc(Stmt::Ret(size)),
+    );
+
+    Proc {
+        name: LambdaName::no_niche(size_proc_name),
+        args: &[],
+        body,
+        closure_data_layout: None,
+        ret_layout: Layout::U64,
+        is_self_recursive: roc_mono::ir::SelfRecursive::NotSelfRecursive,
+        host_exposed_layouts: roc_mono::ir::HostExposedLayouts::NotHostExposed,
+    }
+}
+
 #[allow(clippy::enum_variant_names)]
 enum Exposed {
     ExposedGeneric,
     Exposed,
+    ExposedPacked,
+    ExposedSize,
     NotExposed,
 }
 
+/// Adds the symbol of `proc`, which is the user proc `sym` itself or one of the wrappers exposing it.
+/// The exposed names come from `sym` and its layout id rather than the made up symbol of the wrapper,
+/// so that each specialization gets names of its own and they match the ones of the LLVM backend.
+#[allow(clippy::too_many_arguments)]
 fn build_proc_symbol<'a, B: Backend<'a>>(
     output: &mut Object<'a>,
     layout_ids: &mut LayoutIds<'a>,
     procs: &mut Vec<'a, (String, SectionId, SymbolId, Proc<'a>)>,
     backend: &mut B,
+    sym: symbol::Symbol,
     layout: ProcLayout<'a>,
     proc: Proc<'a>,
     exposed: Exposed,
 ) {
-    let sym = proc.name.name();
+    let proc_sym = proc.name.name();
 
     let section_id = output.add_section(
         output.segment_name(StandardSegment::Text).to_vec(),
-        format!(".text.{:x}", sym.as_u64()).as_bytes().to_vec(),
+        format!(".text.{:x}", proc_sym.as_u64()).as_bytes().to_vec(),
         SectionKind::Text,
     );
 
@@ -559,6 +1227,18 @@ fn build_proc_symbol<'a, B: Backend<'a>>(
         Exposed::Exposed => layout_ids
             .get_toplevel(sym, &layout)
             .to_exposed_symbol_string(sym, backend.interns()),
+        Exposed::ExposedPacked => format!(
+            "{}_packed",
+            layout_ids
+                .get_toplevel(sym, &layout)
+                .to_exposed_symbol_string(sym, backend.interns())
+        ),
+        Exposed::ExposedSize => format!(
+            "{}_size",
+            layout_ids
+                .get_toplevel(sym, &layout)
+                .to_exposed_symbol_string(sym, backend.interns())
+        ),
         Exposed::NotExposed => backend.lambda_name_to_string(
             proc.name,
             layout.arguments.iter().copied(),
@@ -575,7 +1255,10 @@ fn build_proc_symbol<'a, B: Backend<'a>>(
         // TODO: Depending on whether we are building a static or dynamic lib, this should change.
         // We should use Dynamic -> anyone, Linkage -> static link, Compilation -> this module only.
         scope: match exposed {
-            Exposed::ExposedGeneric | Exposed::Exposed => SymbolScope::Dynamic,
+            Exposed::ExposedGeneric
+            | Exposed::Exposed
+            | Exposed::ExposedPacked
+            | Exposed::ExposedSize => SymbolScope::Dynamic,
             Exposed::NotExposed => SymbolScope::Linkage,
         },
         weak: false,
@@ -586,39 +1269,259 @@ fn build_proc_symbol<'a, B: Backend<'a>>(
     procs.push((fn_name, section_id, proc_id, proc));
 }
 
+/// Adds the slot that calls to a user proc go through with `Env::hot_reload`, which starts out pointing to the proc.
+/// It is exported so that the program that loads the object can find it and patch it.
+fn add_hot_reload_slot(
+    output: &mut Object,
+    data_section: SectionId,
+    fn_name: &str,
+    proc_id: SymbolId,
+) {
+    let slot_symbol = Symbol {
+        name: hot_reload_slot_name(fn_name).into_bytes(),
+        value: 0,
+        size: 8,
+        kind: SymbolKind::Data,
+        scope: SymbolScope::Dynamic,
+        weak: false,
+        section: SymbolSection::Section(data_section),
+        flags: SymbolFlags::None,
+    };
+    let slot_id = output.add_symbol(slot_symbol);
+    let slot_offset = output.add_symbol_data(slot_id, data_section, &[0; 8], 8);
+    let reloc = write::Relocation {
+        offset: slot_offset,
+        size: 64,
+        kind: RelocationKind::Absolute,
+        encoding: RelocationEncoding::Generic,
+        symbol: proc_id,
+        addend: 0,
+    };
+    if let Err(e) = output.add_relocation(data_section, reloc) {
+        internal_error!("{:?}", e);
+    }
+}
+
+/// The entry of a proc in the `SymbolMap`, without the size of its code.
+fn mapped_symbol<'a, B: Backend<'a>>(backend: &B, fn_name: &str, proc: &Proc<'a>) -> MappedSymbol {
+    let sym = proc.name.name();
+    let interns = backend.interns();
+    let interner = backend.interner();
+    let arg_layouts: std::vec::Vec<String> = proc
+        .args
+        .iter()
+        .map(|(layout, _)| interner.dbg(*layout))
+        .collect();
+    MappedSymbol {
+        symbol: fn_name.to_string(),
+        roc_name: format!(
+            "{}.{}",
+            sym.module_string(interns).as_str(),
+            sym.as_str(interns)
+        ),
+        size: 0,
+        layout: format!(
+            "({}) -> {}",
+            arg_layouts.join(", "),
+            interner.dbg(proc.ret_layout)
+        ),
+    }
+}
+
+/// Turns the symbol of a proc that is built by another backend into an undefined reference to it.
+fn leave_undefined(output: &mut Object, proc_id: SymbolId) {
+    let symbol = output.symbol_mut(proc_id);
+    symbol.section = SymbolSection::Undefined;
+    symbol.size = 0;
+    if symbol.scope == SymbolScope::Compilation {
+        symbol.scope = SymbolScope::Linkage;
+    }
+}
+
+/// Returns false if the proc is left undefined for another backend to build, see `Env::llvm_fallback`.
+/// Only procs with `can_fall_back` are left to another backend; the others report their unsupported constructs.
 #[allow(clippy::too_many_arguments)]
 fn build_proc<'a, B: Backend<'a>>(
     output: &mut Object,
     backend: &mut B,
     relocations: &mut Vec<'a, (SectionId, object::write::Relocation)>,
+    metadata: &mut ModuleMetadata,
+    report: &mut BuildReport<'a>,
+    unsupported: &mut std::vec::Vec<UnsupportedConstruct>,
     layout_ids: &mut LayoutIds<'a>,
     data_section: SectionId,
+    local_data: &mut MutMap<&'a [u8], SymbolId>,
     fn_name: String,
     section_id: SectionId,
     proc_id: SymbolId,
     proc: Proc<'a>,
-) {
+    cache_key: Option<u64>,
+    can_fall_back: bool,
+) -> bool {
     let mut local_data_index = 0;
-    let (proc_data, relocs, rc_proc_names) = backend.build_proc(proc, layout_ids);
-    let proc_offset = output.add_symbol_data(proc_id, section_id, &proc_data, 16);
+    let arena = backend.env().arena;
+    let proc_cache = backend.env().proc_cache;
+    // The proc is moved into the backend, so its entry is made up front.
+    let mapped_symbol = backend
+        .env()
+        .symbol_map
+        .then(|| mapped_symbol(backend, &fn_name, &proc));
+    let sym = proc.name.name();
+    let module = sym.module_string(backend.interns()).as_str().to_string();
+    let builtin = sym.is_builtin();
+    let build_start = Instant::now();
+    let cached = cache_key.zip(proc_cache).and_then(|(key, cache)| {
+        let mut cache = cache.borrow_mut();
+        let (code, relocs, stack_frame_size, stats) = cache.get(key, arena)?;
+        Some((
+            Vec::from_iter_in(code.iter().copied(), arena),
+            relocs,
+            stack_frame_size,
+            stats,
+        ))
+    });
+    let (proc_data, relocs, rc_proc_names, stack_frame_size, stats, phase_times) = match cached {
+        // Cached procs do not call helpers, so there are no helper names to define.
+        Some((code, relocs, stack_frame_size, stats)) => (
+            code,
+            relocs,
+            bumpalo::vec![in arena],
+            stack_frame_size,
+            stats,
+            PhaseTimes::default(),
+        ),
+        None => {
+            let proc_symbol = proc.name.name();
+            let proc_layout = ProcLayout {
+                arguments: Vec::from_iter_in(proc.args.iter().map(|(layout, _)| *layout), arena)
+                    .into_bump_slice(),
+                result: proc.ret_layout,
+                niche: proc.name.niche(),
+            };
+            // Exported procs are the wrappers that hosts call.
+            let host_abi = output.symbol(proc_id).scope == SymbolScope::Dynamic;
+            let (proc_data, relocs, rc_proc_names) = backend.build_proc(proc, layout_ids, host_abi);
+            let stack_frame_size = backend.stack_frame_size();
+            let stats = backend.proc_stats();
+            let phase_times = backend.phase_times();
+            let constructs = std::mem::take(backend.unsupported_constructs_mut());
+            if !constructs.is_empty() && can_fall_back {
+                leave_undefined(output, proc_id);
+                report
+                    .fallback_procs
+                    .push((proc_symbol, proc_layout, fn_name));
+                return false;
+            }
+            // Procs that crash on an unsupported construct are not worth caching.
+            let cache = proc_cache.filter(|_| constructs.is_empty());
+            unsupported.extend(
+                constructs
+                    .into_iter()
+                    .map(|construct| UnsupportedConstruct {
+                        proc_name: fn_name.clone(),
+                        construct,
+                    }),
+            );
+            if let (Some(key), Some(cache)) = (cache_key, cache) {
+                cache.borrow_mut().insert(
+                    key,
+                    &proc_data,
+                    &relocs,
+                    stack_frame_size,
+                    stats,
+                    &rc_proc_names,
+                );
+            }
+            (
+                proc_data,
+                relocs,
+                rc_proc_names,
+                stack_frame_size,
+                stats,
+                phase_times,
+            )
+        }
+    };
+    let build_time = build_start.elapsed();
+    let write_start = backend.env().phase_timing.then(Instant::now);
+    // This also sets the size of the proc symbol to the length of its code.
+    // Combined with `SymbolKind::Text`, that gives tools like objdump and perf a sized STT_FUNC.
+    let proc_offset = output.add_symbol_data(
+        proc_id,
+        section_id,
+        &proc_data,
+        backend.env().proc_alignment,
+    );
+    debug_assert_eq!(output.symbol(proc_id).size, proc_data.len() as u64);
+    report.memory.code_bytes += proc_data.len();
+    report.memory.largest_proc_buffer = report.memory.largest_proc_buffer.max(proc_data.len());
+
+    if let Some(mapped_symbol) = mapped_symbol {
+        report.symbol_map.symbols.push(MappedSymbol {
+            size: proc_data.len() as u64,
+            ..mapped_symbol
+        });
+    }
+
+    let proc_index = metadata.procs.len() as u32;
+    metadata.procs.push(ProcMetadata {
+        name: fn_name.clone(),
+        exposed: output.symbol(proc_id).scope == SymbolScope::Dynamic,
+        offset: proc_offset,
+        code_size: proc_data.len() as u64,
+        stack_size: stack_frame_size,
+    });
+    let report_index = report.procs.len();
+    report.procs.push(ProcReport {
+        name: fn_name.clone(),
+        module,
+        builtin,
+        code_size: proc_data.len() as u64,
+        relocations: relocs.len() as u32,
+        stats,
+        build_time,
+        phase_times,
+    });
+    if backend.env().print_proc_stats {
+        eprintln!(
+            "{}: {} spills, {} reloads, {} max live values, {} byte stack frame",
+            fn_name, stats.spills, stats.reloads, stats.max_live_values, stats.stack_frame_size
+        );
+    }
+    let mut record_host_relocation =
+        |output: &Object, sym_id: SymbolId, offset: u64, name: &str| {
+            if matches!(output.symbol(sym_id).section, SymbolSection::Undefined) {
+                metadata.host_relocations.push(HostRelocation {
+                    proc_index,
+                    offset,
+                    name: name.to_string(),
+                });
+            }
+        };
     for reloc in relocs.iter() {
         let elfreloc = match reloc {
             Relocation::LocalData { offset, data } => {
-                let data_symbol = write::Symbol {
-                    name: format!("{}.data{}", fn_name, local_data_index)
-                        .as_bytes()
-                        .to_vec(),
-                    value: 0,
-                    size: 0,
-                    kind: SymbolKind::Data,
-                    scope: SymbolScope::Compilation,
-                    weak: false,
-                    section: SymbolSection::Section(data_section),
-                    flags: SymbolFlags::None,
-                };
-                local_data_index += 1;
-                let data_id = output.add_symbol(data_symbol);
-                output.add_symbol_data(data_id, data_section, data, 4);
+                // Procs that use the same literal share its bytes, whichever proc added them first names them.
+                let data_id = *local_data.entry(*data).or_insert_with(|| {
+                    let data_symbol = write::Symbol {
+                        name: format!("{}.data{}", fn_name, local_data_index)
+                            .as_bytes()
+                            .to_vec(),
+                        value: 0,
+                        size: data.len() as u64,
+                        kind: SymbolKind::Data,
+                        scope: SymbolScope::Compilation,
+                        weak: false,
+                        section: SymbolSection::Section(data_section),
+                        flags: SymbolFlags::None,
+                    };
+                    local_data_index += 1;
+                    let data_id = output.add_symbol(data_symbol);
+                    let data_alignment = backend.env().data_alignment;
+                    output.add_symbol_data(data_id, data_section, data, data_alignment);
+                    report.memory.data_bytes += data.len();
+                    data_id
+                });
                 write::Relocation {
                     offset: offset + proc_offset,
                     size: 32,
@@ -628,76 +1531,385 @@ fn build_proc<'a, B: Backend<'a>>(
                     addend: -4,
                 }
             }
-            Relocation::LinkedData { offset, name } => {
+            Relocation::LinkedData {
+                offset,
+                name,
+                addend,
+            } => {
                 if let Some(sym_id) = output.symbol_id(name.as_bytes()) {
+                    // See `Relocation::LinkedData`, the code only goes through the GOT without an addend.
+                    let kind = if *addend == 0 {
+                        RelocationKind::GotRelative
+                    } else {
+                        RelocationKind::Relative
+                    };
                     write::Relocation {
                         offset: offset + proc_offset,
                         size: 32,
-                        kind: RelocationKind::GotRelative,
+                        kind,
                         encoding: RelocationEncoding::Generic,
                         symbol: sym_id,
-                        addend: -4,
+                        addend: addend - 4,
                     }
                 } else {
                     internal_error!("failed to find data symbol for {:?}", name);
                 }
             }
-            Relocation::LinkedFunction { offset, name } => {
-                // If the symbol is an undefined roc function, we need to add it here.
-                if output.symbol_id(name.as_bytes()).is_none() && name.starts_with("roc_") {
-                    let builtin_symbol = Symbol {
-                        name: name.as_bytes().to_vec(),
-                        value: 0,
-                        size: 0,
-                        kind: SymbolKind::Text,
-                        scope: SymbolScope::Linkage,
-                        weak: false,
-                        section: SymbolSection::Undefined,
-                        flags: SymbolFlags::None,
-                    };
-                    output.add_symbol(builtin_symbol);
-                }
-
-                // If the symbol is an undefined reference counting procedure, we need to add it here.
-                if output.symbol_id(name.as_bytes()).is_none() {
-                    for (sym, rc_name) in rc_proc_names.iter() {
-                        if name == rc_name {
-                            let section_id = output.add_section(
-                                output.segment_name(StandardSegment::Text).to_vec(),
-                                format!(".text.{:x}", sym.as_u64()).as_bytes().to_vec(),
-                                SectionKind::Text,
-                            );
-
-                            let rc_symbol = Symbol {
-                                name: name.as_bytes().to_vec(),
-                                value: 0,
-                                size: 0,
-                                kind: SymbolKind::Text,
-                                scope: SymbolScope::Linkage,
-                                weak: false,
-                                section: SymbolSection::Section(section_id),
-                                flags: SymbolFlags::None,
-                            };
-                            output.add_symbol(rc_symbol);
-                        }
-                    }
-                }
-
-                if let Some(sym_id) = output.symbol_id(name.as_bytes()) {
-                    write::Relocation {
-                        offset: offset + proc_offset,
-                        size: 32,
-                        kind: RelocationKind::PltRelative,
-                        encoding: RelocationEncoding::X86Branch,
-                        symbol: sym_id,
-                        addend: -4,
-                    }
-                } else {
-                    internal_error!("failed to find fn symbol for {:?}", name);
-                }
+            Relocation::LinkedFunction {
+                offset,
+                name,
+                addend,
+            } => {
+                let sym_id = linked_function_symbol(output, name, &rc_proc_names);
+                record_host_relocation(output, sym_id, *offset, name);
+                let mut reloc = branch_relocation(output, offset + proc_offset, sym_id, true);
+                reloc.addend += addend;
+                reloc
+            }
+            Relocation::LinkedFunctionPage { offset, name } => {
+                let sym_id = linked_function_symbol(output, name, &rc_proc_names);
+                record_host_relocation(output, sym_id, *offset, name);
+                let [page, page_offset] = page_relocations(output, offset + proc_offset, sym_id);
+                relocations.push((section_id, page));
+                page_offset
             }
             Relocation::JmpToReturn { .. } => unreachable!(),
         };
         relocations.push((section_id, elfreloc));
     }
+    if let Some(start) = write_start {
+        report.procs[report_index].phase_times.object_writing = start.elapsed();
+    }
+    true
+}
+
+/// Finds the symbol for a linked function.
+/// Undefined roc functions and reference counting procedures are added to the object if needed.
+fn linked_function_symbol(
+    output: &mut Object,
+    name: &str,
+    rc_proc_names: &[(symbol::Symbol, String)],
+) -> SymbolId {
+    // If the symbol is an undefined roc function, we need to add it here.
+    if output.symbol_id(name.as_bytes()).is_none() && name.starts_with("roc_") {
+        let builtin_symbol = Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Linkage,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        };
+        output.add_symbol(builtin_symbol);
+    }
+
+    // If the symbol is an undefined reference counting procedure, we need to add it here.
+    if output.symbol_id(name.as_bytes()).is_none() {
+        for (sym, rc_name) in rc_proc_names.iter() {
+            if name == rc_name {
+                let section_id = output.add_section(
+                    output.segment_name(StandardSegment::Text).to_vec(),
+                    format!(".text.{:x}", sym.as_u64()).as_bytes().to_vec(),
+                    SectionKind::Text,
+                );
+
+                let rc_symbol = Symbol {
+                    name: name.as_bytes().to_vec(),
+                    value: 0,
+                    size: 0,
+                    kind: SymbolKind::Text,
+                    scope: SymbolScope::Linkage,
+                    weak: false,
+                    section: SymbolSection::Section(section_id),
+                    flags: SymbolFlags::None,
+                };
+                output.add_symbol(rc_symbol);
+            }
+        }
+    }
+
+    match output.symbol_id(name.as_bytes()) {
+        Some(sym_id) => sym_id,
+        None => internal_error!("failed to find fn symbol for {:?}", name),
+    }
+}
+
+/// Creates the relocation for a direct branch to `symbol`.
+/// On x86_64, `offset` points at the 32 bit displacement of the `call`/`jmp`.
+/// On AArch64, `offset` points at the `BL`/`B` instruction itself.
+/// `is_call` distinguishes a `BL` from a `B` on AArch64 ELF.
+fn branch_relocation(
+    output: &Object,
+    offset: u64,
+    symbol: SymbolId,
+    is_call: bool,
+) -> write::Relocation {
+    match (output.architecture(), output.format()) {
+        (Architecture::Aarch64, BinaryFormat::MachO) => write::Relocation {
+            offset,
+            size: 32,
+            kind: RelocationKind::MachO {
+                value: object::macho::ARM64_RELOC_BRANCH26,
+                relative: true,
+            },
+            encoding: RelocationEncoding::Generic,
+            symbol,
+            addend: 0,
+        },
+        (Architecture::Aarch64, _) if is_call => write::Relocation {
+            offset,
+            size: 26,
+            kind: RelocationKind::PltRelative,
+            encoding: RelocationEncoding::AArch64Call,
+            symbol,
+            addend: 0,
+        },
+        (Architecture::Aarch64, _) => write::Relocation {
+            offset,
+            size: 26,
+            kind: RelocationKind::Elf(object::elf::R_AARCH64_JUMP26),
+            encoding: RelocationEncoding::Generic,
+            symbol,
+            addend: 0,
+        },
+        _ => write::Relocation {
+            offset,
+            size: 32,
+            kind: RelocationKind::PltRelative,
+            encoding: RelocationEncoding::X86Branch,
+            symbol,
+            addend: -4,
+        },
+    }
+}
+
+/// Creates the pair of relocations for an AArch64 `ADRP` + `ADD` loading the address of `symbol`.
+/// `offset` points at the `ADRP`, the `ADD` is expected to be the next instruction.
+fn page_relocations(output: &Object, offset: u64, symbol: SymbolId) -> [write::Relocation; 2] {
+    let (page_kind, page_offset_kind) = match output.format() {
+        BinaryFormat::MachO => (
+            RelocationKind::MachO {
+                value: object::macho::ARM64_RELOC_PAGE21,
+                relative: true,
+            },
+            RelocationKind::MachO {
+                value: object::macho::ARM64_RELOC_PAGEOFF12,
+                relative: false,
+            },
+        ),
+        BinaryFormat::Elf => (
+            RelocationKind::Elf(object::elf::R_AARCH64_ADR_PREL_PG_HI21),
+            RelocationKind::Elf(object::elf::R_AARCH64_ADD_ABS_LO12_NC),
+        ),
+        format => internal_error!("page relocations are not supported for {:?}", format),
+    };
+
+    [
+        write::Relocation {
+            offset,
+            size: 32,
+            kind: page_kind,
+            encoding: RelocationEncoding::Generic,
+            symbol,
+            addend: 0,
+        },
+        write::Relocation {
+            offset: offset + 4,
+            size: 32,
+            kind: page_offset_kind,
+            encoding: RelocationEncoding::Generic,
+            symbol,
+            addend: 0,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug_info::tests::read_subprograms;
+    use crate::disasm_snapshot::Fixture;
+    use bumpalo::Bump;
+    use object::read::{Object as _, ObjectSection, ObjectSymbol, RelocationTarget};
+    use roc_module::low_level::LowLevel;
+    use roc_mono::ir::ModifyRc;
+    use roc_mono::layout::{Builtin, LayoutRepr};
+    use roc_region::all::Region;
+
+    /// A proc with an `expect-fx`, which the dev backend does not support.
+    fn unsupported_proc<'a>(f: &mut Fixture<'a>) -> Proc<'a> {
+        let cond = f.symbol("cond");
+        let x = f.symbol("x");
+        let body = Stmt::ExpectFx {
+            condition: cond,
+            region: Region::zero(),
+            lookups: &[],
+            variables: &[],
+            remainder: f.arena.alloc(Stmt::Ret(x)),
+        };
+        f.proc(
+            "unsupported",
+            &[(Layout::BOOL, cond), (Layout::U64, x)],
+            body,
+            Layout::U64,
+        )
+    }
+
+    /// A proc that drops a `List Str`, which needs a refcounting helper that is built after the procs.
+    fn drop_list_proc<'a>(f: &mut Fixture<'a>) -> Proc<'a> {
+        let list = f.symbol("list");
+        let x = f.symbol("x");
+        let list_layout = f
+            .layout_interner
+            .insert_direct_no_semantic(LayoutRepr::Builtin(Builtin::List(Layout::STR)));
+        let body = Stmt::Refcounting(ModifyRc::Dec(list), f.arena.alloc(Stmt::Ret(x)));
+        f.proc(
+            "drop_list",
+            &[(list_layout, list), (Layout::U64, x)],
+            body,
+            Layout::U64,
+        )
+    }
+
+    #[test]
+    fn llvm_fallback_procs_are_left_out_of_the_debug_info() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let procs = [unsupported_proc(f), drop_list_proc(f)];
+        let env = f
+            .env_builder()
+            .llvm_fallback(true)
+            .debug_info(DebugInfo::Split {
+                dwo_name: "test.dwo",
+            });
+        let (object, dwo, report) = f.build_object(&procs, env);
+
+        let [(symbol, layout, fallback_name)] = report.fallback_procs.as_slice() else {
+            panic!("fallback procs: {:?}", report.fallback_procs);
+        };
+        assert_eq!(*symbol, procs[0].name.name());
+        assert_eq!(layout.arguments, &[Layout::BOOL, Layout::U64]);
+
+        let object = object::File::parse(object.as_slice()).unwrap();
+        let fallback = object
+            .symbols()
+            .find(|symbol| symbol.name() == Ok(fallback_name.as_str()))
+            .unwrap();
+        assert!(fallback.is_undefined());
+        assert!(report.procs.iter().all(|proc| proc.name != *fallback_name));
+
+        // Every subprogram of the dwo object has the address of the proc of the same name.
+        let dwo = object::File::parse(dwo.as_deref().unwrap()).unwrap();
+        let dwo_info = dwo.section_by_name(".debug_info.dwo").unwrap();
+        let subprograms = read_subprograms(&dwo_info.data().unwrap());
+        assert_eq!(subprograms.len(), report.procs.len());
+        assert!(subprograms.len() > 1, "no helpers in {:?}", subprograms);
+        let debug_addr = object.section_by_name(".debug_addr").unwrap();
+        for (name, index, size) in subprograms {
+            let offset = crate::debug_info::DEBUG_ADDR_HEADER_SIZE + 8 * index;
+            let (_, reloc) = debug_addr
+                .relocations()
+                .find(|(reloc_offset, _)| *reloc_offset == offset)
+                .unwrap();
+            let RelocationTarget::Symbol(symbol) = reloc.target() else {
+                panic!("address {} of {} is not a symbol", index, name);
+            };
+            let symbol = object.symbol_by_index(symbol).unwrap();
+            assert_eq!(symbol.name(), Ok(name.as_str()));
+            assert_eq!(symbol.size(), size, "size of {}", name);
+        }
+    }
+
+    #[test]
+    fn tiny_procs_are_still_called_through_their_slot_with_hot_reload() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let [x, ret] = ["x", "ret"].map(|name| f.symbol(name));
+        let ret_stmt = &*f.arena.alloc(Stmt::Ret(ret));
+        let add = f.low_level(LowLevel::NumAdd, &[x, x]);
+        let callee_body = Stmt::Let(ret, add, Layout::U64, ret_stmt);
+        let callee = f.proc("callee", &[(Layout::U64, x)], callee_body, Layout::U64);
+        let call = f.call(&callee, &[x]);
+        let caller_body = Stmt::Let(ret, call, Layout::U64, ret_stmt);
+        let caller = f.proc("caller", &[(Layout::U64, x)], caller_body, Layout::U64);
+        let name_env = f.env_builder();
+        let slot_name = hot_reload_slot_name(&f.symbol_name(&callee, name_env));
+        let env = f
+            .env_builder()
+            .dev_opt_level(DevOptLevel::One)
+            .hot_reload(true);
+        let (object, _, _) = f.build_object(&[callee, caller], env);
+
+        let object = object::File::parse(object.as_slice()).unwrap();
+        // Every proc has a text section of its own.
+        let calls_slot = object
+            .sections()
+            .flat_map(|section| section.relocations().collect::<std::vec::Vec<_>>())
+            .any(|(_, reloc)| match reloc.target() {
+                RelocationTarget::Symbol(symbol) => {
+                    object.symbol_by_index(symbol).unwrap().name() == Ok(slot_name.as_str())
+                }
+                _ => false,
+            });
+        assert!(calls_slot, "the call of the callee was inlined");
+    }
+
+    #[test]
+    fn every_exposed_proc_gets_wrappers_of_its_own() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let [x, s] = ["x", "s"].map(|name| f.symbol(name));
+        let first = f.proc("first", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+        let second = f.proc("second", &[(Layout::STR, s)], Stmt::Ret(s), Layout::STR);
+        let exposed_to_host = [first.name.name(), second.name.name()]
+            .into_iter()
+            .collect();
+        let env = f.env_builder().exposed_to_host(exposed_to_host);
+        let (object, _, _) = f.build_object(&[first, second], env);
+
+        let object = object::File::parse(object.as_slice()).unwrap();
+        let module_metadata = object
+            .section_by_name(metadata::ELF_SECTION_NAME)
+            .and_then(|section| ModuleMetadata::from_bytes(&section.data().unwrap()))
+            .unwrap();
+        for (name, result_size) in [("first", 8u32), ("second", 24)] {
+            for suffix in ["exposed", "exposed_generic", "exposed_size"] {
+                let fn_name = format!("roc__{}_1_{}", name, suffix);
+                let symbol = object
+                    .symbols()
+                    .find(|symbol| symbol.name() == Ok(fn_name.as_str()))
+                    .unwrap_or_else(|| panic!("no symbol {}", fn_name));
+                assert!(symbol.is_definition() && symbol.is_global(), "{}", fn_name);
+                let proc = module_metadata
+                    .procs
+                    .iter()
+                    .find(|proc| proc.name == fn_name);
+                assert!(
+                    proc.map_or(false, |proc| proc.exposed),
+                    "metadata of {}",
+                    fn_name
+                );
+            }
+
+            // The size is a literal of the size proc, which returns it right away.
+            let size_name = format!("roc__{}_1_exposed_size", name);
+            let size_symbol = object
+                .symbols()
+                .find(|symbol| symbol.name() == Ok(size_name.as_str()))
+                .unwrap();
+            let section = object
+                .section_by_index(size_symbol.section_index().unwrap())
+                .unwrap();
+            let code = section.data().unwrap();
+            assert!(
+                code.windows(4)
+                    .any(|bytes| bytes == result_size.to_le_bytes()),
+                "{} does not return {}",
+                size_name,
+                result_size
+            );
+        }
+    }
 }
@@ -0,0 +1,284 @@
+//! Keeps the machine code of procs between builds of a module,
+//! so that after a small edit only the procs that changed are built again.
+
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use bumpalo::{collections::Vec, Bump};
+use object::write::Object;
+use roc_collections::all::MutMap;
+use roc_mono::ir::Proc;
+use roc_mono::layout::STLayoutInterner;
+
+use crate::{AssemblyBackendMode, Env, ProcStats, Relocation};
+
+/// A relocation of a cached proc.
+/// The cache outlives the arena of the build, so local data is owned instead of borrowed.
+#[derive(Debug, Clone)]
+enum CachedRelocation {
+    LocalData {
+        offset: u64,
+        data: std::vec::Vec<u8>,
+    },
+    Linked(Relocation<'static>),
+}
+
+impl CachedRelocation {
+    fn new(reloc: &Relocation<'_>) -> Self {
+        match reloc {
+            Relocation::LocalData { offset, data } => CachedRelocation::LocalData {
+                offset: *offset,
+                data: data.to_vec(),
+            },
+            Relocation::LinkedFunction {
+                offset,
+                name,
+                addend,
+            } => CachedRelocation::Linked(Relocation::LinkedFunction {
+                offset: *offset,
+                name: name.clone(),
+                addend: *addend,
+            }),
+            Relocation::LinkedFunctionPage { offset, name } => {
+                CachedRelocation::Linked(Relocation::LinkedFunctionPage {
+                    offset: *offset,
+                    name: name.clone(),
+                })
+            }
+            Relocation::LinkedData {
+                offset,
+                name,
+                addend,
+            } => CachedRelocation::Linked(Relocation::LinkedData {
+                offset: *offset,
+                name: name.clone(),
+                addend: *addend,
+            }),
+            Relocation::JmpToReturn {
+                inst_loc,
+                inst_size,
+                offset,
+            } => CachedRelocation::Linked(Relocation::JmpToReturn {
+                inst_loc: *inst_loc,
+                inst_size: *inst_size,
+                offset: *offset,
+            }),
+        }
+    }
+
+    fn to_relocation<'a>(&self, arena: &'a Bump) -> Relocation<'a> {
+        match self {
+            CachedRelocation::LocalData { offset, data } => Relocation::LocalData {
+                offset: *offset,
+                data: arena.alloc_slice_copy(data),
+            },
+            CachedRelocation::Linked(reloc) => reloc.clone(),
+        }
+    }
+}
+
+/// The finalized code of a proc from an earlier build.
+#[derive(Debug, Clone)]
+struct CachedProc {
+    code: std::vec::Vec<u8>,
+    relocs: std::vec::Vec<CachedRelocation>,
+    stack_frame_size: u32,
+    stats: ProcStats,
+}
+
+/// The finalized code of procs from earlier builds, see `EnvBuilder::proc_cache`.
+/// Procs are keyed by a hash of their mono IR, layouts and name, along with the settings of the build.
+/// Procs that call generated helpers, like the refcounting procs, are always built again,
+/// since the helpers are only generated for the procs that ask for them.
+#[derive(Debug, Default)]
+pub struct ProcCache {
+    procs: MutMap<u64, CachedProc>,
+    hits: u32,
+}
+
+impl ProcCache {
+    /// The number of procs that were copied from the cache instead of being built.
+    pub fn hits(&self) -> u32 {
+        self.hits
+    }
+
+    /// The number of procs in the cache.
+    pub fn len(&self) -> usize {
+        self.procs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.procs.is_empty()
+    }
+
+    pub(crate) fn key<'a>(
+        env: &Env<'a>,
+        output: &Object,
+        fn_name: &str,
+        proc: &Proc<'a>,
+        interner: &STLayoutInterner<'a>,
+    ) -> u64 {
+        let mut state = roc_collections::all::BuildHasher::default().build_hasher();
+        format!("{:?} {:?}", output.architecture(), output.format()).hash(&mut state);
+        matches!(env.mode, AssemblyBackendMode::Test).hash(&mut state);
+        (
+            env.lazy_literals,
+            env.overflow_checks,
+            env.bounds_checks,
+            env.atomic_refcounts,
+            env.stack_check_size,
+            env.report_expects,
+            env.expect_checks,
+            env.poison_stack,
+            env.hot_reload,
+            env.bit_count_instructions,
+            env.vector_instructions,
+            env.layout_symbol_names,
+            env.dev_opt_level,
+        )
+            .hash(&mut state);
+        fn_name.hash(&mut state);
+        proc.to_pretty(interner, 200, false).hash(&mut state);
+        state.finish()
+    }
+
+    /// The code, relocations, stack frame size and stats of the proc with `key`.
+    /// The relocations are allocated in `arena`.
+    pub(crate) fn get<'a>(
+        &mut self,
+        key: u64,
+        arena: &'a Bump,
+    ) -> Option<(&[u8], Vec<'a, Relocation<'a>>, u32, ProcStats)> {
+        let cached = self.procs.get(&key)?;
+        self.hits += 1;
+        Some((
+            &cached.code,
+            Vec::from_iter_in(
+                cached.relocs.iter().map(|reloc| reloc.to_relocation(arena)),
+                arena,
+            ),
+            cached.stack_frame_size,
+            cached.stats,
+        ))
+    }
+
+    /// Stores the code of a proc that was just built, unless it calls one of the generated `helper_names`.
+    pub(crate) fn insert(
+        &mut self,
+        key: u64,
+        code: &[u8],
+        relocs: &[Relocation<'_>],
+        stack_frame_size: u32,
+        stats: ProcStats,
+        helper_names: &[(roc_module::symbol::Symbol, String)],
+    ) {
+        let calls_helper = relocs.iter().any(|reloc| match reloc {
+            Relocation::LinkedFunction { name, .. }
+            | Relocation::LinkedFunctionPage { name, .. } => {
+                helper_names.iter().any(|(_, helper)| helper == name)
+            }
+            _ => false,
+        });
+        if calls_helper {
+            return;
+        }
+        self.procs.insert(
+            key,
+            CachedProc {
+                code: code.to_vec(),
+                relocs: relocs.iter().map(CachedRelocation::new).collect(),
+                stack_frame_size,
+                stats,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm_snapshot::Fixture;
+    use crate::DevOptLevel;
+    use object::{Architecture, BinaryFormat, Endianness};
+    use roc_module::symbol::Symbol;
+    use roc_mono::ir::Stmt;
+    use roc_mono::layout::Layout;
+
+    #[test]
+    fn procs_that_call_helpers_are_not_cached() {
+        let arena = Bump::new();
+        let mut cache = ProcCache::default();
+        let helper = (Symbol::ARG_1, "rc_helper".to_string());
+        let call = |name: &str| Relocation::LinkedFunction {
+            offset: 1,
+            name: name.to_string(),
+            addend: 0,
+        };
+
+        cache.insert(
+            1,
+            &[0xC3],
+            &[call("other_proc")],
+            16,
+            ProcStats::default(),
+            &[helper.clone()],
+        );
+        cache.insert(
+            2,
+            &[0xC3],
+            &[call("rc_helper")],
+            16,
+            ProcStats::default(),
+            &[helper],
+        );
+
+        assert_eq!(cache.len(), 1);
+        let (code, relocs, stack_frame_size, _) = cache.get(1, &arena).unwrap();
+        assert_eq!(code, [0xC3]);
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(stack_frame_size, 16);
+        assert!(cache.get(2, &arena).is_none());
+        assert_eq!(cache.hits(), 1);
+    }
+
+    #[test]
+    fn cached_local_data_is_copied_into_the_new_arena() {
+        let mut cache = ProcCache::default();
+        {
+            let arena = Bump::new();
+            let data = arena.alloc_slice_copy(&1.5f64.to_le_bytes());
+            cache.insert(
+                1,
+                &[0xC3],
+                &[Relocation::LocalData { offset: 4, data }],
+                0,
+                ProcStats::default(),
+                &[],
+            );
+        }
+
+        let arena = Bump::new();
+        let (_, relocs, _, _) = cache.get(1, &arena).unwrap();
+        match &relocs[..] {
+            [Relocation::LocalData { offset: 4, data }] => {
+                assert_eq!(*data, 1.5f64.to_le_bytes());
+            }
+            other => panic!("unexpected relocations: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn procs_built_at_other_dev_opt_levels_have_other_keys() {
+        let arena = Bump::new();
+        let f = &mut Fixture::new(&arena);
+        let x = f.symbol("x");
+        let proc = f.proc("id", &[(Layout::U64, x)], Stmt::Ret(x), Layout::U64);
+        let output = Object::new(BinaryFormat::Elf, Architecture::X86_64, Endianness::Little);
+        let key = |dev_opt_level| {
+            let env = f.env_builder().dev_opt_level(dev_opt_level).build();
+            ProcCache::key(&env, &output, "id", &proc, &f.layout_interner)
+        };
+
+        assert_eq!(key(DevOptLevel::One), key(DevOptLevel::One));
+        assert_ne!(key(DevOptLevel::Zero), key(DevOptLevel::One));
+    }
+}
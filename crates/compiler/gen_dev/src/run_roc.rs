@@ -33,3 +33,254 @@ macro_rules! run_jit_function_raw {
         }
     }};
 }
+
+#[macro_export]
+/// run_jit_function_with_args runs the packed wrapper of an exposed function with `$args`, a slice of `RocArg`.
+/// The wrapper only exists in objects built with `Env::packed_exposed_procs`.
+/// Like run_jit_function_raw, this trusts the generated code completely.
+macro_rules! run_jit_function_with_args {
+    ($lib: expr, $exposed_fn_name: expr, $ty:ty, $args:expr, $transform:expr) => {{
+        unsafe {
+            let packed_fn_name = format!("{}_packed", $exposed_fn_name);
+            let main: libloading::Symbol<unsafe extern "C" fn(*const u8, *mut $ty)> = $lib
+                .get(packed_fn_name.as_bytes())
+                .ok()
+                .ok_or(format!("Unable to JIT compile `{}`", packed_fn_name))
+                .expect("errored");
+
+            let args = $crate::PackedArgs::new(&$args);
+            let mut result = std::mem::MaybeUninit::<$ty>::uninit();
+            main(args.as_ptr(), result.as_mut_ptr());
+
+            $transform(result.assume_init())
+        }
+    }};
+}
+
+/// An argument for run_jit_function_with_args.
+/// The fields of a record go in the order of its layout, which sorts them by alignment and then by name.
+#[derive(Debug, Clone)]
+pub enum RocArg<'a> {
+    I8(i8),
+    U8(u8),
+    I16(i16),
+    U16(u16),
+    I32(i32),
+    U32(u32),
+    I64(i64),
+    U64(u64),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    Str(&'a str),
+    Record(Vec<RocArg<'a>>),
+}
+
+impl RocArg<'_> {
+    fn alignment(&self) -> usize {
+        match self {
+            RocArg::I8(_) | RocArg::U8(_) | RocArg::Bool(_) => 1,
+            RocArg::I16(_) | RocArg::U16(_) => 2,
+            RocArg::I32(_) | RocArg::U32(_) | RocArg::F32(_) => 4,
+            RocArg::I64(_) | RocArg::U64(_) | RocArg::F64(_) | RocArg::Str(_) => 8,
+            RocArg::Record(fields) => fields.iter().map(RocArg::alignment).max().unwrap_or(1),
+        }
+    }
+}
+
+/// Arguments laid out the way the dev backend stores a struct of them,
+/// each field right after the other and every record padded to its alignment.
+/// Strs that are not small point to a copy of their bytes with a static refcount, so Roc never frees them.
+pub struct PackedArgs {
+    bytes: Vec<u8>,
+    // Keeps the bytes of the big Strs in `bytes` alive.
+    _str_allocations: Vec<Vec<u64>>,
+}
+
+impl PackedArgs {
+    pub fn new(args: &[RocArg]) -> Self {
+        let mut packed = PackedArgs {
+            bytes: Vec::new(),
+            _str_allocations: Vec::new(),
+        };
+        for arg in args {
+            packed.push(arg);
+        }
+        packed
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.bytes.as_ptr()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    fn push(&mut self, arg: &RocArg) {
+        match arg {
+            RocArg::I8(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::U8(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::I16(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::U16(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::I32(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::U32(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::I64(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::U64(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::F32(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::F64(value) => self.bytes.extend(value.to_ne_bytes()),
+            RocArg::Bool(value) => self.bytes.push(*value as u8),
+            RocArg::Str(text) => self.push_str(text),
+            RocArg::Record(fields) => {
+                let start = self.bytes.len();
+                for field in fields {
+                    self.push(field);
+                }
+                let alignment = arg.alignment();
+                let size = self.bytes.len() - start;
+                let padded = (size + alignment - 1) / alignment * alignment;
+                self.bytes.resize(start + padded, 0);
+            }
+        }
+    }
+
+    fn push_str(&mut self, text: &str) {
+        const SIZE: usize = std::mem::size_of::<RawRocList>();
+
+        let mut str_bytes = [0u8; SIZE];
+        if text.len() < SIZE {
+            str_bytes[..text.len()].copy_from_slice(text.as_bytes());
+            str_bytes[SIZE - 1] = 0b1000_0000 | text.len() as u8;
+        } else {
+            // The refcount goes right before the bytes, 0 is the refcount of static data.
+            let mut allocation = vec![0u64; 1 + (text.len() + 7) / 8];
+            let elements = unsafe { allocation.as_mut_ptr().add(1) as *mut u8 };
+            unsafe { std::ptr::copy_nonoverlapping(text.as_ptr(), elements, text.len()) };
+            let raw = RawRocList {
+                elements,
+                length: text.len(),
+                capacity_or_ref_ptr: text.len(),
+            };
+            str_bytes = unsafe { std::mem::transmute(raw) };
+            self._str_allocations.push(allocation);
+        }
+        self.bytes.extend(str_bytes);
+    }
+}
+
+/// The representation of a `List` or `Str` returned from generated code, to use as the `$ty` of run_jit_function_raw.
+/// Decode it with roc_list_to_vec or roc_str_to_string.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RawRocList {
+    pub elements: *const u8,
+    pub length: usize,
+    pub capacity_or_ref_ptr: usize,
+}
+
+impl RawRocList {
+    /// The high bit of the length marks a seamless slice.
+    const FLAG: usize = isize::MIN as usize;
+
+    fn len(&self) -> usize {
+        self.length & !Self::FLAG
+    }
+}
+
+/// roc_list_to_vec copies the elements of a `List` returned from generated code.
+/// Seamless slices are copied like any other list. The refcount of the list is left alone,
+/// so the list stays valid and is leaked once the caller is done with it.
+///
+/// # Safety
+///
+/// `raw` must be a `List` of elements with the layout of `T`.
+pub unsafe fn roc_list_to_vec<T: Copy>(raw: RawRocList) -> Vec<T> {
+    if raw.len() == 0 {
+        return Vec::new();
+    }
+    std::slice::from_raw_parts(raw.elements as *const T, raw.len()).to_vec()
+}
+
+/// roc_str_to_string copies a `Str` returned from generated code.
+/// Small strings are stored in the `RawRocList` itself. They have the high bit of the last byte set,
+/// and the rest of that byte is their length.
+/// Others are copied like roc_list_to_vec.
+///
+/// # Safety
+///
+/// `raw` must be a `Str`.
+pub unsafe fn roc_str_to_string(raw: RawRocList) -> String {
+    const SIZE: usize = std::mem::size_of::<RawRocList>();
+
+    let bytes: [u8; SIZE] = std::mem::transmute(raw);
+    let bytes = if bytes[SIZE - 1] & 0b1000_0000 != 0 {
+        let len = (bytes[SIZE - 1] & 0b0111_1111) as usize;
+        bytes[..len].to_vec()
+    } else {
+        roc_list_to_vec(raw)
+    };
+    String::from_utf8(bytes).expect("a Str returned from generated code is not valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_strings_are_decoded_from_the_str_itself() {
+        let mut bytes = [0u8; std::mem::size_of::<RawRocList>()];
+        bytes[..5].copy_from_slice(b"hello");
+        *bytes.last_mut().unwrap() = 0b1000_0000 | 5;
+        let raw: RawRocList = unsafe { std::mem::transmute(bytes) };
+
+        assert_eq!(unsafe { roc_str_to_string(raw) }, "hello");
+    }
+
+    #[test]
+    fn packed_args_pad_records_to_their_alignment() {
+        let args = [
+            RocArg::U8(1),
+            RocArg::Record(vec![RocArg::I64(2), RocArg::Bool(true)]),
+            RocArg::I16(-3),
+        ];
+        let packed = PackedArgs::new(&args);
+
+        let mut expected = vec![1u8];
+        expected.extend(2i64.to_ne_bytes());
+        expected.extend([1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend((-3i16).to_ne_bytes());
+        assert_eq!(packed.as_bytes(), expected);
+    }
+
+    #[test]
+    fn packed_strs_decode_to_the_same_text() {
+        for text in ["small", "a string that is too long to be small"] {
+            let packed = PackedArgs::new(&[RocArg::Str(text)]);
+            let bytes: [u8; std::mem::size_of::<RawRocList>()] =
+                packed.as_bytes().try_into().unwrap();
+            let raw: RawRocList = unsafe { std::mem::transmute(bytes) };
+
+            assert_eq!(unsafe { roc_str_to_string(raw) }, text);
+        }
+    }
+
+    #[test]
+    fn big_strs_and_lists_are_copied_from_their_elements() {
+        let text = "a string that is too long to be small";
+        let raw = RawRocList {
+            elements: text.as_ptr(),
+            length: text.len(),
+            capacity_or_ref_ptr: text.len(),
+        };
+        assert_eq!(unsafe { roc_str_to_string(raw) }, text);
+
+        let numbers = [1i64, -2, 3];
+        // A seamless slice has the high bit of its length set.
+        let raw = RawRocList {
+            elements: numbers.as_ptr() as *const u8,
+            length: numbers.len() | RawRocList::FLAG,
+            capacity_or_ref_ptr: 0,
+        };
+        assert_eq!(unsafe { roc_list_to_vec::<i64>(raw) }, numbers);
+    }
+}
@@ -0,0 +1,100 @@
+//! A side file that maps the symbols of a dev backend object back to the Roc procs they were built from,
+//! so that crash addresses and profiler output can be demangled. See `Env::symbol_map`.
+//!
+//! The file has a line per symbol with tab separated fields:
+//! the name of the symbol, the Roc definition, the size of its code in bytes, and its layout.
+
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedSymbol {
+    /// The name of the symbol in the object.
+    pub symbol: String,
+    /// The Roc definition the proc was built from, like `#UserApp.main`.
+    pub roc_name: String,
+    /// Size of the machine code of the proc in bytes.
+    pub size: u64,
+    /// The layouts of the arguments and result of the proc.
+    pub layout: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    pub symbols: std::vec::Vec<MappedSymbol>,
+}
+
+impl SymbolMap {
+    /// The entry of the symbol named `symbol`, if it came from a proc.
+    pub fn find(&self, symbol: &str) -> Option<&MappedSymbol> {
+        self.symbols.iter().find(|mapped| mapped.symbol == symbol)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for mapped in self.symbols.iter() {
+            out.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                mapped.symbol,
+                mapped.roc_name,
+                mapped.size,
+                mapped.layout.replace(['\t', '\n'], " ")
+            ));
+        }
+        out
+    }
+
+    /// Parses the text of a symbol map file, returns `None` if a line is malformed.
+    pub fn from_text(text: &str) -> Option<Self> {
+        let mut symbols = std::vec::Vec::new();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let mut fields = line.splitn(4, '\t');
+            symbols.push(MappedSymbol {
+                symbol: fields.next()?.to_string(),
+                roc_name: fields.next()?.to_string(),
+                size: fields.next()?.parse().ok()?,
+                layout: fields.next()?.to_string(),
+            });
+        }
+        Some(SymbolMap { symbols })
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let map = SymbolMap {
+            symbols: vec![
+                MappedSymbol {
+                    symbol: "UserApp_main_123".to_string(),
+                    roc_name: "#UserApp.main".to_string(),
+                    size: 42,
+                    layout: "() -> Builtin(Str)".to_string(),
+                },
+                MappedSymbol {
+                    symbol: "roc__main_1_exposed".to_string(),
+                    roc_name: "#UserApp.main".to_string(),
+                    size: 16,
+                    layout: "() -> Builtin(Str)".to_string(),
+                },
+            ],
+        };
+
+        let parsed = SymbolMap::from_text(&map.to_text()).unwrap();
+        assert_eq!(parsed, map);
+        assert_eq!(parsed.find("UserApp_main_123").unwrap().size, 42);
+        assert!(parsed.find("roc_alloc").is_none());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!(SymbolMap::from_text("UserApp_main_123\t#UserApp.main\tbig\t()\n").is_none());
+        assert!(SymbolMap::from_text("UserApp_main_123\t#UserApp.main\n").is_none());
+    }
+}
@@ -5067,6 +5067,64 @@ pub fn build_procedures_expose_expects<'a>(
     expect_names
 }
 
+/// Builds the procedures, and exposes the given specializations of them with the C ABI under the given names.
+/// The dev backend leaves the procs that it can not build undefined under these names, see `roc_gen_dev::Env::llvm_fallback`,
+/// and calls them with the C ABI, so that linking this module in defines them.
+pub fn build_procedures_expose_fallbacks<'a>(
+    env: &Env<'a, '_, '_>,
+    layout_interner: &STLayoutInterner<'a>,
+    opt_level: OptLevel,
+    procedures: MutMap<(Symbol, ProcLayout<'a>), roc_mono::ir::Proc<'a>>,
+    entry_point: EntryPoint<'a>,
+    fallbacks: &[(Symbol, ProcLayout<'a>, String)],
+) {
+    let mod_solutions = build_procedures_help(
+        env,
+        layout_interner,
+        opt_level,
+        procedures,
+        entry_point,
+        None,
+    );
+
+    for (symbol, top_level, c_function_name) in fallbacks.iter() {
+        let it = top_level.arguments.iter().copied();
+        let bytes =
+            roc_alias_analysis::func_name_bytes_help(*symbol, it, top_level.niche, top_level.result);
+        let func_name = FuncName(&bytes);
+        let func_solutions = mod_solutions.func_solutions(func_name).unwrap();
+
+        let mut it = func_solutions.specs();
+        let func_spec = match it.next() {
+            Some(spec) => spec,
+            None => panic!("no specialization for fallback proc {}", symbol),
+        };
+
+        debug_assert!(
+            it.next().is_none(),
+            "we expect only one specialization of this symbol"
+        );
+
+        let roc_function = function_value_by_func_spec(
+            env,
+            *func_spec,
+            *symbol,
+            top_level.arguments,
+            top_level.niche,
+            top_level.result,
+        );
+
+        let _ = expose_function_to_host_help_c_abi_v2(
+            env,
+            layout_interner,
+            roc_function,
+            top_level.arguments,
+            top_level.result,
+            c_function_name,
+        );
+    }
+}
+
 fn build_procedures_help<'a>(
     env: &Env<'a, '_, '_>,
     layout_interner: &STLayoutInterner<'a>,
@@ -1219,6 +1219,13 @@ pub(crate) fn run_low_level<'a, 'ctx>(
             // which could be useful to look at when implementing this.
             todo!("implement checked float conversion");
         }
+        NumF32ToBits | NumF64ToBits | NumF32FromBits | NumF64FromBits => {
+            arguments!(arg);
+
+            let to = basic_type_from_layout(env, layout_interner, layout_interner.get_repr(layout));
+
+            env.builder.build_bitcast(arg, to, "float_bits_cast")
+        }
         I128OfDec => {
             arguments!(dec);
             dec_to_i128(env, dec)
@@ -1932,6 +1932,22 @@ impl<'a> LowLevelCall<'a> {
             NumToFloatChecked => {
                 todo!("implement toF32Checked and toF64Checked");
             }
+            NumF32ToBits => {
+                self.load_args(backend);
+                backend.code_builder.i32_reinterpret_f32();
+            }
+            NumF64ToBits => {
+                self.load_args(backend);
+                backend.code_builder.i64_reinterpret_f64();
+            }
+            NumF32FromBits => {
+                self.load_args(backend);
+                backend.code_builder.f32_reinterpret_i32();
+            }
+            NumF64FromBits => {
+                self.load_args(backend);
+                backend.code_builder.f64_reinterpret_i64();
+            }
             I128OfDec => self.load_args_and_call_zig(backend, bitcode::DEC_TO_I128),
             And => {
                 self.load_args(backend);
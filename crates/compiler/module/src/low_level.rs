@@ -110,6 +110,10 @@ pub enum LowLevel {
     NumCountLeadingZeroBits,
     NumCountTrailingZeroBits,
     NumCountOneBits,
+    NumF32ToBits,
+    NumF64ToBits,
+    NumF32FromBits,
+    NumF64FromBits,
     I128OfDec,
     Eq,
     NotEq,
@@ -347,6 +351,10 @@ map_symbol_to_lowlevel! {
     NumCountLeadingZeroBits <= NUM_COUNT_LEADING_ZERO_BITS,
     NumCountTrailingZeroBits <= NUM_COUNT_TRAILING_ZERO_BITS,
     NumCountOneBits <= NUM_COUNT_ONE_BITS,
+    NumF32ToBits <= NUM_F32_TO_BITS,
+    NumF64ToBits <= NUM_F64_TO_BITS,
+    NumF32FromBits <= NUM_F32_FROM_BITS,
+    NumF64FromBits <= NUM_F64_FROM_BITS,
     I128OfDec <= I128_OF_DEC,
     Eq <= BOOL_STRUCTURAL_EQ,
     NotEq <= BOOL_STRUCTURAL_NOT_EQ,
@@ -1268,6 +1268,10 @@ define_builtins! {
         162 NUM_PI: "pi"
         163 NUM_TAU: "tau"
         164 NUM_BITWISE_NOT: "bitwiseNot"
+        165 NUM_F32_TO_BITS: "f32ToBits"
+        166 NUM_F64_TO_BITS: "f64ToBits"
+        167 NUM_F32_FROM_BITS: "f32FromBits"
+        168 NUM_F64_FROM_BITS: "f64FromBits"
     }
     4 BOOL: "Bool" => {
         0 BOOL_BOOL: "Bool" exposed_type=true // the Bool.Bool type alias
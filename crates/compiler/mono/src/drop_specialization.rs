@@ -1658,7 +1658,11 @@ fn low_level_no_rc(lowlevel: &LowLevel) -> RC {
         | NumToFloatChecked
         | NumCountLeadingZeroBits
         | NumCountTrailingZeroBits
-        | NumCountOneBits => RC::NoRc,
+        | NumCountOneBits
+        | NumF32ToBits
+        | NumF64ToBits
+        | NumF32FromBits
+        | NumF64FromBits => RC::NoRc,
         NumBytesToU16 => RC::NoRc,
         NumBytesToU32 => RC::NoRc,
         NumBytesToU64 => RC::NoRc,
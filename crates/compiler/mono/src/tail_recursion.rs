@@ -109,8 +109,7 @@ pub fn apply_trmc<'a, 'i>(
 ///
 /// This will effectively compile into a loop in llvm, and
 /// won't grow the call stack for each iteration
-
-fn make_tail_recursive<'a>(
+pub fn make_tail_recursive<'a>(
     arena: &'a Bump,
     id: JoinPointId,
     needle: LambdaName,
@@ -582,19 +582,58 @@ fn list_eq_empty() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn list_eq_by_length() {
     assert_evals_to!("[1] == []", false, bool);
     assert_evals_to!("[] == [1]", false, bool);
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn list_eq_compare_pointwise() {
     assert_evals_to!("[1] == [1]", true, bool);
     assert_evals_to!("[2] == [1]", false, bool);
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn list_eq_flat_elements() {
+    // Longer than the 16 bytes that are compared at once, with differences before and after them.
+    assert_evals_to!(
+        "[1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18] == [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]",
+        true,
+        bool
+    );
+    assert_evals_to!(
+        "[1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18] == [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 0]",
+        false,
+        bool
+    );
+    assert_evals_to!(
+        "[1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18] == [0u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18]",
+        false,
+        bool
+    );
+    assert_evals_to!(
+        "[1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18] == [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17]",
+        false,
+        bool
+    );
+
+    assert_evals_to!("[1u32, 2, 3, 4, 5] == [1u32, 2, 3, 4, 5]", true, bool);
+    assert_evals_to!("[1u32, 2, 3, 4, 5] == [1u32, 2, 3, 4, 6]", false, bool);
+    assert_evals_to!(
+        "[{ x: 1u32, y: 2u32 }] == [{ x: 1u32, y: 2u32 }]",
+        true,
+        bool
+    );
+    assert_evals_to!(
+        "[{ x: 1u32, y: 2u32 }] == [{ x: 1u32, y: 3u32 }]",
+        false,
+        bool
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
 fn list_eq_nested() {
@@ -603,7 +642,7 @@ fn list_eq_nested() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn list_neq_compare_pointwise() {
     assert_evals_to!("[1] != [1]", false, bool);
     assert_evals_to!("[2] != [1]", true, bool);
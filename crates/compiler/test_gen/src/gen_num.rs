@@ -1181,6 +1181,59 @@ fn gen_rem_i64() {
     assert_evals_to!("Num.rem 8 3", 2, i64);
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn gen_rem_negative_divisor_i64() {
+    assert_evals_to!("Num.rem 8 -3", 2, i64);
+    assert_evals_to!("Num.rem -8 -3", -2, i64);
+    assert_evals_to!("Num.rem -8 3", -2, i64);
+}
+
+#[test]
+#[cfg(feature = "gen-dev")]
+fn gen_rem_min_by_minus_one_i64() {
+    // The quotient does not fit an I64, but the remainder is just 0.
+    assert_evals_to!("Num.rem Num.minI64 -1", 0, i64);
+}
+
+#[test]
+#[cfg(feature = "gen-dev")]
+#[should_panic(expected = r#"Roc failed with message: "integer division by zero!"#)]
+fn gen_div_by_zero_i64() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            divisor = 0
+
+            1000 // divisor
+            "#
+        ),
+        0,
+        i64
+    );
+}
+
+#[test]
+#[cfg(feature = "gen-dev")]
+#[should_panic(expected = r#"Roc failed with message: "integer division by zero!"#)]
+fn gen_div_by_zero_u8() {
+    assert_evals_to!("200u8 // 0", 0, u8);
+}
+
+#[test]
+#[cfg(feature = "gen-dev")]
+#[should_panic(expected = r#"Roc failed with message: "integer division overflowed!"#)]
+fn gen_div_min_by_minus_one_i64() {
+    assert_evals_to!("Num.minI64 // -1", 0, i64);
+}
+
+#[test]
+#[cfg(feature = "gen-dev")]
+#[should_panic(expected = r#"Roc failed with message: "integer division by zero!"#)]
+fn gen_rem_by_zero_i64() {
+    assert_evals_to!("Num.rem 8 0", 0, i64);
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn gen_rem_checked_div_by_zero_i64() {
@@ -2007,7 +2060,33 @@ fn int_mul_wrap_i128() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn int_mul_wrap_128_bit_halves() {
+    assert_evals_to!(
+        "Num.mulWrap 0xffff_ffff_ffff_ffffi128 0xffff_ffff_ffff_ffffi128",
+        (u64::MAX as i128).wrapping_mul(u64::MAX as i128),
+        i128
+    );
+    assert_evals_to!(
+        "Num.mulWrap -3i128 0x1_0000_0000_0000_0005i128",
+        -3 * 0x1_0000_0000_0000_0005i128,
+        i128
+    );
+    assert_evals_to!(
+        "Num.mulWrap 0x1_0000_0000_0000_0000u128 0x1_0000_0000_0000_0001u128",
+        0x1_0000_0000_0000_0000u128,
+        u128
+    );
+    assert_evals_to!(
+        "Num.mulWrap 0x1234_5678_9abc_def0_0fed_cba9_8765_4321u128 0xfedc_ba98_7654_3210_0123_4567_89ab_cdefu128",
+        0x1234_5678_9abc_def0_0fed_cba9_8765_4321u128
+            .wrapping_mul(0xfedc_ba98_7654_3210_0123_4567_89ab_cdefu128),
+        u128
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn int_mul_checked() {
     assert_evals_to!(
         indoc!(
@@ -2034,6 +2113,70 @@ fn int_mul_checked() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn int_mul_checked_upper_half() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+                when Num.mulChecked -4_611_686_018_427_387_904i64 2 is
+                    Ok v -> v
+                    Err Overflow -> 0
+                "#
+        ),
+        i64::MIN,
+        i64
+    );
+
+    assert_evals_to!(
+        indoc!(
+            r#"
+                when Num.mulChecked 4_611_686_018_427_387_904i64 -2 is
+                    Ok v -> v
+                    Err Overflow -> 0
+                "#
+        ),
+        i64::MIN,
+        i64
+    );
+
+    assert_evals_to!(
+        indoc!(
+            r#"
+                when Num.mulChecked 4_611_686_018_427_387_904i64 2 is
+                    Ok v -> v
+                    Err Overflow -> 0
+                "#
+        ),
+        0,
+        i64
+    );
+
+    assert_evals_to!(
+        indoc!(
+            r#"
+                when Num.mulChecked 4_294_967_296u64 4_294_967_295u64 is
+                    Ok v -> v
+                    Err Overflow -> 0
+                "#
+        ),
+        18_446_744_069_414_584_320,
+        u64
+    );
+
+    assert_evals_to!(
+        indoc!(
+            r#"
+                when Num.mulChecked 4_294_967_296u64 4_294_967_296u64 is
+                    Ok v -> v
+                    Err Overflow -> 0
+                "#
+        ),
+        0,
+        u64
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
 fn float_mul_checked() {
@@ -2406,7 +2549,7 @@ num_conversion_tests! {
 macro_rules! to_int_checked_tests {
     ($($fn:expr, $typ:ty, ($($test_name:ident, $input:expr, $output:expr)*))*) => {$($(
         #[test]
-        #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+        #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
         fn $test_name() {
             let sentinel = 23;
             // Some n = Ok n, None = OutOfBounds
@@ -3268,7 +3411,7 @@ fn u8_mul_greater_than_i8() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn add_saturated() {
     assert_evals_to!(
         indoc!(
@@ -3314,7 +3457,18 @@ fn add_saturated() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn saturated_at_full_width() {
+    assert_evals_to!("Num.addSaturated Num.maxI64 1", i64::MAX, i64);
+    assert_evals_to!("Num.subSaturated Num.minI64 1", i64::MIN, i64);
+    assert_evals_to!("Num.addSaturated Num.maxU64 1", u64::MAX, u64);
+    assert_evals_to!("Num.subSaturated 1u64 2", 0, u64);
+    assert_evals_to!("Num.addSaturated 40u32 2", 42, u32);
+    assert_evals_to!("Num.subSaturated -40i16 2", -42, i16);
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn sub_saturated() {
     assert_evals_to!(
         indoc!(
@@ -3818,7 +3972,7 @@ fn condition_polymorphic_num_becomes_float() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn num_count_leading_zero_bits() {
     assert_evals_to!(r#"Num.countLeadingZeroBits 0b0010_1000u8"#, 2, usize);
     assert_evals_to!(r#"Num.countLeadingZeroBits 0b0010_1000u16"#, 10, usize);
@@ -3827,7 +3981,7 @@ fn num_count_leading_zero_bits() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn num_count_trailing_zero_bits() {
     assert_evals_to!(r#"Num.countTrailingZeroBits 0b0010_1000u8"#, 3, usize);
     assert_evals_to!(r#"Num.countTrailingZeroBits 0b0010_0000u16"#, 5, usize);
@@ -3836,7 +3990,7 @@ fn num_count_trailing_zero_bits() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn num_count_one_bits() {
     assert_evals_to!(r#"Num.countOneBits 0b0010_1000u8"#, 2, usize);
     assert_evals_to!(r#"Num.countOneBits 0b0010_0000u16"#, 1, usize);
@@ -3844,6 +3998,35 @@ fn num_count_one_bits() {
     assert_evals_to!(r#"Num.countOneBits 0b0010_1111u64"#, 5, usize);
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn num_count_bits_at_the_width() {
+    assert_evals_to!(r#"Num.countLeadingZeroBits 0u8"#, 8, usize);
+    assert_evals_to!(r#"Num.countLeadingZeroBits 0u64"#, 64, usize);
+    assert_evals_to!(r#"Num.countLeadingZeroBits -1i16"#, 0, usize);
+    assert_evals_to!(r#"Num.countTrailingZeroBits 0u8"#, 8, usize);
+    assert_evals_to!(r#"Num.countTrailingZeroBits 0u64"#, 64, usize);
+    assert_evals_to!(r#"Num.countOneBits -1i8"#, 8, usize);
+    assert_evals_to!(r#"Num.countOneBits -1i64"#, 64, usize);
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn float_to_bits() {
+    assert_evals_to!(r#"Num.f32ToBits 1.0f32"#, 0x3f80_0000, u32);
+    assert_evals_to!(r#"Num.f32ToBits -2.5f32"#, (-2.5f32).to_bits(), u32);
+    assert_evals_to!(r#"Num.f64ToBits 1.0f64"#, 0x3ff0_0000_0000_0000, u64);
+    assert_evals_to!(r#"Num.f64ToBits -0.0f64"#, (-0.0f64).to_bits(), u64);
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn float_from_bits() {
+    assert_evals_to!(r#"Num.f32FromBits 0x3f80_0000"#, 1.0, f32);
+    assert_evals_to!(r#"Num.f64FromBits 0xc004_0000_0000_0000"#, -2.5, f64);
+    assert_evals_to!(r#"Num.f64FromBits (Num.f64ToBits 0.1f64)"#, 0.1, f64);
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
 fn num_abs_diff_int() {
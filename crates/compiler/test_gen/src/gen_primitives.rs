@@ -231,6 +231,29 @@ fn gen_large_when_int() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn gen_dense_when_int() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+                foo = \num ->
+                    when num is
+                        0 -> 1
+                        1 -> 10
+                        2 -> 100
+                        4 -> 1000
+                        5 -> 10000
+                        _ -> 100000
+
+                foo 0 + foo 1 + foo 2 + foo 3 + foo 4 + foo 5 + foo 6 + foo 1000
+            "#
+        ),
+        311111,
+        i64
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn gen_large_when_float() {
@@ -3367,6 +3390,26 @@ fn box_and_unbox_f32() {
     assert_evals_to!("Box.unbox (Box.box (123.0f32))", 123.0, f32)
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn box_and_unbox_i128() {
+    assert_evals_to!("Box.unbox (Box.box Num.maxI128)", i128::MAX, i128)
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn box_and_unbox_f32_record() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            Box.unbox (Box.box { a: 1.5f32, b: 7u32 })
+            "#
+        ),
+        (1.5, 7),
+        (f32, u32)
+    )
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn box_and_unbox_record() {
@@ -3558,6 +3601,33 @@ fn mutual_recursion_top_level_defs() {
     )
 }
 
+#[test]
+#[cfg(feature = "gen-dev")]
+fn mutual_recursion_tail_calls() {
+    // Every call reuses the frame of its caller, a million frames would overflow the stack of the test thread.
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [ main ] to "./platform"
+
+            isEven = \n ->
+                when n is
+                    0 -> Bool.true
+                    _ -> isOdd (n - 1)
+
+            isOdd = \n ->
+                when n is
+                    0 -> Bool.false
+                    _ -> isEven (n - 1)
+
+            main = isOdd 1_000_001
+            "#
+        ),
+        true,
+        bool
+    )
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn polymorphic_lambda_captures_polymorphic_value() {
@@ -4533,3 +4603,165 @@ fn linked_list_trmc() {
         i64
     );
 }
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn pass_small_captures_to_a_function() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            apply : (I64 -> I64), I64 -> I64
+            apply = \f, x -> f x
+
+            main : I64
+            main =
+                a = 3
+                b = 4
+
+                apply (\x -> a * x + b) 5
+            "#
+        ),
+        19,
+        i64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn pass_big_captures_to_a_function() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            apply : (I64 -> I64), I64 -> I64
+            apply = \f, x -> f x
+
+            main : I64
+            main =
+                a = 3
+                b = 4
+                c = 5
+                d = 6
+
+                apply (\x -> a * x + b * c - d) 2
+            "#
+        ),
+        20,
+        i64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn more_args_than_registers() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            f : I64, I64, I64, I64, I64, I64, I64, I64 -> I64
+            f = \a, b, c, d, e, g, h, i ->
+                a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * g + 7 * h + 8 * i
+
+            main : I64
+            main = f 1 1 1 1 1 1 1 1
+            "#
+        ),
+        36,
+        i64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-dev"))]
+fn i128_args_in_registers_and_on_the_stack() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            f : I128, I128, I128, I128 -> I128
+            f = \a, b, c, d -> a - b + 2 * c - d
+
+            main : I128
+            main = f 10 1 100 1000
+            "#
+        ),
+        -791,
+        i128
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn unaligned_records_and_i128_on_the_stack() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            f : { a : U32, b : U32, c : U32 }, { a : U32, b : U32, c : U32 }, I128, I128, I128, I128 -> I128
+            f = \x, y, p, q, r, s ->
+                Num.toI128 (x.a + x.b + x.c + y.a + y.b + y.c) + p + q + r + s
+
+            main : I128
+            main = f { a: 1, b: 2, c: 3 } { a: 4, b: 5, c: 6 } 10 20 30 40
+            "#
+        ),
+        121,
+        i128
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn interleaved_int_and_float_args() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            f : I64, F64, I64, F64, I64, I64, I64, I64, F64 -> F64
+            f = \a, x, b, y, c, d, e, g, z ->
+                Num.toF64 (a + 2 * b + 3 * c + 4 * d + 5 * e + 6 * g) + x * y - z
+
+            main : F64
+            main = f 1 1.5 1 2.0 1 1 1 1 0.5
+            "#
+        ),
+        23.5,
+        f64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn call_one_expression_helpers() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            double : I64 -> I64
+            double = \x -> x + x
+
+            first : { a : I64, b : I64 } -> I64
+            first = \r -> r.a
+
+            pair : I64, I64 -> { a : I64, b : I64 }
+            pair = \a, b -> { a, b }
+
+            main : I64
+            main =
+                r = pair (double 3) 10
+
+                first r + double r.b
+            "#
+        ),
+        26,
+        i64
+    );
+}
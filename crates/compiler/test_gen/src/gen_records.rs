@@ -8,6 +8,8 @@ use crate::helpers::dev::assert_evals_to;
 use crate::helpers::wasm::assert_evals_to;
 
 // use crate::assert_wasm_evals_to as assert_evals_to;
+#[cfg(feature = "gen-dev-differential")]
+use crate::helpers::assert_dev_matches_llvm;
 use indoc::indoc;
 
 #[cfg(all(test, any(feature = "gen-llvm", feature = "gen-wasm")))]
@@ -87,6 +89,80 @@ fn f64_record() {
     );
 }
 
+#[test]
+#[cfg(feature = "gen-dev-differential")]
+fn basic_record_dev_matches_llvm() {
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                    { y: 17, x: 15, z: 19 }.x
+                "#
+        ),
+        15,
+        i64
+    );
+
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                    { x: 15, y: 17, z: 19 }.y
+                "#
+        ),
+        17,
+        i64
+    );
+
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                    { x: 15, y: 17, z: 19 }.z
+                "#
+        ),
+        19,
+        i64
+    );
+}
+
+#[test]
+#[cfg(feature = "gen-dev-differential")]
+fn f64_record_dev_matches_llvm() {
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                   rec = { y: 17.2, x: 15.1, z: 19.3 }
+
+                   rec.x
+                "#
+        ),
+        15.1,
+        f64
+    );
+
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                   rec = { y: 17.2, x: 15.1, z: 19.3 }
+
+                   rec.y
+                "#
+        ),
+        17.2,
+        f64
+    );
+
+    assert_dev_matches_llvm!(
+        indoc!(
+            r#"
+                    rec = { y: 17.2, x: 15.1, z: 19.3 }
+
+                    rec.z
+                "#
+        ),
+        19.3,
+        f64
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
 fn fn_record() {
@@ -247,6 +323,47 @@ fn empty_record() {
         ()
     );
 }
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-dev", feature = "gen-wasm"))]
+fn pass_and_return_empty_record() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            identity : {} -> {}
+            identity = \x -> x
+
+            answer : {}, I64 -> I64
+            answer = \{}, n -> n + 1
+
+            main = answer (identity {}) 41
+            "#
+        ),
+        42,
+        i64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-dev", feature = "gen-wasm"))]
+fn record_with_empty_record_field() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            sum : { a : I64, unit : {}, c : I64 } -> I64
+            sum = \{ a, unit: _, c } -> a + c
+
+            main = sum { a: 2, unit: {}, c: 3 }
+            "#
+        ),
+        5,
+        i64
+    );
+}
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn i64_record2_literal() {
@@ -782,7 +899,7 @@ fn return_nested_record() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn nested_record_load() {
     assert_evals_to!(
         indoc!(
@@ -799,6 +916,41 @@ fn nested_record_load() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn nested_record_load_mixed_fields() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+                rec = { a: { b: { c: 7, d: 2.5 }, e: 3 }, f: 4 }
+
+                inner = rec.a.b
+
+                inner.c + rec.a.e + rec.f
+                "#
+        ),
+        14,
+        i64
+    );
+}
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn nested_single_field_record_arg() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+                get : { a : { b : F64 } } -> F64
+                get = \x -> x.a.b
+
+                get { a: { b: 1.5 } } + get { a: { b: 2.25 } }
+                "#
+        ),
+        3.75,
+        f64
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
 fn accessor_twice() {
@@ -820,7 +972,7 @@ fn accessor_multi_element_record() {
 }
 
 #[test]
-#[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn accessor_single_element_record() {
     assert_evals_to!(
         indoc!(
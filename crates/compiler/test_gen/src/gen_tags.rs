@@ -618,6 +618,34 @@ fn when_on_enum() {
     );
 }
 
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn enum_through_functions() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            Color : [Red, Green, Blue, Purple]
+
+            next : Color -> Color
+            next = \color ->
+                when color is
+                    Red -> Green
+                    Green -> Blue
+                    Blue -> Purple
+                    Purple -> Red
+
+            main =
+                color = next (next Red)
+                if color == Blue && color != Purple && next Purple == Red then 1 else 0
+            "#
+        ),
+        1,
+        i64
+    );
+}
+
 #[test]
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
 fn pattern_matching_unit() {
@@ -2303,3 +2331,28 @@ fn recursive_tag_id_in_allocation_eq() {
         bool
     );
 }
+
+#[test]
+#[cfg(any(feature = "gen-llvm", feature = "gen-wasm", feature = "gen-dev"))]
+fn unit_tag_payload() {
+    assert_evals_to!(
+        indoc!(
+            r#"
+            app "test" provides [main] to "./platform"
+
+            pick : Bool -> [Empty {}, Value I64]
+            pick = \b -> if b then Empty {} else Value 7
+
+            toNum : [Empty {}, Value I64] -> I64
+            toNum = \tag ->
+                when tag is
+                    Empty {} -> 1
+                    Value n -> n
+
+            main = toNum (pick Bool.true) + toNum (pick Bool.false)
+            "#
+        ),
+        8,
+        i64
+    );
+}
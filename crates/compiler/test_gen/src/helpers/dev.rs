@@ -2,12 +2,16 @@ use libloading::Library;
 use roc_build::link::{link, LinkType};
 use roc_builtins::bitcode;
 use roc_load::{EntryPoint, ExecutionMode, LoadConfig, Threading};
-use roc_mono::ir::SingleEntryPoint;
+use roc_mono::ir::{CrashTag, SingleEntryPoint};
 use roc_packaging::cache::RocCacheDir;
 use roc_region::all::LineInfo;
+use roc_std::RocStr;
+use std::ffi::c_void;
+use std::mem::{ManuallyDrop, MaybeUninit};
 use tempfile::tempdir;
 
 #[cfg(any(feature = "gen-llvm", feature = "gen-wasm"))]
+#[allow(unused_imports)]
 use roc_collections::all::MutMap;
 
 #[allow(unused_imports)]
@@ -185,22 +189,23 @@ pub fn helper(
         assert_eq!(0, 1, "Mistakes were made");
     }
 
-    let env = roc_gen_dev::Env {
-        arena,
-        module_id,
-        exposed_to_host: exposed_to_host.top_level_values.keys().copied().collect(),
-        lazy_literals,
-        mode: roc_gen_dev::AssemblyBackendMode::Test,
-    };
+    let env = roc_gen_dev::EnvBuilder::new(arena, module_id)
+        .exposed_to_host(exposed_to_host.top_level_values.keys().copied().collect())
+        .lazy_literals(lazy_literals)
+        .mode(roc_gen_dev::AssemblyBackendMode::Test)
+        .verify_machine_code(true)
+        .check_storage_invariants(true)
+        .build();
 
     let target = target_lexicon::Triple::host();
-    let module_object = roc_gen_dev::build_module(
+    let (module_object, _) = roc_gen_dev::build_module(
         &env,
         &mut interns,
         &mut layout_interner,
         &target,
         procedures,
-    );
+    )
+    .unwrap_or_else(|error| panic!("{}", error));
 
     let module_out = module_object
         .write()
@@ -243,6 +248,55 @@ pub fn helper(
     (main_fn_name, delayed_errors, lib)
 }
 
+/// Runs the main function of `lib` and returns its result, or the message and tag that roc_panic got.
+/// The builtins run main for us and roc_panic jumps back to them with setjmp/longjmp, like in the LLVM tests.
+#[allow(dead_code)]
+pub fn try_run_main<T>(lib: &Library, main_fn_name: &str) -> Result<T, (String, CrashTag)> {
+    struct Run<T> {
+        main: unsafe extern "C" fn() -> T,
+        result: MaybeUninit<T>,
+    }
+
+    // Nothing in here needs dropping, so roc_panic may jump over it.
+    extern "C" fn run_main<T>(run: *mut c_void) {
+        let run = unsafe { &mut *(run as *mut Run<T>) };
+        run.result.write(unsafe { (run.main)() });
+    }
+
+    type CatchPanic =
+        unsafe extern "C" fn(extern "C" fn(*mut c_void), *mut c_void, *mut RocStr) -> u32;
+
+    unsafe {
+        let main: libloading::Symbol<unsafe extern "C" fn() -> T> = lib
+            .get(main_fn_name.as_bytes())
+            .ok()
+            .ok_or(format!("Unable to JIT compile `{}`", main_fn_name))
+            .expect("errored");
+        let catch_panic: libloading::Symbol<CatchPanic> = lib
+            .get(bitcode::UTILS_TEST_CATCH_PANIC.as_bytes())
+            .expect("the builtins are not linked in");
+
+        let mut run = Run {
+            main: *main,
+            result: MaybeUninit::uninit(),
+        };
+        let mut msg = MaybeUninit::<RocStr>::uninit();
+        let ctx = &mut run as *mut Run<T> as *mut c_void;
+        match catch_panic(run_main::<T>, ctx, msg.as_mut_ptr()) {
+            0 => Ok(run.result.assume_init()),
+            n => {
+                // The message belongs to the Roc program.
+                let msg = ManuallyDrop::new(msg.assume_init());
+                let tag = (n - 1)
+                    .try_into()
+                    .unwrap_or_else(|_| panic!("received illegal tag: {}", n - 1));
+
+                Err((msg.as_str().to_owned(), tag))
+            }
+        }
+    }
+}
+
 #[allow(unused_macros)]
 macro_rules! assert_evals_to {
     ($src:expr, $expected:expr, $ty:ty) => {{
@@ -265,19 +319,26 @@ macro_rules! assert_evals_to {
     };
     ($src:expr, $expected:expr, $ty:ty, $transform:expr, $leak:expr, $lazy_literals:expr) => {
         use bumpalo::Bump;
-        use roc_gen_dev::run_jit_function_raw;
+        use roc_mono::ir::CrashTag;
 
         let arena = Bump::new();
         let (main_fn_name, errors, lib) =
             $crate::helpers::dev::helper(&arena, $src, $leak, $lazy_literals);
 
-        let transform = |success| {
-            let expected = $expected;
-            #[allow(clippy::redundant_closure_call)]
-            let given = $transform(success);
-            assert_eq!(&given, &expected);
-        };
-        run_jit_function_raw!(lib, main_fn_name, $ty, transform, errors)
+        match $crate::helpers::dev::try_run_main::<$ty>(&lib, &main_fn_name) {
+            Ok(success) => {
+                assert!(errors.is_empty(), "Encountered errors: {:?}", errors);
+
+                let expected = $expected;
+                #[allow(clippy::redundant_closure_call)]
+                let given = $transform(success);
+                assert_eq!(&given, &expected);
+            }
+            Err((msg, tag)) => match tag {
+                CrashTag::Roc => panic!(r#"Roc failed with message: "{}""#, msg),
+                CrashTag::User => panic!(r#"User crash with message: "{}""#, msg),
+            },
+        }
     };
 }
 
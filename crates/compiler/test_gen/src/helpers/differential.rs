@@ -0,0 +1,76 @@
+//! Runs a test with both the dev backend and LLVM, to catch the dev backend computing something
+//! other than LLVM does, even where no test spells out the expected value yet.
+//! Tests opt in one at a time by using assert_dev_matches_llvm! instead of assert_evals_to!.
+
+use bumpalo::Bump;
+use inkwell::context::Context;
+use roc_gen_llvm::llvm::build::LlvmBackendMode;
+use roc_mono::ir::CrashTag;
+
+use crate::helpers::llvm::{try_run_lib_function, HelperConfig, OPT_LEVEL};
+
+/// dev_matches_llvm builds `src` with both backends and runs it, then checks that LLVM
+/// computes `expected` and that the dev backend computes the same value as LLVM.
+/// Both libraries stay loaded until the results are compared, since they can point into their data.
+#[allow(dead_code)]
+pub(crate) fn dev_matches_llvm<T, U, F>(src: &str, expected: U, transform: F)
+where
+    U: PartialEq + std::fmt::Debug,
+    F: Fn(T) -> U,
+{
+    let arena = Bump::new();
+    let context = Context::create();
+    let config = HelperConfig {
+        mode: LlvmBackendMode::GenTest,
+        add_debug_info: false,
+        ignore_problems: false,
+        opt_level: OPT_LEVEL,
+    };
+
+    let (llvm_main_fn_name, llvm_errors, llvm_lib) =
+        crate::helpers::llvm::helper(&arena, config, src, &context);
+    let llvm_result = match try_run_lib_function::<T>(llvm_main_fn_name, &llvm_lib) {
+        Ok(raw) => {
+            assert!(
+                llvm_errors.is_empty(),
+                "Encountered errors:\n{}",
+                llvm_errors
+            );
+            transform(raw)
+        }
+        Err((msg, tag)) => match tag {
+            CrashTag::Roc => panic!(r#"Roc failed with message: "{}""#, msg),
+            CrashTag::User => panic!(r#"User crash with message: "{}""#, msg),
+        },
+    };
+
+    let (dev_main_fn_name, dev_errors, dev_lib) =
+        crate::helpers::dev::helper(&arena, src, true, false);
+    let dev_result = match crate::helpers::dev::try_run_main::<T>(&dev_lib, &dev_main_fn_name) {
+        Ok(raw) => {
+            assert!(
+                dev_errors.is_empty(),
+                "Encountered errors: {:?}",
+                dev_errors
+            );
+            transform(raw)
+        }
+        Err((msg, tag)) => match tag {
+            CrashTag::Roc => panic!(r#"Roc failed with message: "{}""#, msg),
+            CrashTag::User => panic!(r#"User crash with message: "{}""#, msg),
+        },
+    };
+
+    assert_eq!(&llvm_result, &expected, "LLVM test failed");
+    assert_eq!(
+        &dev_result, &llvm_result,
+        "the dev backend computed something else than LLVM"
+    );
+
+    // on Windows, there are issues with the drop instances of some roc_std
+    #[cfg(windows)]
+    {
+        std::mem::forget(llvm_result);
+        std::mem::forget(dev_result);
+    }
+}
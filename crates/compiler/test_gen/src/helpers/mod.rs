@@ -2,8 +2,10 @@ extern crate bumpalo;
 
 pub mod platform_functions;
 
-#[cfg(feature = "gen-dev")]
+#[cfg(any(feature = "gen-dev", feature = "gen-dev-differential"))]
 pub mod dev;
+#[cfg(feature = "gen-dev-differential")]
+pub mod differential;
 pub mod from_wasm32_memory;
 #[cfg(feature = "gen-llvm")]
 pub mod llvm;
@@ -84,3 +86,27 @@ pub enum RefCount {
     Deallocated,
     Constant,
 }
+
+/// Like assert_evals_to!, but with the `gen-dev-differential` feature the test is run with both
+/// the dev backend and LLVM, and fails when they compute different values.
+/// Without that feature, it is the assert_evals_to! of the enabled backend.
+#[allow(unused_macros)]
+macro_rules! assert_dev_matches_llvm {
+    ($src:expr, $expected:expr, $ty:ty) => {{
+        #[cfg(feature = "gen-dev-differential")]
+        $crate::helpers::differential::dev_matches_llvm::<$ty, _, _>($src, $expected, |val| val);
+
+        #[cfg(not(feature = "gen-dev-differential"))]
+        assert_evals_to!($src, $expected, $ty);
+    }};
+    ($src:expr, $expected:expr, $ty:ty, $transform:expr) => {{
+        #[cfg(feature = "gen-dev-differential")]
+        $crate::helpers::differential::dev_matches_llvm::<$ty, _, _>($src, $expected, $transform);
+
+        #[cfg(not(feature = "gen-dev-differential"))]
+        assert_evals_to!($src, $expected, $ty, $transform);
+    }};
+}
+
+#[allow(unused_imports)]
+pub(crate) use assert_dev_matches_llvm;
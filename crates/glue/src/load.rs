@@ -53,7 +53,9 @@ pub fn generate(
             let code_gen_options = CodeGenOptions {
                 backend,
                 opt_level: OptLevel::Development,
+                dev_opt_level: Default::default(),
                 emit_debug_info: false,
+                verbose: false,
             };
 
             let load_config = standard_load_config(